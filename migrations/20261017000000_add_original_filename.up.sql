@@ -0,0 +1,2 @@
+-- 保存客户端提交的原始文件名，下载时用于还原文件名
+ALTER TABLE upload_file_meta ADD COLUMN original_filename TEXT;
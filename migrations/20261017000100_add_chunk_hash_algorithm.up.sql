@@ -0,0 +1,2 @@
+-- 记录分片校验值所用的算法，旧数据均为 sha256
+ALTER TABLE upload_progress ADD COLUMN hash_algorithm TEXT DEFAULT 'sha256';
@@ -0,0 +1,8 @@
+-- 按天、按主体（目前为客户端 IP）统计上传/下载流量
+CREATE TABLE IF NOT EXISTS traffic_daily (
+    day TEXT NOT NULL,
+    principal TEXT NOT NULL,
+    bytes_uploaded INTEGER NOT NULL DEFAULT 0,
+    bytes_downloaded INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (day, principal)
+);
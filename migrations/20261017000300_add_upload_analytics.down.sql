@@ -0,0 +1,3 @@
+DROP TABLE IF EXISTS upload_failures_daily;
+DROP TABLE IF EXISTS upload_stats_daily;
+ALTER TABLE upload_file_meta DROP COLUMN created_at;
@@ -0,0 +1,20 @@
+-- 记录元数据提交时间，用于统计整次上传耗时
+ALTER TABLE upload_file_meta ADD COLUMN created_at INTEGER DEFAULT 0;
+
+-- 按天汇总已完成上传的数量、字节数、上传耗时与合并耗时（毫秒）
+CREATE TABLE IF NOT EXISTS upload_stats_daily (
+    day TEXT PRIMARY KEY,
+    completed_uploads INTEGER NOT NULL DEFAULT 0,
+    total_bytes INTEGER NOT NULL DEFAULT 0,
+    total_upload_ms INTEGER NOT NULL DEFAULT 0,
+    total_merge_ms INTEGER NOT NULL DEFAULT 0,
+    max_merge_ms INTEGER NOT NULL DEFAULT 0
+);
+
+-- 按天、按错误码统计上传失败次数
+CREATE TABLE IF NOT EXISTS upload_failures_daily (
+    day TEXT NOT NULL,
+    code TEXT NOT NULL,
+    count INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (day, code)
+);
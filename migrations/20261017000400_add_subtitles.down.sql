@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS idx_subtitles_file_id;
+DROP TABLE IF EXISTS subtitles;
@@ -0,0 +1,16 @@
+-- 字幕表，记录字幕文件位置及来源（提供方与其字幕 id）
+CREATE TABLE IF NOT EXISTS subtitles (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    file_id TEXT NOT NULL,
+    language TEXT NOT NULL,
+    format TEXT NOT NULL,
+    file_path TEXT NOT NULL,
+    source TEXT NOT NULL,
+    source_ref TEXT,
+    release_name TEXT,
+    hash_match BOOLEAN NOT NULL DEFAULT 0,
+    created_at INTEGER DEFAULT 0,
+    FOREIGN KEY (file_id) REFERENCES upload_file_meta(file_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_subtitles_file_id ON subtitles(file_id);
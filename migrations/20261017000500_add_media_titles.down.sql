@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS idx_media_titles_title;
+DROP TABLE IF EXISTS media_titles;
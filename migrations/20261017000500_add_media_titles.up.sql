@@ -0,0 +1,18 @@
+-- 影视元数据：片名、年份、海报及剧集信息，来源为 TMDB 或文件名解析
+CREATE TABLE IF NOT EXISTS media_titles (
+    file_id TEXT PRIMARY KEY,
+    media_type TEXT NOT NULL,
+    source TEXT NOT NULL,
+    tmdb_id INTEGER,
+    title TEXT NOT NULL,
+    year INTEGER,
+    overview TEXT,
+    poster_url TEXT,
+    season INTEGER,
+    episode INTEGER,
+    episode_title TEXT,
+    scraped_at INTEGER DEFAULT 0,
+    FOREIGN KEY (file_id) REFERENCES upload_file_meta(file_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_media_titles_title ON media_titles(title);
@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS idx_playback_history_principal_file;
+DROP TABLE IF EXISTS playback_history;
@@ -0,0 +1,12 @@
+-- 播放记录，由播放端上报播放位置，用于计算已看/未看
+CREATE TABLE IF NOT EXISTS playback_history (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    file_id TEXT NOT NULL,
+    principal TEXT NOT NULL,
+    position_secs REAL NOT NULL DEFAULT 0,
+    duration_secs REAL NOT NULL DEFAULT 0,
+    played_at INTEGER DEFAULT 0,
+    FOREIGN KEY (file_id) REFERENCES upload_file_meta(file_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_playback_history_principal_file ON playback_history(principal, file_id);
@@ -0,0 +1,19 @@
+-- 每个主体对每个文件的观看状态：unwatched / in_progress / watched
+CREATE TABLE IF NOT EXISTS watch_state (
+    file_id TEXT NOT NULL,
+    principal TEXT NOT NULL,
+    state TEXT NOT NULL DEFAULT 'unwatched',
+    position_secs REAL NOT NULL DEFAULT 0,
+    duration_secs REAL NOT NULL DEFAULT 0,
+    updated_at INTEGER DEFAULT 0,
+    PRIMARY KEY (file_id, principal),
+    FOREIGN KEY (file_id) REFERENCES upload_file_meta(file_id)
+);
+
+-- 由已有播放记录回填观看状态
+INSERT OR IGNORE INTO watch_state (file_id, principal, state, position_secs, duration_secs, updated_at)
+SELECT file_id, principal,
+       CASE WHEN MAX(duration_secs > 0 AND position_secs >= duration_secs * 0.9) = 1 THEN 'watched' ELSE 'in_progress' END,
+       MAX(position_secs), MAX(duration_secs), MAX(played_at)
+FROM playback_history
+GROUP BY file_id, principal;
@@ -0,0 +1,6 @@
+ALTER TABLE media_titles DROP COLUMN content_age;
+ALTER TABLE media_titles DROP COLUMN content_rating;
+DROP INDEX IF EXISTS idx_file_tags_tag;
+DROP TABLE IF EXISTS file_tags;
+DROP TABLE IF EXISTS active_profiles;
+DROP TABLE IF EXISTS profiles;
@@ -0,0 +1,33 @@
+-- 观看档案：按分级、标签、目录限制可见内容，可设置 PIN
+CREATE TABLE IF NOT EXISTS profiles (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL UNIQUE,
+    pin_hash TEXT,
+    max_content_age INTEGER,
+    allow_unrated BOOLEAN NOT NULL DEFAULT 0,
+    blocked_tags TEXT NOT NULL DEFAULT '[]',
+    blocked_folders TEXT NOT NULL DEFAULT '[]',
+    created_at INTEGER DEFAULT 0
+);
+
+-- 每个主体当前使用的档案
+CREATE TABLE IF NOT EXISTS active_profiles (
+    principal TEXT PRIMARY KEY,
+    profile_id INTEGER NOT NULL,
+    activated_at INTEGER DEFAULT 0,
+    FOREIGN KEY (profile_id) REFERENCES profiles(id)
+);
+
+-- 文件标签
+CREATE TABLE IF NOT EXISTS file_tags (
+    file_id TEXT NOT NULL,
+    tag TEXT NOT NULL,
+    PRIMARY KEY (file_id, tag),
+    FOREIGN KEY (file_id) REFERENCES upload_file_meta(file_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_file_tags_tag ON file_tags(tag);
+
+-- 刮削得到的分级及对应的最低年龄
+ALTER TABLE media_titles ADD COLUMN content_rating TEXT;
+ALTER TABLE media_titles ADD COLUMN content_age INTEGER;
@@ -0,0 +1,7 @@
+-- 渲染设备快照，重启后 /api/dlna/devices 可立即返回已知设备
+CREATE TABLE IF NOT EXISTS dlna_devices (
+    uuid TEXT PRIMARY KEY,
+    source TEXT NOT NULL,
+    message TEXT NOT NULL,
+    updated_at INTEGER DEFAULT 0
+);
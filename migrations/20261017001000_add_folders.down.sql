@@ -0,0 +1,4 @@
+DROP TABLE IF EXISTS transcodes;
+DROP INDEX IF EXISTS idx_upload_file_meta_folder_id;
+ALTER TABLE upload_file_meta DROP COLUMN folder_id;
+DROP TABLE IF EXISTS folders;
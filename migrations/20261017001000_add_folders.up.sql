@@ -0,0 +1,25 @@
+-- 上传目标目录及其策略（允许的 MIME 类型、大小上限、自动转码、保留天数）
+CREATE TABLE IF NOT EXISTS folders (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL UNIQUE,
+    path TEXT NOT NULL UNIQUE,
+    allowed_mime_types TEXT NOT NULL DEFAULT '[]',
+    max_file_size INTEGER,
+    auto_transcode TEXT,
+    retention_days INTEGER,
+    created_at INTEGER DEFAULT 0
+);
+
+ALTER TABLE upload_file_meta ADD COLUMN folder_id INTEGER;
+CREATE INDEX IF NOT EXISTS idx_upload_file_meta_folder_id ON upload_file_meta(folder_id);
+
+-- 自动转码结果，status: pending / done / failed
+CREATE TABLE IF NOT EXISTS transcodes (
+    file_id TEXT PRIMARY KEY,
+    format TEXT NOT NULL,
+    path TEXT,
+    status TEXT NOT NULL,
+    error TEXT,
+    updated_at INTEGER DEFAULT 0,
+    FOREIGN KEY (file_id) REFERENCES upload_file_meta(file_id)
+);
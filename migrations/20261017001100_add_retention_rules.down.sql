@@ -0,0 +1,2 @@
+ALTER TABLE upload_file_meta DROP COLUMN archived_at;
+DROP TABLE IF EXISTS retention_rules;
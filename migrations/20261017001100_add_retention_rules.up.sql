@@ -0,0 +1,16 @@
+-- 保留规则：按目录或标签匹配文件，超过天数或超出保留版本数时删除或移到冷存储
+-- action: delete / cold_storage
+CREATE TABLE IF NOT EXISTS retention_rules (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL,
+    folder_id INTEGER,
+    tag TEXT,
+    max_age_days INTEGER,
+    keep_versions INTEGER,
+    action TEXT NOT NULL DEFAULT 'delete',
+    created_at INTEGER DEFAULT 0,
+    FOREIGN KEY (folder_id) REFERENCES folders(id)
+);
+
+-- 移到冷存储的时间，未归档为 NULL
+ALTER TABLE upload_file_meta ADD COLUMN archived_at INTEGER;
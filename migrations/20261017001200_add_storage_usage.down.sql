@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS storage_usage;
+ALTER TABLE upload_file_meta DROP COLUMN owner;
@@ -0,0 +1,12 @@
+-- 提交元数据的客户端主体，用于按用户统计占用空间
+ALTER TABLE upload_file_meta ADD COLUMN owner TEXT;
+
+-- 已完成文件的占用空间汇总，上传完成和删除时增量更新
+-- dimension: folder（key 为目录 ID，顶层为空串）/ mime（key 为 MIME 类型）/ owner（key 为主体）
+CREATE TABLE IF NOT EXISTS storage_usage (
+    dimension TEXT NOT NULL,
+    key TEXT NOT NULL,
+    files INTEGER NOT NULL DEFAULT 0,
+    bytes INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (dimension, key)
+);
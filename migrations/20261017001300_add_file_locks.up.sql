@@ -0,0 +1,9 @@
+-- 文件锁：持有者在租约到期前独占修改权（删除、归档、去重、重新上传、增量更新）
+-- 过期的锁视为不存在，在下次加锁时清理
+CREATE TABLE IF NOT EXISTS file_locks (
+    file_id TEXT PRIMARY KEY,
+    token TEXT NOT NULL,
+    owner TEXT NOT NULL,
+    acquired_at INTEGER NOT NULL,
+    expires_at INTEGER NOT NULL
+);
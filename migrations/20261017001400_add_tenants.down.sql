@@ -0,0 +1,38 @@
+CREATE TABLE retention_rules_backup AS SELECT * FROM retention_rules;
+DROP TABLE retention_rules;
+
+CREATE TABLE folders_old (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL UNIQUE,
+    path TEXT NOT NULL UNIQUE,
+    allowed_mime_types TEXT NOT NULL DEFAULT '[]',
+    max_file_size INTEGER,
+    auto_transcode TEXT,
+    retention_days INTEGER,
+    created_at INTEGER DEFAULT 0
+);
+INSERT OR IGNORE INTO folders_old (id, name, path, allowed_mime_types, max_file_size, auto_transcode, retention_days, created_at)
+    SELECT id, name, path, allowed_mime_types, max_file_size, auto_transcode, retention_days, created_at FROM folders;
+DROP TABLE folders;
+ALTER TABLE folders_old RENAME TO folders;
+
+-- 同名目录只保留一个，指向被丢弃目录的规则一并丢弃
+CREATE TABLE retention_rules (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL,
+    folder_id INTEGER,
+    tag TEXT,
+    max_age_days INTEGER,
+    keep_versions INTEGER,
+    action TEXT NOT NULL DEFAULT 'delete',
+    created_at INTEGER DEFAULT 0,
+    FOREIGN KEY (folder_id) REFERENCES folders(id)
+);
+INSERT INTO retention_rules (id, name, folder_id, tag, max_age_days, keep_versions, action, created_at)
+    SELECT id, name, folder_id, tag, max_age_days, keep_versions, action, created_at FROM retention_rules_backup
+    WHERE folder_id IS NULL OR folder_id IN (SELECT id FROM folders);
+DROP TABLE retention_rules_backup;
+
+DROP INDEX IF EXISTS idx_upload_file_meta_tenant_id;
+ALTER TABLE upload_file_meta DROP COLUMN tenant_id;
+DROP TABLE IF EXISTS tenants;
@@ -0,0 +1,51 @@
+-- 多租户：按 API key 或子域名识别租户，文件存放在 uploads/tenants/<slug> 下
+-- quota_bytes 为空表示不限额
+CREATE TABLE IF NOT EXISTS tenants (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    slug TEXT NOT NULL UNIQUE,
+    name TEXT NOT NULL,
+    api_key TEXT NOT NULL UNIQUE,
+    quota_bytes INTEGER,
+    created_at INTEGER DEFAULT 0
+);
+
+-- 文件和目录归属的租户，单租户模式下为 NULL；分片、字幕、标签等按 file_id 随文件隔离
+ALTER TABLE upload_file_meta ADD COLUMN tenant_id INTEGER;
+CREATE INDEX IF NOT EXISTS idx_upload_file_meta_tenant_id ON upload_file_meta(tenant_id);
+
+-- 目录名改为在租户内唯一，需要重建 folders 表。
+-- 删除被引用的表会留下外键冲突，先把 retention_rules 移到无外键的临时表，重建后再恢复
+CREATE TABLE retention_rules_backup AS SELECT * FROM retention_rules;
+DROP TABLE retention_rules;
+
+CREATE TABLE folders_new (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL,
+    path TEXT NOT NULL UNIQUE,
+    allowed_mime_types TEXT NOT NULL DEFAULT '[]',
+    max_file_size INTEGER,
+    auto_transcode TEXT,
+    retention_days INTEGER,
+    created_at INTEGER DEFAULT 0,
+    tenant_id INTEGER
+);
+INSERT INTO folders_new (id, name, path, allowed_mime_types, max_file_size, auto_transcode, retention_days, created_at)
+    SELECT id, name, path, allowed_mime_types, max_file_size, auto_transcode, retention_days, created_at FROM folders;
+DROP TABLE folders;
+ALTER TABLE folders_new RENAME TO folders;
+CREATE UNIQUE INDEX IF NOT EXISTS idx_folders_tenant_name ON folders(COALESCE(tenant_id, 0), name);
+
+CREATE TABLE retention_rules (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL,
+    folder_id INTEGER,
+    tag TEXT,
+    max_age_days INTEGER,
+    keep_versions INTEGER,
+    action TEXT NOT NULL DEFAULT 'delete',
+    created_at INTEGER DEFAULT 0,
+    FOREIGN KEY (folder_id) REFERENCES folders(id)
+);
+INSERT INTO retention_rules (id, name, folder_id, tag, max_age_days, keep_versions, action, created_at)
+    SELECT id, name, folder_id, tag, max_age_days, keep_versions, action, created_at FROM retention_rules_backup;
+DROP TABLE retention_rules_backup;
@@ -0,0 +1,10 @@
+-- 可唤醒的设备（电视等渲染设备、备份目标服务器），用 MAC 地址发送 Wake-on-LAN 魔术包
+-- broadcast_address 为空时发往 255.255.255.255
+CREATE TABLE IF NOT EXISTS devices (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL UNIQUE,
+    kind TEXT NOT NULL DEFAULT 'renderer',
+    mac_address TEXT NOT NULL,
+    broadcast_address TEXT,
+    created_at INTEGER DEFAULT 0
+);
@@ -0,0 +1,10 @@
+-- 浏览器 WebPush 订阅（PWA），p256dh 和 auth 为 base64url 编码的客户端密钥
+-- tenant_id 为空的订阅接收所有上传和磁盘空间通知
+CREATE TABLE IF NOT EXISTS push_subscriptions (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    endpoint TEXT NOT NULL UNIQUE,
+    p256dh TEXT NOT NULL,
+    auth TEXT NOT NULL,
+    tenant_id INTEGER,
+    created_at INTEGER DEFAULT 0
+);
@@ -0,0 +1,11 @@
+-- 分享链接：凭 token 无需登录即可下载单个文件，expires_at 为空表示不过期
+-- kind: link 为手动创建，feed 为订阅源中自动生成（每个文件一个，可重复使用）
+CREATE TABLE IF NOT EXISTS shares (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    token TEXT NOT NULL UNIQUE,
+    file_id TEXT NOT NULL,
+    kind TEXT NOT NULL DEFAULT 'link',
+    created_at INTEGER DEFAULT 0,
+    expires_at INTEGER
+);
+CREATE INDEX IF NOT EXISTS idx_shares_file_id ON shares(file_id);
@@ -0,0 +1,2 @@
+-- 上传完成后通知 Jellyfin/Plex 扫描新文件
+ALTER TABLE folders ADD COLUMN media_scan INTEGER NOT NULL DEFAULT 0;
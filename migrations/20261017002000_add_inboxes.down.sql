@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS inbox_uploads;
+DROP TABLE IF EXISTS inboxes;
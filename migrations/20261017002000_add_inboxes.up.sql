@@ -0,0 +1,25 @@
+-- 收件箱（文件请求）：持有 token 的匿名访客可上传文件到指定目录，与分享链接方向相反
+-- allowed_mime_types 为 JSON 数组，空数组表示不限类型；expires_at 为空表示不过期
+CREATE TABLE IF NOT EXISTS inboxes (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    token TEXT NOT NULL UNIQUE,
+    name TEXT NOT NULL,
+    folder_id INTEGER NOT NULL,
+    max_file_size INTEGER NOT NULL,
+    allowed_mime_types TEXT NOT NULL DEFAULT '[]',
+    max_uploads_per_hour INTEGER NOT NULL DEFAULT 10,
+    created_at INTEGER DEFAULT 0,
+    expires_at INTEGER,
+    FOREIGN KEY (folder_id) REFERENCES folders(id)
+);
+
+-- 每次上传尝试一条记录，用于按来源 IP 限流；完成后记下 file_id
+CREATE TABLE IF NOT EXISTS inbox_uploads (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    inbox_id INTEGER NOT NULL,
+    principal TEXT NOT NULL,
+    file_id TEXT,
+    created_at INTEGER DEFAULT 0,
+    FOREIGN KEY (inbox_id) REFERENCES inboxes(id)
+);
+CREATE INDEX IF NOT EXISTS idx_inbox_uploads_inbox_principal ON inbox_uploads(inbox_id, principal, created_at);
@@ -0,0 +1,3 @@
+ALTER TABLE shares DROP COLUMN require_email;
+ALTER TABLE shares DROP COLUMN revoked_at;
+DROP TABLE IF EXISTS share_downloads;
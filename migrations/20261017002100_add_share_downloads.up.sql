@@ -0,0 +1,16 @@
+-- 分享链接的下载记录：每次从头开始的下载一条（视频拖动等 Range 续传请求不计）
+-- email 为要求填写邮箱的分享中下载者填写的地址
+CREATE TABLE IF NOT EXISTS share_downloads (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    share_id INTEGER NOT NULL,
+    principal TEXT NOT NULL,
+    email TEXT,
+    downloaded_at INTEGER DEFAULT 0,
+    FOREIGN KEY (share_id) REFERENCES shares(id)
+);
+CREATE INDEX IF NOT EXISTS idx_share_downloads_share_id ON share_downloads(share_id);
+
+-- 撤销时间，未撤销为 NULL；撤销后链接返回 410，下载记录保留
+ALTER TABLE shares ADD COLUMN revoked_at INTEGER;
+-- 下载前要求填写邮箱
+ALTER TABLE shares ADD COLUMN require_email INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,2 @@
+-- 通过分享链接下载照片和视频时去除 EXIF/GPS 等元数据
+ALTER TABLE shares ADD COLUMN strip_metadata INTEGER NOT NULL DEFAULT 0;
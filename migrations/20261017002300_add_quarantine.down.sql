@@ -0,0 +1,2 @@
+ALTER TABLE upload_file_meta DROP COLUMN quarantine_reason;
+ALTER TABLE folders DROP COLUMN blocked_extensions;
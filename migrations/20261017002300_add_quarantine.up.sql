@@ -0,0 +1,4 @@
+-- 目录级扩展名黑名单（JSON 数组，小写、不含点）
+ALTER TABLE folders ADD COLUMN blocked_extensions TEXT NOT NULL DEFAULT '[]';
+-- 内容与扩展名不符的文件进入隔离（status = 3），记录原因
+ALTER TABLE upload_file_meta ADD COLUMN quarantine_reason TEXT;
@@ -0,0 +1,11 @@
+-- 事务性发件箱：事件与对应的状态变更在同一事务中写入，由后台任务至少投递一次
+CREATE TABLE IF NOT EXISTS event_outbox (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    event TEXT NOT NULL,
+    created_at INTEGER DEFAULT 0,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    next_attempt_at INTEGER NOT NULL DEFAULT 0,
+    delivered_at INTEGER,
+    last_error TEXT
+);
+CREATE INDEX IF NOT EXISTS idx_event_outbox_pending ON event_outbox(delivered_at, next_attempt_at);
@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS idx_upload_file_meta_upload_key;
+ALTER TABLE upload_file_meta DROP COLUMN upload_key;
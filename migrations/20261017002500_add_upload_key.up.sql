@@ -0,0 +1,3 @@
+-- 客户端生成的上传键，重复提交元信息时返回同一个 file_id（按租户唯一）
+ALTER TABLE upload_file_meta ADD COLUMN upload_key TEXT;
+CREATE UNIQUE INDEX idx_upload_file_meta_upload_key ON upload_file_meta (COALESCE(tenant_id, 0), upload_key) WHERE upload_key IS NOT NULL;
@@ -0,0 +1 @@
+ALTER TABLE upload_progress DROP COLUMN is_hole;
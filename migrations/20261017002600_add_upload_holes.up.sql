@@ -0,0 +1,2 @@
+-- 稀疏文件的空洞区间作为已完成的分片记录，不上传数据，合并时直接跳过
+ALTER TABLE upload_progress ADD COLUMN is_hole INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,5 @@
+DROP INDEX IF EXISTS idx_jobs_hook_id;
+ALTER TABLE jobs DROP COLUMN output;
+ALTER TABLE jobs DROP COLUMN file_id;
+ALTER TABLE jobs DROP COLUMN hook_id;
+DROP TABLE IF EXISTS hooks;
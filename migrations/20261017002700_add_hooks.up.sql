@@ -0,0 +1,19 @@
+-- 上传完成后执行的外部命令：按目录和 MIME 类型匹配，command 为 NASCRAFT_HOOKS_DIR 下的脚本文件名
+-- args 为 JSON 字符串数组
+CREATE TABLE IF NOT EXISTS hooks (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL,
+    folder_id INTEGER,
+    mime_type TEXT,
+    command TEXT NOT NULL,
+    args TEXT NOT NULL DEFAULT '[]',
+    timeout_secs INTEGER NOT NULL DEFAULT 300,
+    created_at INTEGER NOT NULL,
+    FOREIGN KEY (folder_id) REFERENCES folders(id)
+);
+
+-- 钩子的每次执行记为一个 kind = 'hook' 的任务，保存触发的文件和命令输出
+ALTER TABLE jobs ADD COLUMN hook_id INTEGER;
+ALTER TABLE jobs ADD COLUMN file_id TEXT;
+ALTER TABLE jobs ADD COLUMN output TEXT;
+CREATE INDEX IF NOT EXISTS idx_jobs_hook_id ON jobs(hook_id);
@@ -0,0 +1 @@
+ALTER TABLE upload_file_meta DROP COLUMN revision;
@@ -0,0 +1,2 @@
+-- 文件记录的修订号，每次重命名、移动或状态变更时递增，与 checksum、last_updated 一起组成 ETag
+ALTER TABLE upload_file_meta ADD COLUMN revision INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,5 @@
+DROP INDEX IF EXISTS idx_upload_file_meta_client_device_id;
+ALTER TABLE upload_file_meta DROP COLUMN client_user_agent;
+ALTER TABLE upload_file_meta DROP COLUMN client_device_name;
+ALTER TABLE upload_file_meta DROP COLUMN client_device_id;
+DROP TABLE IF EXISTS client_devices;
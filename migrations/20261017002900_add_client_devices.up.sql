@@ -0,0 +1,17 @@
+-- 上传客户端（手机备份应用、命令行工具）各自持有令牌，丢失设备时单独吊销而不必更换租户 API key
+CREATE TABLE IF NOT EXISTS client_devices (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    tenant_id INTEGER,
+    name TEXT NOT NULL,
+    token TEXT NOT NULL UNIQUE,
+    user_agent TEXT,
+    created_at INTEGER DEFAULT 0,
+    last_seen_at INTEGER,
+    revoked_at INTEGER
+);
+
+-- 每次上传会话记录发起的客户端；未使用令牌的客户端只记录声明的设备名与 User-Agent
+ALTER TABLE upload_file_meta ADD COLUMN client_device_id INTEGER;
+ALTER TABLE upload_file_meta ADD COLUMN client_device_name TEXT;
+ALTER TABLE upload_file_meta ADD COLUMN client_user_agent TEXT;
+CREATE INDEX IF NOT EXISTS idx_upload_file_meta_client_device_id ON upload_file_meta(client_device_id);
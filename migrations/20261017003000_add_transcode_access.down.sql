@@ -0,0 +1 @@
+ALTER TABLE transcodes DROP COLUMN last_accessed_at;
@@ -0,0 +1,3 @@
+-- 转码结果按最近访问时间淘汰，status 增加 evicted（文件已删除，下次请求时重新转码）
+ALTER TABLE transcodes ADD COLUMN last_accessed_at INTEGER NOT NULL DEFAULT 0;
+UPDATE transcodes SET last_accessed_at = updated_at;
@@ -0,0 +1 @@
+ALTER TABLE upload_file_meta DROP COLUMN source_file_id;
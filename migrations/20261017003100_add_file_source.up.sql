@@ -0,0 +1,2 @@
+-- 由其他文件生成的文件（如从视频提取的音频）记录来源文件
+ALTER TABLE upload_file_meta ADD COLUMN source_file_id TEXT;
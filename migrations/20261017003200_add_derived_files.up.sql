@@ -0,0 +1,21 @@
+-- 由文件生成的产物（缩略图、转码、提取的音频、解压出的文件）与来源文件关联，删除来源时一并清理
+CREATE TABLE IF NOT EXISTS derived_files (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    source_id TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    params TEXT,
+    path TEXT NOT NULL,
+    -- 产物本身登记为文件时（音频、解压出的文件）的 file_id
+    derived_file_id TEXT,
+    created_at INTEGER DEFAULT 0,
+    UNIQUE (source_id, kind, path)
+);
+CREATE INDEX IF NOT EXISTS idx_derived_files_derived_file_id ON derived_files(derived_file_id);
+
+-- 已有的缩略图、转码和提取的音频
+INSERT OR IGNORE INTO derived_files (source_id, kind, params, path, created_at)
+    SELECT file_id, 'thumbnail', NULL, thumbnail_path, last_updated FROM upload_file_meta WHERE thumbnail_path IS NOT NULL;
+INSERT OR IGNORE INTO derived_files (source_id, kind, params, path, created_at)
+    SELECT file_id, 'transcode', json_object('format', format), path, updated_at FROM transcodes WHERE status = 'done' AND path IS NOT NULL;
+INSERT OR IGNORE INTO derived_files (source_id, kind, params, path, derived_file_id, created_at)
+    SELECT source_file_id, 'audio', NULL, file_path, file_id, last_updated FROM upload_file_meta WHERE source_file_id IS NOT NULL;
@@ -0,0 +1,2 @@
+-- 补齐的创建时间无法与原有数据区分，回滚时保留
+SELECT 1;
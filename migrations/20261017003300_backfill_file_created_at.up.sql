@@ -0,0 +1,2 @@
+-- 早于上传统计的文件没有创建时间，已完成的文件以最后更新时间补齐；进行中的上传保持 0，避免误算上传耗时
+UPDATE upload_file_meta SET created_at = last_updated WHERE (created_at IS NULL OR created_at = 0) AND status = 2;
@@ -0,0 +1,2 @@
+ALTER TABLE jobs DROP COLUMN priority;
+ALTER TABLE upload_file_meta DROP COLUMN priority;
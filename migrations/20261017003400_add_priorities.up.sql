@@ -0,0 +1,3 @@
+-- 上传及其后续任务（合并、转码）的优先级：bulk / normal / high
+ALTER TABLE upload_file_meta ADD COLUMN priority TEXT NOT NULL DEFAULT 'normal';
+ALTER TABLE jobs ADD COLUMN priority TEXT NOT NULL DEFAULT 'normal';
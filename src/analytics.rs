@@ -0,0 +1,163 @@
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use chrono::{Duration, Utc};
+use log::error;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, SqlitePool};
+use std::collections::BTreeMap;
+use crate::context::AppContext;
+use crate::helper::{error_response, ApiResponse};
+use crate::tenants::ensure_not_tenant;
+
+fn today() -> String {
+    Utc::now().format("%Y-%m-%d").to_string()
+}
+
+/// 记录一次成功完成的上传：总耗时（从提交元数据开始）与合并耗时
+pub async fn record_upload_completed(db_pool: &SqlitePool, total_bytes: u64, upload_ms: u64, merge_ms: u64) {
+    if let Err(e) = sqlx::query(
+        "INSERT INTO upload_stats_daily (day, completed_uploads, total_bytes, total_upload_ms, total_merge_ms, max_merge_ms) VALUES (?, 1, ?, ?, ?, ?)
+         ON CONFLICT(day) DO UPDATE SET
+             completed_uploads = completed_uploads + 1,
+             total_bytes = total_bytes + excluded.total_bytes,
+             total_upload_ms = total_upload_ms + excluded.total_upload_ms,
+             total_merge_ms = total_merge_ms + excluded.total_merge_ms,
+             max_merge_ms = MAX(max_merge_ms, excluded.max_merge_ms)"
+    )
+    .bind(today())
+    .bind(total_bytes as i64)
+    .bind(upload_ms as i64)
+    .bind(merge_ms as i64)
+    .bind(merge_ms as i64)
+    .execute(db_pool)
+    .await
+    {
+        error!("Failed to record upload completion stats: {}", e);
+    }
+}
+
+/// 按错误码累计当天的上传失败次数
+pub async fn record_upload_failure(db_pool: &SqlitePool, code: &str) {
+    if let Err(e) = sqlx::query(
+        "INSERT INTO upload_failures_daily (day, code, count) VALUES (?, ?, 1)
+         ON CONFLICT(day, code) DO UPDATE SET count = count + 1"
+    )
+    .bind(today())
+    .bind(code)
+    .execute(db_pool)
+    .await
+    {
+        error!("Failed to record upload failure stats: {}", e);
+    }
+}
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct UploadStatsDay {
+    pub day: String,
+    pub completed_uploads: i64,
+    pub total_bytes: i64,
+    pub total_upload_ms: i64,
+    pub total_merge_ms: i64,
+    pub max_merge_ms: i64,
+}
+
+#[derive(Debug, FromRow)]
+struct UploadFailureRow {
+    day: String,
+    code: String,
+    count: i64,
+}
+
+pub async fn fetch_upload_stats(db_pool: &SqlitePool, since_day: &str) -> Result<Vec<UploadStatsDay>, String> {
+    sqlx::query_as::<_, UploadStatsDay>(
+        "SELECT day, completed_uploads, total_bytes, total_upload_ms, total_merge_ms, max_merge_ms FROM upload_stats_daily WHERE day >= ? ORDER BY day DESC"
+    )
+    .bind(since_day)
+    .fetch_all(db_pool)
+    .await
+    .map_err(|e| {
+        error!("Failed to fetch upload stats: {}", e);
+        "Failed to fetch upload stats".to_string()
+    })
+}
+
+async fn fetch_upload_failures(db_pool: &SqlitePool, since_day: &str) -> Result<Vec<UploadFailureRow>, String> {
+    sqlx::query_as::<_, UploadFailureRow>(
+        "SELECT day, code, count FROM upload_failures_daily WHERE day >= ?"
+    )
+    .bind(since_day)
+    .fetch_all(db_pool)
+    .await
+    .map_err(|e| {
+        error!("Failed to fetch upload failure stats: {}", e);
+        "Failed to fetch upload failure stats".to_string()
+    })
+}
+
+#[derive(Deserialize)]
+pub struct UploadStatsQuery {
+    days: Option<i64>,
+}
+
+pub async fn get_upload_stats(
+    State(ctx): State<AppContext>,
+    Query(query): Query<UploadStatsQuery>,
+) -> impl IntoResponse {
+    if let Err(resp) = ensure_not_tenant().await {
+        return resp;
+    }
+    let db_pool = &ctx.app_state.db_pool;
+    let days = query.days.unwrap_or(30).clamp(1, 366);
+    let since_day = (Utc::now() - Duration::days(days - 1)).format("%Y-%m-%d").to_string();
+
+    let stats = match fetch_upload_stats(db_pool, &since_day).await {
+        Ok(stats) => stats,
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_UPLOAD_STATS_ERROR", e),
+    };
+    let failures = match fetch_upload_failures(db_pool, &since_day).await {
+        Ok(failures) => failures,
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_UPLOAD_STATS_ERROR", e),
+    };
+
+    // day -> code -> count
+    let mut failures_by_day: BTreeMap<String, BTreeMap<String, i64>> = BTreeMap::new();
+    for f in failures {
+        failures_by_day.entry(f.day).or_default().insert(f.code, f.count);
+    }
+
+    let mut days_out = Vec::new();
+    for s in &stats {
+        let completed = s.completed_uploads.max(1);
+        days_out.push(serde_json::json!({
+            "day": s.day,
+            "completed_uploads": s.completed_uploads,
+            "total_bytes": s.total_bytes,
+            "avg_upload_ms": s.total_upload_ms / completed,
+            "avg_merge_ms": s.total_merge_ms / completed,
+            "max_merge_ms": s.max_merge_ms,
+            "failures": failures_by_day.remove(&s.day).unwrap_or_default(),
+        }));
+    }
+    // Days with failures but no completed upload
+    for (day, codes) in failures_by_day {
+        days_out.push(serde_json::json!({
+            "day": day,
+            "completed_uploads": 0,
+            "total_bytes": 0,
+            "avg_upload_ms": 0,
+            "avg_merge_ms": 0,
+            "max_merge_ms": 0,
+            "failures": codes,
+        }));
+    }
+    days_out.sort_by(|a, b| b["day"].as_str().cmp(&a["day"].as_str()));
+
+    (StatusCode::OK, Json(ApiResponse::success(serde_json::json!({
+        "since": since_day,
+        "days": days_out,
+    })))).into_response()
+}
@@ -0,0 +1,331 @@
+//! Request and response bodies of the upload API, shared by the server and the client.
+
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+/// Version of the HTTP API: endpoints are served under `/api/v1` and every JSON
+/// response carries it as `version`
+pub const API_VERSION: u32 = 1;
+
+/// Token of a registered upload client, see `POST /api/v1/client_devices`
+pub const DEVICE_TOKEN_HEADER: &str = "X-Device-Token";
+/// Name an unregistered client declares for itself, e.g. `Alice's phone`
+pub const DEVICE_NAME_HEADER: &str = "X-Device-Name";
+
+/// Body of `POST /api/v1/submit_metadata`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileMetadata {
+    pub filename: String,
+    pub total_size: u64,
+    pub checksum: String,
+    /// Folder to store the file in; its policies are checked before the upload starts
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub folder_id: Option<i64>,
+    /// Client-generated key, e.g. a UUID, that makes the submission safe to
+    /// retry: submitting the same file with the same key again returns the
+    /// original upload instead of starting another one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upload_key: Option<String>,
+    /// Ranges of the file that are all zeros, e.g. the unallocated parts of a
+    /// disk image. They are left out of the upload plan and recreated as
+    /// holes when the file is assembled.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub holes: Vec<Hole>,
+    /// Replace a file with the same name in the target folder instead of
+    /// being refused with `409 FILE_EXISTS`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub overwrite: bool,
+    /// `normal` when unset; uploads through `/api/backup/upload` default to `bulk`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<Priority>,
+}
+
+/// Data of a `409 FILE_EXISTS` response to `submit_metadata`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileConflict {
+    pub filename: String,
+    /// Free names to submit the file under instead, e.g. `movie (1).mp4`
+    pub suggestions: Vec<String>,
+}
+
+/// A range of zeros in a sparse file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Hole {
+    pub offset: u64,
+    pub length: u64,
+}
+
+/// One planned chunk of an upload; `end_offset` is inclusive
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChunkInfo {
+    pub start_offset: u64,
+    pub end_offset: u64,
+    pub chunk_size: u64,
+    /// Pre-signed URL the chunk can be posted to without `X-File-ID`,
+    /// `X-Start-Offset` or an API key, valid until `UploadHints::urls_expire_at`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upload_url: Option<String>,
+}
+
+/// How the server would like the chunks of an upload to be sent, derived
+/// from its current load
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UploadHints {
+    /// Chunks to send at once
+    pub parallel_chunks: usize,
+    /// First delay before retrying a failed chunk, doubled on each retry up
+    /// to `max_backoff_ms`
+    pub initial_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+    /// Uploads currently receiving data, including this one once it starts
+    pub active_uploads: usize,
+    /// When the chunks' `upload_url`s stop working; they also stop
+    /// working when the server restarts
+    #[serde(with = "timestamp")]
+    pub urls_expire_at: i64,
+}
+
+/// Upload plan returned by `submit_metadata` for a new file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UploadPlan {
+    #[serde(rename = "id")]
+    pub file_id: String,
+    pub filename: String,
+    pub original_filename: String,
+    pub total_size: u64,
+    pub chunk_size: u64,
+    /// Algorithm for the optional `X-Chunk-Checksum` header
+    pub hash_algorithm: String,
+    pub total_chunks: u64,
+    pub chunks: Vec<ChunkInfo>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hints: Option<UploadHints>,
+}
+
+/// Per-chunk progress as stored in `upload_progress`
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct ChunkProgress {
+    pub start_offset: i64,
+    pub end_offset: i64,
+    pub uploaded_size: i64,
+    pub checksum: String,
+    pub hash_algorithm: Option<String>,
+    #[serde(with = "timestamp")]
+    pub last_updated: i64,
+    /// A declared hole: complete without data and skipped when merging
+    #[serde(default)]
+    #[sqlx(default)]
+    pub is_hole: bool,
+}
+
+impl ChunkProgress {
+    pub fn is_complete(&self) -> bool {
+        self.uploaded_size > self.end_offset - self.start_offset
+    }
+}
+
+/// Data of `GET /api/v1/upload_status/:file_id`
+#[derive(Debug, Clone, Deserialize)]
+pub struct UploadStatus {
+    pub file_id: String,
+    /// `uploading`, `paused`, `processing`, `completed` or `quarantined`
+    pub status: String,
+    /// Only present while the upload is unfinished
+    #[serde(default)]
+    pub chunks: Vec<ChunkProgress>,
+    /// Changes whenever the status or progress does; pass it as `since` to
+    /// wait for the next change
+    #[serde(default)]
+    pub version: String,
+}
+
+/// Body of `POST /api/v1/backup/check`: checksums of the items on the device,
+/// as sent in `FileMetadata::checksum`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupCheck {
+    pub checksums: Vec<String>,
+}
+
+/// A checksum the server already has a completed file for
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct StoredChecksum {
+    pub checksum: String,
+    pub file_id: String,
+}
+
+/// Data of `POST /api/v1/backup/check`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupCheckResult {
+    /// Checksums to upload, in request order
+    pub missing: Vec<String>,
+    pub stored: Vec<StoredChecksum>,
+}
+
+/// Body of `POST /api/v1/backup/upload`: the items `backup/check` reported
+/// missing, each submitted like `submit_metadata`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupUpload {
+    pub items: Vec<FileMetadata>,
+}
+
+/// Outcome of one item of `POST /api/v1/backup/upload`, in request order
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupItemResult {
+    pub checksum: String,
+    pub filename: String,
+    /// `planned` (upload the chunks of `plan`), `stored` (nothing to upload)
+    /// or `failed` (see `code` and `message`)
+    pub status: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub plan: Option<UploadPlan>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+/// `sort_by` of `GET /api/v1/uploaded_files`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileSortKey {
+    /// Upload order
+    #[default]
+    Id,
+    /// Original filename, case-insensitive
+    Name,
+    Size,
+    /// Last change
+    Date,
+    /// Upload status
+    Status,
+    /// Extension, case-insensitive
+    Type,
+}
+
+impl FileSortKey {
+    pub const ALL: [FileSortKey; 6] = [Self::Id, Self::Name, Self::Size, Self::Date, Self::Status, Self::Type];
+
+    pub fn parse(value: &str) -> Option<Self> {
+        let value = value.trim().to_lowercase();
+        Self::ALL.into_iter().find(|key| key.as_str() == value)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Id => "id",
+            Self::Name => "name",
+            Self::Size => "size",
+            Self::Date => "date",
+            Self::Status => "status",
+            Self::Type => "type",
+        }
+    }
+}
+
+/// `order` of `GET /api/v1/uploaded_files`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+impl SortOrder {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "asc" => Some(Self::Asc),
+            "desc" => Some(Self::Desc),
+            _ => None,
+        }
+    }
+}
+
+/// How urgently an upload and the work it causes (merging, transcoding) are
+/// wanted. Waiting disk-heavy jobs run highest priority first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    /// Backups and other traffic nobody is waiting for
+    Bulk,
+    #[default]
+    Normal,
+    /// E.g. a file the user wants to play right away
+    High,
+}
+
+impl Priority {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "bulk" => Some(Self::Bulk),
+            "normal" => Some(Self::Normal),
+            "high" => Some(Self::High),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Bulk => "bulk",
+            Self::Normal => "normal",
+            Self::High => "high",
+        }
+    }
+}
+
+/// Timestamps are stored as Unix seconds and sent as UTC RFC 3339 strings,
+/// e.g. `2026-10-17T09:44:57Z`, with `#[serde(with = "timestamp")]`. Reading
+/// accepts both forms, so clients of older servers keep working.
+pub mod timestamp {
+    use chrono::{DateTime, SecondsFormat};
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Raw {
+        Seconds(i64),
+        Text(String),
+    }
+
+    impl Raw {
+        fn seconds<E: de::Error>(self) -> Result<i64, E> {
+            match self {
+                Raw::Seconds(secs) => Ok(secs),
+                Raw::Text(text) => DateTime::parse_from_rfc3339(&text)
+                    .map(|time| time.timestamp())
+                    .map_err(|e| E::custom(format!("invalid timestamp '{}': {}", text, e))),
+            }
+        }
+    }
+
+    pub fn to_rfc3339(secs: i64) -> String {
+        DateTime::from_timestamp(secs, 0).unwrap_or_default().to_rfc3339_opts(SecondsFormat::Secs, true)
+    }
+
+    pub fn serialize<S: Serializer>(secs: &i64, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&to_rfc3339(*secs))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<i64, D::Error> {
+        Raw::deserialize(deserializer)?.seconds()
+    }
+
+    /// For `Option<i64>` fields
+    pub mod option {
+        use super::{to_rfc3339, Raw};
+        use serde::{Deserialize, Deserializer, Serializer};
+
+        pub fn serialize<S: Serializer>(secs: &Option<i64>, serializer: S) -> Result<S::Ok, S::Error> {
+            match secs {
+                Some(secs) => serializer.serialize_str(&to_rfc3339(*secs)),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<i64>, D::Error> {
+            Option::<Raw>::deserialize(deserializer)?.map(Raw::seconds).transpose()
+        }
+    }
+}
@@ -0,0 +1,430 @@
+//! Failed authentication attempts: a wrong API key, admin key, device token
+//! or simple control key. Each is written to the auth log as one line that
+//! fail2ban can match, and counted per client address and per account the
+//! attempt named. An address or account with too many failures within the
+//! window is locked out for a while; admins list and lift lockouts under
+//! `/api/v1/admin/auth`. Counters are kept in memory, so a restart lifts every
+//! lockout.
+
+use axum::{
+    extract::{ConnectInfo, OriginalUri, Request, State},
+    http::{header, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use crate::api::timestamp::to_rfc3339;
+use crate::config::AppConfig;
+use crate::context::AppContext;
+use crate::helper::{error_response, ApiResponse};
+use crate::ip_allowlist::client_ip;
+use crate::tenants::ensure_not_tenant;
+
+/// Counters without a lockout or a failure in the current window are dropped
+/// once more than this many are kept
+const MAX_TRACKED: usize = 10_000;
+
+/// Marks a response as a failed authentication, recorded with the client's
+/// address by `guard_authentication`
+#[derive(Debug, Clone)]
+pub struct AuthFailure {
+    /// What was wrong: `api_key`, `admin_key`, `device_token` or `simple_key`
+    pub kind: &'static str,
+    /// The account the attempt was for, e.g. `admin` or `tenant:alice`
+    pub account: Option<String>,
+}
+
+impl AuthFailure {
+    pub fn new(kind: &'static str, account: Option<String>) -> Self {
+        Self { kind, account }
+    }
+
+    /// `response` marked with this failure
+    pub fn attach(self, mut response: Response) -> Response {
+        response.extensions_mut().insert(self);
+        response
+    }
+}
+
+#[derive(Debug, Default)]
+struct Counter {
+    failures: u32,
+    window_start: i64,
+    locked_until: Option<i64>,
+}
+
+impl Counter {
+    /// Count a failure; true when it starts a lockout
+    fn fail(&mut self, now: i64, config: &AppConfig, max_failures: u32) -> bool {
+        if now - self.window_start >= config.auth_failure_window_secs as i64 {
+            self.failures = 0;
+            self.window_start = now;
+        }
+        self.failures += 1;
+        if max_failures == 0 || self.failures < max_failures || self.is_locked(now) {
+            return false;
+        }
+        self.locked_until = Some(now + config.auth_lockout_secs as i64);
+        true
+    }
+
+    fn is_locked(&self, now: i64) -> bool {
+        self.locked_until.is_some_and(|until| until > now)
+    }
+
+    fn is_current(&self, now: i64, window_secs: i64) -> bool {
+        self.is_locked(now) || now - self.window_start < window_secs
+    }
+}
+
+#[derive(Default)]
+struct Counters {
+    ips: HashMap<IpAddr, Counter>,
+    accounts: HashMap<String, Counter>,
+}
+
+#[derive(Default)]
+pub struct AuthFailures {
+    counters: Mutex<Counters>,
+    /// The auth log, opened on the first failure and again when its path changes
+    log: Mutex<Option<(PathBuf, File)>>,
+}
+
+/// An address or account with recent failures
+#[derive(Debug, Serialize)]
+pub struct AuthFailureCount {
+    /// `ip` or `account`
+    kind: &'static str,
+    key: String,
+    /// Failures within the current window
+    failures: u32,
+    /// Set while locked out
+    #[serde(with = "crate::api::timestamp::option")]
+    locked_until: Option<i64>,
+}
+
+impl AuthFailures {
+    fn write_log(&self, path: &Path, lines: &str) {
+        let mut log = self.log.lock().unwrap();
+        if log.as_ref().is_none_or(|(open, _)| open != path) {
+            if let Some(dir) = path.parent() {
+                let _ = std::fs::create_dir_all(dir);
+            }
+            match OpenOptions::new().create(true).append(true).open(path) {
+                Ok(file) => *log = Some((path.to_path_buf(), file)),
+                Err(e) => {
+                    error!("Failed to open the auth log {}: {}", path.display(), e);
+                    return;
+                }
+            }
+        }
+        if let Some((_, file)) = log.as_mut() {
+            if let Err(e) = writeln!(file, "{}", lines) {
+                error!("Failed to write the auth log {}: {}", path.display(), e);
+            }
+        }
+    }
+
+    /// Count a failure of `ip` and log it, locking the address or account out
+    /// once it reaches its threshold
+    fn record(&self, config: &AppConfig, ip: IpAddr, failure: &AuthFailure, request: &str) {
+        let now = chrono::Utc::now().timestamp();
+        let window_secs = config.auth_failure_window_secs as i64;
+        let (ip_locked, account_locked) = {
+            let mut counters = self.counters.lock().unwrap();
+            if counters.ips.len() > MAX_TRACKED {
+                counters.ips.retain(|_, counter| counter.is_current(now, window_secs));
+            }
+            if counters.accounts.len() > MAX_TRACKED {
+                counters.accounts.retain(|_, counter| counter.is_current(now, window_secs));
+            }
+            let ip_locked = counters.ips.entry(ip).or_default().fail(now, config, config.auth_max_failures);
+            let account_locked = failure.account.as_ref().is_some_and(|account| {
+                counters.accounts.entry(account.clone()).or_default().fail(now, config, config.auth_account_max_failures)
+            });
+            (ip_locked, account_locked)
+        };
+
+        // 每行一个事件，字段顺序固定，便于 fail2ban 用 `ip=<HOST>` 匹配
+        let at = to_rfc3339(now);
+        let account = failure.account.as_deref().unwrap_or("-");
+        warn!("Authentication failure from {}: {} for {} ({})", ip, failure.kind, account, request);
+        let mut lines = format!("{} nascraft auth_failure ip={} kind={} account={} request={:?}", at, ip, failure.kind, account, request);
+        let until = to_rfc3339(now + config.auth_lockout_secs as i64);
+        if ip_locked {
+            warn!("Locked out {} until {} after {} failed authentications", ip, until, config.auth_max_failures);
+            lines.push_str(&format!("\n{} nascraft auth_lockout ip={} until={}", at, ip, until));
+        }
+        if account_locked {
+            warn!("Locked out account {} until {} after {} failed authentications", account, until, config.auth_account_max_failures);
+            lines.push_str(&format!("\n{} nascraft auth_lockout account={} until={}", at, account, until));
+        }
+        self.write_log(&config.auth_log_file, &lines);
+    }
+
+    /// End of the lockout of `ip`, None when it isn't locked out
+    pub fn ip_locked_until(&self, ip: IpAddr) -> Option<i64> {
+        let now = chrono::Utc::now().timestamp();
+        let counters = self.counters.lock().unwrap();
+        counters.ips.get(&ip).filter(|counter| counter.is_locked(now)).and_then(|counter| counter.locked_until)
+    }
+
+    /// End of the lockout of `account`, None when it isn't locked out
+    pub fn account_locked_until(&self, account: &str) -> Option<i64> {
+        let now = chrono::Utc::now().timestamp();
+        let counters = self.counters.lock().unwrap();
+        counters.accounts.get(account).filter(|counter| counter.is_locked(now)).and_then(|counter| counter.locked_until)
+    }
+
+    /// Addresses and accounts that are locked out or failed within the window,
+    /// most failures first
+    fn counts(&self, window_secs: i64) -> Vec<AuthFailureCount> {
+        let now = chrono::Utc::now().timestamp();
+        let counters = self.counters.lock().unwrap();
+        let count = |kind, key: String, counter: &Counter| AuthFailureCount {
+            kind,
+            key,
+            failures: if now - counter.window_start < window_secs { counter.failures } else { 0 },
+            locked_until: counter.locked_until.filter(|_| counter.is_locked(now)),
+        };
+        let mut counts: Vec<_> = counters
+            .ips
+            .iter()
+            .filter(|(_, counter)| counter.is_current(now, window_secs))
+            .map(|(ip, counter)| count("ip", ip.to_string(), counter))
+            .chain(counters
+                .accounts
+                .iter()
+                .filter(|(_, counter)| counter.is_current(now, window_secs))
+                .map(|(account, counter)| count("account", account.clone(), counter)))
+            .collect();
+        counts.sort_by(|a, b| b.locked_until.is_some().cmp(&a.locked_until.is_some()).then(b.failures.cmp(&a.failures)));
+        counts
+    }
+
+    /// Forget the failures of `ip` and `account`, lifting their lockouts;
+    /// false when neither had any
+    fn unlock(&self, ip: Option<IpAddr>, account: Option<&str>) -> bool {
+        let mut counters = self.counters.lock().unwrap();
+        let ip_removed = ip.is_some_and(|ip| counters.ips.remove(&ip).is_some());
+        let account_removed = account.is_some_and(|account| counters.accounts.remove(account).is_some());
+        ip_removed || account_removed
+    }
+}
+
+/// `429 AUTH_LOCKED` with `Retry-After` set to the end of the lockout
+pub fn locked_response(locked_until: i64) -> Response {
+    let retry_after = (locked_until - chrono::Utc::now().timestamp()).max(1);
+    let mut response = error_response(
+        StatusCode::TOO_MANY_REQUESTS,
+        "AUTH_LOCKED",
+        format!("Too many failed authentication attempts, try again in {} seconds", retry_after),
+    );
+    response.headers_mut().insert(header::RETRY_AFTER, HeaderValue::from(retry_after));
+    response
+}
+
+/// Refuse clients that are locked out, and record the failed authentication
+/// a response was marked with
+pub async fn guard_authentication(
+    State(ctx): State<AppContext>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let config = ctx.config.load();
+    let ip = client_ip(&peer, req.headers(), &config.trusted_proxies);
+    if let Some(until) = ctx.auth_failures.ip_locked_until(ip) {
+        return locked_response(until);
+    }
+    // 嵌套路由中的路径已去掉前缀，日志中记录完整路径
+    let path = req.extensions().get::<OriginalUri>().map_or(req.uri().path(), |uri| uri.path()).to_string();
+    let request = format!("{} {}", req.method(), path);
+    let response = next.run(req).await;
+    if let Some(failure) = response.extensions().get::<AuthFailure>() {
+        ctx.auth_failures.record(&config, ip, failure, &request);
+    }
+    response
+}
+
+/// Addresses and accounts with recent failed authentications
+pub async fn list_auth_failures(State(ctx): State<AppContext>) -> Response {
+    if let Err(resp) = ensure_not_tenant().await {
+        return resp;
+    }
+    let counts = ctx.auth_failures.counts(ctx.config.load().auth_failure_window_secs as i64);
+    (StatusCode::OK, Json(ApiResponse::success(counts))).into_response()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UnlockRequest {
+    ip: Option<String>,
+    account: Option<String>,
+}
+
+/// Lift the lockout of an address or account and forget its failures
+pub async fn unlock_auth(State(ctx): State<AppContext>, Json(req): Json<UnlockRequest>) -> Response {
+    if let Err(resp) = ensure_not_tenant().await {
+        return resp;
+    }
+    let ip = match req.ip.as_deref().map(str::trim).map(str::parse::<IpAddr>) {
+        Some(Ok(ip)) => Some(ip.to_canonical()),
+        Some(Err(_)) => return error_response(StatusCode::BAD_REQUEST, "INVALID_IP", format!("'{}' is not an IP address", req.ip.unwrap_or_default())),
+        None => None,
+    };
+    let account = req.account.as_deref().map(str::trim).filter(|account| !account.is_empty());
+    if ip.is_none() && account.is_none() {
+        return error_response(StatusCode::BAD_REQUEST, "MISSING_PARAMETER", "Set ip, account or both".to_string());
+    }
+    if !ctx.auth_failures.unlock(ip, account) {
+        return error_response(StatusCode::NOT_FOUND, "NO_FAILURES", "No failed authentications are recorded for it".to_string());
+    }
+    info!("Lifted the authentication lockout of ip={:?} account={:?}", ip, account);
+    (StatusCode::OK, Json(ApiResponse::success(json!({
+        "ip": ip,
+        "account": account,
+        "unlocked": true,
+    })))).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(log: &str) -> AppConfig {
+        let mut config = AppConfig::for_tests();
+        config.auth_max_failures = 3;
+        config.auth_account_max_failures = 2;
+        config.auth_failure_window_secs = 600;
+        config.auth_lockout_secs = 900;
+        config.auth_log_file = std::env::temp_dir().join(format!("nascraft-{}-{}.log", log, uuid::Uuid::new_v4().simple()));
+        config
+    }
+
+    /// The log without the leading timestamp of each line
+    fn log_lines(config: &AppConfig) -> Vec<String> {
+        let content = std::fs::read_to_string(&config.auth_log_file).unwrap();
+        let _ = std::fs::remove_file(&config.auth_log_file);
+        content
+            .lines()
+            .map(|line| {
+                let (at, rest) = line.split_once(' ').unwrap();
+                assert!(chrono::DateTime::parse_from_rfc3339(at).is_ok(), "{:?} doesn't start with a timestamp", line);
+                rest.to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn counter_locks_at_the_threshold() {
+        let config = config("counter");
+        let mut counter = Counter::default();
+        assert!(!counter.fail(1000, &config, 3));
+        assert!(!counter.fail(1001, &config, 3));
+        assert!(counter.fail(1002, &config, 3));
+        assert_eq!(counter.locked_until, Some(1002 + 900));
+        assert!(counter.is_locked(1002 + 899));
+        assert!(!counter.is_locked(1002 + 900));
+        // 锁定期间的失败不会重新开始锁定
+        assert!(!counter.fail(1003, &config, 3));
+        assert_eq!(counter.locked_until, Some(1002 + 900));
+    }
+
+    #[test]
+    fn counter_forgets_failures_outside_the_window() {
+        let config = config("window");
+        let mut counter = Counter::default();
+        assert!(!counter.fail(1000, &config, 3));
+        assert!(!counter.fail(1599, &config, 3));
+        assert!(!counter.fail(1600, &config, 3));
+        assert_eq!(counter.failures, 1);
+        assert!(counter.is_current(2199, 600));
+        assert!(!counter.is_current(2200, 600));
+    }
+
+    #[test]
+    fn zero_threshold_never_locks() {
+        let config = config("zero");
+        let mut counter = Counter::default();
+        for now in 1000..1100 {
+            assert!(!counter.fail(now, &config, 0));
+        }
+        assert_eq!(counter.locked_until, None);
+    }
+
+    #[test]
+    fn failures_are_logged_one_line_each() {
+        let config = config("lines");
+        let failures = AuthFailures::default();
+        let ip: IpAddr = "203.0.113.7".parse().unwrap();
+        failures.record(&config, ip, &AuthFailure::new("api_key", None), "GET /api/v1/uploaded_files");
+        failures.record(&config, ip, &AuthFailure::new("admin_key", Some("admin".to_string())), "POST /api/v1/admin/restore_metadata");
+        assert_eq!(log_lines(&config), [
+            "nascraft auth_failure ip=203.0.113.7 kind=api_key account=- request=\"GET /api/v1/uploaded_files\"",
+            "nascraft auth_failure ip=203.0.113.7 kind=admin_key account=admin request=\"POST /api/v1/admin/restore_metadata\"",
+        ]);
+    }
+
+    #[test]
+    fn lockouts_are_logged_after_the_failure() {
+        let config = config("lockout");
+        let failures = AuthFailures::default();
+        let ip: IpAddr = "2001:db8::1".parse().unwrap();
+        let failure = AuthFailure::new("device_token", Some("tenant:alice".to_string()));
+        for _ in 0..3 {
+            failures.record(&config, ip, &failure, "GET /api/v1/files");
+        }
+        let lines = log_lines(&config);
+        assert_eq!(lines.len(), 5, "{:#?}", lines);
+        assert_eq!(lines[0], "nascraft auth_failure ip=2001:db8::1 kind=device_token account=tenant:alice request=\"GET /api/v1/files\"");
+        assert!(lines[2].starts_with("nascraft auth_lockout account=tenant:alice until="), "{}", lines[2]);
+        assert!(lines[4].starts_with("nascraft auth_lockout ip=2001:db8::1 until="), "{}", lines[4]);
+        let until = lines[4].rsplit_once("until=").unwrap().1;
+        assert!(chrono::DateTime::parse_from_rfc3339(until).is_ok());
+    }
+
+    #[test]
+    fn request_text_is_quoted() {
+        let config = config("quoted");
+        let failures = AuthFailures::default();
+        failures.record(&config, "192.0.2.1".parse().unwrap(), &AuthFailure::new("simple_key", None), "GET /simple/\"x\" ip=10.0.0.1");
+        assert_eq!(log_lines(&config), ["nascraft auth_failure ip=192.0.2.1 kind=simple_key account=- request=\"GET /simple/\\\"x\\\" ip=10.0.0.1\""]);
+    }
+
+    #[test]
+    fn lockouts_apply_and_lift() {
+        let config = config("unlock");
+        let failures = AuthFailures::default();
+        let ip: IpAddr = "198.51.100.2".parse().unwrap();
+        let other: IpAddr = "198.51.100.3".parse().unwrap();
+        let failure = AuthFailure::new("api_key", Some("tenant:bob".to_string()));
+        failures.record(&config, ip, &failure, "GET /");
+        failures.record(&config, other, &failure, "GET /");
+        assert!(failures.ip_locked_until(ip).is_none());
+        assert!(failures.account_locked_until("tenant:bob").is_some());
+
+        failures.record(&config, ip, &failure, "GET /");
+        failures.record(&config, ip, &failure, "GET /");
+        assert!(failures.ip_locked_until(ip).is_some());
+        assert!(failures.ip_locked_until(other).is_none());
+
+        let counts = failures.counts(600);
+        assert_eq!(counts.len(), 3);
+        assert!(counts.iter().all(|count| count.locked_until.is_some() || count.key == other.to_string()));
+
+        assert!(failures.unlock(Some(ip), Some("tenant:bob")));
+        assert!(failures.ip_locked_until(ip).is_none());
+        assert!(failures.account_locked_until("tenant:bob").is_none());
+        assert!(!failures.unlock(Some(ip), None));
+        let _ = std::fs::remove_file(&config.auth_log_file);
+    }
+}
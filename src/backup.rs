@@ -0,0 +1,151 @@
+//! Camera-roll backup for mobile clients. A phone sends the checksums of its
+//! photos and videos in batches and learns which the server is missing, then
+//! submits only those in one request and uploads their chunks through the
+//! usual `/upload` endpoint.
+
+use axum::{
+    body::to_bytes,
+    extract::{ConnectInfo, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use log::info;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use crate::api::{BackupCheck, BackupCheckResult, BackupItemResult, BackupUpload, FileMetadata, Priority, UploadPlan};
+use crate::context::AppContext;
+use crate::helper::{error_response, ApiResponse};
+use crate::tenants::current_tenant_id;
+use crate::upload::submit_file_metadata;
+use crate::upload_dao::fetch_stored_checksums;
+
+/// Most checksums per `backup/check` request
+const MAX_CHECK_BATCH: usize = 1000;
+/// Most items per `backup/upload` request
+const MAX_UPLOAD_BATCH: usize = 200;
+/// Upload plans of large files list many chunks
+const MAX_SUBMIT_RESPONSE_BYTES: usize = 16 * 1024 * 1024;
+
+/// Report which of the device's checksums the server has no completed file for
+pub async fn check_backup(
+    State(ctx): State<AppContext>,
+    Json(req): Json<BackupCheck>,
+) -> Response {
+    if req.checksums.len() > MAX_CHECK_BATCH {
+        return error_response(StatusCode::BAD_REQUEST, "BATCH_TOO_LARGE", format!(
+            "At most {} checksums may be checked at once",
+            MAX_CHECK_BATCH
+        ));
+    }
+    let mut checksums: Vec<String> = Vec::with_capacity(req.checksums.len());
+    for checksum in req.checksums {
+        let checksum = checksum.trim().to_lowercase();
+        if !checksum.is_empty() && !checksums.contains(&checksum) {
+            checksums.push(checksum);
+        }
+    }
+
+    let stored = match fetch_stored_checksums(&ctx.app_state.db_pool, &checksums, current_tenant_id()).await {
+        Ok(stored) => stored,
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "CHECKSUM_CHECK_ERROR", e),
+    };
+    let missing = checksums
+        .into_iter()
+        .filter(|checksum| !stored.iter().any(|s| &s.checksum == checksum))
+        .collect();
+    (StatusCode::OK, Json(ApiResponse::success(BackupCheckResult { missing, stored }))).into_response()
+}
+
+/// Submit one item through `submit_metadata`, so it is subject to the same
+/// folder policies, quota, deduplication and upload keys
+async fn submit_item(ctx: &AppContext, client_addr: SocketAddr, headers: &HeaderMap, mut item: FileMetadata) -> BackupItemResult {
+    // 备份不急于完成，合并时让位于用户等待的上传
+    item.priority.get_or_insert(Priority::Bulk);
+    let mut result = BackupItemResult {
+        checksum: item.checksum.clone(),
+        filename: item.filename.clone(),
+        status: "failed".to_string(),
+        file_id: None,
+        plan: None,
+        code: None,
+        message: None,
+    };
+    let response = submit_file_metadata(State(ctx.clone()), ConnectInfo(client_addr), headers.clone(), Json(item))
+        .await
+        .into_response();
+    let status = response.status();
+    let body = to_bytes(response.into_body(), MAX_SUBMIT_RESPONSE_BYTES).await.unwrap_or_default();
+    let Ok(envelope) = serde_json::from_slice::<Value>(&body) else {
+        result.code = Some("SUBMIT_ERROR".to_string());
+        result.message = Some(String::from_utf8_lossy(&body).into_owned());
+        return result;
+    };
+
+    let data = envelope.get("data").cloned().unwrap_or(Value::Null);
+    if !status.is_success() || data.is_null() {
+        result.code = envelope.get("code").and_then(Value::as_str).map(str::to_string);
+        result.message = envelope.get("message").and_then(Value::as_str).map(str::to_string);
+        return result;
+    }
+    result.file_id = data.get("id").and_then(Value::as_str).map(str::to_string);
+    if data.get("status").and_then(Value::as_str) == Some("duplicate") {
+        result.status = "stored".to_string();
+        return result;
+    }
+    match serde_json::from_value::<UploadPlan>(data) {
+        Ok(plan) => {
+            result.status = "planned".to_string();
+            result.plan = Some(plan);
+        }
+        Err(e) => {
+            result.code = Some("SUBMIT_ERROR".to_string());
+            result.message = Some(format!("Unexpected upload plan: {}", e));
+        }
+    }
+    result
+}
+
+/// Start uploads for a batch of items, typically those `backup/check`
+/// reported missing. Items that turn out to be stored are skipped; an item's
+/// failure doesn't affect the others.
+pub async fn upload_backup(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(req): Json<BackupUpload>,
+) -> Response {
+    if req.items.len() > MAX_UPLOAD_BATCH {
+        return error_response(StatusCode::BAD_REQUEST, "BATCH_TOO_LARGE", format!(
+            "At most {} items may be submitted at once",
+            MAX_UPLOAD_BATCH
+        ));
+    }
+
+    let mut results: Vec<BackupItemResult> = Vec::with_capacity(req.items.len());
+    // 同一批中内容相同的条目（如连拍的重复照片）只上传一次
+    let mut submitted: HashMap<String, usize> = HashMap::new();
+    for item in req.items {
+        let key = item.checksum.trim().to_lowercase();
+        if let Some(&first) = submitted.get(&key) {
+            let mut repeated = results[first].clone();
+            repeated.filename = item.filename;
+            if repeated.status == "planned" {
+                repeated.status = "stored".to_string();
+                repeated.plan = None;
+            }
+            results.push(repeated);
+            continue;
+        }
+        submitted.insert(key, results.len());
+        results.push(submit_item(&ctx, client_addr, &headers, item).await);
+    }
+
+    let count = |status: &str| results.iter().filter(|r| r.status == status).count();
+    info!(
+        "Backup batch from {}: {} planned, {} already stored, {} failed",
+        client_addr, count("planned"), count("stored"), count("failed")
+    );
+    (StatusCode::OK, Json(ApiResponse::success(results))).into_response()
+}
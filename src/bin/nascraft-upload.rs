@@ -0,0 +1,271 @@
+//! Command-line uploader built on `nascraft::client`.
+//!
+//! ```text
+//! nascraft-upload [--server URL] [--folder ID] [--parallel N] [--retries N] [--state FILE] [--sparse]
+//!                 [--overwrite] [--priority LEVEL] [--device NAME] [--token TOKEN] <PATH>...
+//! ```
+//!
+//! Directories are uploaded recursively. The file id of every upload in flight
+//! is kept in the state file, so running the same command again after an
+//! interruption resumes from the chunks the server already has.
+
+use indicatif::{ProgressBar, ProgressStyle};
+use nascraft::client::{file_md5, find_holes, Client, ClientError, SubmitResult};
+use nascraft::api::{FileMetadata, Priority};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::UNIX_EPOCH;
+use uuid::Uuid;
+
+const USAGE: &str = "Usage: nascraft-upload [--server URL] [--folder ID] [--parallel N] [--retries N] [--state FILE] [--sparse]
+                       [--overwrite] [--priority LEVEL] [--device NAME] [--token TOKEN] <PATH>...
+
+Options:
+  --server URL    Server root (default: $NASCRAFT_SERVER or http://localhost:8080)
+  --folder ID     Upload into this server folder, subject to its policies
+  --parallel N    Chunks uploaded concurrently per file (default: 4)
+  --retries N     Retries per chunk on network errors and checksum mismatches (default: 3)
+  --state FILE    Where unfinished uploads are recorded (default: .nascraft-upload.json)
+  --sparse        Send runs of zeros as holes instead of data, for disk images
+  --overwrite     Replace files with the same name on the server instead of failing
+  --priority LEVEL
+                  bulk, normal or high; the server merges and transcodes higher priorities first
+  --device NAME   Name the server lists the uploads under when no token is given
+  --token TOKEN   Token of this device registered on the server (default: $NASCRAFT_DEVICE_TOKEN)";
+
+struct Options {
+    server: String,
+    folder_id: Option<i64>,
+    parallel: usize,
+    retries: u32,
+    state_path: PathBuf,
+    sparse: bool,
+    overwrite: bool,
+    priority: Option<Priority>,
+    device: Option<String>,
+    token: Option<String>,
+    paths: Vec<PathBuf>,
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options {
+        server: std::env::var("NASCRAFT_SERVER").unwrap_or_else(|_| "http://localhost:8080".to_string()),
+        folder_id: None,
+        parallel: 4,
+        retries: 3,
+        state_path: PathBuf::from(".nascraft-upload.json"),
+        sparse: false,
+        overwrite: false,
+        priority: None,
+        device: None,
+        token: std::env::var("NASCRAFT_DEVICE_TOKEN").ok().filter(|token| !token.is_empty()),
+        paths: Vec::new(),
+    };
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or_else(|| format!("{} needs a value", name));
+        match arg.as_str() {
+            "--server" => options.server = value("--server")?,
+            "--folder" => options.folder_id = Some(value("--folder")?.parse().map_err(|_| "--folder must be a folder id".to_string())?),
+            "--parallel" => options.parallel = value("--parallel")?.parse().map_err(|_| "--parallel must be a number".to_string())?,
+            "--retries" => options.retries = value("--retries")?.parse().map_err(|_| "--retries must be a number".to_string())?,
+            "--state" => options.state_path = PathBuf::from(value("--state")?),
+            "--sparse" => options.sparse = true,
+            "--overwrite" => options.overwrite = true,
+            "--priority" => options.priority = Some(Priority::parse(&value("--priority")?).ok_or_else(|| "--priority must be bulk, normal or high".to_string())?),
+            "--device" => options.device = Some(value("--device")?),
+            "--token" => options.token = Some(value("--token")?),
+            "-h" | "--help" => return Err(String::new()),
+            _ if arg.starts_with("--") => return Err(format!("Unknown option {}", arg)),
+            _ => options.paths.push(PathBuf::from(arg)),
+        }
+    }
+
+    if options.paths.is_empty() {
+        return Err("No files given".to_string());
+    }
+    Ok(options)
+}
+
+/// An upload that was started but not confirmed complete
+#[derive(Serialize, Deserialize, Clone)]
+struct PendingUpload {
+    file_id: String,
+    size: u64,
+    modified: u64,
+    checksum: String,
+}
+
+struct ResumeState {
+    path: PathBuf,
+    pending: HashMap<String, PendingUpload>,
+}
+
+impl ResumeState {
+    fn load(path: &Path) -> Self {
+        let pending = std::fs::read_to_string(path)
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default();
+        Self { path: path.to_path_buf(), pending }
+    }
+
+    fn save(&self) {
+        let result = if self.pending.is_empty() {
+            match std::fs::remove_file(&self.path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            }
+        } else {
+            std::fs::write(&self.path, serde_json::to_string_pretty(&self.pending).unwrap_or_default())
+        };
+        if let Err(e) = result {
+            eprintln!("warning: failed to write {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+fn collect_files(path: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    if path.is_dir() {
+        let mut entries = std::fs::read_dir(path)?.collect::<Result<Vec<_>, _>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+        for entry in entries {
+            collect_files(&entry.path(), files)?;
+        }
+    } else {
+        files.push(path.to_path_buf());
+    }
+    Ok(())
+}
+
+fn progress_bar(name: &str, total: u64) -> ProgressBar {
+    let bar = ProgressBar::new(total);
+    bar.set_style(
+        ProgressStyle::with_template("{msg:30!} [{bar:40}] {bytes}/{total_bytes} {bytes_per_sec} {eta}")
+            .expect("valid progress template")
+            .progress_chars("=> "),
+    );
+    bar.set_message(name.to_string());
+    bar
+}
+
+/// Upload one file, resuming a recorded upload when the file hasn't changed since
+async fn upload_one(client: &Client, state: &mut ResumeState, path: &Path) -> Result<String, ClientError> {
+    let key = std::fs::canonicalize(path)?.to_string_lossy().into_owned();
+    let meta = std::fs::metadata(path)?;
+    let modified = meta.modified()?.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+
+    if let Some(pending) = state.pending.get(&key).filter(|p| p.size == meta.len() && p.modified == modified).cloned() {
+        let bar = progress_bar(&name, pending.size);
+        match client.resume_upload(&pending.file_id, path, Some(&pending.checksum), |done, _| bar.set_position(done)).await {
+            Ok(outcome) => {
+                bar.finish();
+                state.pending.remove(&key);
+                state.save();
+                return Ok(outcome.file_id);
+            }
+            // The server no longer knows this upload; start over
+            Err(ClientError::Api { code, .. }) if code == "FETCH_FILE_RECORD_ERROR" => bar.finish_and_clear(),
+            Err(e) => {
+                bar.abandon();
+                return Err(e);
+            }
+        }
+    }
+
+    let (size, checksum) = file_md5(path).await?;
+    let metadata = FileMetadata {
+        filename: name.clone(),
+        total_size: size,
+        checksum: checksum.clone(),
+        folder_id: client.folder_id(),
+        upload_key: Some(Uuid::new_v4().to_string()),
+        holes: if client.detect_holes() { find_holes(path).await? } else { Vec::new() },
+        overwrite: client.overwrite(),
+        priority: client.priority(),
+    };
+    let plan = match client.submit_metadata(&metadata).await? {
+        SubmitResult::Duplicate { file_id } => {
+            println!("{}: already on server as {}", name, file_id);
+            state.pending.remove(&key);
+            state.save();
+            return Ok(file_id);
+        }
+        SubmitResult::Planned(plan) => plan,
+    };
+
+    state.pending.insert(key.clone(), PendingUpload { file_id: plan.file_id.clone(), size, modified, checksum: checksum.clone() });
+    state.save();
+
+    let bar = progress_bar(&name, size);
+    let result = client.upload_planned(&plan, path, &checksum, |done, _| bar.set_position(done)).await;
+    match result {
+        Ok(outcome) => {
+            bar.finish();
+            state.pending.remove(&key);
+            state.save();
+            Ok(outcome.file_id)
+        }
+        Err(e) => {
+            bar.abandon();
+            Err(e)
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let options = match parse_args() {
+        Ok(options) => options,
+        Err(message) => {
+            if !message.is_empty() {
+                eprintln!("{}\n", message);
+            }
+            eprintln!("{}", USAGE);
+            return ExitCode::from(2);
+        }
+    };
+
+    let mut files = Vec::new();
+    for path in &options.paths {
+        if let Err(e) = collect_files(path, &mut files) {
+            eprintln!("{}: {}", path.display(), e);
+            return ExitCode::FAILURE;
+        }
+    }
+
+    let mut client = Client::new(&options.server)
+        .with_parallel_chunks(options.parallel)
+        .with_max_retries(options.retries)
+        .with_hole_detection(options.sparse)
+        .with_overwrite(options.overwrite)
+        .with_device(options.device.as_deref(), options.token.as_deref());
+    if let Some(folder_id) = options.folder_id {
+        client = client.with_folder(folder_id);
+    }
+    if let Some(priority) = options.priority {
+        client = client.with_priority(priority);
+    }
+    let mut state = ResumeState::load(&options.state_path);
+
+    let mut failed = 0;
+    for file in &files {
+        match upload_one(&client, &mut state, file).await {
+            Ok(file_id) => println!("{} -> {}", file.display(), file_id),
+            Err(e) => {
+                eprintln!("{}: {}", file.display(), e);
+                failed += 1;
+            }
+        }
+    }
+
+    if failed > 0 {
+        eprintln!("{} of {} files failed; run the same command again to resume", failed, files.len());
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
+}
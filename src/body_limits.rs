@@ -0,0 +1,68 @@
+//! Request body limits. JSON endpoints buffer the whole body before parsing
+//! it, so each is capped, and bodies over the cap are refused with a JSON
+//! `413 PAYLOAD_TOO_LARGE` before or while they are read. The streaming
+//! endpoints check the bytes against what the request declared as they
+//! arrive instead.
+
+use axum::{
+    body::Body,
+    extract::Request,
+    http::{header, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use http_body_util::Limited;
+use log::warn;
+use crate::helper::{error_response, format_size};
+
+/// `submit_metadata`, including a file's list of holes
+pub const METADATA_BODY_LIMIT: usize = 1024 * 1024;
+/// Batches of the backup endpoints, each item a `submit_metadata` body
+pub const BATCH_BODY_LIMIT: usize = 16 * 1024 * 1024;
+/// Every other JSON endpoint
+pub const JSON_BODY_LIMIT: usize = 2 * 1024 * 1024;
+
+/// Cap of the body of a request to `path` (relative to `/api/v1`); None for
+/// the endpoints that stream their body to disk
+fn body_limit(path: &str) -> Option<usize> {
+    match path {
+        "/upload" | "/bundles" => None,
+        path if path.starts_with("/files/") && path.ends_with("/delta") => None,
+        "/submit_metadata" => Some(METADATA_BODY_LIMIT),
+        "/backup/check" | "/backup/upload" => Some(BATCH_BODY_LIMIT),
+        _ => Some(JSON_BODY_LIMIT),
+    }
+}
+
+fn payload_too_large(limit: usize) -> Response {
+    error_response(StatusCode::PAYLOAD_TOO_LARGE, "PAYLOAD_TOO_LARGE", format!("Request body is larger than the {} this endpoint accepts", format_size(limit as u64)))
+}
+
+/// Refuse bodies over the route's cap: right away when `Content-Length`
+/// declares more, otherwise once that many bytes have been read
+pub async fn limit_request_body(req: Request, next: Next) -> Response {
+    let Some(limit) = body_limit(req.uri().path()) else {
+        return next.run(req).await;
+    };
+    let declared = req
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.parse::<u64>().ok());
+    if let Some(len) = declared.filter(|&len| len > limit as u64) {
+        warn!("Refused a {} byte body for {}, the limit is {}", len, req.uri().path(), limit);
+        return payload_too_large(limit);
+    }
+
+    // 未声明长度（分块传输）或声明不实的请求体读到上限即中止
+    let response = next.run(req.map(|body| Body::new(Limited::new(body, limit)))).await;
+    // 提取器对超限请求体返回纯文本 413，改为统一的 JSON 错误
+    let is_json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|value| value.as_bytes().starts_with(b"application/json"));
+    if response.status() == StatusCode::PAYLOAD_TOO_LARGE && !is_json {
+        return payload_too_large(limit);
+    }
+    response
+}
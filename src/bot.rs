@@ -0,0 +1,423 @@
+//! Chat bots for Telegram and Discord: commands to list recent files, hand out
+//! share links and play files on renderers, and notifications about completed
+//! uploads and low disk space. Bots act with full access to the library.
+
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use log::{info, warn};
+use ring::signature::{UnparsedPublicKey, ED25519};
+use serde_json::{json, Value};
+use sqlx::SqlitePool;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use crate::config::AppConfig;
+use crate::context::AppContext;
+use crate::events::{EventSender, ServerEvent};
+use crate::feeds::item_title;
+use crate::fuzzy::match_score;
+use crate::helper::format_size;
+use crate::api::{FileSortKey, SortOrder};
+use crate::library_query::LibraryQuery;
+use crate::media_library::{attach_media_titles, parse_media_name};
+use crate::renderer::{discover_renderers, find_renderer, Renderer};
+use crate::repository::UploadRepository;
+use crate::shares::{create_share, ShareKind, ShareOptions};
+use crate::upload_dao::UploadedFile;
+
+const TELEGRAM_API: &str = "https://api.telegram.org";
+const DISCORD_API: &str = "https://discord.com/api/v10";
+/// Long-polling timeout of Telegram's getUpdates
+const POLL_TIMEOUT: Duration = Duration::from_secs(50);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const RETRY_DELAY: Duration = Duration::from_secs(10);
+const DEFAULT_RECENT: u32 = 5;
+const MAX_RECENT: u32 = 20;
+/// Validity of links handed out by `share`
+const SHARE_LINK_HOURS: i64 = 7 * 24;
+/// Validity of the link a renderer streams a file from
+pub const PLAYBACK_LINK_HOURS: i64 = 24;
+
+const HELP: &str = "Commands:
+/recent [count] - newest files
+/share <file> - share link valid for 7 days
+/play <file> on <device> - play a file on a renderer
+/devices - renderers on the network";
+
+/// A command as typed in a chat, without the leading `/`
+enum BotCommand {
+    Recent(u32),
+    Share(String),
+    Play { file: String, device: String },
+    Devices,
+    Help,
+}
+
+impl BotCommand {
+    fn parse(text: &str) -> Result<Self, String> {
+        let text = text.trim();
+        let (name, argument) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+        // Telegram appends the bot's name in groups: /recent@nascraft_bot
+        let name = name.split('@').next().unwrap_or_default().to_lowercase();
+        let argument = argument.trim();
+        match name.as_str() {
+            "recent" if argument.is_empty() => Ok(BotCommand::Recent(DEFAULT_RECENT)),
+            "recent" => argument.parse::<u32>()
+                .map(|count| BotCommand::Recent(count.clamp(1, MAX_RECENT)))
+                .map_err(|_| format!("'{}' is not a number", argument)),
+            "share" if !argument.is_empty() => Ok(BotCommand::Share(argument.to_string())),
+            "play" => match argument.rsplit_once(" on ") {
+                Some((file, device)) if !file.trim().is_empty() && !device.trim().is_empty() => Ok(BotCommand::Play {
+                    file: file.trim().to_string(),
+                    device: device.trim().to_string(),
+                }),
+                _ => Err("Usage: /play <file> on <device>".to_string()),
+            },
+            "devices" => Ok(BotCommand::Devices),
+            "help" | "start" => Ok(BotCommand::Help),
+            "share" => Err("Usage: /share <file>".to_string()),
+            _ => Err(format!("Unknown command '{}'\n\n{}", name, HELP)),
+        }
+    }
+}
+
+/// Lowest `match_score` at which a file counts as named by a query
+const MIN_MATCH_SCORE: f64 = 0.5;
+
+/// How well `query` names a file, by its scraped title or a title guessed from its name
+fn file_score(query: &str, file: &UploadedFile) -> f64 {
+    let name = file.original_filename.as_deref().unwrap_or(&file.filename);
+    match_score(query, &item_title(file)).max(match_score(query, &parse_media_name(name).title))
+}
+
+/// The completed file with this ID, else the one whose name or title matches `query` best
+pub async fn find_file(db_pool: &SqlitePool, query: &str) -> Result<UploadedFile, String> {
+    if let Ok(Some(file)) = db_pool.fetch_uploaded_file(query).await {
+        if file.status == 2 {
+            return Ok(file);
+        }
+    }
+    let mut files = LibraryQuery::unrestricted().sort(FileSortKey::Date, SortOrder::Desc).fetch(db_pool).await?;
+    attach_media_titles(db_pool, &mut files).await;
+
+    let mut ranked: Vec<(f64, UploadedFile)> = files
+        .into_iter()
+        .map(|file| (file_score(query, &file), file))
+        .filter(|(score, _)| *score >= MIN_MATCH_SCORE)
+        .collect();
+    ranked.sort_by(|a, b| b.0.total_cmp(&a.0));
+    match ranked.as_slice() {
+        [] => Err(format!("No file matches '{}'", query)),
+        [(best, _), (second, _), ..] if best - second < f64::EPSILON => Err(format!(
+            "Several files match '{}':\n{}",
+            query,
+            ranked.iter()
+                .take_while(|(score, _)| best - score < f64::EPSILON)
+                .take(5)
+                .map(|(_, f)| format!("- {}", item_title(f)))
+                .collect::<Vec<_>>()
+                .join("\n")
+        )),
+        _ => Ok(ranked.swap_remove(0).1),
+    }
+}
+
+/// Address renderers on the LAN reach the server at
+pub fn lan_base_url(ctx: &AppContext, config: &AppConfig) -> String {
+    format!("http://{}:{}", *ctx.local_addr.borrow(), config.server_port)
+}
+
+pub async fn share_link(ctx: &AppContext, file: &UploadedFile, hours: i64, base_url: &str) -> Result<String, String> {
+    let expires_at = chrono::Utc::now().timestamp() + hours * 3600;
+    let share = create_share(&ctx.app_state.db_pool, &file.file_id, ShareKind::Link, ShareOptions { expires_at: Some(expires_at), ..ShareOptions::default() }).await?;
+    Ok(share.url(base_url))
+}
+
+/// Play a library file on a renderer, which streams it through a share link
+pub async fn play_file(ctx: &AppContext, file: &UploadedFile, renderer: &Renderer) -> Result<String, String> {
+    let config = ctx.config.load();
+    let url = share_link(ctx, file, PLAYBACK_LINK_HOURS, &lan_base_url(ctx, &config)).await?;
+    let name = file.original_filename.as_deref().unwrap_or(&file.filename);
+    let mime_type = mime_guess::from_path(name).first_or_octet_stream();
+    let title = item_title(file);
+    renderer.play_url(&url, &title, mime_type.essence_str()).await?;
+    Ok(format!("Playing {} on {}", title, renderer.name))
+}
+
+async fn execute(ctx: &AppContext, command: BotCommand) -> Result<String, String> {
+    let db_pool = &ctx.app_state.db_pool;
+    let config = ctx.config.load();
+    match command {
+        BotCommand::Recent(count) => {
+            let mut files = LibraryQuery::unrestricted()
+                .sort(FileSortKey::Date, SortOrder::Desc)
+                .paginate(1, count)
+                .fetch(db_pool)
+                .await?;
+            if files.is_empty() {
+                return Ok("No files yet".to_string());
+            }
+            attach_media_titles(db_pool, &mut files).await;
+            Ok(files
+                .iter()
+                .map(|f| format!("- {} ({})", item_title(f), format_size(f.total_size.max(0) as u64)))
+                .collect::<Vec<_>>()
+                .join("\n"))
+        }
+        BotCommand::Share(query) => {
+            let file = find_file(db_pool, &query).await?;
+            let base_url = config.public_url.clone().unwrap_or_else(|| lan_base_url(ctx, &config));
+            let url = share_link(ctx, &file, SHARE_LINK_HOURS, &base_url).await?;
+            Ok(format!("{}\n{}", item_title(&file), url))
+        }
+        BotCommand::Play { file, device } => {
+            let file = find_file(db_pool, &file).await?;
+            let renderers = discover_renderers().await?;
+            play_file(ctx, &file, find_renderer(&renderers, &device)?).await
+        }
+        BotCommand::Devices => {
+            let renderers = discover_renderers().await?;
+            if renderers.is_empty() {
+                return Ok("No renderers found".to_string());
+            }
+            Ok(renderers.iter().map(|r| format!("- {}", r.name)).collect::<Vec<_>>().join("\n"))
+        }
+        BotCommand::Help => Ok(HELP.to_string()),
+    }
+}
+
+/// Reply to a chat command
+async fn run_command(ctx: &AppContext, text: &str) -> String {
+    info!("Bot command: {}", text);
+    match BotCommand::parse(text) {
+        Ok(command) => execute(ctx, command).await.unwrap_or_else(|e| e),
+        Err(e) => e,
+    }
+}
+
+fn event_message(event: &ServerEvent) -> String {
+    match event {
+        ServerEvent::UploadCompleted { filename, size, inbox: Some(inbox), .. } => {
+            format!("New file in {}: {} ({})", inbox, filename, format_size(*size))
+        }
+        ServerEvent::UploadCompleted { filename, size, .. } => {
+            format!("Upload completed: {} ({})", filename, format_size(*size))
+        }
+        ServerEvent::LowDiskSpace { path, available_bytes, total_bytes, critical, read_only } => format!(
+            "{}: {} of {} free on {}{}",
+            if *critical { "Critically low disk space" } else { "Low disk space" },
+            format_size(*available_bytes), format_size(*total_bytes), path.display(),
+            if *read_only { ", uploads are paused until space is freed" } else { "" }
+        ),
+    }
+}
+
+/// Call a Bot API method; errors never contain the URL, which holds the token
+async fn telegram_call(client: &reqwest::Client, token: &str, method: &str, body: Value, timeout: Duration) -> Result<Value, String> {
+    let response = client
+        .post(format!("{}/bot{}/{}", TELEGRAM_API, token, method))
+        .timeout(timeout)
+        .json(&body)
+        .send()
+        .await
+        .map_err(|e| format!("Request failed: {}", e.without_url()))?;
+    let mut body: Value = response.json().await.map_err(|e| format!("Invalid response: {}", e.without_url()))?;
+    if body["ok"] != json!(true) {
+        return Err(body["description"].as_str().unwrap_or("unknown error").to_string());
+    }
+    Ok(body["result"].take())
+}
+
+async fn telegram_send(client: &reqwest::Client, token: &str, chat_id: i64, text: &str) -> Result<(), String> {
+    telegram_call(client, token, "sendMessage", json!({
+        "chat_id": chat_id,
+        "text": text,
+        "disable_web_page_preview": true,
+    }), REQUEST_TIMEOUT).await.map(|_| ())
+}
+
+/// Answer commands sent to the Telegram bot from `NASCRAFT_TELEGRAM_CHAT_ID`
+pub async fn run_telegram_bot(ctx: AppContext, config: Arc<AppConfig>) {
+    let (Some(token), Some(chat_id)) = (config.telegram_bot_token.as_deref(), config.telegram_chat_id) else {
+        return std::future::pending().await;
+    };
+    let client = reqwest::Client::new();
+    let mut offset = 0i64;
+    loop {
+        let request = json!({ "offset": offset, "timeout": POLL_TIMEOUT.as_secs(), "allowed_updates": ["message"] });
+        let updates = match telegram_call(&client, token, "getUpdates", request, POLL_TIMEOUT + REQUEST_TIMEOUT).await {
+            Ok(updates) => updates,
+            Err(e) => {
+                warn!("Failed to fetch Telegram updates: {}", e);
+                tokio::time::sleep(RETRY_DELAY).await;
+                continue;
+            }
+        };
+        for update in updates.as_array().into_iter().flatten() {
+            offset = offset.max(update["update_id"].as_i64().unwrap_or_default() + 1);
+            let message = &update["message"];
+            let Some(command) = message["text"].as_str().and_then(|text| text.strip_prefix('/')) else {
+                continue;
+            };
+            let from = message["chat"]["id"].as_i64();
+            if from != Some(chat_id) {
+                info!("Ignoring Telegram command from chat {:?}", from);
+                continue;
+            }
+            let reply = run_command(&ctx, command).await;
+            if let Err(e) = telegram_send(&client, token, chat_id, &reply).await {
+                warn!("Failed to answer Telegram command: {}", e);
+            }
+        }
+    }
+}
+
+/// Post server events to the Telegram chat and the Discord webhook
+pub async fn run_chat_notifier(config: Arc<AppConfig>, events: EventSender) {
+    let client = reqwest::Client::new();
+    let mut receiver = events.subscribe();
+    loop {
+        let event = match receiver.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(skipped)) => {
+                warn!("Chat notifier fell behind, {} events were not sent", skipped);
+                continue;
+            }
+            Err(RecvError::Closed) => return,
+        };
+        let message = event_message(&event);
+        if let (Some(token), Some(chat_id)) = (config.telegram_bot_token.as_deref(), config.telegram_chat_id) {
+            if let Err(e) = telegram_send(&client, token, chat_id, &message).await {
+                warn!("Failed to send Telegram notification: {}", e);
+            }
+        }
+        if let Some(webhook_url) = &config.discord_webhook_url {
+            let result = client
+                .post(webhook_url)
+                .timeout(REQUEST_TIMEOUT)
+                .json(&json!({ "content": message }))
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+            if let Err(e) = result {
+                warn!("Failed to send Discord notification: {}", e.without_url());
+            }
+        }
+    }
+}
+
+/// Register the slash commands with Discord, replacing earlier definitions
+pub async fn register_discord_commands(config: Arc<AppConfig>) {
+    let (Some(application_id), Some(bot_token)) = (&config.discord_application_id, &config.discord_bot_token) else {
+        return;
+    };
+    let string_option = |name: &str, description: &str| json!({ "type": 3, "name": name, "description": description, "required": true });
+    let commands = json!([
+        { "name": "recent", "description": "Newest files", "options": [
+            { "type": 4, "name": "count", "description": "Number of files", "min_value": 1, "max_value": MAX_RECENT }
+        ] },
+        { "name": "share", "description": "Share link valid for 7 days", "options": [string_option("file", "File name, title or ID")] },
+        { "name": "play", "description": "Play a file on a renderer", "options": [
+            string_option("file", "File name, title or ID"),
+            string_option("device", "Renderer name"),
+        ] },
+        { "name": "devices", "description": "Renderers on the network" },
+    ]);
+    let result = reqwest::Client::new()
+        .put(format!("{}/applications/{}/commands", DISCORD_API, application_id))
+        .timeout(REQUEST_TIMEOUT)
+        .header("Authorization", format!("Bot {}", bot_token))
+        .json(&commands)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status());
+    match result {
+        Ok(_) => info!("Registered Discord slash commands"),
+        Err(e) => warn!("Failed to register Discord slash commands: {}", e.without_url()),
+    }
+}
+
+fn decode_hex(value: &str) -> Option<Vec<u8>> {
+    if !value.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..value.len())
+        .step_by(2)
+        .map(|i| value.get(i..i + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok()))
+        .collect()
+}
+
+/// Command text of a slash command, e.g. `play Movie on Living Room`
+fn discord_command_text(data: &Value) -> String {
+    let name = data["name"].as_str().unwrap_or_default();
+    let option = |key: &str| {
+        data["options"].as_array().into_iter().flatten()
+            .find(|o| o["name"] == key)
+            .map(|o| match &o["value"] {
+                Value::String(s) => s.clone(),
+                value => value.to_string(),
+            })
+            .unwrap_or_default()
+    };
+    match name {
+        "recent" => format!("recent {}", option("count")),
+        "share" => format!("share {}", option("file")),
+        "play" => format!("play {} on {}", option("file"), option("device")),
+        _ => name.to_string(),
+    }
+}
+
+/// Discord's interactions endpoint: verifies the request signature and answers
+/// slash commands with a deferred reply, edited once the command has run
+pub async fn discord_interaction(
+    State(ctx): State<AppContext>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let Some(public_key) = ctx.config.load().discord_public_key.as_deref().and_then(decode_hex) else {
+        return (StatusCode::NOT_FOUND, "Discord interactions are not configured").into_response();
+    };
+    let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok()).unwrap_or_default().to_string();
+    let (signature, timestamp) = (header("X-Signature-Ed25519"), header("X-Signature-Timestamp"));
+    let message = [timestamp.as_bytes(), &body].concat();
+    let verified = decode_hex(&signature)
+        .is_some_and(|signature| UnparsedPublicKey::new(&ED25519, &public_key).verify(&message, &signature).is_ok());
+    if !verified {
+        return (StatusCode::UNAUTHORIZED, "Invalid request signature").into_response();
+    }
+
+    let Ok(interaction) = serde_json::from_slice::<Value>(&body) else {
+        return (StatusCode::BAD_REQUEST, "Invalid interaction").into_response();
+    };
+    match interaction["type"].as_u64() {
+        // PING
+        Some(1) => Json(json!({ "type": 1 })).into_response(),
+        // APPLICATION_COMMAND
+        Some(2) => {
+            let command = discord_command_text(&interaction["data"]);
+            let application_id = interaction["application_id"].as_str().unwrap_or_default().to_string();
+            let token = interaction["token"].as_str().unwrap_or_default().to_string();
+            tokio::spawn(async move {
+                let reply = run_command(&ctx, &command).await;
+                let result = reqwest::Client::new()
+                    .patch(format!("{}/webhooks/{}/{}/messages/@original", DISCORD_API, application_id, token))
+                    .timeout(REQUEST_TIMEOUT)
+                    .json(&json!({ "content": reply }))
+                    .send()
+                    .await
+                    .and_then(|response| response.error_for_status());
+                if let Err(e) = result {
+                    warn!("Failed to answer Discord command: {}", e.without_url());
+                }
+            });
+            // DEFERRED_CHANNEL_MESSAGE_WITH_SOURCE
+            Json(json!({ "type": 5 })).into_response()
+        }
+        _ => (StatusCode::BAD_REQUEST, "Unsupported interaction type").into_response(),
+    }
+}
@@ -0,0 +1,542 @@
+//! Typed async client for the nascraft upload API.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), nascraft::client::ClientError> {
+//! let client = nascraft::client::Client::new("http://nas.local:8080").with_parallel_chunks(4);
+//! let outcome = client.upload_file("backup.tar".as_ref(), |sent, total| println!("{}/{}", sent, total)).await?;
+//! println!("stored as {}", outcome.file_id);
+//! # Ok(())
+//! # }
+//! ```
+
+use futures::{StreamExt, TryStreamExt};
+use md5::{Digest, Md5};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::fmt;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt, SeekFrom};
+use uuid::Uuid;
+use crate::api::{ChunkInfo, FileMetadata, Hole, Priority, UploadPlan, UploadStatus, DEVICE_NAME_HEADER, DEVICE_TOKEN_HEADER};
+use crate::hashing::HashAlgorithm;
+
+const DEFAULT_PARALLEL_CHUNKS: usize = 4;
+const DEFAULT_MAX_RETRIES: u32 = 3;
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(500);
+
+/// Granularity of `find_holes`: shorter runs of zeros are cheaper to send
+/// than to track
+pub const HOLE_BLOCK_SIZE: u64 = 1024 * 1024;
+
+#[derive(Debug)]
+pub enum ClientError {
+    Http(reqwest::Error),
+    Io(std::io::Error),
+    /// The server rejected the request; `code` is the API error code when it sent one
+    Api { status: u16, code: String, message: String },
+    /// Content doesn't match the checksum it was uploaded or downloaded with
+    ChecksumMismatch { expected: String, actual: String },
+}
+
+impl ClientError {
+    /// Whether retrying the same request may succeed
+    fn is_transient(&self) -> bool {
+        match self {
+            Self::Http(_) => true,
+            Self::Api { status, code, .. } => *status >= 500 || code == "CHUNK_CHECKSUM_MISMATCH" || code == "CHUNK_IN_PROGRESS",
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Http(e) => write!(f, "HTTP error: {}", e),
+            Self::Io(e) => write!(f, "I/O error: {}", e),
+            Self::Api { status, code, message } => write!(f, "server returned {} {}: {}", status, code, message),
+            Self::ChecksumMismatch { expected, actual } => write!(f, "checksum mismatch: expected {}, got {}", expected, actual),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+impl From<reqwest::Error> for ClientError {
+    fn from(e: reqwest::Error) -> Self {
+        Self::Http(e)
+    }
+}
+
+impl From<std::io::Error> for ClientError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+/// HTTP client sending `headers` with every request. Panics, like
+/// `reqwest::Client::new`, when the TLS backend can't be initialized.
+fn http_client(headers: reqwest::header::HeaderMap) -> reqwest::Client {
+    reqwest::Client::builder()
+        .user_agent(concat!("nascraft-client/", env!("CARGO_PKG_VERSION")))
+        .default_headers(headers)
+        .build()
+        .expect("Failed to build HTTP client")
+}
+
+/// Response envelope used by every JSON endpoint
+#[derive(Deserialize)]
+struct Envelope<T> {
+    message: String,
+    status: i32,
+    code: String,
+    data: Option<T>,
+}
+
+/// Result of submitting metadata: either a fresh upload plan, or the id of a
+/// file with the same checksum that is already stored
+#[derive(Debug, Clone)]
+pub enum SubmitResult {
+    Planned(UploadPlan),
+    Duplicate { file_id: String },
+}
+
+#[derive(Debug, Clone)]
+pub struct UploadOutcome {
+    pub file_id: String,
+    /// True when the server already had the content and nothing was sent
+    pub skipped: bool,
+    pub bytes_sent: u64,
+}
+
+/// Size and MD5 of a local file, as expected by `submit_metadata`
+pub async fn file_md5(path: &Path) -> Result<(u64, String), ClientError> {
+    let mut file = File::open(path).await?;
+    let mut hasher = Md5::new();
+    let mut buffer = vec![0u8; 1024 * 1024];
+    let mut total = 0u64;
+    loop {
+        let n = file.read(&mut buffer).await?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+        total += n as u64;
+    }
+    Ok((total, format!("{:x}", hasher.finalize())))
+}
+
+/// Runs of zeros in a local file, in whole `HOLE_BLOCK_SIZE` blocks, for
+/// `FileMetadata::holes`
+pub async fn find_holes(path: &Path) -> Result<Vec<Hole>, ClientError> {
+    let mut file = File::open(path).await?;
+    let mut buffer = vec![0u8; HOLE_BLOCK_SIZE as usize];
+    let mut holes: Vec<Hole> = Vec::new();
+    let mut offset = 0u64;
+    loop {
+        let mut filled = 0;
+        while filled < buffer.len() {
+            match file.read(&mut buffer[filled..]).await? {
+                0 => break,
+                n => filled += n,
+            }
+        }
+        if filled == 0 {
+            break;
+        }
+        if buffer[..filled].iter().all(|&b| b == 0) {
+            match holes.last_mut() {
+                Some(last) if last.offset + last.length == offset => last.length += filled as u64,
+                _ => holes.push(Hole { offset, length: filled as u64 }),
+            }
+        }
+        offset += filled as u64;
+    }
+    Ok(holes)
+}
+
+async fn read_chunk(path: &Path, chunk: &ChunkInfo) -> Result<Vec<u8>, ClientError> {
+    let mut file = File::open(path).await?;
+    file.seek(SeekFrom::Start(chunk.start_offset)).await?;
+    let mut data = vec![0u8; chunk.chunk_size as usize];
+    file.read_exact(&mut data).await?;
+    Ok(data)
+}
+
+#[derive(Clone)]
+pub struct Client {
+    http: reqwest::Client,
+    base_url: String,
+    parallel_chunks: usize,
+    max_retries: u32,
+    folder_id: Option<i64>,
+    detect_holes: bool,
+    overwrite: bool,
+    priority: Option<Priority>,
+}
+
+impl Client {
+    /// `base_url` is the server root, e.g. `http://nas.local:8080`
+    pub fn new(base_url: &str) -> Self {
+        Self {
+            http: http_client(Default::default()),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            parallel_chunks: DEFAULT_PARALLEL_CHUNKS,
+            max_retries: DEFAULT_MAX_RETRIES,
+            folder_id: None,
+            detect_holes: false,
+            overwrite: false,
+            priority: None,
+        }
+    }
+
+    /// Number of chunks uploaded concurrently (at least 1)
+    pub fn with_parallel_chunks(mut self, parallel_chunks: usize) -> Self {
+        self.parallel_chunks = parallel_chunks.max(1);
+        self
+    }
+
+    /// Retries per chunk on network errors, 5xx responses and checksum mismatches
+    pub fn with_max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Folder `upload_file` stores files in; the server enforces its policies
+    pub fn with_folder(mut self, folder_id: i64) -> Self {
+        self.folder_id = Some(folder_id);
+        self
+    }
+
+    /// Identify uploads as coming from this device: `name` is listed by the
+    /// server for uploads without a token; `token` is the one the server
+    /// generated when the device was registered, which can be revoked if the
+    /// device is lost
+    pub fn with_device(mut self, name: Option<&str>, token: Option<&str>) -> Self {
+        let mut headers = reqwest::header::HeaderMap::new();
+        // 控制字符不能出现在请求头中
+        let name = name.map(|name| name.chars().filter(|c| !c.is_control()).collect::<String>());
+        if let Some(value) = name.and_then(|name| reqwest::header::HeaderValue::from_bytes(name.trim().as_bytes()).ok()) {
+            headers.insert(DEVICE_NAME_HEADER, value);
+        }
+        if let Some(value) = token.and_then(|token| reqwest::header::HeaderValue::from_str(token.trim()).ok()) {
+            headers.insert(DEVICE_TOKEN_HEADER, value);
+        }
+        self.http = http_client(headers);
+        self
+    }
+
+    pub fn folder_id(&self) -> Option<i64> {
+        self.folder_id
+    }
+
+    /// Have `upload_file` look for runs of zeros and send them as holes, which
+    /// saves most of the transfer for sparse files such as disk images
+    pub fn with_hole_detection(mut self, detect_holes: bool) -> Self {
+        self.detect_holes = detect_holes;
+        self
+    }
+
+    pub fn detect_holes(&self) -> bool {
+        self.detect_holes
+    }
+
+    /// Have `upload_file` replace a file with the same name in the target
+    /// folder; without it such uploads fail with `409 FILE_EXISTS`
+    pub fn with_overwrite(mut self, overwrite: bool) -> Self {
+        self.overwrite = overwrite;
+        self
+    }
+
+    pub fn overwrite(&self) -> bool {
+        self.overwrite
+    }
+
+    /// Priority of the uploads started by `upload_file`; the server's default when unset
+    pub fn with_priority(mut self, priority: Priority) -> Self {
+        self.priority = Some(priority);
+        self
+    }
+
+    pub fn priority(&self) -> Option<Priority> {
+        self.priority
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url, path)
+    }
+
+    /// Unwrap the JSON envelope; plain-text error bodies become `ClientError::Api`
+    async fn parse<T: DeserializeOwned>(response: reqwest::Response) -> Result<T, ClientError> {
+        let status = response.status();
+        let body = response.text().await?;
+        match serde_json::from_str::<Envelope<T>>(&body) {
+            Ok(envelope) if status.is_success() && envelope.status == 1 => envelope.data.ok_or(ClientError::Api {
+                status: status.as_u16(),
+                code: envelope.code,
+                message: "response has no data".to_string(),
+            }),
+            Ok(envelope) => Err(ClientError::Api { status: status.as_u16(), code: envelope.code, message: envelope.message }),
+            Err(_) => Err(ClientError::Api { status: status.as_u16(), code: String::new(), message: body }),
+        }
+    }
+
+    /// Submit a file's metadata. With `metadata.upload_key` set, network errors
+    /// and 5xx responses are retried, since the server answers a repeated
+    /// submission with the original upload.
+    pub async fn submit_metadata(&self, metadata: &FileMetadata) -> Result<SubmitResult, ClientError> {
+        let mut attempt = 0;
+        let data: serde_json::Value = loop {
+            let result = match self.http.post(self.url("/api/v1/submit_metadata")).json(metadata).send().await {
+                Ok(response) => Self::parse(response).await,
+                Err(e) => Err(e.into()),
+            };
+            match result {
+                Err(e) if e.is_transient() && metadata.upload_key.is_some() && attempt < self.max_retries => {
+                    tokio::time::sleep(RETRY_BASE_DELAY * 2u32.pow(attempt)).await;
+                    attempt += 1;
+                }
+                result => break result?,
+            }
+        };
+        if data.get("status").and_then(|s| s.as_str()) == Some("duplicate") {
+            let file_id = data.get("id").and_then(|id| id.as_str()).unwrap_or_default().to_string();
+            return Ok(SubmitResult::Duplicate { file_id });
+        }
+        serde_json::from_value(data).map(SubmitResult::Planned).map_err(|e| ClientError::Api {
+            status: 200,
+            code: String::new(),
+            message: format!("unexpected submit_metadata response: {}", e),
+        })
+    }
+
+    pub async fn upload_status(&self, file_id: &str) -> Result<UploadStatus, ClientError> {
+        let response = self.http.get(self.url(&format!("/api/v1/upload_status/{}", file_id))).send().await?;
+        Self::parse(response).await
+    }
+
+    /// Send one whole chunk. Returns the server's MD5 of the file when this chunk completed it.
+    pub async fn upload_chunk(
+        &self,
+        file_id: &str,
+        total_size: u64,
+        chunk: &ChunkInfo,
+        data: Vec<u8>,
+        algorithm: Option<HashAlgorithm>,
+    ) -> Result<Option<String>, ClientError> {
+        let mut request = self.http
+            .post(self.url("/api/v1/upload"))
+            .header("X-File-ID", file_id)
+            .header("X-Start-Offset", chunk.start_offset.to_string())
+            .header("Content-Range", format!("bytes {}-{}/{}", chunk.start_offset, chunk.end_offset, total_size));
+        if let Some(algorithm) = algorithm {
+            let mut hasher = algorithm.hasher();
+            hasher.update(&data);
+            request = request.header("X-Chunk-Checksum", hasher.hex_digest());
+        }
+
+        let data: serde_json::Value = Self::parse(request.body(data).send().await?).await?;
+        // 隔离的文件同样已完整上传
+        let completed = matches!(data.get("status").and_then(|s| s.as_str()), Some("success" | "quarantined"));
+        Ok(completed.then(|| data.get("checksum").and_then(|c| c.as_str()).unwrap_or_default().to_string()))
+    }
+
+    /// Make the server stop receiving the chunk at `start_offset`; its
+    /// `upload_chunk` call fails with `CHUNK_ABORTED`. Returns whether the
+    /// chunk was being uploaded.
+    pub async fn abort_chunk(&self, file_id: &str, start_offset: u64) -> Result<bool, ClientError> {
+        let response = self.http
+            .post(self.url(&format!("/api/v1/upload/{}/abort_chunk", file_id)))
+            .json(&serde_json::json!({ "start_offset": start_offset }))
+            .send()
+            .await?;
+        let data: serde_json::Value = Self::parse(response).await?;
+        Ok(data.get("aborted").and_then(|a| a.as_bool()).unwrap_or(false))
+    }
+
+    async fn upload_chunk_with_retries(
+        &self,
+        file_id: &str,
+        path: &Path,
+        total_size: u64,
+        chunk: &ChunkInfo,
+        algorithm: Option<HashAlgorithm>,
+    ) -> Result<Option<String>, ClientError> {
+        let mut attempt = 0;
+        loop {
+            let data = read_chunk(path, chunk).await?;
+            match self.upload_chunk(file_id, total_size, chunk, data, algorithm).await {
+                Err(e) if e.is_transient() && attempt < self.max_retries => {
+                    tokio::time::sleep(RETRY_BASE_DELAY * 2u32.pow(attempt)).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Upload `chunks` of `path`. The server assembles the file when the last
+    /// missing chunk arrives, so all but one chunk go in parallel and the final
+    /// one is sent alone to avoid two requests racing to complete the file.
+    async fn upload_chunks<F>(
+        &self,
+        file_id: &str,
+        path: &Path,
+        total_size: u64,
+        expected_md5: Option<&str>,
+        mut chunks: Vec<(ChunkInfo, Option<HashAlgorithm>)>,
+        on_progress: F,
+    ) -> Result<u64, ClientError>
+    where
+        F: Fn(u64, u64) + Send + Sync,
+    {
+        let pending: u64 = chunks.iter().map(|(c, _)| c.chunk_size).sum();
+        let sent = AtomicU64::new(0);
+        let report = |bytes: u64| {
+            let now = sent.fetch_add(bytes, Ordering::Relaxed) + bytes;
+            on_progress(total_size - pending + now, total_size);
+        };
+        report(0);
+
+        let Some((last, last_algorithm)) = chunks.pop() else {
+            return Ok(0);
+        };
+
+        futures::stream::iter(chunks.iter())
+            .map(|(chunk, algorithm)| async {
+                self.upload_chunk_with_retries(file_id, path, total_size, chunk, *algorithm).await?;
+                report(chunk.chunk_size);
+                Ok::<(), ClientError>(())
+            })
+            .buffer_unordered(self.parallel_chunks)
+            .try_collect::<Vec<()>>()
+            .await?;
+
+        let server_md5 = self.upload_chunk_with_retries(file_id, path, total_size, &last, last_algorithm).await?;
+        report(last.chunk_size);
+
+        if let (Some(expected), Some(actual)) = (expected_md5, server_md5) {
+            if expected != actual {
+                return Err(ClientError::ChecksumMismatch { expected: expected.to_string(), actual });
+            }
+        }
+        Ok(sent.load(Ordering::Relaxed))
+    }
+
+    /// Upload a local file. `on_progress(bytes_done, total)` is called as chunks finish.
+    pub async fn upload_file<F>(&self, path: &Path, on_progress: F) -> Result<UploadOutcome, ClientError>
+    where
+        F: Fn(u64, u64) + Send + Sync,
+    {
+        let (total_size, checksum) = file_md5(path).await?;
+        let filename = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        let metadata = FileMetadata {
+            filename,
+            total_size,
+            checksum: checksum.clone(),
+            folder_id: self.folder_id,
+            upload_key: Some(Uuid::new_v4().to_string()),
+            holes: if self.detect_holes { find_holes(path).await? } else { Vec::new() },
+            overwrite: self.overwrite,
+            priority: self.priority,
+        };
+
+        match self.submit_metadata(&metadata).await? {
+            SubmitResult::Duplicate { file_id } => {
+                on_progress(total_size, total_size);
+                Ok(UploadOutcome { file_id, skipped: true, bytes_sent: 0 })
+            }
+            SubmitResult::Planned(plan) => self.upload_planned(&plan, path, &checksum, on_progress).await,
+        }
+    }
+
+    /// Upload every chunk of a plan returned by `submit_metadata`. Callers that
+    /// want to resume later can record `plan.file_id` before calling this.
+    /// Sends no more chunks at once than the server's hints recommend.
+    pub async fn upload_planned<F>(&self, plan: &UploadPlan, path: &Path, expected_md5: &str, on_progress: F) -> Result<UploadOutcome, ClientError>
+    where
+        F: Fn(u64, u64) + Send + Sync,
+    {
+        let algorithm = HashAlgorithm::parse(&plan.hash_algorithm);
+        let chunks = plan.chunks.iter().map(|c| (c.clone(), algorithm)).collect();
+        let client = match &plan.hints {
+            Some(hints) if hints.parallel_chunks < self.parallel_chunks => {
+                self.clone().with_parallel_chunks(hints.parallel_chunks)
+            }
+            _ => self.clone(),
+        };
+        let bytes_sent = client.upload_chunks(&plan.file_id, path, plan.total_size, Some(expected_md5), chunks, on_progress).await?;
+        Ok(UploadOutcome { file_id: plan.file_id.clone(), skipped: false, bytes_sent })
+    }
+
+    /// Continue an interrupted upload of `path` under `file_id`, sending only
+    /// unfinished chunks. The assembled file is checked against `expected_md5` when given.
+    pub async fn resume_upload<F>(
+        &self,
+        file_id: &str,
+        path: &Path,
+        expected_md5: Option<&str>,
+        on_progress: F,
+    ) -> Result<UploadOutcome, ClientError>
+    where
+        F: Fn(u64, u64) + Send + Sync,
+    {
+        let status = self.upload_status(file_id).await?;
+        let total_size = tokio::fs::metadata(path).await?.len();
+        if matches!(status.status.as_str(), "completed" | "processing" | "quarantined") {
+            on_progress(total_size, total_size);
+            return Ok(UploadOutcome { file_id: file_id.to_string(), skipped: true, bytes_sent: 0 });
+        }
+
+        let mut chunks: Vec<(ChunkInfo, Option<HashAlgorithm>)> = status.chunks
+            .iter()
+            .filter(|c| !c.is_complete())
+            .map(|c| {
+                let chunk = ChunkInfo {
+                    start_offset: c.start_offset as u64,
+                    end_offset: c.end_offset as u64,
+                    chunk_size: (c.end_offset - c.start_offset + 1) as u64,
+                    upload_url: None,
+                };
+                (chunk, c.hash_algorithm.as_deref().and_then(HashAlgorithm::parse))
+            })
+            .collect();
+        chunks.sort_by_key(|(c, _)| c.start_offset);
+
+        let bytes_sent = self.upload_chunks(file_id, path, total_size, expected_md5, chunks, on_progress).await?;
+        Ok(UploadOutcome { file_id: file_id.to_string(), skipped: false, bytes_sent })
+    }
+
+    /// Download a file to `dest`, verifying its MD5 when `expected_md5` is given. Returns the size.
+    pub async fn download(&self, file_id: &str, dest: &Path, expected_md5: Option<&str>) -> Result<u64, ClientError> {
+        let response = self.http.get(self.url(&format!("/api/v1/download/{}", file_id))).send().await?;
+        let status = response.status();
+        if !status.is_success() {
+            let message = response.text().await.unwrap_or_default();
+            return Err(ClientError::Api { status: status.as_u16(), code: String::new(), message });
+        }
+
+        let mut file = File::create(dest).await?;
+        let mut hasher = Md5::new();
+        let mut written = 0u64;
+        let mut body = response.bytes_stream();
+        while let Some(chunk) = body.next().await {
+            let chunk = chunk?;
+            hasher.update(&chunk);
+            file.write_all(&chunk).await?;
+            written += chunk.len() as u64;
+        }
+        file.flush().await?;
+
+        if let Some(expected) = expected_md5 {
+            let actual = format!("{:x}", hasher.finalize());
+            if actual != expected {
+                return Err(ClientError::ChecksumMismatch { expected: expected.to_string(), actual });
+            }
+        }
+        Ok(written)
+    }
+}
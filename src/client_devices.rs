@@ -0,0 +1,308 @@
+//! Upload clients (phone backup apps, the command-line uploader) and their
+//! tokens. A client registered here sends its token as `X-Device-Token`; in
+//! multi-tenant mode the token stands in for the tenant's API key, so a lost
+//! phone is shut out by revoking its token without changing the key. Every
+//! upload session records the client that started it.
+
+use axum::{
+    extract::{ConnectInfo, Path, Request, State},
+    http::{header, HeaderMap, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use log::{error, info};
+use nascraft::api::{DEVICE_NAME_HEADER, DEVICE_TOKEN_HEADER};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, Sqlite, SqlitePool, Transaction};
+use std::net::SocketAddr;
+use uuid::Uuid;
+use crate::auth_failures::AuthFailure;
+use crate::context::AppContext;
+use crate::helper::{error_response, ApiResponse};
+use crate::profiles::ensure_unrestricted;
+use crate::tenants::current_tenant_id;
+
+const MAX_NAME_CHARS: usize = 100;
+const MAX_USER_AGENT_CHARS: usize = 512;
+
+/// `last_seen_at` is updated at most this often, not on every chunk
+const LAST_SEEN_INTERVAL_SECS: i64 = 60;
+
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct ClientDevice {
+    pub id: i64,
+    pub tenant_id: Option<i64>,
+    pub name: String,
+    #[serde(skip)]
+    pub token: String,
+    /// User agent of the last request made with the token
+    pub user_agent: Option<String>,
+    #[serde(with = "crate::api::timestamp")]
+    pub created_at: i64,
+    #[serde(with = "crate::api::timestamp::option")]
+    pub last_seen_at: Option<i64>,
+    #[serde(with = "crate::api::timestamp::option")]
+    pub revoked_at: Option<i64>,
+}
+
+const CLIENT_DEVICE_COLUMNS: &str = "id, tenant_id, name, token, user_agent, created_at, last_seen_at, revoked_at";
+
+tokio::task_local! {
+    static CURRENT_DEVICE: ClientDevice;
+}
+
+/// Registered client the current request was made with, if it sent a token
+pub fn current_client_device() -> Option<ClientDevice> {
+    CURRENT_DEVICE.try_with(ClientDevice::clone).ok()
+}
+
+fn header_text(headers: &HeaderMap, name: impl header::AsHeaderName, max_chars: usize) -> Option<String> {
+    let value = headers.get(name)?;
+    // 设备名可能含中文等非 ASCII 字符，按 UTF-8 读取
+    let text = String::from_utf8_lossy(value.as_bytes());
+    let text = text.trim();
+    (!text.is_empty()).then(|| text.chars().take(max_chars).collect())
+}
+
+async fn fetch_device_by_token(db_pool: &SqlitePool, token: &str) -> Result<Option<ClientDevice>, String> {
+    sqlx::query_as::<_, ClientDevice>(&format!("SELECT {} FROM client_devices WHERE token = ?", CLIENT_DEVICE_COLUMNS))
+        .bind(token)
+        .fetch_optional(db_pool)
+        .await
+        .map_err(|e| {
+            error!("Failed to fetch client device: {}", e);
+            "Failed to fetch client device".to_string()
+        })
+}
+
+async fn touch_device(db_pool: &SqlitePool, id: i64, user_agent: Option<&str>) {
+    if let Err(e) = sqlx::query(
+        "UPDATE client_devices SET last_seen_at = strftime('%s', 'now'), user_agent = COALESCE(?, user_agent)
+         WHERE id = ? AND (last_seen_at IS NULL OR last_seen_at < strftime('%s', 'now') - ?)",
+    )
+    .bind(user_agent)
+    .bind(id)
+    .bind(LAST_SEEN_INTERVAL_SECS)
+    .execute(db_pool)
+    .await
+    {
+        error!("Failed to update last use of client device {}: {}", id, e);
+    }
+}
+
+/// Resolve the client of a request carrying `X-Device-Token` and run the
+/// handler with it; unknown and revoked tokens are refused. Runs before
+/// `resolve_tenant`, which accepts the token in place of the API key.
+pub async fn resolve_client_device(
+    State(ctx): State<AppContext>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let Some(token) = req.headers().get(DEVICE_TOKEN_HEADER).and_then(|v| v.to_str().ok()).map(str::to_string) else {
+        return next.run(req).await;
+    };
+    let db_pool = &ctx.app_state.db_pool;
+    match fetch_device_by_token(db_pool, token.trim()).await {
+        Ok(Some(device)) if device.revoked_at.is_some() => AuthFailure::new("device_token", Some(format!("device:{}", device.id))).attach(error_response(
+            StatusCode::UNAUTHORIZED,
+            "DEVICE_REVOKED",
+            format!("The token of '{}' has been revoked", device.name),
+        )),
+        Ok(Some(device)) => {
+            let user_agent = header_text(req.headers(), header::USER_AGENT, MAX_USER_AGENT_CHARS);
+            touch_device(db_pool, device.id, user_agent.as_deref()).await;
+            CURRENT_DEVICE.scope(device, next.run(req)).await
+        }
+        Ok(None) => AuthFailure::new("device_token", None).attach(error_response(
+            StatusCode::UNAUTHORIZED,
+            "INVALID_DEVICE_TOKEN",
+            format!("Unknown {} header", DEVICE_TOKEN_HEADER),
+        )),
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_CLIENT_DEVICE_ERROR", e),
+    }
+}
+
+/// Record which client started the upload session `file_id`: the registered
+/// device, else the name declared in `X-Device-Name`, and the user agent
+pub async fn record_upload_client(tx: &mut Transaction<'_, Sqlite>, file_id: &str, headers: &HeaderMap) -> Result<(), String> {
+    let device = current_client_device();
+    let name = device.as_ref().map(|d| d.name.clone()).or_else(|| header_text(headers, DEVICE_NAME_HEADER, MAX_NAME_CHARS));
+    sqlx::query("UPDATE upload_file_meta SET client_device_id = ?, client_device_name = ?, client_user_agent = ? WHERE file_id = ?")
+        .bind(device.map(|d| d.id))
+        .bind(name)
+        .bind(header_text(headers, header::USER_AGENT, MAX_USER_AGENT_CHARS))
+        .bind(file_id)
+        .execute(&mut **tx)
+        .await
+        .map(|_| ())
+        .map_err(|e| {
+            error!("Failed to record upload client of {}: {}", file_id, e);
+            "Failed to record upload client".to_string()
+        })
+}
+
+#[derive(Serialize, FromRow)]
+pub struct ClientDeviceUsage {
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pub device: ClientDevice,
+    pub uploads: i64,
+    pub uploaded_bytes: i64,
+    #[serde(with = "crate::api::timestamp::option")]
+    pub last_upload_at: Option<i64>,
+}
+
+/// Clients that uploaded without a token, grouped by declared name and user agent
+#[derive(Serialize, FromRow)]
+pub struct UnregisteredClient {
+    pub device_name: Option<String>,
+    pub user_agent: Option<String>,
+    pub uploads: i64,
+    pub uploaded_bytes: i64,
+    #[serde(with = "crate::api::timestamp::option")]
+    pub last_upload_at: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct ClientDeviceList {
+    pub devices: Vec<ClientDeviceUsage>,
+    pub unregistered: Vec<UnregisteredClient>,
+}
+
+async fn fetch_client_devices(db_pool: &SqlitePool, tenant_id: Option<i64>) -> Result<ClientDeviceList, String> {
+    let devices = sqlx::query_as::<_, ClientDeviceUsage>(
+        "SELECT d.*, COUNT(f.file_id) AS uploads, COALESCE(SUM(f.total_size), 0) AS uploaded_bytes, MAX(f.created_at) AS last_upload_at
+         FROM client_devices d LEFT JOIN upload_file_meta f ON f.client_device_id = d.id
+         WHERE ?1 IS NULL OR d.tenant_id = ?1
+         GROUP BY d.id ORDER BY d.revoked_at IS NOT NULL, d.name",
+    )
+    .bind(tenant_id)
+    .fetch_all(db_pool)
+    .await
+    .map_err(|e| {
+        error!("Failed to fetch client devices: {}", e);
+        "Failed to fetch client devices".to_string()
+    })?;
+
+    let unregistered = sqlx::query_as::<_, UnregisteredClient>(
+        "SELECT client_device_name AS device_name, client_user_agent AS user_agent, COUNT(*) AS uploads,
+                COALESCE(SUM(total_size), 0) AS uploaded_bytes, MAX(created_at) AS last_upload_at
+         FROM upload_file_meta
+         WHERE client_device_id IS NULL AND (client_device_name IS NOT NULL OR client_user_agent IS NOT NULL)
+           AND (?1 IS NULL OR tenant_id = ?1)
+         GROUP BY client_device_name, client_user_agent ORDER BY last_upload_at DESC",
+    )
+    .bind(tenant_id)
+    .fetch_all(db_pool)
+    .await
+    .map_err(|e| {
+        error!("Failed to fetch unregistered clients: {}", e);
+        "Failed to fetch unregistered clients".to_string()
+    })?;
+
+    Ok(ClientDeviceList { devices, unregistered })
+}
+
+/// Registered clients of the tenant (every client outside multi-tenant mode)
+/// with their uploads, and the clients that uploaded without a token
+pub async fn list_client_devices(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+) -> Response {
+    if let Err(resp) = ensure_unrestricted(&ctx, &client_addr).await {
+        return resp;
+    }
+    match fetch_client_devices(&ctx.app_state.db_pool, current_tenant_id()).await {
+        Ok(list) => (StatusCode::OK, Json(ApiResponse::success(list))).into_response(),
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_CLIENT_DEVICES_ERROR", e),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CreateClientDeviceRequest {
+    name: String,
+}
+
+#[derive(Serialize)]
+struct CreatedClientDevice {
+    #[serde(flatten)]
+    device: ClientDevice,
+    /// Only returned when the device is registered
+    token: String,
+}
+
+/// Register a client and generate its token
+pub async fn create_client_device(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    Json(req): Json<CreateClientDeviceRequest>,
+) -> Response {
+    if let Err(resp) = ensure_unrestricted(&ctx, &client_addr).await {
+        return resp;
+    }
+    let name = req.name.trim();
+    if name.is_empty() || name.chars().count() > MAX_NAME_CHARS {
+        return error_response(StatusCode::BAD_REQUEST, "INVALID_CLIENT_DEVICE", format!(
+            "name must be 1-{} characters",
+            MAX_NAME_CHARS
+        ));
+    }
+    let result = sqlx::query_as::<_, ClientDevice>(&format!(
+        "INSERT INTO client_devices (tenant_id, name, token, created_at) VALUES (?, ?, ?, strftime('%s', 'now'))
+         RETURNING {}",
+        CLIENT_DEVICE_COLUMNS
+    ))
+    .bind(current_tenant_id())
+    .bind(name)
+    .bind(Uuid::new_v4().simple().to_string())
+    .fetch_one(&ctx.app_state.db_pool)
+    .await;
+    match result {
+        Ok(device) => {
+            info!("Registered client device '{}' ({})", device.name, device.id);
+            (StatusCode::OK, Json(ApiResponse::success(CreatedClientDevice {
+                token: device.token.clone(),
+                device,
+            }))).into_response()
+        }
+        Err(e) => {
+            error!("Failed to create client device: {}", e);
+            error_response(StatusCode::INTERNAL_SERVER_ERROR, "CREATE_CLIENT_DEVICE_ERROR", "Failed to create client device".to_string())
+        }
+    }
+}
+
+/// Revoke a client's token; requests made with it are refused from now on.
+/// Its uploads stay.
+pub async fn revoke_client_device(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    Path(id): Path<i64>,
+) -> Response {
+    if let Err(resp) = ensure_unrestricted(&ctx, &client_addr).await {
+        return resp;
+    }
+    let tenant_id = current_tenant_id();
+    let result = sqlx::query_as::<_, ClientDevice>(&format!(
+        "UPDATE client_devices SET revoked_at = COALESCE(revoked_at, strftime('%s', 'now'))
+         WHERE id = ?1 AND (?2 IS NULL OR tenant_id = ?2)
+         RETURNING {}",
+        CLIENT_DEVICE_COLUMNS
+    ))
+    .bind(id)
+    .bind(tenant_id)
+    .fetch_optional(&ctx.app_state.db_pool)
+    .await;
+    match result {
+        Ok(Some(device)) => {
+            info!("Revoked the token of client device '{}' ({})", device.name, device.id);
+            (StatusCode::OK, Json(ApiResponse::success(device))).into_response()
+        }
+        Ok(None) => error_response(StatusCode::NOT_FOUND, "CLIENT_DEVICE_NOT_FOUND", "Client device not found".to_string()),
+        Err(e) => {
+            error!("Failed to revoke client device {}: {}", id, e);
+            error_response(StatusCode::INTERNAL_SERVER_ERROR, "REVOKE_CLIENT_DEVICE_ERROR", "Failed to revoke client device".to_string())
+        }
+    }
+}
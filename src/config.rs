@@ -1,49 +1,911 @@
-use std::env;
-use log::info;
-
-#[derive(Clone, Debug)]
-pub struct AppConfig {
-    pub server_port: u16,
-    pub mdns_service_type: String,
-    pub mdns_instance_name: String,
-    pub udp_discovery_port: u16,
-    pub enable_dlna_remote: bool,
-}
-
-impl AppConfig {
-    pub fn from_env() -> Self {
-        let server_port: u16 = env::var("NASCRAFT_PORT")
-            .ok()
-            .and_then(|v| v.parse::<u16>().ok())
-            .unwrap_or(8080);
-
-        let mdns_service_type = env::var("NASCRAFT_MDNS_SERVICE_TYPE")
-            .unwrap_or_else(|_| "_nascraft._tcp.local.".to_string());
-
-        let mdns_instance_name = env::var("NASCRAFT_MDNS_INSTANCE")
-            .unwrap_or_else(|_| "nascraft".to_string());
-
-        let udp_discovery_port: u16 = env::var("NASCRAFT_UDP_DISCOVERY_PORT")
-            .ok()
-            .and_then(|v| v.parse::<u16>().ok())
-            .unwrap_or(53530);
-
-        let enable_dlna_remote = env::var("NASCRAFT_ENABLE_DLNA_REMOTE")
-            .ok()
-            .map(|v| v.to_lowercase() == "true" || v == "1")
-            .unwrap_or(false);
-
-        info!(
-            "Loaded config: server_port={}, mdns_service_type={}, mdns_instance_name={}, udp_discovery_port={}, enable_dlna_remote={}",
-            server_port, mdns_service_type, mdns_instance_name, udp_discovery_port, enable_dlna_remote
-        );
-
-        Self {
-            server_port,
-            mdns_service_type,
-            mdns_instance_name,
-            udp_discovery_port,
-            enable_dlna_remote,
-        }
-    }
-}
+use std::collections::HashMap;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use log::info;
+use serde::{Serialize, Serializer};
+use sqlx::SqlitePool;
+use crate::filename::SanitizePolicy;
+use crate::hashing::{fastest_hash_algorithm, HashAlgorithm};
+use crate::hwaccel::HwAccel;
+use crate::ip_allowlist::{lan_networks, parse_networks, IpNetwork};
+use crate::quarantine::normalize_extensions;
+use crate::upload_dao::fetch_chunk_size;
+
+/// Chunk size used until `system_config` has been read
+const DEFAULT_CHUNK_SIZE: u64 = 1024 * 1024;
+
+/// Settings file read when it exists; `NASCRAFT_CONFIG` points to another one
+const DEFAULT_CONFIG_FILE: &str = "nascraft.toml";
+
+/// Environment variables of all settings. In the settings file a setting's key
+/// is its variable name in lowercase without the `NASCRAFT_` prefix.
+const SETTINGS: &[&str] = &[
+    "DATABASE_URL",
+    "LOG_FILE_PATH",
+    "LOG_DIR",
+    "NASCRAFT_PORT",
+    "NASCRAFT_PUBLIC_URL",
+    "NASCRAFT_MDNS_SERVICE_TYPE",
+    "NASCRAFT_MDNS_INSTANCE",
+    "NASCRAFT_MDNS_HTTP",
+    "NASCRAFT_UDP_DISCOVERY_PORT",
+    "NASCRAFT_ENABLE_DLNA_REMOTE",
+    "NASCRAFT_MEDIA_SERVER_URL",
+    "NASCRAFT_MEDIA_SERVER_TOKEN",
+    "NASCRAFT_MEDIA_SERVER_SSE_PATH",
+    "NASCRAFT_FILENAME_POLICY",
+    "NASCRAFT_HASH_ALGORITHM",
+    "NASCRAFT_HASH_OFFLOAD_MIN_BYTES",
+    "NASCRAFT_FUSE_MOUNT",
+    "NASCRAFT_FUSE_ALLOW_OTHER",
+    "NASCRAFT_MOCK_RENDERERS",
+    "NASCRAFT_OPENSUBTITLES_API_KEY",
+    "NASCRAFT_SUBTITLE_LANGUAGES",
+    "NASCRAFT_TMDB_API_KEY",
+    "NASCRAFT_TMDB_LANGUAGE",
+    "NASCRAFT_TMDB_REGION",
+    "NASCRAFT_JELLYFIN_URL",
+    "NASCRAFT_JELLYFIN_API_KEY",
+    "NASCRAFT_PLEX_URL",
+    "NASCRAFT_PLEX_TOKEN",
+    "NASCRAFT_MEDIA_SCAN_PATH",
+    "NASCRAFT_DEFAULT_PROFILE",
+    "NASCRAFT_HTTP3_PORT",
+    "NASCRAFT_TLS_CERT",
+    "NASCRAFT_TLS_KEY",
+    "NASCRAFT_FFMPEG_PATH",
+    "NASCRAFT_COLD_STORAGE_DIR",
+    "NASCRAFT_EXPORT_ROOTS",
+    "NASCRAFT_MULTI_TENANT",
+    "NASCRAFT_ADMIN_KEY",
+    "NASCRAFT_ADMIN_ALLOWED_NETWORKS",
+    "NASCRAFT_TRUSTED_PROXIES",
+    "NASCRAFT_AUTH_MAX_FAILURES",
+    "NASCRAFT_AUTH_ACCOUNT_MAX_FAILURES",
+    "NASCRAFT_AUTH_FAILURE_WINDOW_SECS",
+    "NASCRAFT_AUTH_LOCKOUT_SECS",
+    "NASCRAFT_AUTH_LOG_FILE",
+    "NASCRAFT_DOWNLOAD_BUFFER_SIZE",
+    "NASCRAFT_IMAGE_BUFFER_SIZE",
+    "NASCRAFT_IMAGE_READ_AHEAD",
+    "NASCRAFT_BACKUP_DIR",
+    "NASCRAFT_BACKUP_INTERVAL_HOURS",
+    "NASCRAFT_BACKUP_KEEP",
+    "NASCRAFT_VAPID_KEY_FILE",
+    "NASCRAFT_VAPID_SUBJECT",
+    "NASCRAFT_LOW_DISK_SPACE_PERCENT",
+    "NASCRAFT_CRITICAL_DISK_SPACE_PERCENT",
+    "NASCRAFT_READ_ONLY_ON_CRITICAL_DISK",
+    "NASCRAFT_MQTT_URL",
+    "NASCRAFT_MQTT_USERNAME",
+    "NASCRAFT_MQTT_PASSWORD",
+    "NASCRAFT_MQTT_TOPIC",
+    "NASCRAFT_MQTT_DISCOVERY_PREFIX",
+    "NASCRAFT_TELEGRAM_BOT_TOKEN",
+    "NASCRAFT_TELEGRAM_CHAT_ID",
+    "NASCRAFT_DISCORD_WEBHOOK_URL",
+    "NASCRAFT_DISCORD_PUBLIC_KEY",
+    "NASCRAFT_DISCORD_APPLICATION_ID",
+    "NASCRAFT_DISCORD_BOT_TOKEN",
+    "NASCRAFT_SIMPLE_API_KEY",
+    "NASCRAFT_MEDIA_PROXY_HOSTS",
+    "NASCRAFT_BLOCKED_EXTENSIONS",
+    "NASCRAFT_QUARANTINE_MISMATCHED_TYPES",
+    "NASCRAFT_MAX_EXTRACTED_BYTES",
+    "NASCRAFT_HOOKS_DIR",
+    "NASCRAFT_MAX_CONCURRENT_MERGES",
+    "NASCRAFT_MAX_CONCURRENT_TRANSCODES",
+    "NASCRAFT_MAX_CONCURRENT_SCRUBS",
+    "NASCRAFT_TRANSCODE_CACHE_MAX_BYTES",
+    "NASCRAFT_FFMPEG_HWACCEL",
+    "NASCRAFT_VAAPI_DEVICE",
+    "NASCRAFT_WORKER_THREADS",
+    "NASCRAFT_MAX_BLOCKING_THREADS",
+    "NASCRAFT_MAX_CONNECTIONS",
+    "NASCRAFT_KEEP_ALIVE",
+    "NASCRAFT_CLIENT_TIMEOUT_SECS",
+];
+
+fn file_key(var: &str) -> String {
+    var.strip_prefix("NASCRAFT_").unwrap_or(var).to_lowercase()
+}
+
+fn parse_flag(value: &str) -> Option<bool> {
+    match value.to_lowercase().as_str() {
+        "true" | "1" | "yes" | "on" => Some(true),
+        "false" | "0" | "no" | "off" => Some(false),
+        _ => None,
+    }
+}
+
+/// Raw values of the settings: the environment, falling back to the settings
+/// file. Invalid values are collected so they can all be reported at once.
+struct SettingsSource {
+    file: HashMap<String, String>,
+    errors: Vec<String>,
+}
+
+impl SettingsSource {
+    fn load(path: &Path, required: bool) -> Self {
+        let mut source = Self { file: HashMap::new(), errors: Vec::new() };
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && !required => return source,
+            Err(e) => {
+                source.errors.push(format!("Failed to read {}: {}", path.display(), e));
+                return source;
+            }
+        };
+        let table = match content.parse::<toml::Table>() {
+            Ok(table) => table,
+            Err(e) => {
+                source.errors.push(format!("Failed to parse {}: {}", path.display(), e));
+                return source;
+            }
+        };
+        for (key, value) in table {
+            if !SETTINGS.iter().any(|var| file_key(var) == key) {
+                source.errors.push(format!("Unknown setting '{}' in {}", key, path.display()));
+                continue;
+            }
+            // 列表写成逗号分隔，与环境变量的写法一致
+            let value = match value {
+                toml::Value::String(s) => Some(s),
+                toml::Value::Array(items) => items
+                    .into_iter()
+                    .map(|item| match item {
+                        toml::Value::String(s) => Some(s),
+                        toml::Value::Array(_) | toml::Value::Table(_) => None,
+                        other => Some(other.to_string()),
+                    })
+                    .collect::<Option<Vec<_>>>()
+                    .map(|items| items.join(",")),
+                toml::Value::Table(_) => None,
+                other => Some(other.to_string()),
+            };
+            match value {
+                Some(value) => {
+                    source.file.insert(key, value);
+                }
+                None => source.errors.push(format!("Setting '{}' in {} must be a value or a list of values", key, path.display())),
+            }
+        }
+        source
+    }
+
+    fn get(&self, var: &str) -> Option<String> {
+        env::var(var).ok().or_else(|| self.file.get(&file_key(var)).cloned())
+    }
+
+    /// Trimmed value, None when unset or empty
+    fn string(&self, var: &str) -> Option<String> {
+        self.get(var).map(|v| v.trim().to_string()).filter(|v| !v.is_empty())
+    }
+
+    fn parse_with<T>(&mut self, var: &str, parse: impl Fn(&str) -> Option<T>) -> Option<T> {
+        let value = self.string(var)?;
+        let parsed = parse(&value);
+        if parsed.is_none() {
+            self.errors.push(format!("Invalid value for {} ({}): '{}'", var, file_key(var), value));
+        }
+        parsed
+    }
+
+    fn parse<T: std::str::FromStr>(&mut self, var: &str) -> Option<T> {
+        self.parse_with(var, |v| v.parse().ok())
+    }
+
+    fn flag(&mut self, var: &str) -> bool {
+        self.parse_with(var, parse_flag).unwrap_or(false)
+    }
+}
+
+fn redact<S: Serializer>(value: &Option<String>, serializer: S) -> Result<S::Ok, S::Error> {
+    value.as_ref().map(|_| "<redacted>").serialize(serializer)
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct AppConfig {
+    /// Settings file that was read, if any
+    pub config_file: Option<PathBuf>,
+    /// None keeps metadata in an in-memory database
+    pub database_url: Option<String>,
+    pub log_file_path: Option<PathBuf>,
+    pub log_dir: PathBuf,
+    pub server_port: u16,
+    /// Base URL clients outside the LAN reach the server at, for absolute links
+    /// in feeds and share links; None derives it from the request's Host header
+    pub public_url: Option<String>,
+    pub mdns_service_type: String,
+    pub mdns_instance_name: String,
+    /// Also advertise the web API as a generic `_http._tcp` service
+    pub mdns_http: bool,
+    pub udp_discovery_port: u16,
+    pub enable_dlna_remote: bool,
+    pub media_server_url: Option<String>,
+    #[serde(serialize_with = "redact")]
+    pub media_server_token: Option<String>,
+    pub media_server_sse_path: String,
+    pub filename_policy: SanitizePolicy,
+    pub hash_algorithm: HashAlgorithm,
+    /// `hash_algorithm` was picked by the startup benchmark
+    pub hash_algorithm_auto: bool,
+    pub hash_offload_min_bytes: u64,
+    pub fuse_mount: Option<PathBuf>,
+    pub fuse_allow_other: bool,
+    /// Names of the simulated renderers announced over SSDP, see `mock_renderer`
+    pub mock_renderers: Vec<String>,
+    #[serde(serialize_with = "redact")]
+    pub opensubtitles_api_key: Option<String>,
+    pub subtitle_languages: String,
+    #[serde(serialize_with = "redact")]
+    pub tmdb_api_key: Option<String>,
+    pub tmdb_language: String,
+    pub tmdb_region: String,
+    /// Jellyfin server told about new files in folders with `media_scan`
+    pub jellyfin_url: Option<String>,
+    #[serde(serialize_with = "redact")]
+    pub jellyfin_api_key: Option<String>,
+    /// Plex server whose library section holding a new file is rescanned
+    pub plex_url: Option<String>,
+    #[serde(serialize_with = "redact")]
+    pub plex_token: Option<String>,
+    /// The uploads directory as the media servers see it; None uses its local absolute path
+    pub media_scan_path: Option<String>,
+    pub default_profile: Option<String>,
+    pub http3_port: Option<u16>,
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+    pub ffmpeg_path: String,
+    pub cold_storage_dir: Option<PathBuf>,
+    /// Directories `/api/admin/export` may copy to, below or at them; exports are disabled when empty
+    pub export_roots: Vec<PathBuf>,
+    pub multi_tenant: bool,
+    #[serde(serialize_with = "redact")]
+    pub admin_key: Option<String>,
+    /// Networks the admin endpoints accept requests from, `lan` unless set
+    pub admin_allowed_networks: Vec<IpNetwork>,
+    /// Reverse proxies whose `X-Forwarded-For` names the client
+    pub trusted_proxies: Vec<IpNetwork>,
+    /// Failed authentications from one address within the window that lock it out; 0 never locks addresses
+    pub auth_max_failures: u32,
+    /// Failed authentications naming one account within the window that lock it out; 0 never locks accounts
+    pub auth_account_max_failures: u32,
+    pub auth_failure_window_secs: u64,
+    pub auth_lockout_secs: u64,
+    /// One line per failed authentication and lockout, for fail2ban
+    pub auth_log_file: PathBuf,
+    pub download_buffer_size: usize,
+    /// Read size for disk images (ISO, VM disks)
+    pub image_buffer_size: usize,
+    /// Bytes of a disk image read ahead of the client, 0 disables read-ahead
+    pub image_read_ahead: u64,
+    /// Where snapshots of the metadata database are kept
+    pub backup_dir: PathBuf,
+    /// 0 disables scheduled snapshots
+    pub backup_interval_hours: u64,
+    pub backup_keep: usize,
+    /// WebPush (VAPID) signing key, created on first start
+    pub vapid_key_file: PathBuf,
+    /// Contact (`mailto:` or `https:` URL) push services may use to reach the operator
+    pub vapid_subject: String,
+    /// Free space on a storage volume below which a low disk space event is raised, 0 disables it
+    pub low_disk_space_percent: u8,
+    /// Free space below which a volume is critically low, 0 disables the level
+    pub critical_disk_space_percent: u8,
+    /// Refuse uploads while a volume is critically low
+    pub read_only_on_critical_disk: bool,
+    /// Broker (`mqtt://host:port`) server state is published to; None disables MQTT
+    pub mqtt_url: Option<String>,
+    pub mqtt_username: Option<String>,
+    #[serde(serialize_with = "redact")]
+    pub mqtt_password: Option<String>,
+    /// Prefix of the state, event and availability topics
+    pub mqtt_topic: String,
+    /// Home Assistant's MQTT discovery prefix
+    pub mqtt_discovery_prefix: String,
+    #[serde(serialize_with = "redact")]
+    pub telegram_bot_token: Option<String>,
+    /// The only chat the Telegram bot answers and sends notifications to
+    pub telegram_chat_id: Option<i64>,
+    /// Channel webhook upload notifications are posted to
+    #[serde(serialize_with = "redact")]
+    pub discord_webhook_url: Option<String>,
+    /// Verifies requests to the interactions endpoint (hex Ed25519 key)
+    pub discord_public_key: Option<String>,
+    /// With the bot token, used to register the slash commands at startup
+    pub discord_application_id: Option<String>,
+    #[serde(serialize_with = "redact")]
+    pub discord_bot_token: Option<String>,
+    /// Passed as `?key=` to the `/simple` control endpoints, which are disabled when unset
+    #[serde(serialize_with = "redact")]
+    pub simple_api_key: Option<String>,
+    /// Hosts (lowercase, each including its subdomains) `/media/proxy` may fetch from; the proxy is disabled when empty
+    pub media_proxy_hosts: Vec<String>,
+    /// Extensions (lowercase, without the dot) refused in every folder, on top of each folder's own list
+    pub blocked_extensions: Vec<String>,
+    /// Quarantine completed uploads whose content contradicts their extension
+    pub quarantine_mismatched_types: bool,
+    /// Bytes an extracted archive or bundle may unpack to
+    pub max_extracted_bytes: u64,
+    /// Directory of the scripts post-upload hooks may run; hooks are disabled when unset
+    pub hooks_dir: Option<PathBuf>,
+    /// Uploads assembled and hash-checked at once, see `io_scheduler`
+    pub max_concurrent_merges: usize,
+    pub max_concurrent_transcodes: usize,
+    /// Stored files re-hashed at once by the integrity check
+    pub max_concurrent_scrubs: usize,
+    /// Size of `transcoded/` above which the least recently used transcodes are evicted, 0 for no limit
+    pub transcode_cache_max_bytes: u64,
+    /// Hardware encoders transcodes may use, in order of preference; empty for software only
+    pub ffmpeg_hwaccel: Vec<HwAccel>,
+    /// DRM render node used by VAAPI encoders
+    pub vaapi_device: String,
+    /// Threads of the async runtime, 0 for one per CPU core
+    pub worker_threads: usize,
+    /// Cap of the blocking thread pool (file I/O, offloaded hashing)
+    pub max_blocking_threads: usize,
+    /// HTTP connections served at once, 0 for no limit; further ones wait to be accepted
+    pub max_connections: usize,
+    /// Keep HTTP/1.1 connections open between requests
+    pub keep_alive: bool,
+    /// Time a client has to send a request's headers, which is also how long
+    /// an idle keep-alive connection stays open
+    pub client_timeout_secs: u64,
+    /// From `system_config`, see `load_system_config`
+    pub chunk_size: u64,
+}
+
+impl AppConfig {
+    /// Load the settings from the environment (including `.env`) and the
+    /// settings file. Every invalid value is reported in the error.
+    pub fn load() -> Result<Self, String> {
+        dotenv::dotenv().ok();
+
+        let (config_path, required) = match env::var("NASCRAFT_CONFIG") {
+            Ok(path) if !path.trim().is_empty() => (PathBuf::from(path.trim()), true),
+            _ => (PathBuf::from(DEFAULT_CONFIG_FILE), false),
+        };
+        let mut source = SettingsSource::load(&config_path, required);
+        let config_file = config_path.exists().then_some(config_path);
+
+        // Without it metadata is kept in memory and lost on restart
+        let database_url = source.string("DATABASE_URL");
+
+        // Falls back to nascraft.log in log_dir
+        let log_file_path = source.string("LOG_FILE_PATH").map(PathBuf::from);
+
+        let log_dir = source.string("LOG_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from("logs"));
+
+        let server_port: u16 = source.parse("NASCRAFT_PORT").unwrap_or(8080);
+
+        let http_url = |v: &str| {
+            reqwest::Url::parse(v).ok()
+                .filter(|url| matches!(url.scheme(), "http" | "https"))
+                .map(|_| v.trim_end_matches('/').to_string())
+        };
+
+        let public_url = source.parse_with("NASCRAFT_PUBLIC_URL", http_url);
+
+        let mdns_service_type = source.string("NASCRAFT_MDNS_SERVICE_TYPE")
+            .unwrap_or_else(|| "_nascraft._tcp.local.".to_string());
+
+        let mdns_instance_name = source.string("NASCRAFT_MDNS_INSTANCE")
+            .unwrap_or_else(|| "nascraft".to_string());
+
+        let mdns_http = source.parse_with("NASCRAFT_MDNS_HTTP", parse_flag).unwrap_or(true);
+
+        let udp_discovery_port: u16 = source.parse("NASCRAFT_UDP_DISCOVERY_PORT").unwrap_or(53530);
+
+        let enable_dlna_remote = source.flag("NASCRAFT_ENABLE_DLNA_REMOTE");
+
+        // External media server used for DLNA renderer control and browsing; `off` disables the integration
+        let media_server_url = match source.get("NASCRAFT_MEDIA_SERVER_URL") {
+            Some(v) if v.trim().is_empty() || v.trim().eq_ignore_ascii_case("off") => None,
+            Some(v) => Some(v.trim().trim_end_matches('/').to_string()),
+            None => Some("http://localhost:9001".to_string()),
+        };
+
+        // Sent as a Bearer token when set
+        let media_server_token = source.string("NASCRAFT_MEDIA_SERVER_TOKEN");
+
+        let media_server_sse_path = source.string("NASCRAFT_MEDIA_SERVER_SSE_PATH")
+            .unwrap_or_else(|| "/v1/api/sse/".to_string());
+
+        let filename_policy = source.parse_with("NASCRAFT_FILENAME_POLICY", SanitizePolicy::parse)
+            .unwrap_or(SanitizePolicy::Unicode);
+
+        // `auto` 在启动时测速后选用最快的算法，结果在进程内缓存
+        let hash_algorithm_setting = source.parse_with("NASCRAFT_HASH_ALGORITHM", |v| match v.trim().to_lowercase().as_str() {
+            "auto" => Some(None),
+            v => HashAlgorithm::parse(v).map(Some),
+        });
+        let hash_algorithm_auto = matches!(hash_algorithm_setting, Some(None));
+        let hash_algorithm = match hash_algorithm_setting {
+            Some(Some(algorithm)) => algorithm,
+            Some(None) => fastest_hash_algorithm(),
+            None => HashAlgorithm::Sha256,
+        };
+
+        // 0 disables offloading; below the threshold inline hashing is cheaper than the channel round trips
+        let hash_offload_min_bytes: u64 = source.parse("NASCRAFT_HASH_OFFLOAD_MIN_BYTES").unwrap_or(256 * 1024);
+
+        // Only used when built with the `fuse` feature
+        let fuse_mount = source.string("NASCRAFT_FUSE_MOUNT").map(PathBuf::from);
+
+        let fuse_allow_other = source.flag("NASCRAFT_FUSE_ALLOW_OTHER");
+
+        // Only used when built with the `mock-renderer` feature
+        let mock_renderers = source.string("NASCRAFT_MOCK_RENDERERS")
+            .map(|v| v.split(',').map(str::trim).filter(|name| !name.is_empty()).map(String::from).collect())
+            .unwrap_or_default();
+
+        // Subtitle fetching is disabled unless an API key is configured
+        let opensubtitles_api_key = source.string("NASCRAFT_OPENSUBTITLES_API_KEY");
+
+        let subtitle_languages = source.string("NASCRAFT_SUBTITLE_LANGUAGES")
+            .unwrap_or_else(|| "en".to_string());
+
+        // Without a TMDB key, titles are only guessed from filenames
+        let tmdb_api_key = source.string("NASCRAFT_TMDB_API_KEY");
+
+        let tmdb_language = source.string("NASCRAFT_TMDB_LANGUAGE")
+            .unwrap_or_else(|| "en-US".to_string());
+
+        // Country whose certification (content rating) is stored
+        let tmdb_region = source.string("NASCRAFT_TMDB_REGION")
+            .unwrap_or_else(|| "US".to_string());
+
+        // Media servers are only notified about uploads to folders with `media_scan`
+        let jellyfin_url = source.parse_with("NASCRAFT_JELLYFIN_URL", http_url);
+
+        let jellyfin_api_key = source.string("NASCRAFT_JELLYFIN_API_KEY");
+
+        let plex_url = source.parse_with("NASCRAFT_PLEX_URL", http_url);
+
+        let plex_token = source.string("NASCRAFT_PLEX_TOKEN");
+
+        // Needed when the media servers mount the uploads directory somewhere else, e.g. in a container
+        let media_scan_path = source.string("NASCRAFT_MEDIA_SCAN_PATH")
+            .map(|v| v.trim_end_matches(['/', '\\']).to_string());
+
+        // Profile applied to clients that haven't activated one; unset means unrestricted
+        let default_profile = source.string("NASCRAFT_DEFAULT_PROFILE");
+
+        // Only used when built with the `http3` feature; QUIC always needs a certificate
+        let http3_port: Option<u16> = source.parse("NASCRAFT_HTTP3_PORT");
+
+        let tls_cert = source.string("NASCRAFT_TLS_CERT").map(PathBuf::from);
+
+        let tls_key = source.string("NASCRAFT_TLS_KEY").map(PathBuf::from);
+
+        // Used for folders with auto-transcoding
+        let ffmpeg_path = source.string("NASCRAFT_FFMPEG_PATH")
+            .unwrap_or_else(|| "ffmpeg".to_string());
+
+        // Where cold_storage retention rules move files; such rules are rejected when unset
+        let cold_storage_dir = source.string("NASCRAFT_COLD_STORAGE_DIR").map(PathBuf::from);
+
+        let export_roots = source.string("NASCRAFT_EXPORT_ROOTS")
+            .map(|v| v.split(',').map(str::trim).filter(|root| !root.is_empty()).map(PathBuf::from).collect())
+            .unwrap_or_default();
+
+        // Every request must then name a tenant by API key or device token, except those made with the admin key
+        let multi_tenant = source.flag("NASCRAFT_MULTI_TENANT");
+
+        // Sent as X-Admin-Key to manage tenants and to act across tenants; tenant administration is disabled when unset
+        let admin_key = source.string("NASCRAFT_ADMIN_KEY");
+
+        // Admin and hook endpoints stay on the LAN unless widened, e.g. with `any`
+        let admin_allowed_networks = source.parse_with("NASCRAFT_ADMIN_ALLOWED_NETWORKS", parse_networks).unwrap_or_else(lan_networks);
+
+        let trusted_proxies = source.parse_with("NASCRAFT_TRUSTED_PROXIES", parse_networks).unwrap_or_default();
+
+        let auth_max_failures: u32 = source.parse("NASCRAFT_AUTH_MAX_FAILURES").unwrap_or(10);
+
+        let auth_account_max_failures: u32 = source.parse("NASCRAFT_AUTH_ACCOUNT_MAX_FAILURES").unwrap_or(0);
+
+        let auth_failure_window_secs = source.parse_with("NASCRAFT_AUTH_FAILURE_WINDOW_SECS", |v| v.parse::<u64>().ok().filter(|&n| n > 0)).unwrap_or(600);
+
+        let auth_lockout_secs = source.parse_with("NASCRAFT_AUTH_LOCKOUT_SECS", |v| v.parse::<u64>().ok().filter(|&n| n > 0)).unwrap_or(900);
+
+        // Next to the server log by default
+        let auth_log_file = source.string("NASCRAFT_AUTH_LOG_FILE")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| match log_file_path.as_ref().and_then(|path| path.parent()) {
+                Some(dir) => dir.join("auth.log"),
+                None => log_dir.join("auth.log"),
+            });
+
+        let positive = |v: &str| v.parse::<usize>().ok().filter(|&n| n > 0);
+
+        let download_buffer_size = source.parse_with("NASCRAFT_DOWNLOAD_BUFFER_SIZE", positive).unwrap_or(64 * 1024);
+
+        // Network boot loaders and hypervisors fetch images in long sequential runs, so bigger reads pay off
+        let image_buffer_size = source.parse_with("NASCRAFT_IMAGE_BUFFER_SIZE", positive).unwrap_or(1024 * 1024);
+
+        let image_read_ahead: u64 = source.parse("NASCRAFT_IMAGE_READ_AHEAD").unwrap_or(8 * 1024 * 1024);
+
+        let backup_dir = source.string("NASCRAFT_BACKUP_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from("backups"));
+
+        let backup_interval_hours: u64 = source.parse("NASCRAFT_BACKUP_INTERVAL_HOURS").unwrap_or(24);
+
+        // Rotation always keeps at least the newest snapshot
+        let backup_keep = source.parse_with("NASCRAFT_BACKUP_KEEP", positive).unwrap_or(7);
+
+        let vapid_key_file = source.string("NASCRAFT_VAPID_KEY_FILE")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from("vapid_private_key.pk8"));
+
+        let vapid_subject = source.string("NASCRAFT_VAPID_SUBJECT")
+            .unwrap_or_else(|| "mailto:nascraft@localhost".to_string());
+
+        let low_disk_space_percent = source.parse_with("NASCRAFT_LOW_DISK_SPACE_PERCENT", |v| v.parse::<u8>().ok().filter(|&p| p < 100))
+            .unwrap_or(5);
+        let critical_disk_space_percent = source.parse_with("NASCRAFT_CRITICAL_DISK_SPACE_PERCENT", |v| v.parse::<u8>().ok().filter(|&p| p < 100))
+            .unwrap_or(2);
+        let read_only_on_critical_disk = source.parse_with("NASCRAFT_READ_ONLY_ON_CRITICAL_DISK", parse_flag).unwrap_or(false);
+
+        let mqtt_url = source.parse_with("NASCRAFT_MQTT_URL", |v| {
+            reqwest::Url::parse(v).ok()
+                .filter(|url| url.scheme() == "mqtt" && url.host_str().is_some())
+                .map(|_| v.to_string())
+        });
+
+        let mqtt_username = source.string("NASCRAFT_MQTT_USERNAME");
+
+        let mqtt_password = source.string("NASCRAFT_MQTT_PASSWORD");
+
+        let mqtt_topic = source.string("NASCRAFT_MQTT_TOPIC")
+            .map(|v| v.trim_matches('/').to_string())
+            .unwrap_or_else(|| "nascraft".to_string());
+
+        let mqtt_discovery_prefix = source.string("NASCRAFT_MQTT_DISCOVERY_PREFIX")
+            .map(|v| v.trim_matches('/').to_string())
+            .unwrap_or_else(|| "homeassistant".to_string());
+
+        let telegram_bot_token = source.string("NASCRAFT_TELEGRAM_BOT_TOKEN");
+
+        // Required with the token: the bot would otherwise take commands from anyone who finds it
+        let telegram_chat_id: Option<i64> = source.parse("NASCRAFT_TELEGRAM_CHAT_ID");
+
+        let discord_webhook_url = source.parse_with("NASCRAFT_DISCORD_WEBHOOK_URL", http_url);
+
+        let discord_public_key = source.parse_with("NASCRAFT_DISCORD_PUBLIC_KEY", |v| {
+            (v.len() == 64 && v.chars().all(|c| c.is_ascii_hexdigit())).then(|| v.to_lowercase())
+        });
+
+        let discord_application_id = source.string("NASCRAFT_DISCORD_APPLICATION_ID");
+
+        let discord_bot_token = source.string("NASCRAFT_DISCORD_BOT_TOKEN");
+
+        let simple_api_key = source.string("NASCRAFT_SIMPLE_API_KEY");
+
+        let media_proxy_hosts = source.string("NASCRAFT_MEDIA_PROXY_HOSTS")
+            .map(|v| v
+                .split(',')
+                .map(|host| host.trim().trim_start_matches("*.").trim_matches('.').to_lowercase())
+                .filter(|host| !host.is_empty())
+                .collect())
+            .unwrap_or_default();
+
+        let blocked_extensions = source.string("NASCRAFT_BLOCKED_EXTENSIONS")
+            .map(|v| normalize_extensions(v.split(',')))
+            .unwrap_or_default();
+
+        let quarantine_mismatched_types = source.parse_with("NASCRAFT_QUARANTINE_MISMATCHED_TYPES", parse_flag).unwrap_or(true);
+
+        let max_extracted_bytes = source.parse_with("NASCRAFT_MAX_EXTRACTED_BYTES", |v| v.parse::<u64>().ok().filter(|&n| n > 0))
+            .unwrap_or(16 * 1024 * 1024 * 1024);
+        let hooks_dir = source.string("NASCRAFT_HOOKS_DIR").map(PathBuf::from);
+
+        let max_concurrent_merges = source.parse_with("NASCRAFT_MAX_CONCURRENT_MERGES", positive).unwrap_or(2);
+        let max_concurrent_transcodes = source.parse_with("NASCRAFT_MAX_CONCURRENT_TRANSCODES", positive).unwrap_or(1);
+        let max_concurrent_scrubs = source.parse_with("NASCRAFT_MAX_CONCURRENT_SCRUBS", positive).unwrap_or(1);
+        let transcode_cache_max_bytes: u64 = source.parse("NASCRAFT_TRANSCODE_CACHE_MAX_BYTES").unwrap_or(20 * 1024 * 1024 * 1024);
+        let ffmpeg_hwaccel = source.parse_with("NASCRAFT_FFMPEG_HWACCEL", HwAccel::parse_list)
+            .unwrap_or_else(|| HwAccel::ALL.to_vec());
+        let vaapi_device = source.string("NASCRAFT_VAAPI_DEVICE")
+            .unwrap_or_else(|| "/dev/dri/renderD128".to_string());
+
+        let worker_threads: usize = source.parse("NASCRAFT_WORKER_THREADS").unwrap_or(0);
+        let max_blocking_threads = source.parse_with("NASCRAFT_MAX_BLOCKING_THREADS", positive).unwrap_or(512);
+        let max_connections: usize = source.parse("NASCRAFT_MAX_CONNECTIONS").unwrap_or(0);
+        let keep_alive = source.parse_with("NASCRAFT_KEEP_ALIVE", parse_flag).unwrap_or(true);
+        let client_timeout_secs = source.parse_with("NASCRAFT_CLIENT_TIMEOUT_SECS", |v| v.parse::<u64>().ok().filter(|&n| n > 0))
+            .unwrap_or(30);
+
+        if telegram_bot_token.is_some() && telegram_chat_id.is_none() {
+            source.errors.push("NASCRAFT_TELEGRAM_CHAT_ID is required with NASCRAFT_TELEGRAM_BOT_TOKEN".to_string());
+        }
+
+        if !source.errors.is_empty() {
+            return Err(source.errors.join("; "));
+        }
+
+        Ok(Self {
+            config_file,
+            database_url,
+            log_file_path,
+            log_dir,
+            server_port,
+            public_url,
+            mdns_service_type,
+            mdns_instance_name,
+            mdns_http,
+            udp_discovery_port,
+            enable_dlna_remote,
+            media_server_url,
+            media_server_token,
+            media_server_sse_path,
+            filename_policy,
+            hash_algorithm,
+            hash_algorithm_auto,
+            hash_offload_min_bytes,
+            fuse_mount,
+            fuse_allow_other,
+            mock_renderers,
+            opensubtitles_api_key,
+            subtitle_languages,
+            tmdb_api_key,
+            tmdb_language,
+            tmdb_region,
+            jellyfin_url,
+            jellyfin_api_key,
+            plex_url,
+            plex_token,
+            media_scan_path,
+            default_profile,
+            http3_port,
+            tls_cert,
+            tls_key,
+            ffmpeg_path,
+            cold_storage_dir,
+            export_roots,
+            multi_tenant,
+            admin_key,
+            admin_allowed_networks,
+            trusted_proxies,
+            auth_max_failures,
+            auth_account_max_failures,
+            auth_failure_window_secs,
+            auth_lockout_secs,
+            auth_log_file,
+            download_buffer_size,
+            image_buffer_size,
+            image_read_ahead,
+            backup_dir,
+            backup_interval_hours,
+            backup_keep,
+            vapid_key_file,
+            vapid_subject,
+            low_disk_space_percent,
+            critical_disk_space_percent,
+            read_only_on_critical_disk,
+            mqtt_url,
+            mqtt_username,
+            mqtt_password,
+            mqtt_topic,
+            mqtt_discovery_prefix,
+            telegram_bot_token,
+            telegram_chat_id,
+            discord_webhook_url,
+            discord_public_key,
+            discord_application_id,
+            discord_bot_token,
+            simple_api_key,
+            media_proxy_hosts,
+            blocked_extensions,
+            quarantine_mismatched_types,
+            max_extracted_bytes,
+            hooks_dir,
+            max_concurrent_merges,
+            max_concurrent_transcodes,
+            max_concurrent_scrubs,
+            transcode_cache_max_bytes,
+            ffmpeg_hwaccel,
+            vaapi_device,
+            worker_threads,
+            max_blocking_threads,
+            max_connections,
+            keep_alive,
+            client_timeout_secs,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+        })
+    }
+
+    /// Logged once logging is set up, which itself depends on the config
+    pub fn log_summary(&self) {
+        info!(
+            "Loaded config: config_file={:?}, server_port={}, public_url={:?}, mdns_service_type={}, mdns_instance_name={}, mdns_http={}, udp_discovery_port={}, enable_dlna_remote={}, media_server_url={:?}, media_server_auth={}, media_server_sse_path={}, filename_policy={:?}, hash_algorithm={}, hash_algorithm_auto={}, hash_offload_min_bytes={}, fuse_mount={:?}, fuse_allow_other={}, mock_renderers={:?}, opensubtitles_enabled={}, subtitle_languages={}, tmdb_enabled={}, tmdb_language={}, tmdb_region={}, jellyfin_url={:?}, jellyfin_auth={}, plex_url={:?}, plex_auth={}, media_scan_path={:?}, default_profile={:?}, http3_port={:?}, tls_cert={:?}, tls_key={:?}, ffmpeg_path={}, cold_storage_dir={:?}, export_roots={:?}, multi_tenant={}, admin_key_set={}, admin_allowed_networks={:?}, trusted_proxies={:?}, auth_max_failures={}, auth_account_max_failures={}, auth_failure_window_secs={}, auth_lockout_secs={}, auth_log_file={:?}, download_buffer_size={}, image_buffer_size={}, image_read_ahead={}, backup_dir={:?}, backup_interval_hours={}, backup_keep={}, vapid_key_file={:?}, vapid_subject={}, low_disk_space_percent={}, critical_disk_space_percent={}, read_only_on_critical_disk={}, mqtt_url={:?}, mqtt_username={:?}, mqtt_auth={}, mqtt_topic={}, mqtt_discovery_prefix={}, telegram_bot={}, telegram_chat_id={:?}, discord_webhook={}, discord_interactions={}, discord_application_id={:?}, simple_api={}, media_proxy_hosts={:?}, blocked_extensions={:?}, quarantine_mismatched_types={}, max_extracted_bytes={}, hooks_dir={:?}, max_concurrent_merges={}, max_concurrent_transcodes={}, max_concurrent_scrubs={}, transcode_cache_max_bytes={}, ffmpeg_hwaccel={:?}, vaapi_device={}, worker_threads={}, max_blocking_threads={}, max_connections={}, keep_alive={}, client_timeout_secs={}",
+            self.config_file, self.server_port, self.public_url, self.mdns_service_type, self.mdns_instance_name, self.mdns_http, self.udp_discovery_port, self.enable_dlna_remote, self.media_server_url, self.media_server_token.is_some(), self.media_server_sse_path, self.filename_policy, self.hash_algorithm.as_str(), self.hash_algorithm_auto, self.hash_offload_min_bytes, self.fuse_mount, self.fuse_allow_other, self.mock_renderers, self.opensubtitles_api_key.is_some(), self.subtitle_languages, self.tmdb_api_key.is_some(), self.tmdb_language, self.tmdb_region, self.jellyfin_url, self.jellyfin_api_key.is_some(), self.plex_url, self.plex_token.is_some(), self.media_scan_path, self.default_profile, self.http3_port, self.tls_cert, self.tls_key, self.ffmpeg_path, self.cold_storage_dir, self.export_roots, self.multi_tenant, self.admin_key.is_some(), self.admin_allowed_networks, self.trusted_proxies, self.auth_max_failures, self.auth_account_max_failures, self.auth_failure_window_secs, self.auth_lockout_secs, self.auth_log_file, self.download_buffer_size, self.image_buffer_size, self.image_read_ahead, self.backup_dir, self.backup_interval_hours, self.backup_keep, self.vapid_key_file, self.vapid_subject, self.low_disk_space_percent, self.critical_disk_space_percent, self.read_only_on_critical_disk, self.mqtt_url, self.mqtt_username, self.mqtt_password.is_some(), self.mqtt_topic, self.mqtt_discovery_prefix, self.telegram_bot_token.is_some(), self.telegram_chat_id, self.discord_webhook_url.is_some(), self.discord_public_key.is_some(), self.discord_application_id, self.simple_api_key.is_some(), self.media_proxy_hosts, self.blocked_extensions, self.quarantine_mismatched_types, self.max_extracted_bytes, self.hooks_dir, self.max_concurrent_merges, self.max_concurrent_transcodes, self.max_concurrent_scrubs, self.transcode_cache_max_bytes, self.ffmpeg_hwaccel, self.vaapi_device, self.worker_threads, self.max_blocking_threads, self.max_connections, self.keep_alive, self.client_timeout_secs
+        );
+    }
+
+    /// Read the settings kept in the `system_config` table
+    pub async fn load_system_config(&mut self, db_pool: &SqlitePool) -> Result<(), String> {
+        let chunk_size = fetch_chunk_size(db_pool).await?;
+        if chunk_size == 0 {
+            return Err("chunk_size in system_config must be positive".to_string());
+        }
+        self.chunk_size = chunk_size;
+        info!("Loaded system config: chunk_size={}", chunk_size);
+        Ok(())
+    }
+
+    /// Names of the settings that differ from `other`
+    pub fn changed_settings(&self, other: &AppConfig) -> Vec<&'static str> {
+        let settings = [
+            ("config_file", self.config_file != other.config_file),
+            ("database_url", self.database_url != other.database_url),
+            ("log_file_path", self.log_file_path != other.log_file_path),
+            ("log_dir", self.log_dir != other.log_dir),
+            ("server_port", self.server_port != other.server_port),
+            ("public_url", self.public_url != other.public_url),
+            ("mdns_service_type", self.mdns_service_type != other.mdns_service_type),
+            ("mdns_instance_name", self.mdns_instance_name != other.mdns_instance_name),
+            ("mdns_http", self.mdns_http != other.mdns_http),
+            ("udp_discovery_port", self.udp_discovery_port != other.udp_discovery_port),
+            ("enable_dlna_remote", self.enable_dlna_remote != other.enable_dlna_remote),
+            ("media_server_url", self.media_server_url != other.media_server_url),
+            ("media_server_token", self.media_server_token != other.media_server_token),
+            ("media_server_sse_path", self.media_server_sse_path != other.media_server_sse_path),
+            ("filename_policy", self.filename_policy != other.filename_policy),
+            ("hash_algorithm", self.hash_algorithm != other.hash_algorithm),
+            ("hash_algorithm_auto", self.hash_algorithm_auto != other.hash_algorithm_auto),
+            ("hash_offload_min_bytes", self.hash_offload_min_bytes != other.hash_offload_min_bytes),
+            ("fuse_mount", self.fuse_mount != other.fuse_mount),
+            ("fuse_allow_other", self.fuse_allow_other != other.fuse_allow_other),
+            ("mock_renderers", self.mock_renderers != other.mock_renderers),
+            ("opensubtitles_api_key", self.opensubtitles_api_key != other.opensubtitles_api_key),
+            ("subtitle_languages", self.subtitle_languages != other.subtitle_languages),
+            ("tmdb_api_key", self.tmdb_api_key != other.tmdb_api_key),
+            ("tmdb_language", self.tmdb_language != other.tmdb_language),
+            ("tmdb_region", self.tmdb_region != other.tmdb_region),
+            ("jellyfin_url", self.jellyfin_url != other.jellyfin_url),
+            ("jellyfin_api_key", self.jellyfin_api_key != other.jellyfin_api_key),
+            ("plex_url", self.plex_url != other.plex_url),
+            ("plex_token", self.plex_token != other.plex_token),
+            ("media_scan_path", self.media_scan_path != other.media_scan_path),
+            ("default_profile", self.default_profile != other.default_profile),
+            ("http3_port", self.http3_port != other.http3_port),
+            ("tls_cert", self.tls_cert != other.tls_cert),
+            ("tls_key", self.tls_key != other.tls_key),
+            ("ffmpeg_path", self.ffmpeg_path != other.ffmpeg_path),
+            ("cold_storage_dir", self.cold_storage_dir != other.cold_storage_dir),
+            ("export_roots", self.export_roots != other.export_roots),
+            ("multi_tenant", self.multi_tenant != other.multi_tenant),
+            ("admin_key", self.admin_key != other.admin_key),
+            ("admin_allowed_networks", self.admin_allowed_networks != other.admin_allowed_networks),
+            ("trusted_proxies", self.trusted_proxies != other.trusted_proxies),
+            ("auth_max_failures", self.auth_max_failures != other.auth_max_failures),
+            ("auth_account_max_failures", self.auth_account_max_failures != other.auth_account_max_failures),
+            ("auth_failure_window_secs", self.auth_failure_window_secs != other.auth_failure_window_secs),
+            ("auth_lockout_secs", self.auth_lockout_secs != other.auth_lockout_secs),
+            ("auth_log_file", self.auth_log_file != other.auth_log_file),
+            ("download_buffer_size", self.download_buffer_size != other.download_buffer_size),
+            ("image_buffer_size", self.image_buffer_size != other.image_buffer_size),
+            ("image_read_ahead", self.image_read_ahead != other.image_read_ahead),
+            ("backup_dir", self.backup_dir != other.backup_dir),
+            ("backup_interval_hours", self.backup_interval_hours != other.backup_interval_hours),
+            ("backup_keep", self.backup_keep != other.backup_keep),
+            ("vapid_key_file", self.vapid_key_file != other.vapid_key_file),
+            ("vapid_subject", self.vapid_subject != other.vapid_subject),
+            ("low_disk_space_percent", self.low_disk_space_percent != other.low_disk_space_percent),
+            ("critical_disk_space_percent", self.critical_disk_space_percent != other.critical_disk_space_percent),
+            ("read_only_on_critical_disk", self.read_only_on_critical_disk != other.read_only_on_critical_disk),
+            ("mqtt_url", self.mqtt_url != other.mqtt_url),
+            ("mqtt_username", self.mqtt_username != other.mqtt_username),
+            ("mqtt_password", self.mqtt_password != other.mqtt_password),
+            ("mqtt_topic", self.mqtt_topic != other.mqtt_topic),
+            ("mqtt_discovery_prefix", self.mqtt_discovery_prefix != other.mqtt_discovery_prefix),
+            ("telegram_bot_token", self.telegram_bot_token != other.telegram_bot_token),
+            ("telegram_chat_id", self.telegram_chat_id != other.telegram_chat_id),
+            ("discord_webhook_url", self.discord_webhook_url != other.discord_webhook_url),
+            ("discord_public_key", self.discord_public_key != other.discord_public_key),
+            ("discord_application_id", self.discord_application_id != other.discord_application_id),
+            ("discord_bot_token", self.discord_bot_token != other.discord_bot_token),
+            ("simple_api_key", self.simple_api_key != other.simple_api_key),
+            ("media_proxy_hosts", self.media_proxy_hosts != other.media_proxy_hosts),
+            ("blocked_extensions", self.blocked_extensions != other.blocked_extensions),
+            ("quarantine_mismatched_types", self.quarantine_mismatched_types != other.quarantine_mismatched_types),
+            ("max_extracted_bytes", self.max_extracted_bytes != other.max_extracted_bytes),
+            ("hooks_dir", self.hooks_dir != other.hooks_dir),
+            ("max_concurrent_merges", self.max_concurrent_merges != other.max_concurrent_merges),
+            ("max_concurrent_transcodes", self.max_concurrent_transcodes != other.max_concurrent_transcodes),
+            ("max_concurrent_scrubs", self.max_concurrent_scrubs != other.max_concurrent_scrubs),
+            ("transcode_cache_max_bytes", self.transcode_cache_max_bytes != other.transcode_cache_max_bytes),
+            ("ffmpeg_hwaccel", self.ffmpeg_hwaccel != other.ffmpeg_hwaccel),
+            ("vaapi_device", self.vaapi_device != other.vaapi_device),
+            ("worker_threads", self.worker_threads != other.worker_threads),
+            ("max_blocking_threads", self.max_blocking_threads != other.max_blocking_threads),
+            ("max_connections", self.max_connections != other.max_connections),
+            ("keep_alive", self.keep_alive != other.keep_alive),
+            ("client_timeout_secs", self.client_timeout_secs != other.client_timeout_secs),
+            ("chunk_size", self.chunk_size != other.chunk_size),
+        ];
+        settings.into_iter().filter(|(_, changed)| *changed).map(|(name, _)| name).collect()
+    }
+}
+
+/// Settings that are only read at startup, by listeners and background services
+pub const RESTART_REQUIRED_SETTINGS: &[&str] = &[
+    "database_url",
+    "log_file_path",
+    "log_dir",
+    "server_port",
+    "mdns_service_type",
+    "mdns_instance_name",
+    "mdns_http",
+    "udp_discovery_port",
+    "enable_dlna_remote",
+    "media_server_url",
+    "media_server_token",
+    "media_server_sse_path",
+    "fuse_mount",
+    "fuse_allow_other",
+    "mock_renderers",
+    "http3_port",
+    "tls_cert",
+    "tls_key",
+    "backup_interval_hours",
+    "vapid_key_file",
+    "vapid_subject",
+    "mqtt_url",
+    "mqtt_username",
+    "mqtt_password",
+    "mqtt_topic",
+    "mqtt_discovery_prefix",
+    "telegram_bot_token",
+    "telegram_chat_id",
+    "discord_webhook_url",
+    "discord_application_id",
+    "discord_bot_token",
+    "max_concurrent_merges",
+    "max_concurrent_transcodes",
+    "max_concurrent_scrubs",
+    "ffmpeg_hwaccel",
+    "vaapi_device",
+    "worker_threads",
+    "max_blocking_threads",
+    "max_connections",
+    "keep_alive",
+    "client_timeout_secs",
+];
+
+/// Handle to the current config shared by all handlers. `load` returns a
+/// snapshot, so a request keeps seeing one consistent config while `store`
+/// swaps in a reloaded one.
+#[derive(Clone)]
+pub struct SharedConfig {
+    current: Arc<RwLock<Arc<AppConfig>>>,
+}
+
+impl SharedConfig {
+    pub fn new(config: AppConfig) -> Self {
+        Self {
+            current: Arc::new(RwLock::new(Arc::new(config))),
+        }
+    }
+
+    pub fn load(&self) -> Arc<AppConfig> {
+        self.current.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    pub fn store(&self, config: AppConfig) {
+        *self.current.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(config);
+    }
+}
+
+#[cfg(test)]
+impl AppConfig {
+    /// The settings as loaded at startup, for tests to adjust the ones they rely on
+    pub fn for_tests() -> Self {
+        Self::load().expect("the test environment has valid settings")
+    }
+}
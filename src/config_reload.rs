@@ -0,0 +1,91 @@
+use axum::{
+    extract::{ConnectInfo, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use log::{error, info};
+use serde::Serialize;
+use std::env;
+use std::net::SocketAddr;
+use crate::config::{AppConfig, RESTART_REQUIRED_SETTINGS};
+use crate::context::AppContext;
+use crate::helper::{error_response, ApiResponse};
+use crate::profiles::ensure_unrestricted;
+
+#[derive(Serialize)]
+struct ReloadReport {
+    changed: Vec<&'static str>,
+    /// Changed settings that only take effect after a restart
+    restart_required: Vec<&'static str>,
+}
+
+/// Apply the variables in `.env`. Unlike at startup they override the
+/// environment, so edits to the file take effect.
+fn reload_env_file() -> Result<(), String> {
+    // dotenv() skips variables that are already set, so the file is iterated instead
+    #[allow(deprecated)]
+    let vars = match dotenv::dotenv_iter() {
+        Ok(vars) => vars,
+        Err(dotenv::Error::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => {
+            error!("Failed to read .env: {}", e);
+            return Err(format!("Failed to read .env: {}", e));
+        }
+    };
+    for var in vars {
+        let (key, value) = var.map_err(|e| {
+            error!("Failed to parse .env: {}", e);
+            format!("Failed to parse .env: {}", e)
+        })?;
+        env::set_var(key, value);
+    }
+    Ok(())
+}
+
+/// Re-read `.env`, the environment and `system_config` and swap the result in
+/// for all handlers
+pub async fn reload_config(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+) -> impl IntoResponse {
+    if let Err(resp) = ensure_unrestricted(&ctx, &client_addr).await {
+        return resp;
+    }
+    if let Err(e) = reload_env_file() {
+        return error_response(StatusCode::INTERNAL_SERVER_ERROR, "RELOAD_CONFIG_ERROR", e);
+    }
+    let mut config = match AppConfig::load() {
+        Ok(config) => config,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, "INVALID_CONFIG", e),
+    };
+    config.log_summary();
+    if let Err(e) = config.load_system_config(&ctx.app_state.db_pool).await {
+        return error_response(StatusCode::INTERNAL_SERVER_ERROR, "RELOAD_CONFIG_ERROR", e);
+    }
+
+    let changed = config.changed_settings(&ctx.config.load());
+    let restart_required: Vec<&'static str> = changed
+        .iter()
+        .filter(|name| RESTART_REQUIRED_SETTINGS.contains(name))
+        .copied()
+        .collect();
+    ctx.config.store(config);
+    info!("Reloaded config, changed settings: {:?}", changed);
+
+    (StatusCode::OK, Json(ApiResponse::success(ReloadReport {
+        changed,
+        restart_required,
+    }))).into_response()
+}
+
+/// The settings in effect, with API keys and tokens redacted
+pub async fn get_config(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+) -> impl IntoResponse {
+    if let Err(resp) = ensure_unrestricted(&ctx, &client_addr).await {
+        return resp;
+    }
+    (StatusCode::OK, Json(ApiResponse::success(ctx.config.load()))).into_response()
+}
@@ -0,0 +1,201 @@
+/// A parsed `Content-Range: bytes <start>-<end>/<total>` request header.
+/// `end` is inclusive; `total` is None for `*`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ContentRange {
+    pub start: u64,
+    pub end: u64,
+    pub total: Option<u64>,
+}
+
+impl ContentRange {
+    pub fn len(&self) -> u64 {
+        self.end - self.start + 1
+    }
+}
+
+/// Digits only: `u64::from_str` would also accept a leading `+`
+fn parse_position(value: &str, what: &str) -> Result<u64, String> {
+    if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(format!("Invalid {} in Content-Range: {:?}", what, value));
+    }
+    value.parse::<u64>().map_err(|_| format!("{} in Content-Range is too large", what))
+}
+
+/// Parse a Content-Range header value. Only the `bytes` unit and a complete
+/// `start-end` range are accepted; the unsatisfied form `bytes */total` is a
+/// response-only form and is rejected.
+pub fn parse_content_range(value: &str) -> Result<ContentRange, String> {
+    let (unit, spec) = value
+        .trim()
+        .split_once(' ')
+        .ok_or_else(|| format!("Malformed Content-Range: {:?}", value))?;
+    if !unit.eq_ignore_ascii_case("bytes") {
+        return Err(format!("Unsupported Content-Range unit: {:?}", unit));
+    }
+
+    let (range, total) = spec
+        .trim_start()
+        .split_once('/')
+        .ok_or_else(|| format!("Content-Range is missing the total size: {:?}", value))?;
+    let (start, end) = range
+        .split_once('-')
+        .ok_or_else(|| format!("Content-Range is missing the range: {:?}", value))?;
+
+    let start = parse_position(start, "start")?;
+    let end = parse_position(end, "end")?;
+    if end < start {
+        return Err(format!("Content-Range end {} is before start {}", end, start));
+    }
+
+    let total = match total {
+        "*" => None,
+        total => {
+            let total = parse_position(total, "total size")?;
+            if end >= total {
+                return Err(format!("Content-Range end {} is beyond total size {}", end, total));
+            }
+            Some(total)
+        }
+    };
+
+    Ok(ContentRange { start, end, total })
+}
+
+/// What a `Range` request header asks for from a file of a known length
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RangeRequest {
+    /// No usable single range: serve the whole file. Malformed headers and
+    /// requests for several ranges may be ignored this way.
+    Full,
+    /// `start..=end`, clamped to the file
+    Partial { start: u64, end: u64 },
+    /// Starts at or past the end of the file
+    Unsatisfiable,
+}
+
+/// Parse a `Range: bytes=<start>-<end>`, `bytes=<start>-` or `bytes=-<suffix>`
+/// request header against a file of `len` bytes
+pub fn parse_range(value: &str, len: u64) -> RangeRequest {
+    let digits = |s: &str| -> Option<u64> {
+        (!s.is_empty() && s.bytes().all(|b| b.is_ascii_digit())).then(|| s.parse().ok()).flatten()
+    };
+    let Some(spec) = value.trim().strip_prefix("bytes=") else {
+        return RangeRequest::Full;
+    };
+    if spec.contains(',') {
+        return RangeRequest::Full;
+    }
+    let Some((start, end)) = spec.trim().split_once('-') else {
+        return RangeRequest::Full;
+    };
+
+    match (start, end) {
+        ("", suffix) => match digits(suffix) {
+            Some(0) => RangeRequest::Unsatisfiable,
+            Some(_) if len == 0 => RangeRequest::Unsatisfiable,
+            Some(suffix) => RangeRequest::Partial { start: len.saturating_sub(suffix), end: len - 1 },
+            None => RangeRequest::Full,
+        },
+        (start, end) => {
+            let Some(start) = digits(start) else {
+                return RangeRequest::Full;
+            };
+            let end = match end {
+                "" => u64::MAX,
+                end => match digits(end) {
+                    Some(end) if end >= start => end,
+                    _ => return RangeRequest::Full,
+                },
+            };
+            if start >= len {
+                RangeRequest::Unsatisfiable
+            } else {
+                RangeRequest::Partial { start, end: end.min(len - 1) }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_complete_ranges() {
+        assert_eq!(parse_content_range("bytes 0-4/10"), Ok(ContentRange { start: 0, end: 4, total: Some(10) }));
+        assert_eq!(parse_content_range("bytes 5-9/*"), Ok(ContentRange { start: 5, end: 9, total: None }));
+        assert_eq!(parse_content_range(" BYTES  3-3/4 "), Ok(ContentRange { start: 3, end: 3, total: Some(4) }));
+        assert_eq!(parse_content_range("bytes 0-4/10").unwrap().len(), 5);
+    }
+
+    #[test]
+    fn rejects_malformed_content_ranges() {
+        for value in [
+            "",
+            "bytes",
+            "items 0-4/10",
+            "bytes 0-4",
+            "bytes 0-4/",
+            "bytes 4/10",
+            "bytes */10",
+            "bytes +0-4/10",
+            "bytes 0-+4/10",
+            "bytes 0-4/+10",
+            "bytes 0- 4/10",
+            "bytes 0-4 /10",
+            "bytes -1-4/10",
+            "bytes 0x0-4/10",
+        ] {
+            assert!(parse_content_range(value).is_err(), "{:?} was accepted", value);
+        }
+    }
+
+    #[test]
+    fn rejects_inverted_and_out_of_bounds_ranges() {
+        assert!(parse_content_range("bytes 5-4/10").is_err());
+        assert!(parse_content_range("bytes 0-10/10").is_err());
+        assert!(parse_content_range("bytes 0-0/0").is_err());
+        assert!(parse_content_range("bytes 0-9/10").is_ok());
+    }
+
+    #[test]
+    fn rejects_positions_that_overflow() {
+        assert!(parse_content_range("bytes 0-18446744073709551616/*").is_err());
+        assert!(parse_content_range("bytes 18446744073709551616-18446744073709551617/*").is_err());
+        assert!(parse_content_range("bytes 0-1/18446744073709551616").is_err());
+        assert_eq!(
+            parse_content_range("bytes 0-18446744073709551614/18446744073709551615"),
+            Ok(ContentRange { start: 0, end: u64::MAX - 1, total: Some(u64::MAX) })
+        );
+    }
+
+    #[test]
+    fn parses_ranges() {
+        assert_eq!(parse_range("bytes=0-4", 10), RangeRequest::Partial { start: 0, end: 4 });
+        assert_eq!(parse_range("bytes=5-100", 10), RangeRequest::Partial { start: 5, end: 9 });
+        assert_eq!(parse_range("bytes=10-12", 10), RangeRequest::Unsatisfiable);
+    }
+
+    #[test]
+    fn parses_open_ended_ranges() {
+        assert_eq!(parse_range("bytes=3-", 10), RangeRequest::Partial { start: 3, end: 9 });
+        assert_eq!(parse_range("bytes=9-", 10), RangeRequest::Partial { start: 9, end: 9 });
+        assert_eq!(parse_range("bytes=10-", 10), RangeRequest::Unsatisfiable);
+        assert_eq!(parse_range("bytes=0-", 0), RangeRequest::Unsatisfiable);
+    }
+
+    #[test]
+    fn parses_suffix_ranges() {
+        assert_eq!(parse_range("bytes=-4", 10), RangeRequest::Partial { start: 6, end: 9 });
+        assert_eq!(parse_range("bytes=-100", 10), RangeRequest::Partial { start: 0, end: 9 });
+        assert_eq!(parse_range("bytes=-0", 10), RangeRequest::Unsatisfiable);
+        assert_eq!(parse_range("bytes=-4", 0), RangeRequest::Unsatisfiable);
+    }
+
+    #[test]
+    fn ignores_unusable_ranges() {
+        for value in ["", "items=0-4", "bytes=0-1,3-4", "bytes=4-2", "bytes=+1-4", "bytes=a-4", "bytes=-", "bytes=5"] {
+            assert_eq!(parse_range(value, 10), RangeRequest::Full, "{:?} was not ignored", value);
+        }
+    }
+}
@@ -1,10 +1,78 @@
-use crate::display_remote::DLNAPlayer;
-use crate::upload::AppState;
-use std::sync::Arc;
-use tokio::sync::Mutex;
-
-#[derive(Clone)]
-pub struct AppContext {
-    pub app_state: Arc<AppState>,
-    pub dlna_player: Arc<Mutex<DLNAPlayer>>,
-}
+use crate::auth_failures::AuthFailures;
+use crate::config::SharedConfig;
+use crate::db_health::DbHealth;
+use crate::disk_space::DiskStatus;
+use crate::display_remote::DLNAPlayer;
+use crate::download_verify::Verifications;
+use crate::events::EventSender;
+use crate::hwaccel::HwEncoders;
+use crate::io_scheduler::IoScheduler;
+use crate::music_queue::MusicQueues;
+use crate::network_watch::LocalAddr;
+use crate::slideshow::Slideshows;
+use crate::supervisor::Supervisor;
+use crate::upload::AppState;
+use crate::web_push::WebPush;
+use std::sync::Arc;
+use tokio::sync::{Mutex, Notify};
+
+#[derive(Clone)]
+pub struct AppContext {
+    pub app_state: Arc<AppState>,
+    pub dlna_player: Arc<Mutex<DLNAPlayer>>,
+    pub config: SharedConfig,
+    pub db_health: Arc<DbHealth>,
+    pub supervisor: Arc<Supervisor>,
+    pub events: EventSender,
+    /// Wakes the outbox dispatcher after events were committed to the outbox
+    pub outbox: Arc<Notify>,
+    /// Limits concurrent merges, transcodes and integrity checks
+    pub io: Arc<IoScheduler>,
+    /// Hardware encoders found at startup, see `hwaccel`
+    pub encoders: Arc<HwEncoders>,
+    /// Free space of the storage volumes and whether uploads are paused
+    pub disk: Arc<DiskStatus>,
+    /// Digests of recent `?verify=true` downloads
+    pub verifications: Arc<Verifications>,
+    /// Slideshows running on renderers
+    pub slideshows: Arc<Slideshows>,
+    /// Music queues playing on renderers
+    pub music_queues: Arc<MusicQueues>,
+    /// LAN address the server is advertised on, for URLs handed to renderers
+    pub local_addr: LocalAddr,
+    /// None when the VAPID key couldn't be loaded
+    pub web_push: Option<Arc<WebPush>>,
+    /// Failed authentications and lockouts by client address and account
+    pub auth_failures: Arc<AuthFailures>,
+}
+
+#[cfg(test)]
+impl AppContext {
+    /// A context over a fresh in-memory database with every migration applied,
+    /// without renderers, push notifications or hardware encoders
+    pub async fn for_tests(mut config: crate::config::AppConfig) -> Self {
+        config.enable_dlna_remote = false;
+        let db_pool = crate::init_env::open_memory_db_pool().expect("in-memory database");
+        crate::init_env::bootstrap_schema(&db_pool).await.expect("schema");
+        let supervisor = Arc::new(Supervisor::default());
+        let dlna_player = crate::display_remote::DLNAPlayer::new(&config, db_pool.clone(), &supervisor).await;
+        Self {
+            app_state: Arc::new(AppState { db_pool, ..Default::default() }),
+            dlna_player: Arc::new(Mutex::new(dlna_player)),
+            io: Arc::new(IoScheduler::new(&config)),
+            config: SharedConfig::new(config),
+            db_health: Arc::default(),
+            supervisor,
+            events: crate::events::event_channel(),
+            outbox: Arc::new(Notify::new()),
+            encoders: Arc::default(),
+            disk: Arc::default(),
+            verifications: Arc::default(),
+            slideshows: Arc::default(),
+            music_queues: Arc::default(),
+            local_addr: crate::network_watch::local_addr_channel().1,
+            web_push: None,
+            auth_failures: Arc::default(),
+        }
+    }
+}
@@ -0,0 +1,144 @@
+use axum::{
+    extract::{Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use log::{error, info, warn};
+use sqlx::SqlitePool;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use crate::config::SharedConfig;
+use crate::context::AppContext;
+use crate::helper::error_response;
+use crate::init_env::bootstrap_schema;
+use crate::jobs::fail_interrupted_jobs;
+use crate::upload_progress::recover_upload_progress;
+use crate::usage::rebuild_usage_if_empty;
+
+const MIN_RETRY_DELAY: Duration = Duration::from_secs(1);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+/// How often an available database is checked
+const PING_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Whether the database answered the last check
+#[derive(Default)]
+pub struct DbHealth {
+    available: AtomicBool,
+    /// The startup work in `prepare_database` has run; it isn't repeated when
+    /// the database comes back or the monitor is restarted
+    prepared: AtomicBool,
+}
+
+impl DbHealth {
+    pub fn is_available(&self) -> bool {
+        self.available.load(Ordering::Relaxed)
+    }
+
+    fn set_available(&self, available: bool) {
+        self.available.store(available, Ordering::Relaxed);
+    }
+
+    fn is_prepared(&self) -> bool {
+        self.prepared.load(Ordering::Relaxed)
+    }
+
+    fn set_prepared(&self) {
+        self.prepared.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Create the schema and run the startup work that needs the database
+async fn prepare_database(db_pool: &SqlitePool, config: &SharedConfig) -> Result<(), String> {
+    bootstrap_schema(db_pool).await.map_err(|e| e.to_string())?;
+
+    let mut loaded = (*config.load()).clone();
+    match loaded.load_system_config(db_pool).await {
+        Ok(()) => config.store(loaded),
+        Err(e) => warn!("Using default chunk size: {}", e),
+    }
+    if let Err(e) = recover_upload_progress(db_pool, config.load().filename_policy).await {
+        warn!("Failed to recover upload progress: {}", e);
+    }
+    if let Err(e) = fail_interrupted_jobs(db_pool).await {
+        warn!("Failed to mark interrupted jobs: {}", e);
+    }
+    if let Err(e) = rebuild_usage_if_empty(db_pool).await {
+        warn!("Storage usage stats are unavailable: {}", e);
+    }
+    Ok(())
+}
+
+async fn ping(db_pool: &SqlitePool) -> Result<(), String> {
+    sqlx::query("SELECT 1 FROM system_config LIMIT 1")
+        .execute(db_pool)
+        .await
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+/// Prepare the database at startup. When it can't be opened the server still
+/// starts and `run_database_monitor` retries; API requests get 503 until it succeeds.
+pub async fn start_database(db_pool: &SqlitePool, health: &DbHealth, config: &SharedConfig) {
+    match prepare_database(db_pool, config).await {
+        Ok(()) => {
+            health.set_prepared();
+            health.set_available(true);
+        }
+        Err(e) => warn!("Database is unavailable, retrying in the background: {}", e),
+    }
+}
+
+/// Keep checking the database, retrying preparation with exponential backoff
+/// while it hasn't succeeded
+pub async fn run_database_monitor(db_pool: SqlitePool, health: Arc<DbHealth>, config: SharedConfig) {
+    let mut retry_delay = MIN_RETRY_DELAY;
+    loop {
+        let delay = if health.is_available() { PING_INTERVAL } else { retry_delay };
+        tokio::time::sleep(delay).await;
+
+        let result = if health.is_prepared() {
+            ping(&db_pool).await
+        } else {
+            prepare_database(&db_pool, &config).await
+        };
+        match result {
+            Ok(()) => {
+                if !health.is_available() {
+                    info!("Database is available");
+                }
+                health.set_prepared();
+                health.set_available(true);
+                retry_delay = MIN_RETRY_DELAY;
+            }
+            Err(e) => {
+                if health.is_available() {
+                    error!("Database became unavailable: {}", e);
+                } else {
+                    retry_delay = (retry_delay * 2).min(MAX_RETRY_DELAY);
+                    warn!("Database is still unavailable, next attempt in {:?}: {}", retry_delay, e);
+                }
+                health.set_available(false);
+            }
+        }
+    }
+}
+
+/// Answer 503 instead of running handlers that need the database while it is
+/// unavailable
+pub async fn require_database(
+    State(ctx): State<AppContext>,
+    req: Request,
+    next: Next,
+) -> Response {
+    if ctx.db_health.is_available() {
+        return next.run(req).await;
+    }
+    let response = error_response(
+        StatusCode::SERVICE_UNAVAILABLE,
+        "SERVICE_UNAVAILABLE",
+        "The database is unavailable, try again shortly".to_string(),
+    );
+    ([(header::RETRY_AFTER, "5")], response).into_response()
+}
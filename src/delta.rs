@@ -0,0 +1,576 @@
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use futures::StreamExt;
+use log::{error, info};
+use md5::{Digest, Md5};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::io::Read;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use tokio::fs::{self, File};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt, BufWriter, SeekFrom};
+use crate::context::AppContext;
+use crate::disk_space::disk_space;
+use crate::file_locks::{ensure_unlocked, lock_token};
+use crate::hashing::HashAlgorithm;
+use crate::helper::{error_response, ApiResponse};
+use crate::paths::{delta_temp_path, file_inode, long_path, path_to_string, UPLOADS_DIR};
+use crate::profiles::ensure_file_allowed;
+use crate::tenants::{check_quota, current_tenant};
+use crate::thumbnail::{generate_thumbnail, is_image_file, ThumbnailConfig};
+use crate::traffic::{client_principal, record_traffic};
+use crate::usage::record_file_usage;
+use crate::upload_dao::{fetch_uploaded_file_by_id, is_file_path_shared, update_file_content, update_file_meta_info, update_file_thumbnail_path, UploadedFile};
+
+pub const DEFAULT_BLOCK_SIZE: u64 = 64 * 1024;
+const MIN_BLOCK_SIZE: u64 = 1024;
+const MAX_BLOCK_SIZE: u64 = 16 * 1024 * 1024;
+/// Largest literal a single delta op may carry
+const MAX_LITERAL_LEN: usize = 16 * 1024 * 1024;
+
+/// Copy block `index` of the current file: `0x01` + u64 big-endian index
+pub const OP_COPY: u8 = 0x01;
+/// Literal bytes: `0x02` + u32 big-endian length + bytes
+pub const OP_DATA: u8 = 0x02;
+
+/// rsync-style weak checksum: a = Σ xᵢ, b = Σ (len − i)·xᵢ, both mod 2¹⁶, packed as `b << 16 | a`.
+/// Clients roll it one byte at a time with a' = a − out + in, b' = b − len·out + a'.
+pub fn weak_checksum(block: &[u8]) -> u32 {
+    let len = block.len() as u32;
+    let (mut a, mut b) = (0u32, 0u32);
+    for (i, &x) in block.iter().enumerate() {
+        a = a.wrapping_add(x as u32);
+        b = b.wrapping_add((len - i as u32).wrapping_mul(x as u32));
+    }
+    (a & 0xffff) | ((b & 0xffff) << 16)
+}
+
+#[derive(Debug, Serialize)]
+pub struct BlockSignature {
+    pub index: u64,
+    pub weak: u32,
+    pub strong: String,
+}
+
+/// Read until `buf` is full or EOF, returning the number of bytes read
+fn read_block(file: &mut std::fs::File, buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match file.read(&mut buf[filled..])? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+    Ok(filled)
+}
+
+fn compute_signatures(path: PathBuf, block_size: u64, algorithm: HashAlgorithm) -> std::io::Result<Vec<BlockSignature>> {
+    let mut file = std::fs::File::open(path)?;
+    let mut buf = vec![0u8; block_size as usize];
+    let mut signatures = Vec::new();
+    loop {
+        let n = read_block(&mut file, &mut buf)?;
+        if n == 0 {
+            break;
+        }
+        let block = &buf[..n];
+        let mut hasher = algorithm.hasher();
+        hasher.update(block);
+        signatures.push(BlockSignature {
+            index: signatures.len() as u64,
+            weak: weak_checksum(block),
+            strong: hasher.hex_digest(),
+        });
+        if n < buf.len() {
+            break;
+        }
+    }
+    Ok(signatures)
+}
+
+/// Completed file for `file_id`, or the error response to return
+async fn completed_file(ctx: &AppContext, client_addr: &SocketAddr, file_id: &str) -> Result<UploadedFile, Response> {
+    ensure_file_allowed(ctx, client_addr, file_id).await?;
+    match fetch_uploaded_file_by_id(&ctx.app_state.db_pool, file_id).await {
+        Ok(Some(file)) if file.status == 2 => Ok(file),
+        Ok(Some(_)) => Err(error_response(StatusCode::CONFLICT, "FILE_NOT_COMPLETE", "File upload has not completed".to_string())),
+        Ok(None) => Err(error_response(StatusCode::NOT_FOUND, "FILE_NOT_FOUND", "File not found".to_string())),
+        Err(e) => Err(error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_FILE_RECORD_ERROR", e)),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct SignatureQuery {
+    block_size: Option<u64>,
+}
+
+/// Block signatures of a completed file, for clients preparing a delta upload
+pub async fn get_signatures(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    Path(file_id): Path<String>,
+    Query(query): Query<SignatureQuery>,
+) -> impl IntoResponse {
+    let file = match completed_file(&ctx, &client_addr, &file_id).await {
+        Ok(file) => file,
+        Err(resp) => return resp,
+    };
+    let block_size = query.block_size.unwrap_or(DEFAULT_BLOCK_SIZE).clamp(MIN_BLOCK_SIZE, MAX_BLOCK_SIZE);
+    let algorithm = ctx.config.load().hash_algorithm;
+
+    // 大文件的哈希计算放到阻塞线程池
+    let path = long_path(std::path::Path::new(&file.file_path));
+    let signatures = match tokio::task::spawn_blocking(move || compute_signatures(path, block_size, algorithm)).await {
+        Ok(Ok(signatures)) => signatures,
+        Ok(Err(e)) => {
+            error!("Failed to compute block signatures for {}: {}", file_id, e);
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "SIGNATURE_ERROR", "Failed to read file".to_string());
+        }
+        Err(e) => {
+            error!("Signature task failed for {}: {}", file_id, e);
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "SIGNATURE_ERROR", "Failed to compute signatures".to_string());
+        }
+    };
+
+    (StatusCode::OK, Json(ApiResponse::success(json!({
+        "file_id": file_id,
+        "total_size": file.total_size,
+        "checksum": file.checksum,
+        "block_size": block_size,
+        "hash_algorithm": algorithm.as_str(),
+        "blocks": signatures,
+    })))).into_response()
+}
+
+enum DeltaOp<'a> {
+    Copy(u64),
+    Data(&'a [u8]),
+}
+
+/// Decode the op at the front of `buf`; None when more bytes are needed
+fn next_op(buf: &[u8]) -> Result<Option<(DeltaOp<'_>, usize)>, String> {
+    match buf.first() {
+        None => Ok(None),
+        Some(&OP_COPY) => {
+            if buf.len() < 9 {
+                return Ok(None);
+            }
+            let index = u64::from_be_bytes(buf[1..9].try_into().expect("8 bytes"));
+            Ok(Some((DeltaOp::Copy(index), 9)))
+        }
+        Some(&OP_DATA) => {
+            if buf.len() < 5 {
+                return Ok(None);
+            }
+            let len = u32::from_be_bytes(buf[1..5].try_into().expect("4 bytes")) as usize;
+            if len > MAX_LITERAL_LEN {
+                return Err(format!("Literal of {} bytes exceeds the {} byte limit", len, MAX_LITERAL_LEN));
+            }
+            if buf.len() < 5 + len {
+                return Ok(None);
+            }
+            Ok(Some((DeltaOp::Data(&buf[5..5 + len]), 5 + len)))
+        }
+        Some(other) => Err(format!("Unknown delta op 0x{:02x}", other)),
+    }
+}
+
+type DeltaError = (StatusCode, &'static str, String);
+
+fn invalid_delta(message: String) -> DeltaError {
+    (StatusCode::BAD_REQUEST, "INVALID_DELTA", message)
+}
+
+/// Writes the new version of a file from copy/literal ops against the old one
+struct DeltaWriter {
+    base: File,
+    base_size: u64,
+    block_size: u64,
+    out: BufWriter<File>,
+    md5: Md5,
+    written: u64,
+    /// Size the client declared for the new version; copies can expand a
+    /// tiny body into many blocks, so nothing past it is written
+    limit: u64,
+}
+
+impl DeltaWriter {
+    async fn apply(&mut self, op: DeltaOp<'_>) -> Result<(), DeltaError> {
+        match op {
+            DeltaOp::Copy(index) => {
+                let offset = index.checked_mul(self.block_size).filter(|o| *o < self.base_size)
+                    .ok_or_else(|| invalid_delta(format!("Block {} is past the end of the file", index)))?;
+                let len = self.block_size.min(self.base_size - offset);
+                self.ensure_room(len)?;
+                let mut block = vec![0u8; len as usize];
+                self.base.seek(SeekFrom::Start(offset)).await.map_err(|e| invalid_delta(e.to_string()))?;
+                self.base.read_exact(&mut block).await.map_err(|e| invalid_delta(e.to_string()))?;
+                self.write(&block).await
+            }
+            DeltaOp::Data(bytes) => {
+                self.ensure_room(bytes.len() as u64)?;
+                self.write(bytes).await
+            }
+        }
+    }
+
+    fn ensure_room(&self, len: u64) -> Result<(), DeltaError> {
+        if self.written + len > self.limit {
+            return Err((StatusCode::PAYLOAD_TOO_LARGE, "DELTA_TOO_LARGE", format!(
+                "Rebuilt file grows past the declared {} bytes", self.limit
+            )));
+        }
+        Ok(())
+    }
+
+    async fn write(&mut self, bytes: &[u8]) -> Result<(), DeltaError> {
+        self.md5.update(bytes);
+        self.written += bytes.len() as u64;
+        self.out.write_all(bytes).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, "DELTA_WRITE_ERROR", e.to_string()))
+    }
+}
+
+/// Rebuild the file into `temp_path`, returning (md5, size, bytes received).
+/// The rebuilt file may not grow past `limit` bytes.
+async fn apply_delta(base_path: PathBuf, temp_path: PathBuf, block_size: u64, limit: u64, body: Body) -> Result<(String, u64, u64), DeltaError> {
+    let io_error = |e: std::io::Error| (StatusCode::INTERNAL_SERVER_ERROR, "DELTA_WRITE_ERROR", e.to_string());
+    let base = File::open(&base_path).await.map_err(io_error)?;
+    let base_size = base.metadata().await.map_err(io_error)?.len();
+    let out = File::create(&temp_path).await.map_err(io_error)?;
+    let mut writer = DeltaWriter {
+        base,
+        base_size,
+        block_size,
+        out: BufWriter::new(out),
+        md5: Md5::new(),
+        written: 0,
+        limit,
+    };
+
+    let mut received = 0u64;
+    let mut pending: Vec<u8> = Vec::new();
+    let mut stream = body.into_data_stream();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|e| (StatusCode::BAD_REQUEST, "PAYLOAD_ERROR", e.to_string()))?;
+        received += chunk.len() as u64;
+        pending.extend_from_slice(&chunk);
+
+        let mut pos = 0;
+        while let Some((op, used)) = next_op(&pending[pos..]).map_err(invalid_delta)? {
+            writer.apply(op).await?;
+            pos += used;
+        }
+        pending.drain(..pos);
+    }
+    if !pending.is_empty() {
+        return Err(invalid_delta("Delta stream ends in the middle of an op".to_string()));
+    }
+    if writer.written != limit {
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, "FILE_SIZE_MISMATCH", format!(
+            "Rebuilt file has {} bytes but {} were declared", writer.written, limit
+        )));
+    }
+
+    writer.out.flush().await.map_err(io_error)?;
+    writer.out.get_ref().sync_all().await.map_err(io_error)?;
+    Ok((format!("{:x}", writer.md5.finalize()), writer.written, received))
+}
+
+/// Replace a completed file by applying a delta against its current content.
+/// The body is a stream of ops (see `OP_COPY` / `OP_DATA`) built from `get_signatures`.
+pub async fn upload_delta(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    Path(file_id): Path<String>,
+    headers: HeaderMap,
+    body: Body,
+) -> impl IntoResponse {
+    let db_pool = &ctx.app_state.db_pool;
+
+    let block_size = match headers.get("X-Block-Size").and_then(|v| v.to_str().ok()).and_then(|v| v.parse::<u64>().ok()) {
+        Some(size) if (MIN_BLOCK_SIZE..=MAX_BLOCK_SIZE).contains(&size) => size,
+        _ => return error_response(StatusCode::BAD_REQUEST, "INVALID_BLOCK_SIZE", format!(
+            "X-Block-Size must be between {} and {}", MIN_BLOCK_SIZE, MAX_BLOCK_SIZE
+        )),
+    };
+    let expected_checksum = match headers.get("X-File-Checksum").and_then(|v| v.to_str().ok()) {
+        Some(checksum) if !checksum.trim().is_empty() => checksum.trim().to_lowercase(),
+        _ => return error_response(StatusCode::BAD_REQUEST, "MISSING_CHECKSUM", "X-File-Checksum header is required".to_string()),
+    };
+    let Some(new_size) = headers.get("X-File-Size").and_then(|v| v.to_str().ok()).and_then(|v| v.trim().parse::<u64>().ok()) else {
+        return error_response(StatusCode::BAD_REQUEST, "MISSING_FILE_SIZE", "X-File-Size header is required".to_string());
+    };
+
+    let file = match completed_file(&ctx, &client_addr, &file_id).await {
+        Ok(file) => file,
+        Err(resp) => return resp,
+    };
+    if let Err(resp) = ensure_unlocked(db_pool, &file.file_path, lock_token(&headers)).await {
+        return resp;
+    }
+    // 共用路径的文件被替换后其他记录的内容也会改变
+    match is_file_path_shared(db_pool, &file.file_path, &file_id).await {
+        Ok(false) => {}
+        Ok(true) => return error_response(StatusCode::CONFLICT, "FILE_SHARED", "File content is shared with other uploads and can't be patched in place".to_string()),
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_FILE_RECORD_ERROR", e),
+    }
+    // 新版本只有增长的部分占用配额，临时文件则需要完整大小的剩余空间
+    if let Some(tenant) = current_tenant() {
+        if let Err((code, message)) = check_quota(db_pool, &tenant, new_size.saturating_sub(file.total_size.max(0) as u64)).await {
+            let status = if code == "QUOTA_EXCEEDED" { StatusCode::PAYLOAD_TOO_LARGE } else { StatusCode::INTERNAL_SERVER_ERROR };
+            return error_response(status, code, message);
+        }
+    }
+    if let Ok(space) = disk_space(std::path::Path::new(UPLOADS_DIR)) {
+        if space.available < new_size {
+            return error_response(StatusCode::INSUFFICIENT_STORAGE, "INSUFFICIENT_SPACE", format!(
+                "The rebuilt file needs {} bytes but only {} are free", new_size, space.available
+            ));
+        }
+    }
+
+    let stored_path = std::path::Path::new(&file.file_path).to_path_buf();
+    let temp_path = long_path(&delta_temp_path(&file.filename));
+    let result = apply_delta(long_path(&stored_path), temp_path.clone(), block_size, new_size, body).await;
+    let (checksum, total_size, received) = match result {
+        Ok(done) => done,
+        Err((status, code, message)) => {
+            let _ = fs::remove_file(&temp_path).await;
+            error!("Delta upload for {} failed: {}", file_id, message);
+            return error_response(status, code, message);
+        }
+    };
+    record_traffic(db_pool, &client_principal(&client_addr), received, 0).await;
+
+    if checksum != expected_checksum {
+        let _ = fs::remove_file(&temp_path).await;
+        return error_response(StatusCode::UNPROCESSABLE_ENTITY, "FILE_CHECKSUM_MISMATCH", format!(
+            "Rebuilt file has MD5 {} but {} was expected", checksum, expected_checksum
+        ));
+    }
+
+    if let Err(e) = fs::rename(&temp_path, long_path(&stored_path)).await {
+        error!("Failed to replace {} with rebuilt file: {}", file.file_path, e);
+        let _ = fs::remove_file(&temp_path).await;
+        return error_response(StatusCode::INTERNAL_SERVER_ERROR, "DELTA_WRITE_ERROR", "Failed to replace file".to_string());
+    }
+
+    // 大小变化计入占用统计：先减去旧记录，更新后再加回
+    record_file_usage(db_pool, &file_id, -1).await;
+    let updated = update_file_content(db_pool, &file_id, &checksum, total_size).await;
+    record_file_usage(db_pool, &file_id, 1).await;
+    if let Err(e) = updated {
+        return error_response(StatusCode::INTERNAL_SERVER_ERROR, "UPDATE_FILE_ERROR", e);
+    }
+    if let Ok(metadata) = fs::metadata(long_path(&stored_path)).await {
+        let file_mtime = metadata.modified()
+            .map(|t| t.duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_secs() as i64)
+            .unwrap_or(0);
+        let file_ctime = metadata.created()
+            .map(|t| t.duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_secs() as i64)
+            .unwrap_or(file_mtime);
+        if let Err(e) = update_file_meta_info(db_pool, &file_id, file_mtime, file_ctime, file_inode(&metadata).unwrap_or(0)).await {
+            error!("Failed to update file meta info: {}", e);
+        }
+    }
+
+    // 缩略图以内容哈希命名，内容变化后重新生成
+    if is_image_file(&file.filename) {
+        if let Some(thumbnail_path) = generate_thumbnail(&ThumbnailConfig::default(), &path_to_string(&stored_path), &checksum).await {
+            if let Err(e) = update_file_thumbnail_path(db_pool, &file_id, &thumbnail_path).await {
+                error!("Failed to save thumbnail path to database: {}", e);
+            }
+        }
+    }
+
+    info!("Delta upload for {} rebuilt {} bytes from {} bytes received", file_id, total_size, received);
+    (StatusCode::OK, Json(ApiResponse::success(json!({
+        "file_id": file_id,
+        "size": total_size,
+        "checksum": checksum,
+        "bytes_received": received,
+    })))).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_file(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("nascraft-delta-{}-{}", name, uuid::Uuid::new_v4().simple()))
+    }
+
+    async fn writer(base: &[u8], block_size: u64, limit: u64) -> (DeltaWriter, PathBuf, PathBuf) {
+        let base_path = temp_file("base");
+        let out_path = temp_file("out");
+        fs::write(&base_path, base).await.unwrap();
+        let writer = DeltaWriter {
+            base: File::open(&base_path).await.unwrap(),
+            base_size: base.len() as u64,
+            block_size,
+            out: BufWriter::new(File::create(&out_path).await.unwrap()),
+            md5: Md5::new(),
+            written: 0,
+            limit,
+        };
+        (writer, base_path, out_path)
+    }
+
+    fn copy_op(index: u64) -> Vec<u8> {
+        let mut op = vec![OP_COPY];
+        op.extend_from_slice(&index.to_be_bytes());
+        op
+    }
+
+    fn data_op(bytes: &[u8]) -> Vec<u8> {
+        let mut op = vec![OP_DATA];
+        op.extend_from_slice(&(bytes.len() as u32).to_be_bytes());
+        op.extend_from_slice(bytes);
+        op
+    }
+
+    #[test]
+    fn weak_checksum_packs_both_sums() {
+        // a = 97 + 98 + 99，b = 3·97 + 2·98 + 1·99
+        assert_eq!(weak_checksum(b"abc"), (586 << 16) | 294);
+        assert_eq!(weak_checksum(b""), 0);
+        // 两个分量都按 2¹⁶ 取模
+        let block = vec![0xffu8; 1024];
+        assert_eq!(weak_checksum(&block) & 0xffff, (1024 * 255) % 65536);
+    }
+
+    #[test]
+    fn weak_checksum_can_be_rolled() {
+        let data: Vec<u8> = (0..4096u32).map(|i| (i.wrapping_mul(2654435761) >> 13) as u8).collect();
+        let len = 64;
+        let first = weak_checksum(&data[..len]);
+        let (mut a, mut b) = (first & 0xffff, first >> 16);
+        for start in 1..=data.len() - len {
+            let out = data[start - 1] as u32;
+            let new = data[start + len - 1] as u32;
+            a = a.wrapping_sub(out).wrapping_add(new) & 0xffff;
+            b = b.wrapping_sub((len as u32).wrapping_mul(out)).wrapping_add(a) & 0xffff;
+            assert_eq!((b << 16) | a, weak_checksum(&data[start..start + len]), "window at {}", start);
+        }
+    }
+
+    #[test]
+    fn next_op_waits_for_a_complete_op() {
+        assert!(next_op(&[]).unwrap().is_none());
+        assert!(next_op(&copy_op(7)[..8]).unwrap().is_none());
+        assert!(next_op(&[OP_DATA, 0, 0]).unwrap().is_none());
+        let data = data_op(b"abc");
+        assert!(next_op(&data[..data.len() - 1]).unwrap().is_none());
+    }
+
+    #[test]
+    fn next_op_decodes_copies_and_literals() {
+        let mut buf = copy_op(0x0102_0304_0506_0708);
+        buf.extend(data_op(b"abc"));
+        let (op, used) = next_op(&buf).unwrap().unwrap();
+        assert!(matches!(op, DeltaOp::Copy(0x0102_0304_0506_0708)));
+        assert_eq!(used, 9);
+        let (op, used) = next_op(&buf[9..]).unwrap().unwrap();
+        assert!(matches!(op, DeltaOp::Data(b"abc")));
+        assert_eq!(used, 8);
+
+        let empty = data_op(b"");
+        let (op, used) = next_op(&empty).unwrap().unwrap();
+        assert!(matches!(op, DeltaOp::Data(b"")));
+        assert_eq!(used, 5);
+    }
+
+    #[test]
+    fn next_op_rejects_unknown_ops_and_huge_literals() {
+        assert_eq!(next_op(&[0x03, 0, 0]).err().unwrap(), "Unknown delta op 0x03");
+        // 长度头超限时立即拒绝，不等数据到齐
+        let mut huge = vec![OP_DATA];
+        huge.extend_from_slice(&(MAX_LITERAL_LEN as u32 + 1).to_be_bytes());
+        assert!(next_op(&huge).is_err());
+        let mut max = vec![OP_DATA];
+        max.extend_from_slice(&(MAX_LITERAL_LEN as u32).to_be_bytes());
+        assert!(next_op(&max).unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn writer_copies_blocks_and_literals() {
+        let (mut writer, base_path, out_path) = writer(b"0123456789", 4, 8).await;
+        // 最后一块不足 block_size
+        writer.apply(DeltaOp::Copy(2)).await.unwrap();
+        writer.apply(DeltaOp::Copy(0)).await.unwrap();
+        writer.apply(DeltaOp::Data(b"xy")).await.unwrap();
+        writer.out.flush().await.unwrap();
+
+        assert_eq!(writer.written, 8);
+        assert_eq!(fs::read(&out_path).await.unwrap(), b"890123xy");
+        assert_eq!(format!("{:x}", writer.md5.finalize()), format!("{:x}", Md5::digest(b"890123xy")));
+        let _ = fs::remove_file(base_path).await;
+        let _ = fs::remove_file(out_path).await;
+    }
+
+    #[tokio::test]
+    async fn writer_rejects_blocks_past_the_end() {
+        let (mut writer, base_path, out_path) = writer(b"0123456789", 4, 100).await;
+        for index in [3, u64::MAX] {
+            let (status, code, _) = writer.apply(DeltaOp::Copy(index)).await.unwrap_err();
+            assert_eq!((status, code), (StatusCode::BAD_REQUEST, "INVALID_DELTA"));
+        }
+        assert_eq!(writer.written, 0);
+        let _ = fs::remove_file(base_path).await;
+        let _ = fs::remove_file(out_path).await;
+    }
+
+    #[tokio::test]
+    async fn writer_stops_at_the_declared_size() {
+        let (mut writer, base_path, out_path) = writer(b"0123456789", 4, 6).await;
+        writer.apply(DeltaOp::Copy(0)).await.unwrap();
+        let (status, code, _) = writer.apply(DeltaOp::Copy(1)).await.unwrap_err();
+        assert_eq!((status, code), (StatusCode::PAYLOAD_TOO_LARGE, "DELTA_TOO_LARGE"));
+        let (_, code, _) = writer.apply(DeltaOp::Data(b"abc")).await.unwrap_err();
+        assert_eq!(code, "DELTA_TOO_LARGE");
+        writer.apply(DeltaOp::Data(b"ab")).await.unwrap();
+        writer.out.flush().await.unwrap();
+
+        assert_eq!(fs::read(&out_path).await.unwrap(), b"0123ab");
+        let _ = fs::remove_file(base_path).await;
+        let _ = fs::remove_file(out_path).await;
+    }
+
+    #[tokio::test]
+    async fn apply_delta_checks_the_declared_size() {
+        let base_path = temp_file("base");
+        fs::write(&base_path, b"0123456789").await.unwrap();
+        let mut ops = copy_op(1);
+        ops.extend(data_op(b"!"));
+
+        let out_path = temp_file("out");
+        let (checksum, size, received) = apply_delta(base_path.clone(), out_path.clone(), 4, 5, Body::from(ops.clone())).await.unwrap();
+        assert_eq!(fs::read(&out_path).await.unwrap(), b"4567!");
+        assert_eq!(checksum, format!("{:x}", Md5::digest(b"4567!")));
+        assert_eq!((size, received), (5, ops.len() as u64));
+
+        let short = temp_file("out");
+        let (status, code, _) = apply_delta(base_path.clone(), short.clone(), 4, 6, Body::from(ops.clone())).await.unwrap_err();
+        assert_eq!((status, code), (StatusCode::UNPROCESSABLE_ENTITY, "FILE_SIZE_MISMATCH"));
+
+        // 大量重复的 copy 不能超过声明的大小
+        let bomb: Vec<u8> = (0..1000).flat_map(|_| copy_op(0)).collect();
+        let capped = temp_file("out");
+        let (_, code, _) = apply_delta(base_path.clone(), capped.clone(), 4, 40, Body::from(bomb)).await.unwrap_err();
+        assert_eq!(code, "DELTA_TOO_LARGE");
+        assert!(fs::metadata(&capped).await.unwrap().len() <= 40);
+
+        let truncated = temp_file("out");
+        let (_, code, _) = apply_delta(base_path.clone(), truncated.clone(), 4, 5, Body::from(ops[..ops.len() - 1].to_vec())).await.unwrap_err();
+        assert_eq!(code, "INVALID_DELTA");
+        for path in [base_path, out_path, short, capped, truncated] {
+            let _ = fs::remove_file(path).await;
+        }
+    }
+}
@@ -0,0 +1,141 @@
+//! What the server made from a stored file: its thumbnail, its transcode,
+//! audio extracted from it and the files unpacked from it. Each is recorded in
+//! `derived_files` against its source, listed with the source's record, and
+//! cleaned up when the source is deleted.
+
+use log::{error, warn};
+use serde::Serialize;
+use sqlx::{FromRow, Sqlite, SqlitePool};
+use crate::paths::long_path;
+
+pub const KIND_THUMBNAIL: &str = "thumbnail";
+pub const KIND_TRANSCODE: &str = "transcode";
+/// Audio saved as its own file by `extract_audio`
+pub const KIND_AUDIO: &str = "audio";
+/// A file unpacked from an archive by `extract`
+pub const KIND_EXTRACTED: &str = "extracted";
+
+/// Where a new file comes from, see `extract::Placement`
+pub struct DerivedFrom {
+    pub source_id: String,
+    pub kind: &'static str,
+    pub params: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct DerivedFile {
+    pub id: i64,
+    pub kind: String,
+    /// How it was made, e.g. `{"format": "mp4"}`
+    #[serde(skip)]
+    #[sqlx(rename = "params")]
+    params_json: Option<String>,
+    #[sqlx(skip)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub params: Option<serde_json::Value>,
+    pub path: String,
+    /// The derived file's own record, for those registered as files
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub derived_file_id: Option<String>,
+    #[serde(with = "crate::api::timestamp")]
+    pub created_at: i64,
+}
+
+fn parse_params(mut derived: DerivedFile) -> DerivedFile {
+    derived.params = derived.params_json.as_deref().and_then(|p| serde_json::from_str(p).ok());
+    derived
+}
+
+/// Record an artifact of `source_id`
+pub async fn record_derived_file<'e, E: sqlx::Executor<'e, Database = Sqlite>>(
+    executor: E,
+    source_id: &str,
+    kind: &str,
+    params: Option<&serde_json::Value>,
+    path: &str,
+    derived_file_id: Option<&str>,
+) -> Result<(), String> {
+    sqlx::query(
+        "INSERT INTO derived_files (source_id, kind, params, path, derived_file_id, created_at) VALUES (?, ?, ?, ?, ?, strftime('%s', 'now'))
+         ON CONFLICT(source_id, kind, path) DO UPDATE SET params = excluded.params, derived_file_id = excluded.derived_file_id, created_at = excluded.created_at"
+    )
+    .bind(source_id)
+    .bind(kind)
+    .bind(params.map(|p| p.to_string()))
+    .bind(path)
+    .bind(derived_file_id)
+    .execute(executor)
+    .await
+    .map(|_| ())
+    .map_err(|e| {
+        error!("Failed to record {} of file ID {}: {}", kind, source_id, e);
+        "Failed to record derived file".to_string()
+    })
+}
+
+/// Record the one artifact of `kind` a file has, such as its thumbnail or
+/// transcode, in place of the previous one
+pub async fn replace_derived_file(db_pool: &SqlitePool, source_id: &str, kind: &str, params: Option<&serde_json::Value>, path: &str) -> Result<(), String> {
+    remove_derived_files(db_pool, source_id, kind).await?;
+    record_derived_file(db_pool, source_id, kind, params, path, None).await
+}
+
+/// Forget the artifacts of `source_id` of `kind`, e.g. an evicted transcode
+pub async fn remove_derived_files(db_pool: &SqlitePool, source_id: &str, kind: &str) -> Result<(), String> {
+    sqlx::query("DELETE FROM derived_files WHERE source_id = ? AND kind = ?")
+        .bind(source_id)
+        .bind(kind)
+        .execute(db_pool)
+        .await
+        .map(|_| ())
+        .map_err(|e| {
+            error!("Failed to remove {} of file ID {}: {}", kind, source_id, e);
+            "Failed to remove derived file".to_string()
+        })
+}
+
+/// What was made from `source_id`, oldest first
+pub async fn fetch_derived_files(db_pool: &SqlitePool, source_id: &str) -> Result<Vec<DerivedFile>, String> {
+    sqlx::query_as::<_, DerivedFile>(
+        "SELECT id, kind, params, path, derived_file_id, created_at FROM derived_files WHERE source_id = ? ORDER BY created_at, id"
+    )
+    .bind(source_id)
+    .fetch_all(db_pool)
+    .await
+    .map(|rows| rows.into_iter().map(parse_params).collect())
+    .map_err(|e| {
+        error!("Failed to fetch derived files of file ID {}: {}", source_id, e);
+        "Failed to fetch derived files".to_string()
+    })
+}
+
+/// Remove the artifacts of a deleted file that aren't files of their own.
+/// Files registered from it (extracted audio, unpacked entries) are kept as
+/// standalone files; other uses of a shared path, such as a thumbnail of
+/// identical content, keep theirs.
+pub async fn clean_up_derived_files(db_pool: &SqlitePool, derived: &[DerivedFile]) {
+    for artifact in derived.iter().filter(|d| d.derived_file_id.is_none()) {
+        // 缩略图按内容校验和命名，内容相同的文件共用同一个
+        let still_used = sqlx::query_scalar::<_, i64>(
+            "SELECT (SELECT COUNT(*) FROM derived_files WHERE path = ?)
+                  + (SELECT COUNT(*) FROM upload_file_meta WHERE thumbnail_path = ? OR file_path = ?)"
+        )
+        .bind(&artifact.path)
+        .bind(&artifact.path)
+        .bind(&artifact.path)
+        .fetch_one(db_pool)
+        .await;
+        match still_used {
+            Ok(0) => {}
+            Ok(_) => continue,
+            Err(e) => {
+                error!("Failed to check references to {}: {}", artifact.path, e);
+                continue;
+            }
+        }
+        match tokio::fs::remove_file(long_path(std::path::Path::new(&artifact.path))).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => warn!("Failed to remove {}: {}", artifact.path, e),
+            _ => {}
+        }
+    }
+}
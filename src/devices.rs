@@ -0,0 +1,298 @@
+use axum::{
+    extract::{ConnectInfo, Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, SqlitePool};
+use std::net::{Ipv4Addr, SocketAddr};
+use tokio::net::UdpSocket;
+use crate::context::AppContext;
+use crate::helper::{error_response, ApiResponse};
+use crate::profiles::ensure_unrestricted;
+
+/// Port magic packets are sent to (discard); network cards listen regardless of port
+const WOL_PORT: u16 = 9;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceKind {
+    /// A TV or other DLNA renderer
+    Renderer,
+    /// Another server, e.g. a backup target
+    Server,
+}
+
+impl DeviceKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            DeviceKind::Renderer => "renderer",
+            DeviceKind::Server => "server",
+        }
+    }
+
+    fn parse(value: &str) -> Self {
+        match value {
+            "server" => DeviceKind::Server,
+            _ => DeviceKind::Renderer,
+        }
+    }
+}
+
+#[derive(Debug, FromRow)]
+struct DeviceRow {
+    id: i64,
+    name: String,
+    kind: String,
+    mac_address: String,
+    broadcast_address: Option<String>,
+    created_at: i64,
+}
+
+/// A device that can be woken with a Wake-on-LAN magic packet
+#[derive(Debug, Clone, Serialize)]
+pub struct Device {
+    pub id: i64,
+    pub name: String,
+    pub kind: DeviceKind,
+    pub mac_address: String,
+    /// Where the magic packet is sent, 255.255.255.255 when unset
+    pub broadcast_address: Option<String>,
+    #[serde(with = "crate::api::timestamp")]
+    pub created_at: i64,
+}
+
+impl From<DeviceRow> for Device {
+    fn from(row: DeviceRow) -> Self {
+        Device {
+            id: row.id,
+            name: row.name,
+            kind: DeviceKind::parse(&row.kind),
+            mac_address: row.mac_address,
+            broadcast_address: row.broadcast_address,
+            created_at: row.created_at,
+        }
+    }
+}
+
+const DEVICE_COLUMNS: &str = "id, name, kind, mac_address, broadcast_address, created_at";
+
+pub async fn fetch_devices(db_pool: &SqlitePool) -> Result<Vec<Device>, String> {
+    sqlx::query_as::<_, DeviceRow>(&format!("SELECT {} FROM devices ORDER BY name", DEVICE_COLUMNS))
+        .fetch_all(db_pool)
+        .await
+        .map(|rows| rows.into_iter().map(Device::from).collect())
+        .map_err(|e| {
+            error!("Failed to fetch devices: {}", e);
+            "Failed to fetch devices".to_string()
+        })
+}
+
+pub async fn fetch_device(db_pool: &SqlitePool, id: i64) -> Result<Option<Device>, String> {
+    sqlx::query_as::<_, DeviceRow>(&format!("SELECT {} FROM devices WHERE id = ?", DEVICE_COLUMNS))
+        .bind(id)
+        .fetch_optional(db_pool)
+        .await
+        .map(|row| row.map(Device::from))
+        .map_err(|e| {
+            error!("Failed to fetch device: {}", e);
+            "Failed to fetch device".to_string()
+        })
+}
+
+/// Parse a MAC address written as `aa:bb:cc:dd:ee:ff`, `aa-bb-cc-dd-ee-ff` or `aabbccddeeff`
+fn parse_mac(value: &str) -> Option<[u8; 6]> {
+    let hex: String = value.chars().filter(|c| *c != ':' && *c != '-').collect();
+    if hex.len() != 12 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let mut mac = [0u8; 6];
+    for (i, byte) in mac.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(mac)
+}
+
+fn format_mac(mac: &[u8; 6]) -> String {
+    mac.iter().map(|b| format!("{:02x}", b)).collect::<Vec<_>>().join(":")
+}
+
+/// Six 0xFF bytes followed by the MAC address repeated 16 times
+fn magic_packet(mac: &[u8; 6]) -> Vec<u8> {
+    let mut packet = vec![0xFF; 6];
+    for _ in 0..16 {
+        packet.extend_from_slice(mac);
+    }
+    packet
+}
+
+/// Broadcast a magic packet for `device`
+pub async fn send_magic_packet(device: &Device) -> Result<SocketAddr, String> {
+    let mac = parse_mac(&device.mac_address)
+        .ok_or_else(|| format!("Invalid MAC address '{}'", device.mac_address))?;
+    let broadcast = match &device.broadcast_address {
+        Some(addr) => addr.parse::<Ipv4Addr>().map_err(|_| format!("Invalid broadcast address '{}'", addr))?,
+        None => Ipv4Addr::BROADCAST,
+    };
+    let target = SocketAddr::from((broadcast, WOL_PORT));
+
+    let sock = UdpSocket::bind("0.0.0.0:0").await.map_err(|e| {
+        error!("Wake-on-LAN bind failed: {}", e);
+        "Failed to open a socket for the magic packet".to_string()
+    })?;
+    sock.set_broadcast(true).map_err(|e| {
+        error!("Wake-on-LAN set_broadcast failed: {}", e);
+        "Failed to enable broadcast".to_string()
+    })?;
+    sock.send_to(&magic_packet(&mac), target).await.map_err(|e| {
+        error!("Wake-on-LAN send to {} failed: {}", target, e);
+        format!("Failed to send the magic packet to {}", target)
+    })?;
+    Ok(target)
+}
+
+fn invalid_device(message: String) -> axum::response::Response {
+    error_response(StatusCode::BAD_REQUEST, "INVALID_DEVICE", message)
+}
+
+fn device_not_found() -> axum::response::Response {
+    error_response(StatusCode::NOT_FOUND, "DEVICE_NOT_FOUND", "Device not found".to_string())
+}
+
+pub async fn list_devices(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+) -> impl IntoResponse {
+    if let Err(resp) = ensure_unrestricted(&ctx, &client_addr).await {
+        return resp;
+    }
+    match fetch_devices(&ctx.app_state.db_pool).await {
+        Ok(devices) => (StatusCode::OK, Json(ApiResponse::success(devices))).into_response(),
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_DEVICES_ERROR", e),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CreateDeviceRequest {
+    name: String,
+    #[serde(default = "default_kind")]
+    kind: DeviceKind,
+    mac_address: String,
+    broadcast_address: Option<String>,
+}
+
+fn default_kind() -> DeviceKind {
+    DeviceKind::Renderer
+}
+
+pub async fn create_device(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    Json(req): Json<CreateDeviceRequest>,
+) -> impl IntoResponse {
+    if let Err(resp) = ensure_unrestricted(&ctx, &client_addr).await {
+        return resp;
+    }
+
+    let name = req.name.trim().to_string();
+    if name.is_empty() {
+        return invalid_device("Device name must not be empty".to_string());
+    }
+    let Some(mac) = parse_mac(req.mac_address.trim()) else {
+        return invalid_device(format!("Invalid MAC address '{}'", req.mac_address));
+    };
+    let mac_address = format_mac(&mac);
+    let broadcast_address = req.broadcast_address.map(|a| a.trim().to_string()).filter(|a| !a.is_empty());
+    if let Some(addr) = &broadcast_address {
+        if addr.parse::<Ipv4Addr>().is_err() {
+            return invalid_device(format!("Invalid broadcast address '{}', expected an IPv4 address", addr));
+        }
+    }
+
+    let result = sqlx::query(
+        "INSERT INTO devices (name, kind, mac_address, broadcast_address, created_at) VALUES (?, ?, ?, ?, strftime('%s', 'now'))"
+    )
+    .bind(&name)
+    .bind(req.kind.as_str())
+    .bind(&mac_address)
+    .bind(&broadcast_address)
+    .execute(&ctx.app_state.db_pool)
+    .await;
+
+    match result {
+        Ok(done) => {
+            info!("Added device '{}' ({})", name, mac_address);
+            (StatusCode::OK, Json(ApiResponse::success(Device {
+                id: done.last_insert_rowid(),
+                name,
+                kind: req.kind,
+                mac_address,
+                broadcast_address,
+                created_at: chrono::Utc::now().timestamp(),
+            }))).into_response()
+        }
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => error_response(
+            StatusCode::CONFLICT,
+            "DEVICE_EXISTS",
+            format!("A device named '{}' already exists", name),
+        ),
+        Err(e) => {
+            error!("Failed to create device: {}", e);
+            error_response(StatusCode::INTERNAL_SERVER_ERROR, "CREATE_DEVICE_ERROR", "Failed to create device".to_string())
+        }
+    }
+}
+
+pub async fn delete_device(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    Path(id): Path<i64>,
+) -> impl IntoResponse {
+    if let Err(resp) = ensure_unrestricted(&ctx, &client_addr).await {
+        return resp;
+    }
+    match sqlx::query("DELETE FROM devices WHERE id = ?").bind(id).execute(&ctx.app_state.db_pool).await {
+        Ok(done) if done.rows_affected() == 0 => device_not_found(),
+        Ok(_) => (StatusCode::OK, Json(ApiResponse::success(()))).into_response(),
+        Err(e) => {
+            error!("Failed to delete device: {}", e);
+            error_response(StatusCode::INTERNAL_SERVER_ERROR, "DELETE_DEVICE_ERROR", "Failed to delete device".to_string())
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct WakeResult {
+    device: Device,
+    sent_to: String,
+}
+
+/// Send a Wake-on-LAN magic packet to a stored device. Delivery isn't
+/// confirmed; the device may take a while to come up.
+pub async fn wake_device(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    Path(id): Path<i64>,
+) -> impl IntoResponse {
+    if let Err(resp) = ensure_unrestricted(&ctx, &client_addr).await {
+        return resp;
+    }
+    let device = match fetch_device(&ctx.app_state.db_pool, id).await {
+        Ok(Some(device)) => device,
+        Ok(None) => return device_not_found(),
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_DEVICE_ERROR", e),
+    };
+
+    match send_magic_packet(&device).await {
+        Ok(target) => {
+            info!("Sent Wake-on-LAN packet for '{}' ({}) to {}", device.name, device.mac_address, target);
+            (StatusCode::OK, Json(ApiResponse::success(WakeResult {
+                device,
+                sent_to: target.to_string(),
+            }))).into_response()
+        }
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, "WAKE_DEVICE_ERROR", e),
+    }
+}
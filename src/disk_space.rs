@@ -0,0 +1,216 @@
+use axum::{
+    extract::{Request, State},
+    http::{Method, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use log::{info, warn};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use crate::config::{AppConfig, SharedConfig};
+use crate::context::AppContext;
+use crate::events::{EventSender, ServerEvent};
+use crate::helper::{error_response, format_size};
+use crate::paths::UPLOADS_DIR;
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(300);
+/// Checked more often while a volume is low, so uploads resume soon after
+/// space was freed
+const LOW_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct DiskSpace {
+    /// Bytes available to unprivileged users
+    pub available: u64,
+    pub total: u64,
+}
+
+/// How close a volume is to full, against `NASCRAFT_LOW_DISK_SPACE_PERCENT`
+/// and `NASCRAFT_CRITICAL_DISK_SPACE_PERCENT`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DiskLevel {
+    Ok,
+    Warning,
+    Critical,
+}
+
+impl DiskLevel {
+    fn of(space: DiskSpace, config: &AppConfig) -> Self {
+        let percent_free = space.available * 100 / space.total;
+        if percent_free < config.critical_disk_space_percent as u64 {
+            Self::Critical
+        } else if percent_free < config.low_disk_space_percent as u64 {
+            Self::Warning
+        } else {
+            Self::Ok
+        }
+    }
+}
+
+/// A monitored volume as of its last check
+#[derive(Debug, Clone, Serialize)]
+pub struct VolumeStatus {
+    pub path: PathBuf,
+    pub level: DiskLevel,
+    pub available_bytes: u64,
+    pub total_bytes: u64,
+}
+
+/// Free space of the monitored volumes, shown in `/admin/stats`, and whether
+/// uploads are paused because one of them is critically low
+#[derive(Debug, Default)]
+pub struct DiskStatus {
+    volumes: RwLock<Vec<VolumeStatus>>,
+    read_only: AtomicBool,
+}
+
+impl DiskStatus {
+    pub fn read_only(&self) -> bool {
+        self.read_only.load(Ordering::Relaxed)
+    }
+
+    /// The volumes, with the worst level and a banner for the web UI while
+    /// one of them is low
+    pub fn summary(&self) -> serde_json::Value {
+        let volumes = self.volumes.read().unwrap_or_else(|e| e.into_inner()).clone();
+        let level = volumes.iter().map(|v| v.level).max().unwrap_or(DiskLevel::Ok);
+        let banner = volumes.iter().filter(|v| v.level == level && level != DiskLevel::Ok).map(|v| {
+            format!("{} of {} free on {}", format_size(v.available_bytes), format_size(v.total_bytes), v.path.display())
+        }).collect::<Vec<_>>();
+        let banner = match (level, self.read_only()) {
+            (DiskLevel::Ok, _) => None,
+            (DiskLevel::Warning, _) => Some(format!("Low disk space: {}", banner.join(", "))),
+            (DiskLevel::Critical, false) => Some(format!("Disk space is critically low: {}", banner.join(", "))),
+            (DiskLevel::Critical, true) => Some(format!("Disk space is critically low, uploads are paused: {}", banner.join(", "))),
+        };
+        serde_json::json!({
+            "level": level,
+            "banner": banner,
+            "read_only": self.read_only(),
+            "volumes": volumes,
+        })
+    }
+}
+
+#[cfg(unix)]
+pub fn disk_space(path: &Path) -> io::Result<DiskSpace> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: c_path is NUL-terminated and stat is a valid out pointer
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let block_size = stat.f_frsize as u64;
+    Ok(DiskSpace {
+        available: stat.f_bavail as u64 * block_size,
+        total: stat.f_blocks as u64 * block_size,
+    })
+}
+
+#[cfg(not(unix))]
+pub fn disk_space(_path: &Path) -> io::Result<DiskSpace> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "free disk space can't be checked on this platform"))
+}
+
+/// The uploads volume, and the cold storage volume when one is configured
+fn monitored_paths(config: &AppConfig) -> Vec<PathBuf> {
+    let mut paths = vec![PathBuf::from(UPLOADS_DIR)];
+    paths.extend(config.cold_storage_dir.clone());
+    paths
+}
+
+/// Check the monitored volumes every few minutes. `LowDiskSpace` is raised
+/// when a volume drops to the warning level and again when it turns critical,
+/// once until it has recovered; at the critical level uploads are refused
+/// while `NASCRAFT_READ_ONLY_ON_CRITICAL_DISK` is set.
+pub async fn run_disk_space_monitor(config: SharedConfig, events: EventSender, status: Arc<DiskStatus>) {
+    // 每个卷自上次恢复以来已通知过的最高级别
+    let mut reported: HashMap<PathBuf, DiskLevel> = HashMap::new();
+    loop {
+        let config = config.load();
+        let mut volumes = Vec::new();
+        if config.low_disk_space_percent > 0 || config.critical_disk_space_percent > 0 {
+            for path in monitored_paths(&config) {
+                let space = match disk_space(&path) {
+                    Ok(space) if space.total > 0 => space,
+                    Ok(_) => continue,
+                    Err(e) if e.kind() == io::ErrorKind::Unsupported => {
+                        warn!("Low disk space events are disabled: {}", e);
+                        return std::future::pending().await;
+                    }
+                    Err(e) => {
+                        warn!("Failed to check free space of {}: {}", path.display(), e);
+                        continue;
+                    }
+                };
+                volumes.push(VolumeStatus { level: DiskLevel::of(space, &config), path, available_bytes: space.available, total_bytes: space.total });
+            }
+        }
+
+        let read_only = config.read_only_on_critical_disk && volumes.iter().any(|v| v.level == DiskLevel::Critical);
+        for volume in &volumes {
+            let previous = reported.get(&volume.path).copied().unwrap_or(DiskLevel::Ok);
+            if volume.level > previous {
+                warn!(
+                    "{} disk space on {}: {} of {} bytes free",
+                    if volume.level == DiskLevel::Critical { "Critically low" } else { "Low" },
+                    volume.path.display(), volume.available_bytes, volume.total_bytes
+                );
+                let _ = events.send(ServerEvent::LowDiskSpace {
+                    path: volume.path.clone(),
+                    available_bytes: volume.available_bytes,
+                    total_bytes: volume.total_bytes,
+                    critical: volume.level == DiskLevel::Critical,
+                    read_only,
+                });
+                reported.insert(volume.path.clone(), volume.level);
+            } else if volume.level == DiskLevel::Ok && previous != DiskLevel::Ok {
+                info!("Disk space on {} recovered: {} of {} bytes free", volume.path.display(), volume.available_bytes, volume.total_bytes);
+                reported.remove(&volume.path);
+            }
+        }
+
+        if status.read_only.swap(read_only, Ordering::Relaxed) != read_only {
+            if read_only {
+                warn!("Disk space is critically low, refusing uploads until space is freed");
+            } else {
+                info!("Accepting uploads again");
+            }
+        }
+        let low = volumes.iter().any(|v| v.level != DiskLevel::Ok);
+        *status.volumes.write().unwrap_or_else(|e| e.into_inner()) = volumes;
+        tokio::time::sleep(if low { LOW_CHECK_INTERVAL } else { CHECK_INTERVAL }).await;
+    }
+}
+
+/// Whether a request stores new data: uploads, deltas, extractions and the
+/// inbox upload form. Deletes stay allowed, they free space.
+fn writes_data(method: &Method, path: &str) -> bool {
+    if *method != Method::POST {
+        return false;
+    }
+    match path {
+        "/upload" | "/submit_metadata" | "/bundles" | "/backup/upload" => true,
+        path if path.starts_with("/inbox/") => true,
+        path => path.starts_with("/files/") && (path.ends_with("/delta") || path.ends_with("/extract") || path.ends_with("/extract_audio")),
+    }
+}
+
+/// Refuse requests that store data with `507 READ_ONLY` while disk space is
+/// critically low
+pub async fn refuse_writes_when_read_only(State(ctx): State<AppContext>, req: Request, next: Next) -> Response {
+    if !ctx.disk.read_only() || !writes_data(req.method(), req.uri().path()) {
+        return next.run(req).await;
+    }
+    error_response(StatusCode::INSUFFICIENT_STORAGE, "READ_ONLY", "Disk space is critically low, uploads are paused until space is freed".to_string())
+}
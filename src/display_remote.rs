@@ -1,533 +1,863 @@
-use std::time::Duration;
-use log::{info, error};
-use axum::{
-    extract::State,
-    http::StatusCode,
-    response::IntoResponse,
-    Json,
-};
-use serde::{Deserialize, Serialize};
-use tokio::sync::Mutex;
-use std::sync::Arc;
-use reqwest;
-use tokio::sync::broadcast;
-use std::collections::HashMap;
-use uuid;
-use crate::helper::ApiResponse;
-
-#[derive(Debug, Clone, Deserialize, Serialize)]
-pub struct DeviceState {
-    #[serde(default)]
-    pub playback: i32,
-    #[serde(default)]
-    pub mute: bool,
-    #[serde(default)]
-    pub volume: i32,
-    #[serde(default = "empty_string")]
-    pub position: String,
-    #[serde(default = "empty_string")]
-    pub duration: String,
-    #[serde(default)]
-    pub buffer: i32,
-    #[serde(default = "empty_string")]
-    pub name: String,
-    #[serde(default = "empty_string")]
-    pub uri: String,
-    #[serde(default = "empty_string")]
-    pub metadata: String,
-}
-
-fn empty_string() -> String {
-    String::new()
-}
-
-#[derive(Debug, Clone, Deserialize)]
-#[serde(rename_all = "snake_case")]
-pub enum RendererAction {
-    RendererAdd,
-    RendererDelete,
-    RendererUpdate,
-}
-
-#[derive(Debug, Clone, Deserialize)]
-pub struct DeviceMessage {
-    pub id: i32,
-    pub name: String,
-    pub address: String,
-    pub uuid: String,
-    pub icon: String,
-    #[serde(rename = "iconOverlays")]
-    pub icon_overlays: String,
-    pub playing: String,
-    pub time: String,
-    #[serde(rename = "progressPercent")]
-    pub progress_percent: i32,
-    #[serde(rename = "userId")]
-    pub user_id: i32,
-    pub state: DeviceState,
-    #[serde(rename = "isActive")]
-    pub is_active: bool,
-    #[serde(rename = "isAllowed")]
-    pub is_allowed: bool,
-    #[serde(rename = "isAuthenticated")]
-    pub is_authenticated: bool,
-    pub controls: i32,
-    pub action: String,
-}
-
-pub struct SSEListener {
-    devices: Arc<Mutex<HashMap<String, DeviceMessage>>>,
-    tx: broadcast::Sender<DeviceMessage>,
-    media_server_port: u16,
-}
-
-impl SSEListener {
-    pub fn new(port: u16) -> Self {
-        info!("Creating new SSE listener");
-        let (tx, _) = broadcast::channel(100);
-        SSEListener {
-            devices: Arc::new(Mutex::new(HashMap::new())),
-            tx,
-            media_server_port: port,
-        }
-    }
-
-    pub fn subscribe(&self) -> broadcast::Receiver<DeviceMessage> {
-        info!("New subscriber connected to SSE listener");
-        self.tx.subscribe()
-    }
-
-    pub async fn get_devices(&self) -> HashMap<String, DeviceMessage> {
-        info!("Retrieving current device list");
-        let devices = self.devices.lock().await.clone();
-        info!("Found {} devices in cache", devices.len());
-        devices
-    }
-
-    pub async fn start_listening(self: Arc<Self>) {
-        info!("Starting SSE listener");
-        tokio::spawn(async move {
-            loop {
-                if let Err(e) = self.listen().await {
-                    error!("SSE listener error: {}", e);
-                    info!("Retrying SSE connection in 5 seconds");
-                    tokio::time::sleep(Duration::from_secs(5)).await;
-                }
-            }
-        });
-    }
-
-    async fn listen(&self) -> Result<(), String> {
-        info!("Establishing SSE connection to server");
-        let client = reqwest::Client::new();
-        let base_url = format!("http://localhost:{}", self.media_server_port);
-        let mut response = client.get(format!("{}/v1/api/sse/", base_url))
-            .header("Accept", "text/event-stream")
-            .header("Authorization", "Bearer null")
-            .header("Referer", format!("{}/", base_url))
-            .send()
-            .await
-            .map_err(|e| format!("Failed to connect to SSE stream: {}", e))?;
-
-        info!("SSE connection established successfully");
-        let mut event_type = String::new();
-        let mut event_data = String::new();
-
-        while let Ok(Some(chunk)) = response.chunk().await {
-            let text = String::from_utf8_lossy(&chunk);
-            for line in text.lines() {
-                info!("origin message: {}", line);
-                if line.is_empty() {
-                    if !event_data.is_empty() {
-                        self.handle_event(&event_type, &event_data).await?;
-                        event_type.clear();
-                        event_data.clear();
-                    }
-                } else if line.starts_with("event: ") {
-                    event_type = line[7..].to_string();
-                } else if line.starts_with("data: ") {
-                    event_data = line[6..].to_string();
-                }
-            }
-        }
-        error!("SSE connection closed unexpectedly");
-        Ok(())
-    }
-
-    async fn handle_event(&self, event_type: &str, data: &str) -> Result<(), String> {
-        match event_type {
-            "message" => {
-                info!("Parsing message data: {}", data);
-                match serde_json::from_str::<DeviceMessage>(data) {
-                    Ok(msg) => {
-                        match msg.action.as_str() {
-                            "renderer_add" | "renderer_delete" | "renderer_update" => {
-                                info!("收到设备事件 - 动作: {}, ID: {}, 名称: {}", 
-                                    msg.action, msg.id, msg.name);
-                                self.devices.lock().await.insert(msg.uuid.clone(), msg.clone());
-                                let _ = self.tx.send(msg);
-                            }
-                            _ => {
-                                info!("忽略未知的渲染器动作: {}", msg.action);
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        error!("解析设备消息失败: {} - 原始数据: {}", e, data);
-                    }
-                }
-            }
-            _ => {
-                info!("忽略未知的事件类型: {}", event_type);
-            }
-        }
-        Ok(())
-    }
-}
-
-pub struct DLNAPlayer {
-    media_server_port: u16,
-    sse_listener: Arc<SSEListener>,
-    enabled: bool,
-}
-
-impl DLNAPlayer {
-    pub async fn new(enabled: bool) -> Self {
-        info!("Initializing DLNA player");
-        let media_server_port = 9001;
-        let sse_listener = Arc::new(SSEListener::new(media_server_port));
-        if enabled {
-            info!("DLNA remote enabled, starting SSE listener");
-            sse_listener.clone().start_listening().await;
-        } else {
-            info!("DLNA remote disabled, skipping SSE listener startup");
-        }
-
-        info!("DLNA player initialized with media server port: {}, enabled: {}", media_server_port, enabled);
-        DLNAPlayer {
-            media_server_port,
-            sse_listener,
-            enabled,
-        }
-    }
-
-    async fn send_control_request(&self, device_id: i32, action: &str, value: Option<String>) -> Result<(), String> {
-        info!("Sending control request - Device ID: {}, Action: {}", device_id, action);
-        if let Some(val) = &value {
-            info!("Control request value: {}", val);
-        }
-
-        let client = reqwest::Client::new();
-        
-        let mut control_request = serde_json::json!({
-            "id": device_id,
-            "action": action
-        });
-
-        if let Some(val) = value {
-            control_request["value"] = serde_json::Value::String(val);
-        }
-
-        let request_url = format!("http://localhost:{}/v1/api/renderers/control", self.media_server_port);
-        info!("Sending request to: {}", request_url);
-        info!("Request payload: {}", serde_json::to_string_pretty(&control_request).unwrap());
-
-        let response = client.post(&request_url)
-            .json(&control_request)
-            .send()
-            .await
-            .map_err(|e| {
-                error!("Failed to send control request: {}", e);
-                format!("Failed to send control request: {}", e)
-            })?;
-
-        let status = response.status();
-        info!("Received response with status: {}", status);
-
-        if !status.is_success() {
-            let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-            error!("Control request failed with error: {}", error_text);
-            return Err(format!("Control request failed: {}", error_text));
-        }
-
-        info!("Control request completed successfully");
-        Ok(())
-    }
-
-    pub async fn browse_files(&self, id: String) -> Result<ApiResponse<BrowseResponse>, String> {
-        info!("Browsing files with ID: {}", id);
-        
-        let client = reqwest::Client::new();
-        let base_url = format!("http://localhost:{}", self.media_server_port);
-        
-        let request_body = serde_json::json!({
-            "uuid": uuid::Uuid::new_v4().to_string(),
-            "id": id,
-            "lang": "zh-CN"
-        });
-
-        let request_url = format!("{}/v1/api/player/browse", base_url);
-        info!("Sending browse request to: {}", request_url);
-        info!("Request payload: {}", serde_json::to_string_pretty(&request_body).unwrap());
-
-        let response = client.post(&request_url)
-            .header("User-Agent", "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/132.0.0.0 Safari/537.36")
-            .json(&request_body)
-            .send()
-            .await
-            .map_err(|e| format!("Failed to send browse request: {}", e))?;
-
-        if !response.status().is_success() {
-            let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-            error!("Browse request failed: {}", error_text);
-            return Ok(ApiResponse::error("500".to_string(), format!("Browse request failed: {}", error_text)));
-        }
-
-        let response_text = response.text().await
-            .map_err(|e| format!("Failed to get response text: {}", e))?;
-        info!("Browse response: {}", response_text);
-
-        match serde_json::from_str::<BrowseResponse>(&response_text) {
-            Ok(browse_response) => {
-                info!("Successfully retrieved file list");
-                Ok(ApiResponse::success(browse_response))
-            }
-            Err(e) => {
-                error!("Failed to parse browse response: {}", e);
-                Ok(ApiResponse::error("500".to_string(), format!("Failed to parse browse response: {}", e)))
-            }
-        }
-    }
-}
-
-#[derive(Debug, Serialize)]
-pub struct DeviceResponse {
-    pub id: i32,
-    pub name: String,
-    pub address: String,
-    pub uuid: String,
-    pub state: DeviceState,
-    pub is_active: bool,
-}
-
-pub async fn discovered_devices(
-    State(ctx): State<crate::context::AppContext>,
-) -> impl IntoResponse {
-    info!("Handling device discovery request");
-    let player = ctx.dlna_player.lock().await;
-    let devices = player.sse_listener.get_devices().await;
-    
-    info!("Converting device messages to response format");
-    let device_responses: Vec<DeviceResponse> = devices.values()
-        .map(|msg| {
-            info!("Processing device - ID: {}, Name: {}", msg.id, msg.name);
-            DeviceResponse {
-                id: msg.id,
-                name: msg.name.clone(),
-                address: msg.address.clone(),
-                uuid: msg.uuid.clone(),
-                state: msg.state.clone(),
-                is_active: msg.is_active,
-            }
-        })
-        .collect();
-
-    info!("Returning {} devices in response", device_responses.len());
-    (StatusCode::OK, Json(ApiResponse::success(device_responses))).into_response()
-}
-
-#[derive(Debug, Deserialize)]
-pub struct PlayVideoRequest {
-    device_id: i32,
-    media_id: String,
-}
-
-#[derive(Debug, Deserialize)]
-pub struct DeviceControlRequest {
-    device_id: i32,
-}
-
-pub async fn play_video(
-    State(ctx): State<crate::context::AppContext>,
-    Json(req): Json<PlayVideoRequest>,
-) -> impl IntoResponse {
-    info!("Handling play video request - Device ID: {}, Media ID: {}", 
-        req.device_id, req.media_id);
-    
-    let player = ctx.dlna_player.lock().await;
-    match player.send_control_request(req.device_id, "mediaid", Some(req.media_id.clone())).await {
-        Ok(_) => {
-            info!("Play video request sent successfully");
-            (StatusCode::OK, Json(ApiResponse::<()>::success(()))).into_response()
-        }
-        Err(e) => {
-            error!("Failed to send play request: {}", e);
-            (StatusCode::INTERNAL_SERVER_ERROR, Json(ApiResponse::<()>::error(
-                "500".to_string(),
-                e,
-            ))).into_response()
-        }
-    }
-}
-
-pub async fn pause_video(
-    State(ctx): State<crate::context::AppContext>,
-    Json(req): Json<DeviceControlRequest>,
-) -> impl IntoResponse {
-    info!("Handling pause video request - Device ID: {}", req.device_id);
-    
-    let player = ctx.dlna_player.lock().await;
-    match player.send_control_request(req.device_id, "pause", None).await {
-        Ok(_) => {
-            info!("Pause request sent successfully");
-            (StatusCode::OK, Json(ApiResponse::<()>::success(()))).into_response()
-        }
-        Err(e) => {
-            error!("Failed to send pause request: {}", e);
-            (StatusCode::INTERNAL_SERVER_ERROR, Json(ApiResponse::<()>::error(
-                "500".to_string(),
-                e,
-            ))).into_response()
-        }
-    }
-}
-
-pub async fn resume_video(
-    State(ctx): State<crate::context::AppContext>,
-    Json(req): Json<DeviceControlRequest>,
-) -> impl IntoResponse {
-    info!("Handling resume video request - Device ID: {}", req.device_id);
-    
-    let player = ctx.dlna_player.lock().await;
-    match player.send_control_request(req.device_id, "play", None).await {
-        Ok(_) => {
-            info!("Resume request sent successfully");
-            (StatusCode::OK, Json(ApiResponse::<()>::success(()))).into_response()
-        }
-        Err(e) => {
-            error!("Failed to send resume request: {}", e);
-            (StatusCode::INTERNAL_SERVER_ERROR, Json(ApiResponse::<()>::error(
-                "500".to_string(),
-                e,
-            ))).into_response()
-        }
-    }
-}
-
-pub async fn stop_video(
-    State(ctx): State<crate::context::AppContext>,
-    Json(req): Json<DeviceControlRequest>,
-) -> impl IntoResponse {
-    info!("Handling stop video request - Device ID: {}", req.device_id);
-    
-    let player = ctx.dlna_player.lock().await;
-    match player.send_control_request(req.device_id, "stop", None).await {
-        Ok(_) => {
-            info!("Stop request sent successfully");
-            (StatusCode::OK, Json(ApiResponse::<()>::success(()))).into_response()
-        }
-        Err(e) => {
-            error!("Failed to send stop request: {}", e);
-            (StatusCode::INTERNAL_SERVER_ERROR, Json(ApiResponse::<()>::error(
-                "500".to_string(),
-                e,
-            ))).into_response()
-        }
-    }
-}
-
-pub async fn hello() -> impl IntoResponse {
-    info!("Handling health check request");
-    (StatusCode::OK, "Service is alive").into_response()
-}
-
-#[derive(Debug, Serialize)]
-pub enum TransportState {
-    Playing,
-    Paused,
-    Stopped,
-    Unknown,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct MediaItem {
-    pub goal: String,
-    pub name: String,
-    #[serde(rename = "updateId")]
-    pub update_id: String,
-    pub id: String,
-}
-
-#[derive(Debug, Serialize, Deserialize, Default)]
-pub struct MediaSelections {
-    #[serde(rename = "recentlyAdded", default)]
-    pub recently_added: Vec<MediaItem>,
-    #[serde(rename = "recentlyPlayed", default)]
-    pub recently_played: Vec<MediaItem>,
-    #[serde(rename = "inProgress", default)]
-    pub in_progress: Vec<MediaItem>,
-    #[serde(rename = "mostPlayed", default)]
-    pub most_played: Vec<MediaItem>,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Breadcrumb {
-    pub id: String,
-    pub name: String,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Folder {
-    pub id: String,
-    pub name: String,
-    pub icon: String,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct BrowseResponse {
-    #[serde(default)]
-    pub goal: String,
-    #[serde(rename = "mediasSelections", default)]
-    pub medias_selections: MediaSelections,
-    #[serde(default)]
-    pub umsversion: String,
-    #[serde(default)]
-    pub name: String,
-    #[serde(rename = "hasFile", default)]
-    pub has_file: bool,
-    #[serde(rename = "useWebControl", default)]
-    pub use_web_control: bool,
-    #[serde(default)]
-    pub breadcrumbs: Vec<Breadcrumb>,
-    #[serde(default)]
-    pub folders: Vec<Folder>,
-    #[serde(default)]
-    pub medias: Vec<MediaItem>,
-}
-
-#[derive(Debug, Deserialize)]
-pub struct BrowseRequest {
-    pub id: String,
-}
-
-pub async fn browse_files(
-    State(ctx): State<crate::context::AppContext>,
-    Json(req): Json<BrowseRequest>,
-) -> impl IntoResponse {
-    info!("Handling browse request - ID: {}", req.id);
-    
-    let player = ctx.dlna_player.lock().await;
-    match player.browse_files(req.id).await {
-        Ok(response) => {
-            info!("Browse request successful");
-            (StatusCode::OK, Json(response)).into_response()
-        }
-        Err(e) => {
-            error!("Browse request failed: {}", e);
-            (StatusCode::INTERNAL_SERVER_ERROR, Json(ApiResponse::<()>::error(
-                "500".to_string(),
-                e,
-            ))).into_response()
-        }
-    }
+use std::time::Duration;
+use log::{info, error};
+use axum::{
+    extract::{ConnectInfo, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use sqlx::SqlitePool;
+use crate::config::AppConfig;
+use crate::helper::{error_response, ApiResponse};
+use crate::library_query::LibraryQuery;
+use crate::bot::play_file;
+use crate::feeds::item_title;
+use crate::media_proxy::play_remote_url;
+use crate::partial_playback::ensure_playable_while_uploading;
+use crate::profiles::{ensure_file_allowed, ensure_unrestricted};
+use crate::renderer::{discover_renderers, find_renderer};
+use crate::upload_dao::fetch_uploaded_file_by_id;
+use crate::renderer_diagnostics::{record_event, EventSource};
+use crate::traffic::client_principal;
+use crate::supervisor::Supervisor;
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct DeviceState {
+    #[serde(default)]
+    pub playback: i32,
+    #[serde(default)]
+    pub mute: bool,
+    #[serde(default)]
+    pub volume: i32,
+    #[serde(default = "empty_string")]
+    pub position: String,
+    #[serde(default = "empty_string")]
+    pub duration: String,
+    #[serde(default)]
+    pub buffer: i32,
+    #[serde(default = "empty_string")]
+    pub name: String,
+    #[serde(default = "empty_string")]
+    pub uri: String,
+    #[serde(default = "empty_string")]
+    pub metadata: String,
+}
+
+fn empty_string() -> String {
+    String::new()
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct DeviceMessage {
+    pub id: i32,
+    pub name: String,
+    pub address: String,
+    pub uuid: String,
+    pub icon: String,
+    #[serde(rename = "iconOverlays")]
+    pub icon_overlays: String,
+    pub playing: String,
+    pub time: String,
+    #[serde(rename = "progressPercent")]
+    pub progress_percent: i32,
+    #[serde(rename = "userId")]
+    pub user_id: i32,
+    pub state: DeviceState,
+    #[serde(rename = "isActive")]
+    pub is_active: bool,
+    #[serde(rename = "isAllowed")]
+    pub is_allowed: bool,
+    #[serde(rename = "isAuthenticated")]
+    pub is_authenticated: bool,
+    pub controls: i32,
+    pub action: String,
+}
+
+/// 首次重连前的等待时间，连续失败时翻倍，最长 SSE_MAX_BACKOFF
+const SSE_INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const SSE_MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// 超过该时长没有收到任何数据（包括心跳注释行）即认为连接已失效
+const SSE_HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(45);
+
+/// Connection metrics of the SSE listener, served by `/api/dlna/sse_status`
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SseStats {
+    pub connected: bool,
+    pub connects: u64,
+    pub reconnects: u64,
+    pub heartbeat_timeouts: u64,
+    pub events_received: u64,
+    pub bytes_received: u64,
+    pub last_event_id: Option<String>,
+    pub last_error: Option<String>,
+    #[serde(with = "crate::api::timestamp::option")]
+    pub last_connected_at: Option<i64>,
+    /// Reconnection delay requested by the server with a `retry:` field
+    pub server_retry_ms: Option<u64>,
+}
+
+/// 正在解析、尚未分发的事件
+#[derive(Default)]
+struct PendingEvent {
+    event_type: String,
+    data: String,
+    id: Option<String>,
+}
+
+/// Connection settings of the external media server (renderer control, browsing and events)
+#[derive(Debug, Clone)]
+pub struct MediaServer {
+    pub base_url: String,
+    pub token: Option<String>,
+    pub sse_path: String,
+}
+
+impl MediaServer {
+    pub fn from_config(config: &AppConfig) -> Option<Self> {
+        config.media_server_url.as_ref().map(|base_url| Self {
+            base_url: base_url.clone(),
+            token: config.media_server_token.clone(),
+            sse_path: config.media_server_sse_path.clone(),
+        })
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url, path)
+    }
+
+    /// Request with the configured credentials
+    fn request(&self, client: &reqwest::Client, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        let request = client.request(method, self.url(path));
+        match &self.token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+    }
+}
+
+/// `dlna_devices.source` of devices reported by the external media server
+const DEVICE_SOURCE_MEDIA_SERVER: &str = "media_server";
+
+pub struct SSEListener {
+    devices: Arc<Mutex<HashMap<String, DeviceMessage>>>,
+    tx: broadcast::Sender<DeviceMessage>,
+    stats: Mutex<SseStats>,
+    db_pool: SqlitePool,
+}
+
+impl SSEListener {
+    pub fn new(db_pool: SqlitePool) -> Self {
+        info!("Creating new SSE listener");
+        let (tx, _) = broadcast::channel(100);
+        SSEListener {
+            devices: Arc::new(Mutex::new(HashMap::new())),
+            tx,
+            stats: Mutex::new(SseStats::default()),
+            db_pool,
+        }
+    }
+
+    /// Fill the device cache from the last snapshot so devices are listed before the first event arrives
+    pub async fn load_snapshot(&self) {
+        match load_device_snapshot(&self.db_pool, DEVICE_SOURCE_MEDIA_SERVER).await {
+            Ok(snapshot) => {
+                info!("Loaded {} devices from snapshot", snapshot.len());
+                let mut devices = self.devices.lock().await;
+                for device in snapshot {
+                    devices.entry(device.uuid.clone()).or_insert(device);
+                }
+            }
+            Err(e) => error!("Failed to load device snapshot: {}", e),
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<DeviceMessage> {
+        info!("New subscriber connected to SSE listener");
+        self.tx.subscribe()
+    }
+
+    pub async fn get_devices(&self) -> HashMap<String, DeviceMessage> {
+        info!("Retrieving current device list");
+        let devices = self.devices.lock().await.clone();
+        info!("Found {} devices in cache", devices.len());
+        devices
+    }
+
+    pub async fn stats(&self) -> SseStats {
+        self.stats.lock().await.clone()
+    }
+
+    /// Keep the event stream connected, reconnecting with backoff
+    pub async fn run_listener(self: Arc<Self>, server: MediaServer) {
+        info!("Starting SSE listener");
+        let mut backoff = SSE_INITIAL_BACKOFF;
+        loop {
+            let bytes_before = self.stats.lock().await.bytes_received;
+            let error = match self.listen(&server).await {
+                Ok(never) => match never {},
+                Err(e) => e,
+            };
+
+            let delay = {
+                let mut stats = self.stats.lock().await;
+                stats.connected = false;
+                stats.reconnects += 1;
+                stats.last_error = Some(error.clone());
+                let base = stats.server_retry_ms.map(Duration::from_millis).unwrap_or(SSE_INITIAL_BACKOFF);
+                // 本次连接收到过数据说明服务端可用，退避从初始间隔重新开始
+                backoff = if stats.bytes_received > bytes_before {
+                    base
+                } else {
+                    (backoff * 2).clamp(base, SSE_MAX_BACKOFF.max(base))
+                };
+                backoff
+            };
+
+            error!("SSE listener error: {}", error);
+            info!("Retrying SSE connection in {:?}", delay);
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    /// Read the event stream until it fails; only returns with the reason the connection ended
+    async fn listen(&self, server: &MediaServer) -> Result<std::convert::Infallible, String> {
+        info!("Establishing SSE connection to {}", server.url(&server.sse_path));
+        let client = reqwest::Client::new();
+        let mut request = server.request(&client, reqwest::Method::GET, &server.sse_path)
+            .header("Accept", "text/event-stream")
+            .header("Referer", format!("{}/", server.base_url));
+        if let Some(id) = self.stats.lock().await.last_event_id.clone() {
+            info!("Resuming SSE stream after event {}", id);
+            request = request.header("Last-Event-ID", id);
+        }
+        let mut response = request
+            .send()
+            .await
+            .map_err(|e| format!("Failed to connect to SSE stream: {}", e))?;
+        if !response.status().is_success() {
+            return Err(format!("SSE stream returned status {}", response.status()));
+        }
+
+        info!("SSE connection established successfully");
+        {
+            let mut stats = self.stats.lock().await;
+            stats.connected = true;
+            stats.connects += 1;
+            stats.last_connected_at = Some(chrono::Utc::now().timestamp());
+        }
+
+        let mut buffer = Vec::new();
+        let mut event = PendingEvent::default();
+        loop {
+            let chunk = match tokio::time::timeout(SSE_HEARTBEAT_TIMEOUT, response.chunk()).await {
+                Ok(Ok(Some(chunk))) => chunk,
+                Ok(Ok(None)) => return Err("SSE connection closed by server".to_string()),
+                Ok(Err(e)) => return Err(format!("SSE stream error: {}", e)),
+                Err(_) => {
+                    self.stats.lock().await.heartbeat_timeouts += 1;
+                    return Err(format!("No data on SSE stream for {} seconds", SSE_HEARTBEAT_TIMEOUT.as_secs()));
+                }
+            };
+            self.stats.lock().await.bytes_received += chunk.len() as u64;
+
+            // 数据块可能在行中间截断，不完整的行留在缓冲区等待后续数据
+            buffer.extend_from_slice(&chunk);
+            while let Some(pos) = buffer.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = buffer.drain(..=pos).collect();
+                let line = String::from_utf8_lossy(&line);
+                self.process_line(line.trim_end_matches(['\n', '\r']), &mut event).await?;
+            }
+        }
+    }
+
+    async fn process_line(&self, line: &str, event: &mut PendingEvent) -> Result<(), String> {
+        // 空行表示事件结束
+        if line.is_empty() {
+            let event = std::mem::take(event);
+            if let Some(id) = event.id {
+                self.stats.lock().await.last_event_id = Some(id);
+            }
+            if !event.data.is_empty() {
+                self.stats.lock().await.events_received += 1;
+                let event_type = if event.event_type.is_empty() { "message" } else { &event.event_type };
+                self.handle_event(event_type, &event.data).await?;
+            }
+            return Ok(());
+        }
+
+        // 注释行，服务端用作心跳
+        if line.starts_with(':') {
+            return Ok(());
+        }
+
+        info!("origin message: {}", line);
+        let (field, value) = match line.split_once(':') {
+            Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+            None => (line, ""),
+        };
+        match field {
+            "event" => event.event_type = value.to_string(),
+            "data" => {
+                if !event.data.is_empty() {
+                    event.data.push('\n');
+                }
+                event.data.push_str(value);
+            }
+            "id" if !value.contains('\0') => event.id = Some(value.to_string()),
+            "retry" => {
+                if let Ok(ms) = value.parse::<u64>() {
+                    self.stats.lock().await.server_retry_ms = Some(ms);
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    async fn handle_event(&self, event_type: &str, data: &str) -> Result<(), String> {
+        match event_type {
+            "message" => {
+                info!("Parsing message data: {}", data);
+                match serde_json::from_str::<DeviceMessage>(data) {
+                    Ok(msg) => {
+                        record_event(&msg.uuid, EventSource::MediaServer, false, data.to_string());
+                        match msg.action.as_str() {
+                            "renderer_add" | "renderer_delete" | "renderer_update" => {
+                                info!("收到设备事件 - 动作: {}, ID: {}, 名称: {}", 
+                                    msg.action, msg.id, msg.name);
+                                self.devices.lock().await.insert(msg.uuid.clone(), msg.clone());
+                                if let Err(e) = save_device_snapshot(&self.db_pool, DEVICE_SOURCE_MEDIA_SERVER, &msg).await {
+                                    error!("Failed to save device snapshot: {}", e);
+                                }
+                                let _ = self.tx.send(msg);
+                            }
+                            _ => {
+                                info!("忽略未知的渲染器动作: {}", msg.action);
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        error!("解析设备消息失败: {} - 原始数据: {}", e, data);
+                        // 能读出 uuid 的消息仍记入该设备的诊断记录
+                        let uuid = serde_json::from_str::<serde_json::Value>(data)
+                            .ok()
+                            .and_then(|value| value.get("uuid").and_then(|v| v.as_str()).map(String::from));
+                        if let Some(uuid) = uuid {
+                            record_event(&uuid, EventSource::MediaServer, true, format!("{}: {}", e, data));
+                        }
+                    }
+                }
+            }
+            _ => {
+                info!("忽略未知的事件类型: {}", event_type);
+            }
+        }
+        Ok(())
+    }
+}
+
+async fn load_device_snapshot(db_pool: &SqlitePool, source: &str) -> Result<Vec<DeviceMessage>, String> {
+    let rows = sqlx::query_scalar::<_, String>("SELECT message FROM dlna_devices WHERE source = ?")
+        .bind(source)
+        .fetch_all(db_pool)
+        .await
+        .map_err(|e| format!("Failed to fetch device snapshot: {}", e))?;
+
+    Ok(rows
+        .iter()
+        .filter_map(|message| match serde_json::from_str::<DeviceMessage>(message) {
+            Ok(device) => Some(device),
+            Err(e) => {
+                error!("Skipping unreadable device snapshot entry: {}", e);
+                None
+            }
+        })
+        .collect())
+}
+
+async fn save_device_snapshot(db_pool: &SqlitePool, source: &str, device: &DeviceMessage) -> Result<(), String> {
+    let message = serde_json::to_string(device).map_err(|e| format!("Failed to serialize device: {}", e))?;
+    sqlx::query(
+        "INSERT INTO dlna_devices (uuid, source, message, updated_at) VALUES (?, ?, ?, ?)
+         ON CONFLICT(uuid) DO UPDATE SET source = excluded.source, message = excluded.message, updated_at = excluded.updated_at"
+    )
+    .bind(&device.uuid)
+    .bind(source)
+    .bind(message)
+    .bind(chrono::Utc::now().timestamp())
+    .execute(db_pool)
+    .await
+    .map_err(|e| format!("Failed to save device snapshot: {}", e))?;
+    Ok(())
+}
+
+pub struct DLNAPlayer {
+    media_server: Option<MediaServer>,
+    sse_listener: Arc<SSEListener>,
+    enabled: bool,
+}
+
+impl DLNAPlayer {
+    pub async fn new(config: &AppConfig, db_pool: SqlitePool, supervisor: &Arc<Supervisor>) -> Self {
+        info!("Initializing DLNA player");
+        let media_server = MediaServer::from_config(config);
+        let sse_listener = Arc::new(SSEListener::new(db_pool));
+        let enabled = config.enable_dlna_remote && media_server.is_some();
+        match &media_server {
+            Some(server) if config.enable_dlna_remote => {
+                info!("DLNA remote enabled, starting SSE listener");
+                sse_listener.load_snapshot().await;
+                let (listener, server) = (sse_listener.clone(), server.clone());
+                supervisor.spawn("sse_listener", move || listener.clone().run_listener(server.clone()));
+            }
+            Some(_) => info!("DLNA remote disabled, skipping SSE listener startup"),
+            None => info!("External media server integration disabled"),
+        }
+
+        info!("DLNA player initialized with media server: {:?}, enabled: {}", media_server.as_ref().map(|s| &s.base_url), enabled);
+        DLNAPlayer {
+            media_server,
+            sse_listener,
+            enabled,
+        }
+    }
+
+    /// Renderers reported by the media server, by UUID, including deleted ones
+    pub async fn renderers(&self) -> HashMap<String, DeviceMessage> {
+        self.sse_listener.get_devices().await
+    }
+
+    /// Renderer updates as they arrive
+    pub fn subscribe_renderers(&self) -> broadcast::Receiver<DeviceMessage> {
+        self.sse_listener.subscribe()
+    }
+
+    fn media_server(&self) -> Result<&MediaServer, String> {
+        self.media_server.as_ref().ok_or_else(|| "External media server integration is disabled".to_string())
+    }
+
+    async fn send_control_request(&self, device_id: i32, action: &str, value: Option<String>) -> Result<(), String> {
+        info!("Sending control request - Device ID: {}, Action: {}", device_id, action);
+        if let Some(val) = &value {
+            info!("Control request value: {}", val);
+        }
+
+        let server = self.media_server()?;
+        let client = reqwest::Client::new();
+        
+        let mut control_request = serde_json::json!({
+            "id": device_id,
+            "action": action
+        });
+
+        if let Some(val) = value {
+            control_request["value"] = serde_json::Value::String(val);
+        }
+
+        let request_path = "/v1/api/renderers/control";
+        info!("Sending request to: {}", server.url(request_path));
+        info!("Request payload: {}", serde_json::to_string_pretty(&control_request).unwrap());
+
+        let result = async {
+            let response = server.request(&client, reqwest::Method::POST, request_path)
+                .json(&control_request)
+                .send()
+                .await
+                .map_err(|e| {
+                    error!("Failed to send control request: {}", e);
+                    format!("Failed to send control request: {}", e)
+                })?;
+
+            let status = response.status();
+            info!("Received response with status: {}", status);
+
+            if !status.is_success() {
+                let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
+                error!("Control request failed with error: {}", error_text);
+                return Err(format!("Control request failed: {} {}", status.as_u16(), error_text));
+            }
+            Ok(())
+        }.await;
+
+        // 控制请求按设备 id 发送，诊断记录按 uuid 保存
+        if let Some(device) = self.sse_listener.get_devices().await.values().find(|d| d.id == device_id) {
+            let (error, message) = match &result {
+                Ok(()) => (false, control_request.to_string()),
+                Err(e) => (true, format!("{} -> {}", control_request, e)),
+            };
+            record_event(&device.uuid, EventSource::Control, error, message);
+        }
+        if result.is_ok() {
+            info!("Control request completed successfully");
+        }
+        result
+    }
+
+    pub async fn browse_files(&self, id: String) -> Result<ApiResponse<BrowseResponse>, String> {
+        info!("Browsing files with ID: {}", id);
+        
+        let server = self.media_server()?;
+        let client = reqwest::Client::new();
+        
+        let request_body = serde_json::json!({
+            "uuid": uuid::Uuid::new_v4().to_string(),
+            "id": id,
+            "lang": "zh-CN"
+        });
+
+        let request_path = "/v1/api/player/browse";
+        info!("Sending browse request to: {}", server.url(request_path));
+        info!("Request payload: {}", serde_json::to_string_pretty(&request_body).unwrap());
+
+        let response = server.request(&client, reqwest::Method::POST, request_path)
+            .header("User-Agent", "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/132.0.0.0 Safari/537.36")
+            .json(&request_body)
+            .send()
+            .await
+            .map_err(|e| format!("Failed to send browse request: {}", e))?;
+
+        if !response.status().is_success() {
+            let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
+            error!("Browse request failed: {}", error_text);
+            return Ok(ApiResponse::error("500".to_string(), format!("Browse request failed: {}", error_text)));
+        }
+
+        let response_text = response.text().await
+            .map_err(|e| format!("Failed to get response text: {}", e))?;
+        info!("Browse response: {}", response_text);
+
+        match serde_json::from_str::<BrowseResponse>(&response_text) {
+            Ok(browse_response) => {
+                info!("Successfully retrieved file list");
+                Ok(ApiResponse::success(browse_response))
+            }
+            Err(e) => {
+                error!("Failed to parse browse response: {}", e);
+                Ok(ApiResponse::error("500".to_string(), format!("Failed to parse browse response: {}", e)))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeviceResponse {
+    pub id: i32,
+    pub name: String,
+    pub address: String,
+    pub uuid: String,
+    pub state: DeviceState,
+    pub is_active: bool,
+}
+
+pub async fn discovered_devices(
+    State(ctx): State<crate::context::AppContext>,
+) -> impl IntoResponse {
+    info!("Handling device discovery request");
+    let player = ctx.dlna_player.lock().await;
+    let devices = player.sse_listener.get_devices().await;
+    
+    info!("Converting device messages to response format");
+    let device_responses: Vec<DeviceResponse> = devices.values()
+        .map(|msg| {
+            info!("Processing device - ID: {}, Name: {}", msg.id, msg.name);
+            DeviceResponse {
+                id: msg.id,
+                name: msg.name.clone(),
+                address: msg.address.clone(),
+                uuid: msg.uuid.clone(),
+                state: msg.state.clone(),
+                is_active: msg.is_active,
+            }
+        })
+        .collect();
+
+    info!("Returning {} devices in response", device_responses.len());
+    (StatusCode::OK, Json(ApiResponse::success(device_responses))).into_response()
+}
+
+#[derive(Debug, Serialize)]
+pub struct SseStatusResponse {
+    pub enabled: bool,
+    #[serde(flatten)]
+    pub stats: SseStats,
+}
+
+pub async fn sse_status(
+    State(ctx): State<crate::context::AppContext>,
+) -> impl IntoResponse {
+    let player = ctx.dlna_player.lock().await;
+    let stats = player.sse_listener.stats().await;
+    (StatusCode::OK, Json(ApiResponse::success(SseStatusResponse { enabled: player.enabled, stats }))).into_response()
+}
+
+/// Either an item of the external media server on one of its devices, or a
+/// library file or web-hosted media on a UPnP renderer
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum PlayVideoRequest {
+    Media {
+        device_id: i32,
+        media_id: String,
+    },
+    File {
+        /// Renderer name, matched like `/simple/play`'s `device`
+        renderer: String,
+        file_id: String,
+    },
+    Url {
+        renderer: String,
+        /// Web-hosted media, played through `/media/proxy`
+        url: String,
+        title: Option<String>,
+    },
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeviceControlRequest {
+    device_id: i32,
+}
+
+pub async fn play_video(
+    State(ctx): State<crate::context::AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    Json(req): Json<PlayVideoRequest>,
+) -> impl IntoResponse {
+    let (device_id, media_id) = match req {
+        PlayVideoRequest::Media { device_id, media_id } => (device_id, media_id),
+        PlayVideoRequest::File { renderer, file_id } => return play_library_file(&ctx, &client_addr, &renderer, &file_id).await,
+        PlayVideoRequest::Url { renderer, url, title } => return play_remote_url(&ctx, &client_addr, &renderer, &url, title).await,
+    };
+    info!("Handling play video request - Device ID: {}, Media ID: {}", 
+        device_id, media_id);
+
+    // 媒体服务器的 id 无法对应到本地文件，受限档案下不允许投屏
+    if let Err(resp) = ensure_unrestricted(&ctx, &client_addr).await {
+        return resp;
+    }
+    
+    let player = ctx.dlna_player.lock().await;
+    match player.send_control_request(device_id, "mediaid", Some(media_id.clone())).await {
+        Ok(_) => {
+            info!("Play video request sent successfully");
+            (StatusCode::OK, Json(ApiResponse::<()>::success(()))).into_response()
+        }
+        Err(e) => {
+            error!("Failed to send play request: {}", e);
+            error_response(StatusCode::INTERNAL_SERVER_ERROR, "500", e)
+        }
+    }
+}
+
+/// Play a library file on a renderer through a share link. Videos and songs
+/// still uploading play as far as they have arrived.
+async fn play_library_file(ctx: &crate::context::AppContext, client_addr: &SocketAddr, renderer: &str, file_id: &str) -> Response {
+    if let Err(resp) = ensure_file_allowed(ctx, client_addr, file_id).await {
+        return resp;
+    }
+    let db_pool = &ctx.app_state.db_pool;
+    let file = match fetch_uploaded_file_by_id(db_pool, file_id).await {
+        Ok(Some(file)) if matches!(file.status, 0..=2) => file,
+        Ok(_) => return error_response(StatusCode::NOT_FOUND, "FILE_NOT_FOUND", "File not found".to_string()),
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_FILE_RECORD_ERROR", e),
+    };
+    if file.status != 2 {
+        if let Err((code, message)) = ensure_playable_while_uploading(db_pool, &file).await {
+            return error_response(StatusCode::CONFLICT, code, message);
+        }
+    }
+    let renderers = match discover_renderers().await {
+        Ok(renderers) => renderers,
+        Err(e) => return error_response(StatusCode::BAD_GATEWAY, "DISCOVERY_ERROR", e),
+    };
+    let renderer = match find_renderer(&renderers, renderer) {
+        Ok(renderer) => renderer,
+        Err(e) => return error_response(StatusCode::NOT_FOUND, "DEVICE_NOT_FOUND", e),
+    };
+    match play_file(ctx, &file, renderer).await {
+        Ok(message) => (StatusCode::OK, Json(ApiResponse::success_with_message(&message, serde_json::json!({
+            "file_id": file.file_id,
+            "title": item_title(&file),
+            "device": renderer.name,
+            "uploading": file.status != 2,
+        })))).into_response(),
+        Err(e) => error_response(StatusCode::BAD_GATEWAY, "PLAY_ERROR", e),
+    }
+}
+
+pub async fn pause_video(
+    State(ctx): State<crate::context::AppContext>,
+    Json(req): Json<DeviceControlRequest>,
+) -> impl IntoResponse {
+    info!("Handling pause video request - Device ID: {}", req.device_id);
+    
+    let player = ctx.dlna_player.lock().await;
+    match player.send_control_request(req.device_id, "pause", None).await {
+        Ok(_) => {
+            info!("Pause request sent successfully");
+            (StatusCode::OK, Json(ApiResponse::<()>::success(()))).into_response()
+        }
+        Err(e) => {
+            error!("Failed to send pause request: {}", e);
+            error_response(StatusCode::INTERNAL_SERVER_ERROR, "500", e)
+        }
+    }
+}
+
+pub async fn resume_video(
+    State(ctx): State<crate::context::AppContext>,
+    Json(req): Json<DeviceControlRequest>,
+) -> impl IntoResponse {
+    info!("Handling resume video request - Device ID: {}", req.device_id);
+    
+    let player = ctx.dlna_player.lock().await;
+    match player.send_control_request(req.device_id, "play", None).await {
+        Ok(_) => {
+            info!("Resume request sent successfully");
+            (StatusCode::OK, Json(ApiResponse::<()>::success(()))).into_response()
+        }
+        Err(e) => {
+            error!("Failed to send resume request: {}", e);
+            error_response(StatusCode::INTERNAL_SERVER_ERROR, "500", e)
+        }
+    }
+}
+
+pub async fn stop_video(
+    State(ctx): State<crate::context::AppContext>,
+    Json(req): Json<DeviceControlRequest>,
+) -> impl IntoResponse {
+    info!("Handling stop video request - Device ID: {}", req.device_id);
+    
+    let player = ctx.dlna_player.lock().await;
+    match player.send_control_request(req.device_id, "stop", None).await {
+        Ok(_) => {
+            info!("Stop request sent successfully");
+            (StatusCode::OK, Json(ApiResponse::<()>::success(()))).into_response()
+        }
+        Err(e) => {
+            error!("Failed to send stop request: {}", e);
+            error_response(StatusCode::INTERNAL_SERVER_ERROR, "500", e)
+        }
+    }
+}
+
+pub async fn hello() -> impl IntoResponse {
+    info!("Handling health check request");
+    (StatusCode::OK, "Service is alive").into_response()
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MediaItem {
+    pub goal: String,
+    pub name: String,
+    #[serde(rename = "updateId")]
+    pub update_id: String,
+    pub id: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct MediaSelections {
+    #[serde(rename = "recentlyAdded", default)]
+    pub recently_added: Vec<MediaItem>,
+    #[serde(rename = "recentlyPlayed", default)]
+    pub recently_played: Vec<MediaItem>,
+    #[serde(rename = "inProgress", default)]
+    pub in_progress: Vec<MediaItem>,
+    #[serde(rename = "mostPlayed", default)]
+    pub most_played: Vec<MediaItem>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Breadcrumb {
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Folder {
+    pub id: String,
+    pub name: String,
+    pub icon: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BrowseResponse {
+    #[serde(default)]
+    pub goal: String,
+    #[serde(rename = "mediasSelections", default)]
+    pub medias_selections: MediaSelections,
+    #[serde(default)]
+    pub umsversion: String,
+    #[serde(default)]
+    pub name: String,
+    #[serde(rename = "hasFile", default)]
+    pub has_file: bool,
+    #[serde(rename = "useWebControl", default)]
+    pub use_web_control: bool,
+    #[serde(default)]
+    pub breadcrumbs: Vec<Breadcrumb>,
+    #[serde(default)]
+    pub folders: Vec<Folder>,
+    #[serde(default)]
+    pub medias: Vec<MediaItem>,
+}
+
+/// 媒体服务器的条目 id 与本地文件无关，只能按名称和目录名过滤
+async fn hide_restricted_entries(library: &LibraryQuery, db_pool: &sqlx::SqlitePool, browse: &mut BrowseResponse) -> Result<(), String> {
+    if !library.is_restricted() {
+        return Ok(());
+    }
+    let hidden = library.hidden_names(db_pool).await?;
+    let hidden_folders = library.hidden_folder_names();
+    let visible = |item: &MediaItem| !hidden.contains(&item.name.to_lowercase());
+
+    browse.medias.retain(visible);
+    browse.medias_selections.recently_added.retain(visible);
+    browse.medias_selections.recently_played.retain(visible);
+    browse.medias_selections.in_progress.retain(visible);
+    browse.medias_selections.most_played.retain(visible);
+    browse.folders.retain(|folder| !hidden_folders.contains(&folder.name.to_lowercase()));
+    Ok(())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BrowseRequest {
+    pub id: String,
+}
+
+pub async fn browse_files(
+    State(ctx): State<crate::context::AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    Json(req): Json<BrowseRequest>,
+) -> impl IntoResponse {
+    info!("Handling browse request - ID: {}", req.id);
+
+    let db_pool = &ctx.app_state.db_pool;
+    let library = match LibraryQuery::for_client(db_pool, &ctx.config.load(), &client_principal(&client_addr)).await {
+        Ok(library) => library,
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "PROFILE_CHECK_ERROR", e),
+    };
+    
+    let player = ctx.dlna_player.lock().await;
+    match player.browse_files(req.id).await {
+        Ok(mut response) => {
+            if let Some(browse) = response.data_mut() {
+                if let Err(e) = hide_restricted_entries(&library, db_pool, browse).await {
+                    return error_response(StatusCode::INTERNAL_SERVER_ERROR, "PROFILE_CHECK_ERROR", e);
+                }
+            }
+            info!("Browse request successful");
+            (StatusCode::OK, Json(response)).into_response()
+        }
+        Err(e) => {
+            error!("Browse request failed: {}", e);
+            error_response(StatusCode::INTERNAL_SERVER_ERROR, "500", e)
+        }
+    }
 }
\ No newline at end of file
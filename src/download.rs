@@ -1,99 +1,188 @@
-use axum::{
-    extract::{Path, State},
-    http::{header, StatusCode},
-    response::IntoResponse,
-};
-use tokio::fs::File;
-use tokio::io::AsyncReadExt;
-use log::error;
-use crate::upload_dao::fetch_file_record;
-use crate::AppContext;
-
-pub async fn download_file(
-    State(ctx): State<AppContext>,
-    Path(file_id_str): Path<String>,
-) -> impl IntoResponse {
-    let db_pool = &ctx.app_state.db_pool;
-
-    // Fetch file record to get the file path
-    let (_, _, _, _, file_path) = match fetch_file_record(db_pool, &file_id_str).await {
-        Ok(record) => record,
-        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
-    };
-
-    // Open the file
-    let mut file = match File::open(&file_path).await {
-        Ok(f) => f,
-        Err(e) => {
-            error!("Failed to open file: {}", e);
-            return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to open file").into_response();
-        }
-    };
-
-    // Read the file content
-    let mut buffer = Vec::new();
-    if let Err(e) = file.read_to_end(&mut buffer).await {
-        error!("Failed to read file: {}", e);
-        return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read file").into_response();
-    }
-
-    // Return the file content as a response
-    (
-        StatusCode::OK,
-        [(header::CONTENT_TYPE, "application/octet-stream")],
-        buffer,
-    )
-        .into_response()
-}
-
-pub async fn serve_thumbnail(
-    State(ctx): State<AppContext>,
-    Path(file_id_str): Path<String>,
-) -> impl IntoResponse {
-    let db_pool = &ctx.app_state.db_pool;
-
-    // Fetch the uploaded file to get thumbnail path
-    match crate::upload_dao::fetch_uploaded_file_by_id(db_pool, &file_id_str).await {
-        Ok(Some(file)) => {
-            let thumbnail_path = match &file.thumbnail_path {
-                Some(path) => path,
-                None => {
-                    return (StatusCode::NOT_FOUND, "No thumbnail for this file").into_response();
-                }
-            };
-
-            // Open the thumbnail file
-            let mut file = match tokio::fs::File::open(&thumbnail_path).await {
-                Ok(f) => f,
-                Err(e) => {
-                    error!("Failed to open thumbnail: {}", e);
-                    return (StatusCode::NOT_FOUND, "Thumbnail not found").into_response();
-                }
-            };
-
-            // Read the file content
-            let mut buffer = Vec::new();
-            if let Err(e) = file.read_to_end(&mut buffer).await {
-                error!("Failed to read thumbnail: {}", e);
-                return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read thumbnail").into_response();
-            }
-
-            // Return with proper content type and cache headers
-            (
-                StatusCode::OK,
-                [
-                    (header::CONTENT_TYPE, "image/webp"),
-                    (header::CACHE_CONTROL, "public, max-age=86400"),
-                ],
-                buffer,
-            ).into_response()
-        }
-        Ok(None) => {
-            (StatusCode::NOT_FOUND, "File not found").into_response()
-        }
-        Err(e) => {
-            error!("Failed to fetch file for thumbnail: {}", e);
-            (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
-        }
-    }
-}
\ No newline at end of file
+use axum::{
+    extract::{ConnectInfo, Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use log::error;
+use serde::Deserialize;
+use crate::content_range::{parse_range, RangeRequest};
+use crate::download_verify::verify_response;
+use crate::file_stream::{file_body, is_disk_image};
+use crate::filename::content_disposition;
+use crate::paths::long_path;
+use crate::profiles::ensure_file_allowed;
+use crate::quarantine::QUARANTINED;
+use crate::traffic::{client_principal, record_traffic};
+use std::net::SocketAddr;
+use crate::repository::UploadRepository;
+use crate::upload_dao::UploadedFile;
+use crate::AppContext;
+
+#[derive(Deserialize)]
+pub struct DownloadQuery {
+    /// Hash the body while sending it, see `download_verify`
+    #[serde(default)]
+    verify: bool,
+}
+
+pub async fn download_file(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    Path(file_id_str): Path<String>,
+    Query(query): Query<DownloadQuery>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let db_pool = &ctx.app_state.db_pool;
+
+    if let Err(resp) = ensure_file_allowed(&ctx, &client_addr, &file_id_str).await {
+        return resp;
+    }
+
+    // Fetch file record to get the file path and the name to restore
+    let record = match db_pool.fetch_uploaded_file(&file_id_str).await {
+        Ok(Some(record)) if record.status != QUARANTINED => record,
+        Ok(Some(_)) => return (StatusCode::FORBIDDEN, "File is quarantined").into_response(),
+        Ok(None) => return (StatusCode::NOT_FOUND, "File not found").into_response(),
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
+    };
+    let response = stream_file(&ctx, &client_addr, &record, &headers).await;
+    if !query.verify || !response.status().is_success() {
+        return response;
+    }
+    verify_response(&ctx, &file_id_str, &headers, response)
+}
+
+/// Stream a stored file under its original name, honouring a single `Range`.
+/// Access checks are up to the caller.
+pub async fn stream_file(ctx: &AppContext, client_addr: &SocketAddr, record: &UploadedFile, headers: &HeaderMap) -> Response {
+    let db_pool = &ctx.app_state.db_pool;
+    let download_name = record.original_filename.as_deref().unwrap_or(&record.filename);
+
+    // Open the file
+    let mut file = match File::open(long_path(std::path::Path::new(&record.file_path))).await {
+        Ok(f) => f,
+        Err(e) => {
+            error!("Failed to open file: {}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to open file").into_response();
+        }
+    };
+
+    let file_len = match file.metadata().await {
+        Ok(metadata) => metadata.len(),
+        Err(e) => {
+            error!("Failed to read file metadata: {}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read file").into_response();
+        }
+    };
+
+    // 只支持单个区间，多区间请求按整个文件返回
+    let range = headers
+        .get(header::RANGE)
+        .and_then(|h| h.to_str().ok())
+        .map_or(RangeRequest::Full, |h| parse_range(h, file_len));
+    let (status, start, len) = match range {
+        RangeRequest::Full => (StatusCode::OK, 0, file_len),
+        RangeRequest::Partial { start, end } => (StatusCode::PARTIAL_CONTENT, start, end - start + 1),
+        RangeRequest::Unsatisfiable => return (
+            StatusCode::RANGE_NOT_SATISFIABLE,
+            [(header::CONTENT_RANGE, format!("bytes */{}", file_len))],
+        ).into_response(),
+    };
+    if start > 0 {
+        if let Err(e) = file.seek(std::io::SeekFrom::Start(start)).await {
+            error!("Failed to seek file: {}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read file").into_response();
+        }
+    }
+
+    record_traffic(db_pool, &client_principal(client_addr), 0, len).await;
+
+    // Disk images are read in bigger blocks and ahead of the client
+    let config = ctx.config.load();
+    let (buffer_size, read_ahead) = if is_disk_image(&record.filename) {
+        let read_ahead = config.image_read_ahead.div_ceil(config.image_buffer_size as u64) as usize;
+        (config.image_buffer_size, read_ahead)
+    } else {
+        (config.download_buffer_size, 0)
+    };
+
+    let mut response = (
+        status,
+        [
+            (header::CONTENT_TYPE, "application/octet-stream".to_string()),
+            (header::CONTENT_DISPOSITION, content_disposition(download_name)),
+            (header::ACCEPT_RANGES, "bytes".to_string()),
+            (header::CONTENT_LENGTH, len.to_string()),
+        ],
+        file_body(file, len, buffer_size, read_ahead),
+    )
+        .into_response();
+    if status == StatusCode::PARTIAL_CONTENT {
+        if let Ok(value) = format!("bytes {}-{}/{}", start, start + len - 1, file_len).parse() {
+            response.headers_mut().insert(header::CONTENT_RANGE, value);
+        }
+    }
+    response
+}
+
+pub async fn serve_thumbnail(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    Path(file_id_str): Path<String>,
+) -> impl IntoResponse {
+    let db_pool = &ctx.app_state.db_pool;
+
+    if let Err(resp) = ensure_file_allowed(&ctx, &client_addr, &file_id_str).await {
+        return resp;
+    }
+
+    // Fetch the uploaded file to get thumbnail path
+    match db_pool.fetch_uploaded_file(&file_id_str).await {
+        Ok(Some(file)) => thumbnail_response(&file).await,
+        Ok(None) => {
+            (StatusCode::NOT_FOUND, "File not found").into_response()
+        }
+        Err(e) => {
+            error!("Failed to fetch file for thumbnail: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
+        }
+    }
+}
+
+/// Serve a file's thumbnail. Access checks are up to the caller.
+pub async fn thumbnail_response(record: &UploadedFile) -> Response {
+    let thumbnail_path = match &record.thumbnail_path {
+        Some(path) => path,
+        None => {
+            return (StatusCode::NOT_FOUND, "No thumbnail for this file").into_response();
+        }
+    };
+
+    // Open the thumbnail file
+    let mut file = match tokio::fs::File::open(long_path(std::path::Path::new(thumbnail_path))).await {
+        Ok(f) => f,
+        Err(e) => {
+            error!("Failed to open thumbnail: {}", e);
+            return (StatusCode::NOT_FOUND, "Thumbnail not found").into_response();
+        }
+    };
+
+    // Read the file content
+    let mut buffer = Vec::new();
+    if let Err(e) = file.read_to_end(&mut buffer).await {
+        error!("Failed to read thumbnail: {}", e);
+        return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read thumbnail").into_response();
+    }
+
+    // Return with proper content type and cache headers
+    (
+        StatusCode::OK,
+        [
+            (header::CONTENT_TYPE, "image/webp"),
+            (header::CACHE_CONTROL, "public, max-age=86400"),
+        ],
+        buffer,
+    ).into_response()
+}
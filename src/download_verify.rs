@@ -0,0 +1,190 @@
+//! `?verify=true` on downloads: the body is hashed with SHA-256 while it is
+//! sent, so a client can confirm what it received without reading the file a
+//! second time. The digest arrives as the `X-Content-SHA256` trailer when the
+//! client asks for trailers (`TE: trailers`), and is kept for a while under
+//! the `X-Verify-Id` of the response for clients that can't read trailers.
+
+use axum::{
+    body::{Body, Bytes},
+    extract::{ConnectInfo, Path, State},
+    http::{header, HeaderMap, HeaderName, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use http_body::Frame;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{ready, Context, Poll};
+use std::time::{Duration, Instant};
+use crate::context::AppContext;
+use crate::helper::{error_response, ApiResponse};
+use crate::profiles::ensure_file_allowed;
+
+const DIGEST_TRAILER: HeaderName = HeaderName::from_static("x-content-sha256");
+const VERIFY_ID_HEADER: HeaderName = HeaderName::from_static("x-verify-id");
+
+/// Digests can be fetched this long after the download started
+const KEEP_VERIFICATIONS: Duration = Duration::from_secs(3600);
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Verification {
+    pub file_id: String,
+    /// Hex digest of the bytes sent, None while the download is still running
+    /// or when it was interrupted
+    pub sha256: Option<String>,
+    /// Bytes sent, recorded once the download ended or failed
+    pub bytes: u64,
+    #[serde(skip)]
+    started: Instant,
+}
+
+/// Digests of recent verified downloads by `X-Verify-Id`
+#[derive(Default)]
+pub struct Verifications {
+    entries: Mutex<HashMap<String, Verification>>,
+}
+
+impl Verifications {
+    fn start(&self, file_id: &str) -> String {
+        let id = uuid::Uuid::new_v4().to_string();
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, v| v.started.elapsed() < KEEP_VERIFICATIONS);
+        entries.insert(id.clone(), Verification {
+            file_id: file_id.to_string(),
+            sha256: None,
+            bytes: 0,
+            started: Instant::now(),
+        });
+        id
+    }
+
+    fn update(&self, id: &str, bytes: u64, sha256: Option<String>) {
+        if let Some(entry) = self.entries.lock().unwrap().get_mut(id) {
+            entry.bytes = bytes;
+            entry.sha256 = sha256;
+        }
+    }
+
+    fn get(&self, id: &str) -> Option<Verification> {
+        self.entries
+            .lock()
+            .unwrap()
+            .get(id)
+            .filter(|v| v.started.elapsed() < KEEP_VERIFICATIONS)
+            .cloned()
+    }
+}
+
+/// Passes a body through while hashing it, ending it with the digest trailer
+struct HashingBody {
+    inner: Body,
+    hasher: Option<Sha256>,
+    bytes: u64,
+    /// Content-Length of the response. The server stops polling the body once
+    /// it has sent that many bytes, so the digest is recorded right then.
+    expected: Option<u64>,
+    send_trailer: bool,
+    verifications: Arc<Verifications>,
+    id: String,
+}
+
+impl HashingBody {
+    fn finish(&mut self) -> Option<String> {
+        let digest = format!("{:x}", self.hasher.take()?.finalize());
+        self.verifications.update(&self.id, self.bytes, Some(digest.clone()));
+        Some(digest)
+    }
+}
+
+impl http_body::Body for HashingBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Frame<Bytes>, axum::Error>>> {
+        let this = self.get_mut();
+        match ready!(Pin::new(&mut this.inner).poll_frame(cx)) {
+            Some(Ok(frame)) => {
+                if let (Some(data), Some(hasher)) = (frame.data_ref(), this.hasher.as_mut()) {
+                    hasher.update(data);
+                    this.bytes += data.len() as u64;
+                    if this.expected == Some(this.bytes) {
+                        this.finish();
+                    }
+                }
+                Poll::Ready(Some(Ok(frame)))
+            }
+            Some(Err(e)) => {
+                // 读取失败时摘要不完整，不再记录
+                this.hasher = None;
+                this.verifications.update(&this.id, this.bytes, None);
+                Poll::Ready(Some(Err(e)))
+            }
+            None => {
+                let Some(digest) = this.finish() else {
+                    return Poll::Ready(None);
+                };
+                if !this.send_trailer {
+                    return Poll::Ready(None);
+                }
+                let mut trailers = HeaderMap::new();
+                if let Ok(value) = HeaderValue::from_str(&digest) {
+                    trailers.insert(DIGEST_TRAILER, value);
+                }
+                Poll::Ready(Some(Ok(Frame::trailers(trailers))))
+            }
+        }
+    }
+}
+
+/// Hash a successful download response of `file_id` while it is sent
+pub fn verify_response(ctx: &AppContext, file_id: &str, request_headers: &HeaderMap, response: Response) -> Response {
+    // 客户端声明接受 trailer 时改用分块传输，摘要随响应末尾发送
+    let send_trailer = request_headers
+        .get_all(header::TE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .any(|v| v.split(',').any(|t| t.trim().eq_ignore_ascii_case("trailers")));
+    let id = ctx.verifications.start(file_id);
+    let verifications = ctx.verifications.clone();
+    let (mut parts, inner) = response.into_parts();
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        parts.headers.insert(VERIFY_ID_HEADER, value);
+    }
+    let expected = parts.headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok());
+    if send_trailer {
+        parts.headers.remove(header::CONTENT_LENGTH);
+        parts.headers.insert(header::TRAILER, HeaderValue::from_static("x-content-sha256"));
+    }
+    let body = HashingBody {
+        inner,
+        hasher: Some(Sha256::new()),
+        bytes: 0,
+        expected: if send_trailer { None } else { expected },
+        send_trailer,
+        verifications,
+        id,
+    };
+    Response::from_parts(parts, Body::new(body))
+}
+
+/// Digest of a download started with `?verify=true`
+pub async fn get_verification(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    Path((file_id, verify_id)): Path<(String, String)>,
+) -> impl IntoResponse {
+    if let Err(resp) = ensure_file_allowed(&ctx, &client_addr, &file_id).await {
+        return resp;
+    }
+    match ctx.verifications.get(&verify_id).filter(|v| v.file_id == file_id) {
+        Some(verification) => (StatusCode::OK, Json(ApiResponse::success(verification))).into_response(),
+        None => error_response(StatusCode::NOT_FOUND, "VERIFICATION_NOT_FOUND", "Verification not found or expired".to_string()),
+    }
+}
@@ -0,0 +1,282 @@
+use axum::{
+    extract::{ConnectInfo, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, SqlitePool};
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::path::Path;
+use crate::context::AppContext;
+use crate::file_locks::fetch_locked_paths;
+use crate::helper::{error_response, ApiResponse};
+use crate::paths::{file_inode, long_path};
+use crate::profiles::ensure_unrestricted;
+use crate::upload_dao::{is_file_path_referenced, update_deduplicated_file_path, update_file_meta_info};
+
+#[derive(Debug, FromRow)]
+struct DuplicateRow {
+    file_id: String,
+    filename: String,
+    checksum: String,
+    total_size: i64,
+    file_path: String,
+    uploaded_at: i64,
+    tenant_id: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DuplicateFile {
+    pub file_id: String,
+    pub filename: String,
+    pub file_path: String,
+    #[serde(with = "crate::api::timestamp")]
+    pub uploaded_at: i64,
+    /// The stored file is gone; it neither counts towards savings nor is linked
+    pub missing: bool,
+    #[serde(skip)]
+    inode: Option<i64>,
+}
+
+/// Completed files with the same checksum and size
+#[derive(Debug, Clone, Serialize)]
+pub struct DuplicateGroup {
+    pub checksum: String,
+    pub size: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tenant_id: Option<i64>,
+    /// Oldest first; the first present file is kept when deduplicating
+    pub files: Vec<DuplicateFile>,
+    /// Distinct copies on disk, after shared paths and hard links
+    pub physical_copies: usize,
+    pub reclaimable_bytes: i64,
+}
+
+/// Group completed files by checksum and size, keeping groups with more than one file.
+/// Files of different tenants are never grouped together.
+pub async fn find_duplicates(db_pool: &SqlitePool) -> Result<Vec<DuplicateGroup>, String> {
+    let rows = sqlx::query_as::<_, DuplicateRow>(
+        "SELECT file_id, COALESCE(original_filename, filename) AS filename, checksum, total_size, file_path,
+                COALESCE(NULLIF(created_at, 0), last_updated) AS uploaded_at, tenant_id
+         FROM upload_file_meta
+         WHERE status = 2 AND (checksum, total_size, COALESCE(tenant_id, 0)) IN (
+             SELECT checksum, total_size, COALESCE(tenant_id, 0) FROM upload_file_meta WHERE status = 2
+             GROUP BY checksum, total_size, COALESCE(tenant_id, 0) HAVING COUNT(*) > 1
+         )
+         ORDER BY checksum, total_size, COALESCE(tenant_id, 0), uploaded_at, file_id"
+    )
+    .fetch_all(db_pool)
+    .await
+    .map_err(|e| {
+        error!("Failed to fetch duplicate files: {}", e);
+        "Failed to fetch duplicate files".to_string()
+    })?;
+
+    let mut groups: Vec<DuplicateGroup> = Vec::new();
+    for row in rows {
+        let metadata = tokio::fs::metadata(long_path(Path::new(&row.file_path))).await.ok();
+        let file = DuplicateFile {
+            file_id: row.file_id,
+            filename: row.filename,
+            file_path: row.file_path,
+            uploaded_at: row.uploaded_at,
+            missing: metadata.is_none(),
+            inode: metadata.as_ref().and_then(file_inode),
+        };
+        match groups.last_mut() {
+            Some(group) if group.checksum == row.checksum && group.size == row.total_size && group.tenant_id == row.tenant_id => {
+                group.files.push(file)
+            }
+            _ => groups.push(DuplicateGroup {
+                checksum: row.checksum,
+                size: row.total_size,
+                tenant_id: row.tenant_id,
+                files: vec![file],
+                physical_copies: 0,
+                reclaimable_bytes: 0,
+            }),
+        }
+    }
+
+    for group in &mut groups {
+        // 同一路径或同一 inode 的记录只占一份空间
+        let mut paths = HashSet::new();
+        let mut inodes = HashSet::new();
+        group.physical_copies = group.files
+            .iter()
+            .filter(|f| !f.missing)
+            .filter(|f| paths.insert(f.file_path.as_str()) && f.inode.is_none_or(|ino| inodes.insert(ino)))
+            .count();
+        group.reclaimable_bytes = group.physical_copies.saturating_sub(1) as i64 * group.size;
+    }
+    Ok(groups)
+}
+
+#[derive(Serialize)]
+struct DuplicateReport {
+    groups: Vec<DuplicateGroup>,
+    total_reclaimable_bytes: i64,
+}
+
+pub async fn list_duplicates(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+) -> impl IntoResponse {
+    if let Err(resp) = ensure_unrestricted(&ctx, &client_addr).await {
+        return resp;
+    }
+    match find_duplicates(&ctx.app_state.db_pool).await {
+        Ok(groups) => (StatusCode::OK, Json(ApiResponse::success(DuplicateReport {
+            total_reclaimable_bytes: groups.iter().map(|g| g.reclaimable_bytes).sum(),
+            groups,
+        }))).into_response(),
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_DUPLICATES_ERROR", e),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DedupeMode {
+    /// Replace each copy with a hard link to the kept file; records keep their paths
+    HardLink,
+    /// Point the records at the kept file and delete the copies; the file is only
+    /// removed once no record references it
+    Reference,
+}
+
+#[derive(Deserialize)]
+pub struct DedupeRequest {
+    checksums: Vec<String>,
+    mode: DedupeMode,
+}
+
+#[derive(Serialize)]
+struct DedupeResult {
+    checksum: String,
+    kept_file_id: Option<String>,
+    deduplicated: Vec<String>,
+    reclaimed_bytes: i64,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    errors: Vec<String>,
+}
+
+/// Replace `path` with a hard link to `kept` without a window where `path` is missing
+async fn replace_with_hard_link(kept: &Path, path: &Path) -> std::io::Result<()> {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".dedupe");
+    let temp = Path::new(&temp);
+    let _ = tokio::fs::remove_file(long_path(temp)).await;
+    tokio::fs::hard_link(long_path(kept), long_path(temp)).await?;
+    if let Err(e) = tokio::fs::rename(long_path(temp), long_path(path)).await {
+        let _ = tokio::fs::remove_file(long_path(temp)).await;
+        return Err(e);
+    }
+    Ok(())
+}
+
+async fn dedupe_group(db_pool: &SqlitePool, group: &DuplicateGroup, mode: DedupeMode) -> DedupeResult {
+    let mut result = DedupeResult {
+        checksum: group.checksum.clone(),
+        kept_file_id: None,
+        deduplicated: Vec::new(),
+        reclaimed_bytes: 0,
+        errors: Vec::new(),
+    };
+    let Some(kept) = group.files.iter().find(|f| !f.missing) else {
+        result.errors.push("No copy of this file is present on disk".to_string());
+        return result;
+    };
+    result.kept_file_id = Some(kept.file_id.clone());
+    let kept_path = Path::new(&kept.file_path);
+    let locked = match fetch_locked_paths(db_pool).await {
+        Ok(locked) => locked,
+        Err(e) => {
+            result.errors.push(e);
+            return result;
+        }
+    };
+
+    let mut freed_paths = HashSet::new();
+    for file in group.files.iter().filter(|f| !f.missing && f.file_id != kept.file_id) {
+        if locked.contains(&file.file_path) {
+            result.errors.push(format!("File {} is locked", file.file_id));
+            continue;
+        }
+        let already_shared = file.file_path == kept.file_path || (file.inode.is_some() && file.inode == kept.inode);
+        if already_shared && mode == DedupeMode::HardLink {
+            continue;
+        }
+        let outcome = match mode {
+            DedupeMode::HardLink => match replace_with_hard_link(kept_path, Path::new(&file.file_path)).await {
+                Ok(()) => update_file_meta_info(db_pool, &file.file_id, 0, 0, 0).await,
+                Err(e) => Err(format!("Failed to hard-link {}: {}", file.file_path, e)),
+            },
+            DedupeMode::Reference if file.file_path == kept.file_path => continue,
+            DedupeMode::Reference => update_deduplicated_file_path(db_pool, &file.file_id, &kept.file_path).await,
+        };
+        match outcome {
+            Ok(()) => {
+                result.deduplicated.push(file.file_id.clone());
+                if mode == DedupeMode::HardLink && !already_shared && freed_paths.insert(file.file_path.clone()) {
+                    result.reclaimed_bytes += group.size;
+                }
+            }
+            Err(e) => {
+                warn!("Failed to deduplicate file ID {}: {}", file.file_id, e);
+                result.errors.push(e);
+            }
+        }
+
+        // 按引用去重后，不再被任何记录使用的副本才删除
+        if mode == DedupeMode::Reference && !freed_paths.contains(&file.file_path) {
+            match is_file_path_referenced(db_pool, &file.file_path).await {
+                Ok(false) => match tokio::fs::remove_file(long_path(Path::new(&file.file_path))).await {
+                    Ok(()) => {
+                        freed_paths.insert(file.file_path.clone());
+                        if !already_shared {
+                            result.reclaimed_bytes += group.size;
+                        }
+                    }
+                    Err(e) => result.errors.push(format!("Failed to remove {}: {}", file.file_path, e)),
+                },
+                Ok(true) => {}
+                Err(e) => result.errors.push(e),
+            }
+        }
+    }
+    result
+}
+
+/// Deduplicate the selected groups, keeping the oldest present copy of each
+pub async fn deduplicate(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    Json(req): Json<DedupeRequest>,
+) -> impl IntoResponse {
+    if let Err(resp) = ensure_unrestricted(&ctx, &client_addr).await {
+        return resp;
+    }
+    if req.checksums.is_empty() {
+        return error_response(StatusCode::BAD_REQUEST, "INVALID_DEDUPE_REQUEST", "Select at least one checksum to deduplicate".to_string());
+    }
+
+    let db_pool = &ctx.app_state.db_pool;
+    let groups = match find_duplicates(db_pool).await {
+        Ok(groups) => groups,
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_DUPLICATES_ERROR", e),
+    };
+
+    let mut results = Vec::new();
+    for group in groups.iter().filter(|g| req.checksums.iter().any(|c| c.eq_ignore_ascii_case(&g.checksum))) {
+        let result = dedupe_group(db_pool, group, req.mode).await;
+        info!(
+            "Deduplicated {} files with checksum {}, reclaimed {} bytes",
+            result.deduplicated.len(), result.checksum, result.reclaimed_bytes
+        );
+        results.push(result);
+    }
+    (StatusCode::OK, Json(ApiResponse::success(results))).into_response()
+}
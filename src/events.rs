@@ -0,0 +1,40 @@
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use tokio::sync::broadcast;
+
+/// Events raised by the server for notifiers (WebPush, ...) to react to.
+/// Serialized into the outbox for events that follow a database change.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerEvent {
+    UploadCompleted {
+        file_id: String,
+        filename: String,
+        size: u64,
+        /// None in single-tenant mode
+        tenant_id: Option<i64>,
+        /// Name of the inbox an anonymous visitor uploaded the file through
+        inbox: Option<String>,
+    },
+    /// Free space on the uploads or cold storage volume dropped below
+    /// `NASCRAFT_LOW_DISK_SPACE_PERCENT`, or below
+    /// `NASCRAFT_CRITICAL_DISK_SPACE_PERCENT` when `critical`
+    LowDiskSpace {
+        path: PathBuf,
+        available_bytes: u64,
+        total_bytes: u64,
+        critical: bool,
+        /// Uploads are refused until space is freed
+        read_only: bool,
+    },
+}
+
+/// Events are dropped when nobody listens; a listener that falls more than
+/// this many events behind skips the oldest
+const EVENT_CAPACITY: usize = 256;
+
+pub type EventSender = broadcast::Sender<ServerEvent>;
+
+pub fn event_channel() -> EventSender {
+    broadcast::channel(EVENT_CAPACITY).0
+}
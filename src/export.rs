@@ -0,0 +1,491 @@
+use axum::{
+    extract::{ConnectInfo, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use log::{error, info, warn};
+use md5::{Digest, Md5};
+use serde::Deserialize;
+use sqlx::{FromRow, SqlitePool};
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use crate::context::AppContext;
+use crate::folders::fetch_folder;
+use crate::helper::{error_response, ApiResponse};
+use crate::jobs::{create_job, fetch_job, finish_job, update_job_progress, JobFailure};
+use crate::paths::long_path;
+use crate::profiles::ensure_unrestricted;
+
+const COPY_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// How often the progress of a running export is saved
+const PROGRESS_FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Deserialize)]
+pub struct ExportRequest {
+    #[serde(default)]
+    file_ids: Vec<String>,
+    /// Every completed file stored in these folders is exported
+    #[serde(default)]
+    folder_ids: Vec<i64>,
+    /// Existing directory, e.g. where a USB disk is mounted, below one of
+    /// `NASCRAFT_EXPORT_ROOTS`
+    destination: String,
+    /// Replace files already at the destination instead of skipping them
+    #[serde(default)]
+    overwrite: bool,
+}
+
+#[derive(Debug, FromRow)]
+struct ExportFile {
+    file_id: String,
+    filename: String,
+    file_path: String,
+    checksum: String,
+    total_size: i64,
+}
+
+/// Completed files selected by ID or folder, one per stored path
+async fn fetch_export_files(db_pool: &SqlitePool, file_ids: &[String], folder_ids: &[i64]) -> Result<Vec<ExportFile>, String> {
+    let mut files = Vec::new();
+    for file_id in file_ids {
+        let file = sqlx::query_as::<_, ExportFile>(
+            "SELECT file_id, filename, file_path, checksum, total_size FROM upload_file_meta WHERE file_id = ? AND status = 2"
+        )
+        .bind(file_id)
+        .fetch_optional(db_pool)
+        .await
+        .map_err(|e| {
+            error!("Failed to fetch file for export: {}", e);
+            "Failed to fetch files".to_string()
+        })?;
+        files.push(file.ok_or_else(|| format!("File {} not found or not completed", file_id))?);
+    }
+    for folder_id in folder_ids {
+        let folder_files = sqlx::query_as::<_, ExportFile>(
+            "SELECT file_id, filename, file_path, checksum, total_size FROM upload_file_meta WHERE folder_id = ? AND status = 2 ORDER BY file_id"
+        )
+        .bind(folder_id)
+        .fetch_all(db_pool)
+        .await
+        .map_err(|e| {
+            error!("Failed to fetch folder files for export: {}", e);
+            "Failed to fetch files".to_string()
+        })?;
+        files.extend(folder_files);
+    }
+    // 同名重复上传与去重后的记录共用同一路径，只复制一次
+    let mut seen = HashSet::new();
+    files.retain(|file| seen.insert(file.file_path.clone()));
+    Ok(files)
+}
+
+/// Where a stored file goes under the destination: its stored path, relative
+/// to the working directory
+fn export_target(destination: &Path, file_path: &str) -> PathBuf {
+    let relative: PathBuf = Path::new(file_path)
+        .components()
+        .filter(|c| matches!(c, std::path::Component::Normal(_)))
+        .collect();
+    destination.join(relative)
+}
+
+/// `destination` with links resolved, when it is one of `roots` or below one
+async fn allowed_destination(roots: &[PathBuf], destination: &Path) -> Option<PathBuf> {
+    let destination = tokio::fs::canonicalize(destination).await.ok()?;
+    for root in roots {
+        // 两边都解析符号链接和 ..，避免借链接跳出导出目录
+        if let Ok(root) = tokio::fs::canonicalize(root).await {
+            if destination.starts_with(&root) {
+                return Some(destination);
+            }
+        }
+    }
+    None
+}
+
+/// Running totals of an export, saved about once a second
+struct ExportProgress<'a> {
+    db_pool: &'a SqlitePool,
+    job_id: i64,
+    done_files: i64,
+    done_bytes: i64,
+    failures: Vec<JobFailure>,
+    /// Files left alone because the destination already had them
+    skipped: usize,
+    last_flush: Instant,
+}
+
+impl ExportProgress<'_> {
+    async fn add_bytes(&mut self, n: u64) {
+        self.done_bytes += n as i64;
+        if self.last_flush.elapsed() >= PROGRESS_FLUSH_INTERVAL {
+            self.flush().await;
+        }
+    }
+
+    async fn flush(&mut self) {
+        // 进度写入失败不影响复制本身
+        let _ = update_job_progress(self.db_pool, self.job_id, self.done_files, self.done_bytes, &self.failures).await;
+        self.last_flush = Instant::now();
+    }
+}
+
+/// Where a file is written until its copy is verified
+fn partial_path(target: &Path) -> PathBuf {
+    let mut name = target.file_name().unwrap_or_default().to_os_string();
+    name.push(".partial");
+    target.with_file_name(name)
+}
+
+async fn md5_file(path: &Path) -> std::io::Result<String> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut hasher = Md5::new();
+    let mut buf = vec![0u8; COPY_BUFFER_SIZE];
+    loop {
+        match file.read(&mut buf).await? {
+            0 => break,
+            n => hasher.update(&buf[..n]),
+        }
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Whether something, even a dangling link, is at `path`
+async fn target_exists(path: &Path) -> bool {
+    tokio::fs::symlink_metadata(long_path(path)).await.is_ok()
+}
+
+/// Copy one file, then read the copy back and compare its MD5 with the source
+/// and the recorded checksum. The copy is written next to the target and only
+/// renamed into place once verified; it is removed when anything fails.
+async fn copy_verified(file: &ExportFile, target: &Path, overwrite: bool, progress: &mut ExportProgress<'_>) -> Result<(), String> {
+    let partial = partial_path(target);
+    let result = write_verified(file, target, &partial, overwrite, progress).await;
+    if result.is_err() {
+        let _ = tokio::fs::remove_file(long_path(&partial)).await;
+    }
+    result
+}
+
+async fn write_verified(file: &ExportFile, target: &Path, partial: &Path, overwrite: bool, progress: &mut ExportProgress<'_>) -> Result<(), String> {
+    let source = long_path(Path::new(&file.file_path));
+
+    if let Some(parent) = target.parent() {
+        tokio::fs::create_dir_all(long_path(parent))
+            .await
+            .map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+    }
+    let mut reader = tokio::fs::File::open(&source)
+        .await
+        .map_err(|e| format!("Failed to open {}: {}", file.file_path, e))?;
+    let mut writer = tokio::fs::File::create(long_path(partial))
+        .await
+        .map_err(|e| format!("Failed to create {}: {}", partial.display(), e))?;
+
+    let mut hasher = Md5::new();
+    let mut buf = vec![0u8; COPY_BUFFER_SIZE];
+    loop {
+        let n = reader.read(&mut buf).await.map_err(|e| format!("Failed to read {}: {}", file.file_path, e))?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        writer.write_all(&buf[..n]).await.map_err(|e| format!("Failed to write {}: {}", partial.display(), e))?;
+        progress.add_bytes(n as u64).await;
+    }
+    // 校验前确保数据已落盘，外置磁盘可能被随时拔出
+    writer.sync_all().await.map_err(|e| format!("Failed to flush {}: {}", partial.display(), e))?;
+    drop(writer);
+
+    let source_md5 = format!("{:x}", hasher.finalize());
+    if !file.checksum.is_empty() && source_md5 != file.checksum {
+        return Err(format!("Stored file doesn't match its checksum: expected {}, read {}", file.checksum, source_md5));
+    }
+    let copy_md5 = md5_file(&long_path(partial))
+        .await
+        .map_err(|e| format!("Failed to read back {}: {}", partial.display(), e))?;
+    if copy_md5 != source_md5 {
+        return Err(format!("Copy doesn't match the source: expected {}, read back {}", source_md5, copy_md5));
+    }
+    // 复制期间目标可能已被其他程序写入
+    if !overwrite && target_exists(target).await {
+        return Err(format!("{} was created while the file was copied", target.display()));
+    }
+    tokio::fs::rename(long_path(partial), long_path(target))
+        .await
+        .map_err(|e| format!("Failed to move {} into place: {}", partial.display(), e))
+}
+
+async fn run_export(db_pool: SqlitePool, job_id: i64, destination: PathBuf, files: Vec<ExportFile>, overwrite: bool) {
+    let mut progress = ExportProgress {
+        db_pool: &db_pool,
+        job_id,
+        done_files: 0,
+        done_bytes: 0,
+        failures: Vec::new(),
+        skipped: 0,
+        last_flush: Instant::now(),
+    };
+
+    for file in &files {
+        let target = export_target(&destination, &file.file_path);
+        let copied_before = progress.done_bytes;
+        if !overwrite && target_exists(&target).await {
+            info!("Export job {} skipped file ID {}: {} already exists", job_id, file.file_id, target.display());
+            progress.skipped += 1;
+            progress.failures.push(JobFailure {
+                file_id: file.file_id.clone(),
+                filename: file.filename.clone(),
+                error: format!("Skipped, {} already exists", target.display()),
+            });
+        } else if let Err(e) = copy_verified(file, &target, overwrite, &mut progress).await {
+            warn!("Export job {} failed to copy file ID {}: {}", job_id, file.file_id, e);
+            progress.failures.push(JobFailure {
+                file_id: file.file_id.clone(),
+                filename: file.filename.clone(),
+                error: e,
+            });
+        } else {
+            info!("Export job {} copied file ID {} to {}", job_id, file.file_id, target.display());
+        }
+        // 失败或跳过的文件也按完整大小计入，使进度最终到达总量
+        progress.done_bytes = copied_before + file.total_size;
+        progress.done_files += 1;
+        progress.flush().await;
+    }
+
+    let skipped = progress.skipped;
+    let failed = progress.failures.len() - skipped;
+    let error_text = match (failed, skipped) {
+        (0, 0) => None,
+        (failed, 0) => Some(format!("{} of {} files failed", failed, files.len())),
+        (0, skipped) => Some(format!("{} of {} files were skipped because they already exist", skipped, files.len())),
+        (failed, skipped) => Some(format!("{} of {} files failed and {} were skipped because they already exist", failed, files.len(), skipped)),
+    };
+    if finish_job(&db_pool, job_id, error_text.as_deref()).await.is_ok() {
+        info!("Export job {} finished: {} files, {} failed, {} skipped", job_id, files.len(), failed, skipped);
+    }
+}
+
+/// Copy files and folders to a directory outside the library, such as a
+/// mounted USB disk, as a background job. Unrestricted profiles only.
+pub async fn export_files(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    Json(req): Json<ExportRequest>,
+) -> impl IntoResponse {
+    if let Err(resp) = ensure_unrestricted(&ctx, &client_addr).await {
+        return resp;
+    }
+    let db_pool = &ctx.app_state.db_pool;
+
+    let invalid = |message: String| error_response(StatusCode::BAD_REQUEST, "INVALID_EXPORT", message);
+    let destination = PathBuf::from(req.destination.trim());
+    if !destination.is_absolute() {
+        return invalid("destination must be an absolute path".to_string());
+    }
+    match tokio::fs::metadata(long_path(&destination)).await {
+        Ok(metadata) if metadata.is_dir() => {}
+        _ => return invalid(format!("{} is not an existing directory", destination.display())),
+    }
+    let roots = ctx.config.load().export_roots.clone();
+    if roots.is_empty() {
+        return error_response(StatusCode::FORBIDDEN, "EXPORT_DISABLED", "Exports are disabled, set NASCRAFT_EXPORT_ROOTS to allow them".to_string());
+    }
+    let Some(destination) = allowed_destination(&roots, &destination).await else {
+        return error_response(StatusCode::FORBIDDEN, "EXPORT_NOT_ALLOWED", format!(
+            "{} is not in one of the export roots set by NASCRAFT_EXPORT_ROOTS", destination.display()
+        ));
+    };
+    if req.file_ids.is_empty() && req.folder_ids.is_empty() {
+        return invalid("Select files with file_ids or folder_ids".to_string());
+    }
+    for folder_id in &req.folder_ids {
+        match fetch_folder(db_pool, *folder_id).await {
+            Ok(Some(_)) => {}
+            Ok(None) => return error_response(StatusCode::NOT_FOUND, "FOLDER_NOT_FOUND", format!("Folder {} not found", folder_id)),
+            Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_FOLDER_ERROR", e),
+        }
+    }
+
+    let files = match fetch_export_files(db_pool, &req.file_ids, &req.folder_ids).await {
+        Ok(files) => files,
+        Err(e) => return error_response(StatusCode::NOT_FOUND, "FILE_NOT_FOUND", e),
+    };
+    let total_bytes = files.iter().map(|f| f.total_size).sum();
+    let destination_text = destination.to_string_lossy();
+    let job_id = match create_job(db_pool, "export", Some(&destination_text), files.len() as i64, total_bytes).await {
+        Ok(id) => id,
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "CREATE_JOB_ERROR", e),
+    };
+    info!("Started export job {}: {} files ({} bytes) to {}", job_id, files.len(), total_bytes, destination.display());
+    tokio::spawn(run_export(db_pool.clone(), job_id, destination, files, req.overwrite));
+
+    match fetch_job(db_pool, job_id).await {
+        Ok(Some(job)) => (StatusCode::ACCEPTED, Json(ApiResponse::success(job))).into_response(),
+        Ok(None) => error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_JOB_ERROR", "Job not found".to_string()),
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_JOB_ERROR", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::init_env::{bootstrap_schema, open_memory_db_pool};
+
+    struct TestDir(PathBuf);
+
+    impl TestDir {
+        fn new() -> Self {
+            let dir = std::env::temp_dir().join(format!("nascraft-export-{}", uuid::Uuid::new_v4().simple()));
+            std::fs::create_dir_all(&dir).unwrap();
+            TestDir(dir)
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    async fn db_pool() -> SqlitePool {
+        let db_pool = open_memory_db_pool().unwrap();
+        bootstrap_schema(&db_pool).await.unwrap();
+        db_pool
+    }
+
+    /// A stored file with `content`, recorded with its MD5
+    fn stored_file(dir: &TestDir, name: &str, content: &[u8]) -> ExportFile {
+        let path = dir.0.join("uploads").join(name);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, content).unwrap();
+        ExportFile {
+            file_id: format!("id-{}", name),
+            filename: name.to_string(),
+            file_path: path.to_string_lossy().into_owned(),
+            checksum: format!("{:x}", Md5::digest(content)),
+            total_size: content.len() as i64,
+        }
+    }
+
+    fn progress(db_pool: &SqlitePool) -> ExportProgress<'_> {
+        ExportProgress {
+            db_pool,
+            job_id: 0,
+            done_files: 0,
+            done_bytes: 0,
+            failures: Vec::new(),
+            skipped: 0,
+            last_flush: Instant::now(),
+        }
+    }
+
+    #[tokio::test]
+    async fn copies_are_renamed_into_place_once_verified() {
+        let dir = TestDir::new();
+        let db_pool = db_pool().await;
+        let file = stored_file(&dir, "a.txt", b"hello");
+        let target = dir.0.join("usb").join("a.txt");
+
+        let mut progress = progress(&db_pool);
+        copy_verified(&file, &target, false, &mut progress).await.unwrap();
+        assert_eq!(std::fs::read(&target).unwrap(), b"hello");
+        assert!(!partial_path(&target).exists());
+        assert_eq!(progress.done_bytes, 5);
+    }
+
+    #[tokio::test]
+    async fn failed_copies_leave_no_partial_file() {
+        let dir = TestDir::new();
+        let db_pool = db_pool().await;
+        let target = dir.0.join("usb").join("a.txt");
+
+        // 存储的文件与记录的校验和不符：复制完成后校验失败
+        let mut file = stored_file(&dir, "a.txt", b"hello");
+        file.checksum = format!("{:x}", Md5::digest(b"other"));
+        let e = copy_verified(&file, &target, false, &mut progress(&db_pool)).await.unwrap_err();
+        assert!(e.starts_with("Stored file doesn't match its checksum"), "{}", e);
+        assert!(!target.exists());
+        assert!(!partial_path(&target).exists());
+
+        // 改名前目标已存在时不覆盖（run_export 在复制前已检查过一次）
+        let file = stored_file(&dir, "b.txt", b"hello");
+        let target = dir.0.join("usb").join("b.txt");
+        std::fs::write(&target, b"theirs").unwrap();
+        let e = copy_verified(&file, &target, false, &mut progress(&db_pool)).await.unwrap_err();
+        assert!(e.ends_with("was created while the file was copied"), "{}", e);
+        assert_eq!(std::fs::read(&target).unwrap(), b"theirs");
+        assert!(!partial_path(&target).exists());
+
+        // 源文件缺失
+        let missing = ExportFile { file_path: dir.0.join("uploads/none").to_string_lossy().into_owned(), ..stored_file(&dir, "c.txt", b"") };
+        let target = dir.0.join("usb").join("c.txt");
+        assert!(copy_verified(&missing, &target, false, &mut progress(&db_pool)).await.is_err());
+        assert!(!target.exists() && !partial_path(&target).exists());
+    }
+
+    #[tokio::test]
+    async fn existing_files_are_skipped_unless_overwriting() {
+        let dir = TestDir::new();
+        let db_pool = db_pool().await;
+        let destination = dir.0.join("usb");
+        let files = || vec![stored_file(&dir, "a.txt", b"new"), stored_file(&dir, "b.txt", b"new")];
+        let target = export_target(&destination, &files()[0].file_path);
+        std::fs::create_dir_all(target.parent().unwrap()).unwrap();
+        std::fs::write(&target, b"old").unwrap();
+
+        let job_id = create_job(&db_pool, "export", None, 2, 6).await.unwrap();
+        run_export(db_pool.clone(), job_id, destination.clone(), files(), false).await;
+        assert_eq!(std::fs::read(&target).unwrap(), b"old");
+        assert_eq!(std::fs::read(export_target(&destination, &files()[1].file_path)).unwrap(), b"new");
+        let job = fetch_job(&db_pool, job_id).await.unwrap().unwrap();
+        assert_eq!(job.done_files, 2);
+        assert_eq!(job.failures.len(), 1);
+        assert_eq!(job.failures[0].file_id, "id-a.txt");
+        assert!(job.failures[0].error.starts_with("Skipped"));
+        assert_eq!(job.error.as_deref(), Some("1 of 2 files were skipped because they already exist"));
+
+        let job_id = create_job(&db_pool, "export", None, 2, 6).await.unwrap();
+        run_export(db_pool.clone(), job_id, destination.clone(), files(), true).await;
+        assert_eq!(std::fs::read(&target).unwrap(), b"new");
+        let job = fetch_job(&db_pool, job_id).await.unwrap().unwrap();
+        assert!(job.failures.is_empty());
+        assert_eq!(job.error, None);
+    }
+
+    #[tokio::test]
+    async fn destinations_must_be_in_an_export_root() {
+        let dir = TestDir::new();
+        let root = dir.0.join("media");
+        let usb = root.join("usb");
+        let outside = dir.0.join("etc");
+        for path in [&usb, &outside] {
+            std::fs::create_dir_all(path).unwrap();
+        }
+        let roots = [dir.0.join("missing"), root.clone()];
+
+        let canonical = |path: &Path| std::fs::canonicalize(path).unwrap();
+        assert_eq!(allowed_destination(&roots, &root).await, Some(canonical(&root)));
+        assert_eq!(allowed_destination(&roots, &usb).await, Some(canonical(&usb)));
+        assert_eq!(allowed_destination(&roots, &outside).await, None);
+        assert_eq!(allowed_destination(&roots, &usb.join("..").join("..").join("etc")).await, None);
+        assert_eq!(allowed_destination(&roots, &root.join("missing")).await, None);
+        // 名称以导出目录开头的兄弟目录不算在内
+        std::fs::create_dir_all(dir.0.join("media2")).unwrap();
+        assert_eq!(allowed_destination(&roots, &dir.0.join("media2")).await, None);
+        assert_eq!(allowed_destination(&[], &usb).await, None);
+
+        #[cfg(unix)]
+        {
+            let link = root.join("escape");
+            std::os::unix::fs::symlink(&outside, &link).unwrap();
+            assert_eq!(allowed_destination(&roots, &link).await, None);
+        }
+    }
+}
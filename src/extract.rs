@@ -0,0 +1,1297 @@
+//! Unpacking uploaded zip and tar(.gz) archives on the server as a background
+//! job. Entries are written below a new directory next to where the archive's
+//! files belong and registered as completed files. Entries that would leave
+//! that directory, links and encrypted entries are skipped, and the job stops
+//! once an archive holds more entries or bytes than allowed, so a small
+//! archive can't fill the disk. The sizes an archive declares are added up
+//! before anything is written and checked against `NASCRAFT_MAX_EXTRACTED_BYTES`,
+//! the free space of the volume and the tenant's quota.
+//!
+//! Bundles use the same job: a client uploading many small files streams them
+//! as one archive to `POST /api/v1/bundles`, and the entries are unpacked in
+//! place into the target folder instead of a new directory, checked against
+//! the bundle's optional index.
+
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use futures::StreamExt;
+use flate2::read::{DeflateDecoder, GzDecoder};
+use flate2::Crc;
+use log::{error, info, warn};
+use md5::{Digest, Md5};
+use serde::Deserialize;
+use sqlx::SqlitePool;
+use std::fs::{File, OpenOptions};
+use std::collections::HashMap;
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+use uuid::Uuid;
+use crate::config::AppConfig;
+use crate::context::AppContext;
+use crate::derived_files::{record_derived_file, DerivedFrom, KIND_EXTRACTED};
+use crate::disk_space::disk_space;
+use crate::filename::{sanitize_filename, SanitizePolicy};
+use crate::folders::{fetch_file_folder, fetch_visible_folder, Folder};
+use crate::helper::{error_response, ApiResponse};
+use crate::init_env::check_system_initialized;
+use crate::jobs::{create_job, fetch_job, finish_job, set_job_totals, update_job_progress, JobFailure};
+use crate::paths::{bundle_temp_path, file_inode, long_path, path_to_string};
+use crate::profiles::{ensure_file_allowed, ensure_unrestricted};
+use crate::quarantine::{check_extension, quarantine_file, quarantine_reason};
+use crate::repository::UploadRepository;
+use crate::tenants::{check_quota, current_tenant, fetch_file_tenant, tenant_usage, Tenant};
+use crate::thumbnail::{generate_thumbnail, is_image_file, ThumbnailConfig};
+use crate::traffic::client_principal;
+use crate::upload::stored_file_path;
+use crate::upload_dao::{fetch_uploaded_file_by_id, save_upload_state_to_db, set_file_placement, set_file_source, update_file_status_and_path, update_file_thumbnail_path};
+use crate::usage::record_file_usage;
+
+/// Entries (files and directories) an archive may hold
+const MAX_ENTRIES: u64 = 10_000;
+
+/// Directories an entry may be nested in
+const MAX_DEPTH: usize = 32;
+
+const COPY_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// Optional first entry of a bundle listing its files
+const BUNDLE_INDEX_NAME: &str = ".nascraft-bundle.json";
+
+const MAX_BUNDLE_INDEX_BYTES: u64 = 16 * 1024 * 1024;
+
+/// A file listed in a bundle's index
+#[derive(Deserialize)]
+struct BundleEntry {
+    path: String,
+    /// MD5 of the content; the entry is skipped when it doesn't match
+    checksum: Option<String>,
+    /// Modification time to restore, in Unix seconds
+    modified: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct BundleIndex {
+    files: Vec<BundleEntry>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum ArchiveKind {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+impl ArchiveKind {
+    /// Kind by the archive's name, and the name without the extension
+    fn detect(filename: &str) -> Option<(Self, &str)> {
+        let lower = filename.to_ascii_lowercase();
+        [(".tar.gz", Self::TarGz), (".tgz", Self::TarGz), (".tar", Self::Tar), (".zip", Self::Zip)]
+            .into_iter()
+            .find(|(ext, _)| lower.ends_with(ext) && lower.len() > ext.len())
+            .map(|(ext, kind)| (kind, &filename[..filename.len() - ext.len()]))
+    }
+}
+
+/// What the extracting thread reports for each entry
+enum Extracted {
+    File {
+        path: PathBuf,
+        original_name: String,
+        size: u64,
+        md5: String,
+    },
+    Skipped {
+        name: String,
+        error: String,
+    },
+}
+
+/// Why an entry couldn't be written: `Skip` moves on to the next entry,
+/// `Stop` ends the extraction
+enum EntryError {
+    Skip(String),
+    Stop(String),
+}
+
+impl From<std::io::Error> for EntryError {
+    fn from(e: std::io::Error) -> Self {
+        EntryError::Stop(e.to_string())
+    }
+}
+
+/// Bytes an archive may unpack to, and which limit that is
+struct ByteLimit {
+    bytes: u64,
+    reason: &'static str,
+}
+
+impl ByteLimit {
+    /// The tightest of the configured limit, the free space of the volume
+    /// holding `dir` and what is left of the tenant's quota
+    async fn for_extraction(db_pool: &SqlitePool, config: &AppConfig, dir: &std::path::Path, tenant: Option<&Tenant>) -> Result<Self, String> {
+        let mut limit = ByteLimit { bytes: config.max_extracted_bytes, reason: "the limit set by NASCRAFT_MAX_EXTRACTED_BYTES" };
+        if let Ok(space) = disk_space(dir) {
+            limit = limit.min(space.available, "the free space on the volume");
+        }
+        if let Some(tenant) = tenant {
+            if let Some(quota) = tenant.quota_bytes {
+                let used = tenant_usage(db_pool, tenant.id).await?;
+                limit = limit.min((quota - used).max(0) as u64, "what is left of the tenant's quota");
+            }
+        }
+        Ok(limit)
+    }
+
+    fn min(self, bytes: u64, reason: &'static str) -> Self {
+        if bytes < self.bytes {
+            ByteLimit { bytes, reason }
+        } else {
+            self
+        }
+    }
+
+    fn exceeded(&self) -> String {
+        format!("The archive unpacks to more than {} bytes, {}", self.bytes, self.reason)
+    }
+}
+
+/// Writes entries below `dir`, keeping count of the limits
+struct Extractor {
+    dir: PathBuf,
+    policy: SanitizePolicy,
+    /// Unpacking a bundle into a directory that may already hold files
+    bundle: bool,
+    /// Files of the bundle's index by their path below `dir`, removed as
+    /// they are unpacked
+    index: Option<HashMap<PathBuf, BundleEntry>>,
+    /// Whether a file entry was seen; a bundle's index must come before them
+    seen_file: bool,
+    entries: u64,
+    bytes: u64,
+    limit: ByteLimit,
+    tx: mpsc::Sender<Extracted>,
+}
+
+impl Extractor {
+    /// Relative path of an entry below the extraction directory, None when it
+    /// would leave it or is nested too deep
+    fn entry_path(&self, name: &str) -> Option<PathBuf> {
+        let mut path = PathBuf::new();
+        let mut depth = 0;
+        for part in name.split(['/', '\\']) {
+            match part {
+                "" | "." => continue,
+                ".." => return None,
+                part => {
+                    let safe = sanitize_filename(part, self.policy);
+                    if safe.is_empty() || safe == "." || safe == ".." {
+                        return None;
+                    }
+                    path.push(safe);
+                    depth += 1;
+                }
+            }
+        }
+        (depth > 0 && depth <= MAX_DEPTH).then_some(path)
+    }
+
+    fn count_entry(&mut self) -> Result<(), String> {
+        self.entries += 1;
+        if self.entries > MAX_ENTRIES {
+            return Err(format!("The archive has more than {} entries", MAX_ENTRIES));
+        }
+        Ok(())
+    }
+
+    fn skip(&self, name: &str, error: impl Into<String>) -> Result<(), String> {
+        self.tx
+            .blocking_send(Extracted::Skipped { name: name.to_string(), error: error.into() })
+            .map_err(|_| "Extraction was cancelled".to_string())
+    }
+
+    /// Read the index of a bundle, which is only recognized before its first file
+    fn read_index(&mut self, reader: &mut dyn Read) -> Result<(), String> {
+        let mut data = Vec::new();
+        reader
+            .take(MAX_BUNDLE_INDEX_BYTES + 1)
+            .read_to_end(&mut data)
+            .map_err(|e| format!("Failed to read the bundle index: {}", e))?;
+        if data.len() as u64 > MAX_BUNDLE_INDEX_BYTES {
+            return Err(format!("The bundle index is larger than {} bytes", MAX_BUNDLE_INDEX_BYTES));
+        }
+        let index: BundleIndex = serde_json::from_slice(&data).map_err(|e| format!("Invalid bundle index: {}", e))?;
+        let mut files = HashMap::with_capacity(index.files.len());
+        for entry in index.files {
+            let Some(relative) = self.entry_path(&entry.path) else {
+                return Err(format!("The bundle index lists an unsafe path: {}", entry.path));
+            };
+            files.insert(relative, entry);
+        }
+        self.index = Some(files);
+        Ok(())
+    }
+
+    /// Write one file entry from `reader`. `expected` is the size and CRC-32
+    /// the archive declares for it, when it does.
+    fn write_file(&mut self, name: &str, reader: &mut dyn Read, expected: Option<(u64, u32)>) -> Result<(), String> {
+        if self.bundle && !self.seen_file && name.trim_start_matches("./") == BUNDLE_INDEX_NAME {
+            return self.read_index(reader);
+        }
+        self.seen_file = true;
+        let Some(relative) = self.entry_path(name) else {
+            return self.skip(name, "Unsafe path");
+        };
+        let listed = match self.index.as_mut() {
+            Some(index) => match index.remove(&relative) {
+                Some(entry) => Some(entry),
+                None => return self.skip(name, "Not listed in the bundle index"),
+            },
+            None => None,
+        };
+        let path = self.dir.join(&relative);
+        let bundle = self.bundle;
+        let mut created = false;
+        let result = (|| -> Result<(u64, String), EntryError> {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(long_path(parent))?;
+            }
+            let mut file = match OpenOptions::new().write(true).create_new(true).open(long_path(&path)) {
+                Ok(file) => file,
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists && bundle => {
+                    return Err(EntryError::Skip("A file with this name already exists".to_string()));
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                    return Err(EntryError::Skip("The archive has another entry with this name".to_string()));
+                }
+                Err(e) => return Err(e.into()),
+            };
+            created = true;
+            let mut md5 = Md5::new();
+            let mut crc = Crc::new();
+            let mut size = 0u64;
+            let mut buf = vec![0u8; COPY_BUFFER_SIZE];
+            loop {
+                let n = match reader.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(n) => n,
+                    Err(e) => return Err(EntryError::Skip(format!("Failed to read entry: {}", e))),
+                };
+                size += n as u64;
+                if self.bytes + size > self.limit.bytes {
+                    return Err(EntryError::Stop(self.limit.exceeded()));
+                }
+                md5.update(&buf[..n]);
+                crc.update(&buf[..n]);
+                file.write_all(&buf[..n])?;
+            }
+            if expected.is_some_and(|expected| expected != (size, crc.sum())) {
+                return Err(EntryError::Skip("The entry is damaged: checksum mismatch".to_string()));
+            }
+            let md5 = format!("{:x}", md5.finalize());
+            if let Some(entry) = &listed {
+                if entry.checksum.as_deref().is_some_and(|checksum| !checksum.trim().eq_ignore_ascii_case(&md5)) {
+                    return Err(EntryError::Skip(format!("Checksum mismatch: the index lists {}", entry.checksum.as_deref().unwrap_or_default())));
+                }
+                if let Some(modified) = entry.modified.filter(|&m| m >= 0) {
+                    file.set_modified(UNIX_EPOCH + Duration::from_secs(modified as u64))?;
+                }
+            }
+            file.sync_all()?;
+            Ok((size, md5))
+        })();
+        match result {
+            Ok((size, md5)) => {
+                self.bytes += size;
+                let original_name = name.rsplit(['/', '\\']).find(|p| !p.is_empty()).unwrap_or(name).to_string();
+                self.tx
+                    .blocking_send(Extracted::File { path, original_name, size, md5 })
+                    .map_err(|_| "Extraction was cancelled".to_string())
+            }
+            Err(e) => {
+                if created {
+                    let _ = std::fs::remove_file(long_path(&path));
+                }
+                match e {
+                    EntryError::Skip(e) => self.skip(name, e),
+                    EntryError::Stop(e) => Err(e),
+                }
+            }
+        }
+    }
+
+    fn extract(&mut self, kind: ArchiveKind, archive: &std::path::Path) -> Result<(), String> {
+        let open = || File::open(long_path(archive)).map_err(|e| format!("Failed to open the archive: {}", e));
+        match kind {
+            ArchiveKind::Zip => self.extract_zip(open()?)?,
+            ArchiveKind::Tar => {
+                tar_declared_size(BufReader::new(open()?), &self.limit)?;
+                self.extract_tar(BufReader::new(open()?))?
+            }
+            ArchiveKind::TarGz => {
+                tar_declared_size(GzDecoder::new(BufReader::new(open()?)), &self.limit)?;
+                self.extract_tar(GzDecoder::new(BufReader::new(open()?)))?
+            }
+        }
+        let mut missing: Vec<String> = self.index.take().unwrap_or_default().into_values().map(|entry| entry.path).collect();
+        missing.sort();
+        for path in missing {
+            self.skip(&path, "Listed in the bundle index but missing from the bundle")?;
+        }
+        Ok(())
+    }
+
+    fn extract_tar(&mut self, mut reader: impl Read) -> Result<(), String> {
+        let mut header = [0u8; 512];
+        // GNU 长文件名与 PAX 扩展头中的路径作用于下一个条目
+        let mut next_name: Option<String> = None;
+        loop {
+            if let Err(e) = reader.read_exact(&mut header) {
+                // 缺少结尾的空块也视为结束
+                if e.kind() == std::io::ErrorKind::UnexpectedEof && self.entries > 0 {
+                    return Ok(());
+                }
+                return Err(format!("Failed to read the archive: {}", e));
+            }
+            if header.iter().all(|&b| b == 0) {
+                return Ok(());
+            }
+            if !tar_checksum_ok(&header) {
+                return Err("Not a tar archive or the archive is damaged".to_string());
+            }
+            let size = tar_size(&header).ok_or("The archive is damaged: invalid entry size")?;
+            let padding = (512 - size % 512) % 512;
+            let typeflag = header[156];
+            let name = next_name.take().unwrap_or_else(|| tar_name(&header));
+            match typeflag {
+                b'L' | b'x' => {
+                    if size > 1024 * 1024 {
+                        return Err("The archive is damaged: extended header is too large".to_string());
+                    }
+                    let mut data = vec![0u8; size as usize];
+                    reader.read_exact(&mut data).map_err(|e| format!("Failed to read the archive: {}", e))?;
+                    next_name = if typeflag == b'L' {
+                        Some(String::from_utf8_lossy(&data).trim_end_matches('\0').to_string())
+                    } else {
+                        pax_path(&data)
+                    };
+                }
+                b'0' | 0 | b'7' => {
+                    self.count_entry()?;
+                    let mut entry = (&mut reader).take(size);
+                    self.write_file(&name, &mut entry, None)?;
+                    // 跳过未读完的数据（被跳过的条目）
+                    std::io::copy(&mut entry, &mut std::io::sink()).map_err(|e| format!("Failed to read the archive: {}", e))?;
+                }
+                b'5' => {
+                    self.count_entry()?;
+                    skip_bytes(&mut reader, size)?;
+                }
+                b'g' => skip_bytes(&mut reader, size)?,
+                _ => {
+                    self.count_entry()?;
+                    self.skip(&name, "Links and special files aren't extracted")?;
+                    skip_bytes(&mut reader, size)?;
+                }
+            }
+            skip_bytes(&mut reader, padding)?;
+        }
+    }
+
+    fn extract_zip(&mut self, mut file: File) -> Result<(), String> {
+        let entries = zip_central_directory(&mut file)?;
+        if entries.len() as u64 > MAX_ENTRIES {
+            return Err(format!("The archive has more than {} entries", MAX_ENTRIES));
+        }
+        // 每个条目最多读出目录中声明的大小，声明的总和即为上限
+        let declared = entries.iter().try_fold(0u64, |sum, e| sum.checked_add(e.size));
+        if declared.is_none_or(|declared| declared > self.limit.bytes) {
+            return Err(self.limit.exceeded());
+        }
+        for entry in entries {
+            self.count_entry()?;
+            if entry.name.ends_with('/') {
+                continue;
+            }
+            if entry.flags & 1 != 0 {
+                self.skip(&entry.name, "Encrypted entries aren't extracted")?;
+                continue;
+            }
+            // 高 16 位为 Unix 权限，符号链接不解压
+            if (entry.external_attributes >> 16) & 0o170000 == 0o120000 {
+                self.skip(&entry.name, "Links and special files aren't extracted")?;
+                continue;
+            }
+            let data_offset = zip_data_offset(&mut file, entry.header_offset)?;
+            file.seek(SeekFrom::Start(data_offset)).map_err(|e| format!("Failed to read the archive: {}", e))?;
+            let compressed = (&mut file).take(entry.compressed_size);
+            let expected = Some((entry.size, entry.crc32));
+            match entry.method {
+                0 => self.write_file(&entry.name, &mut compressed.take(entry.size), expected)?,
+                8 => self.write_file(&entry.name, &mut DeflateDecoder::new(BufReader::new(compressed)).take(entry.size), expected)?,
+                method => self.skip(&entry.name, format!("Compression method {} isn't supported", method))?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Add up the sizes of a tar archive's files without writing anything,
+/// stopping as soon as they pass `limit`
+fn tar_declared_size(mut reader: impl Read, limit: &ByteLimit) -> Result<u64, String> {
+    let mut header = [0u8; 512];
+    let mut total = 0u64;
+    loop {
+        if let Err(e) = reader.read_exact(&mut header) {
+            if e.kind() == std::io::ErrorKind::UnexpectedEof {
+                return Ok(total);
+            }
+            return Err(format!("Failed to read the archive: {}", e));
+        }
+        if header.iter().all(|&b| b == 0) || !tar_checksum_ok(&header) {
+            // 损坏的归档留给解压时报告
+            return Ok(total);
+        }
+        let size = tar_size(&header).ok_or("The archive is damaged: invalid entry size")?;
+        if matches!(header[156], b'0' | 0 | b'7') {
+            total = total.saturating_add(size);
+            if total > limit.bytes {
+                return Err(limit.exceeded());
+            }
+        }
+        skip_bytes(&mut reader, size.saturating_add((512 - size % 512) % 512))?;
+    }
+}
+
+fn skip_bytes(reader: &mut impl Read, n: u64) -> Result<(), String> {
+    std::io::copy(&mut reader.take(n), &mut std::io::sink())
+        .map_err(|e| format!("Failed to read the archive: {}", e))
+        .and_then(|skipped| if skipped == n { Ok(()) } else { Err("The archive is truncated".to_string()) })
+}
+
+fn tar_field(header: &[u8]) -> &[u8] {
+    let end = header.iter().position(|&b| b == 0).unwrap_or(header.len());
+    &header[..end]
+}
+
+fn tar_octal(field: &[u8]) -> Option<u64> {
+    let text = std::str::from_utf8(tar_field(field)).ok()?.trim();
+    if text.is_empty() {
+        return Some(0);
+    }
+    u64::from_str_radix(text, 8).ok()
+}
+
+fn tar_size(header: &[u8; 512]) -> Option<u64> {
+    let field = &header[124..136];
+    // GNU 对超过 8 GiB 的文件使用 base-256 编码
+    if field[0] & 0x80 != 0 {
+        return field[4..].iter().try_fold(0u64, |acc, &b| acc.checked_mul(256).map(|v| v + b as u64));
+    }
+    tar_octal(field)
+}
+
+fn tar_checksum_ok(header: &[u8; 512]) -> bool {
+    let Some(expected) = tar_octal(&header[148..156]) else {
+        return false;
+    };
+    let sum: u64 = header
+        .iter()
+        .enumerate()
+        .map(|(i, &b)| if (148..156).contains(&i) { b' ' as u64 } else { b as u64 })
+        .sum();
+    sum == expected
+}
+
+fn tar_name(header: &[u8; 512]) -> String {
+    let name = String::from_utf8_lossy(tar_field(&header[0..100])).into_owned();
+    // ustar 格式的长路径拆分在 prefix 字段中
+    if &header[257..262] == b"ustar" {
+        let prefix = String::from_utf8_lossy(tar_field(&header[345..500])).into_owned();
+        if !prefix.is_empty() {
+            return format!("{}/{}", prefix, name);
+        }
+    }
+    name
+}
+
+/// `path` from the records of a PAX extended header (`"<len> path=<value>\n"`)
+fn pax_path(data: &[u8]) -> Option<String> {
+    let text = String::from_utf8_lossy(data);
+    text.lines()
+        .filter_map(|record| record.split_once(' ').map(|(_, kv)| kv))
+        .find_map(|kv| kv.strip_prefix("path=").map(str::to_string))
+}
+
+struct ZipEntry {
+    name: String,
+    flags: u16,
+    method: u16,
+    crc32: u32,
+    compressed_size: u64,
+    size: u64,
+    external_attributes: u32,
+    header_offset: u64,
+}
+
+fn damaged() -> String {
+    "Not a zip archive or the archive is damaged".to_string()
+}
+
+/// `N` bytes of `data` from `at`, an error when the record is too short
+fn field<const N: usize>(data: &[u8], at: usize) -> Result<[u8; N], String> {
+    at.checked_add(N)
+        .and_then(|end| data.get(at..end))
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(damaged)
+}
+
+fn le16(data: &[u8], at: usize) -> Result<u16, String> {
+    field(data, at).map(u16::from_le_bytes)
+}
+
+fn le32(data: &[u8], at: usize) -> Result<u32, String> {
+    field(data, at).map(u32::from_le_bytes)
+}
+
+fn le64(data: &[u8], at: usize) -> Result<u64, String> {
+    field(data, at).map(u64::from_le_bytes)
+}
+
+/// `len` bytes at `offset`; only used for records of a bounded size
+fn read_at(file: &mut File, offset: u64, len: usize) -> Result<Vec<u8>, String> {
+    let mut data = vec![0u8; len];
+    file.seek(SeekFrom::Start(offset))
+        .and_then(|_| file.read_exact(&mut data))
+        .map_err(|_| damaged())?;
+    Ok(data)
+}
+
+/// Fixed part of a central directory header
+const ZIP_HEADER_BYTES: usize = 46;
+
+/// A central directory header with the longest name, extra field and comment
+const MAX_ZIP_HEADER_BYTES: u64 = ZIP_HEADER_BYTES as u64 + 3 * 0xFFFF;
+
+/// Entries listed in the central directory at the end of a zip archive
+fn zip_central_directory(file: &mut File) -> Result<Vec<ZipEntry>, String> {
+    let len = file.metadata().map_err(|e| format!("Failed to read the archive: {}", e))?.len();
+    // 目录结尾记录最多带 64 KiB 注释
+    let tail_len = len.min(22 + 65535);
+    let tail = read_at(file, len - tail_len, tail_len as usize)?;
+    let eocd = (0..tail.len().saturating_sub(21))
+        .rev()
+        .find(|&i| le32(&tail, i) == Ok(0x06054b50))
+        .ok_or_else(damaged)?;
+    let mut count = le16(&tail, eocd + 10)? as u64;
+    let mut directory_size = le32(&tail, eocd + 12)? as u64;
+    let mut directory_offset = le32(&tail, eocd + 16)? as u64;
+
+    // ZIP64：条目数或偏移超出 32 位时从 ZIP64 目录结尾记录读取
+    if count == 0xFFFF || directory_size == 0xFFFF_FFFF || directory_offset == 0xFFFF_FFFF {
+        let locator_at = (len - tail_len + eocd as u64).checked_sub(20).ok_or_else(damaged)?;
+        let locator = read_at(file, locator_at, 20)?;
+        if le32(&locator, 0)? != 0x07064b50 {
+            return Err(damaged());
+        }
+        let record = read_at(file, le64(&locator, 8)?, 56)?;
+        if le32(&record, 0)? != 0x06064b50 {
+            return Err(damaged());
+        }
+        count = le64(&record, 32)?;
+        directory_size = le64(&record, 40)?;
+        directory_offset = le64(&record, 48)?;
+    }
+    if count > MAX_ENTRIES {
+        return Err(format!("The archive has more than {} entries", MAX_ENTRIES));
+    }
+    // 目录大小来自归档本身，不能据此分配内存；逐条读取并限制在声明的条目所能占用的范围内
+    if directory_size > count * MAX_ZIP_HEADER_BYTES || directory_offset.checked_add(directory_size).is_none_or(|end| end > len) {
+        return Err(damaged());
+    }
+
+    file.seek(SeekFrom::Start(directory_offset)).map_err(|_| damaged())?;
+    let mut directory = BufReader::new(file).take(directory_size);
+    let mut entries = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let mut header = [0u8; ZIP_HEADER_BYTES];
+        directory.read_exact(&mut header).map_err(|_| damaged())?;
+        if le32(&header, 0)? != 0x02014b50 {
+            return Err(damaged());
+        }
+        let name_len = le16(&header, 28)? as usize;
+        let extra_len = le16(&header, 30)? as usize;
+        let comment_len = le16(&header, 32)? as usize;
+        let mut variable = vec![0u8; name_len + extra_len + comment_len];
+        directory.read_exact(&mut variable).map_err(|_| damaged())?;
+        let mut entry = ZipEntry {
+            name: String::from_utf8_lossy(&variable[..name_len]).into_owned(),
+            flags: le16(&header, 8)?,
+            method: le16(&header, 10)?,
+            crc32: le32(&header, 16)?,
+            compressed_size: le32(&header, 20)? as u64,
+            size: le32(&header, 24)? as u64,
+            external_attributes: le32(&header, 38)?,
+            header_offset: le32(&header, 42)? as u64,
+        };
+        // ZIP64 扩展字段按顺序给出被置为 0xFFFFFFFF 的字段
+        let mut extra = &variable[name_len..name_len + extra_len];
+        while extra.len() >= 4 {
+            let (id, field_len) = (le16(extra, 0)?, le16(extra, 2)? as usize);
+            let data = extra.get(4..4 + field_len).ok_or_else(damaged)?;
+            if id == 0x0001 {
+                let mut values = data.chunks_exact(8).map(|v| le64(v, 0));
+                for value in [&mut entry.size, &mut entry.compressed_size, &mut entry.header_offset] {
+                    if *value == 0xFFFF_FFFF {
+                        *value = values.next().ok_or_else(damaged)??;
+                    }
+                }
+            }
+            extra = &extra[4 + field_len..];
+        }
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// Where an entry's data starts, after its local header
+fn zip_data_offset(file: &mut File, header_offset: u64) -> Result<u64, String> {
+    let header = read_at(file, header_offset, 30)?;
+    if le32(&header, 0)? != 0x04034b50 {
+        return Err(damaged());
+    }
+    Ok(header_offset + 30 + le16(&header, 26)? as u64 + le16(&header, 28)? as u64)
+}
+
+/// Where extracted files are placed and who they belong to
+pub struct Placement {
+    pub folder: Option<Folder>,
+    pub tenant: Option<Tenant>,
+    pub owner: String,
+    /// Unpacking an uploaded bundle, which is removed afterwards
+    pub bundle: bool,
+    /// File the new files were made from, None for bundles
+    pub derived_from: Option<DerivedFrom>,
+}
+
+/// Record an extracted file as a completed upload, or remove it when the
+/// placement's policies refuse it. Returns the new file's ID.
+pub async fn register_file(db_pool: &SqlitePool, config: &AppConfig, placement: &Placement, path: &std::path::Path, original_name: &str, size: u64, md5: &str) -> Result<String, String> {
+    let refused = async {
+        check_extension(&config.blocked_extensions, original_name, "This server")?;
+        if let Some(folder) = &placement.folder {
+            folder.check_upload(original_name, size)?;
+        }
+        if let Some(tenant) = &placement.tenant {
+            check_quota(db_pool, tenant, size).await?;
+        }
+        Ok::<_, (&'static str, String)>(())
+    }
+    .await;
+    if let Err((_, message)) = refused {
+        let _ = tokio::fs::remove_file(long_path(path)).await;
+        return Err(message);
+    }
+
+    let file_id = Uuid::new_v4().to_string();
+    let file_path = path_to_string(path);
+    let filename = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    let quarantine = quarantine_reason(config, path, &filename).await;
+    let saved = async {
+        let mut tx = db_pool.begin().await.map_err(|e| {
+            error!("Failed to begin transaction: {}", e);
+            "Failed to begin transaction".to_string()
+        })?;
+        save_upload_state_to_db(&mut tx, &file_id, &filename, original_name, size, md5, &file_path).await?;
+        set_file_placement(&mut tx, &file_id, placement.folder.as_ref().map(|f| f.id), &placement.owner, placement.tenant.as_ref().map(|t| t.id)).await?;
+        if let Some(source) = &placement.derived_from {
+            set_file_source(&mut tx, &file_id, &source.source_id).await?;
+            record_derived_file(&mut *tx, &source.source_id, source.kind, source.params.as_ref(), &file_path, Some(&file_id)).await?;
+        }
+        match &quarantine {
+            Some(reason) => quarantine_file(&mut *tx, &file_id, 0, &file_path, reason).await?,
+            None => update_file_status_and_path(&mut *tx, &file_id, 0, 2, &file_path).await?,
+        }
+        tx.commit().await.map_err(|e| {
+            error!("Failed to commit transaction: {}", e);
+            "Failed to commit transaction".to_string()
+        })
+    }
+    .await;
+    if let Err(e) = saved {
+        let _ = tokio::fs::remove_file(long_path(path)).await;
+        return Err(e);
+    }
+
+    if let Ok(metadata) = tokio::fs::metadata(long_path(path)).await {
+        let file_mtime = metadata.modified()
+            .map(|t| t.duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_secs() as i64)
+            .unwrap_or(0);
+        let file_ctime = metadata.created()
+            .map(|t| t.duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_secs() as i64)
+            .unwrap_or(file_mtime);
+        if let Err(e) = db_pool.update_file_meta_info(&file_id, file_mtime, file_ctime, file_inode(&metadata).unwrap_or(0)).await {
+            error!("Failed to update file meta info: {}", e);
+        }
+    }
+    if quarantine.is_some() {
+        return Ok(file_id);
+    }
+    record_file_usage(db_pool, &file_id, 1).await;
+    if is_image_file(&filename) {
+        if let Some(thumbnail_path) = generate_thumbnail(&ThumbnailConfig::default(), &file_path, md5).await {
+            if let Err(e) = update_file_thumbnail_path(db_pool, &file_id, &thumbnail_path).await {
+                error!("Failed to save thumbnail path to database: {}", e);
+            }
+        }
+    }
+    Ok(file_id)
+}
+
+async fn run_extract(
+    db_pool: SqlitePool,
+    config: Arc<AppConfig>,
+    job_id: i64,
+    kind: ArchiveKind,
+    archive: PathBuf,
+    dir: PathBuf,
+    placement: Placement,
+) {
+    let bundle = placement.bundle;
+    let label = if bundle { "Bundle" } else { "Extract" };
+    let (mut done_files, mut done_bytes) = (0i64, 0i64);
+    let mut failures = Vec::new();
+    let result = match ByteLimit::for_extraction(&db_pool, &config, &dir, placement.tenant.as_ref()).await {
+        Ok(limit) => {
+            let (tx, mut rx) = mpsc::channel(16);
+            let mut extractor = Extractor {
+                dir: dir.clone(),
+                policy: config.filename_policy,
+                bundle,
+                index: None,
+                seen_file: false,
+                entries: 0,
+                bytes: 0,
+                limit,
+                tx,
+            };
+            let extraction = tokio::task::spawn_blocking({
+                let archive = archive.clone();
+                move || extractor.extract(kind, &archive)
+            });
+
+            while let Some(extracted) = rx.recv().await {
+                match extracted {
+                    Extracted::File { path, original_name, size, md5 } => {
+                        let name = path.strip_prefix(&dir).map(path_to_string).unwrap_or_else(|_| original_name.clone());
+                        if let Err(e) = register_file(&db_pool, &config, &placement, &path, &original_name, size, &md5).await {
+                            warn!("{} job {} skipped {}: {}", label, job_id, name, e);
+                            failures.push(JobFailure { file_id: String::new(), filename: name, error: e });
+                        }
+                        done_bytes += size as i64;
+                    }
+                    Extracted::Skipped { name, error } => {
+                        warn!("{} job {} skipped {}: {}", label, job_id, name, error);
+                        failures.push(JobFailure { file_id: String::new(), filename: name, error });
+                    }
+                }
+                done_files += 1;
+                let _ = update_job_progress(&db_pool, job_id, done_files, done_bytes, &failures).await;
+            }
+
+            match extraction.await {
+                Ok(result) => result,
+                Err(e) => Err(format!("Extraction stopped: {}", e)),
+            }
+        }
+        Err(e) => Err(e),
+    };
+    // 包只是传输载体，解压后不保留
+    if bundle {
+        if let Err(e) = tokio::fs::remove_file(long_path(&archive)).await {
+            warn!("Failed to remove bundle {}: {}", archive.display(), e);
+        }
+    } else if done_files == 0 {
+        // 在写入任何条目前就被拒绝时不留下空目录
+        let _ = tokio::fs::remove_dir(long_path(&dir)).await;
+    }
+    let _ = set_job_totals(&db_pool, job_id, done_files, done_bytes).await;
+    let error_text = match result {
+        Err(e) => Some(e),
+        Ok(()) if !failures.is_empty() => Some(format!("{} of {} entries were skipped", failures.len(), done_files)),
+        Ok(()) => None,
+    };
+    if finish_job(&db_pool, job_id, error_text.as_deref()).await.is_ok() {
+        info!("{} job {} finished: {} entries to {}, {:?}", label, job_id, done_files, dir.display(), error_text);
+    }
+}
+
+#[derive(Deserialize, Default)]
+pub struct ExtractRequest {
+    /// Folder to extract into; the archive's own placement when unset
+    folder_id: Option<i64>,
+}
+
+/// Unpack a completed zip or tar(.gz) archive into a new directory as a
+/// background job. Unrestricted profiles only.
+pub async fn extract_archive(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    Path(file_id): Path<String>,
+    req: Option<Json<ExtractRequest>>,
+) -> impl IntoResponse {
+    let req = req.map(|Json(r)| r).unwrap_or_default();
+    if let Err(resp) = ensure_unrestricted(&ctx, &client_addr).await {
+        return resp;
+    }
+    if let Err(resp) = ensure_file_allowed(&ctx, &client_addr, &file_id).await {
+        return resp;
+    }
+    let db_pool = &ctx.app_state.db_pool;
+
+    let archive = match fetch_uploaded_file_by_id(db_pool, &file_id).await {
+        Ok(Some(file)) if file.status == 2 => file,
+        Ok(Some(_)) => return error_response(StatusCode::CONFLICT, "FILE_NOT_COMPLETE", "File upload has not completed".to_string()),
+        Ok(None) => return error_response(StatusCode::NOT_FOUND, "FILE_NOT_FOUND", "File not found".to_string()),
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_FILE_RECORD_ERROR", e),
+    };
+    let name = archive.original_filename.as_deref().unwrap_or(&archive.filename);
+    let Some((kind, stem)) = ArchiveKind::detect(name) else {
+        return error_response(StatusCode::UNSUPPORTED_MEDIA_TYPE, "UNSUPPORTED_ARCHIVE", "Only .zip, .tar, .tar.gz and .tgz archives can be extracted".to_string());
+    };
+
+    let folder = match req.folder_id {
+        Some(id) => match fetch_visible_folder(db_pool, id).await {
+            Ok(Some(folder)) => Some(folder),
+            Ok(None) => return error_response(StatusCode::NOT_FOUND, "FOLDER_NOT_FOUND", "Folder not found".to_string()),
+            Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_FOLDER_ERROR", e),
+        },
+        None => match fetch_file_folder(db_pool, &file_id).await {
+            Ok(folder) => folder,
+            Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_FOLDER_ERROR", e),
+        },
+    };
+    let tenant = match fetch_file_tenant(db_pool, &file_id).await {
+        Ok(tenant) => tenant,
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_TENANT_ERROR", e),
+    };
+
+    // 解压到以压缩包命名的新目录，已存在时依次尝试 name (1)、name (2)...
+    let config = ctx.config.load();
+    let stem = sanitize_filename(stem, config.filename_policy);
+    let mut dir = None;
+    for n in 0..1000 {
+        let candidate = stored_file_path(folder.as_ref(), tenant.as_ref(), &match n {
+            0 => stem.clone(),
+            n => format!("{} ({})", stem, n),
+        });
+        if let Some(parent) = candidate.parent() {
+            if let Err(e) = tokio::fs::create_dir_all(long_path(parent)).await {
+                return error_response(StatusCode::INTERNAL_SERVER_ERROR, "EXTRACT_ERROR", format!("Failed to create {}: {}", parent.display(), e));
+            }
+        }
+        match tokio::fs::create_dir(long_path(&candidate)).await {
+            Ok(()) => {
+                dir = Some(candidate);
+                break;
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "EXTRACT_ERROR", format!("Failed to create directory: {}", e)),
+        }
+    }
+    let Some(dir) = dir else {
+        return error_response(StatusCode::CONFLICT, "EXTRACT_ERROR", format!("Too many directories named like {}", stem));
+    };
+
+    let dir_text = path_to_string(&dir);
+    let job_id = match create_job(db_pool, "extract", Some(&dir_text), 0, 0).await {
+        Ok(id) => id,
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "CREATE_JOB_ERROR", e),
+    };
+    info!("Started extract job {}: {} to {}", job_id, archive.file_path, dir_text);
+    let placement = Placement {
+        folder,
+        tenant,
+        owner: client_principal(&client_addr),
+        bundle: false,
+        derived_from: Some(DerivedFrom { source_id: file_id.clone(), kind: KIND_EXTRACTED, params: None }),
+    };
+    tokio::spawn(run_extract(db_pool.clone(), config, job_id, kind, PathBuf::from(&archive.file_path), dir, placement));
+
+    match fetch_job(db_pool, job_id).await {
+        Ok(Some(job)) => (StatusCode::ACCEPTED, Json(ApiResponse::success(job))).into_response(),
+        Ok(None) => error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_JOB_ERROR", "Job not found".to_string()),
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_JOB_ERROR", e),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct BundleQuery {
+    /// Folder the bundle's paths are relative to; the tenant's or the
+    /// uploads directory when unset
+    folder_id: Option<i64>,
+}
+
+/// Receive a bundle of at most `max_bytes` into `temp_path`, returning its size
+async fn receive_bundle(temp_path: &std::path::Path, max_bytes: u64, body: Body) -> Result<u64, (StatusCode, &'static str, String)> {
+    let io_error = |e: std::io::Error| (StatusCode::INTERNAL_SERVER_ERROR, "BUNDLE_WRITE_ERROR", e.to_string());
+    let mut file = tokio::fs::File::create(long_path(temp_path)).await.map_err(io_error)?;
+    let mut received = 0u64;
+    let mut stream = body.into_data_stream();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|e| (StatusCode::BAD_REQUEST, "PAYLOAD_ERROR", e.to_string()))?;
+        received += chunk.len() as u64;
+        if received > max_bytes {
+            return Err((StatusCode::PAYLOAD_TOO_LARGE, "BUNDLE_TOO_LARGE", format!("Bundles may be at most {} bytes", max_bytes)));
+        }
+        file.write_all(&chunk).await.map_err(io_error)?;
+    }
+    file.sync_all().await.map_err(io_error)?;
+    Ok(received)
+}
+
+/// Upload many small files at once: the body is a tar, tar.gz or zip archive
+/// (by `Content-Type`) whose entries are unpacked into the target folder and
+/// registered as completed uploads in one background job
+pub async fn upload_bundle(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    Query(query): Query<BundleQuery>,
+    headers: HeaderMap,
+    body: Body,
+) -> impl IntoResponse {
+    let db_pool = &ctx.app_state.db_pool;
+    if check_system_initialized(db_pool).await.is_err() {
+        return error_response(StatusCode::BAD_REQUEST, "SYSTEM_NOT_INITIALIZED", "System not initialized".to_string());
+    }
+
+    let content_type = headers.get(header::CONTENT_TYPE).and_then(|v| v.to_str().ok()).unwrap_or("");
+    let kind = match content_type.split(';').next().unwrap_or("").trim().to_ascii_lowercase().as_str() {
+        "application/zip" => ArchiveKind::Zip,
+        "application/gzip" | "application/x-gzip" | "application/x-compressed-tar" => ArchiveKind::TarGz,
+        "application/x-tar" | "application/octet-stream" | "" => ArchiveKind::Tar,
+        other => return error_response(StatusCode::UNSUPPORTED_MEDIA_TYPE, "UNSUPPORTED_ARCHIVE", format!(
+            "Bundles are sent as application/x-tar, application/gzip or application/zip, not {}",
+            other
+        )),
+    };
+    let folder = match query.folder_id {
+        Some(id) => match fetch_visible_folder(db_pool, id).await {
+            Ok(Some(folder)) => Some(folder),
+            Ok(None) => return error_response(StatusCode::NOT_FOUND, "FOLDER_NOT_FOUND", "Folder not found".to_string()),
+            Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_FOLDER_ERROR", e),
+        },
+        None => None,
+    };
+    let tenant = current_tenant();
+    let dir: PathBuf = stored_file_path(folder.as_ref(), tenant.as_ref(), "").components().collect();
+    if let Err(e) = tokio::fs::create_dir_all(long_path(&dir)).await {
+        return error_response(StatusCode::INTERNAL_SERVER_ERROR, "BUNDLE_WRITE_ERROR", format!("Failed to create {}: {}", dir.display(), e));
+    }
+
+    let temp_path = bundle_temp_path();
+    let size = match receive_bundle(&temp_path, ctx.config.load().max_extracted_bytes, body).await {
+        Ok(size) => size,
+        Err((status, code, message)) => {
+            let _ = tokio::fs::remove_file(long_path(&temp_path)).await;
+            return error_response(status, code, message);
+        }
+    };
+
+    let dir_text = path_to_string(&dir);
+    let job_id = match create_job(db_pool, "bundle", Some(&dir_text), 0, 0).await {
+        Ok(id) => id,
+        Err(e) => {
+            let _ = tokio::fs::remove_file(long_path(&temp_path)).await;
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "CREATE_JOB_ERROR", e);
+        }
+    };
+    info!("Started bundle job {}: {} bytes from {} to {}", job_id, size, client_addr, dir_text);
+    let placement = Placement {
+        folder,
+        tenant,
+        owner: client_principal(&client_addr),
+        bundle: true,
+        derived_from: None,
+    };
+    tokio::spawn(run_extract(db_pool.clone(), ctx.config.load(), job_id, kind, temp_path, dir, placement));
+
+    match fetch_job(db_pool, job_id).await {
+        Ok(Some(job)) => (StatusCode::ACCEPTED, Json(ApiResponse::success(job))).into_response(),
+        Ok(None) => error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_JOB_ERROR", "Job not found".to_string()),
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_JOB_ERROR", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Component;
+
+    struct TestDir(PathBuf);
+
+    impl TestDir {
+        fn new() -> Self {
+            let dir = std::env::temp_dir().join(format!("nascraft-extract-{}", Uuid::new_v4().simple()));
+            std::fs::create_dir_all(&dir).unwrap();
+            TestDir(dir)
+        }
+
+        /// Files written below the extraction directory
+        fn files(&self) -> Vec<String> {
+            let mut files = Vec::new();
+            let mut pending = vec![self.0.join("out")];
+            while let Some(dir) = pending.pop() {
+                for entry in std::fs::read_dir(&dir).into_iter().flatten().flatten() {
+                    let path = entry.path();
+                    if entry.file_type().unwrap().is_dir() {
+                        pending.push(path);
+                    } else {
+                        files.push(path_to_string(path.strip_prefix(self.0.join("out")).unwrap()));
+                    }
+                }
+            }
+            files.sort();
+            files
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn extractor(dir: &TestDir, limit: u64) -> (Extractor, mpsc::Receiver<Extracted>) {
+        let (tx, rx) = mpsc::channel(64);
+        let extractor = Extractor {
+            dir: dir.0.join("out"),
+            policy: SanitizePolicy::Unicode,
+            bundle: false,
+            index: None,
+            seen_file: false,
+            entries: 0,
+            bytes: 0,
+            limit: ByteLimit { bytes: limit, reason: "the test limit" },
+            tx,
+        };
+        (extractor, rx)
+    }
+
+    /// Extract `archive` and return the result with the names of the skipped entries
+    fn extract(dir: &TestDir, kind: ArchiveKind, archive: &[u8], limit: u64) -> (Result<(), String>, Vec<String>) {
+        let path = dir.0.join("archive");
+        std::fs::write(&path, archive).unwrap();
+        let (mut extractor, mut rx) = extractor(dir, limit);
+        let result = extractor.extract(kind, &path);
+        drop(extractor);
+        let mut skipped = Vec::new();
+        while let Ok(extracted) = rx.try_recv() {
+            if let Extracted::Skipped { name, .. } = extracted {
+                skipped.push(name);
+            }
+        }
+        (result, skipped)
+    }
+
+    struct ZipFile {
+        name: &'static str,
+        data: &'static [u8],
+        /// Size written to the central directory instead of the real one
+        declared_size: Option<u32>,
+        mode: u32,
+    }
+
+    fn zip_file(name: &'static str, data: &'static [u8]) -> ZipFile {
+        ZipFile { name, data, declared_size: None, mode: 0o100644 }
+    }
+
+    /// A zip archive of stored entries; `count` overrides the number of
+    /// entries the end of central directory record claims
+    fn zip(files: &[ZipFile], count: Option<u16>) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut directory = Vec::new();
+        for file in files {
+            let mut crc = Crc::new();
+            crc.update(file.data);
+            let size = file.data.len() as u32;
+            let offset = out.len() as u32;
+            out.extend_from_slice(&0x04034b50u32.to_le_bytes());
+            out.extend_from_slice(&[20, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+            for value in [crc.sum(), size, size] {
+                out.extend_from_slice(&value.to_le_bytes());
+            }
+            out.extend_from_slice(&(file.name.len() as u16).to_le_bytes());
+            out.extend_from_slice(&[0, 0]);
+            out.extend_from_slice(file.name.as_bytes());
+            out.extend_from_slice(file.data);
+
+            let declared = file.declared_size.unwrap_or(size);
+            directory.extend_from_slice(&0x02014b50u32.to_le_bytes());
+            directory.extend_from_slice(&[0x14, 0x03, 20, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+            for value in [crc.sum(), declared, declared] {
+                directory.extend_from_slice(&value.to_le_bytes());
+            }
+            directory.extend_from_slice(&(file.name.len() as u16).to_le_bytes());
+            directory.extend_from_slice(&[0; 8]);
+            directory.extend_from_slice(&(file.mode << 16).to_le_bytes());
+            directory.extend_from_slice(&offset.to_le_bytes());
+            directory.extend_from_slice(file.name.as_bytes());
+        }
+        let directory_offset = out.len() as u32;
+        out.extend_from_slice(&directory);
+        let count = count.unwrap_or(files.len() as u16);
+        out.extend_from_slice(&0x06054b50u32.to_le_bytes());
+        out.extend_from_slice(&[0; 4]);
+        out.extend_from_slice(&count.to_le_bytes());
+        out.extend_from_slice(&count.to_le_bytes());
+        out.extend_from_slice(&(directory.len() as u32).to_le_bytes());
+        out.extend_from_slice(&directory_offset.to_le_bytes());
+        out.extend_from_slice(&[0, 0]);
+        out
+    }
+
+    fn tar_entry(out: &mut Vec<u8>, name: &str, typeflag: u8, data: &[u8]) {
+        let mut header = [0u8; 512];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        header[100..107].copy_from_slice(b"0000644");
+        header[124..135].copy_from_slice(format!("{:011o}", data.len()).as_bytes());
+        header[156] = typeflag;
+        if typeflag == b'2' {
+            header[157..168].copy_from_slice(b"/etc/passwd");
+        }
+        header[148..156].copy_from_slice(b"        ");
+        let sum: u64 = header.iter().map(|&b| b as u64).sum();
+        header[148..155].copy_from_slice(format!("{:06o}\0", sum).as_bytes());
+        out.extend_from_slice(&header);
+        out.extend_from_slice(data);
+        out.resize(out.len().div_ceil(512) * 512, 0);
+    }
+
+    #[test]
+    fn entry_paths_stay_inside_the_directory() {
+        let dir = TestDir::new();
+        let (extractor, _rx) = extractor(&dir, u64::MAX);
+        let deep = vec!["d"; MAX_DEPTH + 1].join("/");
+        for name in ["../evil", "a/../../evil", "a/..", "..\\evil", "a\\..\\..\\evil", "", "./", "/", deep.as_str()] {
+            assert_eq!(extractor.entry_path(name), None, "{:?}", name);
+        }
+        // 绝对路径和盘符都落在解压目录之内
+        for (name, expected) in [
+            ("/etc/passwd", "etc/passwd"),
+            ("//server/share/x", "server/share/x"),
+            ("C:\\Windows\\system.ini", if cfg!(windows) { "C_/Windows/system.ini" } else { "C:/Windows/system.ini" }),
+            ("./a/./b", "a/b"),
+        ] {
+            let path = extractor.entry_path(name).unwrap();
+            assert!(path.components().all(|c| matches!(c, Component::Normal(_))), "{:?} became {:?}", name, path);
+            assert_eq!(path, expected.split('/').collect::<PathBuf>());
+        }
+        assert!(extractor.entry_path(&vec!["d"; MAX_DEPTH].join("/")).is_some());
+    }
+
+    #[test]
+    fn zip_entries_leaving_the_directory_are_skipped() {
+        let dir = TestDir::new();
+        let archive = zip(&[zip_file("../evil.txt", b"evil"), zip_file("/abs.txt", b"abs"), zip_file("ok.txt", b"fine")], None);
+        let (result, skipped) = extract(&dir, ArchiveKind::Zip, &archive, u64::MAX);
+        result.unwrap();
+        assert_eq!(skipped, ["../evil.txt"]);
+        assert_eq!(dir.files(), ["abs.txt", "ok.txt"]);
+        assert!(!dir.0.join("evil.txt").exists());
+    }
+
+    #[test]
+    fn link_entries_are_skipped() {
+        let dir = TestDir::new();
+        let link = ZipFile { mode: 0o120777, ..zip_file("link", b"/etc/passwd") };
+        let (result, skipped) = extract(&dir, ArchiveKind::Zip, &zip(&[link, zip_file("ok.txt", b"fine")], None), u64::MAX);
+        result.unwrap();
+        assert_eq!(skipped, ["link"]);
+        assert_eq!(dir.files(), ["ok.txt"]);
+
+        let dir = TestDir::new();
+        let mut tar = Vec::new();
+        tar_entry(&mut tar, "link", b'2', b"");
+        tar_entry(&mut tar, "hard", b'1', b"");
+        tar_entry(&mut tar, "ok.txt", b'0', b"fine");
+        tar.extend_from_slice(&[0; 1024]);
+        let (result, skipped) = extract(&dir, ArchiveKind::Tar, &tar, u64::MAX);
+        result.unwrap();
+        assert_eq!(skipped, ["link", "hard"]);
+        assert_eq!(dir.files(), ["ok.txt"]);
+    }
+
+    #[test]
+    fn damaged_zips_are_refused_before_writing() {
+        let archive = zip(&[zip_file("a.txt", b"first"), zip_file("b.txt", b"second")], None);
+        let dir = TestDir::new();
+        let (result, _) = extract(&dir, ArchiveKind::Zip, &archive[..archive.len() - 30], u64::MAX);
+        assert_eq!(result.unwrap_err(), damaged());
+        assert!(dir.files().is_empty());
+
+        // 目录结尾记录声称的条目比实际多
+        let dir = TestDir::new();
+        let (result, _) = extract(&dir, ArchiveKind::Zip, &zip(&[zip_file("a.txt", b"first")], Some(3)), u64::MAX);
+        assert_eq!(result.unwrap_err(), damaged());
+        assert!(dir.files().is_empty());
+
+        // 目录偏移指向归档之外
+        let dir = TestDir::new();
+        let mut archive = zip(&[zip_file("a.txt", b"first")], None);
+        let at = archive.len() - 6;
+        archive[at..at + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        let (result, _) = extract(&dir, ArchiveKind::Zip, &archive, u64::MAX);
+        assert_eq!(result.unwrap_err(), damaged());
+        assert!(dir.files().is_empty());
+    }
+
+    #[test]
+    fn entries_are_read_up_to_their_declared_size() {
+        let dir = TestDir::new();
+        let lying = ZipFile { declared_size: Some(4), ..zip_file("big.bin", b"0123456789") };
+        let (result, skipped) = extract(&dir, ArchiveKind::Zip, &zip(&[lying, zip_file("ok.txt", b"fine")], None), 4 + 4);
+        result.unwrap();
+        assert_eq!(skipped, ["big.bin"]);
+        assert_eq!(dir.files(), ["ok.txt"]);
+    }
+
+    #[test]
+    fn archives_over_the_limit_write_nothing() {
+        let dir = TestDir::new();
+        let archive = zip(&[zip_file("a.txt", b"0123456789"), zip_file("b.txt", b"0123456789")], None);
+        let (result, _) = extract(&dir, ArchiveKind::Zip, &archive, 15);
+        assert_eq!(result.unwrap_err(), "The archive unpacks to more than 15 bytes, the test limit");
+        assert!(dir.files().is_empty());
+
+        // 声明的大小可能溢出
+        let dir = TestDir::new();
+        let huge = |name| ZipFile { declared_size: Some(u32::MAX), ..zip_file(name, b"x") };
+        let (result, _) = extract(&dir, ArchiveKind::Zip, &zip(&[huge("a"), huge("b")], None), u32::MAX as u64);
+        assert!(result.is_err());
+        assert!(dir.files().is_empty());
+
+        let mut tar = Vec::new();
+        tar_entry(&mut tar, "a.txt", b'0', b"0123456789");
+        tar_entry(&mut tar, "b.txt", b'0', b"0123456789");
+        tar.extend_from_slice(&[0; 1024]);
+        let dir = TestDir::new();
+        let (result, _) = extract(&dir, ArchiveKind::Tar, &tar, 15);
+        assert!(result.unwrap_err().starts_with("The archive unpacks to more than 15 bytes"));
+        assert!(dir.files().is_empty());
+
+        let dir = TestDir::new();
+        let (result, _) = extract(&dir, ArchiveKind::Tar, &tar, 20);
+        result.unwrap();
+        assert_eq!(dir.files(), ["a.txt", "b.txt"]);
+    }
+}
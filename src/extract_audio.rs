@@ -0,0 +1,262 @@
+//! Saving the audio track of a video as its own file, e.g. a concert or a
+//! video podcast, as a background job. ffmpeg writes the audio next to the
+//! video (or into another folder) and it is registered as a completed file
+//! derived from the video.
+
+use axum::{
+    extract::{ConnectInfo, Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use log::{info, warn};
+use md5::{Digest, Md5};
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::SqlitePool;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::io::AsyncReadExt;
+use crate::api::Priority;
+use crate::config::AppConfig;
+use crate::context::AppContext;
+use crate::derived_files::{DerivedFrom, KIND_AUDIO};
+use crate::extract::{register_file, Placement};
+use crate::filename::{normalize_original_filename, sanitize_filename};
+use crate::folders::{fetch_file_folder, fetch_visible_folder};
+use crate::helper::{error_response, ApiResponse};
+use crate::io_scheduler::{IoClass, IoScheduler};
+use crate::jobs::{create_file_job, fetch_job, finish_job, set_job_totals, update_job_progress};
+use crate::paths::{long_path, path_to_string};
+use crate::profiles::{ensure_file_allowed, ensure_unrestricted};
+use crate::tenants::fetch_file_tenant;
+use crate::thumbnail::is_video_file;
+use crate::traffic::client_principal;
+use crate::upload::{filename_taken, numbered_filename, stored_file_path};
+use crate::upload_dao::fetch_uploaded_file_by_id;
+
+/// Audio formats a video's sound can be saved as
+pub const AUDIO_FORMATS: [&str; 3] = ["mp3", "aac", "flac"];
+
+/// Numbered names tried when the audio file's name is taken
+const MAX_NAME_ATTEMPTS: usize = 1000;
+
+const READ_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// Extension of the saved file and ffmpeg output options for each format
+fn audio_output(format: &str) -> Option<(&'static str, &'static [&'static str])> {
+    match format {
+        "mp3" => Some(("mp3", &["-vn", "-c:a", "libmp3lame", "-q:a", "2", "-f", "mp3"])),
+        "aac" => Some(("m4a", &["-vn", "-c:a", "aac", "-b:a", "192k", "-movflags", "+faststart", "-f", "ipod"])),
+        "flac" => Some(("flac", &["-vn", "-c:a", "flac", "-f", "flac"])),
+        _ => None,
+    }
+}
+
+async fn md5_file(path: &std::path::Path) -> std::io::Result<String> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut hasher = Md5::new();
+    let mut buf = vec![0u8; READ_BUFFER_SIZE];
+    loop {
+        match file.read(&mut buf).await? {
+            0 => break,
+            n => hasher.update(&buf[..n]),
+        }
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+async fn run_ffmpeg(config: &AppConfig, source: &std::path::Path, args: &[&str], output: &std::path::Path) -> Result<(), String> {
+    let result = tokio::process::Command::new(&config.ffmpeg_path)
+        .arg("-nostdin")
+        .arg("-y")
+        .arg("-i")
+        .arg(source)
+        .args(args)
+        .arg(output)
+        .output()
+        .await
+        .map_err(|e| format!("Failed to run {}: {}", config.ffmpeg_path, e))?;
+
+    if result.status.success() {
+        return Ok(());
+    }
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    let tail: Vec<&str> = stderr.lines().rev().take(5).collect();
+    Err(format!("ffmpeg exited with {}: {}", result.status, tail.into_iter().rev().collect::<Vec<_>>().join(" | ")))
+}
+
+/// An audio extraction to run in the background
+struct AudioJob {
+    id: i64,
+    source: PathBuf,
+    output: PathBuf,
+    original_name: String,
+    args: &'static [&'static str],
+    placement: Placement,
+    priority: Priority,
+}
+
+async fn run_extract_audio(db_pool: SqlitePool, config: Arc<AppConfig>, io: Arc<IoScheduler>, job: AudioJob) {
+    let result = async {
+        // 与转码共用并发限制
+        let _slot = io.acquire(IoClass::Transcode, job.priority).await;
+        run_ffmpeg(&config, &long_path(&job.source), job.args, &long_path(&job.output)).await?;
+        let size = tokio::fs::metadata(long_path(&job.output))
+            .await
+            .map_err(|e| format!("Failed to read {}: {}", job.output.display(), e))?
+            .len();
+        let md5 = md5_file(&long_path(&job.output))
+            .await
+            .map_err(|e| format!("Failed to hash {}: {}", job.output.display(), e))?;
+        let file_id = register_file(&db_pool, &config, &job.placement, &job.output, &job.original_name, size, &md5).await?;
+        Ok::<_, String>((file_id, size))
+    }
+    .await;
+
+    let error_text = match result {
+        Ok((file_id, size)) => {
+            info!("Extract audio job {} saved {} as file ID {}", job.id, job.output.display(), file_id);
+            let _ = set_job_totals(&db_pool, job.id, 1, size as i64).await;
+            let _ = update_job_progress(&db_pool, job.id, 1, size as i64, &[]).await;
+            None
+        }
+        Err(e) => {
+            warn!("Extract audio job {} failed: {}", job.id, e);
+            // register_file 已在拒绝时删除文件，其余失败在此清理
+            let _ = tokio::fs::remove_file(long_path(&job.output)).await;
+            Some(e)
+        }
+    };
+    let _ = finish_job(&db_pool, job.id, error_text.as_deref()).await;
+}
+
+#[derive(Deserialize, Default)]
+pub struct ExtractAudioRequest {
+    /// `mp3` (default), `aac` or `flac`
+    format: Option<String>,
+    /// Folder to save the audio in; the video's own placement when unset
+    folder_id: Option<i64>,
+    /// Order among waiting transcodes and extractions, `normal` when unset
+    #[serde(default)]
+    priority: Priority,
+}
+
+/// Save the audio track of a completed video as a new file, as a background
+/// job. Unrestricted profiles only.
+pub async fn extract_audio(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    Path(file_id): Path<String>,
+    req: Option<Json<ExtractAudioRequest>>,
+) -> Response {
+    let req = req.map(|Json(r)| r).unwrap_or_default();
+    if let Err(resp) = ensure_unrestricted(&ctx, &client_addr).await {
+        return resp;
+    }
+    if let Err(resp) = ensure_file_allowed(&ctx, &client_addr, &file_id).await {
+        return resp;
+    }
+    let db_pool = &ctx.app_state.db_pool;
+
+    let format = req.format.map(|f| f.trim().to_lowercase()).unwrap_or_else(|| "mp3".to_string());
+    let Some((extension, args)) = audio_output(&format) else {
+        return error_response(StatusCode::BAD_REQUEST, "INVALID_FORMAT", format!("format must be one of {}, got '{}'", AUDIO_FORMATS.join(", "), format));
+    };
+
+    let video = match fetch_uploaded_file_by_id(db_pool, &file_id).await {
+        Ok(Some(file)) if file.status == 2 => file,
+        Ok(Some(_)) => return error_response(StatusCode::CONFLICT, "FILE_NOT_COMPLETE", "File upload has not completed".to_string()),
+        Ok(None) => return error_response(StatusCode::NOT_FOUND, "FILE_NOT_FOUND", "File not found".to_string()),
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_FILE_RECORD_ERROR", e),
+    };
+    let name = video.original_filename.clone().unwrap_or_else(|| video.filename.clone());
+    if !is_video_file(&name) {
+        return error_response(StatusCode::UNSUPPORTED_MEDIA_TYPE, "NOT_A_VIDEO", "Audio can only be extracted from videos".to_string());
+    }
+
+    let folder = match req.folder_id {
+        Some(id) => match fetch_visible_folder(db_pool, id).await {
+            Ok(Some(folder)) => Some(folder),
+            Ok(None) => return error_response(StatusCode::NOT_FOUND, "FOLDER_NOT_FOUND", "Folder not found".to_string()),
+            Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_FOLDER_ERROR", e),
+        },
+        None => match fetch_file_folder(db_pool, &file_id).await {
+            Ok(folder) => folder,
+            Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_FOLDER_ERROR", e),
+        },
+    };
+    let tenant = match fetch_file_tenant(db_pool, &file_id).await {
+        Ok(tenant) => tenant,
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_TENANT_ERROR", e),
+    };
+
+    // 以视频名加音频扩展名命名，已被占用时依次尝试 name (1)、name (2)...
+    let config = ctx.config.load();
+    let stem = name.rsplit_once('.').map(|(stem, _)| stem).unwrap_or(&name);
+    let original_name = format!("{}.{}", stem, extension);
+    let safe_name = sanitize_filename(&original_name, config.filename_policy);
+    let mut output = None;
+    for n in 0..MAX_NAME_ATTEMPTS {
+        let candidate = match n {
+            0 => safe_name.clone(),
+            n => numbered_filename(&safe_name, n),
+        };
+        match filename_taken(db_pool, folder.as_ref(), tenant.as_ref(), &candidate).await {
+            Ok(true) => continue,
+            Ok(false) => {}
+            Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "EXTRACT_AUDIO_ERROR", e),
+        }
+        let path = stored_file_path(folder.as_ref(), tenant.as_ref(), &candidate);
+        if let Some(parent) = path.parent() {
+            if let Err(e) = tokio::fs::create_dir_all(long_path(parent)).await {
+                return error_response(StatusCode::INTERNAL_SERVER_ERROR, "EXTRACT_AUDIO_ERROR", format!("Failed to create {}: {}", parent.display(), e));
+            }
+        }
+        // 先创建空文件占住名字，ffmpeg 随后覆盖写入
+        match tokio::fs::OpenOptions::new().write(true).create_new(true).open(long_path(&path)).await {
+            Ok(_) => {
+                output = Some(path);
+                break;
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "EXTRACT_AUDIO_ERROR", format!("Failed to create {}: {}", path.display(), e)),
+        }
+    }
+    let Some(output) = output else {
+        return error_response(StatusCode::CONFLICT, "EXTRACT_AUDIO_ERROR", format!("Too many files named like {}", safe_name));
+    };
+
+    let output_text = path_to_string(&output);
+    let job_id = match create_file_job(db_pool, "extract_audio", &file_id, &output_text, 0, req.priority).await {
+        Ok(id) => id,
+        Err(e) => {
+            let _ = tokio::fs::remove_file(long_path(&output)).await;
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "CREATE_JOB_ERROR", e);
+        }
+    };
+    info!("Started extract audio job {}: {} to {}", job_id, video.file_path, output_text);
+    let job = AudioJob {
+        id: job_id,
+        source: PathBuf::from(&video.file_path),
+        output,
+        original_name: normalize_original_filename(&original_name),
+        args,
+        placement: Placement {
+            folder,
+            tenant,
+            owner: client_principal(&client_addr),
+            bundle: false,
+            derived_from: Some(DerivedFrom { source_id: file_id.clone(), kind: KIND_AUDIO, params: Some(json!({ "format": format })) }),
+        },
+        priority: req.priority,
+    };
+    tokio::spawn(run_extract_audio(db_pool.clone(), config, ctx.io.clone(), job));
+
+    match fetch_job(db_pool, job_id).await {
+        Ok(Some(job)) => (StatusCode::ACCEPTED, Json(ApiResponse::success(job))).into_response(),
+        Ok(None) => error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_JOB_ERROR", "Job not found".to_string()),
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_JOB_ERROR", e),
+    }
+}
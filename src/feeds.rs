@@ -0,0 +1,244 @@
+//! Feeds of recently added library files for feed readers and calendars.
+//! Items link to share links, so subscribers can open them without an API key
+//! or access to the LAN.
+
+use axum::{
+    extract::{ConnectInfo, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use serde_json::json;
+use std::net::SocketAddr;
+use crate::context::AppContext;
+use crate::helper::{error_response, format_size, xml_escape, MAX_PAGE_SIZE};
+use crate::api::{FileSortKey, SortOrder};
+use crate::library_query::LibraryQuery;
+use crate::media_library::attach_media_titles;
+use crate::shares::{feed_share, public_base_url};
+use crate::traffic::client_principal;
+use crate::upload_dao::UploadedFile;
+
+const DEFAULT_FEED_ITEMS: u32 = 50;
+
+struct FeedItem {
+    file_id: String,
+    title: String,
+    summary: String,
+    url: String,
+    thumbnail_url: Option<String>,
+    mime_type: String,
+    size: u64,
+    published: DateTime<Utc>,
+}
+
+/// Scraped title when there is one, e.g. `Show S01E02 - Pilot` or `Movie (2024)`
+pub fn item_title(file: &UploadedFile) -> String {
+    let name = file.original_filename.clone().unwrap_or_else(|| file.filename.clone());
+    let Some(media) = &file.media else {
+        return name;
+    };
+    match (media.season, media.episode) {
+        (Some(season), Some(episode)) => match &media.episode_title {
+            Some(episode_title) => format!("{} S{:02}E{:02} - {}", media.title, season, episode, episode_title),
+            None => format!("{} S{:02}E{:02}", media.title, season, episode),
+        },
+        _ => match media.year {
+            Some(year) => format!("{} ({})", media.title, year),
+            None => media.title.clone(),
+        },
+    }
+}
+
+/// Fold a content line at 75 octets (RFC 5545)
+fn ical_line(line: &str) -> String {
+    let mut folded = String::new();
+    let mut width = 0;
+    for c in line.chars() {
+        if width + c.len_utf8() > 75 {
+            folded.push_str("\r\n ");
+            width = 1;
+        }
+        folded.push(c);
+        width += c.len_utf8();
+    }
+    folded.push_str("\r\n");
+    folded
+}
+
+fn ical_text(name: &str, value: &str) -> String {
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n");
+    ical_line(&format!("{}:{}", name, escaped))
+}
+
+#[derive(Deserialize)]
+pub struct FeedQuery {
+    limit: Option<u32>,
+}
+
+struct Feed {
+    title: String,
+    base_url: String,
+    items: Vec<FeedItem>,
+}
+
+/// The newest completed files visible to the client, newest first
+async fn recent_items(
+    ctx: &AppContext,
+    client_addr: &SocketAddr,
+    headers: &HeaderMap,
+    query: &FeedQuery,
+) -> Result<Feed, Response> {
+    let db_pool = &ctx.app_state.db_pool;
+    let config = ctx.config.load();
+    let limit = query.limit.unwrap_or(DEFAULT_FEED_ITEMS).clamp(1, MAX_PAGE_SIZE);
+
+    let library = LibraryQuery::for_client(db_pool, &config, &client_principal(client_addr))
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "PROFILE_CHECK_ERROR", e))?
+        .sort(FileSortKey::Date, SortOrder::Desc)
+        .paginate(1, limit);
+    let mut files = library.fetch(db_pool).await.map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_FILES_ERROR", e))?;
+    attach_media_titles(db_pool, &mut files).await;
+
+    let base_url = public_base_url(&config, headers);
+    let mut items = Vec::with_capacity(files.len());
+    for file in files {
+        let share = feed_share(db_pool, &file.file_id).await.map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "CREATE_SHARE_ERROR", e))?;
+        let share_url = share.url(&base_url);
+        let name = file.original_filename.as_deref().unwrap_or(&file.filename);
+        let mime_type = mime_guess::from_path(name).first_or_octet_stream().essence_str().to_string();
+        let size = file.total_size.max(0) as u64;
+        let summary = file.media.as_ref()
+            .and_then(|m| m.overview.clone())
+            .unwrap_or_else(|| format!("{}, {}", name, format_size(size)));
+        items.push(FeedItem {
+            title: item_title(&file),
+            summary,
+            thumbnail_url: file.thumbnail_path.as_ref().map(|_| format!("{}/thumbnail", share_url)),
+            url: share_url,
+            mime_type,
+            size,
+            published: DateTime::from_timestamp(file.last_updated, 0).unwrap_or_default(),
+            file_id: file.file_id,
+        });
+    }
+
+    Ok(Feed {
+        title: format!("{}: recent uploads", config.mdns_instance_name),
+        base_url,
+        items,
+    })
+}
+
+/// RSS 2.0 feed of recent uploads
+pub async fn recent_rss(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Query(query): Query<FeedQuery>,
+) -> Response {
+    let feed = match recent_items(&ctx, &client_addr, &headers, &query).await {
+        Ok(feed) => feed,
+        Err(resp) => return resp,
+    };
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<rss version=\"2.0\">\n<channel>\n");
+    xml.push_str(&format!("<title>{}</title>\n", xml_escape(&feed.title)));
+    xml.push_str(&format!("<link>{}</link>\n", xml_escape(&feed.base_url)));
+    xml.push_str("<description>Files recently added to the NAS</description>\n");
+    xml.push_str(&format!("<lastBuildDate>{}</lastBuildDate>\n", Utc::now().to_rfc2822()));
+    for item in &feed.items {
+        xml.push_str("<item>\n");
+        xml.push_str(&format!("<title>{}</title>\n", xml_escape(&item.title)));
+        xml.push_str(&format!("<link>{}</link>\n", xml_escape(&item.url)));
+        xml.push_str(&format!("<guid isPermaLink=\"false\">{}</guid>\n", xml_escape(&item.file_id)));
+        xml.push_str(&format!("<pubDate>{}</pubDate>\n", item.published.to_rfc2822()));
+        xml.push_str(&format!("<description>{}</description>\n", xml_escape(&item.summary)));
+        xml.push_str(&format!(
+            "<enclosure url=\"{}\" length=\"{}\" type=\"{}\"/>\n",
+            xml_escape(&item.url), item.size, xml_escape(&item.mime_type)
+        ));
+        xml.push_str("</item>\n");
+    }
+    xml.push_str("</channel>\n</rss>\n");
+
+    (StatusCode::OK, [(header::CONTENT_TYPE, "application/rss+xml; charset=utf-8")], xml).into_response()
+}
+
+/// JSON Feed 1.1 of recent uploads
+pub async fn recent_json_feed(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Query(query): Query<FeedQuery>,
+) -> Response {
+    let feed = match recent_items(&ctx, &client_addr, &headers, &query).await {
+        Ok(feed) => feed,
+        Err(resp) => return resp,
+    };
+
+    let items: Vec<_> = feed.items.iter().map(|item| {
+        let mut entry = json!({
+            "id": item.file_id,
+            "url": item.url,
+            "title": item.title,
+            "content_text": item.summary,
+            "date_published": item.published.to_rfc3339(),
+            "attachments": [{
+                "url": item.url,
+                "mime_type": item.mime_type,
+                "size_in_bytes": item.size,
+            }],
+        });
+        if let Some(thumbnail_url) = &item.thumbnail_url {
+            entry["image"] = json!(thumbnail_url);
+        }
+        entry
+    }).collect();
+    let body = json!({
+        "version": "https://jsonfeed.org/version/1.1",
+        "title": feed.title,
+        "home_page_url": feed.base_url,
+        "items": items,
+    });
+
+    (StatusCode::OK, [(header::CONTENT_TYPE, "application/feed+json")], body.to_string()).into_response()
+}
+
+/// iCalendar of recent uploads, one event at the time each file was added
+pub async fn recent_ical(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Query(query): Query<FeedQuery>,
+) -> Response {
+    let feed = match recent_items(&ctx, &client_addr, &headers, &query).await {
+        Ok(feed) => feed,
+        Err(resp) => return resp,
+    };
+
+    let stamp = Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+    let mut ics = String::from("BEGIN:VCALENDAR\r\nVERSION:2.0\r\nPRODID:-//nascraft//recent uploads//EN\r\n");
+    ics.push_str(&ical_text("X-WR-CALNAME", &feed.title));
+    for item in &feed.items {
+        let start = item.published.format("%Y%m%dT%H%M%SZ");
+        ics.push_str("BEGIN:VEVENT\r\n");
+        ics.push_str(&format!("UID:{}@nascraft\r\n", item.file_id));
+        ics.push_str(&format!("DTSTAMP:{}\r\n", stamp));
+        ics.push_str(&format!("DTSTART:{}\r\n", start));
+        ics.push_str(&format!("DTEND:{}\r\n", start));
+        ics.push_str(&ical_text("SUMMARY", &item.title));
+        ics.push_str(&ical_text("DESCRIPTION", &item.summary));
+        ics.push_str(&ical_line(&format!("URL:{}", item.url)));
+        ics.push_str("END:VEVENT\r\n");
+    }
+    ics.push_str("END:VCALENDAR\r\n");
+
+    (StatusCode::OK, [(header::CONTENT_TYPE, "text/calendar; charset=utf-8")], ics).into_response()
+}
@@ -0,0 +1,290 @@
+//! Renaming and moving stored files. Changes to a file record are guarded by
+//! its ETag: the client sends the ETag it last saw as `If-Match`, and the
+//! change is refused with `412` when someone else changed the file since, so
+//! two clients editing the same file don't silently overwrite each other.
+
+use axum::{
+    extract::{ConnectInfo, Path, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use log::{error, info};
+use serde::{Deserialize, Deserializer};
+use sqlx::{Sqlite, Transaction};
+use std::net::SocketAddr;
+use crate::context::AppContext;
+use crate::derived_files::fetch_derived_files;
+use crate::file_locks::{ensure_unlocked, lock_token};
+use crate::filename::{normalize_original_filename, sanitize_filename};
+use crate::folders::{fetch_file_folder, fetch_visible_folder};
+use crate::helper::{error_response, ApiResponse};
+use crate::paths::{long_path, path_to_string};
+use crate::profiles::{ensure_file_allowed, ensure_unrestricted};
+use crate::quarantine::check_extension;
+use crate::tenants::fetch_file_tenant;
+use crate::upload::stored_file_path;
+use crate::upload_dao::{fetch_uploaded_file_by_id, UploadedFile};
+
+/// ETag of a file record. It changes with the content (`checksum`), with
+/// background updates (`last_updated`) and with every ETag-checked change
+/// (`revision`), which may happen within the same second.
+pub fn file_etag(checksum: &str, last_updated: i64, revision: i64) -> String {
+    format!("\"{}-{}-{}\"", checksum, last_updated, revision)
+}
+
+fn with_etag(mut response: Response, etag: &str) -> Response {
+    if let Ok(value) = HeaderValue::from_str(etag) {
+        response.headers_mut().insert(header::ETAG, value);
+    }
+    response
+}
+
+/// `412` for a change based on an outdated ETag, carrying the current one
+pub fn etag_mismatch(etag: &str) -> Response {
+    with_etag(error_response(StatusCode::PRECONDITION_FAILED, "ETAG_MISMATCH", "The file was changed by someone else, fetch it again and retry".to_string()), etag)
+}
+
+/// The response refusing a change unless `If-Match` matches `etag`. `*`
+/// matches any version; weak ETags never match.
+pub fn if_match_failure(headers: &HeaderMap, etag: &str) -> Option<Response> {
+    let Some(if_match) = headers.get(header::IF_MATCH).and_then(|v| v.to_str().ok()) else {
+        return Some(with_etag(error_response(StatusCode::PRECONDITION_REQUIRED, "IF_MATCH_REQUIRED", "Send the file's ETag in If-Match to change it".to_string()), etag));
+    };
+    if if_match.split(',').map(str::trim).any(|tag| tag == "*" || tag == etag) {
+        None
+    } else {
+        Some(etag_mismatch(etag))
+    }
+}
+
+/// Bump the revision of `file_id` if its ETag is still made of `checksum`,
+/// `last_updated` and `revision`. False when it changed in the meantime.
+pub async fn bump_revision(
+    tx: &mut Transaction<'_, Sqlite>,
+    file_id: &str,
+    checksum: &str,
+    last_updated: i64,
+    revision: i64,
+) -> Result<bool, String> {
+    sqlx::query(
+        "UPDATE upload_file_meta SET revision = revision + 1, last_updated = strftime('%s', 'now')
+         WHERE file_id = ? AND checksum = ? AND last_updated = ? AND revision = ?"
+    )
+    .bind(file_id)
+    .bind(checksum)
+    .bind(last_updated)
+    .bind(revision)
+    .execute(&mut **tx)
+    .await
+    .map(|done| done.rows_affected() > 0)
+    .map_err(|e| {
+        error!("Failed to update revision of file ID {}: {}", file_id, e);
+        "Failed to update file".to_string()
+    })
+}
+
+fn file_error(status: StatusCode, code: &str, message: String) -> Response {
+    error_response(status, code, message)
+}
+
+async fn fetch_file(db_pool: &sqlx::SqlitePool, file_id: &str) -> Result<UploadedFile, Response> {
+    match fetch_uploaded_file_by_id(db_pool, file_id).await {
+        Ok(Some(file)) => Ok(file),
+        Ok(None) => Err(file_error(StatusCode::NOT_FOUND, "FILE_NOT_FOUND", "File not found".to_string())),
+        Err(e) => Err(file_error(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_FILE_RECORD_ERROR", e)),
+    }
+}
+
+fn file_response(message: &str, mut file: UploadedFile) -> Response {
+    let etag = file_etag(&file.checksum, file.last_updated, file.revision);
+    file.etag = Some(etag.clone());
+    with_etag((StatusCode::OK, Json(ApiResponse::success_with_message(message, file))).into_response(), &etag)
+}
+
+/// A file record with its ETag
+pub async fn get_file(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    Path(file_id): Path<String>,
+) -> Response {
+    if let Err(resp) = ensure_file_allowed(&ctx, &client_addr, &file_id).await {
+        return resp;
+    }
+    let db_pool = &ctx.app_state.db_pool;
+    let mut file = match fetch_file(db_pool, &file_id).await {
+        Ok(file) => file,
+        Err(resp) => return resp,
+    };
+    match fetch_derived_files(db_pool, &file_id).await {
+        Ok(derived) => file.derived = Some(derived),
+        Err(e) => return file_error(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_DERIVED_FILES_ERROR", e),
+    }
+    file_response("Success", file)
+}
+
+/// Present but null means "no folder", absent means "keep the folder"
+fn present<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Option<i64>>, D::Error> {
+    Option::<i64>::deserialize(deserializer).map(Some)
+}
+
+#[derive(Deserialize)]
+pub struct FileUpdateRequest {
+    filename: Option<String>,
+    #[serde(default, deserialize_with = "present")]
+    folder_id: Option<Option<i64>>,
+}
+
+/// Rename a completed file and/or move it to another folder, on disk and in
+/// its record. Requires `If-Match` with the file's current ETag.
+pub async fn update_file(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    Path(file_id): Path<String>,
+    headers: HeaderMap,
+    Json(req): Json<FileUpdateRequest>,
+) -> Response {
+    if let Err(resp) = ensure_unrestricted(&ctx, &client_addr).await {
+        return resp;
+    }
+    if let Err(resp) = ensure_file_allowed(&ctx, &client_addr, &file_id).await {
+        return resp;
+    }
+    let db_pool = &ctx.app_state.db_pool;
+    let file = match fetch_file(db_pool, &file_id).await {
+        Ok(file) => file,
+        Err(resp) => return resp,
+    };
+    let etag = file_etag(&file.checksum, file.last_updated, file.revision);
+    if let Some(resp) = if_match_failure(&headers, &etag) {
+        return resp;
+    }
+    if file.status != 2 {
+        return file_error(StatusCode::CONFLICT, "FILE_NOT_COMPLETE", "Only completed files can be renamed or moved".to_string());
+    }
+
+    let config = ctx.config.load();
+    let (safe_filename, original_filename) = match &req.filename {
+        Some(name) => {
+            let original = normalize_original_filename(name);
+            let safe = sanitize_filename(name, config.filename_policy);
+            if safe.is_empty() || original.is_empty() {
+                return file_error(StatusCode::BAD_REQUEST, "INVALID_FILENAME", "Filename is empty".to_string());
+            }
+            (safe, original)
+        }
+        None => (file.filename.clone(), file.original_filename.clone().unwrap_or_else(|| file.filename.clone())),
+    };
+
+    let folder = match req.folder_id {
+        Some(Some(id)) => match fetch_visible_folder(db_pool, id).await {
+            Ok(Some(folder)) => Some(folder),
+            Ok(None) => return file_error(StatusCode::NOT_FOUND, "FOLDER_NOT_FOUND", "Folder not found".to_string()),
+            Err(e) => return file_error(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_FOLDER_ERROR", e),
+        },
+        Some(None) => None,
+        None => match fetch_file_folder(db_pool, &file_id).await {
+            Ok(folder) => folder,
+            Err(e) => return file_error(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_FOLDER_ERROR", e),
+        },
+    };
+    let tenant = match fetch_file_tenant(db_pool, &file_id).await {
+        Ok(tenant) => tenant,
+        Err(e) => return file_error(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_TENANT_ERROR", e),
+    };
+
+    // 新名称与目标目录的规则与上传时一致
+    if let Err((code, message)) = check_extension(&config.blocked_extensions, &original_filename, "This server") {
+        return file_error(StatusCode::UNPROCESSABLE_ENTITY, code, message);
+    }
+    if let Some(folder) = &folder {
+        if let Err((code, message)) = folder.check_upload(&original_filename, file.total_size as u64) {
+            return file_error(StatusCode::UNPROCESSABLE_ENTITY, code, message);
+        }
+    }
+
+    if let Err(resp) = ensure_unlocked(db_pool, &file.file_path, lock_token(&headers)).await {
+        return resp;
+    }
+    let new_path = stored_file_path(folder.as_ref(), tenant.as_ref(), &safe_filename);
+    let new_path_str = path_to_string(&new_path);
+    let moved = new_path_str != file.file_path;
+    if moved {
+        match sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM upload_file_meta WHERE file_path = ? AND file_id != ?")
+            .bind(&file.file_path)
+            .bind(&file_id)
+            .fetch_one(db_pool)
+            .await
+        {
+            Ok(0) => {}
+            Ok(_) => return file_error(StatusCode::CONFLICT, "FILE_PATH_SHARED", "Other records share this file, it can't be moved".to_string()),
+            Err(e) => {
+                error!("Failed to check records sharing {}: {}", file.file_path, e);
+                return file_error(StatusCode::INTERNAL_SERVER_ERROR, "UPDATE_FILE_ERROR", "Failed to check the file's path".to_string());
+            }
+        }
+        if tokio::fs::try_exists(long_path(&new_path)).await.unwrap_or(true) {
+            return file_error(StatusCode::CONFLICT, "FILE_EXISTS", format!("A file named '{}' already exists there", safe_filename));
+        }
+    }
+
+    let result = async {
+        let mut tx = db_pool.begin().await.map_err(|e| {
+            error!("Failed to begin transaction: {}", e);
+            "Failed to begin transaction".to_string()
+        })?;
+        if !bump_revision(&mut tx, &file_id, &file.checksum, file.last_updated, file.revision).await? {
+            return Ok(false);
+        }
+        sqlx::query("UPDATE upload_file_meta SET filename = ?, original_filename = ?, folder_id = ?, file_path = ? WHERE file_id = ?")
+            .bind(&safe_filename)
+            .bind(&original_filename)
+            .bind(folder.as_ref().map(|f| f.id))
+            .bind(&new_path_str)
+            .bind(&file_id)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| {
+                error!("Failed to update file ID {}: {}", file_id, e);
+                "Failed to update file".to_string()
+            })?;
+        // 记录更新成功后再移动磁盘文件，移动失败时回滚记录
+        if moved {
+            if let Some(parent) = new_path.parent() {
+                tokio::fs::create_dir_all(long_path(parent))
+                    .await
+                    .map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+            }
+            tokio::fs::rename(long_path(std::path::Path::new(&file.file_path)), long_path(&new_path))
+                .await
+                .map_err(|e| format!("Failed to move file: {}", e))?;
+        }
+        if let Err(e) = tx.commit().await {
+            error!("Failed to commit transaction: {}", e);
+            if moved {
+                let _ = tokio::fs::rename(long_path(&new_path), long_path(std::path::Path::new(&file.file_path))).await;
+            }
+            return Err("Failed to commit transaction".to_string());
+        }
+        Ok(true)
+    }
+    .await;
+
+    match result {
+        Ok(true) => {}
+        Ok(false) => {
+            // 校验 If-Match 之后记录又被修改
+            return match fetch_file(db_pool, &file_id).await {
+                Ok(current) => etag_mismatch(&file_etag(&current.checksum, current.last_updated, current.revision)),
+                Err(resp) => resp,
+            };
+        }
+        Err(e) => return file_error(StatusCode::INTERNAL_SERVER_ERROR, "UPDATE_FILE_ERROR", e),
+    }
+    info!("Updated file ID {}: {} -> {}", file_id, file.file_path, new_path_str);
+
+    match fetch_file(db_pool, &file_id).await {
+        Ok(file) => file_response("File updated", file),
+        Err(resp) => resp,
+    }
+}
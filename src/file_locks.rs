@@ -0,0 +1,225 @@
+use axum::{
+    extract::{ConnectInfo, Path, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::Utc;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, SqlitePool};
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use uuid::Uuid;
+use crate::context::AppContext;
+use crate::helper::{error_response, ApiResponse};
+use crate::profiles::ensure_file_allowed;
+use crate::traffic::client_principal;
+use crate::upload_dao::fetch_uploaded_file_by_id;
+
+/// Header carrying the token returned by `lock_file`, sent by the holder to
+/// refresh or release the lock and to modify the locked file
+pub const LOCK_TOKEN_HEADER: &str = "X-Lock-Token";
+
+const DEFAULT_LEASE_SECS: u64 = 300;
+const MAX_LEASE_SECS: u64 = 3600;
+
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct FileLock {
+    pub file_id: String,
+    #[serde(skip)]
+    pub token: String,
+    pub owner: String,
+    #[serde(with = "crate::api::timestamp")]
+    pub acquired_at: i64,
+    #[serde(with = "crate::api::timestamp")]
+    pub expires_at: i64,
+}
+
+pub fn lock_token(headers: &HeaderMap) -> Option<&str> {
+    headers.get(LOCK_TOKEN_HEADER).and_then(|v| v.to_str().ok()).map(str::trim).filter(|t| !t.is_empty())
+}
+
+/// Unexpired lock on any file stored at `file_path`. Locks are checked by path
+/// because records sharing a path share the content being edited.
+pub async fn fetch_lock_at_path(db_pool: &SqlitePool, file_path: &str) -> Result<Option<FileLock>, String> {
+    sqlx::query_as::<_, FileLock>(
+        "SELECT l.file_id, l.token, l.owner, l.acquired_at, l.expires_at
+         FROM file_locks l JOIN upload_file_meta m ON m.file_id = l.file_id
+         WHERE m.file_path = ? AND l.expires_at > ?
+         LIMIT 1"
+    )
+    .bind(file_path)
+    .bind(Utc::now().timestamp())
+    .fetch_optional(db_pool)
+    .await
+    .map_err(|e| {
+        error!("Failed to fetch file lock: {}", e);
+        "Failed to fetch file lock".to_string()
+    })
+}
+
+/// Paths of all files with an unexpired lock, for background jobs that skip them
+pub async fn fetch_locked_paths(db_pool: &SqlitePool) -> Result<HashSet<String>, String> {
+    sqlx::query_scalar::<_, String>(
+        "SELECT m.file_path FROM file_locks l JOIN upload_file_meta m ON m.file_id = l.file_id
+         WHERE l.expires_at > ?"
+    )
+    .bind(Utc::now().timestamp())
+    .fetch_all(db_pool)
+    .await
+    .map(|paths| paths.into_iter().collect())
+    .map_err(|e| {
+        error!("Failed to fetch locked files: {}", e);
+        "Failed to fetch locked files".to_string()
+    })
+}
+
+fn locked_response(lock: &FileLock) -> Response {
+    error_response(StatusCode::LOCKED, "FILE_LOCKED", format!("File is locked by {} until {}", lock.owner, lock.expires_at))
+}
+
+/// Reject a modification of the file at `file_path` unless it is unlocked or
+/// `token` is the token of the lock
+pub async fn ensure_unlocked(db_pool: &SqlitePool, file_path: &str, token: Option<&str>) -> Result<(), Response> {
+    match fetch_lock_at_path(db_pool, file_path).await {
+        Ok(Some(lock)) if token != Some(lock.token.as_str()) => Err(locked_response(&lock)),
+        Ok(_) => Ok(()),
+        Err(e) => Err(error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_LOCK_ERROR", e)),
+    }
+}
+
+#[derive(Deserialize, Default)]
+pub struct LockRequest {
+    lease_seconds: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct LockResponse {
+    token: String,
+    #[serde(flatten)]
+    lock: FileLock,
+}
+
+/// Acquire a lock on a completed file, or extend it when `X-Lock-Token` is the
+/// current token. The lease defaults to 5 minutes and is capped at an hour.
+pub async fn lock_file(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    Path(file_id): Path<String>,
+    headers: HeaderMap,
+    req: Option<Json<LockRequest>>,
+) -> impl IntoResponse {
+    let db_pool = &ctx.app_state.db_pool;
+    let req = req.map(|Json(r)| r).unwrap_or_default();
+    let lease = req.lease_seconds.unwrap_or(DEFAULT_LEASE_SECS);
+    if lease == 0 || lease > MAX_LEASE_SECS {
+        return error_response(StatusCode::BAD_REQUEST, "INVALID_LEASE", format!("lease_seconds must be between 1 and {}", MAX_LEASE_SECS));
+    }
+
+    if let Err(resp) = ensure_file_allowed(&ctx, &client_addr, &file_id).await {
+        return resp;
+    }
+    let file = match fetch_uploaded_file_by_id(db_pool, &file_id).await {
+        Ok(Some(file)) if file.status == 2 => file,
+        Ok(_) => return error_response(StatusCode::NOT_FOUND, "FILE_NOT_FOUND", "File not found".to_string()),
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_FILE_RECORD_ERROR", e),
+    };
+
+    let token = lock_token(&headers);
+    match fetch_lock_at_path(db_pool, &file.file_path).await {
+        // 同一路径的其他记录被锁定时同样视为冲突
+        Ok(Some(lock)) if token != Some(lock.token.as_str()) => return locked_response(&lock),
+        Ok(Some(lock)) if lock.file_id != file_id => return locked_response(&lock),
+        Ok(_) => {}
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_LOCK_ERROR", e),
+    }
+
+    let now = Utc::now().timestamp();
+    let expires_at = now + lease as i64;
+    let owner = client_principal(&client_addr);
+    let result = async {
+        let mut tx = db_pool.begin().await?;
+        sqlx::query("DELETE FROM file_locks WHERE expires_at <= ?")
+            .bind(now)
+            .execute(&mut *tx)
+            .await?;
+        // 续约只延长到期时间；已被他人抢先加锁时不覆盖
+        let lock = match token {
+            Some(token) => sqlx::query_as::<_, FileLock>(
+                "UPDATE file_locks SET expires_at = ? WHERE file_id = ? AND token = ?
+                 RETURNING file_id, token, owner, acquired_at, expires_at"
+            )
+            .bind(expires_at)
+            .bind(&file_id)
+            .bind(token)
+            .fetch_optional(&mut *tx)
+            .await?,
+            None => sqlx::query_as::<_, FileLock>(
+                "INSERT INTO file_locks (file_id, token, owner, acquired_at, expires_at) VALUES (?, ?, ?, ?, ?)
+                 ON CONFLICT(file_id) DO NOTHING
+                 RETURNING file_id, token, owner, acquired_at, expires_at"
+            )
+            .bind(&file_id)
+            .bind(Uuid::new_v4().to_string())
+            .bind(&owner)
+            .bind(now)
+            .bind(expires_at)
+            .fetch_optional(&mut *tx)
+            .await?,
+        };
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(lock)
+    }
+    .await;
+
+    match result {
+        Ok(Some(lock)) => {
+            info!("File {} locked by {} until {}", file_id, lock.owner, lock.expires_at);
+            (StatusCode::OK, Json(ApiResponse::success(LockResponse {
+                token: lock.token.clone(),
+                lock,
+            }))).into_response()
+        }
+        Ok(None) if token.is_none() => error_response(StatusCode::LOCKED, "FILE_LOCKED", "File was locked by another client".to_string()),
+        Ok(None) => error_response(StatusCode::CONFLICT, "LOCK_NOT_HELD", "The lock has expired or was taken by another client".to_string()),
+        Err(e) => {
+            error!("Failed to lock file {}: {}", file_id, e);
+            error_response(StatusCode::INTERNAL_SERVER_ERROR, "LOCK_FILE_ERROR", "Failed to lock file".to_string())
+        }
+    }
+}
+
+/// Release a lock; requires the lock's token in `X-Lock-Token`
+pub async fn unlock_file(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    Path(file_id): Path<String>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let db_pool = &ctx.app_state.db_pool;
+    if let Err(resp) = ensure_file_allowed(&ctx, &client_addr, &file_id).await {
+        return resp;
+    }
+    let Some(token) = lock_token(&headers) else {
+        return error_response(StatusCode::BAD_REQUEST, "MISSING_LOCK_TOKEN", format!("{} header is required", LOCK_TOKEN_HEADER));
+    };
+
+    let result = sqlx::query("DELETE FROM file_locks WHERE file_id = ? AND token = ? AND expires_at > ?")
+        .bind(&file_id)
+        .bind(token)
+        .bind(Utc::now().timestamp())
+        .execute(db_pool)
+        .await;
+    match result {
+        Ok(done) if done.rows_affected() > 0 => {
+            info!("File {} unlocked", file_id);
+            (StatusCode::OK, Json(ApiResponse::success(()))).into_response()
+        }
+        Ok(_) => error_response(StatusCode::CONFLICT, "LOCK_NOT_HELD", "The lock has expired or was taken by another client".to_string()),
+        Err(e) => {
+            error!("Failed to unlock file {}: {}", file_id, e);
+            error_response(StatusCode::INTERNAL_SERVER_ERROR, "UNLOCK_FILE_ERROR", "Failed to unlock file".to_string())
+        }
+    }
+}
@@ -0,0 +1,58 @@
+use axum::body::{Body, Bytes};
+use tokio::fs::File;
+use tokio::io::AsyncReadExt;
+
+/// Extensions of installer and VM disk images. Hypervisors and network boot
+/// loaders read them with long runs of range requests, so they get larger
+/// reads and read-ahead.
+const DISK_IMAGE_EXTENSIONS: &[&str] = &["iso", "img", "raw", "qcow2", "vmdk", "vdi", "vhd", "vhdx"];
+
+pub fn is_disk_image(filename: &str) -> bool {
+    std::path::Path::new(filename)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| DISK_IMAGE_EXTENSIONS.iter().any(|known| ext.eq_ignore_ascii_case(known)))
+}
+
+/// Next read of at most `buffer_size` bytes, None once `remaining` is 0
+async fn read_block(file: &mut File, remaining: &mut u64, buffer_size: usize) -> Option<std::io::Result<Bytes>> {
+    if *remaining == 0 {
+        return None;
+    }
+    let mut buf = vec![0u8; (*remaining).min(buffer_size as u64) as usize];
+    let n = match file.read(&mut buf).await {
+        Ok(0) => return Some(Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "File is shorter than expected"))),
+        Ok(n) => n,
+        Err(e) => return Some(Err(e)),
+    };
+    buf.truncate(n);
+    *remaining -= n as u64;
+    Some(Ok(Bytes::from(buf)))
+}
+
+/// Response body with `len` bytes of `file` from its current position, read
+/// `buffer_size` bytes at a time. With `read_ahead` > 0 a separate task keeps
+/// up to that many reads queued ahead of the client.
+pub fn file_body(file: File, len: u64, buffer_size: usize, read_ahead: usize) -> Body {
+    if read_ahead == 0 {
+        return Body::from_stream(futures::stream::unfold((file, len), move |(mut file, mut remaining)| async move {
+            let block = read_block(&mut file, &mut remaining, buffer_size).await?;
+            Some((block, (file, remaining)))
+        }));
+    }
+
+    let (tx, rx) = tokio::sync::mpsc::channel(read_ahead);
+    tokio::spawn(async move {
+        let (mut file, mut remaining) = (file, len);
+        while let Some(block) = read_block(&mut file, &mut remaining, buffer_size).await {
+            let failed = block.is_err();
+            // 客户端断开后接收端被丢弃，停止预读
+            if tx.send(block).await.is_err() || failed {
+                break;
+            }
+        }
+    });
+    Body::from_stream(futures::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|block| (block, rx))
+    }))
+}
@@ -0,0 +1,207 @@
+use sanitize_filename::sanitize;
+use unicode_normalization::UnicodeNormalization;
+use serde::Serialize;
+
+/// Most filesystems cap a single path component at 255 bytes
+const MAX_FILENAME_BYTES: usize = 255;
+
+/// Extensions longer than this are treated as part of the stem when truncating
+const MAX_EXTENSION_BYTES: usize = 16;
+
+/// Device names Windows refuses to use as a filename, with or without an extension
+const WINDOWS_RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL",
+    "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+    "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Characters that are invalid in Windows filenames besides path separators
+const WINDOWS_INVALID_CHARS: [char; 7] = ['<', '>', ':', '"', '|', '?', '*'];
+
+/// Policy used to turn a client supplied filename into the name stored on disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SanitizePolicy {
+    /// Legacy behaviour of the sanitize-filename crate
+    Strict,
+    /// Keep Unicode, normalize to NFC and strip only path separators and control characters
+    Unicode,
+}
+
+impl SanitizePolicy {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "strict" | "legacy" => Some(Self::Strict),
+            "unicode" | "nfc" => Some(Self::Unicode),
+            _ => None,
+        }
+    }
+}
+
+/// Build the filename used on disk according to the configured policy
+pub fn sanitize_filename(name: &str, policy: SanitizePolicy) -> String {
+    let cleaned = match policy {
+        SanitizePolicy::Strict => sanitize(name),
+        SanitizePolicy::Unicode => name
+            .nfc()
+            .filter(|c| !c.is_control())
+            .map(|c| if c == '/' || c == '\\' || (cfg!(windows) && WINDOWS_INVALID_CHARS.contains(&c)) { '_' } else { c })
+            .collect::<String>()
+            .trim()
+            .to_string(),
+    };
+    let cleaned = portable_filename(&cleaned);
+
+    let cleaned = truncate_filename(&cleaned, MAX_FILENAME_BYTES);
+    if cleaned.is_empty() || cleaned == "." || cleaned == ".." {
+        "unnamed".to_string()
+    } else {
+        cleaned
+    }
+}
+
+/// Normalize the original client filename before storing it (NFC, no control characters)
+pub fn normalize_original_filename(name: &str) -> String {
+    name.nfc().filter(|c| !c.is_control()).collect()
+}
+
+/// Build a Content-Disposition value that restores the original filename,
+/// with an ASCII fallback for clients that don't understand RFC 5987
+pub fn content_disposition(original_filename: &str) -> String {
+    let fallback: String = original_filename
+        .chars()
+        .map(|c| if c.is_ascii() && !c.is_ascii_control() && c != '"' && c != '\\' { c } else { '_' })
+        .collect();
+    let encoded = percent_encoding::utf8_percent_encode(original_filename, percent_encoding::NON_ALPHANUMERIC);
+    format!("attachment; filename=\"{}\"; filename*=UTF-8''{}", fallback, encoded)
+}
+
+/// Make a filename usable on Windows as well: avoid reserved device names and
+/// trailing dots/spaces, which Windows silently strips
+pub fn portable_filename(name: &str) -> String {
+    let trimmed = name.trim_end_matches(['.', ' ']);
+    let name = if trimmed.is_empty() { name } else { trimmed };
+
+    let stem = name.split('.').next().unwrap_or(name);
+    if WINDOWS_RESERVED_NAMES.iter().any(|reserved| reserved.eq_ignore_ascii_case(stem.trim_end())) {
+        format!("_{}", name)
+    } else {
+        name.to_string()
+    }
+}
+
+/// Truncate to at most `max_bytes` on a char boundary, keeping a short extension intact
+fn truncate_filename(name: &str, max_bytes: usize) -> String {
+    if name.len() <= max_bytes {
+        return name.to_string();
+    }
+
+    let (stem, ext) = match name.rfind('.') {
+        Some(idx) if idx > 0 && name.len() - idx <= MAX_EXTENSION_BYTES => name.split_at(idx),
+        _ => (name, ""),
+    };
+
+    let budget = max_bytes.saturating_sub(ext.len());
+    let mut end = budget.min(stem.len());
+    while !stem.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}{}", &stem[..end], ext)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strict_policy_drops_unsafe_characters() {
+        assert_eq!(sanitize_filename("report.pdf", SanitizePolicy::Strict), "report.pdf");
+        assert_eq!(sanitize_filename("../a/b:c?.txt", SanitizePolicy::Strict), "..abc.txt");
+        assert_eq!(sanitize_filename("a\u{7}b.txt", SanitizePolicy::Strict), "ab.txt");
+        assert_eq!(sanitize_filename("..", SanitizePolicy::Strict), "unnamed");
+    }
+
+    #[test]
+    fn unicode_policy_keeps_unicode_in_nfc() {
+        assert_eq!(sanitize_filename("cafe\u{301}.txt", SanitizePolicy::Unicode), "caf\u{e9}.txt");
+        assert_eq!(sanitize_filename("照片 2024.jpg", SanitizePolicy::Unicode), "照片 2024.jpg");
+        assert_eq!(sanitize_filename(" a/b\\c\u{7}.txt ", SanitizePolicy::Unicode), "a_b_c.txt");
+        assert_eq!(sanitize_filename("..", SanitizePolicy::Unicode), "unnamed");
+        assert_eq!(sanitize_filename("/", SanitizePolicy::Unicode), "_");
+        assert_eq!(sanitize_filename("\u{7}", SanitizePolicy::Unicode), "unnamed");
+    }
+
+    #[test]
+    fn parses_policies() {
+        assert_eq!(SanitizePolicy::parse(" Legacy "), Some(SanitizePolicy::Strict));
+        assert_eq!(SanitizePolicy::parse("NFC"), Some(SanitizePolicy::Unicode));
+        assert_eq!(SanitizePolicy::parse("ascii"), None);
+    }
+
+    #[test]
+    fn portable_filename_avoids_reserved_windows_names() {
+        assert_eq!(portable_filename("CON"), "_CON");
+        assert_eq!(portable_filename("con.txt"), "_con.txt");
+        assert_eq!(portable_filename("Lpt9.tar.gz"), "_Lpt9.tar.gz");
+        assert_eq!(portable_filename("COM1 .txt"), "_COM1 .txt");
+        assert_eq!(portable_filename("CONSOLE.txt"), "CONSOLE.txt");
+        assert_eq!(portable_filename("COM10"), "COM10");
+        assert_eq!(sanitize_filename("aux.log", SanitizePolicy::Unicode), "_aux.log");
+    }
+
+    #[test]
+    fn portable_filename_strips_trailing_dots_and_spaces() {
+        assert_eq!(portable_filename("notes. . "), "notes");
+        assert_eq!(portable_filename("notes.txt..."), "notes.txt");
+        assert_eq!(portable_filename("nul. "), "_nul");
+        assert_eq!(portable_filename("..."), "...");
+        assert_eq!(portable_filename(".hidden"), ".hidden");
+    }
+
+    #[test]
+    fn truncate_keeps_short_names() {
+        assert_eq!(truncate_filename("a.txt", 5), "a.txt");
+        assert_eq!(truncate_filename("abcdef.txt", 8), "abcd.txt");
+    }
+
+    #[test]
+    fn truncate_stops_on_char_boundaries() {
+        let name = format!("{}.txt", "é".repeat(200));
+        let truncated = truncate_filename(&name, MAX_FILENAME_BYTES);
+        assert_eq!(truncated, format!("{}.txt", "é".repeat(125)));
+        assert!(truncated.len() <= MAX_FILENAME_BYTES);
+
+        let name = "🎵".repeat(100);
+        assert_eq!(truncate_filename(&name, 10), "🎵🎵");
+    }
+
+    #[test]
+    fn truncate_treats_long_extensions_as_part_of_the_stem() {
+        let name = format!("{}.{}", "a".repeat(300), "b".repeat(MAX_EXTENSION_BYTES));
+        assert_eq!(truncate_filename(&name, MAX_FILENAME_BYTES), "a".repeat(MAX_FILENAME_BYTES));
+
+        let name = format!(".{}", "a".repeat(300));
+        assert_eq!(truncate_filename(&name, 10), format!(".{}", "a".repeat(9)));
+    }
+
+    #[test]
+    fn content_disposition_escapes_the_filename() {
+        assert_eq!(
+            content_disposition("report.pdf"),
+            "attachment; filename=\"report.pdf\"; filename*=UTF-8''report%2Epdf"
+        );
+        assert_eq!(
+            content_disposition("r\u{e9}sum\u{e9} \"final\"\\1.pdf"),
+            "attachment; filename=\"r_sum_ _final__1.pdf\"; filename*=UTF-8''r%C3%A9sum%C3%A9%20%22final%22%5C1%2Epdf"
+        );
+        assert_eq!(
+            content_disposition("a\r\nSet-Cookie: x"),
+            "attachment; filename=\"a__Set-Cookie: x\"; filename*=UTF-8''a%0D%0ASet%2DCookie%3A%20x"
+        );
+    }
+
+    #[test]
+    fn normalizes_original_filenames() {
+        assert_eq!(normalize_original_filename("cafe\u{301}\u{0}.txt"), "caf\u{e9}.txt");
+    }
+}
@@ -0,0 +1,403 @@
+use axum::{
+    extract::{ConnectInfo, Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, SqlitePool};
+use std::net::SocketAddr;
+use crate::context::AppContext;
+use crate::filename::{sanitize_filename, SanitizePolicy};
+use crate::helper::{error_response, ApiResponse};
+use crate::paths::{long_path, UPLOADS_DIR};
+use crate::profiles::ensure_unrestricted;
+use crate::quarantine::{check_extension, normalize_extensions};
+use crate::tenants::{current_tenant, current_tenant_id};
+use crate::transcode::TRANSCODE_FORMATS;
+
+#[derive(Debug, FromRow)]
+struct FolderRow {
+    id: i64,
+    name: String,
+    path: String,
+    allowed_mime_types: String,
+    blocked_extensions: String,
+    max_file_size: Option<i64>,
+    auto_transcode: Option<String>,
+    retention_days: Option<i64>,
+    media_scan: bool,
+    created_at: i64,
+    tenant_id: Option<i64>,
+}
+
+/// An upload target directory and the policies applied to files uploaded into it
+#[derive(Debug, Clone, Serialize)]
+pub struct Folder {
+    pub id: i64,
+    pub name: String,
+    /// Directory under `uploads/` the folder's files are stored in
+    pub path: String,
+    /// Accepted MIME types, `type/*` wildcards allowed; empty accepts anything
+    pub allowed_mime_types: Vec<String>,
+    /// Refused extensions, lowercase without the dot
+    pub blocked_extensions: Vec<String>,
+    pub max_file_size: Option<i64>,
+    /// Format (`mp4` or `webm`) completed videos are transcoded to
+    pub auto_transcode: Option<String>,
+    /// Completed files are deleted this many days after upload
+    pub retention_days: Option<i64>,
+    /// Completed uploads are announced to the configured Jellyfin/Plex servers
+    pub media_scan: bool,
+    #[serde(with = "crate::api::timestamp")]
+    pub created_at: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tenant_id: Option<i64>,
+}
+
+impl From<FolderRow> for Folder {
+    fn from(row: FolderRow) -> Self {
+        Self {
+            id: row.id,
+            name: row.name,
+            path: row.path,
+            allowed_mime_types: serde_json::from_str(&row.allowed_mime_types).unwrap_or_default(),
+            blocked_extensions: serde_json::from_str(&row.blocked_extensions).unwrap_or_default(),
+            max_file_size: row.max_file_size,
+            auto_transcode: row.auto_transcode,
+            retention_days: row.retention_days,
+            media_scan: row.media_scan,
+            created_at: row.created_at,
+            tenant_id: row.tenant_id,
+        }
+    }
+}
+
+pub fn mime_matches(pattern: &str, mime: &str) -> bool {
+    match pattern.strip_suffix("/*") {
+        Some("*") => true,
+        Some(kind) => mime.split('/').next() == Some(kind),
+        None => pattern == mime,
+    }
+}
+
+impl Folder {
+    /// Check a new upload against the folder's size and type policies.
+    /// The error is an API error code and message.
+    pub fn check_upload(&self, filename: &str, total_size: u64) -> Result<(), (&'static str, String)> {
+        if let Some(max) = self.max_file_size {
+            if total_size > max as u64 {
+                return Err(("FILE_TOO_LARGE", format!(
+                    "Folder '{}' accepts files up to {} bytes, got {}", self.name, max, total_size
+                )));
+            }
+        }
+
+        check_extension(&self.blocked_extensions, filename, &format!("Folder '{}'", self.name))?;
+
+        if !self.allowed_mime_types.is_empty() {
+            let mime = mime_guess::from_path(filename).first_or_octet_stream();
+            if !self.allowed_mime_types.iter().any(|pattern| mime_matches(pattern, mime.essence_str())) {
+                return Err(("MIME_TYPE_NOT_ALLOWED", format!(
+                    "Folder '{}' does not accept {} files", self.name, mime.essence_str()
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the current request may see the folder: tenants only see their own
+    pub fn is_visible(&self) -> bool {
+        current_tenant_id().is_none_or(|id| self.tenant_id == Some(id))
+    }
+}
+
+/// Tenants manage their own folders; otherwise changing folders needs an unrestricted profile
+pub async fn ensure_folder_admin(ctx: &AppContext, client_addr: &SocketAddr) -> Result<(), Response> {
+    if current_tenant_id().is_some() {
+        return Ok(());
+    }
+    ensure_unrestricted(ctx, client_addr).await
+}
+
+/// Folder by ID if the current request may see it
+pub async fn fetch_visible_folder(db_pool: &SqlitePool, id: i64) -> Result<Option<Folder>, String> {
+    Ok(fetch_folder(db_pool, id).await?.filter(Folder::is_visible))
+}
+
+/// Turn a requested folder path into a relative path under the uploads directory,
+/// dropping empty, `.` and `..` components
+fn normalize_folder_path(path: &str, policy: SanitizePolicy) -> Option<String> {
+    let components: Vec<String> = path
+        .split(['/', '\\'])
+        .map(str::trim)
+        .filter(|c| !c.is_empty() && *c != "." && *c != "..")
+        .map(|c| sanitize_filename(c, policy))
+        .filter(|c| !c.is_empty())
+        .collect();
+    (!components.is_empty()).then(|| components.join("/"))
+}
+
+const FOLDER_COLUMNS: &str = "id, name, path, allowed_mime_types, blocked_extensions, max_file_size, auto_transcode, retention_days, media_scan, created_at, tenant_id";
+
+pub async fn fetch_folders(db_pool: &SqlitePool) -> Result<Vec<Folder>, String> {
+    sqlx::query_as::<_, FolderRow>(&format!("SELECT {} FROM folders ORDER BY name", FOLDER_COLUMNS))
+        .fetch_all(db_pool)
+        .await
+        .map(|rows| rows.into_iter().map(Folder::from).collect())
+        .map_err(|e| {
+            error!("Failed to fetch folders: {}", e);
+            "Failed to fetch folders".to_string()
+        })
+}
+
+pub async fn fetch_folder(db_pool: &SqlitePool, id: i64) -> Result<Option<Folder>, String> {
+    sqlx::query_as::<_, FolderRow>(&format!("SELECT {} FROM folders WHERE id = ?", FOLDER_COLUMNS))
+        .bind(id)
+        .fetch_optional(db_pool)
+        .await
+        .map(|row| row.map(Folder::from))
+        .map_err(|e| {
+            error!("Failed to fetch folder: {}", e);
+            "Failed to fetch folder".to_string()
+        })
+}
+
+/// Folder an upload was submitted to, None for the top-level uploads directory
+pub async fn fetch_file_folder(db_pool: &SqlitePool, file_id: &str) -> Result<Option<Folder>, String> {
+    sqlx::query_as::<_, FolderRow>(&format!(
+        "SELECT {} FROM folders WHERE id = (SELECT folder_id FROM upload_file_meta WHERE file_id = ?)",
+        FOLDER_COLUMNS
+    ))
+    .bind(file_id)
+    .fetch_optional(db_pool)
+    .await
+    .map(|row| row.map(Folder::from))
+    .map_err(|e| {
+        error!("Failed to fetch file folder: {}", e);
+        "Failed to fetch file folder".to_string()
+    })
+}
+
+pub async fn list_folders(
+    State(ctx): State<AppContext>,
+) -> impl IntoResponse {
+    match fetch_folders(&ctx.app_state.db_pool).await {
+        Ok(folders) => (StatusCode::OK, Json(ApiResponse::success(
+            folders.into_iter().filter(Folder::is_visible).collect::<Vec<_>>()
+        ))).into_response(),
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_FOLDERS_ERROR", e),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct FolderPolicyRequest {
+    #[serde(default)]
+    allowed_mime_types: Vec<String>,
+    #[serde(default)]
+    blocked_extensions: Vec<String>,
+    max_file_size: Option<i64>,
+    auto_transcode: Option<String>,
+    retention_days: Option<i64>,
+    #[serde(default)]
+    media_scan: bool,
+}
+
+impl FolderPolicyRequest {
+    /// The policy with normalized fields, or a message describing the invalid one
+    fn validate(self) -> Result<Self, String> {
+        if self.max_file_size.is_some_and(|size| size <= 0) {
+            return Err("max_file_size must be positive".to_string());
+        }
+        if self.retention_days.is_some_and(|days| days <= 0) {
+            return Err("retention_days must be positive".to_string());
+        }
+        let auto_transcode = self.auto_transcode.map(|f| f.trim().to_lowercase()).filter(|f| !f.is_empty());
+        if let Some(format) = auto_transcode.as_deref().filter(|f| !TRANSCODE_FORMATS.contains(f)) {
+            return Err(format!("auto_transcode must be one of {}, got '{}'", TRANSCODE_FORMATS.join(", "), format));
+        }
+        let allowed_mime_types = self.allowed_mime_types
+            .iter()
+            .map(|m| m.trim().to_lowercase())
+            .filter(|m| !m.is_empty())
+            .collect();
+        let blocked_extensions = normalize_extensions(self.blocked_extensions.iter().map(String::as_str));
+        Ok(Self { allowed_mime_types, blocked_extensions, max_file_size: self.max_file_size, auto_transcode, retention_days: self.retention_days, media_scan: self.media_scan })
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CreateFolderRequest {
+    name: String,
+    /// Directory under `uploads/`, defaults to the name
+    path: Option<String>,
+    #[serde(flatten)]
+    policy: FolderPolicyRequest,
+}
+
+/// Reject changes to another tenant's folder as if it didn't exist; missing folders are left to the caller
+async fn ensure_folder_visible(db_pool: &SqlitePool, id: i64) -> Result<(), Response> {
+    match fetch_folder(db_pool, id).await {
+        Ok(Some(folder)) if !folder.is_visible() => Err(error_response(StatusCode::NOT_FOUND, "FOLDER_NOT_FOUND", "Folder not found".to_string())),
+        Ok(_) => Ok(()),
+        Err(e) => Err(error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_FOLDER_ERROR", e)),
+    }
+}
+
+fn invalid_folder(message: String) -> Response {
+    error_response(StatusCode::BAD_REQUEST, "INVALID_FOLDER", message)
+}
+
+pub async fn create_folder(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    Json(req): Json<CreateFolderRequest>,
+) -> impl IntoResponse {
+    if let Err(resp) = ensure_folder_admin(&ctx, &client_addr).await {
+        return resp;
+    }
+    let name = req.name.trim().to_string();
+    if name.is_empty() {
+        return invalid_folder("Folder name must not be empty".to_string());
+    }
+    let Some(path) = normalize_folder_path(req.path.as_deref().unwrap_or(&name), ctx.config.load().filename_policy) else {
+        return invalid_folder("Folder path must not be empty".to_string());
+    };
+    // 租户的目录都放在租户自己的存储目录下
+    let tenant = current_tenant();
+    let path = match &tenant {
+        Some(tenant) => format!("{}/{}", tenant.storage_dir(), path),
+        None => path,
+    };
+    let policy = match req.policy.validate() {
+        Ok(policy) => policy,
+        Err(message) => return invalid_folder(message),
+    };
+
+    let db_pool = &ctx.app_state.db_pool;
+    let result = sqlx::query(
+        "INSERT INTO folders (name, path, allowed_mime_types, blocked_extensions, max_file_size, auto_transcode, retention_days, media_scan, created_at, tenant_id) VALUES (?, ?, ?, ?, ?, ?, ?, ?, strftime('%s', 'now'), ?)"
+    )
+    .bind(&name)
+    .bind(&path)
+    .bind(serde_json::to_string(&policy.allowed_mime_types).unwrap_or_else(|_| "[]".to_string()))
+    .bind(serde_json::to_string(&policy.blocked_extensions).unwrap_or_else(|_| "[]".to_string()))
+    .bind(policy.max_file_size)
+    .bind(policy.auto_transcode)
+    .bind(policy.retention_days)
+    .bind(policy.media_scan)
+    .bind(tenant.as_ref().map(|t| t.id))
+    .execute(db_pool)
+    .await;
+
+    let id = match result {
+        Ok(done) => done.last_insert_rowid(),
+        Err(e) => {
+            error!("Failed to create folder: {}", e);
+            return error_response(StatusCode::CONFLICT, "CREATE_FOLDER_ERROR", format!("A folder named '{}' or at '{}' already exists", name, path));
+        }
+    };
+
+    // 目录记录写入成功后再创建磁盘目录，失败时撤销记录
+    if let Err(e) = tokio::fs::create_dir_all(long_path(&std::path::Path::new(UPLOADS_DIR).join(&path))).await {
+        error!("Failed to create folder directory {}: {}", path, e);
+        let _ = sqlx::query("DELETE FROM folders WHERE id = ?").bind(id).execute(db_pool).await;
+        return error_response(StatusCode::INTERNAL_SERVER_ERROR, "CREATE_FOLDER_ERROR", format!("Failed to create directory for folder '{}'", name));
+    }
+
+    info!("Created folder '{}' at {}", name, path);
+    match fetch_folder(db_pool, id).await {
+        Ok(Some(folder)) => (StatusCode::OK, Json(ApiResponse::success(folder))).into_response(),
+        Ok(None) | Err(_) => (StatusCode::OK, Json(ApiResponse::success(()))).into_response(),
+    }
+}
+
+/// Replace a folder's policies. Its name and path stay fixed since stored files live under the path.
+pub async fn update_folder(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    Path(id): Path<i64>,
+    Json(req): Json<FolderPolicyRequest>,
+) -> impl IntoResponse {
+    if let Err(resp) = ensure_folder_admin(&ctx, &client_addr).await {
+        return resp;
+    }
+    let policy = match req.validate() {
+        Ok(policy) => policy,
+        Err(message) => return invalid_folder(message),
+    };
+
+    let db_pool = &ctx.app_state.db_pool;
+    if let Err(resp) = ensure_folder_visible(db_pool, id).await {
+        return resp;
+    }
+    let result = sqlx::query(
+        "UPDATE folders SET allowed_mime_types = ?, blocked_extensions = ?, max_file_size = ?, auto_transcode = ?, retention_days = ?, media_scan = ? WHERE id = ?"
+    )
+    .bind(serde_json::to_string(&policy.allowed_mime_types).unwrap_or_else(|_| "[]".to_string()))
+    .bind(serde_json::to_string(&policy.blocked_extensions).unwrap_or_else(|_| "[]".to_string()))
+    .bind(policy.max_file_size)
+    .bind(policy.auto_transcode)
+    .bind(policy.retention_days)
+    .bind(policy.media_scan)
+    .bind(id)
+    .execute(db_pool)
+    .await;
+
+    match result {
+        Ok(done) if done.rows_affected() == 0 => error_response(StatusCode::NOT_FOUND, "FOLDER_NOT_FOUND", "Folder not found".to_string()),
+        Ok(_) => match fetch_folder(db_pool, id).await {
+            Ok(Some(folder)) => (StatusCode::OK, Json(ApiResponse::success(folder))).into_response(),
+            Ok(None) | Err(_) => (StatusCode::OK, Json(ApiResponse::success(()))).into_response(),
+        },
+        Err(e) => {
+            error!("Failed to update folder: {}", e);
+            error_response(StatusCode::INTERNAL_SERVER_ERROR, "UPDATE_FOLDER_ERROR", "Failed to update folder".to_string())
+        }
+    }
+}
+
+/// Delete an empty folder with its retention rules and inboxes; the directory on disk is left in place
+pub async fn delete_folder(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    Path(id): Path<i64>,
+) -> impl IntoResponse {
+    if let Err(resp) = ensure_folder_admin(&ctx, &client_addr).await {
+        return resp;
+    }
+
+    let db_pool = &ctx.app_state.db_pool;
+    if let Err(resp) = ensure_folder_visible(db_pool, id).await {
+        return resp;
+    }
+    let result = async {
+        let mut tx = db_pool.begin().await?;
+        let files: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM upload_file_meta WHERE folder_id = ?")
+            .bind(id)
+            .fetch_one(&mut *tx)
+            .await?;
+        if files > 0 {
+            return Ok(None);
+        }
+        sqlx::query("DELETE FROM retention_rules WHERE folder_id = ?").bind(id).execute(&mut *tx).await?;
+        sqlx::query("DELETE FROM hooks WHERE folder_id = ?").bind(id).execute(&mut *tx).await?;
+        sqlx::query("DELETE FROM inbox_uploads WHERE inbox_id IN (SELECT id FROM inboxes WHERE folder_id = ?)").bind(id).execute(&mut *tx).await?;
+        sqlx::query("DELETE FROM inboxes WHERE folder_id = ?").bind(id).execute(&mut *tx).await?;
+        let deleted = sqlx::query("DELETE FROM folders WHERE id = ?").bind(id).execute(&mut *tx).await?;
+        tx.commit().await?;
+        Ok::<Option<u64>, sqlx::Error>(Some(deleted.rows_affected()))
+    }
+    .await;
+
+    match result {
+        Ok(None) => error_response(StatusCode::CONFLICT, "FOLDER_NOT_EMPTY", "Folder still contains files".to_string()),
+        Ok(Some(0)) => error_response(StatusCode::NOT_FOUND, "FOLDER_NOT_FOUND", "Folder not found".to_string()),
+        Ok(Some(_)) => (StatusCode::OK, Json(ApiResponse::success(()))).into_response(),
+        Err(e) => {
+            error!("Failed to delete folder: {}", e);
+            error_response(StatusCode::INTERNAL_SERVER_ERROR, "DELETE_FOLDER_ERROR", "Failed to delete folder".to_string())
+        }
+    }
+}
@@ -0,0 +1,265 @@
+//! Read-only FUSE view of the library, built with the `fuse` cargo feature.
+//!
+//! Completed uploads are exposed as a flat directory so media players such as
+//! Kodi can browse them like a local disk.
+
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::fs::File;
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use fuser::{
+    FileAttr, FileType, Filesystem, MountOption, ReplyAttr, ReplyData, ReplyDirectory, ReplyEntry,
+    ReplyOpen, Request, FUSE_ROOT_ID,
+};
+use log::{error, info};
+use sqlx::{FromRow, SqlitePool};
+use tokio::runtime::Handle;
+
+use crate::paths::long_path;
+
+/// How long the kernel may cache attributes and directory entries
+const ATTR_TTL: Duration = Duration::from_secs(1);
+
+/// Re-read the library from the database at most this often
+const REFRESH_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, FromRow)]
+struct LibraryRow {
+    id: i64,
+    filename: String,
+    file_path: String,
+    total_size: i64,
+    file_mtime: Option<i64>,
+}
+
+struct LibraryEntry {
+    ino: u64,
+    name: String,
+    path: PathBuf,
+    size: u64,
+    mtime: SystemTime,
+}
+
+pub struct LibraryFs {
+    db_pool: SqlitePool,
+    runtime: Handle,
+    entries: Vec<LibraryEntry>,
+    refreshed_at: Option<Instant>,
+}
+
+impl LibraryFs {
+    pub fn new(db_pool: SqlitePool, runtime: Handle) -> Self {
+        Self {
+            db_pool,
+            runtime,
+            entries: Vec::new(),
+            refreshed_at: None,
+        }
+    }
+
+    /// Reload completed files from the database when the cached listing is stale.
+    /// FUSE callbacks run on fuser's own thread, so blocking on the runtime is fine here.
+    fn refresh(&mut self) {
+        if self.refreshed_at.is_some_and(|t| t.elapsed() < REFRESH_INTERVAL) {
+            return;
+        }
+
+        let rows = self.runtime.block_on(
+            sqlx::query_as::<_, LibraryRow>(
+                "SELECT id, filename, file_path, total_size, file_mtime FROM upload_file_meta WHERE status = 2 ORDER BY id DESC"
+            )
+            .fetch_all(&self.db_pool),
+        );
+        let rows = match rows {
+            Ok(rows) => rows,
+            Err(e) => {
+                error!("Failed to load library for FUSE mount: {}", e);
+                return;
+            }
+        };
+
+        // Several records may share a filename; the newest one wins
+        let mut seen = HashSet::new();
+        self.entries = rows
+            .into_iter()
+            .filter(|row| seen.insert(row.filename.clone()))
+            .map(|row| LibraryEntry {
+                ino: FUSE_ROOT_ID + row.id as u64,
+                name: row.filename,
+                path: PathBuf::from(row.file_path),
+                size: row.total_size.max(0) as u64,
+                mtime: UNIX_EPOCH + Duration::from_secs(row.file_mtime.unwrap_or(0).max(0) as u64),
+            })
+            .collect();
+        self.refreshed_at = Some(Instant::now());
+    }
+
+    fn entry(&self, ino: u64) -> Option<&LibraryEntry> {
+        self.entries.iter().find(|e| e.ino == ino)
+    }
+}
+
+fn root_attr(req: &Request<'_>) -> FileAttr {
+    FileAttr {
+        ino: FUSE_ROOT_ID,
+        size: 0,
+        blocks: 0,
+        atime: UNIX_EPOCH,
+        mtime: UNIX_EPOCH,
+        ctime: UNIX_EPOCH,
+        crtime: UNIX_EPOCH,
+        kind: FileType::Directory,
+        perm: 0o555,
+        nlink: 2,
+        uid: req.uid(),
+        gid: req.gid(),
+        rdev: 0,
+        blksize: 512,
+        flags: 0,
+    }
+}
+
+fn file_attr(req: &Request<'_>, entry: &LibraryEntry) -> FileAttr {
+    FileAttr {
+        ino: entry.ino,
+        size: entry.size,
+        blocks: entry.size.div_ceil(512),
+        atime: entry.mtime,
+        mtime: entry.mtime,
+        ctime: entry.mtime,
+        crtime: entry.mtime,
+        kind: FileType::RegularFile,
+        perm: 0o444,
+        nlink: 1,
+        uid: req.uid(),
+        gid: req.gid(),
+        rdev: 0,
+        blksize: 512,
+        flags: 0,
+    }
+}
+
+impl Filesystem for LibraryFs {
+    fn lookup(&mut self, req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        if parent != FUSE_ROOT_ID {
+            reply.error(libc::ENOENT);
+            return;
+        }
+        self.refresh();
+        match self.entries.iter().find(|e| OsStr::new(&e.name) == name) {
+            Some(entry) => reply.entry(&ATTR_TTL, &file_attr(req, entry), 0),
+            None => reply.error(libc::ENOENT),
+        }
+    }
+
+    fn getattr(&mut self, req: &Request<'_>, ino: u64, _fh: Option<u64>, reply: ReplyAttr) {
+        if ino == FUSE_ROOT_ID {
+            reply.attr(&ATTR_TTL, &root_attr(req));
+            return;
+        }
+        self.refresh();
+        match self.entry(ino) {
+            Some(entry) => reply.attr(&ATTR_TTL, &file_attr(req, entry)),
+            None => reply.error(libc::ENOENT),
+        }
+    }
+
+    fn open(&mut self, _req: &Request<'_>, ino: u64, flags: i32, reply: ReplyOpen) {
+        if flags & libc::O_ACCMODE != libc::O_RDONLY {
+            reply.error(libc::EROFS);
+            return;
+        }
+        if self.entry(ino).is_none() {
+            reply.error(libc::ENOENT);
+            return;
+        }
+        reply.opened(0, 0);
+    }
+
+    fn read(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        size: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyData,
+    ) {
+        let Some(entry) = self.entry(ino) else {
+            reply.error(libc::ENOENT);
+            return;
+        };
+
+        let file = match File::open(long_path(&entry.path)) {
+            Ok(f) => f,
+            Err(e) => {
+                error!("FUSE read failed to open {}: {}", entry.path.display(), e);
+                reply.error(e.raw_os_error().unwrap_or(libc::EIO));
+                return;
+            }
+        };
+
+        let mut buffer = vec![0u8; size as usize];
+        let mut filled = 0;
+        while filled < buffer.len() {
+            match file.read_at(&mut buffer[filled..], offset.max(0) as u64 + filled as u64) {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(e) => {
+                    error!("FUSE read failed on {}: {}", entry.path.display(), e);
+                    reply.error(e.raw_os_error().unwrap_or(libc::EIO));
+                    return;
+                }
+            }
+        }
+        reply.data(&buffer[..filled]);
+    }
+
+    fn readdir(&mut self, _req: &Request<'_>, ino: u64, _fh: u64, offset: i64, mut reply: ReplyDirectory) {
+        if ino != FUSE_ROOT_ID {
+            reply.error(libc::ENOTDIR);
+            return;
+        }
+        if offset == 0 {
+            self.refresh();
+        }
+
+        let dots = [(FUSE_ROOT_ID, FileType::Directory, "."), (FUSE_ROOT_ID, FileType::Directory, "..")];
+        let listing = dots
+            .into_iter()
+            .chain(self.entries.iter().map(|e| (e.ino, FileType::RegularFile, e.name.as_str())));
+        for (i, (ino, kind, name)) in listing.enumerate().skip(offset.max(0) as usize) {
+            // The offset passed back to us is that of the next entry
+            if reply.add(ino, (i + 1) as i64, kind, name) {
+                break;
+            }
+        }
+        reply.ok();
+    }
+}
+
+/// Mount the library read-only at `mountpoint`. The filesystem stays mounted
+/// until the returned session is dropped.
+pub fn mount_library(db_pool: SqlitePool, mountpoint: &Path, allow_other: bool) -> std::io::Result<fuser::BackgroundSession> {
+    std::fs::create_dir_all(mountpoint)?;
+
+    let mut options = vec![
+        MountOption::RO,
+        MountOption::FSName("nascraft".to_string()),
+        MountOption::Subtype("nascraft".to_string()),
+        MountOption::DefaultPermissions,
+    ];
+    if allow_other {
+        options.push(MountOption::AllowOther);
+    }
+
+    let fs = LibraryFs::new(db_pool, Handle::current());
+    let session = fuser::spawn_mount2(fs, mountpoint, &options)?;
+    info!("Library mounted read-only at {}", mountpoint.display());
+    Ok(session)
+}
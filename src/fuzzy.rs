@@ -0,0 +1,52 @@
+//! Forgiving title matching for names typed in a chat or dictated to a voice
+//! assistant, e.g. `the matrix` for `The.Matrix.1999.1080p.BluRay.mkv`.
+
+/// Lowercase words, split at anything but letters and digits
+fn words(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(|w| w.to_lowercase())
+        .collect()
+}
+
+/// Levenshtein distance in characters
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Same word, a prefix of it, or a misspelling with one wrong letter per five
+fn word_matches(query: &str, word: &str) -> bool {
+    let length = query.chars().count();
+    query == word
+        || (length >= 3 && word.starts_with(query))
+        || (length >= 5 && edit_distance(query, word) <= length / 5)
+}
+
+/// How well `query` names `candidate`, from 0 (not at all) to 1: the share of
+/// query words found in the candidate, minus a little for each extra word, so
+/// `matrix` ranks `The Matrix` above `The Matrix Reloaded`
+pub fn match_score(query: &str, candidate: &str) -> f64 {
+    let query_words = words(query);
+    if query_words.is_empty() {
+        return 0.0;
+    }
+    let candidate_words = words(candidate);
+    let candidate_text = candidate.to_lowercase();
+    // Titles in scripts without spaces are a single word, so also look inside them
+    let found = query_words
+        .iter()
+        .filter(|q| candidate_words.iter().any(|w| word_matches(q, w)) || (!q.is_ascii() && candidate_text.contains(q.as_str())))
+        .count();
+    let extra = candidate_words.len().saturating_sub(found).min(10);
+    (found as f64 / query_words.len() as f64 - extra as f64 * 0.01).max(0.0)
+}
@@ -0,0 +1,163 @@
+//! Moving playback from one renderer to another, e.g. from the living room TV
+//! to the bedroom: the source renderer is asked what it plays and where, then
+//! stopped, and the target renderer starts the same file at that position.
+
+use axum::{
+    extract::{ConnectInfo, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use log::{info, warn};
+use serde::Deserialize;
+use serde_json::json;
+use std::net::SocketAddr;
+use std::time::Duration;
+use crate::bot::play_file;
+use crate::context::AppContext;
+use crate::feeds::item_title;
+use crate::helper::{error_response, ApiResponse};
+use crate::playback::{fetch_resume_position, record_playback, update_watch_state_from_playback};
+use crate::profiles::ensure_file_allowed;
+use crate::renderer::{discover_renderers, find_renderer, PositionInfo, Renderer};
+use crate::shares::fetch_share_by_token;
+use crate::traffic::client_principal;
+use crate::upload_dao::fetch_uploaded_file_by_id;
+
+/// How long the target renderer gets to start playing before it is told to seek
+const START_TIMEOUT: Duration = Duration::from_secs(15);
+const START_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Seconds played again on the target, so nothing is missed while switching
+const REWIND_SECS: f64 = 3.0;
+
+/// The library file behind a share link the renderer streams from
+async fn shared_file_id(ctx: &AppContext, track_uri: &str) -> Option<String> {
+    let path = track_uri.split(['?', '#']).next()?;
+    let mut segments = path.rsplit('/');
+    let token = segments.next()?;
+    if segments.next() != Some("share") {
+        return None;
+    }
+    fetch_share_by_token(&ctx.app_state.db_pool, token).await.ok().flatten().map(|share| share.file_id)
+}
+
+/// Wait until the renderer plays, then move to `position_secs`
+async fn seek_when_playing(renderer: &Renderer, position_secs: f64) -> Result<(), String> {
+    let deadline = tokio::time::Instant::now() + START_TIMEOUT;
+    // 多数渲染器在开始播放前拒绝 Seek
+    while renderer.transport_state().await.as_deref() != Ok("PLAYING") {
+        if tokio::time::Instant::now() >= deadline {
+            return Err(format!("{} didn't start playing", renderer.name));
+        }
+        tokio::time::sleep(START_POLL_INTERVAL).await;
+    }
+    renderer.seek(position_secs).await
+}
+
+#[derive(Deserialize)]
+pub struct TransferRequest {
+    /// Renderer names, matched like `/simple/play`'s `device`
+    from: String,
+    to: String,
+    /// File to move when the source plays something that isn't a share link
+    /// of the library, e.g. media from the external media server
+    file_id: Option<String>,
+    /// Position to start at instead of the source's
+    position_secs: Option<f64>,
+}
+
+/// Stop playback on one renderer and continue it on another
+pub async fn transfer_playback(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    Json(req): Json<TransferRequest>,
+) -> Response {
+    if req.position_secs.is_some_and(|p| !p.is_finite() || p < 0.0) {
+        return error_response(StatusCode::BAD_REQUEST, "INVALID_PLAYBACK_POSITION", "position_secs must be a non-negative number".to_string());
+    }
+    let renderers = match discover_renderers().await {
+        Ok(renderers) => renderers,
+        Err(e) => return error_response(StatusCode::BAD_GATEWAY, "DISCOVERY_ERROR", e),
+    };
+    let (from, to) = match (find_renderer(&renderers, &req.from), find_renderer(&renderers, &req.to)) {
+        (Ok(from), Ok(to)) if from.location == to.location => {
+            return error_response(StatusCode::BAD_REQUEST, "SAME_DEVICE", format!("{} is both source and target", from.name));
+        }
+        (Ok(from), Ok(to)) => (from, to),
+        (Err(e), _) | (_, Err(e)) => return error_response(StatusCode::NOT_FOUND, "DEVICE_NOT_FOUND", e),
+    };
+
+    // 源渲染器不可达时仍可凭 file_id 与已记录的进度继续播放
+    let playing = match from.position_info().await {
+        Ok(info) => Some(info),
+        Err(e) => {
+            warn!("Couldn't read the position of {}: {}", from.name, e);
+            None
+        }
+    };
+    let file_id = match (&req.file_id, &playing) {
+        (Some(file_id), _) => Some(file_id.clone()),
+        (None, Some(info)) => shared_file_id(&ctx, &info.track_uri).await,
+        (None, None) => None,
+    };
+    let Some(file_id) = file_id else {
+        return error_response(StatusCode::CONFLICT, "NOTHING_PLAYING", format!(
+            "{} isn't playing a library file; pass file_id",
+            from.name
+        ));
+    };
+    if let Err(resp) = ensure_file_allowed(&ctx, &client_addr, &file_id).await {
+        return resp;
+    }
+    let db_pool = &ctx.app_state.db_pool;
+    let file = match fetch_uploaded_file_by_id(db_pool, &file_id).await {
+        Ok(Some(file)) if file.status == 2 => file,
+        Ok(_) => return error_response(StatusCode::NOT_FOUND, "FILE_NOT_FOUND", "File not found".to_string()),
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_FILE_RECORD_ERROR", e),
+    };
+
+    let principal = client_principal(&client_addr);
+    let reported = playing.as_ref().filter(|_| req.file_id.is_none()).and_then(|info| info.position_secs);
+    let position_secs = match req.position_secs.or(reported) {
+        Some(position) => position,
+        None => match fetch_resume_position(db_pool, &file_id, &principal).await {
+            Ok(position) => position.unwrap_or(0.0),
+            Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_PLAYBACK_ERROR", e),
+        },
+    };
+    // 记录切换时的进度，目标设备失败时仍可从这里继续
+    if let Some(PositionInfo { duration_secs: Some(duration_secs), .. }) = &playing {
+        if record_playback(db_pool, &file_id, &principal, position_secs, *duration_secs).await.is_ok() {
+            let _ = update_watch_state_from_playback(db_pool, &file_id, &principal, position_secs, *duration_secs).await;
+        }
+    }
+
+    if playing.is_some() {
+        if let Err(e) = from.stop().await {
+            warn!("Failed to stop {} for the transfer: {}", from.name, e);
+        }
+    }
+    if let Err(e) = play_file(&ctx, &file, to).await {
+        return error_response(StatusCode::BAD_GATEWAY, "PLAY_ERROR", e);
+    }
+    let start_secs = (position_secs - REWIND_SECS).max(0.0);
+    let seeked = if start_secs > 0.0 {
+        seek_when_playing(to, start_secs).await
+    } else {
+        Ok(())
+    };
+    if let Err(e) = &seeked {
+        warn!("Transfer to {} plays from the start: {}", to.name, e);
+    }
+    info!("Transferred {} from {} to {} at {:.0}s", file.file_id, from.name, to.name, start_secs);
+
+    let message = format!("Moved {} from {} to {}", item_title(&file), from.name, to.name);
+    (StatusCode::OK, Json(ApiResponse::success_with_message(&message, json!({
+        "file_id": file.file_id,
+        "title": item_title(&file),
+        "from": from.name,
+        "to": to.name,
+        "position_secs": if seeked.is_ok() { start_secs } else { 0.0 },
+    })))).into_response()
+}
@@ -0,0 +1,289 @@
+use axum::body::Bytes;
+use log::info;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::hint::black_box;
+use std::sync::OnceLock;
+use std::time::Instant;
+use tokio::sync::{mpsc, oneshot};
+use xxhash_rust::xxh3::Xxh3;
+
+/// Buffers queued for a hashing worker before the upload loop waits on it
+const HASH_QUEUE_DEPTH: usize = 16;
+
+/// The benchmark hashes this buffer `BENCHMARK_BUFFERS` times per round, as
+/// an upload feeds a chunk's body to the hasher
+const BENCHMARK_BUFFER_SIZE: usize = 1024 * 1024;
+const BENCHMARK_BUFFERS: usize = 8;
+/// The fastest round counts, so a cold cache or a busy core doesn't skew it
+const BENCHMARK_ROUNDS: usize = 3;
+
+static BENCHMARK: OnceLock<Vec<HashBenchmark>> = OnceLock::new();
+
+/// Algorithm used for per-chunk checksums
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    Sha256,
+    Blake3,
+    /// 128-bit xxHash3, non-cryptographic but very fast on weak CPUs
+    Xxh3,
+}
+
+impl HashAlgorithm {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "sha256" | "sha-256" => Some(Self::Sha256),
+            "blake3" => Some(Self::Blake3),
+            "xxh3" | "xxhash" | "xxh3-128" => Some(Self::Xxh3),
+            _ => None,
+        }
+    }
+
+    /// Name stored alongside checksums in the database and returned to clients
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Sha256 => "sha256",
+            Self::Blake3 => "blake3",
+            Self::Xxh3 => "xxh3",
+        }
+    }
+
+    pub const ALL: [HashAlgorithm; 3] = [HashAlgorithm::Sha256, HashAlgorithm::Blake3, HashAlgorithm::Xxh3];
+
+    /// Code path this CPU gets: instruction set extensions are detected at
+    /// runtime for SHA-256 and BLAKE3 and chosen at build time for xxh3
+    pub fn implementation(&self) -> &'static str {
+        match self {
+            Self::Sha256 => sha256_implementation(),
+            Self::Blake3 => blake3_implementation(),
+            Self::Xxh3 => xxh3_implementation(),
+        }
+    }
+
+    pub fn hasher(&self) -> ChunkHasher {
+        match self {
+            Self::Sha256 => ChunkHasher::Sha256(Sha256::new()),
+            Self::Blake3 => ChunkHasher::Blake3(Box::new(blake3::Hasher::new())),
+            Self::Xxh3 => ChunkHasher::Xxh3(Box::new(Xxh3::new())),
+        }
+    }
+}
+
+/// Incremental hasher for whichever algorithm a chunk was planned with
+#[derive(Clone)]
+pub enum ChunkHasher {
+    Sha256(Sha256),
+    Blake3(Box<blake3::Hasher>),
+    Xxh3(Box<Xxh3>),
+}
+
+impl ChunkHasher {
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Self::Sha256(h) => h.update(data),
+            Self::Blake3(h) => {
+                h.update(data);
+            }
+            Self::Xxh3(h) => h.update(data),
+        }
+    }
+
+    /// Hex digest of everything hashed so far, without consuming the hasher
+    pub fn hex_digest(&self) -> String {
+        match self {
+            Self::Sha256(h) => format!("{:x}", h.clone().finalize()),
+            Self::Blake3(h) => h.finalize().to_hex().to_string(),
+            Self::Xxh3(h) => format!("{:032x}", h.digest128()),
+        }
+    }
+}
+
+enum HashCommand {
+    Update(Bytes),
+    Digest(oneshot::Sender<String>),
+}
+
+/// Hasher running on the blocking thread pool, fed through a bounded channel
+/// so hashing large payloads doesn't compete with I/O on the async runtime
+pub struct OffloadedHasher {
+    tx: mpsc::Sender<HashCommand>,
+}
+
+impl OffloadedHasher {
+    pub fn spawn(algorithm: HashAlgorithm) -> Self {
+        let (tx, mut rx) = mpsc::channel::<HashCommand>(HASH_QUEUE_DEPTH);
+        tokio::task::spawn_blocking(move || {
+            let mut hasher = algorithm.hasher();
+            while let Some(command) = rx.blocking_recv() {
+                match command {
+                    HashCommand::Update(data) => hasher.update(&data),
+                    HashCommand::Digest(reply) => {
+                        let _ = reply.send(hasher.hex_digest());
+                    }
+                }
+            }
+        });
+        Self { tx }
+    }
+
+    async fn update(&self, data: Bytes) -> Result<(), String> {
+        self.tx
+            .send(HashCommand::Update(data))
+            .await
+            .map_err(|_| "Hash worker stopped unexpectedly".to_string())
+    }
+
+    async fn hex_digest(&self) -> Result<String, String> {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        self.tx
+            .send(HashCommand::Digest(reply_tx))
+            .await
+            .map_err(|_| "Hash worker stopped unexpectedly".to_string())?;
+        reply_rx
+            .await
+            .map_err(|_| "Hash worker dropped digest request".to_string())
+    }
+}
+
+/// Hasher used by the upload handler: small payloads are hashed inline,
+/// payloads at or above the configured threshold go to a blocking worker
+pub enum UploadHasher {
+    Inline(ChunkHasher),
+    Offloaded(OffloadedHasher),
+}
+
+impl UploadHasher {
+    pub fn new(algorithm: HashAlgorithm, payload_len: u64, offload_min_bytes: u64) -> Self {
+        if offload_min_bytes > 0 && payload_len >= offload_min_bytes {
+            Self::Offloaded(OffloadedHasher::spawn(algorithm))
+        } else {
+            Self::Inline(algorithm.hasher())
+        }
+    }
+
+    pub async fn update(&mut self, data: Bytes) -> Result<(), String> {
+        match self {
+            Self::Inline(h) => {
+                h.update(&data);
+                Ok(())
+            }
+            Self::Offloaded(h) => h.update(data).await,
+        }
+    }
+
+    pub async fn hex_digest(&self) -> Result<String, String> {
+        match self {
+            Self::Inline(h) => Ok(h.hex_digest()),
+            Self::Offloaded(h) => h.hex_digest().await,
+        }
+    }
+}
+
+fn sha256_implementation() -> &'static str {
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    if std::is_x86_feature_detected!("sha") {
+        return "sha-ni";
+    }
+    #[cfg(target_arch = "aarch64")]
+    if std::arch::is_aarch64_feature_detected!("sha2") {
+        return "armv8-crypto";
+    }
+    "software"
+}
+
+fn blake3_implementation() -> &'static str {
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    let implementation = if std::is_x86_feature_detected!("avx512f") && std::is_x86_feature_detected!("avx512vl") {
+        "avx512"
+    } else if std::is_x86_feature_detected!("avx2") {
+        "avx2"
+    } else if std::is_x86_feature_detected!("sse4.1") {
+        "sse41"
+    } else if std::is_x86_feature_detected!("sse2") {
+        "sse2"
+    } else {
+        "portable"
+    };
+    // aarch64 构建启用了 blake3 的 neon 特性
+    #[cfg(target_arch = "aarch64")]
+    let implementation = "neon";
+    #[cfg(not(any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64")))]
+    let implementation = "portable";
+    implementation
+}
+
+fn xxh3_implementation() -> &'static str {
+    if cfg!(target_feature = "avx512f") {
+        "avx512"
+    } else if cfg!(target_feature = "avx2") {
+        "avx2"
+    } else if cfg!(target_feature = "sse2") {
+        "sse2"
+    } else if cfg!(target_feature = "neon") {
+        "neon"
+    } else {
+        "scalar"
+    }
+}
+
+/// Throughput of one checksum algorithm on this CPU
+#[derive(Debug, Clone, Serialize)]
+pub struct HashBenchmark {
+    pub algorithm: HashAlgorithm,
+    pub implementation: &'static str,
+    pub mb_per_sec: f64,
+}
+
+fn measure(algorithm: HashAlgorithm, data: &[u8]) -> f64 {
+    let mut best = f64::MAX;
+    for _ in 0..BENCHMARK_ROUNDS {
+        let started = Instant::now();
+        let mut hasher = algorithm.hasher();
+        for _ in 0..BENCHMARK_BUFFERS {
+            hasher.update(black_box(data));
+        }
+        black_box(hasher.hex_digest());
+        best = best.min(started.elapsed().as_secs_f64());
+    }
+    let megabytes = (data.len() * BENCHMARK_BUFFERS) as f64 / 1_000_000.0;
+    (megabytes / best.max(f64::EPSILON) * 10.0).round() / 10.0
+}
+
+/// Hash a few megabytes with every algorithm, once per process. CPU bound,
+/// takes well under a second even on a Raspberry Pi.
+pub fn hash_benchmark() -> &'static [HashBenchmark] {
+    BENCHMARK.get_or_init(|| {
+        let data: Vec<u8> = (0..BENCHMARK_BUFFER_SIZE).map(|i| (i % 251) as u8).collect();
+        let results: Vec<HashBenchmark> = HashAlgorithm::ALL
+            .iter()
+            .map(|&algorithm| HashBenchmark {
+                algorithm,
+                implementation: algorithm.implementation(),
+                mb_per_sec: measure(algorithm, &data),
+            })
+            .collect();
+        results
+    })
+}
+
+/// The benchmark results, when `hash_benchmark` has run
+pub fn hash_benchmark_results() -> Option<&'static [HashBenchmark]> {
+    BENCHMARK.get().map(Vec::as_slice)
+}
+
+/// Log the benchmark results, once logging is set up
+pub fn log_hash_benchmark() {
+    for result in hash_benchmark_results().unwrap_or_default() {
+        info!("Hash benchmark: {} ({}) {} MB/s", result.algorithm.as_str(), result.implementation, result.mb_per_sec);
+    }
+}
+
+/// Fastest algorithm on this CPU, used for `NASCRAFT_HASH_ALGORITHM=auto`
+pub fn fastest_hash_algorithm() -> HashAlgorithm {
+    hash_benchmark()
+        .iter()
+        .max_by(|a, b| a.mb_per_sec.total_cmp(&b.mb_per_sec))
+        .map(|result| result.algorithm)
+        .unwrap_or(HashAlgorithm::Sha256)
+}
@@ -1,29 +1,133 @@
-use serde::Serialize;
-
-#[derive(Debug, Serialize)]
-pub struct ApiResponse<T> {
-    message: String,
-    status: i32,
-    code: String,
-    data: Option<T>,
-}
-
-impl<T> ApiResponse<T> {
-    pub fn success(data: T) -> Self {
-        Self {
-            message: "Success".to_string(),
-            status: 1,
-            code: "0".to_string(),
-            data: Some(data),
-        }
-    }
-
-    pub fn error(code: String, message: String) -> Self {
-        Self {
-            message,
-            status: 0,
-            code,
-            data: None,
-        }
-    }
-} 
\ No newline at end of file
+use axum::{
+    http::{StatusCode, Uri},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use nascraft::api::API_VERSION;
+
+#[derive(Debug, Serialize)]
+pub struct ApiResponse<T> {
+    message: String,
+    status: i32,
+    code: String,
+    data: Option<T>,
+    version: u32,
+}
+
+impl<T> ApiResponse<T> {
+    pub fn success(data: T) -> Self {
+        Self {
+            message: "Success".to_string(),
+            status: 1,
+            code: "0".to_string(),
+            data: Some(data),
+            version: API_VERSION,
+        }
+    }
+
+    /// Success with a message describing what was done instead of "Success"
+    pub fn success_with_message(message: &str, data: T) -> Self {
+        Self {
+            message: message.to_string(),
+            ..Self::success(data)
+        }
+    }
+
+    pub fn error(code: String, message: String) -> Self {
+        Self {
+            message,
+            status: 0,
+            code,
+            data: None,
+            version: API_VERSION,
+        }
+    }
+
+    /// Error with data that helps the client recover, e.g. alternatives to try
+    pub fn error_with_data(code: String, message: String, data: T) -> Self {
+        Self {
+            data: Some(data),
+            ..Self::error(code, message)
+        }
+    }
+
+    pub fn data_mut(&mut self) -> Option<&mut T> {
+        self.data.as_mut()
+    }
+}
+
+/// `status` with an `ApiResponse::error` body
+pub fn error_response(status: StatusCode, code: &str, message: String) -> Response {
+    (status, Json(ApiResponse::<()>::error(code.to_string(), message))).into_response()
+}
+
+/// Largest `page_size` a listing returns
+pub const MAX_PAGE_SIZE: u32 = 500;
+
+/// Human readable size for messages, e.g. `1.5 GB`
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+/// Escape text for XML content and attribute values
+pub fn xml_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// Compare a secret sent by a client with the configured one in constant
+/// time. Digests are compared so the length of the secret doesn't leak either.
+pub fn secret_matches(provided: &str, secret: &str) -> bool {
+    let provided = ring::digest::digest(&ring::digest::SHA256, provided.as_bytes());
+    let secret = ring::digest::digest(&ring::digest::SHA256, secret.as_bytes());
+    ring::constant_time::verify_slices_are_equal(provided.as_ref(), secret.as_ref()).is_ok()
+}
+
+/// One page of a listing. `page` starts at 1; `next` is the URL of the
+/// following page, None on the last one.
+#[derive(Debug, Serialize)]
+pub struct Paginated<T> {
+    pub items: Vec<T>,
+    pub page: u32,
+    pub page_size: u32,
+    pub total: i64,
+    pub next: Option<String>,
+}
+
+/// `uri` with its `page` query parameter set to `page`, other parameters kept
+fn page_url(uri: &Uri, page: u32) -> String {
+    let mut params: Vec<String> = uri
+        .query()
+        .unwrap_or("")
+        .split('&')
+        .filter(|param| !param.is_empty() && param.split('=').next() != Some("page"))
+        .map(str::to_string)
+        .collect();
+    params.push(format!("page={}", page));
+    format!("{}?{}", uri.path(), params.join("&"))
+}
+
+impl<T> ApiResponse<Paginated<T>> {
+    /// `uri` is the request's original URI, used to link the next page
+    pub fn paginated(items: Vec<T>, page: u32, page_size: u32, total: i64, uri: &Uri) -> Self {
+        let shown = page as i64 * page_size as i64;
+        let next = (shown < total).then(|| page_url(uri, page + 1));
+        Self::success(Paginated { items, page, page_size, total, next })
+    }
+}
\ No newline at end of file
@@ -0,0 +1,436 @@
+//! Post-upload hooks, the escape hatch for anything the server doesn't do
+//! itself. A hook runs one of the scripts in `NASCRAFT_HOOKS_DIR` for every
+//! completed upload in its folder and of its MIME type, with the file passed
+//! in environment variables. Each run is recorded as a `hook` job with the
+//! command's outcome and output.
+
+use axum::{
+    extract::{ConnectInfo, Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, SqlitePool};
+use std::net::SocketAddr;
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::Semaphore;
+use crate::config::SharedConfig;
+use crate::context::AppContext;
+use crate::events::{EventSender, ServerEvent};
+use crate::folders::{fetch_file_folder, fetch_folder, mime_matches};
+use crate::helper::{error_response, ApiResponse};
+use crate::jobs::{create_hook_job, fetch_hook_jobs, finish_job, set_job_output, update_job_progress, JobFailure};
+use crate::profiles::ensure_unrestricted;
+use crate::upload_dao::{fetch_uploaded_file_by_id, UploadedFile};
+
+const DEFAULT_TIMEOUT_SECS: i64 = 300;
+const MAX_TIMEOUT_SECS: i64 = 24 * 3600;
+
+/// Output kept of each of stdout and stderr; the rest is discarded
+const MAX_OUTPUT_BYTES: usize = 64 * 1024;
+
+/// How long output is still read after the command exited, in case a
+/// process it started keeps the pipes open
+const OUTPUT_GRACE: Duration = Duration::from_secs(2);
+
+/// Hook commands running at the same time; further runs wait for a slot
+const MAX_CONCURRENT_RUNS: usize = 4;
+
+const MAX_LISTED_RUNS: i64 = 100;
+
+/// Variables of the server's environment passed on to hook commands. The rest,
+/// including the server's keys and tokens, is not.
+const INHERITED_ENV: &[&str] = &["PATH", "HOME", "LANG", "TZ"];
+
+#[derive(Debug, FromRow)]
+struct HookRow {
+    id: i64,
+    name: String,
+    folder_id: Option<i64>,
+    mime_type: Option<String>,
+    command: String,
+    args: String,
+    timeout_secs: i64,
+    created_at: i64,
+}
+
+/// A command run for completed uploads in `folder_id` (any folder when unset)
+/// whose MIME type matches `mime_type` (`type/*` wildcards allowed, any type
+/// when unset)
+#[derive(Debug, Clone, Serialize)]
+pub struct Hook {
+    pub id: i64,
+    pub name: String,
+    pub folder_id: Option<i64>,
+    pub mime_type: Option<String>,
+    /// Script file name in `NASCRAFT_HOOKS_DIR`
+    pub command: String,
+    pub args: Vec<String>,
+    pub timeout_secs: i64,
+    #[serde(with = "crate::api::timestamp")]
+    pub created_at: i64,
+}
+
+impl From<HookRow> for Hook {
+    fn from(row: HookRow) -> Self {
+        Self {
+            id: row.id,
+            name: row.name,
+            folder_id: row.folder_id,
+            mime_type: row.mime_type,
+            command: row.command,
+            args: serde_json::from_str(&row.args).unwrap_or_default(),
+            timeout_secs: row.timeout_secs,
+            created_at: row.created_at,
+        }
+    }
+}
+
+impl Hook {
+    fn matches(&self, folder_id: Option<i64>, mime: &str) -> bool {
+        self.folder_id.is_none_or(|id| folder_id == Some(id))
+            && self.mime_type.as_deref().is_none_or(|pattern| mime_matches(pattern, mime))
+    }
+}
+
+pub async fn fetch_hooks(db_pool: &SqlitePool) -> Result<Vec<Hook>, String> {
+    sqlx::query_as::<_, HookRow>(
+        "SELECT id, name, folder_id, mime_type, command, args, timeout_secs, created_at FROM hooks ORDER BY id"
+    )
+    .fetch_all(db_pool)
+    .await
+    .map(|rows| rows.into_iter().map(Hook::from).collect())
+    .map_err(|e| {
+        error!("Failed to fetch hooks: {}", e);
+        "Failed to fetch hooks".to_string()
+    })
+}
+
+/// A command is the name of a file directly in the hooks directory
+fn is_valid_command(command: &str) -> bool {
+    !command.is_empty() && !command.starts_with('.') && !command.contains(['/', '\\'])
+}
+
+/// Read a pipe to the end into `kept`, keeping the first `MAX_OUTPUT_BYTES`
+async fn read_capped<R: AsyncRead + Unpin>(mut reader: R, kept: Arc<Mutex<Vec<u8>>>) {
+    let mut buffer = [0u8; 8192];
+    loop {
+        match reader.read(&mut buffer).await {
+            Ok(0) | Err(_) => return,
+            Ok(n) => {
+                let mut kept = kept.lock().unwrap_or_else(|e| e.into_inner());
+                let room = MAX_OUTPUT_BYTES.saturating_sub(kept.len());
+                kept.extend_from_slice(&buffer[..n.min(room)]);
+            }
+        }
+    }
+}
+
+fn combined_output(stdout: &[u8], stderr: &[u8]) -> String {
+    let mut output = String::from_utf8_lossy(stdout).into_owned();
+    if !stderr.is_empty() {
+        if !output.is_empty() && !output.ends_with('\n') {
+            output.push('\n');
+        }
+        output.push_str("[stderr]\n");
+        output.push_str(&String::from_utf8_lossy(stderr));
+    }
+    output
+}
+
+/// Run the hook's command, returning an error message when it failed and the
+/// output it printed
+async fn execute(hook: &Hook, program: &std::path::Path, env: &[(&str, String)]) -> (Option<String>, String) {
+    let mut command = tokio::process::Command::new(program);
+    command
+        .args(&hook.args)
+        .env_clear()
+        .envs(INHERITED_ENV.iter().filter_map(|name| std::env::var(name).ok().map(|value| (*name, value))))
+        .envs(env.iter().map(|(name, value)| (*name, value)))
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    let mut child = match command.spawn() {
+        Ok(child) => child,
+        Err(e) => return (Some(format!("Failed to start {}: {}", program.display(), e)), String::new()),
+    };
+
+    let (stdout, stderr) = (Arc::new(Mutex::new(Vec::new())), Arc::new(Mutex::new(Vec::new())));
+    let mut readers = Vec::new();
+    if let Some(pipe) = child.stdout.take() {
+        readers.push(tokio::spawn(read_capped(pipe, stdout.clone())));
+    }
+    if let Some(pipe) = child.stderr.take() {
+        readers.push(tokio::spawn(read_capped(pipe, stderr.clone())));
+    }
+
+    let error = match tokio::time::timeout(Duration::from_secs(hook.timeout_secs as u64), child.wait()).await {
+        Ok(Ok(status)) if status.success() => None,
+        Ok(Ok(status)) => Some(format!("Command exited with {}", status)),
+        Ok(Err(e)) => Some(format!("Failed to wait for the command: {}", e)),
+        Err(_) => {
+            let _ = child.kill().await;
+            Some(format!("Command timed out after {} seconds", hook.timeout_secs))
+        }
+    };
+    for reader in readers {
+        let abort = reader.abort_handle();
+        if tokio::time::timeout(OUTPUT_GRACE, reader).await.is_err() {
+            abort.abort();
+        }
+    }
+
+    let output = combined_output(
+        &stdout.lock().unwrap_or_else(|e| e.into_inner()),
+        &stderr.lock().unwrap_or_else(|e| e.into_inner()),
+    );
+    (error, output)
+}
+
+/// Run one hook for a completed file and record it as a job
+async fn run_hook(db_pool: &SqlitePool, hooks_dir: &std::path::Path, hook: &Hook, file: &UploadedFile, folder_id: Option<i64>, tenant_id: Option<i64>, mime: &str) {
+    let program = hooks_dir.join(&hook.command);
+    let job_id = match create_hook_job(db_pool, hook.id, &file.file_id, &program.display().to_string(), file.total_size).await {
+        Ok(id) => id,
+        Err(_) => return,
+    };
+
+    let file_path = std::path::absolute(&file.file_path).unwrap_or_else(|_| file.file_path.clone().into());
+    let mut env = vec![
+        ("NASCRAFT_HOOK_ID", hook.id.to_string()),
+        ("NASCRAFT_HOOK_NAME", hook.name.clone()),
+        ("NASCRAFT_JOB_ID", job_id.to_string()),
+        ("NASCRAFT_FILE_ID", file.file_id.clone()),
+        ("NASCRAFT_FILE_NAME", file.filename.clone()),
+        ("NASCRAFT_FILE_PATH", file_path.display().to_string()),
+        ("NASCRAFT_FILE_SIZE", file.total_size.to_string()),
+        ("NASCRAFT_FILE_MD5", file.checksum.clone()),
+        ("NASCRAFT_MIME_TYPE", mime.to_string()),
+    ];
+    if let Some(folder_id) = folder_id {
+        env.push(("NASCRAFT_FOLDER_ID", folder_id.to_string()));
+    }
+    if let Some(tenant_id) = tenant_id {
+        env.push(("NASCRAFT_TENANT_ID", tenant_id.to_string()));
+    }
+
+    info!("Running hook '{}' for file ID {}", hook.name, file.file_id);
+    let (error_text, output) = execute(hook, &program, &env).await;
+    let _ = set_job_output(db_pool, job_id, &output).await;
+    match &error_text {
+        None => {
+            info!("Hook '{}' succeeded for file ID {}", hook.name, file.file_id);
+            let _ = update_job_progress(db_pool, job_id, 1, file.total_size, &[]).await;
+        }
+        Some(e) => {
+            warn!("Hook '{}' failed for file ID {}: {}", hook.name, file.file_id, e);
+            let failure = JobFailure { file_id: file.file_id.clone(), filename: file.filename.clone(), error: e.clone() };
+            let _ = update_job_progress(db_pool, job_id, 0, 0, &[failure]).await;
+        }
+    }
+    let _ = finish_job(db_pool, job_id, error_text.as_deref()).await;
+}
+
+/// Start the hooks matching a completed file, each waiting for a free slot
+async fn start_hooks(db_pool: &SqlitePool, slots: &Arc<Semaphore>, hooks_dir: std::path::PathBuf, file_id: &str, tenant_id: Option<i64>) {
+    let hooks = match fetch_hooks(db_pool).await {
+        Ok(hooks) if !hooks.is_empty() => hooks,
+        _ => return,
+    };
+    let file = match fetch_uploaded_file_by_id(db_pool, file_id).await {
+        Ok(Some(file)) => file,
+        Ok(None) | Err(_) => return,
+    };
+    let folder_id = match fetch_file_folder(db_pool, file_id).await {
+        Ok(folder) => folder.map(|f| f.id),
+        Err(_) => return,
+    };
+    let mime = mime_guess::from_path(&file.filename).first_or_octet_stream().essence_str().to_string();
+
+    let file = Arc::new(file);
+    for hook in hooks.into_iter().filter(|hook| hook.matches(folder_id, &mime)) {
+        let (db_pool, slots, hooks_dir, file, mime) = (db_pool.clone(), slots.clone(), hooks_dir.clone(), file.clone(), mime.clone());
+        tokio::spawn(async move {
+            let Ok(_slot) = slots.acquire_owned().await else {
+                return;
+            };
+            run_hook(&db_pool, &hooks_dir, &hook, &file, folder_id, tenant_id, &mime).await;
+        });
+    }
+}
+
+/// Run the matching hooks for completed uploads while `NASCRAFT_HOOKS_DIR` is set
+pub async fn run_hook_runner(db_pool: SqlitePool, config: SharedConfig, events: EventSender) {
+    let slots = Arc::new(Semaphore::new(MAX_CONCURRENT_RUNS));
+    let mut receiver = events.subscribe();
+    loop {
+        match receiver.recv().await {
+            Ok(ServerEvent::UploadCompleted { file_id, tenant_id, .. }) => {
+                let Some(hooks_dir) = config.load().hooks_dir.clone() else {
+                    continue;
+                };
+                start_hooks(&db_pool, &slots, hooks_dir, &file_id, tenant_id).await;
+            }
+            Ok(_) => {}
+            Err(RecvError::Lagged(skipped)) => warn!("Hook runner fell behind, hooks didn't run for {} events", skipped),
+            Err(RecvError::Closed) => return,
+        }
+    }
+}
+
+fn invalid_hook(message: String) -> Response {
+    error_response(StatusCode::BAD_REQUEST, "INVALID_HOOK", message)
+}
+
+fn hook_not_found() -> Response {
+    error_response(StatusCode::NOT_FOUND, "HOOK_NOT_FOUND", "Hook not found".to_string())
+}
+
+pub async fn list_hooks(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+) -> impl IntoResponse {
+    if let Err(resp) = ensure_unrestricted(&ctx, &client_addr).await {
+        return resp;
+    }
+    match fetch_hooks(&ctx.app_state.db_pool).await {
+        Ok(hooks) => (StatusCode::OK, Json(ApiResponse::success(hooks))).into_response(),
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_HOOKS_ERROR", e),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CreateHookRequest {
+    name: String,
+    folder_id: Option<i64>,
+    mime_type: Option<String>,
+    command: String,
+    #[serde(default)]
+    args: Vec<String>,
+    timeout_secs: Option<i64>,
+}
+
+pub async fn create_hook(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    Json(req): Json<CreateHookRequest>,
+) -> impl IntoResponse {
+    if let Err(resp) = ensure_unrestricted(&ctx, &client_addr).await {
+        return resp;
+    }
+    let db_pool = &ctx.app_state.db_pool;
+
+    let name = req.name.trim().to_string();
+    if name.is_empty() {
+        return invalid_hook("Hook name must not be empty".to_string());
+    }
+    let Some(hooks_dir) = ctx.config.load().hooks_dir.clone() else {
+        return invalid_hook("Hooks need NASCRAFT_HOOKS_DIR to be set".to_string());
+    };
+    let command = req.command.trim().to_string();
+    if !is_valid_command(&command) {
+        return invalid_hook("command must be the name of a script in NASCRAFT_HOOKS_DIR, without a path".to_string());
+    }
+    if !hooks_dir.join(&command).is_file() {
+        return invalid_hook(format!("{} is not a file in {}", command, hooks_dir.display()));
+    }
+    let mime_type = req.mime_type.map(|m| m.trim().to_lowercase()).filter(|m| !m.is_empty());
+    if mime_type.as_deref().is_some_and(|m| !m.contains('/')) {
+        return invalid_hook("mime_type must look like image/jpeg or image/*".to_string());
+    }
+    let timeout_secs = req.timeout_secs.unwrap_or(DEFAULT_TIMEOUT_SECS);
+    if !(1..=MAX_TIMEOUT_SECS).contains(&timeout_secs) {
+        return invalid_hook(format!("timeout_secs must be between 1 and {}", MAX_TIMEOUT_SECS));
+    }
+    if let Some(folder_id) = req.folder_id {
+        match fetch_folder(db_pool, folder_id).await {
+            Ok(Some(_)) => {}
+            Ok(None) => return error_response(StatusCode::NOT_FOUND, "FOLDER_NOT_FOUND", "Folder not found".to_string()),
+            Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_FOLDER_ERROR", e),
+        }
+    }
+
+    let result = sqlx::query(
+        "INSERT INTO hooks (name, folder_id, mime_type, command, args, timeout_secs, created_at) VALUES (?, ?, ?, ?, ?, ?, strftime('%s', 'now'))"
+    )
+    .bind(&name)
+    .bind(req.folder_id)
+    .bind(&mime_type)
+    .bind(&command)
+    .bind(serde_json::to_string(&req.args).unwrap_or_else(|_| "[]".to_string()))
+    .bind(timeout_secs)
+    .execute(db_pool)
+    .await;
+
+    match result {
+        Ok(done) => {
+            info!("Created hook '{}' running {}", name, command);
+            (StatusCode::OK, Json(ApiResponse::success(Hook {
+                id: done.last_insert_rowid(),
+                name,
+                folder_id: req.folder_id,
+                mime_type,
+                command,
+                args: req.args,
+                timeout_secs,
+                created_at: chrono::Utc::now().timestamp(),
+            }))).into_response()
+        }
+        Err(e) => {
+            error!("Failed to create hook: {}", e);
+            error_response(StatusCode::INTERNAL_SERVER_ERROR, "CREATE_HOOK_ERROR", "Failed to create hook".to_string())
+        }
+    }
+}
+
+pub async fn delete_hook(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    Path(id): Path<i64>,
+) -> impl IntoResponse {
+    if let Err(resp) = ensure_unrestricted(&ctx, &client_addr).await {
+        return resp;
+    }
+    match sqlx::query("DELETE FROM hooks WHERE id = ?").bind(id).execute(&ctx.app_state.db_pool).await {
+        Ok(done) if done.rows_affected() == 0 => hook_not_found(),
+        Ok(_) => {
+            info!("Deleted hook {}", id);
+            (StatusCode::OK, Json(ApiResponse::success(()))).into_response()
+        }
+        Err(e) => {
+            error!("Failed to delete hook: {}", e);
+            error_response(StatusCode::INTERNAL_SERVER_ERROR, "DELETE_HOOK_ERROR", "Failed to delete hook".to_string())
+        }
+    }
+}
+
+/// The newest runs of a hook, as jobs with the command's output
+pub async fn list_hook_runs(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    Path(id): Path<i64>,
+) -> impl IntoResponse {
+    if let Err(resp) = ensure_unrestricted(&ctx, &client_addr).await {
+        return resp;
+    }
+    let db_pool = &ctx.app_state.db_pool;
+    match sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM hooks WHERE id = ?").bind(id).fetch_one(db_pool).await {
+        Ok(0) => return hook_not_found(),
+        Ok(_) => {}
+        Err(e) => {
+            error!("Failed to fetch hook {}: {}", id, e);
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_HOOKS_ERROR", "Failed to fetch hook".to_string());
+        }
+    }
+    match fetch_hook_jobs(db_pool, id, MAX_LISTED_RUNS).await {
+        Ok(runs) => (StatusCode::OK, Json(ApiResponse::success(runs))).into_response(),
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_HOOK_RUNS_ERROR", e),
+    }
+}
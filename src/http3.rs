@@ -0,0 +1,149 @@
+use axum::{
+    body::Body,
+    extract::ConnectInfo,
+    http::{header, HeaderValue, Request, Response},
+    middleware, Router,
+};
+use bytes::{Buf, Bytes};
+use futures::{Stream, StreamExt};
+use h3::error::StreamError;
+use h3::server::RequestStream;
+use log::{error, info, warn};
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use tower::ServiceExt;
+
+fn load_tls_config(cert_path: &Path, key_path: &Path) -> io::Result<rustls::ServerConfig> {
+    let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(cert_path)?)).collect::<Result<Vec<_>, _>>()?;
+    let key = rustls_pemfile::private_key(&mut BufReader::new(File::open(key_path)?))?.ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, format!("No private key found in {}", key_path.display()))
+    })?;
+
+    let mut tls = rustls::ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .map_err(io::Error::other)?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(io::Error::other)?;
+    tls.alpn_protocols = vec![b"h3".to_vec()];
+    Ok(tls)
+}
+
+/// Bind a QUIC endpoint on `port` and serve `app` over HTTP/3 in the background
+pub fn serve_http3(app: Router, port: u16, cert_path: &Path, key_path: &Path) -> io::Result<()> {
+    let tls = load_tls_config(cert_path, key_path)?;
+    let crypto = quinn::crypto::rustls::QuicServerConfig::try_from(tls).map_err(io::Error::other)?;
+    let bind_addr = SocketAddr::from(([0, 0, 0, 0], port));
+    info!("Binding HTTP/3 listener: addr={} (udp)", bind_addr);
+    let endpoint = quinn::Endpoint::server(quinn::ServerConfig::with_crypto(Arc::new(crypto)), bind_addr)?;
+
+    tokio::spawn(async move {
+        info!("HTTP/3 server started");
+        while let Some(incoming) = endpoint.accept().await {
+            let app = app.clone();
+            tokio::spawn(async move {
+                let remote_addr = incoming.remote_address();
+                match incoming.await {
+                    Ok(conn) => serve_connection(app, conn, remote_addr).await,
+                    Err(e) => warn!("HTTP/3 handshake with {} failed: {}", remote_addr, e),
+                }
+            });
+        }
+    });
+
+    Ok(())
+}
+
+/// Advertise the HTTP/3 endpoint on TCP responses so clients can switch over
+pub fn advertise_alt_svc(app: Router, port: u16) -> Router {
+    let alt_svc = HeaderValue::from_str(&format!("h3=\":{}\"; ma=86400", port)).expect("valid Alt-Svc value");
+    app.layer(middleware::map_response(move |mut response: Response<Body>| {
+        let alt_svc = alt_svc.clone();
+        async move {
+            response.headers_mut().insert(header::ALT_SVC, alt_svc);
+            response
+        }
+    }))
+}
+
+async fn serve_connection(app: Router, conn: quinn::Connection, remote_addr: SocketAddr) {
+    let mut h3_conn = match h3::server::Connection::<_, Bytes>::new(h3_quinn::Connection::new(conn)).await {
+        Ok(h3_conn) => h3_conn,
+        Err(e) => {
+            warn!("HTTP/3 connection setup with {} failed: {}", remote_addr, e);
+            return;
+        }
+    };
+
+    loop {
+        match h3_conn.accept().await {
+            Ok(Some(resolver)) => {
+                let app = app.clone();
+                tokio::spawn(async move {
+                    let result = match resolver.resolve_request().await {
+                        Ok((req, stream)) => serve_request(app, req, stream, remote_addr).await,
+                        Err(e) => Err(e),
+                    };
+                    if let Err(e) = result {
+                        warn!("HTTP/3 request from {} failed: {}", remote_addr, e);
+                    }
+                });
+            }
+            Ok(None) => break,
+            Err(e) => {
+                if !e.is_h3_no_error() {
+                    warn!("HTTP/3 connection with {} closed: {}", remote_addr, e);
+                }
+                break;
+            }
+        }
+    }
+}
+
+/// 请求体以流的形式交给 axum，上传时无需先整体缓存
+fn request_body(recv: RequestStream<h3_quinn::RecvStream, Bytes>) -> impl Stream<Item = Result<Bytes, StreamError>> + Send + 'static {
+    futures::stream::unfold(Some(recv), |recv| async move {
+        let mut recv = recv?;
+        match recv.recv_data().await {
+            Ok(Some(mut buf)) => Some((Ok(buf.copy_to_bytes(buf.remaining())), Some(recv))),
+            Ok(None) => None,
+            Err(e) => Some((Err(e), None)),
+        }
+    })
+}
+
+async fn serve_request(
+    app: Router,
+    req: Request<()>,
+    stream: RequestStream<h3_quinn::BidiStream<Bytes>, Bytes>,
+    remote_addr: SocketAddr,
+) -> Result<(), StreamError> {
+    let (mut send, recv) = stream.split();
+    let (parts, ()) = req.into_parts();
+    let mut req = Request::from_parts(parts, Body::from_stream(request_body(recv)));
+    // Handlers use ConnectInfo for traffic accounting and per-client state
+    req.extensions_mut().insert(ConnectInfo(remote_addr));
+
+    let response = match app.oneshot(req).await {
+        Ok(response) => response,
+        Err(infallible) => match infallible {},
+    };
+
+    let (parts, body) = response.into_parts();
+    send.send_response(Response::from_parts(parts, ())).await?;
+
+    let mut data = body.into_data_stream();
+    while let Some(chunk) = data.next().await {
+        match chunk {
+            Ok(bytes) => send.send_data(bytes).await?,
+            Err(e) => {
+                error!("Failed to stream HTTP/3 response body to {}: {}", remote_addr, e);
+                break;
+            }
+        }
+    }
+    send.finish().await
+}
@@ -0,0 +1,200 @@
+//! Hardware video encoders for transcodes. Software x264/VP9 encodes are far
+//! too slow on the low-power CPUs of most NAS boxes, so the accelerators
+//! enabled by `NASCRAFT_FFMPEG_HWACCEL` are probed once at startup and each
+//! transcode uses the fastest one that works for its format.
+
+use log::{info, warn};
+use serde::Serialize;
+use std::process::Stdio;
+use std::time::Duration;
+use crate::config::AppConfig;
+
+/// A test encode taking longer than this counts as a failed probe
+const PROBE_TIMEOUT: Duration = Duration::from_secs(15);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HwAccel {
+    Nvenc,
+    Qsv,
+    Vaapi,
+}
+
+impl HwAccel {
+    /// Fastest first, the order `auto` tries them in
+    pub const ALL: [HwAccel; 3] = [HwAccel::Nvenc, HwAccel::Qsv, HwAccel::Vaapi];
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "nvenc" | "cuda" => Some(Self::Nvenc),
+            "qsv" => Some(Self::Qsv),
+            "vaapi" => Some(Self::Vaapi),
+            _ => None,
+        }
+    }
+
+    /// `auto` for every accelerator, `none` for software only, or a comma
+    /// separated list in order of preference
+    pub fn parse_list(value: &str) -> Option<Vec<Self>> {
+        match value.trim().to_lowercase().as_str() {
+            "auto" => return Some(Self::ALL.to_vec()),
+            "none" | "off" | "software" => return Some(Vec::new()),
+            _ => {}
+        }
+        let mut accels = Vec::new();
+        for accel in value.split(',').filter(|v| !v.trim().is_empty()) {
+            let accel = Self::parse(accel)?;
+            if !accels.contains(&accel) {
+                accels.push(accel);
+            }
+        }
+        Some(accels)
+    }
+
+    /// ffmpeg encoder of this accelerator for a transcode format
+    fn codec(self, format: &str) -> Option<&'static str> {
+        match (self, format) {
+            (Self::Nvenc, "mp4") => Some("h264_nvenc"),
+            (Self::Qsv, "mp4") => Some("h264_qsv"),
+            (Self::Qsv, "webm") => Some("vp9_qsv"),
+            (Self::Vaapi, "mp4") => Some("h264_vaapi"),
+            (Self::Vaapi, "webm") => Some("vp9_vaapi"),
+            _ => None,
+        }
+    }
+}
+
+/// A hardware encoder that passed the startup probe
+#[derive(Clone, Debug, Serialize)]
+pub struct HwEncoder {
+    pub accel: HwAccel,
+    pub format: &'static str,
+    pub codec: &'static str,
+}
+
+impl HwEncoder {
+    /// ffmpeg options that go before the input
+    pub fn input_args(&self, vaapi_device: &str) -> Vec<String> {
+        match self.accel {
+            HwAccel::Vaapi => vec!["-vaapi_device".to_string(), vaapi_device.to_string()],
+            HwAccel::Nvenc | HwAccel::Qsv => Vec::new(),
+        }
+    }
+
+    /// ffmpeg video options, in place of those of the software encoder
+    pub fn video_args(&self) -> Vec<String> {
+        // 与软件编码的 crf 大致相当的质量
+        let quality = if self.format == "webm" { "32" } else { "23" };
+        let mut args = vec!["-c:v", self.codec];
+        match (self.accel, self.format) {
+            (HwAccel::Nvenc, _) => args.extend(["-preset", "p4", "-rc", "vbr", "-cq", quality, "-b:v", "0"]),
+            (HwAccel::Qsv, _) => args.extend(["-vf", "format=nv12", "-global_quality", quality]),
+            (HwAccel::Vaapi, "mp4") => args.extend(["-vf", "format=nv12,hwupload", "-qp", quality]),
+            (HwAccel::Vaapi, _) => args.extend(["-vf", "format=nv12,hwupload", "-global_quality", quality]),
+        }
+        args.into_iter().map(String::from).collect()
+    }
+}
+
+/// The hardware encoders found at startup, fastest first
+#[derive(Debug, Default)]
+pub struct HwEncoders {
+    encoders: Vec<HwEncoder>,
+}
+
+impl HwEncoders {
+    /// Find which of the configured accelerators can encode each transcode
+    /// format: ffmpeg must list the encoder and a short test encode must succeed
+    pub async fn probe(config: &AppConfig, formats: &[&'static str]) -> Self {
+        if config.ffmpeg_hwaccel.is_empty() {
+            info!("Hardware encoding is disabled, transcoding in software");
+            return Self::default();
+        }
+        let listed = match list_encoders(config).await {
+            Ok(listed) => listed,
+            Err(e) => {
+                warn!("Failed to list ffmpeg encoders, transcoding in software: {}", e);
+                return Self::default();
+            }
+        };
+
+        let mut encoders = Vec::new();
+        for &accel in &config.ffmpeg_hwaccel {
+            for &format in formats {
+                let Some(codec) = accel.codec(format) else {
+                    continue;
+                };
+                if !listed.iter().any(|name| name == codec) {
+                    continue;
+                }
+                let encoder = HwEncoder { accel, format, codec };
+                match test_encode(config, &encoder).await {
+                    Ok(()) => encoders.push(encoder),
+                    Err(e) => info!("Hardware encoder {} is not usable: {}", codec, e),
+                }
+            }
+        }
+        if encoders.is_empty() {
+            info!("No hardware encoder is available, transcoding in software");
+        } else {
+            let codecs: Vec<&str> = encoders.iter().map(|e| e.codec).collect();
+            info!("Hardware encoders available: {}", codecs.join(", "));
+        }
+        Self { encoders }
+    }
+
+    /// Fastest working encoder for `format`, None to encode in software
+    pub fn select(&self, format: &str) -> Option<&HwEncoder> {
+        self.encoders.iter().find(|e| e.format == format)
+    }
+
+    pub fn encoders(&self) -> &[HwEncoder] {
+        &self.encoders
+    }
+}
+
+/// Names of the encoders ffmpeg was built with
+async fn list_encoders(config: &AppConfig) -> Result<Vec<String>, String> {
+    let output = tokio::process::Command::new(&config.ffmpeg_path)
+        .args(["-hide_banner", "-encoders"])
+        .stdin(Stdio::null())
+        .output()
+        .await
+        .map_err(|e| format!("Failed to run {}: {}", config.ffmpeg_path, e))?;
+    if !output.status.success() {
+        return Err(format!("ffmpeg exited with {}", output.status));
+    }
+    // 每行形如 " V....D h264_nvenc  NVIDIA NVENC H.264 encoder"
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.split_whitespace().nth(1))
+        .map(String::from)
+        .collect())
+}
+
+/// Encode a fraction of a second of a generated picture, which fails when the
+/// driver or device is missing even though ffmpeg lists the encoder
+async fn test_encode(config: &AppConfig, encoder: &HwEncoder) -> Result<(), String> {
+    let child = tokio::process::Command::new(&config.ffmpeg_path)
+        .args(["-hide_banner", "-nostdin", "-loglevel", "error"])
+        .args(encoder.input_args(&config.vaapi_device))
+        .args(["-f", "lavfi", "-i", "color=black:s=256x256:d=0.1"])
+        .args(encoder.video_args())
+        .args(["-f", "null", "-"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| format!("Failed to run {}: {}", config.ffmpeg_path, e))?;
+
+    let output = tokio::time::timeout(PROBE_TIMEOUT, child.wait_with_output())
+        .await
+        .map_err(|_| format!("test encode timed out after {}s", PROBE_TIMEOUT.as_secs()))?
+        .map_err(|e| format!("Failed to wait for {}: {}", config.ffmpeg_path, e))?;
+    if output.status.success() {
+        return Ok(());
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    Err(stderr.lines().last().unwrap_or("test encode failed").to_string())
+}
@@ -0,0 +1,205 @@
+//! Scaled-down copies of images for web UIs and renderers that don't need the
+//! full-size original. Variants are cached under `thumbnails/variants`, keyed
+//! by the image's checksum like thumbnails, so a changed image never serves a
+//! stale variant.
+
+use axum::{
+    extract::{ConnectInfo, Path, Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+};
+use image::codecs::jpeg::JpegEncoder;
+use image::{io::Reader as ImageReader, GenericImageView, ImageFormat};
+use log::{error, info};
+use serde::Deserialize;
+use std::net::SocketAddr;
+use std::path::{Path as FsPath, PathBuf};
+use crate::context::AppContext;
+use crate::helper::error_response;
+use crate::paths::long_path;
+use crate::profiles::ensure_file_allowed;
+use crate::shares::shared_file;
+use crate::thumbnail::{is_image_file, ThumbnailConfig};
+use crate::traffic::{client_principal, record_traffic};
+use crate::upload_dao::{fetch_uploaded_file_by_id, UploadedFile};
+
+/// Largest width or height a variant may be requested with
+const MAX_DIMENSION: u32 = 4096;
+
+const JPEG_QUALITY: u8 = 85;
+
+#[derive(Debug, Clone, Copy, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum VariantFormat {
+    #[default]
+    Jpeg,
+    Png,
+    /// Lossless, best for graphics rather than photos
+    Webp,
+}
+
+impl VariantFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Jpeg => "jpg",
+            Self::Png => "png",
+            Self::Webp => "webp",
+        }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            Self::Jpeg => "image/jpeg",
+            Self::Png => "image/png",
+            Self::Webp => "image/webp",
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ImageQuery {
+    w: Option<u32>,
+    h: Option<u32>,
+    #[serde(default)]
+    format: VariantFormat,
+}
+
+fn variant_path(checksum: &str, width: u32, height: u32, format: VariantFormat) -> PathBuf {
+    FsPath::new(&ThumbnailConfig::default().thumbnails_dir)
+        .join("variants")
+        .join(format!("{}-{}x{}.{}", checksum, width, height, format.extension()))
+}
+
+/// Scale `source` to fit in `width` x `height`, never enlarging it, and encode
+/// it in `format`
+fn resize_sync(source: &str, width: u32, height: u32, format: VariantFormat) -> Result<Vec<u8>, String> {
+    let img = ImageReader::open(long_path(FsPath::new(source)))
+        .map_err(|e| format!("Failed to open image: {}", e))?
+        .with_guessed_format()
+        .map_err(|e| format!("Failed to read image: {}", e))?
+        .decode()
+        .map_err(|e| format!("Failed to decode image: {}", e))?;
+
+    let (original_width, original_height) = img.dimensions();
+    let img = if original_width > width || original_height > height {
+        img.resize(width, height, image::imageops::Lanczos3)
+    } else {
+        img
+    };
+
+    let mut buffer = Vec::new();
+    match format {
+        // JPEG has no alpha channel
+        VariantFormat::Jpeg => JpegEncoder::new_with_quality(&mut buffer, JPEG_QUALITY)
+            .encode_image(&img.to_rgb8())
+            .map_err(|e| format!("Failed to encode JPEG: {}", e))?,
+        VariantFormat::Png => img
+            .write_to(&mut std::io::Cursor::new(&mut buffer), ImageFormat::Png)
+            .map_err(|e| format!("Failed to encode PNG: {}", e))?,
+        VariantFormat::Webp => img
+            .write_to(&mut std::io::Cursor::new(&mut buffer), ImageFormat::WebP)
+            .map_err(|e| format!("Failed to encode WebP: {}", e))?,
+    }
+    Ok(buffer)
+}
+
+/// The cached variant, generating it first when needed
+async fn load_variant(source: &str, checksum: &str, width: u32, height: u32, format: VariantFormat) -> Result<Vec<u8>, String> {
+    let path = variant_path(checksum, width, height, format);
+    if let Ok(data) = tokio::fs::read(long_path(&path)).await {
+        return Ok(data);
+    }
+
+    let owned_source = source.to_string();
+    let data = tokio::task::spawn_blocking(move || resize_sync(&owned_source, width, height, format))
+        .await
+        .map_err(|e| format!("Resize task panicked: {}", e))??;
+
+    // 先写临时文件再改名，同一变体的并发请求不会读到写了一半的文件
+    let written = async {
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(long_path(parent)).await?;
+        }
+        let partial = path.with_extension(format!("{}.{}.partial", format.extension(), uuid::Uuid::new_v4()));
+        tokio::fs::write(long_path(&partial), &data).await?;
+        tokio::fs::rename(long_path(&partial), long_path(&path)).await
+    }
+    .await;
+    match written {
+        Ok(()) => info!("Image variant generated: {}", path.display()),
+        Err(e) => error!("Failed to cache image variant {}: {}", path.display(), e),
+    }
+    Ok(data)
+}
+
+/// The variant of a completed image `query` asks for, or the error response
+async fn variant(file: &UploadedFile, query: &ImageQuery) -> Result<Vec<u8>, Response> {
+    let (width, height) = match (query.w, query.h) {
+        (None, None) => return Err(error_response(StatusCode::BAD_REQUEST, "INVALID_SIZE", "Specify w, h or both".to_string())),
+        (w, h) => (w.unwrap_or(MAX_DIMENSION), h.unwrap_or(MAX_DIMENSION)),
+    };
+    if !(1..=MAX_DIMENSION).contains(&width) || !(1..=MAX_DIMENSION).contains(&height) {
+        return Err(error_response(StatusCode::BAD_REQUEST, "INVALID_SIZE", format!("w and h must be between 1 and {}", MAX_DIMENSION)));
+    }
+    if !is_image_file(&file.filename) {
+        return Err(error_response(StatusCode::UNSUPPORTED_MEDIA_TYPE, "NOT_AN_IMAGE", "File is not an image".to_string()));
+    }
+    load_variant(&file.file_path, &file.checksum, width, height, query.format).await.map_err(|e| {
+        error!("Failed to resize image {}: {}", file.file_path, e);
+        error_response(StatusCode::UNPROCESSABLE_ENTITY, "RESIZE_ERROR", e)
+    })
+}
+
+fn image_response(format: VariantFormat, data: Vec<u8>) -> Response {
+    (
+        StatusCode::OK,
+        [
+            (header::CONTENT_TYPE, format.content_type()),
+            (header::CACHE_CONTROL, "public, max-age=86400"),
+        ],
+        data,
+    ).into_response()
+}
+
+/// A completed image scaled to fit `w` x `h`, see `ImageQuery`
+pub async fn serve_image(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    Path(file_id): Path<String>,
+    Query(query): Query<ImageQuery>,
+) -> Response {
+    if let Err(resp) = ensure_file_allowed(&ctx, &client_addr, &file_id).await {
+        return resp;
+    }
+    let db_pool = &ctx.app_state.db_pool;
+    let file = match fetch_uploaded_file_by_id(db_pool, &file_id).await {
+        Ok(Some(file)) if file.status == 2 => file,
+        Ok(Some(_)) => return error_response(StatusCode::CONFLICT, "FILE_NOT_COMPLETE", "File upload has not completed".to_string()),
+        Ok(None) => return error_response(StatusCode::NOT_FOUND, "FILE_NOT_FOUND", "File not found".to_string()),
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_FILE_RECORD_ERROR", e),
+    };
+    match variant(&file, &query).await {
+        Ok(data) => {
+            record_traffic(db_pool, &client_principal(&client_addr), 0, data.len() as u64).await;
+            image_response(query.format, data)
+        }
+        Err(resp) => resp,
+    }
+}
+
+/// A shared image scaled down, so renderers showing a slideshow don't have to
+/// load the original
+pub async fn share_image(
+    State(ctx): State<AppContext>,
+    Path(token): Path<String>,
+    Query(query): Query<ImageQuery>,
+) -> Response {
+    let file = match shared_file(&ctx, &token).await {
+        Ok((_, file)) => file,
+        Err(resp) => return resp,
+    };
+    match variant(&file, &query).await {
+        Ok(data) => image_response(query.format, data),
+        Err(resp) => resp,
+    }
+}
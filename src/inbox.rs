@@ -0,0 +1,669 @@
+//! File requests: an inbox link lets anonymous visitors upload files into a
+//! folder, the reverse of share links. Uploads are limited in size, type and
+//! rate per visitor, and announced like any completed upload.
+
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{Html, IntoResponse, Response},
+    Json,
+};
+use chrono::Utc;
+use futures::StreamExt;
+use log::{error, info};
+use md5::{Digest, Md5};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::{FromRow, SqlitePool};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Instant;
+use tokio::fs::{self, File, OpenOptions};
+use tokio::io::{AsyncWriteExt, BufWriter};
+use uuid::Uuid;
+use crate::api::Priority;
+use crate::analytics::{record_upload_completed, record_upload_failure};
+use crate::context::AppContext;
+use crate::events::ServerEvent;
+use crate::filename::{normalize_original_filename, sanitize_filename};
+use crate::folders::{ensure_folder_admin, fetch_folder, fetch_visible_folder, mime_matches, Folder};
+use crate::helper::{error_response, format_size, xml_escape, ApiResponse};
+use crate::media_library::spawn_scrape;
+use crate::outbox::enqueue_event;
+use crate::paths::{file_inode, folder_file_path, long_path, path_to_string, UPLOADS_DIR};
+use crate::quarantine::{check_extension, quarantine_file, quarantine_reason};
+use crate::repository::UploadRepository;
+use crate::shares::public_base_url;
+use crate::tenants::{check_quota, current_tenant_id, fetch_tenant};
+use crate::thumbnail::{generate_thumbnail, is_image_file, is_video_file, ThumbnailConfig};
+use crate::traffic::{client_principal, record_traffic};
+use crate::transcode::spawn_transcode;
+use crate::upload_dao::{save_upload_state_to_db, set_file_placement, update_file_status_and_path, update_file_thumbnail_path};
+use crate::usage::record_file_usage;
+
+const DEFAULT_MAX_FILE_SIZE: i64 = 1024 * 1024 * 1024;
+const DEFAULT_UPLOADS_PER_HOUR: i64 = 10;
+/// Visitors are rate limited over this many seconds
+const RATE_WINDOW_SECS: i64 = 3600;
+
+#[derive(Debug, FromRow)]
+struct InboxRow {
+    id: i64,
+    token: String,
+    name: String,
+    folder_id: i64,
+    max_file_size: i64,
+    allowed_mime_types: String,
+    max_uploads_per_hour: i64,
+    created_at: i64,
+    expires_at: Option<i64>,
+    uploads: i64,
+}
+
+/// A link anonymous visitors can upload files into a folder through
+#[derive(Debug, Clone, Serialize)]
+pub struct Inbox {
+    pub id: i64,
+    pub token: String,
+    /// Shown to visitors and in upload notifications
+    pub name: String,
+    pub folder_id: i64,
+    pub max_file_size: i64,
+    /// Accepted MIME types, `type/*` wildcards allowed; empty accepts anything
+    pub allowed_mime_types: Vec<String>,
+    /// Uploads each visitor IP may start per hour
+    pub max_uploads_per_hour: i64,
+    #[serde(with = "crate::api::timestamp")]
+    pub created_at: i64,
+    #[serde(with = "crate::api::timestamp::option")]
+    pub expires_at: Option<i64>,
+    /// Files received so far
+    pub uploads: i64,
+}
+
+impl From<InboxRow> for Inbox {
+    fn from(row: InboxRow) -> Self {
+        Self {
+            id: row.id,
+            token: row.token,
+            name: row.name,
+            folder_id: row.folder_id,
+            max_file_size: row.max_file_size,
+            allowed_mime_types: serde_json::from_str(&row.allowed_mime_types).unwrap_or_default(),
+            max_uploads_per_hour: row.max_uploads_per_hour,
+            created_at: row.created_at,
+            expires_at: row.expires_at,
+            uploads: row.uploads,
+        }
+    }
+}
+
+impl Inbox {
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|at| at <= Utc::now().timestamp())
+    }
+
+    pub fn url(&self, base_url: &str) -> String {
+        format!("{}/inbox/{}", base_url, self.token)
+    }
+
+    /// Check an upload against the inbox's size and type limits, like `Folder::check_upload`
+    fn check_upload(&self, filename: &str, size: u64) -> Result<(), (&'static str, String)> {
+        if size > self.max_file_size as u64 {
+            return Err(("FILE_TOO_LARGE", format!(
+                "'{}' accepts files up to {}", self.name, format_size(self.max_file_size as u64)
+            )));
+        }
+        if !self.allowed_mime_types.is_empty() {
+            let mime = mime_guess::from_path(filename).first_or_octet_stream();
+            if !self.allowed_mime_types.iter().any(|pattern| mime_matches(pattern, mime.essence_str())) {
+                return Err(("MIME_TYPE_NOT_ALLOWED", format!(
+                    "'{}' does not accept {} files", self.name, mime.essence_str()
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+const INBOX_COLUMNS: &str = "i.id, i.token, i.name, i.folder_id, i.max_file_size, i.allowed_mime_types, i.max_uploads_per_hour, i.created_at, i.expires_at,
+    (SELECT COUNT(*) FROM inbox_uploads u WHERE u.inbox_id = i.id AND u.file_id IS NOT NULL) AS uploads";
+
+/// Inboxes into folders of the given tenant, or all of them
+async fn fetch_inboxes(db_pool: &SqlitePool, tenant_id: Option<i64>) -> Result<Vec<Inbox>, String> {
+    sqlx::query_as::<_, InboxRow>(&format!(
+        "SELECT {} FROM inboxes i JOIN folders f ON f.id = i.folder_id WHERE ?1 IS NULL OR f.tenant_id = ?1 ORDER BY i.id",
+        INBOX_COLUMNS
+    ))
+    .bind(tenant_id)
+    .fetch_all(db_pool)
+    .await
+    .map(|rows| rows.into_iter().map(Inbox::from).collect())
+    .map_err(|e| {
+        error!("Failed to fetch inboxes: {}", e);
+        "Failed to fetch inboxes".to_string()
+    })
+}
+
+async fn fetch_inbox_where(db_pool: &SqlitePool, column: &str, value: &str) -> Result<Option<Inbox>, String> {
+    sqlx::query_as::<_, InboxRow>(&format!("SELECT {} FROM inboxes i WHERE i.{} = ?", INBOX_COLUMNS, column))
+        .bind(value)
+        .fetch_optional(db_pool)
+        .await
+        .map(|row| row.map(Inbox::from))
+        .map_err(|e| {
+            error!("Failed to fetch inbox: {}", e);
+            "Failed to fetch inbox".to_string()
+        })
+}
+
+/// Start an upload by `principal` unless it used up its uploads for the hour.
+/// Returns the attempt's ID, or the seconds until the next upload is allowed.
+async fn start_attempt(db_pool: &SqlitePool, inbox: &Inbox, principal: &str) -> Result<Result<i64, i64>, String> {
+    let now = Utc::now().timestamp();
+    let (attempts, oldest): (i64, Option<i64>) = sqlx::query_as(
+        "SELECT COUNT(*), MIN(created_at) FROM inbox_uploads WHERE inbox_id = ? AND principal = ? AND created_at > ?"
+    )
+    .bind(inbox.id)
+    .bind(principal)
+    .bind(now - RATE_WINDOW_SECS)
+    .fetch_one(db_pool)
+    .await
+    .map_err(|e| {
+        error!("Failed to count inbox uploads: {}", e);
+        "Failed to count inbox uploads".to_string()
+    })?;
+    if attempts >= inbox.max_uploads_per_hour {
+        return Ok(Err((oldest.unwrap_or(now) + RATE_WINDOW_SECS - now).max(1)));
+    }
+
+    sqlx::query("INSERT INTO inbox_uploads (inbox_id, principal, created_at) VALUES (?, ?, ?)")
+        .bind(inbox.id)
+        .bind(principal)
+        .bind(now)
+        .execute(db_pool)
+        .await
+        .map(|done| Ok(done.last_insert_rowid()))
+        .map_err(|e| {
+            error!("Failed to record inbox upload: {}", e);
+            "Failed to record inbox upload".to_string()
+        })
+}
+
+async fn finish_attempt(db_pool: &SqlitePool, attempt_id: i64, file_id: &str) {
+    if let Err(e) = sqlx::query("UPDATE inbox_uploads SET file_id = ? WHERE id = ?")
+        .bind(file_id)
+        .bind(attempt_id)
+        .execute(db_pool)
+        .await
+    {
+        error!("Failed to record file of inbox upload {}: {}", attempt_id, e);
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CreateInboxRequest {
+    name: String,
+    folder_id: i64,
+    max_file_size: Option<i64>,
+    #[serde(default)]
+    allowed_mime_types: Vec<String>,
+    max_uploads_per_hour: Option<i64>,
+    /// None creates an inbox that doesn't expire
+    expires_in_hours: Option<i64>,
+}
+
+#[derive(Serialize)]
+struct InboxWithUrl {
+    #[serde(flatten)]
+    inbox: Inbox,
+    url: String,
+}
+
+pub async fn list_inboxes(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    if let Err(resp) = ensure_folder_admin(&ctx, &client_addr).await {
+        return resp;
+    }
+    let base_url = public_base_url(&ctx.config.load(), &headers);
+    match fetch_inboxes(&ctx.app_state.db_pool, current_tenant_id()).await {
+        Ok(inboxes) => (StatusCode::OK, Json(ApiResponse::success(
+            inboxes.into_iter().map(|inbox| InboxWithUrl { url: inbox.url(&base_url), inbox }).collect::<Vec<_>>()
+        ))).into_response(),
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_INBOXES_ERROR", e),
+    }
+}
+
+pub async fn create_inbox(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(req): Json<CreateInboxRequest>,
+) -> impl IntoResponse {
+    if let Err(resp) = ensure_folder_admin(&ctx, &client_addr).await {
+        return resp;
+    }
+    let name = req.name.trim().to_string();
+    let max_file_size = req.max_file_size.unwrap_or(DEFAULT_MAX_FILE_SIZE);
+    let max_uploads_per_hour = req.max_uploads_per_hour.unwrap_or(DEFAULT_UPLOADS_PER_HOUR);
+    let invalid = |message: &str| error_response(StatusCode::BAD_REQUEST, "INVALID_INBOX", message.to_string());
+    if name.is_empty() {
+        return invalid("Inbox name must not be empty");
+    }
+    if max_file_size <= 0 {
+        return invalid("max_file_size must be positive");
+    }
+    if max_uploads_per_hour <= 0 {
+        return invalid("max_uploads_per_hour must be positive");
+    }
+    if req.expires_in_hours.is_some_and(|hours| hours <= 0) {
+        return invalid("expires_in_hours must be positive");
+    }
+    let allowed_mime_types: Vec<String> = req.allowed_mime_types
+        .iter()
+        .map(|m| m.trim().to_lowercase())
+        .filter(|m| !m.is_empty())
+        .collect();
+
+    let db_pool = &ctx.app_state.db_pool;
+    match fetch_visible_folder(db_pool, req.folder_id).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return error_response(StatusCode::NOT_FOUND, "FOLDER_NOT_FOUND", "Folder not found".to_string()),
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_FOLDER_ERROR", e),
+    }
+
+    let expires_at = req.expires_in_hours.map(|hours| Utc::now().timestamp() + hours * 3600);
+    let token = Uuid::new_v4().simple().to_string();
+    let result = sqlx::query(
+        "INSERT INTO inboxes (token, name, folder_id, max_file_size, allowed_mime_types, max_uploads_per_hour, created_at, expires_at) VALUES (?, ?, ?, ?, ?, ?, strftime('%s', 'now'), ?)"
+    )
+    .bind(&token)
+    .bind(&name)
+    .bind(req.folder_id)
+    .bind(max_file_size)
+    .bind(serde_json::to_string(&allowed_mime_types).unwrap_or_else(|_| "[]".to_string()))
+    .bind(max_uploads_per_hour)
+    .bind(expires_at)
+    .execute(db_pool)
+    .await;
+    if let Err(e) = result {
+        error!("Failed to create inbox: {}", e);
+        return error_response(StatusCode::INTERNAL_SERVER_ERROR, "CREATE_INBOX_ERROR", "Failed to create inbox".to_string());
+    }
+
+    info!("Created inbox '{}' into folder {}", name, req.folder_id);
+    match fetch_inbox_where(db_pool, "token", &token).await {
+        Ok(Some(inbox)) => {
+            let url = inbox.url(&public_base_url(&ctx.config.load(), &headers));
+            (StatusCode::OK, Json(ApiResponse::success(InboxWithUrl { inbox, url }))).into_response()
+        }
+        Ok(None) | Err(_) => (StatusCode::OK, Json(ApiResponse::success(()))).into_response(),
+    }
+}
+
+/// Close an inbox; files already received stay in the folder
+pub async fn delete_inbox(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    Path(id): Path<i64>,
+) -> impl IntoResponse {
+    if let Err(resp) = ensure_folder_admin(&ctx, &client_addr).await {
+        return resp;
+    }
+    let db_pool = &ctx.app_state.db_pool;
+    let not_found = || error_response(StatusCode::NOT_FOUND, "INBOX_NOT_FOUND", "Inbox not found".to_string());
+    match fetch_inbox_where(db_pool, "id", &id.to_string()).await {
+        Ok(Some(inbox)) => match fetch_visible_folder(db_pool, inbox.folder_id).await {
+            Ok(Some(_)) => {}
+            Ok(None) => return not_found(),
+            Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_FOLDER_ERROR", e),
+        },
+        Ok(None) => return not_found(),
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_INBOX_ERROR", e),
+    }
+
+    let result = async {
+        let mut tx = db_pool.begin().await?;
+        sqlx::query("DELETE FROM inbox_uploads WHERE inbox_id = ?").bind(id).execute(&mut *tx).await?;
+        sqlx::query("DELETE FROM inboxes WHERE id = ?").bind(id).execute(&mut *tx).await?;
+        tx.commit().await
+    }
+    .await;
+    match result {
+        Ok(()) => {
+            info!("Deleted inbox {}", id);
+            (StatusCode::OK, Json(ApiResponse::success(()))).into_response()
+        }
+        Err(e) => {
+            error!("Failed to delete inbox {}: {}", id, e);
+            error_response(StatusCode::INTERNAL_SERVER_ERROR, "DELETE_INBOX_ERROR", "Failed to delete inbox".to_string())
+        }
+    }
+}
+
+/// The open inbox behind a token, or the response for a missing or expired link
+async fn open_inbox(db_pool: &SqlitePool, token: &str) -> Result<Inbox, Response> {
+    match fetch_inbox_where(db_pool, "token", token).await {
+        Ok(Some(inbox)) if inbox.is_expired() => Err(error_response(StatusCode::GONE, "INBOX_EXPIRED", "This inbox is closed".to_string())),
+        Ok(Some(inbox)) => Ok(inbox),
+        Ok(None) => Err(error_response(StatusCode::NOT_FOUND, "INBOX_NOT_FOUND", "Inbox not found".to_string())),
+        Err(e) => Err(error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_INBOX_ERROR", e)),
+    }
+}
+
+const UPLOAD_PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>{name}</title>
+<style>
+body { font-family: sans-serif; max-width: 36em; margin: 2em auto; padding: 0 1em; }
+li { margin: .3em 0; }
+.failed { color: #b00; }
+</style>
+</head>
+<body>
+<h1>{name}</h1>
+<p>Files up to {max_size}{types}. Uploaded files can't be seen or changed by other visitors.</p>
+<input type="file" id="files" multiple>
+<ul id="status"></ul>
+<script>
+const files = document.getElementById('files');
+const status = document.getElementById('status');
+function upload(file) {
+  const item = document.createElement('li');
+  item.textContent = file.name + ': waiting';
+  status.appendChild(item);
+  return new Promise(resolve => {
+    const xhr = new XMLHttpRequest();
+    xhr.open('POST', location.pathname + '?filename=' + encodeURIComponent(file.name));
+    xhr.upload.onprogress = e => { item.textContent = file.name + ': ' + Math.floor(e.loaded * 100 / e.total) + '%'; };
+    xhr.onload = () => {
+      let message = xhr.statusText;
+      try { message = JSON.parse(xhr.responseText).message; } catch (e) {}
+      item.textContent = file.name + ': ' + (xhr.status === 200 ? 'uploaded' : message);
+      if (xhr.status !== 200) item.className = 'failed';
+      resolve();
+    };
+    xhr.onerror = () => { item.textContent = file.name + ': connection failed'; item.className = 'failed'; resolve(); };
+    xhr.send(file);
+  });
+}
+files.onchange = async () => {
+  for (const file of Array.from(files.files)) await upload(file);
+  files.value = '';
+};
+</script>
+</body>
+</html>
+"#;
+
+/// Upload page for visitors. Public: the token is the only credential.
+pub async fn inbox_page(
+    State(ctx): State<AppContext>,
+    Path(token): Path<String>,
+) -> Response {
+    let inbox = match open_inbox(&ctx.app_state.db_pool, &token).await {
+        Ok(inbox) => inbox,
+        Err(resp) => return resp,
+    };
+    let types = match inbox.allowed_mime_types.is_empty() {
+        true => String::new(),
+        false => format!(" of type {}", inbox.allowed_mime_types.join(", ")),
+    };
+    Html(UPLOAD_PAGE
+        .replace("{name}", &xml_escape(&inbox.name))
+        .replace("{max_size}", &format_size(inbox.max_file_size as u64))
+        .replace("{types}", &xml_escape(&types))
+    ).into_response()
+}
+
+/// Create `filename` in the folder, or `name (1).ext`, `name (2).ext`... when
+/// it exists, so visitors never replace stored files
+async fn create_unique_file(folder: &Folder, filename: &str) -> Result<(PathBuf, String, File), String> {
+    let dir = std::path::Path::new(UPLOADS_DIR).join(&folder.path);
+    fs::create_dir_all(long_path(&dir))
+        .await
+        .map_err(|e| format!("Failed to create folder directory: {}", e))?;
+    let (stem, extension) = match filename.rfind('.') {
+        Some(idx) if idx > 0 => filename.split_at(idx),
+        _ => (filename, ""),
+    };
+    for n in 0..1000 {
+        let name = match n {
+            0 => filename.to_string(),
+            n => format!("{} ({}){}", stem, n, extension),
+        };
+        let path = folder_file_path(&folder.path, &name);
+        match OpenOptions::new().write(true).create_new(true).open(long_path(&path)).await {
+            Ok(file) => return Ok((path, name, file)),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(format!("Failed to create file: {}", e)),
+        }
+    }
+    Err(format!("Too many files named like {}", filename))
+}
+
+/// Write the request body to `file`, stopping at `max_size` bytes.
+/// Returns the MD5 checksum and size, or the status, error code and message.
+async fn receive_body(file: File, body: Body, max_size: u64) -> Result<(String, u64), (StatusCode, &'static str, String)> {
+    let mut writer = BufWriter::new(file);
+    let mut hasher = Md5::new();
+    let mut size = 0u64;
+    let mut payload = body.into_data_stream();
+    while let Some(chunk) = payload.next().await {
+        let chunk = chunk.map_err(|e| (StatusCode::BAD_REQUEST, "PAYLOAD_ERROR", format!("Payload error: {}", e)))?;
+        size += chunk.len() as u64;
+        if size > max_size {
+            return Err((StatusCode::PAYLOAD_TOO_LARGE, "FILE_TOO_LARGE", format!("Files may be at most {}", format_size(max_size))));
+        }
+        writer.write_all(&chunk).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, "WRITE_ERROR", format!("Write error: {}", e)))?;
+        hasher.update(&chunk);
+    }
+    writer.flush().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, "WRITE_ERROR", format!("Write error: {}", e)))?;
+    if size == 0 {
+        return Err((StatusCode::BAD_REQUEST, "EMPTY_FILE", "The file is empty".to_string()));
+    }
+    Ok((format!("{:x}", hasher.finalize()), size))
+}
+
+fn policy_error(code: &str, message: String) -> Response {
+    let status = match code {
+        "FILE_TOO_LARGE" | "QUOTA_EXCEEDED" => StatusCode::PAYLOAD_TOO_LARGE,
+        "MIME_TYPE_NOT_ALLOWED" | "FILE_TYPE_BLOCKED" => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    error_response(status, code, message)
+}
+
+#[derive(Deserialize)]
+pub struct InboxUploadQuery {
+    filename: Option<String>,
+}
+
+/// Receive one file as the raw request body, named by `?filename=`.
+/// Public: the token is the only credential.
+pub async fn inbox_upload(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    Path(token): Path<String>,
+    Query(query): Query<InboxUploadQuery>,
+    headers: HeaderMap,
+    body: Body,
+) -> Response {
+    let db_pool = &ctx.app_state.db_pool;
+    let inbox = match open_inbox(db_pool, &token).await {
+        Ok(inbox) => inbox,
+        Err(resp) => return resp,
+    };
+    let Some(filename) = query.filename.filter(|name| !name.trim().is_empty()) else {
+        return error_response(StatusCode::BAD_REQUEST, "MISSING_FILENAME", "Query parameter 'filename' is required".to_string());
+    };
+    let original_filename = normalize_original_filename(&filename);
+    let safe_filename = sanitize_filename(&filename, ctx.config.load().filename_policy);
+    if safe_filename.is_empty() {
+        return error_response(StatusCode::BAD_REQUEST, "INVALID_FILENAME", "Invalid filename".to_string());
+    }
+
+    // 先按声明的长度检查，分块传输时在接收过程中限制
+    let declared_size = headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok());
+    let folder = match fetch_folder(db_pool, inbox.folder_id).await {
+        Ok(Some(folder)) => folder,
+        Ok(None) => return error_response(StatusCode::NOT_FOUND, "FOLDER_NOT_FOUND", "The inbox's folder no longer exists".to_string()),
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_FOLDER_ERROR", e),
+    };
+    let size = declared_size.unwrap_or(0);
+    let checked = check_extension(&ctx.config.load().blocked_extensions, &original_filename, "This server")
+        .and_then(|_| inbox.check_upload(&original_filename, size))
+        .and_then(|_| folder.check_upload(&original_filename, size));
+    if let Err((code, message)) = checked {
+        return policy_error(code, message);
+    }
+    let tenant = match folder.tenant_id {
+        Some(tenant_id) => match fetch_tenant(db_pool, tenant_id).await {
+            Ok(tenant) => tenant,
+            Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_TENANT_ERROR", e),
+        },
+        None => None,
+    };
+    if let Some(tenant) = &tenant {
+        if let Err((code, message)) = check_quota(db_pool, tenant, size).await {
+            return policy_error(code, message);
+        }
+    }
+
+    let principal = client_principal(&client_addr);
+    let attempt_id = match start_attempt(db_pool, &inbox, &principal).await {
+        Ok(Ok(attempt_id)) => attempt_id,
+        Ok(Err(retry_after)) => {
+            let response = error_response(
+                StatusCode::TOO_MANY_REQUESTS,
+                "TOO_MANY_UPLOADS",
+                format!("At most {} uploads per hour, try again in {} minutes", inbox.max_uploads_per_hour, (retry_after + 59) / 60),
+            );
+            return ([(header::RETRY_AFTER, retry_after.to_string())], response).into_response();
+        }
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "RATE_LIMIT_ERROR", e),
+    };
+
+    let (stored_path, stored_filename, file) = match create_unique_file(&folder, &safe_filename).await {
+        Ok(created) => created,
+        Err(e) => {
+            error!("Inbox upload of {} failed: {}", safe_filename, e);
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "WRITE_ERROR", e);
+        }
+    };
+    let max_size = folder.max_file_size.map_or(inbox.max_file_size, |max| max.min(inbox.max_file_size)) as u64;
+    let started = Instant::now();
+    let received = receive_body(file, body, max_size).await;
+    let (checksum, size) = match received {
+        Ok(received) => received,
+        Err((status, code, message)) => {
+            let _ = fs::remove_file(long_path(&stored_path)).await;
+            record_upload_failure(db_pool, code).await;
+            error!("Inbox upload of {} to '{}' failed: {}", stored_filename, inbox.name, message);
+            return error_response(status, code, message);
+        }
+    };
+    record_traffic(db_pool, &principal, size, 0).await;
+    if let (None, Some(tenant)) = (declared_size, &tenant) {
+        if let Err((code, message)) = check_quota(db_pool, tenant, size).await {
+            let _ = fs::remove_file(long_path(&stored_path)).await;
+            return policy_error(code, message);
+        }
+    }
+
+    let file_id = Uuid::new_v4().to_string();
+    let file_path = path_to_string(&stored_path);
+    let quarantine = quarantine_reason(&ctx.config.load(), &stored_path, &stored_filename).await;
+    let saved = async {
+        let mut tx = db_pool.begin().await.map_err(|e| {
+            error!("Failed to begin transaction: {}", e);
+            "Failed to begin transaction".to_string()
+        })?;
+        save_upload_state_to_db(&mut tx, &file_id, &stored_filename, &original_filename, size, &checksum, &file_path).await?;
+        set_file_placement(&mut tx, &file_id, Some(folder.id), &principal, folder.tenant_id).await?;
+        // 完成事件与状态在同一事务中写入发件箱；隔离的文件等管理员放行后再通知
+        match &quarantine {
+            Some(reason) => quarantine_file(&mut *tx, &file_id, 0, &file_path, reason).await?,
+            None => {
+                update_file_status_and_path(&mut *tx, &file_id, 0, 2, &file_path).await?;
+                enqueue_event(&mut tx, &ServerEvent::UploadCompleted {
+                    file_id: file_id.clone(),
+                    filename: stored_filename.clone(),
+                    size,
+                    tenant_id: folder.tenant_id,
+                    inbox: Some(inbox.name.clone()),
+                }).await?;
+            }
+        }
+        tx.commit().await.map_err(|e| {
+            error!("Failed to commit transaction: {}", e);
+            "Failed to commit transaction".to_string()
+        })
+    }
+    .await;
+    if let Err(e) = saved {
+        let _ = fs::remove_file(long_path(&stored_path)).await;
+        return error_response(StatusCode::INTERNAL_SERVER_ERROR, "DB_SAVE_ERROR", e);
+    }
+    if let Ok(metadata) = fs::metadata(long_path(&stored_path)).await {
+        let file_mtime = metadata.modified()
+            .map(|t| t.duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_secs() as i64)
+            .unwrap_or(0);
+        let file_ctime = metadata.created()
+            .map(|t| t.duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_secs() as i64)
+            .unwrap_or(file_mtime);
+        if let Err(e) = db_pool.update_file_meta_info(&file_id, file_mtime, file_ctime, file_inode(&metadata).unwrap_or(0)).await {
+            error!("Failed to update file meta info: {}", e);
+        }
+    }
+    finish_attempt(db_pool, attempt_id, &file_id).await;
+    info!("Inbox '{}' received {} ({} bytes) from {}", inbox.name, stored_filename, size, principal);
+
+    record_upload_completed(db_pool, size, started.elapsed().as_millis() as u64, 0).await;
+    record_file_usage(db_pool, &file_id, 1).await;
+    // 隔离的文件等管理员放行后再处理
+    if quarantine.is_some() {
+        return (StatusCode::OK, Json(ApiResponse::success_with_message(
+            "File uploaded and held for review",
+            json!({
+                "filename": stored_filename,
+                "size": size,
+                "checksum": checksum,
+            }),
+        ))).into_response();
+    }
+    ctx.outbox.notify_one();
+
+    if is_image_file(&stored_filename) {
+        if let Some(thumbnail_path) = generate_thumbnail(&ThumbnailConfig::default(), &file_path, &checksum).await {
+            if let Err(e) = update_file_thumbnail_path(db_pool, &file_id, &thumbnail_path).await {
+                error!("Failed to save thumbnail path to database: {}", e);
+            }
+        }
+    }
+    if is_video_file(&stored_filename) {
+        spawn_scrape(db_pool.clone(), ctx.config.load(), file_id.clone());
+        if let Some(format) = folder.auto_transcode.clone() {
+            spawn_transcode(&ctx, file_id.clone(), stored_path.clone(), format, Priority::Normal);
+        }
+    }
+
+    (StatusCode::OK, Json(ApiResponse::success_with_message(
+        "File uploaded",
+        json!({
+            "filename": stored_filename,
+            "size": size,
+            "checksum": checksum,
+        }),
+    ))).into_response()
+}
@@ -0,0 +1,221 @@
+//! Limits how many disk-heavy jobs run at once, per class. Several merges or
+//! transcodes reading and writing at the same time make a spinning disk seek
+//! back and forth and finish all of them later than running them in turn.
+//! Waiting jobs get a free slot highest priority first, and in the order
+//! they arrived within a priority.
+
+use axum::{
+    extract::{ConnectInfo, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use serde::Serialize;
+use serde_json::json;
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Instant;
+use tokio::sync::oneshot;
+use crate::api::Priority;
+use crate::config::AppConfig;
+use crate::context::AppContext;
+use crate::helper::ApiResponse;
+use crate::profiles::ensure_unrestricted;
+use crate::hashing::hash_benchmark_results;
+
+#[derive(Debug, Clone, Copy)]
+pub enum IoClass {
+    /// Assembling uploaded chunks and checking the file's MD5
+    Merge,
+    /// ffmpeg transcodes
+    Transcode,
+    /// Re-hashing stored files in the integrity check
+    Scrub,
+}
+
+/// Key of a waiting job: highest priority first, then first come
+type WaiterKey = (Reverse<Priority>, u64);
+
+struct Slots {
+    free: usize,
+    waiters: BTreeMap<WaiterKey, oneshot::Sender<()>>,
+    next_seq: u64,
+}
+
+struct IoQueue {
+    slots: std::sync::Mutex<Slots>,
+    limit: usize,
+    queued: AtomicUsize,
+    running: AtomicUsize,
+    completed: AtomicU64,
+    total_wait_ms: AtomicU64,
+    max_wait_ms: AtomicU64,
+}
+
+impl IoQueue {
+    fn new(limit: usize) -> Self {
+        Self {
+            slots: std::sync::Mutex::new(Slots { free: limit, waiters: BTreeMap::new(), next_seq: 0 }),
+            limit,
+            queued: AtomicUsize::new(0),
+            running: AtomicUsize::new(0),
+            completed: AtomicU64::new(0),
+            total_wait_ms: AtomicU64::new(0),
+            max_wait_ms: AtomicU64::new(0),
+        }
+    }
+
+    /// Hand a freed slot to the first waiting job, or keep it free
+    fn release(&self) {
+        let mut slots = self.slots.lock().unwrap();
+        while let Some((_, granted)) = slots.waiters.pop_first() {
+            if granted.send(()).is_ok() {
+                return;
+            }
+        }
+        slots.free += 1;
+    }
+
+    fn stats(&self) -> IoQueueStats {
+        let completed = self.completed.load(Ordering::Relaxed);
+        let total_wait_ms = self.total_wait_ms.load(Ordering::Relaxed);
+        IoQueueStats {
+            limit: self.limit,
+            running: self.running.load(Ordering::Relaxed),
+            queued: self.queued.load(Ordering::Relaxed),
+            completed,
+            avg_wait_ms: total_wait_ms.checked_div(completed).unwrap_or(0),
+            max_wait_ms: self.max_wait_ms.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Counts a waiting job until it gets a slot or gives up, e.g. because the
+/// request it belongs to was cancelled
+struct Waiting<'a> {
+    queue: &'a IoQueue,
+    /// Place in the queue while no slot was free
+    waiter: Option<(WaiterKey, oneshot::Receiver<()>)>,
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.queue.queued.fetch_sub(1, Ordering::Relaxed);
+        if let Some((key, mut granted)) = self.waiter.take() {
+            // 放弃等待时已分配到的空位转交给下一个任务
+            let still_waiting = self.queue.slots.lock().unwrap().waiters.remove(&key).is_some();
+            if !still_waiting && granted.try_recv().is_ok() {
+                self.queue.release();
+            }
+        }
+    }
+}
+
+/// A slot of an I/O class, given back when dropped
+pub struct IoPermit<'a> {
+    queue: &'a IoQueue,
+}
+
+impl Drop for IoPermit<'_> {
+    fn drop(&mut self) {
+        self.queue.running.fetch_sub(1, Ordering::Relaxed);
+        self.queue.completed.fetch_add(1, Ordering::Relaxed);
+        self.queue.release();
+    }
+}
+
+/// Queue metrics of one class, see `GET /api/v1/admin/stats`
+#[derive(Debug, Serialize)]
+pub struct IoQueueStats {
+    pub limit: usize,
+    pub running: usize,
+    pub queued: usize,
+    /// Jobs that got a slot and finished since the server started
+    pub completed: u64,
+    pub avg_wait_ms: u64,
+    pub max_wait_ms: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct IoStats {
+    pub merge: IoQueueStats,
+    pub transcode: IoQueueStats,
+    pub scrub: IoQueueStats,
+}
+
+pub struct IoScheduler {
+    merge: IoQueue,
+    transcode: IoQueue,
+    scrub: IoQueue,
+}
+
+impl IoScheduler {
+    pub fn new(config: &AppConfig) -> Self {
+        Self {
+            merge: IoQueue::new(config.max_concurrent_merges),
+            transcode: IoQueue::new(config.max_concurrent_transcodes),
+            scrub: IoQueue::new(config.max_concurrent_scrubs),
+        }
+    }
+
+    fn queue(&self, class: IoClass) -> &IoQueue {
+        match class {
+            IoClass::Merge => &self.merge,
+            IoClass::Transcode => &self.transcode,
+            IoClass::Scrub => &self.scrub,
+        }
+    }
+
+    /// Wait for a free slot of `class`; jobs of a higher `priority` that
+    /// are waiting get one first
+    pub async fn acquire(&self, class: IoClass, priority: Priority) -> IoPermit<'_> {
+        let queue = self.queue(class);
+        let started = Instant::now();
+        queue.queued.fetch_add(1, Ordering::Relaxed);
+        let mut waiting = Waiting { queue, waiter: None };
+        {
+            let mut slots = queue.slots.lock().unwrap();
+            if slots.free > 0 && slots.waiters.is_empty() {
+                slots.free -= 1;
+            } else {
+                let key = (Reverse(priority), slots.next_seq);
+                slots.next_seq += 1;
+                let (grant, granted) = oneshot::channel();
+                slots.waiters.insert(key, grant);
+                waiting.waiter = Some((key, granted));
+            }
+        }
+        if let Some((_, granted)) = waiting.waiter.as_mut() {
+            // 等待者离开队列前接收端一直存在，发送不会失败
+            let _ = granted.await;
+            waiting.waiter = None;
+        }
+        drop(waiting);
+
+        let waited_ms = started.elapsed().as_millis() as u64;
+        queue.total_wait_ms.fetch_add(waited_ms, Ordering::Relaxed);
+        queue.max_wait_ms.fetch_max(waited_ms, Ordering::Relaxed);
+        queue.running.fetch_add(1, Ordering::Relaxed);
+        IoPermit { queue }
+    }
+
+    pub fn stats(&self) -> IoStats {
+        IoStats {
+            merge: self.merge.stats(),
+            transcode: self.transcode.stats(),
+            scrub: self.scrub.stats(),
+        }
+    }
+}
+
+pub async fn get_server_stats(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+) -> impl IntoResponse {
+    if let Err(resp) = ensure_unrestricted(&ctx, &client_addr).await {
+        return resp;
+    }
+    (StatusCode::OK, Json(ApiResponse::success(json!({ "io": ctx.io.stats(), "hw_encoders": ctx.encoders.encoders(), "disk": ctx.disk.summary(), "hash_benchmark": hash_benchmark_results() })))).into_response()
+}
@@ -0,0 +1,315 @@
+//! Restricting the admin endpoints to trusted networks, e.g. the LAN, while
+//! share links and media streams stay reachable from wherever the reverse
+//! proxy exposes them. Behind a reverse proxy every request comes from the
+//! proxy's address, so the client's address is taken from `X-Forwarded-For`
+//! when the request comes from one of `NASCRAFT_TRUSTED_PROXIES`.
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{HeaderMap, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use log::warn;
+use serde::{Serialize, Serializer};
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use crate::context::AppContext;
+use crate::helper::error_response;
+
+/// Networks `lan` stands for: private, loopback and link-local addresses
+const LAN_NETWORKS: [&str; 9] = [
+    "10.0.0.0/8",
+    "172.16.0.0/12",
+    "192.168.0.0/16",
+    "127.0.0.0/8",
+    "169.254.0.0/16",
+    "100.64.0.0/10",
+    "::1/128",
+    "fc00::/7",
+    "fe80::/10",
+];
+
+/// Networks `any` stands for
+const ANY_NETWORKS: [&str; 2] = ["0.0.0.0/0", "::/0"];
+
+/// An IPv4 or IPv6 network in CIDR notation; a bare address is a network of one
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct IpNetwork {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl IpNetwork {
+    pub fn parse(value: &str) -> Option<Self> {
+        let (addr, prefix) = match value.split_once('/') {
+            Some((addr, prefix)) => (addr.trim().parse::<IpAddr>().ok()?, Some(prefix.trim().parse::<u8>().ok()?)),
+            None => (value.trim().parse::<IpAddr>().ok()?, None),
+        };
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = prefix.unwrap_or(max);
+        (prefix <= max).then_some(Self { addr, prefix })
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        // 双栈监听时 IPv4 客户端以 ::ffff:a.b.c.d 的形式出现
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for IpNetwork {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+impl fmt::Debug for IpNetwork {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl Serialize for IpNetwork {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// The networks `lan` stands for
+pub fn lan_networks() -> Vec<IpNetwork> {
+    LAN_NETWORKS.iter().filter_map(|network| IpNetwork::parse(network)).collect()
+}
+
+/// Comma-separated networks, where `lan` stands for the private ranges and
+/// `any` for every address. None when any entry is invalid or none is given,
+/// so a typo can't silently widen access.
+pub fn parse_networks(value: &str) -> Option<Vec<IpNetwork>> {
+    let mut networks = Vec::new();
+    for entry in value.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+        if entry.eq_ignore_ascii_case("lan") {
+            networks.extend(lan_networks());
+        } else if entry.eq_ignore_ascii_case("any") {
+            networks.extend(ANY_NETWORKS.iter().filter_map(|network| IpNetwork::parse(network)));
+        } else {
+            networks.push(IpNetwork::parse(entry)?);
+        }
+    }
+    (!networks.is_empty()).then_some(networks)
+}
+
+/// The client's address: the peer's, or when the peer is a trusted proxy the
+/// last address in `X-Forwarded-For` that isn't one
+pub fn client_ip(peer: &SocketAddr, headers: &HeaderMap, trusted_proxies: &[IpNetwork]) -> IpAddr {
+    let trusted = |ip: IpAddr| trusted_proxies.iter().any(|network| network.contains(ip));
+    let mut client = peer.ip().to_canonical();
+    if !trusted(client) {
+        return client;
+    }
+    // 每一跳代理把它看到的来源追加到末尾，从右往左跳过可信代理；左侧的值可由客户端伪造
+    let forwarded = headers
+        .get_all("X-Forwarded-For")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .collect::<Vec<_>>();
+    for hop in forwarded.into_iter().rev() {
+        let Ok(ip) = hop.parse::<IpAddr>() else {
+            break;
+        };
+        client = ip.to_canonical();
+        if !trusted(client) {
+            break;
+        }
+    }
+    client
+}
+
+/// Endpoints (relative to `/api/v1`) limited to `NASCRAFT_ADMIN_ALLOWED_NETWORKS`:
+/// server administration, hooks, which run scripts on the server, and the
+/// endpoints that delete files server-wide: storage purges, quarantine
+/// decisions and retention rules
+fn is_sensitive(path: &str) -> bool {
+    let under = |prefix: &str| path == prefix || path.strip_prefix(prefix).is_some_and(|rest| rest.starts_with('/'));
+    if under("/admin") || under("/hooks") || under("/retention") || path.starts_with("/quarantine/") {
+        return true;
+    }
+    // /stats/usage/:bucket/purge
+    path.strip_prefix("/stats/usage/").is_some_and(|rest| rest.ends_with("/purge"))
+}
+
+/// Refuse requests to the admin endpoints from outside the allowed networks
+pub async fn restrict_sensitive_endpoints(
+    State(ctx): State<AppContext>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let config = ctx.config.load();
+    if !is_sensitive(req.uri().path()) {
+        return next.run(req).await;
+    }
+    let ip = client_ip(&peer, req.headers(), &config.trusted_proxies);
+    if config.admin_allowed_networks.iter().any(|network| network.contains(ip)) {
+        return next.run(req).await;
+    }
+    warn!("Refused {} {} from {}, which is outside the admin networks", req.method(), req.uri().path(), ip);
+    error_response(StatusCode::FORBIDDEN, "NETWORK_NOT_ALLOWED", format!("{} is only available from the networks in NASCRAFT_ADMIN_ALLOWED_NETWORKS", req.uri().path()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(value: &str) -> IpAddr {
+        value.parse().unwrap()
+    }
+
+    fn network(value: &str) -> IpNetwork {
+        IpNetwork::parse(value).unwrap()
+    }
+
+    #[test]
+    fn parses_networks() {
+        assert_eq!(network("192.168.1.0/24").to_string(), "192.168.1.0/24");
+        assert_eq!(network(" 10.0.0.1 ").to_string(), "10.0.0.1/32");
+        assert_eq!(network("fd00::/8").to_string(), "fd00::/8");
+        assert_eq!(network("::1").to_string(), "::1/128");
+        assert_eq!(network("0.0.0.0/0").to_string(), "0.0.0.0/0");
+        for invalid in ["", "lan", "10.0.0.0/33", "::/129", "10.0.0.0/", "10.0.0.0/-1", "10.0.0/8", "192.168.1.1/24/1"] {
+            assert!(IpNetwork::parse(invalid).is_none(), "{:?} was accepted", invalid);
+        }
+    }
+
+    #[test]
+    fn ipv4_networks_contain_their_range() {
+        let net = network("192.168.1.0/24");
+        for (addr, inside) in [
+            ("192.168.1.0", true),
+            ("192.168.1.255", true),
+            ("192.168.0.255", false),
+            ("192.168.2.0", false),
+            ("10.0.0.1", false),
+        ] {
+            assert_eq!(net.contains(ip(addr)), inside, "{}", addr);
+        }
+        assert!(network("10.0.0.1").contains(ip("10.0.0.1")));
+        assert!(!network("10.0.0.1").contains(ip("10.0.0.2")));
+        assert!(network("172.16.0.0/12").contains(ip("172.31.255.255")));
+        assert!(!network("172.16.0.0/12").contains(ip("172.32.0.0")));
+        assert!(network("0.0.0.0/0").contains(ip("203.0.113.7")));
+    }
+
+    #[test]
+    fn ipv6_networks_contain_their_range() {
+        let net = network("fe80::/10");
+        assert!(net.contains(ip("fe80::1")));
+        assert!(net.contains(ip("febf:ffff::1")));
+        assert!(!net.contains(ip("fec0::1")));
+        assert!(network("::/0").contains(ip("2001:db8::1")));
+        assert!(network("::1/128").contains(ip("::1")));
+        assert!(!network("::1/128").contains(ip("::2")));
+    }
+
+    #[test]
+    fn mapped_ipv4_addresses_match_ipv4_networks() {
+        assert!(network("192.168.0.0/16").contains(ip("::ffff:192.168.4.5")));
+        assert!(!network("192.168.0.0/16").contains(ip("::ffff:10.0.0.1")));
+        assert!(!network("::/0").contains(ip("::ffff:192.168.4.5")));
+        assert!(!network("0.0.0.0/0").contains(ip("2001:db8::1")));
+    }
+
+    #[test]
+    fn parses_network_lists() {
+        let lan = parse_networks("lan").unwrap();
+        assert_eq!(lan, lan_networks());
+        for (addr, inside) in [("127.0.0.1", true), ("10.1.2.3", true), ("fd12::1", true), ("8.8.8.8", false), ("2001:db8::1", false)] {
+            assert_eq!(lan.iter().any(|net| net.contains(ip(addr))), inside, "{}", addr);
+        }
+        let any = parse_networks("ANY").unwrap();
+        assert!(any.iter().any(|net| net.contains(ip("8.8.8.8"))));
+        assert!(any.iter().any(|net| net.contains(ip("2001:db8::1"))));
+        assert_eq!(parse_networks("10.0.0.0/8, 192.168.1.7").unwrap().len(), 2);
+        assert!(parse_networks("").is_none());
+        assert!(parse_networks(" , ").is_none());
+        assert!(parse_networks("lan,10.0.0.0/40").is_none());
+    }
+
+    fn forwarded(values: &[&str]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for value in values {
+            headers.append("X-Forwarded-For", value.parse().unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn client_ip_ignores_forwarded_for_from_untrusted_peers() {
+        let peer: SocketAddr = "203.0.113.7:5000".parse().unwrap();
+        let trusted = [network("10.0.0.0/8")];
+        assert_eq!(client_ip(&peer, &forwarded(&["192.168.1.2"]), &trusted), ip("203.0.113.7"));
+        assert_eq!(client_ip(&peer, &forwarded(&["192.168.1.2"]), &[]), ip("203.0.113.7"));
+    }
+
+    #[test]
+    fn client_ip_is_taken_from_trusted_proxies() {
+        let peer: SocketAddr = "10.0.0.2:5000".parse().unwrap();
+        let trusted = [network("10.0.0.0/8")];
+        assert_eq!(client_ip(&peer, &forwarded(&[]), &trusted), ip("10.0.0.2"));
+        assert_eq!(client_ip(&peer, &forwarded(&["198.51.100.4"]), &trusted), ip("198.51.100.4"));
+        // 经过多层可信代理时取最右侧的非代理地址
+        assert_eq!(client_ip(&peer, &forwarded(&["198.51.100.4, 10.0.0.9"]), &trusted), ip("198.51.100.4"));
+        assert_eq!(client_ip(&peer, &forwarded(&["198.51.100.4", "10.0.0.9"]), &trusted), ip("198.51.100.4"));
+    }
+
+    #[test]
+    fn client_ip_ignores_spoofed_forwarded_for_entries() {
+        let peer: SocketAddr = "10.0.0.2:5000".parse().unwrap();
+        let trusted = [network("10.0.0.0/8")];
+        // 客户端自带的 X-Forwarded-For 在左侧，不能冒充局域网地址
+        assert_eq!(client_ip(&peer, &forwarded(&["192.168.1.2, 198.51.100.4"]), &trusted), ip("198.51.100.4"));
+        assert_eq!(client_ip(&peer, &forwarded(&["127.0.0.1", "198.51.100.4"]), &trusted), ip("198.51.100.4"));
+        // 无法解析的一跳之后的值都不可信
+        assert_eq!(client_ip(&peer, &forwarded(&["192.168.1.2, garbage"]), &trusted), ip("10.0.0.2"));
+    }
+
+    #[test]
+    fn client_ip_canonicalizes_mapped_addresses() {
+        let peer: SocketAddr = "[::ffff:10.0.0.2]:5000".parse().unwrap();
+        assert_eq!(client_ip(&peer, &forwarded(&[]), &[]), ip("10.0.0.2"));
+        let trusted = [network("10.0.0.0/8")];
+        assert_eq!(client_ip(&peer, &forwarded(&["::ffff:198.51.100.4"]), &trusted), ip("198.51.100.4"));
+    }
+
+    #[test]
+    fn sensitive_endpoints() {
+        for path in [
+            "/admin",
+            "/admin/export",
+            "/admin/restore_metadata",
+            "/hooks",
+            "/hooks/3",
+            "/stats/usage/chunks/purge",
+            "/quarantine/550e8400-e29b-41d4-a716-446655440000",
+            "/quarantine/550e8400-e29b-41d4-a716-446655440000/release",
+            "/retention/rules",
+            "/retention/rules/2",
+            "/retention/preview",
+        ] {
+            assert!(is_sensitive(path), "{} is not restricted", path);
+        }
+        for path in ["/uploaded_files", "/adminx", "/hooksmith", "/stats/usage", "/stats/traffic", "/quarantine", "/media/proxy", "/shares/1"] {
+            assert!(!is_sensitive(path), "{} is restricted", path);
+        }
+    }
+}
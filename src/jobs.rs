@@ -0,0 +1,240 @@
+use axum::{
+    extract::{ConnectInfo, Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, SqlitePool};
+use std::net::SocketAddr;
+use crate::api::Priority;
+use crate::context::AppContext;
+use crate::helper::{error_response, ApiResponse};
+use crate::profiles::ensure_unrestricted;
+
+/// A file a job couldn't process
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobFailure {
+    pub file_id: String,
+    pub filename: String,
+    pub error: String,
+}
+
+/// A background job and its progress, see `GET /api/v1/jobs/:id`
+#[derive(Debug, Serialize, FromRow)]
+pub struct Job {
+    pub id: i64,
+    pub kind: String,
+    /// running / done / failed
+    pub status: String,
+    pub destination: Option<String>,
+    pub total_files: i64,
+    pub done_files: i64,
+    pub total_bytes: i64,
+    pub done_bytes: i64,
+    #[serde(skip)]
+    #[sqlx(rename = "failures")]
+    failures_json: String,
+    #[sqlx(skip)]
+    pub failures: Vec<JobFailure>,
+    pub error: Option<String>,
+    /// Hook that ran, for `hook` jobs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hook_id: Option<i64>,
+    /// File the job was run for, for `hook` and `extract_audio` jobs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file_id: Option<String>,
+    /// Captured stdout and stderr of a `hook` job's command
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+    /// bulk / normal / high; waiting disk-heavy work runs highest first
+    pub priority: String,
+    #[serde(with = "crate::api::timestamp")]
+    pub created_at: i64,
+    #[serde(with = "crate::api::timestamp")]
+    pub updated_at: i64,
+}
+
+const JOB_COLUMNS: &str = "id, kind, status, destination, total_files, done_files, total_bytes, done_bytes, failures, error, hook_id, file_id, output, priority, created_at, updated_at";
+
+fn parse_failures(mut job: Job) -> Job {
+    job.failures = serde_json::from_str(&job.failures_json).unwrap_or_default();
+    job
+}
+
+/// Record a job that starts running right away
+pub async fn create_job(db_pool: &SqlitePool, kind: &str, destination: Option<&str>, total_files: i64, total_bytes: i64) -> Result<i64, String> {
+    sqlx::query(
+        "INSERT INTO jobs (kind, status, destination, total_files, total_bytes, created_at, updated_at)
+         VALUES (?, 'running', ?, ?, ?, strftime('%s', 'now'), strftime('%s', 'now'))"
+    )
+    .bind(kind)
+    .bind(destination)
+    .bind(total_files)
+    .bind(total_bytes)
+    .execute(db_pool)
+    .await
+    .map(|result| result.last_insert_rowid())
+    .map_err(|e| {
+        error!("Failed to create {} job: {}", kind, e);
+        "Failed to create job".to_string()
+    })
+}
+
+pub async fn update_job_progress(db_pool: &SqlitePool, id: i64, done_files: i64, done_bytes: i64, failures: &[JobFailure]) -> Result<(), String> {
+    let failures = serde_json::to_string(failures).unwrap_or_else(|_| "[]".to_string());
+    sqlx::query("UPDATE jobs SET done_files = ?, done_bytes = ?, failures = ?, updated_at = strftime('%s', 'now') WHERE id = ?")
+        .bind(done_files)
+        .bind(done_bytes)
+        .bind(failures)
+        .bind(id)
+        .execute(db_pool)
+        .await
+        .map(|_| ())
+        .map_err(|e| {
+            error!("Failed to update progress of job {}: {}", id, e);
+            "Failed to update job progress".to_string()
+        })
+}
+
+/// Set the totals of a job whose size wasn't known when it started
+pub async fn set_job_totals(db_pool: &SqlitePool, id: i64, total_files: i64, total_bytes: i64) -> Result<(), String> {
+    sqlx::query("UPDATE jobs SET total_files = ?, total_bytes = ?, updated_at = strftime('%s', 'now') WHERE id = ?")
+        .bind(total_files)
+        .bind(total_bytes)
+        .bind(id)
+        .execute(db_pool)
+        .await
+        .map(|_| ())
+        .map_err(|e| {
+            error!("Failed to update totals of job {}: {}", id, e);
+            "Failed to update job totals".to_string()
+        })
+}
+
+/// Mark a job done, or failed with `error`
+pub async fn finish_job(db_pool: &SqlitePool, id: i64, error_text: Option<&str>) -> Result<(), String> {
+    sqlx::query("UPDATE jobs SET status = ?, error = ?, updated_at = strftime('%s', 'now') WHERE id = ?")
+        .bind(if error_text.is_some() { "failed" } else { "done" })
+        .bind(error_text)
+        .bind(id)
+        .execute(db_pool)
+        .await
+        .map(|_| ())
+        .map_err(|e| {
+            error!("Failed to finish job {}: {}", id, e);
+            "Failed to finish job".to_string()
+        })
+}
+
+/// Record a run of a hook for a file, which starts right away
+pub async fn create_hook_job(db_pool: &SqlitePool, hook_id: i64, file_id: &str, command: &str, total_bytes: i64) -> Result<i64, String> {
+    sqlx::query(
+        "INSERT INTO jobs (kind, status, destination, total_files, total_bytes, hook_id, file_id, created_at, updated_at)
+         VALUES ('hook', 'running', ?, 1, ?, ?, ?, strftime('%s', 'now'), strftime('%s', 'now'))"
+    )
+    .bind(command)
+    .bind(total_bytes)
+    .bind(hook_id)
+    .bind(file_id)
+    .execute(db_pool)
+    .await
+    .map(|result| result.last_insert_rowid())
+    .map_err(|e| {
+        error!("Failed to create job for hook {}: {}", hook_id, e);
+        "Failed to create job".to_string()
+    })
+}
+
+/// Record a job for one file that starts right away
+pub async fn create_file_job(db_pool: &SqlitePool, kind: &str, file_id: &str, destination: &str, total_bytes: i64, priority: Priority) -> Result<i64, String> {
+    sqlx::query(
+        "INSERT INTO jobs (kind, status, destination, total_files, total_bytes, file_id, priority, created_at, updated_at)
+         VALUES (?, 'running', ?, 1, ?, ?, ?, strftime('%s', 'now'), strftime('%s', 'now'))"
+    )
+    .bind(kind)
+    .bind(destination)
+    .bind(total_bytes)
+    .bind(file_id)
+    .bind(priority.as_str())
+    .execute(db_pool)
+    .await
+    .map(|result| result.last_insert_rowid())
+    .map_err(|e| {
+        error!("Failed to create {} job for file ID {}: {}", kind, file_id, e);
+        "Failed to create job".to_string()
+    })
+}
+
+pub async fn set_job_output(db_pool: &SqlitePool, id: i64, output: &str) -> Result<(), String> {
+    sqlx::query("UPDATE jobs SET output = ?, updated_at = strftime('%s', 'now') WHERE id = ?")
+        .bind(output)
+        .bind(id)
+        .execute(db_pool)
+        .await
+        .map(|_| ())
+        .map_err(|e| {
+            error!("Failed to save output of job {}: {}", id, e);
+            "Failed to save job output".to_string()
+        })
+}
+
+pub async fn fetch_job(db_pool: &SqlitePool, id: i64) -> Result<Option<Job>, String> {
+    let job = sqlx::query_as::<_, Job>(&format!("SELECT {} FROM jobs WHERE id = ?", JOB_COLUMNS))
+        .bind(id)
+        .fetch_optional(db_pool)
+        .await
+        .map_err(|e| {
+            error!("Failed to fetch job {}: {}", id, e);
+            "Failed to fetch job".to_string()
+        })?;
+    Ok(job.map(parse_failures))
+}
+
+/// The newest `limit` runs of a hook
+pub async fn fetch_hook_jobs(db_pool: &SqlitePool, hook_id: i64, limit: i64) -> Result<Vec<Job>, String> {
+    let jobs = sqlx::query_as::<_, Job>(&format!("SELECT {} FROM jobs WHERE hook_id = ? ORDER BY id DESC LIMIT ?", JOB_COLUMNS))
+        .bind(hook_id)
+        .bind(limit)
+        .fetch_all(db_pool)
+        .await
+        .map_err(|e| {
+            error!("Failed to fetch runs of hook {}: {}", hook_id, e);
+            "Failed to fetch hook runs".to_string()
+        })?;
+    Ok(jobs.into_iter().map(parse_failures).collect())
+}
+
+/// Jobs run in the server process, so those still running at startup were cut
+/// short by a restart
+pub async fn fail_interrupted_jobs(db_pool: &SqlitePool) -> Result<(), String> {
+    let result = sqlx::query(
+        "UPDATE jobs SET status = 'failed', error = 'Interrupted by a server restart', updated_at = strftime('%s', 'now') WHERE status = 'running'"
+    )
+    .execute(db_pool)
+    .await
+    .map_err(|e| {
+        error!("Failed to mark interrupted jobs: {}", e);
+        "Failed to mark interrupted jobs".to_string()
+    })?;
+    if result.rows_affected() > 0 {
+        info!("Marked {} jobs interrupted by the restart as failed", result.rows_affected());
+    }
+    Ok(())
+}
+
+pub async fn get_job(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    Path(id): Path<i64>,
+) -> impl IntoResponse {
+    if let Err(resp) = ensure_unrestricted(&ctx, &client_addr).await {
+        return resp;
+    }
+    match fetch_job(&ctx.app_state.db_pool, id).await {
+        Ok(Some(job)) => (StatusCode::OK, Json(ApiResponse::success(job))).into_response(),
+        Ok(None) => error_response(StatusCode::NOT_FOUND, "JOB_NOT_FOUND", "Job not found".to_string()),
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_JOB_ERROR", e),
+    }
+}
@@ -0,0 +1,7 @@
+//! Parts of nascraft usable from other crates: the wire types of the HTTP API,
+//! chunk hashing and, with the `client` feature, a typed async client.
+
+pub mod api;
+pub mod hashing;
+#[cfg(feature = "client")]
+pub mod client;
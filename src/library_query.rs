@@ -0,0 +1,258 @@
+use log::error;
+use sqlx::{Row, SqlitePool};
+use std::collections::HashSet;
+use crate::api::{FileSortKey, SortOrder};
+use crate::config::AppConfig;
+use crate::profiles::{active_profile, Profile};
+use crate::tenants::current_tenant_id;
+use crate::upload_dao::UploadedFile;
+
+const FILE_COLUMNS: &str = "f.file_id, f.filename, f.original_filename, f.total_size, f.checksum, f.status, f.file_path, f.thumbnail_path, f.last_updated, f.created_at, f.revision";
+
+/// Shared query over library files. Every surface that lists or serves library
+/// content (HTTP listings, search, series, downloads, DLNA browse) goes through
+/// here so visibility rules — upload status, the client's profile and tenant —
+/// are applied in one place rather than in per-endpoint SQL.
+#[derive(Debug, Clone)]
+pub struct LibraryQuery {
+    profile: Option<Profile>,
+    tenant_id: Option<i64>,
+    status: Option<i32>,
+    search: Option<String>,
+    folder_id: Option<i64>,
+    tag: Option<String>,
+    sort_column: &'static str,
+    descending: bool,
+    limit: Option<u32>,
+    offset: u32,
+}
+
+impl LibraryQuery {
+    /// Completed files with no profile restrictions
+    pub fn unrestricted() -> Self {
+        Self {
+            profile: None,
+            tenant_id: None,
+            status: Some(2),
+            search: None,
+            folder_id: None,
+            tag: None,
+            sort_column: "f.id",
+            descending: false,
+            limit: None,
+            offset: 0,
+        }
+    }
+
+    /// Completed files visible to a client under its active (or the default) profile,
+    /// within the tenant of the current request
+    pub async fn for_client(db_pool: &SqlitePool, config: &AppConfig, principal: &str) -> Result<Self, String> {
+        let profile = active_profile(db_pool, config, principal).await?;
+        Ok(Self {
+            profile: profile.filter(|p| p.is_restricted()),
+            tenant_id: current_tenant_id(),
+            ..Self::unrestricted()
+        })
+    }
+
+    pub fn is_restricted(&self) -> bool {
+        self.profile.is_some() || self.tenant_id.is_some()
+    }
+
+    /// Condition a file must meet to be visible regardless of status and search, if any
+    fn visibility(&self) -> Option<(String, Vec<String>)> {
+        let restriction = self.profile.as_ref().and_then(|p| p.restriction());
+        match (restriction, self.tenant_id) {
+            (None, None) => None,
+            (None, Some(tenant_id)) => Some((format!("f.tenant_id IS {}", tenant_id), Vec::new())),
+            (Some(restriction), None) => Some((restriction.clause, restriction.binds)),
+            (Some(restriction), Some(tenant_id)) => Some((
+                format!("f.tenant_id IS {} AND ({})", tenant_id, restriction.clause),
+                restriction.binds,
+            )),
+        }
+    }
+
+    /// Filter by upload status; None includes files in any state
+    pub fn status(mut self, status: Option<i32>) -> Self {
+        self.status = status;
+        self
+    }
+
+    /// Case-insensitive match on file names and scraped titles
+    pub fn search(mut self, term: &str) -> Self {
+        self.search = Some(term.trim().to_lowercase());
+        self
+    }
+
+    /// Only files directly in a folder
+    pub fn folder(mut self, folder_id: i64) -> Self {
+        self.folder_id = Some(folder_id);
+        self
+    }
+
+    /// Only files with a tag, already normalized
+    pub fn tag(mut self, tag: &str) -> Self {
+        self.tag = Some(tag.to_string());
+        self
+    }
+
+    /// Order by one of the whitelisted keys; ties keep upload order
+    pub fn sort(mut self, sort_by: FileSortKey, order: SortOrder) -> Self {
+        self.sort_column = match sort_by {
+            FileSortKey::Id => "f.id",
+            FileSortKey::Name => "COALESCE(f.original_filename, f.filename) COLLATE NOCASE",
+            FileSortKey::Size => "f.total_size",
+            FileSortKey::Date => "f.last_updated",
+            FileSortKey::Status => "f.status",
+            // 最后一个点之后的部分：rtrim 去掉末尾所有非点字符得到前缀
+            FileSortKey::Type => "CASE WHEN instr(f.filename, '.') = 0 THEN '' ELSE lower(substr(f.filename, length(rtrim(f.filename, replace(f.filename, '.', ''))) + 1)) END",
+        };
+        self.descending = order == SortOrder::Desc;
+        self
+    }
+
+    pub fn paginate(mut self, page: u32, page_size: u32) -> Self {
+        self.limit = Some(page_size);
+        self.offset = page.saturating_sub(1) * page_size;
+        self
+    }
+
+    /// WHERE clause over `upload_file_meta f` and its bind values, in order
+    fn filter(&self) -> (String, Vec<String>) {
+        let mut clause = "1=1".to_string();
+        let mut binds = Vec::new();
+
+        if let Some(status) = self.status {
+            clause.push_str(&format!(" AND f.status = {}", status));
+        }
+
+        if let Some(term) = &self.search {
+            clause.push_str(
+                " AND (instr(lower(f.filename), ?) > 0
+                    OR instr(lower(COALESCE(f.original_filename, '')), ?) > 0
+                    OR EXISTS (SELECT 1 FROM media_titles mt WHERE mt.file_id = f.file_id
+                        AND (instr(lower(mt.title), ?) > 0 OR instr(lower(COALESCE(mt.episode_title, '')), ?) > 0)))"
+            );
+            binds.extend(std::iter::repeat_n(term.clone(), 4));
+        }
+
+        if let Some(folder_id) = self.folder_id {
+            clause.push_str(&format!(" AND f.folder_id = {}", folder_id));
+        }
+
+        if let Some(tag) = &self.tag {
+            clause.push_str(" AND EXISTS (SELECT 1 FROM file_tags t WHERE t.file_id = f.file_id AND t.tag = ?)");
+            binds.push(tag.clone());
+        }
+
+        if let Some((visibility, visibility_binds)) = self.visibility() {
+            clause.push_str(&format!(" AND ({})", visibility));
+            binds.extend(visibility_binds);
+        }
+
+        (clause, binds)
+    }
+
+    pub async fn fetch(&self, db_pool: &SqlitePool) -> Result<Vec<UploadedFile>, String> {
+        let (clause, binds) = self.filter();
+        let direction = if self.descending { "DESC" } else { "ASC" };
+        let mut query = format!(
+            "SELECT {} FROM upload_file_meta f WHERE {} ORDER BY {} {}, f.id {}",
+            FILE_COLUMNS,
+            clause,
+            self.sort_column,
+            direction,
+            direction
+        );
+        if let Some(limit) = self.limit {
+            query.push_str(&format!(" LIMIT {} OFFSET {}", limit, self.offset));
+        }
+
+        let mut q = sqlx::query_as::<_, UploadedFile>(&query);
+        for bind in &binds {
+            q = q.bind(bind);
+        }
+        q.fetch_all(db_pool).await.map_err(|e| {
+            error!("Failed to fetch library files: {}", e);
+            "Failed to fetch library files".to_string()
+        })
+    }
+
+    /// Number of matching files, ignoring pagination
+    pub async fn count(&self, db_pool: &SqlitePool) -> Result<i64, String> {
+        let (clause, binds) = self.filter();
+        let query = format!("SELECT COUNT(*) FROM upload_file_meta f WHERE {}", clause);
+
+        let mut q = sqlx::query_scalar::<_, i64>(&query);
+        for bind in &binds {
+            q = q.bind(bind);
+        }
+        q.fetch_one(db_pool).await.map_err(|e| {
+            error!("Failed to count library files: {}", e);
+            "Failed to count library files".to_string()
+        })
+    }
+
+    /// Whether a file matches the query
+    pub async fn contains(&self, db_pool: &SqlitePool, file_id: &str) -> Result<bool, String> {
+        let (clause, binds) = self.filter();
+        let query = format!("SELECT COUNT(*) FROM upload_file_meta f WHERE {} AND f.file_id = ?", clause);
+
+        let mut q = sqlx::query_scalar::<_, i64>(&query);
+        for bind in &binds {
+            q = q.bind(bind);
+        }
+        q.bind(file_id).fetch_one(db_pool).await.map(|count| count > 0).map_err(|e| {
+            error!("Failed to check library file: {}", e);
+            "Failed to check library file".to_string()
+        })
+    }
+
+    /// Lowercased names (with and without extension) of completed files the
+    /// profile or tenant hides. Used to filter listings from the external media
+    /// server, whose ids don't map to our files.
+    pub async fn hidden_names(&self, db_pool: &SqlitePool) -> Result<HashSet<String>, String> {
+        let Some((visibility, binds)) = self.visibility() else {
+            return Ok(HashSet::new());
+        };
+        let query = format!(
+            "SELECT f.filename, f.original_filename FROM upload_file_meta f WHERE f.status = 2 AND NOT ({})",
+            visibility
+        );
+
+        let mut q = sqlx::query(&query);
+        for bind in &binds {
+            q = q.bind(bind);
+        }
+        let rows = q.fetch_all(db_pool).await.map_err(|e| {
+            error!("Failed to fetch hidden library files: {}", e);
+            "Failed to fetch hidden library files".to_string()
+        })?;
+
+        let mut names = HashSet::new();
+        for row in rows {
+            let filename: String = row.get("filename");
+            let original: Option<String> = row.get("original_filename");
+            for name in std::iter::once(filename).chain(original) {
+                let name = name.to_lowercase();
+                if let Some((stem, _)) = name.rsplit_once('.') {
+                    names.insert(stem.to_string());
+                }
+                names.insert(name);
+            }
+        }
+        Ok(names)
+    }
+
+    /// Last path component of each folder the profile hides, lowercased
+    pub fn hidden_folder_names(&self) -> HashSet<String> {
+        self.profile
+            .iter()
+            .flat_map(|p| p.blocked_folders.iter())
+            .filter_map(|folder| folder.trim_end_matches(['/', '\\']).rsplit(['/', '\\']).next())
+            .filter(|name| !name.is_empty())
+            .map(|name| name.to_lowercase())
+            .collect()
+    }
+}
@@ -1,97 +1,390 @@
-mod init_env;
-mod upload;
-mod upload_dao;
-mod download;
-mod display_remote;
-mod helper;
-mod config;
-mod logging;
-mod context;
-mod router;
-mod server;
-mod mdns_advertise;
-mod udp_discovery;
-mod ssdp;
-mod file_checker;
-mod thumbnail;
-
-use crate::config::AppConfig;
-use crate::context::AppContext;
-use crate::init_env::init_db_pool;
-use crate::logging::{ensure_data_dirs, init_logging};
-use crate::mdns_advertise::{shutdown_mdns, start_mdns_advertise};
-use crate::router::build_router;
-use crate::server::serve_http;
-use crate::udp_discovery::{run_udp_discovery_responder, run_udp_broadcast_announcer};
-use crate::ssdp::{run_ssdp_responder, run_ssdp_announcer};
-use crate::file_checker::start_file_integrity_checker;
-use crate::upload::AppState;
-use tracing::info;
-use std::collections::HashMap;
-use std::env;
-use std::sync::Arc;
-use tokio::sync::Mutex;
-
-#[tokio::main]
-async fn main() -> std::io::Result<()> {
-    init_logging()?;
-    ensure_data_dirs()?;
-
-    info!("Nascraft starting up");
-
-    // DATABASE_URL is required
-    if env::var("DATABASE_URL").is_err() {
-        return Err(std::io::Error::new(std::io::ErrorKind::Other, "DATABASE_URL must be set"));
-    }
-
-    // Initialize DB pool and ensure tables on startup
-    let db_pool = init_db_pool()
-        .await
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to initialize database pool: {}", e)))?;
-
-    let app_state = Arc::new(AppState {
-        uploads: Mutex::new(HashMap::new()),
-        db_pool,
-    });
-
-    let cfg = AppConfig::from_env();
-
-    // 创建DLNA播放器实例
-    let dlna_player = Arc::new(Mutex::new(crate::display_remote::DLNAPlayer::new(cfg.enable_dlna_remote).await));
-
-    let ctx = AppContext {
-        app_state: app_state.clone(),
-        dlna_player: dlna_player.clone(),
-    };
-
-    info!("Starting mDNS advertisement");
-
-    let mdns = start_mdns_advertise(&cfg)?;
-
-    info!("Starting UDP discovery responder and broadcaster");
-
-    tokio::spawn(run_udp_discovery_responder(cfg.clone()));
-    tokio::spawn(run_udp_broadcast_announcer(cfg.clone()));
-
-    info!("Starting SSDP (UPnP) discovery responder and announcer");
-
-    tokio::spawn(run_ssdp_responder(cfg.clone()));
-    tokio::spawn(run_ssdp_announcer(cfg.clone()));
-
-    info!("Starting file integrity checker (10-minute interval)");
-
-    start_file_integrity_checker(app_state.db_pool.clone());
-
-    println!("Starting server at http://0.0.0.0:{}", cfg.server_port);
-
-    info!("Starting HTTP server on 0.0.0.0:{}", cfg.server_port);
-
-    let app = build_router(ctx.clone());
-    serve_http(app, cfg.server_port).await?;
-
-    tokio::signal::ctrl_c().await.expect("Failed to listen for ctrl-c");
-    info!("Shutdown signal received (ctrl-c)");
-    shutdown_mdns(mdns);
-    info!("Shutdown complete");
-    Ok(())
-}
+mod init_env;
+mod upload;
+mod upload_dao;
+mod download;
+mod display_remote;
+mod helper;
+mod config;
+mod logging;
+mod context;
+mod router;
+mod server;
+mod mdns_advertise;
+mod udp_discovery;
+mod ssdp;
+mod file_checker;
+mod thumbnail;
+mod filename;
+mod paths;
+mod traffic;
+mod analytics;
+mod subtitles;
+mod opensubtitles;
+mod tmdb;
+mod media_library;
+mod playback;
+mod series;
+mod profiles;
+mod library_query;
+mod delta;
+mod content_range;
+mod folders;
+mod transcode;
+mod retention;
+mod duplicates;
+mod usage;
+mod upload_progress;
+mod upload_hints;
+mod body_limits;
+mod storage_buckets;
+mod transcode_cache;
+mod file_locks;
+mod manifest;
+mod tenants;
+mod config_reload;
+mod db_health;
+mod repository;
+mod file_stream;
+mod jobs;
+mod export;
+mod metadata_backup;
+mod supervisor;
+mod network_watch;
+mod devices;
+mod events;
+mod disk_space;
+mod web_push;
+mod shares;
+mod feeds;
+mod media_scan;
+mod ip_allowlist;
+mod auth_failures;
+mod media_proxy;
+mod mqtt;
+mod renderer;
+mod bot;
+mod fuzzy;
+mod simple_control;
+mod inbox;
+mod metadata_scrub;
+mod quarantine;
+mod outbox;
+mod hooks;
+mod io_scheduler;
+mod hwaccel;
+mod download_verify;
+mod file_edit;
+mod extract;
+mod extract_audio;
+mod derived_files;
+mod preflight;
+mod partial_playback;
+mod image_resize;
+mod slideshow;
+mod music_queue;
+mod handoff;
+mod renderer_diagnostics;
+mod client_devices;
+mod backup;
+#[cfg(feature = "fuse")]
+mod fuse_mount;
+#[cfg(feature = "http3")]
+mod http3;
+#[cfg(feature = "mock-renderer")]
+mod mock_renderer;
+
+use nascraft::{api, hashing};
+use crate::hashing::log_hash_benchmark;
+use crate::bot::{register_discord_commands, run_chat_notifier, run_telegram_bot};
+use crate::config::{AppConfig, SharedConfig};
+use crate::context::AppContext;
+use crate::disk_space::{run_disk_space_monitor, DiskStatus};
+use crate::events::event_channel;
+use crate::outbox::run_outbox_dispatcher;
+use crate::io_scheduler::IoScheduler;
+use crate::hwaccel::HwEncoders;
+use crate::transcode::TRANSCODE_FORMATS;
+use crate::hooks::run_hook_runner;
+use crate::db_health::{run_database_monitor, start_database, DbHealth};
+use crate::init_env::{bootstrap_schema, open_db_pool, open_memory_db_pool};
+use crate::logging::{ensure_data_dirs, init_logging};
+use crate::media_scan::run_media_scan_notifier;
+use crate::mqtt::run_mqtt_publisher;
+use crate::mdns_advertise::{shutdown_mdns, start_mdns_advertise};
+use crate::router::build_router;
+use crate::preflight::run_preflight;
+use crate::server::serve_http;
+use crate::udp_discovery::{run_udp_discovery_responder, run_udp_broadcast_announcer};
+use crate::ssdp::{run_ssdp_responder, run_ssdp_announcer};
+use crate::file_checker::run_file_integrity_checker;
+use crate::metadata_backup::run_backup_scheduler;
+use crate::network_watch::{local_addr_channel, run_ip_watcher};
+use crate::retention::run_retention_scheduler;
+use crate::supervisor::Supervisor;
+use crate::upload::AppState;
+use crate::web_push::{run_push_notifier, WebPush};
+use tracing::{info, warn};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::{Mutex, Notify};
+
+fn main() -> std::io::Result<()> {
+    // Logging is configured by the settings, so their errors can only go to stderr
+    let cfg = AppConfig::load()
+        .map_err(|e| std::io::Error::other(format!("Invalid configuration: {}", e)))?;
+
+    // 线程数来自配置，所以运行时在读取配置后手动创建
+    let mut runtime = tokio::runtime::Builder::new_multi_thread();
+    runtime.enable_all().max_blocking_threads(cfg.max_blocking_threads);
+    if cfg.worker_threads > 0 {
+        runtime.worker_threads(cfg.worker_threads);
+    }
+    runtime.build()?.block_on(run(cfg))
+}
+
+async fn run(cfg: AppConfig) -> std::io::Result<()> {
+    init_logging(&cfg)?;
+    ensure_data_dirs()?;
+
+    info!("Nascraft starting up");
+    cfg.log_summary();
+    if cfg.hash_algorithm_auto {
+        log_hash_benchmark();
+        info!("Using {} chunk checksums, the fastest on this CPU", cfg.hash_algorithm.as_str());
+    }
+
+    // 未配置数据库时元数据和上传进度只保存在内存中，重启后丢失
+    let db_pool = match &cfg.database_url {
+        Some(database_url) => open_db_pool(database_url),
+        None => open_memory_db_pool(),
+    }
+        .map_err(|e| std::io::Error::other(format!("Failed to initialize database pool: {}", e)))?;
+
+    // `--bootstrap` only creates or upgrades the database, e.g. from an install script
+    if std::env::args().skip(1).any(|arg| arg == "--bootstrap") {
+        let Some(database_url) = &cfg.database_url else {
+            return Err(std::io::Error::other("DATABASE_URL must be set to bootstrap a database"));
+        };
+        bootstrap_schema(&db_pool)
+            .await
+            .map_err(|e| std::io::Error::other(format!("Failed to bootstrap database: {}", e)))?;
+        info!("Database bootstrapped, exiting");
+        println!("Database at {} is ready", database_url);
+        db_pool.close().await;
+        return Ok(());
+    }
+
+    // 端口被占用、上传目录不可写等问题在启动时报告并退出，而不是等到请求时才失败
+    let preflight = run_preflight(&cfg, &db_pool).await;
+    preflight.report();
+    let listener = preflight
+        .into_listener()
+        .map_err(|e| std::io::Error::other(format!("Startup checks failed: {}", e)))?;
+
+    let app_state = Arc::new(AppState {
+        uploads: Mutex::new(HashMap::new()),
+        db_pool,
+        activity: Default::default(),
+        chunks: Default::default(),
+    });
+
+    let config = SharedConfig::new(cfg.clone());
+    let db_health = Arc::new(DbHealth::default());
+    // Background tasks are restarted with backoff when they panic or stop
+    let supervisor = Arc::new(Supervisor::default());
+
+    if cfg.database_url.is_none() {
+        warn!("DATABASE_URL is not set, keeping file metadata and upload progress in memory: they are lost on restart");
+    }
+    // Ensures tables on startup; when the database can't be opened the server
+    // starts anyway and the monitor keeps retrying
+    start_database(&app_state.db_pool, &db_health, &config).await;
+    {
+        let (db_pool, db_health, config) = (app_state.db_pool.clone(), db_health.clone(), config.clone());
+        supervisor.spawn("database_monitor", move || run_database_monitor(db_pool.clone(), db_health.clone(), config.clone()));
+    }
+
+    // 创建DLNA播放器实例
+    let dlna_player = Arc::new(Mutex::new(crate::display_remote::DLNAPlayer::new(&cfg, app_state.db_pool.clone(), &supervisor).await));
+
+    let web_push = match WebPush::load_or_create(&cfg) {
+        Ok(web_push) => Some(Arc::new(web_push)),
+        Err(e) => {
+            warn!("Push notifications are disabled: {}", e);
+            None
+        }
+    };
+
+    let (local_addr_tx, local_addr) = local_addr_channel();
+
+    // 只在启动时探测一次，转码时按格式选用
+    let encoders = Arc::new(HwEncoders::probe(&cfg, &TRANSCODE_FORMATS).await);
+
+    let ctx = AppContext {
+        app_state: app_state.clone(),
+        dlna_player: dlna_player.clone(),
+        config,
+        db_health,
+        supervisor: supervisor.clone(),
+        events: event_channel(),
+        outbox: Arc::new(Notify::new()),
+        io: Arc::new(IoScheduler::new(&cfg)),
+        encoders,
+        disk: Arc::new(DiskStatus::default()),
+        verifications: Arc::default(),
+        slideshows: Arc::default(),
+        music_queues: Arc::default(),
+        local_addr: local_addr.clone(),
+        web_push: web_push.clone(),
+        auth_failures: Arc::default(),
+    };
+
+    info!("Starting mDNS advertisement");
+
+    let mdns = start_mdns_advertise(&cfg)?;
+
+    info!("Starting UDP discovery responder and broadcaster");
+
+    let discovery_cfg = cfg.clone();
+    supervisor.spawn("udp_discovery_responder", move || run_udp_discovery_responder(discovery_cfg.clone()));
+    let discovery_cfg = cfg.clone();
+    supervisor.spawn("udp_broadcast_announcer", move || run_udp_broadcast_announcer(discovery_cfg.clone()));
+
+    info!("Starting local address watcher");
+
+    supervisor.spawn("ip_watcher", move || run_ip_watcher(local_addr_tx.clone()));
+
+    info!("Starting SSDP (UPnP) discovery responder and announcer");
+
+    let (discovery_cfg, responder_addr) = (cfg.clone(), local_addr.clone());
+    supervisor.spawn("ssdp_responder", move || run_ssdp_responder(discovery_cfg.clone(), responder_addr.clone()));
+    let (discovery_cfg, announcer_addr) = (cfg.clone(), local_addr.clone());
+    supervisor.spawn("ssdp_announcer", move || run_ssdp_announcer(discovery_cfg.clone(), announcer_addr.clone()));
+
+    info!("Starting file integrity checker (10-minute interval)");
+
+    let (db_pool, io) = (app_state.db_pool.clone(), ctx.io.clone());
+    supervisor.spawn("file_integrity_checker", move || run_file_integrity_checker(db_pool.clone(), io.clone()));
+
+    info!("Starting retention scheduler (hourly)");
+
+    let (db_pool, config) = (app_state.db_pool.clone(), ctx.config.clone());
+    supervisor.spawn("retention_scheduler", move || run_retention_scheduler(db_pool.clone(), config.clone()));
+
+    info!("Starting disk space monitor (5-minute interval)");
+
+    let (config, events, disk) = (ctx.config.clone(), ctx.events.clone(), ctx.disk.clone());
+    supervisor.spawn("disk_space_monitor", move || run_disk_space_monitor(config.clone(), events.clone(), disk.clone()));
+
+    info!("Starting media scan notifier");
+
+    let (db_pool, config, events) = (app_state.db_pool.clone(), ctx.config.clone(), ctx.events.clone());
+    supervisor.spawn("media_scan_notifier", move || run_media_scan_notifier(db_pool.clone(), config.clone(), events.clone()));
+
+    info!("Starting hook runner");
+
+    let (db_pool, config, events) = (app_state.db_pool.clone(), ctx.config.clone(), ctx.events.clone());
+    supervisor.spawn("hook_runner", move || run_hook_runner(db_pool.clone(), config.clone(), events.clone()));
+
+    if let Some(web_push) = web_push {
+        info!("Starting push notifier");
+
+        let (db_pool, events) = (app_state.db_pool.clone(), ctx.events.clone());
+        supervisor.spawn("push_notifier", move || run_push_notifier(db_pool.clone(), web_push.clone(), events.clone()));
+    }
+
+    if let Some(mqtt_url) = &cfg.mqtt_url {
+        info!("Starting MQTT publisher ({})", mqtt_url);
+
+        let (mqtt_ctx, mqtt_cfg) = (ctx.clone(), Arc::new(cfg.clone()));
+        supervisor.spawn("mqtt_publisher", move || run_mqtt_publisher(mqtt_ctx.clone(), mqtt_cfg.clone()));
+    }
+
+    if cfg.telegram_bot_token.is_some() {
+        info!("Starting Telegram bot");
+
+        let (bot_ctx, bot_cfg) = (ctx.clone(), Arc::new(cfg.clone()));
+        supervisor.spawn("telegram_bot", move || run_telegram_bot(bot_ctx.clone(), bot_cfg.clone()));
+    }
+
+    if cfg.telegram_bot_token.is_some() || cfg.discord_webhook_url.is_some() {
+        info!("Starting chat notifier");
+
+        let (chat_cfg, events) = (Arc::new(cfg.clone()), ctx.events.clone());
+        supervisor.spawn("chat_notifier", move || run_chat_notifier(chat_cfg.clone(), events.clone()));
+    }
+
+    info!("Starting outbox dispatcher");
+
+    let (db_pool, events, wake) = (app_state.db_pool.clone(), ctx.events.clone(), ctx.outbox.clone());
+    supervisor.spawn("outbox_dispatcher", move || run_outbox_dispatcher(db_pool.clone(), events.clone(), wake.clone()));
+
+    if cfg.discord_application_id.is_some() && cfg.discord_bot_token.is_some() {
+        tokio::spawn(register_discord_commands(Arc::new(cfg.clone())));
+    }
+
+    if cfg.backup_interval_hours > 0 {
+        info!("Starting metadata backups (every {} hours to {})", cfg.backup_interval_hours, cfg.backup_dir.display());
+
+        let (db_pool, config) = (app_state.db_pool.clone(), ctx.config.clone());
+        supervisor.spawn("metadata_backups", move || run_backup_scheduler(db_pool.clone(), config.clone()));
+    }
+
+    // Dropping the session unmounts the library, so keep it alive until shutdown
+    #[cfg(feature = "fuse")]
+    let fuse_session = match &cfg.fuse_mount {
+        Some(mountpoint) => match crate::fuse_mount::mount_library(app_state.db_pool.clone(), mountpoint, cfg.fuse_allow_other) {
+            Ok(session) => Some(session),
+            Err(e) => {
+                warn!("Failed to mount library at {}: {}", mountpoint.display(), e);
+                None
+            }
+        },
+        None => None,
+    };
+    #[cfg(not(feature = "fuse"))]
+    if cfg.fuse_mount.is_some() || cfg.fuse_allow_other {
+        warn!("FUSE mount options are set but nascraft was built without the `fuse` feature");
+    }
+    #[cfg(not(feature = "mock-renderer"))]
+    if !cfg.mock_renderers.is_empty() {
+        warn!("NASCRAFT_MOCK_RENDERERS is set but nascraft was built without the `mock-renderer` feature");
+    }
+
+    println!("Starting server at http://0.0.0.0:{}", cfg.server_port);
+
+    info!("Starting HTTP server on 0.0.0.0:{}", cfg.server_port);
+
+    let app = build_router(ctx.clone());
+
+    #[cfg(feature = "http3")]
+    let app = match (cfg.http3_port, &cfg.tls_cert, &cfg.tls_key) {
+        (Some(port), Some(cert), Some(key)) => match crate::http3::serve_http3(app.clone(), port, cert, key) {
+            Ok(()) => crate::http3::advertise_alt_svc(app, port),
+            Err(e) => {
+                warn!("Failed to start HTTP/3 listener on udp/{}: {}", port, e);
+                app
+            }
+        },
+        (Some(_), _, _) => {
+            warn!("NASCRAFT_HTTP3_PORT is set but NASCRAFT_TLS_CERT or NASCRAFT_TLS_KEY is missing, HTTP/3 disabled");
+            app
+        }
+        _ => app,
+    };
+    #[cfg(not(feature = "http3"))]
+    if cfg.http3_port.is_some() || cfg.tls_cert.is_some() || cfg.tls_key.is_some() {
+        warn!("HTTP/3 options are set but nascraft was built without the `http3` feature");
+    }
+
+    let http_cfg = Arc::new(cfg.clone());
+    supervisor.spawn("http_server", move || serve_http(app.clone(), listener.clone(), http_cfg.clone()));
+
+    tokio::signal::ctrl_c().await.expect("Failed to listen for ctrl-c");
+    info!("Shutdown signal received (ctrl-c)");
+    shutdown_mdns(mdns);
+    #[cfg(feature = "fuse")]
+    drop(fuse_session);
+    info!("Shutdown complete");
+    Ok(())
+}
@@ -0,0 +1,230 @@
+use axum::{
+    extract::{ConnectInfo, Path, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use log::{error, info};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use sqlx::SqlitePool;
+use std::collections::{HashMap, HashSet};
+use std::io::Read;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use crate::context::AppContext;
+use crate::folders::{ensure_folder_admin, fetch_visible_folder, Folder};
+use crate::helper::{error_response, ApiResponse};
+use crate::paths::long_path;
+
+/// Name of a folder's files in its manifest and where each is stored, newest
+/// upload first when several records share a name
+async fn fetch_manifest_files(db_pool: &SqlitePool, folder_id: i64) -> Result<Vec<(String, String)>, String> {
+    let rows: Vec<(String, String)> = sqlx::query_as(
+        "SELECT filename, file_path FROM upload_file_meta
+         WHERE folder_id = ? AND status = 2
+         ORDER BY COALESCE(NULLIF(created_at, 0), last_updated) DESC, file_id"
+    )
+    .bind(folder_id)
+    .fetch_all(db_pool)
+    .await
+    .map_err(|e| {
+        error!("Failed to fetch folder files for manifest: {}", e);
+        "Failed to fetch folder files".to_string()
+    })?;
+
+    // 同名文件合并时会覆盖同一路径，只保留最新的记录
+    let mut seen = HashSet::new();
+    let mut files: Vec<(String, String)> = rows.into_iter().filter(|(name, _)| seen.insert(name.clone())).collect();
+    files.sort();
+    Ok(files)
+}
+
+fn sha256_file(path: PathBuf) -> std::io::Result<String> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 1024 * 1024];
+    loop {
+        match file.read(&mut buf)? {
+            0 => break,
+            n => hasher.update(&buf[..n]),
+        }
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// SHA-256 of a stored file, None when it is missing from disk
+async fn hash_stored_file(file_path: &str) -> Result<Option<String>, String> {
+    let path = long_path(std::path::Path::new(file_path));
+    match tokio::task::spawn_blocking(move || sha256_file(path)).await {
+        Ok(Ok(digest)) => Ok(Some(digest)),
+        Ok(Err(e)) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Ok(Err(e)) => {
+            error!("Failed to hash {}: {}", file_path, e);
+            Err(format!("Failed to read {}", file_path))
+        }
+        Err(e) => {
+            error!("Hash task failed for {}: {}", file_path, e);
+            Err(format!("Failed to hash {}", file_path))
+        }
+    }
+}
+
+/// Folder by ID for a client that may manage it, or the error response to return
+async fn manifest_folder(ctx: &AppContext, client_addr: &SocketAddr, id: i64) -> Result<Folder, Response> {
+    ensure_folder_admin(ctx, client_addr).await?;
+    match fetch_visible_folder(&ctx.app_state.db_pool, id).await {
+        Ok(Some(folder)) => Ok(folder),
+        Ok(None) => Err(error_response(StatusCode::NOT_FOUND, "FOLDER_NOT_FOUND", "Folder not found".to_string())),
+        Err(e) => Err(error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_FOLDER_ERROR", e)),
+    }
+}
+
+/// SHA256SUMS of a folder's completed files, in the format `sha256sum -c` reads.
+/// Files missing from disk are listed as comments.
+pub async fn export_manifest(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    Path(id): Path<i64>,
+) -> impl IntoResponse {
+    let folder = match manifest_folder(&ctx, &client_addr, id).await {
+        Ok(folder) => folder,
+        Err(resp) => return resp,
+    };
+    let files = match fetch_manifest_files(&ctx.app_state.db_pool, id).await {
+        Ok(files) => files,
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_FILES_ERROR", e),
+    };
+
+    let mut manifest = String::new();
+    for (name, file_path) in &files {
+        match hash_stored_file(file_path).await {
+            Ok(Some(digest)) => manifest.push_str(&format!("{}  {}\n", digest, name)),
+            Ok(None) => manifest.push_str(&format!("# missing: {}\n", name)),
+            Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "MANIFEST_ERROR", e),
+        }
+    }
+    info!("Exported manifest of folder '{}' with {} files", folder.name, files.len());
+
+    (
+        StatusCode::OK,
+        [
+            (header::CONTENT_TYPE, "text/plain; charset=utf-8".to_string()),
+            (header::CONTENT_DISPOSITION, "attachment; filename=\"SHA256SUMS\"".to_string()),
+        ],
+        manifest,
+    )
+        .into_response()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum VerifyStatus {
+    Ok,
+    Mismatch,
+    /// Listed in the manifest but not stored in the folder, or gone from disk
+    Missing,
+}
+
+#[derive(Serialize)]
+struct VerifyEntry {
+    name: String,
+    status: VerifyStatus,
+    expected: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    actual: Option<String>,
+}
+
+#[derive(Serialize)]
+struct VerifyReport {
+    passed: bool,
+    ok: usize,
+    mismatched: usize,
+    missing: usize,
+    files: Vec<VerifyEntry>,
+    /// Files stored in the folder that the manifest doesn't list
+    unlisted: Vec<String>,
+    /// 1-based numbers of lines that aren't `<sha256>  <name>`
+    invalid_lines: Vec<usize>,
+}
+
+/// Parse a `<sha256>  <name>` line; `*` before the name (binary mode) is accepted
+fn parse_manifest_line(line: &str) -> Option<(String, String)> {
+    let (digest, name) = line.split_once(char::is_whitespace)?;
+    let name = name.trim_start();
+    let name = name.strip_prefix('*').unwrap_or(name);
+    let valid = digest.len() == 64 && digest.chars().all(|c| c.is_ascii_hexdigit()) && !name.is_empty();
+    valid.then(|| (digest.to_lowercase(), name.to_string()))
+}
+
+/// Check a folder's files on disk against a SHA256SUMS manifest sent as the body
+pub async fn verify_manifest(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    Path(id): Path<i64>,
+    body: String,
+) -> impl IntoResponse {
+    let folder = match manifest_folder(&ctx, &client_addr, id).await {
+        Ok(folder) => folder,
+        Err(resp) => return resp,
+    };
+
+    let mut expected = Vec::new();
+    let mut invalid_lines = Vec::new();
+    for (i, line) in body.lines().enumerate() {
+        let line = line.trim_end_matches('\r');
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match parse_manifest_line(line) {
+            Some(entry) => expected.push(entry),
+            None => invalid_lines.push(i + 1),
+        }
+    }
+    if expected.is_empty() {
+        return error_response(StatusCode::BAD_REQUEST, "INVALID_MANIFEST", "The body must be a SHA256SUMS manifest with at least one entry".to_string());
+    }
+
+    let stored: HashMap<String, String> = match fetch_manifest_files(&ctx.app_state.db_pool, id).await {
+        Ok(files) => files.into_iter().collect(),
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_FILES_ERROR", e),
+    };
+
+    let mut files = Vec::new();
+    for (digest, name) in expected {
+        let actual = match stored.get(&name) {
+            Some(file_path) => match hash_stored_file(file_path).await {
+                Ok(actual) => actual,
+                Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "MANIFEST_ERROR", e),
+            },
+            None => None,
+        };
+        let status = match &actual {
+            Some(actual) if *actual == digest => VerifyStatus::Ok,
+            Some(_) => VerifyStatus::Mismatch,
+            None => VerifyStatus::Missing,
+        };
+        files.push(VerifyEntry { name, status, expected: digest, actual });
+    }
+
+    let listed: HashSet<&str> = files.iter().map(|f| f.name.as_str()).collect();
+    let mut unlisted: Vec<String> = stored.keys().filter(|name| !listed.contains(name.as_str())).cloned().collect();
+    unlisted.sort();
+
+    let count = |status| files.iter().filter(|f| f.status == status).count();
+    let (ok, mismatched, missing) = (count(VerifyStatus::Ok), count(VerifyStatus::Mismatch), count(VerifyStatus::Missing));
+    info!(
+        "Verified folder '{}' against manifest: {} ok, {} mismatched, {} missing",
+        folder.name, ok, mismatched, missing
+    );
+
+    (StatusCode::OK, Json(ApiResponse::success(VerifyReport {
+        passed: mismatched == 0 && missing == 0,
+        ok,
+        mismatched,
+        missing,
+        files,
+        unlisted,
+        invalid_lines,
+    }))).into_response()
+}
@@ -1,63 +1,81 @@
-use crate::config::AppConfig;
-use log::{error, info};
-use local_ip_address::local_ip;
-use mdns_sd::{ServiceDaemon, ServiceInfo};
-use std::collections::HashMap;
-
-pub fn start_mdns_advertise(cfg: &AppConfig) -> std::io::Result<ServiceDaemon> {
-    let mdns = ServiceDaemon::new().map_err(|e| {
-        std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to create mDNS daemon: {e}"))
-    })?;
-
-    let host_name = format!("{}.local.", cfg.mdns_instance_name);
-    info!(
-        "mDNS advertise init: service_type={}, instance_name={}, hostname={}",
-        cfg.mdns_service_type, cfg.mdns_instance_name, host_name
-    );
-    let mut mdns_properties: HashMap<String, String> = HashMap::new();
-    mdns_properties.insert("proto".to_string(), "http".to_string());
-    mdns_properties.insert("port".to_string(), cfg.server_port.to_string());
-
-    let ip = local_ip().unwrap_or_else(|e| {
-        error!("Failed to get local IP: {}", e);
-        "127.0.0.1".parse().expect("127.0.0.1 should be valid")
-    });
-
-    info!("mDNS advertise address: ip={}, port={}", ip, cfg.server_port);
-
-    let service_info = ServiceInfo::new(
-        &cfg.mdns_service_type,
-        &cfg.mdns_instance_name,
-        &host_name,
-        ip,
-        cfg.server_port,
-        mdns_properties,
-    )
-    .map(|s| s.enable_addr_auto())
-    .map_err(|e| {
-        std::io::Error::new(
-            std::io::ErrorKind::Other,
-            format!("Failed to create mDNS service info: {e}"),
-        )
-    })?;
-
-    mdns.register(service_info).map_err(|e| {
-        std::io::Error::new(
-            std::io::ErrorKind::Other,
-            format!("Failed to register mDNS service: {e}"),
-        )
-    })?;
-
-    info!(
-        "mDNS service registered: type={}, instance={}, hostname={}, ip={}, port={}",
-        cfg.mdns_service_type, cfg.mdns_instance_name, host_name, ip, cfg.server_port
-    );
-
-    Ok(mdns)
-}
-
-pub fn shutdown_mdns(mdns: ServiceDaemon) {
-    if let Err(e) = mdns.shutdown() {
-        error!("mDNS shutdown failed: {}", e);
-    }
-}
+use crate::config::AppConfig;
+use log::{error, info};
+use local_ip_address::local_ip;
+use mdns_sd::{ServiceDaemon, ServiceInfo};
+use nascraft::api::API_VERSION;
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+/// Generic web service type browsed by most Bonjour/zeroconf clients
+const HTTP_SERVICE_TYPE: &str = "_http._tcp.local.";
+
+fn register_service(
+    mdns: &ServiceDaemon,
+    service_type: &str,
+    cfg: &AppConfig,
+    host_name: &str,
+    ip: IpAddr,
+    properties: HashMap<String, String>,
+) -> std::io::Result<()> {
+    let service_info = ServiceInfo::new(
+        service_type,
+        &cfg.mdns_instance_name,
+        host_name,
+        ip,
+        cfg.server_port,
+        properties,
+    )
+    .map(|s| s.enable_addr_auto())
+    .map_err(|e| {
+        std::io::Error::other(format!("Failed to create mDNS service info: {e}"))
+    })?;
+
+    mdns.register(service_info).map_err(|e| {
+        std::io::Error::other(format!("Failed to register mDNS service: {e}"))
+    })?;
+
+    info!(
+        "mDNS service registered: type={}, instance={}, hostname={}, ip={}, port={}",
+        service_type, cfg.mdns_instance_name, host_name, ip, cfg.server_port
+    );
+    Ok(())
+}
+
+pub fn start_mdns_advertise(cfg: &AppConfig) -> std::io::Result<ServiceDaemon> {
+    let mdns = ServiceDaemon::new().map_err(|e| {
+        std::io::Error::other(format!("Failed to create mDNS daemon: {e}"))
+    })?;
+
+    let host_name = format!("{}.local.", cfg.mdns_instance_name);
+    info!(
+        "mDNS advertise init: service_type={}, instance_name={}, hostname={}",
+        cfg.mdns_service_type, cfg.mdns_instance_name, host_name
+    );
+    let mut mdns_properties: HashMap<String, String> = HashMap::new();
+    mdns_properties.insert("proto".to_string(), "http".to_string());
+    mdns_properties.insert("port".to_string(), cfg.server_port.to_string());
+    // Where clients find the API, `path` being the key _http._tcp browsers open
+    mdns_properties.insert("path".to_string(), format!("/api/v{}", API_VERSION));
+    mdns_properties.insert("api_version".to_string(), API_VERSION.to_string());
+    mdns_properties.insert("version".to_string(), env!("CARGO_PKG_VERSION").to_string());
+
+    let ip = local_ip().unwrap_or_else(|e| {
+        error!("Failed to get local IP: {}", e);
+        "127.0.0.1".parse().expect("127.0.0.1 should be valid")
+    });
+
+    info!("mDNS advertise address: ip={}, port={}", ip, cfg.server_port);
+
+    register_service(&mdns, &cfg.mdns_service_type, cfg, &host_name, ip, mdns_properties.clone())?;
+    if cfg.mdns_http && cfg.mdns_service_type != HTTP_SERVICE_TYPE {
+        register_service(&mdns, HTTP_SERVICE_TYPE, cfg, &host_name, ip, mdns_properties)?;
+    }
+
+    Ok(mdns)
+}
+
+pub fn shutdown_mdns(mdns: ServiceDaemon) {
+    if let Err(e) = mdns.shutdown() {
+        error!("mDNS shutdown failed: {}", e);
+    }
+}
@@ -0,0 +1,359 @@
+use axum::{
+    extract::{ConnectInfo, OriginalUri, Path, Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, SqlitePool};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use crate::config::AppConfig;
+use crate::context::AppContext;
+use crate::helper::{error_response, ApiResponse, MAX_PAGE_SIZE};
+use crate::api::{FileSortKey, SortOrder};
+use crate::library_query::LibraryQuery;
+use crate::playback::attach_watch_states;
+use crate::profiles::ensure_file_allowed;
+use crate::traffic::client_principal;
+use crate::tmdb::TmdbClient;
+use crate::upload_dao::{fetch_uploaded_file_by_id, UploadedFile};
+
+/// Release tags that mark the end of the title part of a filename
+const RELEASE_TAGS: [&str; 32] = [
+    "480p", "576p", "720p", "1080p", "1080i", "2160p", "4k", "uhd", "hdr", "bluray", "blu-ray",
+    "bdrip", "brrip", "webrip", "web-dl", "webdl", "web", "hdtv", "dvdrip", "x264", "x265",
+    "h264", "h265", "hevc", "xvid", "aac", "ac3", "dts", "remux", "proper", "repack", "multi",
+];
+
+/// Title, year and episode numbers guessed from a filename
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ParsedName {
+    pub title: String,
+    pub year: Option<i64>,
+    pub season: Option<i64>,
+    pub episode: Option<i64>,
+}
+
+/// Scraped (or filename-derived) metadata for a library entry
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct MediaTitle {
+    pub file_id: String,
+    /// `movie` or `episode`
+    pub media_type: String,
+    /// `tmdb` when matched online, `filename` when only parsed from the name
+    pub source: String,
+    pub tmdb_id: Option<i64>,
+    /// Movie title, or show name for episodes
+    pub title: String,
+    pub year: Option<i64>,
+    pub overview: Option<String>,
+    pub poster_url: Option<String>,
+    pub season: Option<i64>,
+    pub episode: Option<i64>,
+    pub episode_title: Option<String>,
+    /// Certification as published, e.g. `PG-13`, `TV-MA`, `16`
+    pub content_rating: Option<String>,
+    /// Minimum viewer age derived from `content_rating`, used by profile restrictions
+    pub content_age: Option<i64>,
+    #[serde(with = "crate::api::timestamp")]
+    pub scraped_at: i64,
+}
+
+/// Leading digits of `s` (at most `max_len` of them) and the rest
+fn split_number(s: &str, max_len: usize) -> Option<(i64, &str)> {
+    let end = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    if end == 0 || end > max_len {
+        return None;
+    }
+    Some((s[..end].parse().ok()?, &s[end..]))
+}
+
+/// Recognize `S01E02` (also `s1e2`, `S01E02E03`) and `1x02`
+fn parse_episode_tag(token: &str) -> Option<(i64, i64)> {
+    let token = token.to_lowercase();
+    if let Some(rest) = token.strip_prefix('s') {
+        let (season, rest) = split_number(rest, 3)?;
+        let (episode, _) = split_number(rest.strip_prefix('e')?, 4)?;
+        return Some((season, episode));
+    }
+
+    let (season, rest) = split_number(&token, 2)?;
+    let (episode, tail) = split_number(rest.strip_prefix('x')?, 3)?;
+    tail.is_empty().then_some((season, episode))
+}
+
+fn parse_year(token: &str) -> Option<i64> {
+    if token.len() != 4 {
+        return None;
+    }
+    token.parse::<i64>().ok().filter(|y| (1900..=2100).contains(y))
+}
+
+/// Guess title, year, season and episode from a video filename such as
+/// `Show.Name.S01E02.720p.mkv` or `Movie Name (2010).mp4`
+pub fn parse_media_name(filename: &str) -> ParsedName {
+    let stem = match filename.rfind('.') {
+        Some(idx) if idx > 0 => &filename[..idx],
+        _ => filename,
+    };
+    let cleaned: String = stem.chars().map(|c| if c == '.' || c == '_' { ' ' } else { c }).collect();
+    let tokens: Vec<&str> = cleaned.split_whitespace().collect();
+
+    let mut parsed = ParsedName::default();
+    let mut title_end = tokens.len();
+    // The first word always belongs to the title, so a movie called "2012" keeps its name
+    for (i, token) in tokens.iter().enumerate().skip(1) {
+        let bare = token.trim_matches(|c| matches!(c, '(' | ')' | '[' | ']' | '-'));
+        if parsed.season.is_none() {
+            if let Some((season, episode)) = parse_episode_tag(bare) {
+                parsed.season = Some(season);
+                parsed.episode = Some(episode);
+                title_end = title_end.min(i);
+                continue;
+            }
+        }
+        if parsed.year.is_none() {
+            if let Some(year) = parse_year(bare) {
+                parsed.year = Some(year);
+                title_end = title_end.min(i);
+                continue;
+            }
+        }
+        if token.starts_with('[') || RELEASE_TAGS.contains(&bare.to_lowercase().as_str()) {
+            title_end = title_end.min(i);
+        }
+    }
+
+    let title = tokens[..title_end].join(" ");
+    let title = title.trim_end_matches([' ', '-']).trim();
+    parsed.title = if title.is_empty() { stem.trim().to_string() } else { title.to_string() };
+    parsed
+}
+
+/// Map a certification from any common rating system to the minimum viewer age,
+/// e.g. `PG-13` -> 13, `TV-MA` -> 17, `FSK 16` -> 16
+pub fn certification_age(certification: &str) -> Option<i64> {
+    let normalized = certification.trim().to_uppercase();
+    match normalized.as_str() {
+        "" | "NR" | "NOT RATED" | "UNRATED" => None,
+        "G" | "U" | "TV-Y" | "TV-G" | "ALL" => Some(0),
+        "TV-Y7" => Some(7),
+        "PG" | "TV-PG" => Some(10),
+        "PG-13" => Some(13),
+        "TV-14" => Some(14),
+        "R" | "TV-MA" => Some(17),
+        "NC-17" | "X" | "ADULT" => Some(18),
+        _ => {
+            // Numeric systems such as "12", "16+", "FSK 16", "R18"
+            let digits: String = normalized
+                .chars()
+                .skip_while(|c| !c.is_ascii_digit())
+                .take_while(|c| c.is_ascii_digit())
+                .collect();
+            digits.parse().ok()
+        }
+    }
+}
+
+/// 保存或更新某个文件的媒体信息
+pub async fn save_media_title(db_pool: &SqlitePool, media: &MediaTitle) -> Result<(), String> {
+    match sqlx::query(
+        "INSERT INTO media_titles (file_id, media_type, source, tmdb_id, title, year, overview, poster_url, season, episode, episode_title, content_rating, content_age, scraped_at)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+         ON CONFLICT(file_id) DO UPDATE SET
+             media_type = excluded.media_type,
+             source = excluded.source,
+             tmdb_id = excluded.tmdb_id,
+             title = excluded.title,
+             year = excluded.year,
+             overview = excluded.overview,
+             poster_url = excluded.poster_url,
+             season = excluded.season,
+             episode = excluded.episode,
+             episode_title = excluded.episode_title,
+             content_rating = excluded.content_rating,
+             content_age = excluded.content_age,
+             scraped_at = excluded.scraped_at"
+    )
+    .bind(&media.file_id)
+    .bind(&media.media_type)
+    .bind(&media.source)
+    .bind(media.tmdb_id)
+    .bind(&media.title)
+    .bind(media.year)
+    .bind(&media.overview)
+    .bind(&media.poster_url)
+    .bind(media.season)
+    .bind(media.episode)
+    .bind(&media.episode_title)
+    .bind(&media.content_rating)
+    .bind(media.content_age)
+    .bind(media.scraped_at)
+    .execute(db_pool)
+    .await
+    {
+        Ok(_) => Ok(()),
+        Err(e) => {
+            error!("Failed to save media title: {}", e);
+            Err("Failed to save media title".to_string())
+        }
+    }
+}
+
+pub async fn fetch_media_titles(db_pool: &SqlitePool, file_ids: &[String]) -> Result<HashMap<String, MediaTitle>, String> {
+    if file_ids.is_empty() {
+        return Ok(HashMap::new());
+    }
+
+    let placeholders = vec!["?"; file_ids.len()].join(", ");
+    let query = format!(
+        "SELECT file_id, media_type, source, tmdb_id, title, year, overview, poster_url, season, episode, episode_title, content_rating, content_age, scraped_at FROM media_titles WHERE file_id IN ({})",
+        placeholders
+    );
+    let mut q = sqlx::query_as::<_, MediaTitle>(&query);
+    for file_id in file_ids {
+        q = q.bind(file_id);
+    }
+
+    match q.fetch_all(db_pool).await {
+        Ok(rows) => Ok(rows.into_iter().map(|m| (m.file_id.clone(), m)).collect()),
+        Err(e) => {
+            error!("Failed to fetch media titles: {}", e);
+            Err("Failed to fetch media titles".to_string())
+        }
+    }
+}
+
+/// Fill in `media` on each file that has scraped metadata. Failures only leave it empty.
+pub async fn attach_media_titles(db_pool: &SqlitePool, files: &mut [UploadedFile]) {
+    let file_ids: Vec<String> = files.iter().map(|f| f.file_id.clone()).collect();
+    if let Ok(mut titles) = fetch_media_titles(db_pool, &file_ids).await {
+        for file in files.iter_mut() {
+            file.media = titles.remove(&file.file_id);
+        }
+    }
+}
+
+/// Match a file against TMDB (when an API key is configured) and store the result.
+/// Without a key, or when nothing matches, the filename-derived guess is stored instead.
+pub async fn scrape_file(db_pool: &SqlitePool, config: &AppConfig, file: &UploadedFile) -> Result<MediaTitle, String> {
+    let name = file.original_filename.as_deref().unwrap_or(&file.filename);
+    let parsed = parse_media_name(name);
+    let is_episode = parsed.season.is_some();
+
+    let found = match &config.tmdb_api_key {
+        Some(api_key) => {
+            let client = TmdbClient::new(api_key, &config.tmdb_language, &config.tmdb_region);
+            match (parsed.season, parsed.episode) {
+                (Some(season), Some(episode)) => client.search_episode(&parsed.title, parsed.year, season, episode).await?,
+                _ => client.search_movie(&parsed.title, parsed.year).await?,
+            }
+        }
+        None => None,
+    };
+
+    let media = MediaTitle {
+        file_id: file.file_id.clone(),
+        media_type: if is_episode { "episode" } else { "movie" }.to_string(),
+        source: if found.is_some() { "tmdb" } else { "filename" }.to_string(),
+        tmdb_id: found.as_ref().map(|m| m.tmdb_id),
+        title: found.as_ref().map(|m| m.title.clone()).unwrap_or_else(|| parsed.title.clone()),
+        year: found.as_ref().and_then(|m| m.year).or(parsed.year),
+        overview: found.as_ref().and_then(|m| m.overview.clone()),
+        poster_url: found.as_ref().and_then(|m| m.poster_url.clone()),
+        season: parsed.season,
+        episode: parsed.episode,
+        episode_title: found.as_ref().and_then(|m| m.episode_title.clone()),
+        content_age: found.as_ref().and_then(|m| m.content_rating.as_deref()).and_then(certification_age),
+        content_rating: found.and_then(|m| m.content_rating),
+        scraped_at: chrono::Utc::now().timestamp(),
+    };
+    save_media_title(db_pool, &media).await?;
+    info!("Scraped {} as {} \"{}\" ({})", file.file_id, media.media_type, media.title, media.source);
+    Ok(media)
+}
+
+/// Scrape a freshly completed upload in the background
+pub fn spawn_scrape(db_pool: SqlitePool, config: std::sync::Arc<AppConfig>, file_id: String) {
+    tokio::spawn(async move {
+        match fetch_uploaded_file_by_id(&db_pool, &file_id).await {
+            Ok(Some(file)) => {
+                if let Err(e) = scrape_file(&db_pool, &config, &file).await {
+                    error!("Failed to scrape metadata for file ID {}: {}", file_id, e);
+                }
+            }
+            Ok(None) => {}
+            Err(e) => error!("Failed to load file ID {} for scraping: {}", file_id, e),
+        }
+    });
+}
+
+pub async fn scrape_library_entry(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    Path(file_id): Path<String>,
+) -> impl IntoResponse {
+    if let Err(resp) = ensure_file_allowed(&ctx, &client_addr, &file_id).await {
+        return resp;
+    }
+    let db_pool = &ctx.app_state.db_pool;
+    let file = match fetch_uploaded_file_by_id(db_pool, &file_id).await {
+        Ok(Some(file)) => file,
+        Ok(None) => return error_response(StatusCode::NOT_FOUND, "FILE_NOT_FOUND", "File not found".to_string()),
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_FILE_RECORD_ERROR", e),
+    };
+
+    match scrape_file(db_pool, &ctx.config.load(), &file).await {
+        Ok(media) => (StatusCode::OK, Json(ApiResponse::success(media))).into_response(),
+        Err(e) => error_response(StatusCode::BAD_GATEWAY, "SCRAPE_ERROR", e),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct LibrarySearchQuery {
+    q: String,
+    page: Option<u32>,
+    page_size: Option<u32>,
+    /// Older name of `page_size`
+    limit: Option<u32>,
+}
+
+/// 按文件名、片名或剧集名搜索已完成的文件
+pub async fn search_library(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    OriginalUri(uri): OriginalUri,
+    Query(query): Query<LibrarySearchQuery>,
+) -> impl IntoResponse {
+    let db_pool = &ctx.app_state.db_pool;
+    let page = query.page.unwrap_or(1).max(1);
+    let page_size = query.page_size.or(query.limit).unwrap_or(50).clamp(1, MAX_PAGE_SIZE);
+    let principal = client_principal(&client_addr);
+
+    let result = match LibraryQuery::for_client(db_pool, &ctx.config.load(), &principal).await {
+        Ok(library) => {
+            let library = library.search(&query.q).sort(FileSortKey::Id, SortOrder::Desc).paginate(page, page_size);
+            match library.count(db_pool).await {
+                Ok(total) => library.fetch(db_pool).await.map(|files| (files, total)),
+                Err(e) => Err(e),
+            }
+        }
+        Err(e) => Err(e),
+    };
+
+    match result {
+        Ok((mut files, total)) => {
+            for file in &mut files {
+                if file.thumbnail_path.is_some() {
+                    file.thumbnail_url = Some(format!("/api/v1/thumbnail/{}", file.file_id));
+                }
+            }
+            attach_media_titles(db_pool, &mut files).await;
+            attach_watch_states(db_pool, &principal, &mut files).await;
+            (StatusCode::OK, Json(ApiResponse::paginated(files, page, page_size, total, &uri))).into_response()
+        }
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, "SEARCH_LIBRARY_ERROR", e),
+    }
+}
@@ -0,0 +1,285 @@
+//! Relaying web-hosted videos to UPnP renderers that can't reach the internet
+//! themselves. The renderer plays `/media/proxy?url=...` from the server,
+//! which fetches the remote file and passes the renderer's range requests on
+//! so it can seek. A proxy link must carry a signature from `/api/media/proxy`,
+//! which is guarded like the rest of the API: it needs an unrestricted
+//! profile, and a tenant's API key only in multi-tenant mode. On a
+//! single-tenant server anyone who can reach the API can sign links, so what
+//! keeps the server from being an open relay is that only hosts listed in
+//! `NASCRAFT_MEDIA_PROXY_HOSTS` are fetched.
+
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, Query, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use log::info;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, NON_ALPHANUMERIC};
+use reqwest::Url;
+use ring::hmac;
+use ring::rand::SystemRandom;
+use serde::Deserialize;
+use serde_json::json;
+use std::net::SocketAddr;
+use std::sync::LazyLock;
+use std::time::Duration;
+use crate::api::timestamp::to_rfc3339;
+use crate::bot::lan_base_url;
+use crate::config::AppConfig;
+use crate::context::AppContext;
+use crate::helper::{error_response, ApiResponse};
+use crate::profiles::ensure_unrestricted;
+use crate::renderer::{discover_renderers, find_renderer};
+use crate::traffic::{client_principal, record_traffic};
+use crate::upload_hints::decode_hex;
+
+/// How long a proxy link works, like the share links renderers play from
+const LINK_TTL_SECS: i64 = 24 * 60 * 60;
+/// Redirects of the remote server followed, each to an allowed host
+const MAX_REDIRECTS: usize = 5;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// A remote server that sends nothing for this long ends the stream
+const READ_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Headers of the remote response passed on to the renderer
+const FORWARDED_HEADERS: [header::HeaderName; 6] = [
+    header::CONTENT_TYPE,
+    header::CONTENT_LENGTH,
+    header::CONTENT_RANGE,
+    header::ACCEPT_RANGES,
+    header::LAST_MODIFIED,
+    header::ETAG,
+];
+
+/// Generated at startup, so links signed before a restart are refused
+static SIGNING_KEY: LazyLock<hmac::Key> = LazyLock::new(|| {
+    hmac::Key::generate(hmac::HMAC_SHA256, &SystemRandom::new()).expect("Failed to generate the proxy link signing key")
+});
+
+/// Redirects are followed by `fetch`, which checks every target's host
+static HTTP_CLIENT: LazyLock<reqwest::Client> = LazyLock::new(|| {
+    reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .connect_timeout(CONNECT_TIMEOUT)
+        .read_timeout(READ_TIMEOUT)
+        .build()
+        .unwrap_or_default()
+});
+
+fn signed_message(url: &str, expires: i64) -> String {
+    format!("{}:{}", expires, url)
+}
+
+fn sign(url: &str, expires: i64) -> String {
+    let tag = hmac::sign(&SIGNING_KEY, signed_message(url, expires).as_bytes());
+    tag.as_ref().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Whether the host of `url` is one of `hosts` or a subdomain of one
+fn host_allowed(hosts: &[String], url: &Url) -> bool {
+    let Some(host) = url.host_str() else {
+        return false;
+    };
+    let host = host.trim_end_matches('.').to_lowercase();
+    hosts
+        .iter()
+        .any(|allowed| host.strip_suffix(allowed.as_str()).is_some_and(|sub| sub.is_empty() || sub.ends_with('.')))
+}
+
+/// `url` if the proxy may fetch it: http(s) on an allowed host. Errors carry
+/// the response status and code.
+fn allowed_url(config: &AppConfig, url: &str) -> Result<Url, (StatusCode, &'static str, String)> {
+    if config.media_proxy_hosts.is_empty() {
+        return Err((StatusCode::NOT_FOUND, "MEDIA_PROXY_DISABLED", "The media proxy is disabled; list the hosts it may fetch from in NASCRAFT_MEDIA_PROXY_HOSTS".to_string()));
+    }
+    let url = Url::parse(url.trim()).map_err(|e| (StatusCode::BAD_REQUEST, "INVALID_URL", format!("Invalid URL '{}': {}", url, e)))?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err((StatusCode::BAD_REQUEST, "INVALID_URL", "Only http and https URLs can be proxied".to_string()));
+    }
+    if !host_allowed(&config.media_proxy_hosts, &url) {
+        return Err((StatusCode::FORBIDDEN, "HOST_NOT_ALLOWED", format!(
+            "{} is not in NASCRAFT_MEDIA_PROXY_HOSTS", url.host_str().unwrap_or_default()
+        )));
+    }
+    Ok(url)
+}
+
+/// Signed link a renderer on the LAN plays `url` from, and when it expires
+fn proxy_link(ctx: &AppContext, config: &AppConfig, url: &Url) -> (String, i64) {
+    let expires = chrono::Utc::now().timestamp() + LINK_TTL_SECS;
+    let link = format!(
+        "{}/media/proxy?url={}&expires={}&signature={}",
+        lan_base_url(ctx, config),
+        utf8_percent_encode(url.as_str(), NON_ALPHANUMERIC),
+        expires,
+        sign(url.as_str(), expires)
+    );
+    (link, expires)
+}
+
+/// The file name at the end of `url`'s path, or its host
+fn url_title(url: &Url) -> String {
+    url.path_segments()
+        .and_then(|mut segments| segments.next_back())
+        .filter(|name| !name.is_empty())
+        .map(|name| percent_decode_str(name).decode_utf8_lossy().into_owned())
+        .unwrap_or_else(|| url.host_str().unwrap_or_default().to_string())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateProxyLinkRequest {
+    url: String,
+}
+
+/// Sign a proxy link for a remote URL on an allowed host
+pub async fn create_proxy_link(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    Json(req): Json<CreateProxyLinkRequest>,
+) -> Response {
+    // 远程内容不受档案限制，与媒体服务器的条目一样只对不受限的档案开放
+    if let Err(resp) = ensure_unrestricted(&ctx, &client_addr).await {
+        return resp;
+    }
+    let config = ctx.config.load();
+    let url = match allowed_url(&config, &req.url) {
+        Ok(url) => url,
+        Err((status, code, message)) => return error_response(status, code, message),
+    };
+    let (link, expires) = proxy_link(&ctx, &config, &url);
+    (StatusCode::OK, Json(ApiResponse::success(json!({
+        "url": link,
+        "expires_at": to_rfc3339(expires),
+    })))).into_response()
+}
+
+/// Play a remote URL on a renderer through a proxy link
+pub async fn play_remote_url(ctx: &AppContext, client_addr: &SocketAddr, renderer: &str, url: &str, title: Option<String>) -> Response {
+    if let Err(resp) = ensure_unrestricted(ctx, client_addr).await {
+        return resp;
+    }
+    let config = ctx.config.load();
+    let url = match allowed_url(&config, url) {
+        Ok(url) => url,
+        Err((status, code, message)) => return error_response(status, code, message),
+    };
+    let renderers = match discover_renderers().await {
+        Ok(renderers) => renderers,
+        Err(e) => return error_response(StatusCode::BAD_GATEWAY, "DISCOVERY_ERROR", e),
+    };
+    let renderer = match find_renderer(&renderers, renderer) {
+        Ok(renderer) => renderer,
+        Err(e) => return error_response(StatusCode::NOT_FOUND, "DEVICE_NOT_FOUND", e),
+    };
+    let title = title.filter(|t| !t.trim().is_empty()).unwrap_or_else(|| url_title(&url));
+    let mime_type = mime_guess::from_path(url.path()).first_or_octet_stream();
+    let (link, _) = proxy_link(ctx, &config, &url);
+    match renderer.play_url(&link, &title, mime_type.essence_str()).await {
+        Ok(()) => (StatusCode::OK, Json(ApiResponse::success_with_message(
+            &format!("Playing {} on {}", title, renderer.name),
+            json!({
+                "url": url.as_str(),
+                "title": title,
+                "device": renderer.name,
+            }),
+        ))).into_response(),
+        Err(e) => error_response(StatusCode::BAD_GATEWAY, "PLAY_ERROR", e),
+    }
+}
+
+/// GET `url` with the renderer's range headers, following redirects to
+/// allowed hosts only
+async fn fetch(config: &AppConfig, mut url: Url, headers: &HeaderMap) -> Result<reqwest::Response, Response> {
+    for _ in 0..=MAX_REDIRECTS {
+        let mut request = HTTP_CLIENT.get(url.clone());
+        for name in [header::RANGE, header::IF_RANGE] {
+            if let Some(value) = headers.get(&name) {
+                request = request.header(name, value.clone());
+            }
+        }
+        let response = request
+            .send()
+            .await
+            .map_err(|e| error_response(StatusCode::BAD_GATEWAY, "UPSTREAM_ERROR", format!("Failed to fetch {}: {}", url, e)))?;
+        if !response.status().is_redirection() {
+            return Ok(response);
+        }
+        let location = response
+            .headers()
+            .get(header::LOCATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|location| url.join(location).ok())
+            .ok_or_else(|| error_response(StatusCode::BAD_GATEWAY, "UPSTREAM_ERROR", format!("{} redirected without a valid Location", url)))?;
+        // 重定向目标同样须在允许列表中，否则可借此访问任意地址
+        url = allowed_url(config, location.as_str()).map_err(|(status, code, message)| error_response(status, code, message))?;
+    }
+    Err(error_response(StatusCode::BAD_GATEWAY, "UPSTREAM_ERROR", format!("More than {} redirects", MAX_REDIRECTS)))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ProxyQuery {
+    url: Option<String>,
+    expires: Option<i64>,
+    signature: Option<String>,
+}
+
+/// Stream a remote file to a renderer, with the ranges it asks for
+pub async fn proxy_media(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    Query(query): Query<ProxyQuery>,
+    headers: HeaderMap,
+) -> Response {
+    let (Some(url), Some(expires), Some(signature)) = (query.url, query.expires, query.signature) else {
+        return error_response(StatusCode::BAD_REQUEST, "MISSING_PARAMETER", "A proxy link needs url, expires and signature".to_string());
+    };
+    let valid = decode_hex(&signature).is_some_and(|tag| {
+        hmac::verify(&SIGNING_KEY, signed_message(&url, expires).as_bytes(), &tag).is_ok()
+    });
+    if !valid {
+        return error_response(StatusCode::FORBIDDEN, "INVALID_SIGNATURE", "Invalid proxy link signature".to_string());
+    }
+    if expires < chrono::Utc::now().timestamp() {
+        return error_response(StatusCode::GONE, "LINK_EXPIRED", "The proxy link has expired".to_string());
+    }
+    // 签发后主机可能已从允许列表中移除
+    let config = ctx.config.load();
+    let url = match allowed_url(&config, &url) {
+        Ok(url) => url,
+        Err((status, code, message)) => return error_response(status, code, message),
+    };
+    let upstream = match fetch(&config, url.clone(), &headers).await {
+        Ok(upstream) => upstream,
+        Err(resp) => return resp,
+    };
+    let status = upstream.status();
+    if !status.is_success() && status != StatusCode::RANGE_NOT_SATISFIABLE {
+        return error_response(StatusCode::BAD_GATEWAY, "UPSTREAM_ERROR", format!(
+            "{} responded {}", url.host_str().unwrap_or_default(), status
+        ));
+    }
+    info!("Proxying {} to {} ({})", url, client_addr, status);
+    record_traffic(&ctx.app_state.db_pool, &client_principal(&client_addr), 0, upstream.content_length().unwrap_or(0)).await;
+
+    let forwarded: Vec<_> = FORWARDED_HEADERS
+        .iter()
+        .filter_map(|name| upstream.headers().get(name).map(|value| (name.clone(), value.clone())))
+        .collect();
+    let mut response = (status, Body::from_stream(upstream.bytes_stream())).into_response();
+    response.headers_mut().extend(forwarded);
+    // 部分服务器不给出媒体类型，渲染器据此判断能否播放
+    let generic_type = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_none_or(|value| value.as_bytes().starts_with(b"application/octet-stream"));
+    if generic_type {
+        if let Some(mime_type) = mime_guess::from_path(url.path()).first() {
+            if let Ok(value) = HeaderValue::from_str(mime_type.essence_str()) {
+                response.headers_mut().insert(header::CONTENT_TYPE, value);
+            }
+        }
+    }
+    response
+}
@@ -0,0 +1,169 @@
+//! Tell Jellyfin and Plex about files completed in folders with `media_scan`,
+//! so they show up without waiting for a scheduled library scan.
+
+use log::{info, warn};
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::SqlitePool;
+use std::path::Path;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use crate::config::{AppConfig, SharedConfig};
+use crate::events::{EventSender, ServerEvent};
+use crate::folders::fetch_file_folder;
+use crate::paths::UPLOADS_DIR;
+use crate::repository::UploadRepository;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Path of a stored file as the media servers see it, under `NASCRAFT_MEDIA_SCAN_PATH`
+/// or the uploads directory's absolute path
+fn media_server_path(config: &AppConfig, file_path: &str) -> Result<String, String> {
+    let relative = Path::new(file_path)
+        .strip_prefix(UPLOADS_DIR)
+        .map_err(|_| format!("{} is outside the uploads directory", file_path))?;
+    let components: Vec<_> = relative.components().map(|c| c.as_os_str().to_string_lossy()).collect();
+    match &config.media_scan_path {
+        Some(base) => Ok(format!("{}/{}", base, components.join("/"))),
+        None => {
+            let base = std::fs::canonicalize(UPLOADS_DIR)
+                .map_err(|e| format!("Failed to resolve {}: {}", UPLOADS_DIR, e))?;
+            Ok(base.join(relative).to_string_lossy().into_owned())
+        }
+    }
+}
+
+/// Report the new file to Jellyfin, which scans only the affected library
+async fn notify_jellyfin(client: &reqwest::Client, url: &str, api_key: Option<&str>, path: &str) -> Result<(), String> {
+    let mut request = client
+        .post(format!("{}/Library/Media/Updated", url))
+        .timeout(REQUEST_TIMEOUT)
+        .json(&json!({ "Updates": [{ "Path": path, "UpdateType": "Created" }] }));
+    if let Some(api_key) = api_key {
+        request = request.header("X-Emby-Token", api_key);
+    }
+    let response = request.send().await.map_err(|e| format!("Request failed: {}", e))?;
+    if !response.status().is_success() {
+        return Err(format!("Jellyfin answered {}", response.status()));
+    }
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct PlexSections {
+    #[serde(rename = "MediaContainer")]
+    container: PlexContainer,
+}
+
+#[derive(Deserialize)]
+struct PlexContainer {
+    #[serde(rename = "Directory", default)]
+    sections: Vec<PlexSection>,
+}
+
+#[derive(Deserialize)]
+struct PlexSection {
+    key: String,
+    #[serde(rename = "Location", default)]
+    locations: Vec<PlexLocation>,
+}
+
+#[derive(Deserialize)]
+struct PlexLocation {
+    path: String,
+}
+
+fn path_is_under(path: &str, dir: &str) -> bool {
+    let dir = dir.trim_end_matches(['/', '\\']);
+    path.strip_prefix(dir).is_some_and(|rest| rest.starts_with(['/', '\\']))
+}
+
+/// Rescan the directory holding the new file in the Plex library section whose location contains it
+async fn notify_plex(client: &reqwest::Client, url: &str, token: Option<&str>, path: &str) -> Result<(), String> {
+    let with_token = |request: reqwest::RequestBuilder| match token {
+        Some(token) => request.header("X-Plex-Token", token),
+        None => request,
+    };
+    let response = with_token(client.get(format!("{}/library/sections", url)))
+        .timeout(REQUEST_TIMEOUT)
+        .header("Accept", "application/json")
+        .send()
+        .await
+        .map_err(|e| format!("Request failed: {}", e))?;
+    if !response.status().is_success() {
+        return Err(format!("Plex answered {} listing library sections", response.status()));
+    }
+    let sections: PlexSections = response.json().await.map_err(|e| format!("Invalid library sections: {}", e))?;
+    let Some(section) = sections.container.sections.iter()
+        .find(|s| s.locations.iter().any(|l| path_is_under(path, &l.path)))
+    else {
+        return Err(format!("No Plex library section contains {}", path));
+    };
+
+    let directory = path.rfind(['/', '\\']).map_or(path, |end| &path[..end]);
+    let response = with_token(client.get(format!("{}/library/sections/{}/refresh", url, section.key)))
+        .timeout(REQUEST_TIMEOUT)
+        .query(&[("path", directory)])
+        .send()
+        .await
+        .map_err(|e| format!("Request failed: {}", e))?;
+    if !response.status().is_success() {
+        return Err(format!("Plex answered {} refreshing section {}", response.status(), section.key));
+    }
+    Ok(())
+}
+
+async fn scan_upload(db_pool: &SqlitePool, config: &AppConfig, client: &reqwest::Client, file_id: &str) {
+    if config.jellyfin_url.is_none() && config.plex_url.is_none() {
+        return;
+    }
+    match fetch_file_folder(db_pool, file_id).await {
+        Ok(Some(folder)) if folder.media_scan => {}
+        _ => return,
+    }
+    let file = match db_pool.fetch_uploaded_file(file_id).await {
+        Ok(Some(file)) => file,
+        Ok(None) => return,
+        Err(e) => {
+            warn!("Media scan for {} skipped: {}", file_id, e);
+            return;
+        }
+    };
+    let path = match media_server_path(config, &file.file_path) {
+        Ok(path) => path,
+        Err(e) => {
+            warn!("Media scan for {} skipped: {}", file_id, e);
+            return;
+        }
+    };
+
+    if let Some(url) = &config.jellyfin_url {
+        match notify_jellyfin(client, url, config.jellyfin_api_key.as_deref(), &path).await {
+            Ok(()) => info!("Asked Jellyfin to scan {}", path),
+            Err(e) => warn!("Failed to notify Jellyfin about {}: {}", path, e),
+        }
+    }
+    if let Some(url) = &config.plex_url {
+        match notify_plex(client, url, config.plex_token.as_deref(), &path).await {
+            Ok(()) => info!("Asked Plex to scan {}", path),
+            Err(e) => warn!("Failed to notify Plex about {}: {}", path, e),
+        }
+    }
+}
+
+/// Trigger media server scans for completed uploads
+pub async fn run_media_scan_notifier(db_pool: SqlitePool, config: SharedConfig, events: EventSender) {
+    let client = reqwest::Client::new();
+    let mut receiver = events.subscribe();
+    loop {
+        match receiver.recv().await {
+            Ok(ServerEvent::UploadCompleted { file_id, .. }) => {
+                let config = config.load();
+                scan_upload(&db_pool, &config, &client, &file_id).await;
+            }
+            Ok(_) => {}
+            Err(RecvError::Lagged(skipped)) => warn!("Media scan notifier fell behind, {} events were skipped", skipped),
+            Err(RecvError::Closed) => return,
+        }
+    }
+}
@@ -0,0 +1,356 @@
+use axum::{
+    extract::{ConnectInfo, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use log::{error, info, warn};
+use md5::{Digest, Md5};
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+use serde::{Deserialize, Serialize};
+use sqlx::{Connection, SqliteConnection, SqlitePool};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+use crate::config::SharedConfig;
+use crate::context::AppContext;
+use crate::helper::{error_response, ApiResponse};
+use crate::jobs::fail_interrupted_jobs;
+use crate::profiles::ensure_unrestricted;
+
+const BACKUP_PREFIX: &str = "nascraft-";
+const BACKUP_SUFFIX: &str = ".db";
+
+/// Characters with a meaning in SQLite URIs
+const URI_PATH: &AsciiSet = &CONTROLS.add(b'%').add(b'?').add(b'#');
+
+#[derive(Serialize)]
+pub struct BackupInfo {
+    name: String,
+    size: u64,
+    #[serde(with = "crate::api::timestamp")]
+    created_at: i64,
+}
+
+fn is_backup_name(name: &str) -> bool {
+    name.starts_with(BACKUP_PREFIX)
+        && name.ends_with(BACKUP_SUFFIX)
+        && !name.contains(['/', '\\'])
+        && !name.contains("..")
+}
+
+/// Snapshots in the backup directory, newest first. The names embed the UTC
+/// time they were taken, so they sort by age.
+async fn list_backup_files(backup_dir: &Path) -> Result<Vec<BackupInfo>, String> {
+    let mut entries = match tokio::fs::read_dir(backup_dir).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => {
+            error!("Failed to read backup directory {}: {}", backup_dir.display(), e);
+            return Err("Failed to read backup directory".to_string());
+        }
+    };
+    let mut backups = Vec::new();
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if !is_backup_name(&name) {
+            continue;
+        }
+        let Ok(metadata) = entry.metadata().await else { continue };
+        let created_at = metadata
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or(0);
+        backups.push(BackupInfo { name, size: metadata.len(), created_at });
+    }
+    backups.sort_by(|a, b| b.name.cmp(&a.name));
+    Ok(backups)
+}
+
+/// SQLite URI for a snapshot file. `VACUUM INTO` and `ATTACH` open their file
+/// with the flags of the connection, which include `SQLITE_OPEN_MEMORY` for
+/// the in-memory database, so the mode has to be given explicitly.
+fn snapshot_uri(path: &Path, mode: &str) -> Result<String, String> {
+    let path = std::path::absolute(path).map_err(|e| e.to_string())?;
+    let path = path.to_string_lossy().replace('\\', "/");
+    let path = utf8_percent_encode(&path, URI_PATH).to_string();
+    Ok(format!("file:{}?mode={}", path, mode))
+}
+
+async fn md5_file(path: &Path) -> Option<String> {
+    let data = tokio::fs::read(path).await.ok()?;
+    Some(format!("{:x}", Md5::digest(&data)))
+}
+
+/// Write a consistent copy of the database with `VACUUM INTO` and return its
+/// name. A snapshot identical to the newest one is dropped and that one's name
+/// returned instead, so an idle server doesn't rotate out older snapshots.
+pub async fn take_snapshot(db_pool: &SqlitePool, backup_dir: &Path) -> Result<String, String> {
+    tokio::fs::create_dir_all(backup_dir).await.map_err(|e| {
+        error!("Failed to create backup directory {}: {}", backup_dir.display(), e);
+        "Failed to create backup directory".to_string()
+    })?;
+    let previous = list_backup_files(backup_dir).await?.into_iter().next();
+
+    let name = format!("{}{}{}", BACKUP_PREFIX, chrono::Utc::now().format("%Y%m%d-%H%M%S-%3f"), BACKUP_SUFFIX);
+    let path = backup_dir.join(&name);
+    sqlx::query("VACUUM INTO ?")
+        .bind(snapshot_uri(&path, "rwc")?)
+        .execute(db_pool)
+        .await
+        .map_err(|e| {
+            error!("Failed to write metadata snapshot {}: {}", path.display(), e);
+            "Failed to write metadata snapshot".to_string()
+        })?;
+
+    if let Some(previous) = previous {
+        let previous_md5 = md5_file(&backup_dir.join(&previous.name)).await;
+        if previous_md5.is_some() && previous_md5 == md5_file(&path).await {
+            let _ = tokio::fs::remove_file(&path).await;
+            return Ok(previous.name);
+        }
+    }
+    info!("Wrote metadata snapshot {}", path.display());
+    Ok(name)
+}
+
+/// Delete all but the newest `keep` snapshots
+pub async fn rotate_backups(backup_dir: &Path, keep: usize) -> Result<usize, String> {
+    let backups = list_backup_files(backup_dir).await?;
+    let mut removed = 0;
+    for backup in backups.iter().skip(keep) {
+        match tokio::fs::remove_file(backup_dir.join(&backup.name)).await {
+            Ok(()) => removed += 1,
+            Err(e) => warn!("Failed to remove old metadata snapshot {}: {}", backup.name, e),
+        }
+    }
+    Ok(removed)
+}
+
+/// 按配置的间隔快照元数据库，启动时先执行一次
+pub async fn run_backup_scheduler(db_pool: SqlitePool, config: SharedConfig) {
+    let hours = config.load().backup_interval_hours;
+    let mut interval = tokio::time::interval(Duration::from_secs(hours * 3600));
+    loop {
+        interval.tick().await;
+        let config = config.load();
+        if take_snapshot(&db_pool, &config.backup_dir).await.is_err() {
+            continue;
+        }
+        match rotate_backups(&config.backup_dir, config.backup_keep).await {
+            Ok(0) => {}
+            Ok(removed) => info!("Removed {} old metadata snapshots", removed),
+            Err(e) => error!("Failed to rotate metadata snapshots: {}", e),
+        }
+    }
+}
+
+fn quote_ident(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+async fn table_columns(conn: &mut SqliteConnection, schema: &str, table: &str) -> Result<Vec<String>, sqlx::Error> {
+    sqlx::query_scalar("SELECT name FROM pragma_table_info(?, ?)")
+        .bind(table)
+        .bind(schema)
+        .fetch_all(conn)
+        .await
+}
+
+#[derive(Serialize)]
+struct RestoreReport {
+    restored_from: String,
+    /// Snapshot of the database as it was before the restore
+    previous_snapshot: String,
+    rows: u64,
+    /// Tables created after the snapshot was taken; they are left empty
+    tables_not_in_backup: Vec<String>,
+}
+
+/// Replace the contents of every table with the attached `backup` database,
+/// in one transaction. Columns the snapshot doesn't have keep their defaults.
+async fn copy_from_backup(conn: &mut SqliteConnection) -> Result<(u64, Vec<String>), String> {
+    let check: Vec<String> = sqlx::query_scalar("PRAGMA backup.quick_check")
+        .fetch_all(&mut *conn)
+        .await
+        .map_err(|e| format!("Snapshot can't be read: {}", e))?;
+    if check != ["ok"] {
+        return Err(format!("Snapshot is damaged: {}", check.join("; ")));
+    }
+    let has_files: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM backup.sqlite_master WHERE type = 'table' AND name = 'upload_file_meta'")
+        .fetch_one(&mut *conn)
+        .await
+        .map_err(|e| e.to_string())?;
+    if has_files == 0 {
+        return Err("Not a nascraft metadata snapshot".to_string());
+    }
+
+    let result = async {
+        let mut tx = conn.begin().await?;
+        let tables: Vec<String> = sqlx::query_scalar(
+            "SELECT name FROM main.sqlite_master
+             WHERE type = 'table' AND name NOT LIKE 'sqlite_%' AND name NOT LIKE '_sqlx_%'
+             ORDER BY name"
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+
+        let (mut rows, mut missing) = (0, Vec::new());
+        for table in tables {
+            sqlx::query(&format!("DELETE FROM main.{}", quote_ident(&table))).execute(&mut *tx).await?;
+            let backup_columns = table_columns(&mut tx, "backup", &table).await?;
+            if backup_columns.is_empty() {
+                missing.push(table);
+                continue;
+            }
+            let columns: Vec<String> = table_columns(&mut tx, "main", &table)
+                .await?
+                .into_iter()
+                .filter(|c| backup_columns.contains(c))
+                .map(|c| quote_ident(&c))
+                .collect();
+            let columns = columns.join(", ");
+            let inserted = sqlx::query(&format!(
+                "INSERT INTO main.{table} ({columns}) SELECT {columns} FROM backup.{table}",
+                table = quote_ident(&table),
+                columns = columns,
+            ))
+            .execute(&mut *tx)
+            .await?;
+            rows += inserted.rows_affected();
+        }
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>((rows, missing))
+    }
+    .await;
+    result.map_err(|e| {
+        error!("Failed to restore metadata: {}", e);
+        format!("Failed to restore metadata: {}", e)
+    })
+}
+
+async fn restore_from_snapshot(db_pool: &SqlitePool, path: &Path) -> Result<(u64, Vec<String>), String> {
+    let mut conn = db_pool.acquire().await.map_err(|e| e.to_string())?;
+    // 快照内部的外键本身一致，逐表替换时暂时关闭检查
+    sqlx::query("PRAGMA foreign_keys = OFF").execute(&mut *conn).await.map_err(|e| e.to_string())?;
+    let attached = sqlx::query("ATTACH DATABASE ? AS backup")
+        .bind(snapshot_uri(path, "ro")?)
+        .execute(&mut *conn)
+        .await;
+    let result = match attached {
+        Ok(_) => {
+            let result = copy_from_backup(&mut conn).await;
+            if let Err(e) = sqlx::query("DETACH DATABASE backup").execute(&mut *conn).await {
+                warn!("Failed to detach metadata snapshot: {}", e);
+            }
+            result
+        }
+        Err(e) => Err(format!("Failed to open snapshot: {}", e)),
+    };
+    if let Err(e) = sqlx::query("PRAGMA foreign_keys = ON").execute(&mut *conn).await {
+        // 连接状态不可信，关闭而不是放回连接池
+        warn!("Failed to re-enable foreign keys, closing connection: {}", e);
+        let _ = conn.detach().close().await;
+    }
+    result
+}
+
+/// Snapshots of the metadata database, newest first. Unrestricted profiles only.
+pub async fn list_backups(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+) -> impl IntoResponse {
+    if let Err(resp) = ensure_unrestricted(&ctx, &client_addr).await {
+        return resp;
+    }
+    match list_backup_files(&ctx.config.load().backup_dir).await {
+        Ok(backups) => (StatusCode::OK, Json(ApiResponse::success(backups))).into_response(),
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, "LIST_BACKUPS_ERROR", e),
+    }
+}
+
+/// Take a snapshot now, outside the schedule
+pub async fn create_backup(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+) -> impl IntoResponse {
+    if let Err(resp) = ensure_unrestricted(&ctx, &client_addr).await {
+        return resp;
+    }
+    let config = ctx.config.load();
+    let name = match take_snapshot(&ctx.app_state.db_pool, &config.backup_dir).await {
+        Ok(name) => name,
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "BACKUP_ERROR", e),
+    };
+    if let Err(e) = rotate_backups(&config.backup_dir, config.backup_keep).await {
+        warn!("Failed to rotate metadata snapshots: {}", e);
+    }
+    match list_backup_files(&config.backup_dir).await {
+        Ok(backups) => match backups.into_iter().find(|b| b.name == name) {
+            Some(backup) => (StatusCode::CREATED, Json(ApiResponse::success(backup))).into_response(),
+            None => error_response(StatusCode::INTERNAL_SERVER_ERROR, "BACKUP_ERROR", "Snapshot disappeared after it was written".to_string()),
+        },
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, "LIST_BACKUPS_ERROR", e),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct RestoreRequest {
+    /// Name of a snapshot in the backup directory
+    backup: String,
+}
+
+/// Rebuild the metadata database from a snapshot. The current contents are
+/// snapshotted first, so a restore can itself be undone. Unrestricted profiles only.
+pub async fn restore_metadata(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    Json(req): Json<RestoreRequest>,
+) -> impl IntoResponse {
+    if let Err(resp) = ensure_unrestricted(&ctx, &client_addr).await {
+        return resp;
+    }
+    let db_pool = &ctx.app_state.db_pool;
+    let backup_dir: PathBuf = ctx.config.load().backup_dir.clone();
+
+    if !is_backup_name(&req.backup) {
+        return error_response(StatusCode::BAD_REQUEST, "INVALID_BACKUP", format!("'{}' is not a snapshot name", req.backup));
+    }
+    let path = backup_dir.join(&req.backup);
+    if !tokio::fs::try_exists(&path).await.unwrap_or(false) {
+        return error_response(StatusCode::NOT_FOUND, "BACKUP_NOT_FOUND", format!("Snapshot {} not found", req.backup));
+    }
+
+    let previous_snapshot = match take_snapshot(db_pool, &backup_dir).await {
+        Ok(name) => name,
+        Err(e) => return error_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "BACKUP_ERROR",
+            format!("Not restoring, the current metadata couldn't be saved first: {}", e),
+        ),
+    };
+    let (rows, tables_not_in_backup) = match restore_from_snapshot(db_pool, &path).await {
+        Ok(restored) => restored,
+        Err(e) => return error_response(StatusCode::UNPROCESSABLE_ENTITY, "RESTORE_ERROR", e),
+    };
+    warn!("Restored metadata from {} ({} rows), previous contents saved as {}", req.backup, rows, previous_snapshot);
+
+    // 快照中的 system_config 和未完成的任务需要重新加载
+    let mut config = (*ctx.config.load()).clone();
+    match config.load_system_config(db_pool).await {
+        Ok(()) => ctx.config.store(config),
+        Err(e) => warn!("Keeping the current chunk size after restore: {}", e),
+    }
+    if let Err(e) = fail_interrupted_jobs(db_pool).await {
+        warn!("Failed to mark interrupted jobs: {}", e);
+    }
+
+    (StatusCode::OK, Json(ApiResponse::success(RestoreReport {
+        restored_from: req.backup,
+        previous_snapshot,
+        rows,
+        tables_not_in_backup,
+    }))).into_response()
+}
@@ -0,0 +1,348 @@
+//! Serving photos and videos without their EXIF, GPS and other embedded
+//! metadata, for share links that leave the household. Images are rewritten
+//! in memory without re-encoding; videos are remuxed by ffmpeg while they are
+//! sent.
+
+use axum::{
+    body::{Body, Bytes},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use log::error;
+use std::net::SocketAddr;
+use std::process::Stdio;
+use tokio::io::AsyncReadExt;
+use crate::content_range::{parse_range, RangeRequest};
+use crate::context::AppContext;
+use crate::filename::content_disposition;
+use crate::paths::long_path;
+use crate::traffic::{client_principal, record_traffic};
+use crate::upload_dao::UploadedFile;
+
+/// Images are scrubbed in memory, so larger ones are refused
+const MAX_IMAGE_BYTES: u64 = 256 * 1024 * 1024;
+
+/// EXIF orientation tag, the only metadata kept so photos aren't shown sideways
+const ORIENTATION_TAG: u16 = 0x0112;
+
+fn read_u16(data: &[u8], offset: usize, big_endian: bool) -> Option<u16> {
+    let bytes: [u8; 2] = data.get(offset..offset + 2)?.try_into().ok()?;
+    Some(if big_endian { u16::from_be_bytes(bytes) } else { u16::from_le_bytes(bytes) })
+}
+
+fn read_u32(data: &[u8], offset: usize, big_endian: bool) -> Option<u32> {
+    let bytes: [u8; 4] = data.get(offset..offset + 4)?.try_into().ok()?;
+    Some(if big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) })
+}
+
+/// Orientation from the first IFD of an `Exif\0\0` APP1 payload
+fn exif_orientation(payload: &[u8]) -> Option<u16> {
+    let tiff = payload.strip_prefix(b"Exif\0\0")?;
+    let big_endian = match tiff.get(..2)? {
+        b"MM" => true,
+        b"II" => false,
+        _ => return None,
+    };
+    let ifd = read_u32(tiff, 4, big_endian)? as usize;
+    let entries = read_u16(tiff, ifd, big_endian)? as usize;
+    (0..entries)
+        .map(|i| ifd + 2 + i * 12)
+        .find(|&entry| read_u16(tiff, entry, big_endian) == Some(ORIENTATION_TAG))
+        .and_then(|entry| read_u16(tiff, entry + 8, big_endian))
+        .filter(|orientation| (2..=8).contains(orientation))
+}
+
+/// APP1 segment holding nothing but the orientation
+fn orientation_segment(orientation: u16) -> Vec<u8> {
+    let mut payload = b"Exif\0\0MM\0\x2a\0\0\0\x08\0\x01".to_vec();
+    payload.extend_from_slice(&ORIENTATION_TAG.to_be_bytes());
+    // SHORT, one value, padded to four bytes, then no next IFD
+    payload.extend_from_slice(&[0, 3, 0, 0, 0, 1]);
+    payload.extend_from_slice(&orientation.to_be_bytes());
+    payload.extend_from_slice(&[0; 6]);
+    let mut segment = vec![0xFF, 0xE1];
+    segment.extend_from_slice(&(payload.len() as u16 + 2).to_be_bytes());
+    segment.extend_from_slice(&payload);
+    segment
+}
+
+/// Whether a JPEG segment only describes how to decode the image. APP0 (JFIF),
+/// ICC profiles in APP2 and APP14 (Adobe colour transform) are kept; EXIF and
+/// XMP (APP1), IPTC (APP13), comments and the other application segments go.
+fn keep_jpeg_segment(marker: u8, payload: &[u8]) -> bool {
+    match marker {
+        0xE0 | 0xEE => true,
+        0xE2 => payload.starts_with(b"ICC_PROFILE\0"),
+        0xE1 | 0xE3..=0xED | 0xEF | 0xFE => false,
+        _ => true,
+    }
+}
+
+/// Length of the entropy-coded data starting at `data[0]`, up to the next marker
+fn entropy_len(data: &[u8]) -> usize {
+    let mut i = 0;
+    while i + 1 < data.len() {
+        // 0xFF00 是转义的数据字节，0xFFD0-0xFFD7 是复位标记，都属于扫描数据
+        if data[i] == 0xFF && data[i + 1] != 0 && !(0xD0..=0xD7).contains(&data[i + 1]) {
+            return i;
+        }
+        i += 1;
+    }
+    data.len()
+}
+
+/// The JPEG without metadata segments and anything after its end, where
+/// phones append previews and depth maps with their own EXIF
+fn scrub_jpeg(data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut output = vec![0xFF, 0xD8];
+    let mut orientation = None;
+    let mut pos = 2;
+    loop {
+        // 标记前可以有填充的 0xFF
+        while data.get(pos) == Some(&0xFF) && data.get(pos + 1) == Some(&0xFF) {
+            pos += 1;
+        }
+        let marker = match data.get(pos..pos + 2) {
+            Some([0xFF, marker]) => *marker,
+            _ => return Err("Malformed JPEG: expected a marker".to_string()),
+        };
+        if marker == 0xD9 {
+            output.extend_from_slice(&[0xFF, 0xD9]);
+            break;
+        }
+        let length = read_u16(data, pos + 2, true).ok_or("Malformed JPEG: truncated segment")? as usize;
+        let end = pos + 2 + length;
+        let payload = data.get(pos + 4..end).ok_or("Malformed JPEG: truncated segment")?;
+        if marker == 0xE1 && orientation.is_none() {
+            orientation = exif_orientation(payload);
+        }
+        if keep_jpeg_segment(marker, payload) {
+            // 方向写在 JFIF 段之后、其余段之前
+            if marker != 0xE0 {
+                if let Some(orientation) = orientation.take() {
+                    output.extend_from_slice(&orientation_segment(orientation));
+                }
+            }
+            output.extend_from_slice(&data[pos..end]);
+        }
+        pos = end;
+        if marker == 0xDA {
+            let len = entropy_len(&data[pos..]);
+            output.extend_from_slice(&data[pos..pos + len]);
+            pos += len;
+            if pos >= data.len() {
+                return Err("Malformed JPEG: missing end of image".to_string());
+            }
+        }
+    }
+    Ok(output)
+}
+
+/// The PNG without EXIF, text and timestamp chunks
+fn scrub_png(data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut output = data[..8].to_vec();
+    let mut pos = 8;
+    while pos < data.len() {
+        let length = read_u32(data, pos, true).ok_or("Malformed PNG: truncated chunk")? as usize;
+        let end = pos + 12 + length;
+        let chunk = data.get(pos..end).ok_or("Malformed PNG: truncated chunk")?;
+        if !matches!(&chunk[4..8], b"eXIf" | b"tEXt" | b"zTXt" | b"iTXt" | b"tIME") {
+            output.extend_from_slice(chunk);
+        }
+        pos = end;
+        if &chunk[4..8] == b"IEND" {
+            break;
+        }
+    }
+    Ok(output)
+}
+
+/// The WebP without its EXIF and XMP chunks
+fn scrub_webp(data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut output = data[..12].to_vec();
+    let mut pos = 12;
+    while pos + 8 <= data.len() {
+        let length = read_u32(data, pos + 4, false).ok_or("Malformed WebP: truncated chunk")? as usize;
+        let end = (pos + 8 + length + length % 2).min(data.len());
+        let mut chunk = data.get(pos..end).ok_or("Malformed WebP: truncated chunk")?.to_vec();
+        match &chunk[..4] {
+            b"EXIF" | b"XMP " => {}
+            fourcc => {
+                if fourcc == b"VP8X" && chunk.len() > 8 {
+                    // 清除 VP8X 中声明 EXIF 与 XMP 存在的标志位
+                    chunk[8] &= !0x0C;
+                }
+                output.extend_from_slice(&chunk);
+            }
+        }
+        pos = end;
+    }
+    let riff_size = (output.len() - 8) as u32;
+    output[4..8].copy_from_slice(&riff_size.to_le_bytes());
+    Ok(output)
+}
+
+/// The image without metadata, or None when it isn't a JPEG, PNG or WebP
+fn scrub_image(data: &[u8]) -> Option<Result<Vec<u8>, String>> {
+    if data.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Some(scrub_jpeg(data))
+    } else if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some(scrub_png(data))
+    } else if data.len() >= 12 && data.starts_with(b"RIFF") && &data[8..12] == b"WEBP" {
+        Some(scrub_webp(data))
+    } else {
+        None
+    }
+}
+
+/// ffmpeg output options that keep a video container streamable to a pipe
+fn video_output_args(filename: &str) -> Option<&'static [&'static str]> {
+    let extension = std::path::Path::new(filename).extension()?.to_str()?.to_ascii_lowercase();
+    match extension.as_str() {
+        "mp4" | "m4v" | "mov" | "3gp" => Some(&["-f", "mp4", "-movflags", "frag_keyframe+empty_moov"]),
+        "mkv" => Some(&["-f", "matroska"]),
+        "webm" => Some(&["-f", "webm"]),
+        _ => None,
+    }
+}
+
+/// Share downloads answer errors in plain text rather than `ApiResponse` JSON
+fn text_error(status: StatusCode, message: &str) -> Response {
+    (status, message.to_string()).into_response()
+}
+
+async fn scrubbed_image(ctx: &AppContext, client_addr: &SocketAddr, record: &UploadedFile, headers: &HeaderMap) -> Response {
+    let path = long_path(std::path::Path::new(&record.file_path));
+    let data = match tokio::fs::metadata(&path).await {
+        Ok(metadata) if metadata.len() > MAX_IMAGE_BYTES => {
+            return text_error(StatusCode::PAYLOAD_TOO_LARGE, "Image is too large to remove its metadata");
+        }
+        Ok(_) => tokio::fs::read(&path).await,
+        Err(e) => Err(e),
+    };
+    let data = match data {
+        Ok(data) => data,
+        Err(e) => {
+            error!("Failed to read {}: {}", record.file_path, e);
+            return text_error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to read file");
+        }
+    };
+    let data = match scrub_image(&data) {
+        Some(Ok(data)) => data,
+        Some(Err(e)) => {
+            error!("Failed to remove metadata from {}: {}", record.file_path, e);
+            return text_error(StatusCode::UNPROCESSABLE_ENTITY, "Failed to remove the image's metadata");
+        }
+        None => return text_error(StatusCode::UNSUPPORTED_MEDIA_TYPE, "Metadata can only be removed from JPEG, PNG and WebP images"),
+    };
+
+    let total = data.len() as u64;
+    let range = headers
+        .get(header::RANGE)
+        .and_then(|h| h.to_str().ok())
+        .map_or(RangeRequest::Full, |h| parse_range(h, total));
+    let (status, start, end) = match range {
+        RangeRequest::Full => (StatusCode::OK, 0, total),
+        RangeRequest::Partial { start, end } => (StatusCode::PARTIAL_CONTENT, start, end + 1),
+        RangeRequest::Unsatisfiable => return (
+            StatusCode::RANGE_NOT_SATISFIABLE,
+            [(header::CONTENT_RANGE, format!("bytes */{}", total))],
+        ).into_response(),
+    };
+    record_traffic(&ctx.app_state.db_pool, &client_principal(client_addr), 0, end - start).await;
+
+    let download_name = record.original_filename.as_deref().unwrap_or(&record.filename);
+    let mut response = (
+        status,
+        [
+            (header::CONTENT_TYPE, "application/octet-stream".to_string()),
+            (header::CONTENT_DISPOSITION, content_disposition(download_name)),
+            (header::ACCEPT_RANGES, "bytes".to_string()),
+        ],
+        data[start as usize..end as usize].to_vec(),
+    )
+        .into_response();
+    if status == StatusCode::PARTIAL_CONTENT {
+        if let Ok(value) = format!("bytes {}-{}/{}", start, end - 1, total).parse() {
+            response.headers_mut().insert(header::CONTENT_RANGE, value);
+        }
+    }
+    response
+}
+
+/// The video remuxed by ffmpeg without global, stream and chapter metadata.
+/// The length isn't known up front, so ranges aren't supported.
+async fn scrubbed_video(ctx: &AppContext, client_addr: &SocketAddr, record: &UploadedFile) -> Response {
+    let Some(args) = video_output_args(&record.filename) else {
+        return text_error(StatusCode::UNSUPPORTED_MEDIA_TYPE, "Metadata can only be removed from MP4, MOV, MKV and WebM videos");
+    };
+    let config = ctx.config.load();
+    let child = tokio::process::Command::new(&config.ffmpeg_path)
+        .arg("-nostdin")
+        .arg("-i")
+        .arg(long_path(std::path::Path::new(&record.file_path)))
+        .args(["-map", "0", "-c", "copy", "-map_metadata", "-1", "-map_chapters", "-1", "-fflags", "+bitexact"])
+        .args(args)
+        .arg("pipe:1")
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        // 客户端断开时结束 ffmpeg
+        .kill_on_drop(true)
+        .spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(e) => {
+            error!("Failed to run {}: {}", config.ffmpeg_path, e);
+            return text_error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to remove the video's metadata");
+        }
+    };
+    let Some(stdout) = child.stdout.take() else {
+        return text_error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to remove the video's metadata");
+    };
+
+    let db_pool = ctx.app_state.db_pool.clone();
+    let principal = client_principal(client_addr);
+    let buffer_size = config.download_buffer_size;
+    let body = Body::from_stream(futures::stream::unfold((child, stdout, 0u64), move |(child, mut stdout, sent)| {
+        let (db_pool, principal) = (db_pool.clone(), principal.clone());
+        async move {
+            let mut buf = vec![0u8; buffer_size];
+            match stdout.read(&mut buf).await {
+                Ok(0) => {
+                    record_traffic(&db_pool, &principal, 0, sent).await;
+                    None
+                }
+                Ok(n) => {
+                    buf.truncate(n);
+                    Some((Ok::<_, std::io::Error>(Bytes::from(buf)), (child, stdout, sent + n as u64)))
+                }
+                Err(e) => Some((Err(e), (child, stdout, sent))),
+            }
+        }
+    }));
+
+    let download_name = record.original_filename.as_deref().unwrap_or(&record.filename);
+    (
+        StatusCode::OK,
+        [
+            (header::CONTENT_TYPE, "application/octet-stream".to_string()),
+            (header::CONTENT_DISPOSITION, content_disposition(download_name)),
+            (header::ACCEPT_RANGES, "none".to_string()),
+        ],
+        body,
+    )
+        .into_response()
+}
+
+/// Serve a stored photo or video without its metadata. Other files carry no
+/// camera metadata and are left to the caller, so this returns None for them.
+/// Access checks are up to the caller.
+pub async fn scrubbed_response(ctx: &AppContext, client_addr: &SocketAddr, record: &UploadedFile, headers: &HeaderMap) -> Option<Response> {
+    let mime = mime_guess::from_path(&record.filename).first()?;
+    match mime.type_().as_str() {
+        "image" => Some(scrubbed_image(ctx, client_addr, record, headers).await),
+        "video" => Some(scrubbed_video(ctx, client_addr, record).await),
+        _ => None,
+    }
+}
@@ -0,0 +1,393 @@
+//! Simulated UPnP media renderers, to try the `/api/dlna` endpoints in CI and
+//! local development without a TV. Each name in `NASCRAFT_MOCK_RENDERERS`
+//! becomes a MediaRenderer that answers SSDP searches through the SSDP
+//! responder and is served by this server, so discovery and control go
+//! through the same SOAP code as with real devices.
+//!
+//! Playback is simulated: every media lasts `TRACK_SECONDS`, after which the
+//! renderer moves on to the URI set with SetNextAVTransportURI or stops. Like
+//! a picky TV, a renderer refuses media types missing from its protocolInfo
+//! and media it can't fetch.
+
+use axum::{
+    extract::{Path, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Router,
+};
+use log::{error, info};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::net::UdpSocket;
+use crate::config::AppConfig;
+use crate::helper::xml_escape;
+use crate::renderer::{format_duration, parse_duration};
+
+const MEDIA_RENDERER_TYPE: &str = "urn:schemas-upnp-org:device:MediaRenderer:1";
+const AV_TRANSPORT_TYPE: &str = "urn:schemas-upnp-org:service:AVTransport:1";
+const CONNECTION_MANAGER_TYPE: &str = "urn:schemas-upnp-org:service:ConnectionManager:1";
+
+/// How long every simulated media plays
+const TRACK_SECONDS: f64 = 60.0;
+
+/// Formats the mock renderers accept
+const SINK_PROTOCOL_INFO: &[&str] = &[
+    "http-get:*:video/mp4:*",
+    "http-get:*:video/x-matroska:*",
+    "http-get:*:audio/mpeg:*",
+    "http-get:*:audio/flac:*",
+    "http-get:*:audio/mp4:*",
+    "http-get:*:image/jpeg:*",
+    "http-get:*:image/png:*",
+];
+
+/// Stable UDN of the renderer at `index` in `NASCRAFT_MOCK_RENDERERS`
+fn udn(index: usize) -> String {
+    // 6d6f636b 即 "mock"
+    format!("uuid:6d6f636b-0000-4000-8000-{:012x}", index + 1)
+}
+
+fn description_path(index: usize) -> String {
+    format!("/mock-renderer/{}/desc.xml", index)
+}
+
+/// Answer an M-SEARCH for media renderers for each mock renderer
+pub async fn answer_search(sock: &UdpSocket, cfg: &AppConfig, st: &str, local_ipv4: Ipv4Addr, peer: SocketAddr) {
+    let st = match st {
+        "ssdp:all" | MEDIA_RENDERER_TYPE => MEDIA_RENDERER_TYPE,
+        "upnp:rootdevice" => "upnp:rootdevice",
+        _ => return,
+    };
+    for index in 0..cfg.mock_renderers.len() {
+        let location = format!("http://{}:{}{}", local_ipv4, cfg.server_port, description_path(index));
+        let resp = format!(
+            "HTTP/1.1 200 OK\r\nCACHE-CONTROL: max-age=120\r\nEXT:\r\nLOCATION: {}\r\nSERVER: nascraft/0.1 UPnP/1.1\r\nST: {}\r\nUSN: {}::{}\r\n\r\n",
+            location, st, udn(index), st
+        );
+        if let Err(e) = sock.send_to(resp.as_bytes(), peer).await {
+            error!("SSDP send of mock renderer failed: peer={}, err={}", peer, e);
+        }
+    }
+}
+
+/// State of the AVTransport of one renderer
+#[derive(Default)]
+struct Transport {
+    uri: String,
+    metadata: String,
+    next: Option<(String, String)>,
+    playing_since: Option<Instant>,
+    paused: bool,
+    /// Position when playback last started, paused or seeked
+    offset: f64,
+}
+
+impl Transport {
+    fn position(&self) -> f64 {
+        let played = self.playing_since.map_or(0.0, |since| since.elapsed().as_secs_f64());
+        (self.offset + played).min(TRACK_SECONDS)
+    }
+
+    /// Move on to the next media, or stop, once the current one has ended
+    fn advance(&mut self) {
+        let Some(since) = self.playing_since else {
+            return;
+        };
+        let played = self.offset + since.elapsed().as_secs_f64();
+        if played < TRACK_SECONDS {
+            return;
+        }
+        match self.next.take() {
+            Some((uri, metadata)) => {
+                // 无缝切换：下一首从上一首结束的时刻开始计时
+                let overrun = played - TRACK_SECONDS;
+                self.uri = uri;
+                self.metadata = metadata;
+                self.offset = overrun.min(TRACK_SECONDS);
+                self.playing_since = Some(Instant::now());
+            }
+            None => {
+                self.playing_since = None;
+                self.offset = 0.0;
+            }
+        }
+    }
+
+    fn state(&self) -> &'static str {
+        match (self.playing_since, self.paused, self.uri.is_empty()) {
+            (Some(_), _, _) => "PLAYING",
+            (None, true, _) => "PAUSED_PLAYBACK",
+            (None, false, true) => "NO_MEDIA_PRESENT",
+            (None, false, false) => "STOPPED",
+        }
+    }
+
+    fn pause(&mut self) {
+        self.offset = self.position();
+        self.playing_since = None;
+        self.paused = true;
+    }
+}
+
+struct MockRenderer {
+    name: String,
+    transport: Mutex<Transport>,
+}
+
+pub struct MockRenderers {
+    renderers: Vec<MockRenderer>,
+    http: reqwest::Client,
+}
+
+/// A UPnP error, answered as a SOAP fault
+struct Fault(u16, &'static str);
+
+fn xml_unescape(value: &str) -> String {
+    value
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// The text of the `<name>` argument in a SOAP request body
+fn argument(body: &str, name: &str) -> Option<String> {
+    let open = format!("<{}>", name);
+    let close = format!("</{}>", name);
+    let start = body.find(&open)? + open.len();
+    let end = start + body[start..].find(&close)?;
+    Some(xml_unescape(&body[start..end]))
+}
+
+/// The MIME type in the protocolInfo of DIDL-Lite metadata
+fn metadata_mime_type(metadata: &str) -> Option<String> {
+    let start = metadata.find("protocolInfo=\"")? + "protocolInfo=\"".len();
+    let protocol_info = &metadata[start..start + metadata[start..].find('"')?];
+    protocol_info.split(':').nth(2).map(|mime| mime.to_lowercase())
+}
+
+impl MockRenderers {
+    /// Check a URI set by a control point the way a renderer would: it must
+    /// be a supported type and load
+    async fn check_media(&self, uri: &str, metadata: &str) -> Result<(), Fault> {
+        if let Some(mime_type) = metadata_mime_type(metadata) {
+            if !SINK_PROTOCOL_INFO.iter().any(|entry| entry.split(':').nth(2) == Some(mime_type.as_str())) {
+                return Err(Fault(714, "Illegal MIME-type"));
+            }
+        }
+        let response = self.http
+            .get(uri)
+            .header(header::RANGE, "bytes=0-1023")
+            .send()
+            .await
+            .map_err(|_| Fault(716, "Resource not found"))?;
+        if !response.status().is_success() {
+            return Err(Fault(716, "Resource not found"));
+        }
+        Ok(())
+    }
+
+    async fn av_transport(&self, renderer: &MockRenderer, action: &str, body: &str) -> Result<Vec<(&'static str, String)>, Fault> {
+        match action {
+            "SetAVTransportURI" => {
+                let uri = argument(body, "CurrentURI").unwrap_or_default();
+                let metadata = argument(body, "CurrentURIMetaData").unwrap_or_default();
+                self.check_media(&uri, &metadata).await?;
+                let mut transport = renderer.transport.lock().unwrap();
+                *transport = Transport { uri, metadata, ..Transport::default() };
+                Ok(Vec::new())
+            }
+            "SetNextAVTransportURI" => {
+                let uri = argument(body, "NextURI").unwrap_or_default();
+                let metadata = argument(body, "NextURIMetaData").unwrap_or_default();
+                self.check_media(&uri, &metadata).await?;
+                renderer.transport.lock().unwrap().next = Some((uri, metadata));
+                Ok(Vec::new())
+            }
+            _ => {
+                let mut transport = renderer.transport.lock().unwrap();
+                transport.advance();
+                Self::transport_action(&mut transport, action, body)
+            }
+        }
+    }
+
+    fn transport_action(transport: &mut Transport, action: &str, body: &str) -> Result<Vec<(&'static str, String)>, Fault> {
+        match action {
+            "Play" if transport.uri.is_empty() => Err(Fault(701, "Transition not available")),
+            "Play" => {
+                if transport.playing_since.is_none() {
+                    transport.playing_since = Some(Instant::now());
+                    transport.paused = false;
+                }
+                Ok(Vec::new())
+            }
+            "Pause" if transport.playing_since.is_none() => Err(Fault(701, "Transition not available")),
+            "Pause" => {
+                transport.pause();
+                Ok(Vec::new())
+            }
+            "Stop" => {
+                transport.playing_since = None;
+                transport.paused = false;
+                transport.offset = 0.0;
+                Ok(Vec::new())
+            }
+            "Seek" => {
+                if argument(body, "Unit").as_deref() != Some("REL_TIME") {
+                    return Err(Fault(710, "Seek mode not supported"));
+                }
+                let target = argument(body, "Target").as_deref().and_then(parse_duration);
+                match target {
+                    Some(target) if target < TRACK_SECONDS && !transport.uri.is_empty() => {
+                        transport.offset = target;
+                        if transport.playing_since.is_some() {
+                            transport.playing_since = Some(Instant::now());
+                        }
+                        Ok(Vec::new())
+                    }
+                    _ => Err(Fault(711, "Illegal seek target")),
+                }
+            }
+            "GetTransportInfo" => Ok(vec![
+                ("CurrentTransportState", transport.state().to_string()),
+                ("CurrentTransportStatus", "OK".to_string()),
+                ("CurrentSpeed", "1".to_string()),
+            ]),
+            "GetPositionInfo" => {
+                let (duration, position) = match transport.uri.is_empty() {
+                    true => ("0:00:00".to_string(), "0:00:00".to_string()),
+                    false => (format_duration(TRACK_SECONDS), format_duration(transport.position())),
+                };
+                Ok(vec![
+                    ("Track", if transport.uri.is_empty() { "0" } else { "1" }.to_string()),
+                    ("TrackDuration", duration),
+                    ("TrackMetaData", transport.metadata.clone()),
+                    ("TrackURI", transport.uri.clone()),
+                    ("RelTime", position.clone()),
+                    ("AbsTime", position),
+                    ("RelCount", "2147483647".to_string()),
+                    ("AbsCount", "2147483647".to_string()),
+                ])
+            }
+            _ => Err(Fault(401, "Invalid Action")),
+        }
+    }
+}
+
+fn soap_response(service_type: &str, action: &str, output: &[(&'static str, String)]) -> Response {
+    let arguments: String = output
+        .iter()
+        .map(|(name, value)| format!("<{}>{}</{}>", name, xml_escape(value), name))
+        .collect();
+    let body = format!(
+        concat!(
+            r#"<?xml version="1.0" encoding="utf-8"?>"#,
+            r#"<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/" s:encodingStyle="http://schemas.xmlsoap.org/soap/encoding/">"#,
+            r#"<s:Body><u:{}Response xmlns:u="{}">{}</u:{}Response></s:Body></s:Envelope>"#
+        ),
+        action, service_type, arguments, action
+    );
+    ([(header::CONTENT_TYPE, "text/xml; charset=\"utf-8\"")], body).into_response()
+}
+
+fn fault_response(fault: Fault) -> Response {
+    let Fault(code, description) = fault;
+    let body = format!(
+        concat!(
+            r#"<?xml version="1.0" encoding="utf-8"?>"#,
+            r#"<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/" s:encodingStyle="http://schemas.xmlsoap.org/soap/encoding/">"#,
+            r#"<s:Body><s:Fault><faultcode>s:Client</faultcode><faultstring>UPnPError</faultstring><detail>"#,
+            r#"<UPnPError xmlns="urn:schemas-upnp-org:control-1-0"><errorCode>{}</errorCode><errorDescription>{}</errorDescription></UPnPError>"#,
+            r#"</detail></s:Fault></s:Body></s:Envelope>"#
+        ),
+        code, description
+    );
+    // UPnP 规定错误以 500 返回
+    (StatusCode::INTERNAL_SERVER_ERROR, [(header::CONTENT_TYPE, "text/xml; charset=\"utf-8\"")], body).into_response()
+}
+
+async fn device_description(State(mocks): State<Arc<MockRenderers>>, Path(index): Path<usize>) -> Response {
+    let Some(renderer) = mocks.renderers.get(index) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let service = |service_type: &str, name: &str| {
+        format!(
+            "<service><serviceType>{}</serviceType><serviceId>urn:upnp-org:serviceId:{}</serviceId><SCPDURL>/mock-renderer/{}/{}.xml</SCPDURL><controlURL>/mock-renderer/{}/control/{}</controlURL><eventSubURL>/mock-renderer/{}/event/{}</eventSubURL></service>",
+            service_type, name, index, name, index, name, index, name
+        )
+    };
+    let xml = format!(
+        concat!(
+            r#"<?xml version="1.0"?>"#,
+            r#"<root xmlns="urn:schemas-upnp-org:device-1-0"><specVersion><major>1</major><minor>0</minor></specVersion><device>"#,
+            "<deviceType>{}</deviceType><friendlyName>{}</friendlyName><manufacturer>Nascraft</manufacturer>",
+            "<modelName>Mock Renderer</modelName><UDN>{}</UDN><serviceList>{}{}</serviceList></device></root>"
+        ),
+        MEDIA_RENDERER_TYPE,
+        xml_escape(&renderer.name),
+        udn(index),
+        service(AV_TRANSPORT_TYPE, "AVTransport"),
+        service(CONNECTION_MANAGER_TYPE, "ConnectionManager"),
+    );
+    ([(header::CONTENT_TYPE, "text/xml; charset=utf-8")], xml).into_response()
+}
+
+async fn control(
+    State(mocks): State<Arc<MockRenderers>>,
+    Path((index, service)): Path<(usize, String)>,
+    headers: HeaderMap,
+    body: String,
+) -> Response {
+    let Some(renderer) = mocks.renderers.get(index) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    // SOAPAction: "urn:schemas-upnp-org:service:AVTransport:1#Play"
+    let action = headers
+        .get("soapaction")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim_matches('"').rsplit_once('#'))
+        .map(|(_, action)| action.to_string())
+        .unwrap_or_default();
+
+    let (service_type, result) = match service.as_str() {
+        "AVTransport" => (AV_TRANSPORT_TYPE, mocks.av_transport(renderer, &action, &body).await),
+        "ConnectionManager" => (CONNECTION_MANAGER_TYPE, match action.as_str() {
+            "GetProtocolInfo" => Ok(vec![("Source", String::new()), ("Sink", SINK_PROTOCOL_INFO.join(","))]),
+            "GetCurrentConnectionIDs" => Ok(vec![("ConnectionIDs", "0".to_string())]),
+            _ => Err(Fault(401, "Invalid Action")),
+        }),
+        _ => return StatusCode::NOT_FOUND.into_response(),
+    };
+    match result {
+        Ok(output) => soap_response(service_type, &action, &output),
+        Err(fault) => {
+            info!("Mock renderer {} refused {}: {} {}", renderer.name, action, fault.0, fault.1);
+            fault_response(fault)
+        }
+    }
+}
+
+/// Serve the device descriptions and control endpoints of the mock renderers
+pub fn mock_renderer_routes(router: Router, cfg: &AppConfig) -> Router {
+    if cfg.mock_renderers.is_empty() {
+        return router;
+    }
+    info!("Simulating renderers: {}", cfg.mock_renderers.join(", "));
+    let mocks = Arc::new(MockRenderers {
+        renderers: cfg.mock_renderers
+            .iter()
+            .map(|name| MockRenderer { name: name.clone(), transport: Mutex::default() })
+            .collect(),
+        http: reqwest::Client::new(),
+    });
+    router.merge(
+        Router::new()
+            .route("/mock-renderer/:index/desc.xml", get(device_description))
+            .route("/mock-renderer/:index/control/:service", post(control))
+            .with_state(mocks),
+    )
+}
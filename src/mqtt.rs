@@ -0,0 +1,346 @@
+//! Home Assistant integration over MQTT. Server state and DLNA renderers are
+//! published together with discovery configs, so they show up as entities;
+//! server events go to `<topic>/events` for automations.
+
+use log::{error, info, warn};
+use rumqttc::{AsyncClient, Event, LastWill, MqttOptions, Packet, QoS};
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use crate::config::AppConfig;
+use crate::context::AppContext;
+use crate::disk_space::disk_space;
+use crate::display_remote::DeviceMessage;
+use crate::events::ServerEvent;
+use crate::paths::UPLOADS_DIR;
+
+/// State is republished this often even when nothing happened
+const STATE_INTERVAL: Duration = Duration::from_secs(30);
+/// An upload counts as active while it has received a chunk this recently
+const ACTIVE_UPLOAD_WINDOW: Duration = Duration::from_secs(120);
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+const KEEP_ALIVE: Duration = Duration::from_secs(30);
+
+/// A sensor of the NAS device; its value is taken from the JSON on the state topic
+struct Sensor {
+    component: &'static str,
+    object_id: &'static str,
+    name: &'static str,
+    value_template: &'static str,
+    unit: Option<&'static str>,
+    device_class: Option<&'static str>,
+    icon: Option<&'static str>,
+    /// Only announced when free disk space can be read on this platform
+    needs_disk: bool,
+}
+
+const SENSORS: &[Sensor] = &[
+    Sensor {
+        component: "sensor",
+        object_id: "active_uploads",
+        name: "Active uploads",
+        value_template: "{{ value_json.active_uploads }}",
+        unit: None,
+        device_class: None,
+        icon: Some("mdi:upload"),
+        needs_disk: false,
+    },
+    Sensor {
+        component: "sensor",
+        object_id: "files",
+        name: "Files",
+        value_template: "{{ value_json.files }}",
+        unit: None,
+        device_class: None,
+        icon: Some("mdi:file-multiple"),
+        needs_disk: false,
+    },
+    Sensor {
+        component: "sensor",
+        object_id: "disk_free",
+        name: "Disk free",
+        value_template: "{{ (value_json.disk_free_bytes / 1073741824) | round(1) }}",
+        unit: Some("GiB"),
+        device_class: Some("data_size"),
+        icon: None,
+        needs_disk: true,
+    },
+    Sensor {
+        component: "sensor",
+        object_id: "disk_used",
+        name: "Disk used",
+        value_template: "{{ value_json.disk_used_percent }}",
+        unit: Some("%"),
+        device_class: None,
+        icon: Some("mdi:harddisk"),
+        needs_disk: true,
+    },
+    Sensor {
+        component: "binary_sensor",
+        object_id: "low_disk_space",
+        name: "Low disk space",
+        value_template: "{{ 'ON' if value_json.low_disk_space else 'OFF' }}",
+        unit: None,
+        device_class: Some("problem"),
+        icon: None,
+        needs_disk: true,
+    },
+];
+
+/// Lowercase letters, digits and underscores, as Home Assistant expects in IDs
+fn object_id(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '_' })
+        .collect()
+}
+
+/// Playback state of a renderer as reported by the media server
+fn playback_state(renderer: &DeviceMessage) -> &'static str {
+    match renderer.state.playback {
+        1 => "playing",
+        2 => "paused",
+        _ => "idle",
+    }
+}
+
+struct Publisher {
+    client: AsyncClient,
+    config: Arc<AppConfig>,
+    /// Node ID in discovery topics and prefix of unique IDs
+    node_id: String,
+    /// Renderers whose discovery config has been published, by object ID
+    renderers: HashSet<String>,
+}
+
+impl Publisher {
+    fn topic(&self, suffix: &str) -> String {
+        format!("{}/{}", self.config.mqtt_topic, suffix)
+    }
+
+    fn discovery_topic(&self, component: &str, object_id: &str) -> String {
+        format!("{}/{}/{}/{}/config", self.config.mqtt_discovery_prefix, component, self.node_id, object_id)
+    }
+
+    fn publish(&self, topic: String, retain: bool, payload: String) {
+        if let Err(e) = self.client.try_publish(&topic, QoS::AtLeastOnce, retain, payload) {
+            warn!("Failed to publish to MQTT topic {}: {}", topic, e);
+        }
+    }
+
+    fn device(&self) -> Value {
+        let mut device = json!({
+            "identifiers": [self.node_id],
+            "name": self.config.mdns_instance_name,
+            "manufacturer": "nascraft",
+            "model": "NAS",
+            "sw_version": env!("CARGO_PKG_VERSION"),
+        });
+        if let Some(url) = &self.config.public_url {
+            device["configuration_url"] = json!(url);
+        }
+        device
+    }
+
+    /// Discovery configs, availability and current state, sent on every (re)connect
+    async fn announce(&mut self, ctx: &AppContext) {
+        let has_disk = disk_space(Path::new(UPLOADS_DIR)).is_ok();
+        for sensor in SENSORS {
+            let topic = self.discovery_topic(sensor.component, sensor.object_id);
+            if sensor.needs_disk && !has_disk {
+                self.publish(topic, true, String::new());
+                continue;
+            }
+            let mut config = json!({
+                "name": sensor.name,
+                "unique_id": format!("{}_{}", self.node_id, sensor.object_id),
+                "state_topic": self.topic("state"),
+                "value_template": sensor.value_template,
+                "availability_topic": self.topic("status"),
+                "device": self.device(),
+            });
+            if sensor.component == "sensor" {
+                config["state_class"] = json!("measurement");
+            }
+            if let Some(unit) = sensor.unit {
+                config["unit_of_measurement"] = json!(unit);
+            }
+            if let Some(device_class) = sensor.device_class {
+                config["device_class"] = json!(device_class);
+            }
+            if let Some(icon) = sensor.icon {
+                config["icon"] = json!(icon);
+            }
+            self.publish(topic, true, config.to_string());
+        }
+        self.publish(self.topic("status"), true, "online".to_string());
+        self.publish_state(ctx).await;
+
+        let renderers = ctx.dlna_player.lock().await.renderers().await;
+        for renderer in renderers.values() {
+            self.publish_renderer(renderer);
+        }
+    }
+
+    async fn publish_state(&self, ctx: &AppContext) {
+        let db_pool = &ctx.app_state.db_pool;
+        let files: i64 = match sqlx::query_scalar("SELECT COUNT(*) FROM upload_file_meta WHERE status = 2")
+            .fetch_one(db_pool)
+            .await
+        {
+            Ok(count) => count,
+            Err(e) => {
+                error!("Failed to count files for MQTT state: {}", e);
+                return;
+            }
+        };
+        let mut state = json!({
+            "active_uploads": ctx.app_state.activity.active(ACTIVE_UPLOAD_WINDOW),
+            "files": files,
+        });
+        if let Ok(space) = disk_space(Path::new(UPLOADS_DIR)) {
+            let used_percent = match space.total {
+                0 => 0.0,
+                total => ((total - space.available) as f64 * 1000.0 / total as f64).round() / 10.0,
+            };
+            let threshold = ctx.config.load().low_disk_space_percent as u64;
+            state["disk_free_bytes"] = json!(space.available);
+            state["disk_total_bytes"] = json!(space.total);
+            state["disk_used_percent"] = json!(used_percent);
+            state["low_disk_space"] = json!(space.total > 0 && space.available * 100 / space.total < threshold);
+        }
+        self.publish(self.topic("state"), true, state.to_string());
+    }
+
+    fn publish_event(&self, event: &ServerEvent) {
+        let payload = match event {
+            ServerEvent::UploadCompleted { file_id, filename, size, tenant_id, inbox } => json!({
+                "event": "upload_completed",
+                "file_id": file_id,
+                "filename": filename,
+                "size": size,
+                "tenant_id": tenant_id,
+                "inbox": inbox,
+            }),
+            ServerEvent::LowDiskSpace { path, available_bytes, total_bytes, critical, read_only } => json!({
+                "event": "low_disk_space",
+                "path": path.display().to_string(),
+                "available_bytes": available_bytes,
+                "total_bytes": total_bytes,
+                "critical": critical,
+                "read_only": read_only,
+            }),
+        };
+        self.publish(self.topic("events"), false, payload.to_string());
+    }
+
+    /// Publish a renderer's playback state, or remove its entity once the media server deleted it
+    fn publish_renderer(&mut self, renderer: &DeviceMessage) {
+        let id = object_id(&renderer.uuid);
+        let state_topic = self.topic(&format!("renderers/{}", id));
+        let config_topic = self.discovery_topic("sensor", &format!("renderer_{}", id));
+        if renderer.action == "renderer_delete" {
+            if self.renderers.remove(&id) {
+                self.publish(config_topic, true, String::new());
+                self.publish(state_topic, true, String::new());
+            }
+            return;
+        }
+
+        if self.renderers.insert(id.clone()) {
+            let unique_id = format!("{}_renderer_{}", self.node_id, id);
+            let config = json!({
+                "name": "Playback",
+                "unique_id": unique_id,
+                "state_topic": state_topic,
+                "value_template": "{{ value_json.state }}",
+                "json_attributes_topic": state_topic,
+                "availability_topic": self.topic("status"),
+                "icon": "mdi:cast",
+                "device": {
+                    "identifiers": [unique_id],
+                    "name": renderer.name,
+                    "model": "DLNA renderer",
+                    "via_device": self.node_id,
+                },
+            });
+            self.publish(config_topic, true, config.to_string());
+        }
+        let state = json!({
+            "state": playback_state(renderer),
+            "title": renderer.playing,
+            "position": renderer.state.position,
+            "duration": renderer.state.duration,
+            "progress_percent": renderer.progress_percent,
+            "volume": renderer.state.volume,
+            "mute": renderer.state.mute,
+            "address": renderer.address,
+        });
+        self.publish(state_topic, true, state.to_string());
+    }
+}
+
+/// Publish state to the broker in `NASCRAFT_MQTT_URL`, reconnecting until the server stops
+pub async fn run_mqtt_publisher(ctx: AppContext, config: Arc<AppConfig>) {
+    let Some(url) = config.mqtt_url.as_deref().and_then(|url| reqwest::Url::parse(url).ok()) else {
+        return std::future::pending().await;
+    };
+    let host = url.host_str().unwrap_or_default().to_string();
+    let port = url.port().unwrap_or(1883);
+    let node_id = object_id(&config.mqtt_topic);
+
+    let mut options = MqttOptions::new(format!("{}-{}", node_id, uuid::Uuid::new_v4().simple()), host.clone(), port);
+    options.set_keep_alive(KEEP_ALIVE);
+    options.set_last_will(LastWill::new(format!("{}/status", config.mqtt_topic), "offline", QoS::AtLeastOnce, true));
+    if let Some(username) = &config.mqtt_username {
+        options.set_credentials(username, config.mqtt_password.clone().unwrap_or_default());
+    }
+    let (client, mut eventloop) = AsyncClient::new(options, 256);
+    let mut publisher = Publisher { client, config, node_id, renderers: HashSet::new() };
+
+    let mut events = ctx.events.subscribe();
+    let mut renderers = ctx.dlna_player.lock().await.subscribe_renderers();
+    let mut interval = tokio::time::interval(STATE_INTERVAL);
+    let mut connected = false;
+    let mut failing = false;
+    loop {
+        tokio::select! {
+            notification = eventloop.poll() => match notification {
+                Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                    info!("Connected to MQTT broker {}:{}", host, port);
+                    connected = true;
+                    failing = false;
+                    publisher.announce(&ctx).await;
+                    interval.reset();
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    if !failing {
+                        warn!("MQTT broker {}:{} unavailable, retrying every {}s: {}", host, port, RECONNECT_DELAY.as_secs(), e);
+                    }
+                    connected = false;
+                    failing = true;
+                    tokio::time::sleep(RECONNECT_DELAY).await;
+                }
+            },
+            _ = interval.tick(), if connected => publisher.publish_state(&ctx).await,
+            event = events.recv() => match event {
+                Ok(event) if connected => {
+                    publisher.publish_event(&event);
+                    publisher.publish_state(&ctx).await;
+                }
+                Ok(_) => {}
+                Err(RecvError::Lagged(skipped)) => warn!("MQTT publisher fell behind, {} events were not published", skipped),
+                Err(RecvError::Closed) => return,
+            },
+            update = renderers.recv() => match update {
+                Ok(renderer) if connected => publisher.publish_renderer(&renderer),
+                Ok(_) | Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => return,
+            },
+        }
+    }
+}
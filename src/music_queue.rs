@@ -0,0 +1,505 @@
+//! Music queues on UPnP renderers. The server plays the tracks of a queue one
+//! after another, following the renderer's transport state to notice when a
+//! track ends. Renderers that accept SetNextAVTransportURI get the next track
+//! in advance and move on to it without a gap; the queue then only follows
+//! along. A renderer plays one queue at a time; starting another replaces it.
+//!
+//! The library has no audio tags, so albums are enqueued as folders or tags.
+
+use axum::{
+    extract::{ConnectInfo, Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use crate::bot::{lan_base_url, share_link, PLAYBACK_LINK_HOURS};
+use crate::context::AppContext;
+use crate::feeds::item_title;
+use crate::folders::fetch_visible_folder;
+use crate::helper::{error_response, ApiResponse};
+use crate::library_query::LibraryQuery;
+use crate::profiles::normalize_tag;
+use crate::renderer::{discover_renderers, take_renderer, Renderer};
+use crate::thumbnail::is_audio_file;
+use crate::traffic::client_principal;
+use crate::upload_dao::{fetch_uploaded_file_by_id, UploadedFile};
+
+/// How often the renderer is asked whether the track is still playing
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Tracks in a row the renderer may refuse before the queue ends
+const MAX_FAILURES: u32 = 3;
+
+const MAX_TRACKS: usize = 5000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RepeatMode {
+    #[default]
+    Off,
+    /// Start over after the last track
+    All,
+    /// Play the current track again when it ends
+    One,
+}
+
+enum QueueCommand {
+    Next,
+    Previous,
+    Stop,
+    Enqueue(Vec<UploadedFile>),
+    Mode { repeat: Option<RepeatMode>, shuffle: Option<bool> },
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MusicQueueStatus {
+    pub id: u64,
+    pub device: String,
+    pub repeat: RepeatMode,
+    pub shuffle: bool,
+    /// Whether the renderer takes the next track in advance
+    pub gapless: bool,
+    pub total: usize,
+    /// Index of the track playing in play order, from 0
+    pub position: usize,
+    pub file_id: String,
+    pub title: String,
+    #[serde(with = "crate::api::timestamp")]
+    pub started_at: i64,
+}
+
+struct MusicQueue {
+    location: String,
+    status: MusicQueueStatus,
+    commands: mpsc::UnboundedSender<QueueCommand>,
+}
+
+/// Running queues by id
+#[derive(Default)]
+pub struct MusicQueues {
+    next_id: AtomicU64,
+    running: Mutex<HashMap<u64, MusicQueue>>,
+}
+
+impl MusicQueues {
+    fn list(&self) -> Vec<MusicQueueStatus> {
+        let mut list: Vec<MusicQueueStatus> = self.running.lock().unwrap().values().map(|q| q.status.clone()).collect();
+        list.sort_by_key(|q| q.id);
+        list
+    }
+
+    fn send(&self, id: u64, command: QueueCommand) -> bool {
+        self.running
+            .lock()
+            .unwrap()
+            .get(&id)
+            .is_some_and(|q| q.commands.send(command).is_ok())
+    }
+
+    fn update(&self, id: u64, tracks: &Tracks, gapless: bool) {
+        if let Some(queue) = self.running.lock().unwrap().get_mut(&id) {
+            let file = tracks.current();
+            queue.status.repeat = tracks.repeat;
+            queue.status.shuffle = tracks.shuffle;
+            queue.status.gapless = gapless;
+            queue.status.total = tracks.files.len();
+            queue.status.position = tracks.position;
+            queue.status.file_id = file.file_id.clone();
+            queue.status.title = item_title(file);
+        }
+    }
+
+    fn remove(&self, id: u64) {
+        self.running.lock().unwrap().remove(&id);
+    }
+}
+
+/// The tracks of a queue and the order they are played in
+struct Tracks {
+    files: Vec<UploadedFile>,
+    /// Indexes into `files`, shuffled or in queue order
+    order: Vec<usize>,
+    position: usize,
+    repeat: RepeatMode,
+    shuffle: bool,
+}
+
+impl Tracks {
+    fn new(files: Vec<UploadedFile>, repeat: RepeatMode, shuffle: bool) -> Self {
+        let mut order: Vec<usize> = (0..files.len()).collect();
+        if shuffle {
+            fastrand::shuffle(&mut order);
+        }
+        Tracks { files, order, position: 0, repeat, shuffle }
+    }
+
+    fn current(&self) -> &UploadedFile {
+        &self.files[self.order[self.position]]
+    }
+
+    /// Position after the current track; `ended` when it finished playing
+    /// rather than being skipped, which repeats it in `RepeatMode::One`
+    fn next(&self, ended: bool) -> Option<usize> {
+        if ended && self.repeat == RepeatMode::One {
+            Some(self.position)
+        } else if self.position + 1 < self.order.len() {
+            Some(self.position + 1)
+        } else if self.repeat != RepeatMode::Off {
+            Some(0)
+        } else {
+            None
+        }
+    }
+
+    fn previous(&self) -> usize {
+        match self.position {
+            0 if self.repeat != RepeatMode::Off => self.order.len() - 1,
+            0 => 0,
+            position => position - 1,
+        }
+    }
+
+    /// Shuffle the tracks after the current one, or go back to queue order,
+    /// keeping the current track playing
+    fn set_shuffle(&mut self, shuffle: bool) {
+        let current = self.order[self.position];
+        if shuffle {
+            self.order.swap(0, self.position);
+            fastrand::shuffle(&mut self.order[1..]);
+            self.position = 0;
+        } else {
+            self.order = (0..self.files.len()).collect();
+            self.position = current;
+        }
+        self.shuffle = shuffle;
+    }
+
+    fn append(&mut self, files: Vec<UploadedFile>) {
+        let start = self.files.len();
+        let space = MAX_TRACKS.saturating_sub(start);
+        self.files.extend(files.into_iter().take(space));
+        let mut added: Vec<usize> = (start..self.files.len()).collect();
+        if self.shuffle {
+            // 新加入的曲目随机插在尚未播放的曲目之间
+            for index in added.drain(..) {
+                let at = fastrand::usize(self.position + 1..=self.order.len());
+                self.order.insert(at, index);
+            }
+        }
+        self.order.extend(added);
+    }
+}
+
+fn mime_type(file: &UploadedFile) -> String {
+    let name = file.original_filename.as_deref().unwrap_or(&file.filename);
+    mime_guess::from_path(name).first_or_octet_stream().essence_str().to_string()
+}
+
+async fn track_url(ctx: &AppContext, file: &UploadedFile) -> Result<String, String> {
+    let config = ctx.config.load();
+    share_link(ctx, file, PLAYBACK_LINK_HOURS, &lan_base_url(ctx, &config)).await
+}
+
+/// Hand the track after the current one to the renderer in advance. Returns
+/// its position and URL, or None when there is none or the renderer refused.
+async fn prepare_next(ctx: &AppContext, renderer: &Renderer, tracks: &Tracks, gapless: &mut bool) -> Option<(usize, String)> {
+    if !*gapless {
+        return None;
+    }
+    let position = tracks.next(true)?;
+    let file = &tracks.files[tracks.order[position]];
+    let url = track_url(ctx, file).await.ok()?;
+    match renderer.set_next_url(&url, &item_title(file), &mime_type(file)).await {
+        Ok(()) => Some((position, url)),
+        Err(e) => {
+            info!("{} doesn't take a next track, playing without gapless: {}", renderer.name, e);
+            *gapless = false;
+            None
+        }
+    }
+}
+
+async fn run_queue(
+    ctx: AppContext,
+    id: u64,
+    renderer: Renderer,
+    mut tracks: Tracks,
+    mut commands: mpsc::UnboundedReceiver<QueueCommand>,
+) {
+    let queues = ctx.music_queues.clone();
+    let mut gapless = true;
+    let mut failures = 0;
+    'tracks: loop {
+        queues.update(id, &tracks, gapless);
+        let file = tracks.current();
+        let played = async {
+            let url = track_url(&ctx, file).await?;
+            renderer.play_url(&url, &item_title(file), &mime_type(file)).await
+        }
+        .await;
+        // 单首失败时跳到下一首，连续失败说明渲染器已离线，结束播放
+        if let Err(e) = played {
+            warn!("Music queue {} couldn't play {}: {}", id, file.file_id, e);
+            failures += 1;
+            match tracks.next(false) {
+                Some(next) if failures < MAX_FAILURES => {
+                    tracks.position = next;
+                    continue;
+                }
+                _ => break,
+            }
+        }
+        failures = 0;
+        let mut prepared = prepare_next(&ctx, &renderer, &tracks, &mut gapless).await;
+        // 渲染器切换媒体时会短暂报告 STOPPED，开始播放前不判断曲目结束
+        let mut started = false;
+
+        loop {
+            let command = tokio::select! {
+                _ = tokio::time::sleep(POLL_INTERVAL) => None,
+                command = commands.recv() => match command {
+                    Some(command) => Some(command),
+                    // 通道关闭表示被同一渲染器上的新队列取代
+                    None => break 'tracks,
+                },
+            };
+            match command {
+                None => {
+                    if let Some((position, url)) = &prepared {
+                        if renderer.position_info().await.is_ok_and(|info| &info.track_uri == url) {
+                            tracks.position = *position;
+                            queues.update(id, &tracks, gapless);
+                            prepared = prepare_next(&ctx, &renderer, &tracks, &mut gapless).await;
+                            continue;
+                        }
+                    }
+                    match renderer.transport_state().await.as_deref() {
+                        Ok("PLAYING") => started = true,
+                        Ok("STOPPED") | Ok("NO_MEDIA_PRESENT") if started => match tracks.next(true) {
+                            Some(next) => {
+                                tracks.position = next;
+                                continue 'tracks;
+                            }
+                            None => break 'tracks,
+                        },
+                        _ => {}
+                    }
+                }
+                Some(QueueCommand::Next) => match tracks.next(false) {
+                    Some(next) => {
+                        tracks.position = next;
+                        continue 'tracks;
+                    }
+                    None => {
+                        let _ = renderer.stop().await;
+                        break 'tracks;
+                    }
+                },
+                Some(QueueCommand::Previous) => {
+                    tracks.position = tracks.previous();
+                    continue 'tracks;
+                }
+                Some(QueueCommand::Stop) => {
+                    if let Err(e) = renderer.stop().await {
+                        warn!("Failed to stop music queue {} on {}: {}", id, renderer.name, e);
+                    }
+                    break 'tracks;
+                }
+                Some(QueueCommand::Enqueue(files)) => {
+                    tracks.append(files);
+                    queues.update(id, &tracks, gapless);
+                    prepared = prepare_next(&ctx, &renderer, &tracks, &mut gapless).await;
+                }
+                Some(QueueCommand::Mode { repeat, shuffle }) => {
+                    if let Some(repeat) = repeat {
+                        tracks.repeat = repeat;
+                    }
+                    if let Some(shuffle) = shuffle.filter(|s| *s != tracks.shuffle) {
+                        tracks.set_shuffle(shuffle);
+                    }
+                    queues.update(id, &tracks, gapless);
+                    prepared = prepare_next(&ctx, &renderer, &tracks, &mut gapless).await;
+                }
+            }
+        }
+    }
+    queues.remove(id);
+    info!("Music queue {} on {} ended", id, renderer.name);
+}
+
+/// What to put in a queue: the audio files in a folder and/or with a tag, in
+/// file name order, and/or files by id in the order given
+#[derive(Deserialize, Default)]
+pub struct TrackSource {
+    folder_id: Option<i64>,
+    tag: Option<String>,
+    #[serde(default)]
+    file_ids: Vec<String>,
+}
+
+/// Audio files of `source` the client can see
+async fn fetch_tracks(ctx: &AppContext, client_addr: &SocketAddr, source: &TrackSource) -> Result<Vec<UploadedFile>, Response> {
+    let db_pool = &ctx.app_state.db_pool;
+    let tag = source.tag.as_deref().map(normalize_tag).filter(|t| !t.is_empty());
+    if source.folder_id.is_none() && tag.is_none() && source.file_ids.is_empty() {
+        return Err(error_response(StatusCode::BAD_REQUEST, "INVALID_QUEUE", "Set folder_id, tag or file_ids".to_string()));
+    }
+    let library = LibraryQuery::for_client(db_pool, &ctx.config.load(), &client_principal(client_addr))
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_PROFILE_ERROR", e))?;
+
+    let mut files = Vec::new();
+    for file_id in &source.file_ids {
+        let visible = library.contains(db_pool, file_id).await
+            .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_FILES_ERROR", e))?;
+        match fetch_uploaded_file_by_id(db_pool, file_id).await {
+            Ok(Some(file)) if visible => files.push(file),
+            Ok(_) => return Err(error_response(StatusCode::NOT_FOUND, "FILE_NOT_FOUND", format!("File {} not found", file_id))),
+            Err(e) => return Err(error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_FILE_RECORD_ERROR", e)),
+        }
+    }
+
+    if source.folder_id.is_some() || tag.is_some() {
+        let mut query = library;
+        if let Some(folder_id) = source.folder_id {
+            match fetch_visible_folder(db_pool, folder_id).await {
+                Ok(Some(_)) => query = query.folder(folder_id),
+                Ok(None) => return Err(error_response(StatusCode::NOT_FOUND, "FOLDER_NOT_FOUND", "Folder not found".to_string())),
+                Err(e) => return Err(error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_FOLDER_ERROR", e)),
+            }
+        }
+        if let Some(tag) = &tag {
+            query = query.tag(tag);
+        }
+        let mut matched = query.fetch(db_pool).await
+            .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_FILES_ERROR", e))?;
+        // 按文件名排序，带曲目编号的专辑按曲目顺序播放
+        matched.sort_by_cached_key(|f| f.original_filename.as_deref().unwrap_or(&f.filename).to_lowercase());
+        files.extend(matched);
+    }
+
+    files.retain(|f| is_audio_file(&f.filename));
+    if files.is_empty() {
+        return Err(error_response(StatusCode::NOT_FOUND, "NO_TRACKS", "No audio files match".to_string()));
+    }
+    if files.len() > MAX_TRACKS {
+        return Err(error_response(StatusCode::BAD_REQUEST, "INVALID_QUEUE", format!("A queue holds at most {} tracks", MAX_TRACKS)));
+    }
+    Ok(files)
+}
+
+#[derive(Deserialize)]
+pub struct MusicQueueRequest {
+    /// Renderer name, matched like `/simple/play`'s `device`
+    device: String,
+    #[serde(flatten)]
+    source: TrackSource,
+    #[serde(default)]
+    shuffle: bool,
+    #[serde(default)]
+    repeat: RepeatMode,
+}
+
+/// Start playing a queue of audio files on a renderer
+pub async fn start_music_queue(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    Json(req): Json<MusicQueueRequest>,
+) -> Response {
+    let files = match fetch_tracks(&ctx, &client_addr, &req.source).await {
+        Ok(files) => files,
+        Err(resp) => return resp,
+    };
+    let renderers = match discover_renderers().await {
+        Ok(renderers) => renderers,
+        Err(e) => return error_response(StatusCode::BAD_GATEWAY, "DISCOVERY_ERROR", e),
+    };
+    let renderer = match take_renderer(renderers, &req.device) {
+        Ok(renderer) => renderer,
+        Err(e) => return error_response(StatusCode::NOT_FOUND, "DEVICE_NOT_FOUND", e),
+    };
+
+    let tracks = Tracks::new(files, req.repeat, req.shuffle);
+    let id = ctx.music_queues.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+    let (commands, receiver) = mpsc::unbounded_channel();
+    let status = MusicQueueStatus {
+        id,
+        device: renderer.name.clone(),
+        repeat: tracks.repeat,
+        shuffle: tracks.shuffle,
+        gapless: true,
+        total: tracks.files.len(),
+        position: tracks.position,
+        file_id: tracks.current().file_id.clone(),
+        title: item_title(tracks.current()),
+        started_at: chrono::Utc::now().timestamp(),
+    };
+    {
+        let mut running = ctx.music_queues.running.lock().unwrap();
+        running.retain(|_, q| q.location != renderer.location);
+        running.insert(id, MusicQueue { location: renderer.location.clone(), status: status.clone(), commands });
+    }
+    info!("Starting music queue {} of {} tracks on {}", id, tracks.files.len(), renderer.name);
+    tokio::spawn(run_queue(ctx.clone(), id, renderer, tracks, receiver));
+
+    (StatusCode::OK, Json(ApiResponse::success(status))).into_response()
+}
+
+pub async fn list_music_queues(State(ctx): State<AppContext>) -> Response {
+    (StatusCode::OK, Json(ApiResponse::success(ctx.music_queues.list()))).into_response()
+}
+
+fn control(ctx: &AppContext, id: u64, command: QueueCommand) -> Response {
+    if !ctx.music_queues.send(id, command) {
+        return error_response(StatusCode::NOT_FOUND, "QUEUE_NOT_FOUND", "Music queue not found or ended".to_string());
+    }
+    (StatusCode::OK, Json(ApiResponse::<()>::success(()))).into_response()
+}
+
+pub async fn next_track(State(ctx): State<AppContext>, Path(id): Path<u64>) -> Response {
+    control(&ctx, id, QueueCommand::Next)
+}
+
+pub async fn previous_track(State(ctx): State<AppContext>, Path(id): Path<u64>) -> Response {
+    control(&ctx, id, QueueCommand::Previous)
+}
+
+pub async fn stop_music_queue(State(ctx): State<AppContext>, Path(id): Path<u64>) -> Response {
+    control(&ctx, id, QueueCommand::Stop)
+}
+
+/// Add audio files to the end of a running queue
+pub async fn enqueue_tracks(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    Path(id): Path<u64>,
+    Json(source): Json<TrackSource>,
+) -> Response {
+    match fetch_tracks(&ctx, &client_addr, &source).await {
+        Ok(files) => control(&ctx, id, QueueCommand::Enqueue(files)),
+        Err(resp) => resp,
+    }
+}
+
+#[derive(Deserialize)]
+pub struct QueueModeRequest {
+    repeat: Option<RepeatMode>,
+    shuffle: Option<bool>,
+}
+
+/// Change repeat and shuffle of a running queue
+pub async fn set_queue_mode(
+    State(ctx): State<AppContext>,
+    Path(id): Path<u64>,
+    Json(req): Json<QueueModeRequest>,
+) -> Response {
+    control(&ctx, id, QueueCommand::Mode { repeat: req.repeat, shuffle: req.shuffle })
+}
@@ -0,0 +1,45 @@
+use local_ip_address::local_ip;
+use log::{info, warn};
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+
+/// How often the local address is checked for DHCP renewals and network switches
+const IP_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The LAN address the server is advertised on
+pub type LocalAddr = watch::Receiver<Ipv4Addr>;
+
+fn current_ipv4() -> Option<Ipv4Addr> {
+    match local_ip() {
+        Ok(IpAddr::V4(v4)) => Some(v4),
+        _ => None,
+    }
+}
+
+/// Channel carrying the local address, starting with the current one
+/// (loopback when there is no network yet)
+pub fn local_addr_channel() -> (Arc<watch::Sender<Ipv4Addr>>, LocalAddr) {
+    let ip = current_ipv4().unwrap_or_else(|| {
+        warn!("No IPv4 address yet, advertising {} until one is assigned", Ipv4Addr::LOCALHOST);
+        Ipv4Addr::LOCALHOST
+    });
+    let (tx, rx) = watch::channel(ip);
+    (Arc::new(tx), rx)
+}
+
+/// Publish the new address when the host's IPv4 address changes. A lost
+/// address keeps the last one, so a brief disconnect doesn't cause a change.
+pub async fn run_ip_watcher(tx: Arc<watch::Sender<Ipv4Addr>>) {
+    let mut interval = tokio::time::interval(IP_CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        let Some(ip) = current_ipv4() else { continue };
+        let previous = *tx.borrow();
+        if ip != previous {
+            info!("Local address changed from {} to {}, updating advertised URLs", previous, ip);
+            tx.send_replace(ip);
+        }
+    }
+}
@@ -0,0 +1,260 @@
+use axum::{
+    extract::{ConnectInfo, Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use log::{error, info};
+use serde::Deserialize;
+use serde_json::json;
+use std::io::{Read, Seek, SeekFrom};
+use std::net::SocketAddr;
+use crate::context::AppContext;
+use crate::helper::{error_response, ApiResponse};
+use crate::paths::{long_path, path_to_string, subtitle_file_path};
+use crate::profiles::ensure_file_allowed;
+use crate::subtitles::{fetch_subtitle_by_source, save_subtitle, NewSubtitle};
+use crate::upload_dao::fetch_uploaded_file_by_id;
+
+const API_BASE: &str = "https://api.opensubtitles.com/api/v1";
+const USER_AGENT: &str = concat!("nascraft v", env!("CARGO_PKG_VERSION"));
+const SOURCE: &str = "opensubtitles";
+
+/// The OpenSubtitles hash reads 64 KiB from each end of the file
+const HASH_CHUNK_SIZE: u64 = 64 * 1024;
+
+#[derive(Deserialize)]
+struct SearchResponse {
+    data: Vec<SearchResult>,
+}
+
+#[derive(Deserialize)]
+struct SearchResult {
+    attributes: SearchAttributes,
+}
+
+#[derive(Deserialize)]
+struct SearchAttributes {
+    language: Option<String>,
+    release: Option<String>,
+    #[serde(default)]
+    download_count: i64,
+    #[serde(default)]
+    moviehash_match: bool,
+    #[serde(default)]
+    files: Vec<SearchFile>,
+}
+
+#[derive(Deserialize)]
+struct SearchFile {
+    file_id: i64,
+    file_name: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct DownloadResponse {
+    link: String,
+}
+
+#[derive(Deserialize, Default)]
+pub struct FetchSubtitleRequest {
+    /// Comma separated language codes, defaults to NASCRAFT_SUBTITLE_LANGUAGES
+    language: Option<String>,
+}
+
+/// Compute the OpenSubtitles movie hash: file size plus the little-endian u64
+/// words of the first and last 64 KiB. Files smaller than that have no hash.
+fn movie_hash(path: &std::path::Path) -> std::io::Result<Option<String>> {
+    let mut file = std::fs::File::open(path)?;
+    let size = file.metadata()?.len();
+    if size < HASH_CHUNK_SIZE {
+        return Ok(None);
+    }
+
+    let mut hash = size;
+    let mut buffer = vec![0u8; HASH_CHUNK_SIZE as usize];
+    for start in [0, size - HASH_CHUNK_SIZE] {
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(&mut buffer)?;
+        for word in buffer.chunks_exact(8) {
+            hash = hash.wrapping_add(u64::from_le_bytes(word.try_into().unwrap_or_default()));
+        }
+    }
+    Ok(Some(format!("{:016x}", hash)))
+}
+
+fn file_stem(filename: &str) -> &str {
+    match filename.rfind('.') {
+        Some(idx) if idx > 0 => &filename[..idx],
+        _ => filename,
+    }
+}
+
+fn subtitle_format(file_name: Option<&str>) -> &'static str {
+    match file_name.and_then(|n| n.rsplit('.').next()).map(|e| e.to_lowercase()) {
+        Some(ext) if ext == "vtt" => "vtt",
+        _ => "srt",
+    }
+}
+
+/// Search OpenSubtitles for an uploaded video, download the best match and store it
+pub async fn fetch_subtitle_for_file(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    Path(file_id): Path<String>,
+    request: Option<Json<FetchSubtitleRequest>>,
+) -> impl IntoResponse {
+    if let Err(resp) = ensure_file_allowed(&ctx, &client_addr, &file_id).await {
+        return resp;
+    }
+    let Some(api_key) = ctx.config.load().opensubtitles_api_key.clone() else {
+        return error_response(StatusCode::SERVICE_UNAVAILABLE, "SUBTITLE_PROVIDER_DISABLED", "OpenSubtitles API key is not configured".to_string());
+    };
+    let db_pool = &ctx.app_state.db_pool;
+    let request = request.map(|Json(r)| r).unwrap_or_default();
+    let languages = request.language.unwrap_or_else(|| ctx.config.load().subtitle_languages.clone());
+
+    let record = match fetch_uploaded_file_by_id(db_pool, &file_id).await {
+        Ok(Some(record)) => record,
+        Ok(None) => return error_response(StatusCode::NOT_FOUND, "FILE_NOT_FOUND", "File not found".to_string()),
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_FILE_RECORD_ERROR", e),
+    };
+    if record.status != 2 {
+        return error_response(StatusCode::CONFLICT, "FILE_NOT_COMPLETED", "File upload is not completed".to_string());
+    }
+
+    let video_path = long_path(std::path::Path::new(&record.file_path));
+    let hash = match tokio::task::spawn_blocking(move || movie_hash(&video_path)).await {
+        Ok(Ok(hash)) => hash,
+        Ok(Err(e)) => {
+            error!("Failed to hash {} for subtitle search: {}", record.file_path, e);
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "FILE_READ_ERROR", "Failed to read file".to_string());
+        }
+        Err(e) => {
+            error!("Subtitle hash task failed: {}", e);
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "FILE_READ_ERROR", "Failed to read file".to_string());
+        }
+    };
+
+    let name = record.original_filename.as_deref().unwrap_or(&record.filename);
+    let mut query = vec![
+        ("query", file_stem(name).to_string()),
+        ("languages", languages.clone()),
+    ];
+    if let Some(hash) = &hash {
+        query.push(("moviehash", hash.clone()));
+    }
+
+    let client = reqwest::Client::new();
+    let search = client
+        .get(format!("{}/subtitles", API_BASE))
+        .header("Api-Key", &api_key)
+        .header(reqwest::header::USER_AGENT, USER_AGENT)
+        .query(&query)
+        .send()
+        .await
+        .and_then(|r| r.error_for_status());
+    let results = match search {
+        Ok(resp) => match resp.json::<SearchResponse>().await {
+            Ok(results) => results.data,
+            Err(e) => {
+                error!("Failed to parse OpenSubtitles search response: {}", e);
+                return error_response(StatusCode::BAD_GATEWAY, "SUBTITLE_PROVIDER_ERROR", "Invalid response from OpenSubtitles".to_string());
+            }
+        },
+        Err(e) => {
+            error!("OpenSubtitles search failed: {}", e);
+            return error_response(StatusCode::BAD_GATEWAY, "SUBTITLE_PROVIDER_ERROR", format!("OpenSubtitles search failed: {}", e));
+        }
+    };
+
+    // 优先选择按文件哈希匹配的结果，其次按下载次数
+    let best = results
+        .into_iter()
+        .filter(|r| !r.attributes.files.is_empty())
+        .max_by_key(|r| (r.attributes.moviehash_match, r.attributes.download_count));
+    let Some(best) = best else {
+        return error_response(StatusCode::NOT_FOUND, "SUBTITLE_NOT_FOUND", "No matching subtitle found".to_string());
+    };
+    let attributes = best.attributes;
+    let subtitle_file = &attributes.files[0];
+    let source_ref = subtitle_file.file_id.to_string();
+    let format = subtitle_format(subtitle_file.file_name.as_deref());
+    let language = attributes.language.clone().unwrap_or_else(|| languages.split(',').next().unwrap_or("und").trim().to_string());
+
+    match fetch_subtitle_by_source(db_pool, &file_id, SOURCE, &source_ref).await {
+        Ok(Some(existing)) => return (StatusCode::OK, Json(ApiResponse::success(existing))).into_response(),
+        Ok(None) => {}
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_SUBTITLES_ERROR", e),
+    }
+
+    let download = client
+        .post(format!("{}/download", API_BASE))
+        .header("Api-Key", &api_key)
+        .header(reqwest::header::USER_AGENT, USER_AGENT)
+        .json(&json!({ "file_id": subtitle_file.file_id, "sub_format": format }))
+        .send()
+        .await
+        .and_then(|r| r.error_for_status());
+    let link = match download {
+        Ok(resp) => match resp.json::<DownloadResponse>().await {
+            Ok(d) => d.link,
+            Err(e) => {
+                error!("Failed to parse OpenSubtitles download response: {}", e);
+                return error_response(StatusCode::BAD_GATEWAY, "SUBTITLE_PROVIDER_ERROR", "Invalid response from OpenSubtitles".to_string());
+            }
+        },
+        Err(e) => {
+            error!("OpenSubtitles download request failed: {}", e);
+            return error_response(StatusCode::BAD_GATEWAY, "SUBTITLE_PROVIDER_ERROR", format!("OpenSubtitles download failed: {}", e));
+        }
+    };
+
+    let content = match client.get(&link).send().await.and_then(|r| r.error_for_status()) {
+        Ok(resp) => match resp.bytes().await {
+            Ok(content) => content,
+            Err(e) => return error_response(StatusCode::BAD_GATEWAY, "SUBTITLE_PROVIDER_ERROR", format!("Failed to download subtitle: {}", e)),
+        },
+        Err(e) => return error_response(StatusCode::BAD_GATEWAY, "SUBTITLE_PROVIDER_ERROR", format!("Failed to download subtitle: {}", e)),
+    };
+
+    let stored_path = subtitle_file_path(&file_id, &language, &source_ref, format);
+    if let Some(parent) = stored_path.parent() {
+        if let Err(e) = tokio::fs::create_dir_all(long_path(parent)).await {
+            error!("Failed to create subtitle directory: {}", e);
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "SUBTITLE_SAVE_ERROR", "Failed to save subtitle".to_string());
+        }
+    }
+    if let Err(e) = tokio::fs::write(long_path(&stored_path), &content).await {
+        error!("Failed to write subtitle file: {}", e);
+        return error_response(StatusCode::INTERNAL_SERVER_ERROR, "SUBTITLE_SAVE_ERROR", "Failed to save subtitle".to_string());
+    }
+
+    let stored_path = path_to_string(&stored_path);
+    let new_subtitle = NewSubtitle {
+        file_id: &file_id,
+        language: &language,
+        format,
+        file_path: &stored_path,
+        source: SOURCE,
+        source_ref: Some(&source_ref),
+        release_name: attributes.release.as_deref(),
+        hash_match: attributes.moviehash_match,
+    };
+    let id = match save_subtitle(db_pool, &new_subtitle).await {
+        Ok(id) => id,
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "SUBTITLE_SAVE_ERROR", e),
+    };
+
+    info!("Fetched subtitle {} ({}) for file ID: {}", source_ref, language, file_id);
+    (StatusCode::OK, Json(ApiResponse::success(json!({
+        "id": id,
+        "file_id": file_id,
+        "language": language,
+        "format": format,
+        "source": SOURCE,
+        "source_ref": source_ref,
+        "release_name": attributes.release,
+        "hash_match": attributes.moviehash_match,
+    })))).into_response()
+}
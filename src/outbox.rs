@@ -0,0 +1,172 @@
+//! Transactional outbox for events that follow a database change. The event
+//! is written in the same transaction as the change, and a dispatcher
+//! publishes it to the notifiers afterwards, retrying until it is delivered.
+//! A server that stops right after completing an upload still announces it
+//! once it is back, possibly twice if it stopped right after publishing.
+
+use log::{error, info, warn};
+use sqlx::{FromRow, Sqlite, SqlitePool, Transaction};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+use crate::events::{EventSender, ServerEvent};
+use crate::upload_dao::update_file_status_and_path;
+
+/// Gives the notifiers started with the dispatcher time to subscribe before
+/// events left over from the last run are published
+const STARTUP_DELAY: Duration = Duration::from_secs(2);
+
+/// Pending events are also picked up without a wake-up, e.g. for retries
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+const MAX_RETRY_DELAY_SECS: i64 = 300;
+
+/// Delivered events are kept this long for troubleshooting
+const KEEP_DELIVERED_SECS: i64 = 7 * 24 * 3600;
+
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(3600);
+
+const BATCH_SIZE: i64 = 100;
+
+/// Record `event` for delivery once `tx` commits. Call `Notify::notify_one`
+/// on the dispatcher's handle after the commit to deliver it right away.
+pub async fn enqueue_event(tx: &mut Transaction<'_, Sqlite>, event: &ServerEvent) -> Result<(), String> {
+    let payload = serde_json::to_string(event).map_err(|e| format!("Failed to encode event: {}", e))?;
+    sqlx::query("INSERT INTO event_outbox (event, created_at) VALUES (?, strftime('%s', 'now'))")
+        .bind(payload)
+        .execute(&mut **tx)
+        .await
+        .map(|_| ())
+        .map_err(|e| {
+            error!("Failed to enqueue event: {}", e);
+            "Failed to enqueue event".to_string()
+        })
+}
+
+/// `update_file_status_and_path` and `enqueue_event` in one transaction
+pub async fn update_status_with_event(
+    db_pool: &SqlitePool,
+    file_id: &str,
+    current_status: i32,
+    new_status: i32,
+    file_path: &str,
+    event: &ServerEvent,
+) -> Result<(), String> {
+    let mut tx = db_pool.begin().await.map_err(|e| {
+        error!("Failed to begin transaction: {}", e);
+        "Failed to begin transaction".to_string()
+    })?;
+    update_file_status_and_path(&mut *tx, file_id, current_status, new_status, file_path).await?;
+    enqueue_event(&mut tx, event).await?;
+    tx.commit().await.map_err(|e| {
+        error!("Failed to commit transaction: {}", e);
+        "Failed to commit transaction".to_string()
+    })
+}
+
+#[derive(Debug, FromRow)]
+struct PendingEvent {
+    id: i64,
+    event: String,
+    attempts: i64,
+}
+
+async fn fetch_due(db_pool: &SqlitePool) -> Result<Vec<PendingEvent>, sqlx::Error> {
+    sqlx::query_as::<_, PendingEvent>(
+        "SELECT id, event, attempts FROM event_outbox
+         WHERE delivered_at IS NULL AND next_attempt_at <= strftime('%s', 'now')
+         ORDER BY id LIMIT ?"
+    )
+    .bind(BATCH_SIZE)
+    .fetch_all(db_pool)
+    .await
+}
+
+async fn mark_delivered(db_pool: &SqlitePool, id: i64, error_text: Option<&str>) {
+    if let Err(e) = sqlx::query("UPDATE event_outbox SET delivered_at = strftime('%s', 'now'), attempts = attempts + 1, last_error = ? WHERE id = ?")
+        .bind(error_text)
+        .bind(id)
+        .execute(db_pool)
+        .await
+    {
+        error!("Failed to mark outbox event {} delivered: {}", id, e);
+    }
+}
+
+async fn schedule_retry(db_pool: &SqlitePool, event: &PendingEvent, error_text: &str) {
+    // 指数退避，最长 5 分钟
+    let delay = 1i64.checked_shl(event.attempts.min(16) as u32).unwrap_or(MAX_RETRY_DELAY_SECS).min(MAX_RETRY_DELAY_SECS);
+    if let Err(e) = sqlx::query(
+        "UPDATE event_outbox SET attempts = attempts + 1, next_attempt_at = strftime('%s', 'now') + ?, last_error = ? WHERE id = ?"
+    )
+    .bind(delay)
+    .bind(error_text)
+    .bind(event.id)
+    .execute(db_pool)
+    .await
+    {
+        error!("Failed to reschedule outbox event {}: {}", event.id, e);
+    }
+}
+
+/// Publish the due events in order, returning whether a full batch was sent
+/// so the caller continues without waiting
+async fn dispatch_due(db_pool: &SqlitePool, events: &EventSender) -> bool {
+    let pending = match fetch_due(db_pool).await {
+        Ok(pending) => pending,
+        Err(e) => {
+            error!("Failed to fetch outbox events: {}", e);
+            return false;
+        }
+    };
+    for event in &pending {
+        let decoded: ServerEvent = match serde_json::from_str(&event.event) {
+            Ok(decoded) => decoded,
+            Err(e) => {
+                // 无法解析的事件重试也不会成功，记录后放弃
+                error!("Dropping undecodable outbox event {}: {}", event.id, e);
+                mark_delivered(db_pool, event.id, Some(&format!("Undecodable event: {}", e))).await;
+                continue;
+            }
+        };
+        match events.send(decoded) {
+            Ok(_) => mark_delivered(db_pool, event.id, None).await,
+            Err(_) => {
+                warn!("No notifier is listening, retrying outbox event {} later", event.id);
+                schedule_retry(db_pool, event, "No notifier is listening").await;
+            }
+        }
+    }
+    pending.len() as i64 == BATCH_SIZE
+}
+
+async fn remove_delivered(db_pool: &SqlitePool) {
+    match sqlx::query("DELETE FROM event_outbox WHERE delivered_at < strftime('%s', 'now') - ?")
+        .bind(KEEP_DELIVERED_SECS)
+        .execute(db_pool)
+        .await
+    {
+        Ok(done) if done.rows_affected() > 0 => info!("Removed {} delivered outbox events", done.rows_affected()),
+        Ok(_) => {}
+        Err(e) => error!("Failed to remove delivered outbox events: {}", e),
+    }
+}
+
+/// Publish outbox events to the notifiers, woken by `wake` after new ones are committed
+pub async fn run_outbox_dispatcher(db_pool: SqlitePool, events: EventSender, wake: Arc<Notify>) {
+    tokio::time::sleep(STARTUP_DELAY).await;
+    let mut last_cleanup: Option<Instant> = None;
+    loop {
+        if last_cleanup.is_none_or(|at| at.elapsed() >= CLEANUP_INTERVAL) {
+            remove_delivered(&db_pool).await;
+            last_cleanup = Some(Instant::now());
+        }
+        if dispatch_due(&db_pool, &events).await {
+            continue;
+        }
+        tokio::select! {
+            _ = wake.notified() => {}
+            _ = tokio::time::sleep(POLL_INTERVAL) => {}
+        }
+    }
+}
@@ -0,0 +1,219 @@
+//! Playing a video or song on a renderer while it is still being uploaded.
+//! The renderer gets the file's full length up front and is sent the bytes
+//! in order as they arrive: from the chunk files while the upload runs and
+//! from the assembled file once it is merged. This works for uploads that
+//! send their chunks from the start of the file onwards; a renderer asking
+//! for a part that hasn't arrived waits for it.
+
+use axum::{
+    body::{Body, Bytes},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use log::{info, warn};
+use sqlx::SqlitePool;
+use std::io::SeekFrom;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use crate::api::ChunkProgress;
+use crate::content_range::{parse_range, RangeRequest};
+use crate::context::AppContext;
+use crate::filename::content_disposition;
+use crate::paths::{chunk_file_path, long_path};
+use crate::quarantine::QUARANTINED;
+use crate::repository::{ProgressRepository, UploadRepository};
+use crate::thumbnail::{is_audio_file, is_video_file};
+use crate::traffic::{client_principal, record_traffic};
+use crate::upload_dao::UploadedFile;
+
+const BLOCK_SIZE: u64 = 256 * 1024;
+/// How often the upload's progress is checked while waiting for data
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// The stream ends when no data arrived for this long
+const STALL_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Where the bytes at the read position currently are
+enum Source {
+    /// Part of a chunk the upload has received; `available` is its end offset
+    Chunk { path: PathBuf, start: u64, available: u64 },
+    /// A declared hole, all zeros up to `end`
+    Hole { end: u64 },
+    /// The merged file
+    Merged { path: PathBuf },
+    /// Not received yet, or being merged
+    Pending,
+}
+
+struct UnfinishedUpload {
+    db_pool: SqlitePool,
+    file_id: String,
+    pos: u64,
+    end: u64,
+    source: Option<Source>,
+    file: Option<(PathBuf, File)>,
+    last_data: Instant,
+}
+
+impl UnfinishedUpload {
+    async fn locate(&self) -> Result<Source, String> {
+        let (_, _, _, status, file_path) = self.db_pool.fetch_file_record(&self.file_id).await?;
+        match status {
+            2 => return Ok(Source::Merged { path: PathBuf::from(file_path) }),
+            QUARANTINED => return Err("The upload was quarantined".to_string()),
+            1 => return Ok(Source::Pending),
+            _ => {}
+        }
+        let chunks = self.db_pool.fetch_upload_progress(&self.file_id).await?;
+        let Some(chunk) = chunks.iter().find(|c| c.start_offset as u64 <= self.pos && self.pos <= c.end_offset as u64) else {
+            return Err(format!("No chunk of the upload contains byte {}", self.pos));
+        };
+        Ok(chunk_source(&self.file_id, chunk, self.pos))
+    }
+
+    /// Read up to `len` bytes at the position from `path`, reopening it when
+    /// the source changed
+    async fn read(&mut self, path: PathBuf, offset: u64, len: u64) -> std::io::Result<Bytes> {
+        if self.file.as_ref().map(|(open, _)| open) != Some(&path) {
+            self.file = Some((path.clone(), File::open(long_path(&path)).await?));
+        }
+        let (_, file) = self.file.as_mut().expect("file was just opened");
+        file.seek(SeekFrom::Start(offset)).await?;
+        let mut buf = vec![0u8; len as usize];
+        let n = file.read(&mut buf).await?;
+        buf.truncate(n);
+        Ok(Bytes::from(buf))
+    }
+
+    /// Next block of the response, waiting while the data hasn't arrived
+    async fn next_block(&mut self) -> Option<std::io::Result<Bytes>> {
+        while self.pos < self.end {
+            let source = match self.source.take() {
+                Some(source) => source,
+                None => match self.locate().await {
+                    Ok(source) => source,
+                    Err(e) => return Some(Err(std::io::Error::other(e))),
+                },
+            };
+            let want = BLOCK_SIZE.min(self.end - self.pos);
+            let block = match &source {
+                Source::Chunk { path, start, available } if self.pos < *available => {
+                    let len = want.min(available - self.pos);
+                    self.read(path.clone(), self.pos - start, len).await
+                }
+                Source::Hole { end } => Ok(Bytes::from(vec![0u8; want.min(end - self.pos) as usize])),
+                Source::Merged { path } => self.read(path.clone(), self.pos, want).await,
+                Source::Chunk { .. } | Source::Pending => Ok(Bytes::new()),
+            };
+            match block {
+                Ok(block) if !block.is_empty() => {
+                    self.pos += block.len() as u64;
+                    self.last_data = Instant::now();
+                    // 当前来源仍覆盖后续数据时不必再查询数据库
+                    self.source = Some(source);
+                    return Some(Ok(block));
+                }
+                // 数据尚未到达，或分片已在合并后删除，稍后重新定位
+                Ok(_) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => self.file = None,
+                Err(e) => return Some(Err(e)),
+            }
+            if self.last_data.elapsed() >= STALL_TIMEOUT {
+                return Some(Err(std::io::Error::new(std::io::ErrorKind::TimedOut, format!(
+                    "No data arrived at byte {} for {}s", self.pos, STALL_TIMEOUT.as_secs()
+                ))));
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+        None
+    }
+}
+
+fn chunk_source(file_id: &str, chunk: &ChunkProgress, pos: u64) -> Source {
+    let start = chunk.start_offset as u64;
+    if chunk.is_hole {
+        return Source::Hole { end: chunk.end_offset as u64 + 1 };
+    }
+    let available = start + chunk.uploaded_size.max(0) as u64;
+    if pos >= available {
+        return Source::Pending;
+    }
+    Source::Chunk { path: chunk_file_path(file_id, start), start, available }
+}
+
+/// Check that an unfinished upload can be played: a video or song whose
+/// first chunk has started to arrive. Errors carry the response code.
+pub async fn ensure_playable_while_uploading(db_pool: &SqlitePool, file: &UploadedFile) -> Result<(), (&'static str, String)> {
+    let name = file.original_filename.as_deref().unwrap_or(&file.filename);
+    if !is_video_file(name) && !is_audio_file(name) {
+        return Err(("NOT_PLAYABLE", format!("{} is still uploading and isn't a video or song", name)));
+    }
+    let chunks = db_pool.fetch_upload_progress(&file.file_id).await.map_err(|e| ("FETCH_PROGRESS_ERROR", e))?;
+    let started = chunks
+        .iter()
+        .find(|c| c.start_offset == 0)
+        .is_some_and(|c| c.is_hole || c.uploaded_size > 0);
+    if file.status == 0 && !started {
+        return Err(("UPLOAD_NOT_STARTED", format!("No data of {} has arrived yet; upload it from the start to play it while uploading", name)));
+    }
+    Ok(())
+}
+
+/// Stream an upload that is still in progress, with its full length and
+/// single ranges like a completed file
+pub async fn stream_unfinished_upload(ctx: &AppContext, client_addr: &SocketAddr, file: &UploadedFile, headers: &HeaderMap) -> Response {
+    let total_size = file.total_size.max(0) as u64;
+    let range = headers
+        .get(header::RANGE)
+        .and_then(|h| h.to_str().ok())
+        .map_or(RangeRequest::Full, |h| parse_range(h, total_size));
+    let (status, start, len) = match range {
+        RangeRequest::Full => (StatusCode::OK, 0, total_size),
+        RangeRequest::Partial { start, end } => (StatusCode::PARTIAL_CONTENT, start, end - start + 1),
+        RangeRequest::Unsatisfiable => return (
+            StatusCode::RANGE_NOT_SATISFIABLE,
+            [(header::CONTENT_RANGE, format!("bytes */{}", total_size))],
+        ).into_response(),
+    };
+    info!("Streaming unfinished upload {} from byte {}", file.file_id, start);
+    record_traffic(&ctx.app_state.db_pool, &client_principal(client_addr), 0, len).await;
+
+    let upload = UnfinishedUpload {
+        db_pool: ctx.app_state.db_pool.clone(),
+        file_id: file.file_id.clone(),
+        pos: start,
+        end: start + len,
+        source: None,
+        file: None,
+        last_data: Instant::now(),
+    };
+    let body = Body::from_stream(futures::stream::unfold(upload, |mut upload| async move {
+        let block = upload.next_block().await?;
+        if let Err(e) = &block {
+            warn!("Stopped streaming unfinished upload {}: {}", upload.file_id, e);
+            upload.pos = upload.end;
+        }
+        Some((block, upload))
+    }));
+
+    let download_name = file.original_filename.as_deref().unwrap_or(&file.filename);
+    let mut response = (
+        status,
+        [
+            (header::CONTENT_TYPE, "application/octet-stream".to_string()),
+            (header::CONTENT_DISPOSITION, content_disposition(download_name)),
+            (header::ACCEPT_RANGES, "bytes".to_string()),
+            (header::CONTENT_LENGTH, len.to_string()),
+        ],
+        body,
+    )
+        .into_response();
+    if status == StatusCode::PARTIAL_CONTENT {
+        if let Ok(value) = format!("bytes {}-{}/{}", start, start + len - 1, total_size).parse() {
+            response.headers_mut().insert(header::CONTENT_RANGE, value);
+        }
+    }
+    response
+}
@@ -0,0 +1,156 @@
+use std::path::{Path, PathBuf};
+
+/// Directory holding chunk files and merged uploads
+pub const UPLOADS_DIR: &str = "uploads";
+
+/// Directory holding the chunks of unfinished uploads, one subdirectory per file_id.
+/// Kept outside `uploads/` so it can't collide with a stored file or folder.
+pub const CHUNKS_DIR: &str = "chunks";
+
+/// Directory chunks were kept in by earlier versions
+pub const PREVIOUS_CHUNKS_DIR: &str = "uploads/chunks";
+
+/// Directory holding subtitles, one subdirectory per file_id
+pub const SUBTITLES_DIR: &str = "subtitles";
+
+/// Directory holding auto-transcoded copies, named after the file_id
+pub const TRANSCODED_DIR: &str = "transcoded";
+
+/// Paths longer than this need the `\\?\` prefix on Windows (MAX_PATH minus room for a filename suffix)
+#[cfg(windows)]
+const WINDOWS_LONG_PATH_THRESHOLD: usize = 240;
+
+/// Directory holding the chunk files of an upload
+pub fn chunk_dir(file_id: &str) -> PathBuf {
+    Path::new(CHUNKS_DIR).join(file_id)
+}
+
+/// Path of the temporary file holding the chunk that starts at `start_offset`
+pub fn chunk_file_path(file_id: &str, start_offset: u64) -> PathBuf {
+    chunk_dir(file_id).join(start_offset.to_string())
+}
+
+/// Where chunks were kept while their directory was under `uploads/`. Only
+/// read to carry over unfinished uploads.
+pub fn previous_chunk_file_path(file_id: &str, start_offset: u64) -> PathBuf {
+    Path::new(PREVIOUS_CHUNKS_DIR).join(file_id).join(start_offset.to_string())
+}
+
+/// Where chunks were kept before they were grouped by file_id, named after
+/// the sanitized filename. Only read to carry over unfinished uploads.
+pub fn legacy_chunk_file_path(filename: &str, start_offset: u64) -> PathBuf {
+    Path::new(UPLOADS_DIR).join(format!("{}_chunk_{}", filename, start_offset))
+}
+
+/// Path of the merged file for a completed upload
+pub fn final_file_path(filename: &str) -> PathBuf {
+    Path::new(UPLOADS_DIR).join(filename)
+}
+
+/// Path of the merged file for a completed upload into a folder; `folder` is relative to the uploads directory
+pub fn folder_file_path(folder: &str, filename: &str) -> PathBuf {
+    Path::new(UPLOADS_DIR).join(folder).join(filename)
+}
+
+/// Path of the transcoded copy of a file
+pub fn transcode_file_path(file_id: &str, format: &str) -> PathBuf {
+    Path::new(TRANSCODED_DIR).join(format!("{}.{}", file_id, format))
+}
+
+/// Temporary file a delta upload is rebuilt into before replacing the stored file
+pub fn delta_temp_path(filename: &str) -> PathBuf {
+    Path::new(UPLOADS_DIR).join(format!(".{}.delta-{}", filename, uuid::Uuid::new_v4().simple()))
+}
+
+/// Temporary file a bundle upload is received into before it is unpacked
+pub fn bundle_temp_path() -> PathBuf {
+    Path::new(UPLOADS_DIR).join(format!(".bundle-{}", uuid::Uuid::new_v4().simple()))
+}
+
+/// Path of a subtitle fetched for `file_id` from a provider entry `source_ref`
+pub fn subtitle_file_path(file_id: &str, language: &str, source_ref: &str, format: &str) -> PathBuf {
+    Path::new(SUBTITLES_DIR)
+        .join(file_id)
+        .join(format!("{}_{}.{}", language, source_ref, format))
+}
+
+/// Render a path for storage in the database and API responses
+pub fn path_to_string(path: &Path) -> String {
+    path.to_string_lossy().into_owned()
+}
+
+/// Turn a path into one the OS can open even when it exceeds MAX_PATH on Windows.
+/// On other platforms the path is returned unchanged.
+#[cfg(windows)]
+pub fn long_path(path: &Path) -> PathBuf {
+    if path.as_os_str().len() < WINDOWS_LONG_PATH_THRESHOLD {
+        return path.to_path_buf();
+    }
+
+    let absolute = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    let text = absolute.to_string_lossy();
+    if text.starts_with(r"\\?\") {
+        absolute
+    } else if let Some(unc) = text.strip_prefix(r"\\") {
+        PathBuf::from(format!(r"\\?\UNC\{}", unc))
+    } else {
+        PathBuf::from(format!(r"\\?\{}", text))
+    }
+}
+
+#[cfg(not(windows))]
+pub fn long_path(path: &Path) -> PathBuf {
+    path.to_path_buf()
+}
+
+/// Inode number of a file, only available on Unix-like systems
+#[cfg(unix)]
+pub fn file_inode(metadata: &std::fs::Metadata) -> Option<i64> {
+    std::os::unix::fs::MetadataExt::ino(metadata).try_into().ok()
+}
+
+#[cfg(not(unix))]
+pub fn file_inode(_metadata: &std::fs::Metadata) -> Option<i64> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunk_files_are_grouped_by_file_id() {
+        let file_id = "550e8400-e29b-41d4-a716-446655440000";
+        assert_eq!(chunk_dir(file_id), Path::new("chunks").join(file_id));
+        assert_eq!(chunk_file_path(file_id, 0), Path::new("chunks").join(file_id).join("0"));
+        assert_eq!(chunk_file_path(file_id, 10485760), Path::new("chunks").join(file_id).join("10485760"));
+        assert!(!chunk_file_path(file_id, 0).starts_with(UPLOADS_DIR));
+    }
+
+    #[test]
+    fn previous_chunk_layouts() {
+        assert_eq!(previous_chunk_file_path("id", 5), Path::new("uploads").join("chunks").join("id").join("5"));
+        assert_eq!(legacy_chunk_file_path("a.bin", 5), Path::new("uploads").join("a.bin_chunk_5"));
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn long_path_is_unchanged() {
+        let path = Path::new("uploads").join("a".repeat(300));
+        assert_eq!(long_path(&path), path);
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn long_path_adds_the_verbatim_prefix() {
+        let short = Path::new(r"C:\uploads\a.txt");
+        assert_eq!(long_path(short), short);
+
+        let long = format!(r"C:\uploads\{}", "a".repeat(300));
+        assert_eq!(long_path(Path::new(&long)), PathBuf::from(format!(r"\\?\{}", long)));
+        let verbatim = format!(r"\\?\{}", long);
+        assert_eq!(long_path(Path::new(&verbatim)), PathBuf::from(&verbatim));
+        let unc = format!(r"\\nas\share\{}", "a".repeat(300));
+        assert_eq!(long_path(Path::new(&unc)), PathBuf::from(format!(r"\\?\UNC\nas\share\{}", "a".repeat(300))));
+    }
+}
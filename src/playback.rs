@@ -0,0 +1,239 @@
+use axum::{
+    extract::{ConnectInfo, Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use log::error;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::SqlitePool;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use crate::context::AppContext;
+use crate::helper::{error_response, ApiResponse};
+use crate::profiles::ensure_file_allowed;
+use crate::traffic::client_principal;
+use crate::upload_dao::{fetch_uploaded_file_by_id, UploadedFile};
+
+/// Fraction of the duration after which an item counts as watched
+pub const WATCHED_THRESHOLD: f64 = 0.9;
+
+/// 记录一次播放进度
+pub async fn record_playback(db_pool: &SqlitePool, file_id: &str, principal: &str, position_secs: f64, duration_secs: f64) -> Result<(), String> {
+    if let Err(e) = sqlx::query(
+        "INSERT INTO playback_history (file_id, principal, position_secs, duration_secs, played_at) VALUES (?, ?, ?, ?, strftime('%s', 'now'))"
+    )
+    .bind(file_id)
+    .bind(principal)
+    .bind(position_secs)
+    .bind(duration_secs)
+    .execute(db_pool)
+    .await
+    {
+        error!("Failed to record playback: {}", e);
+        return Err("Failed to record playback".to_string());
+    }
+    Ok(())
+}
+
+/// Per-principal watch status of a media item
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WatchState {
+    Unwatched,
+    InProgress,
+    Watched,
+}
+
+impl WatchState {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "unwatched" => Some(Self::Unwatched),
+            "in_progress" => Some(Self::InProgress),
+            "watched" => Some(Self::Watched),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Unwatched => "unwatched",
+            Self::InProgress => "in_progress",
+            Self::Watched => "watched",
+        }
+    }
+
+    /// State implied by a playback position
+    pub fn from_position(position_secs: f64, duration_secs: f64) -> Self {
+        if duration_secs > 0.0 && position_secs >= duration_secs * WATCHED_THRESHOLD {
+            Self::Watched
+        } else if position_secs > 0.0 {
+            Self::InProgress
+        } else {
+            Self::Unwatched
+        }
+    }
+}
+
+/// 根据播放进度更新观看状态；已看过的条目重看时保持已看
+pub async fn update_watch_state_from_playback(db_pool: &SqlitePool, file_id: &str, principal: &str, position_secs: f64, duration_secs: f64) -> Result<(), String> {
+    let state = WatchState::from_position(position_secs, duration_secs);
+    if let Err(e) = sqlx::query(
+        "INSERT INTO watch_state (file_id, principal, state, position_secs, duration_secs, updated_at) VALUES (?, ?, ?, ?, ?, strftime('%s', 'now'))
+         ON CONFLICT(file_id, principal) DO UPDATE SET
+             state = CASE WHEN watch_state.state = 'watched' THEN 'watched' ELSE excluded.state END,
+             position_secs = excluded.position_secs,
+             duration_secs = excluded.duration_secs,
+             updated_at = excluded.updated_at"
+    )
+    .bind(file_id)
+    .bind(principal)
+    .bind(state.as_str())
+    .bind(position_secs)
+    .bind(duration_secs)
+    .execute(db_pool)
+    .await
+    {
+        error!("Failed to update watch state: {}", e);
+        return Err("Failed to update watch state".to_string());
+    }
+    Ok(())
+}
+
+/// 手动设置观看状态；标记为未看时同时清除播放位置
+pub async fn set_watch_state(db_pool: &SqlitePool, file_id: &str, principal: &str, state: WatchState) -> Result<(), String> {
+    if let Err(e) = sqlx::query(
+        "INSERT INTO watch_state (file_id, principal, state, position_secs, duration_secs, updated_at) VALUES (?, ?, ?, 0, 0, strftime('%s', 'now'))
+         ON CONFLICT(file_id, principal) DO UPDATE SET
+             state = excluded.state,
+             position_secs = CASE WHEN excluded.state = 'unwatched' THEN 0 ELSE watch_state.position_secs END,
+             updated_at = excluded.updated_at"
+    )
+    .bind(file_id)
+    .bind(principal)
+    .bind(state.as_str())
+    .execute(db_pool)
+    .await
+    {
+        error!("Failed to set watch state: {}", e);
+        return Err("Failed to set watch state".to_string());
+    }
+    Ok(())
+}
+
+/// Where to resume a file: the principal's own position while it is in
+/// progress, else the latest position anyone reported. None when nobody played it.
+pub async fn fetch_resume_position(db_pool: &SqlitePool, file_id: &str, principal: &str) -> Result<Option<f64>, String> {
+    sqlx::query_scalar::<_, f64>(
+        "SELECT position_secs FROM (
+             SELECT position_secs, 0 AS rank FROM watch_state WHERE file_id = ? AND principal = ? AND state = 'in_progress'
+             UNION ALL
+             SELECT position_secs, 1 AS rank FROM (SELECT position_secs FROM playback_history WHERE file_id = ? ORDER BY played_at DESC, id DESC LIMIT 1)
+         ) ORDER BY rank LIMIT 1"
+    )
+    .bind(file_id)
+    .bind(principal)
+    .bind(file_id)
+    .fetch_optional(db_pool)
+    .await
+    .map_err(|e| {
+        error!("Failed to fetch resume position: {}", e);
+        "Failed to fetch resume position".to_string()
+    })
+}
+
+/// Watch state of every item the principal has touched; missing items are unwatched
+pub async fn fetch_watch_states(db_pool: &SqlitePool, principal: &str) -> Result<HashMap<String, WatchState>, String> {
+    let rows = sqlx::query_as::<_, (String, String)>(
+        "SELECT file_id, state FROM watch_state WHERE principal = ?"
+    )
+    .bind(principal)
+    .fetch_all(db_pool)
+    .await
+    .map_err(|e| {
+        error!("Failed to fetch watch states: {}", e);
+        "Failed to fetch watch states".to_string()
+    })?;
+
+    Ok(rows
+        .into_iter()
+        .filter_map(|(file_id, state)| WatchState::parse(&state).map(|s| (file_id, s)))
+        .collect())
+}
+
+/// Fill in `watch_state` on each file for the requesting principal. Failures only leave it empty.
+pub async fn attach_watch_states(db_pool: &SqlitePool, principal: &str, files: &mut [UploadedFile]) {
+    if let Ok(states) = fetch_watch_states(db_pool, principal).await {
+        for file in files.iter_mut() {
+            file.watch_state = Some(states.get(&file.file_id).copied().unwrap_or(WatchState::Unwatched));
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct PlaybackReport {
+    position_secs: f64,
+    duration_secs: f64,
+}
+
+/// Players report their position periodically and when playback stops
+pub async fn report_playback(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    Path(file_id): Path<String>,
+    Json(report): Json<PlaybackReport>,
+) -> impl IntoResponse {
+    if !report.position_secs.is_finite() || !report.duration_secs.is_finite() || report.position_secs < 0.0 || report.duration_secs < 0.0 {
+        return error_response(StatusCode::BAD_REQUEST, "INVALID_PLAYBACK_POSITION", "position_secs and duration_secs must be non-negative numbers".to_string());
+    }
+    if let Err(resp) = ensure_file_allowed(&ctx, &client_addr, &file_id).await {
+        return resp;
+    }
+
+    let db_pool = &ctx.app_state.db_pool;
+    let principal = client_principal(&client_addr);
+    let result = match record_playback(db_pool, &file_id, &principal, report.position_secs, report.duration_secs).await {
+        Ok(()) => update_watch_state_from_playback(db_pool, &file_id, &principal, report.position_secs, report.duration_secs).await,
+        Err(e) => Err(e),
+    };
+
+    match result {
+        Ok(()) => (StatusCode::OK, Json(ApiResponse::success(()))).into_response(),
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, "RECORD_PLAYBACK_ERROR", e),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct WatchStateRequest {
+    state: String,
+}
+
+pub async fn update_watch_state(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    Path(file_id): Path<String>,
+    Json(req): Json<WatchStateRequest>,
+) -> impl IntoResponse {
+    let Some(state) = WatchState::parse(&req.state) else {
+        return error_response(StatusCode::BAD_REQUEST, "INVALID_WATCH_STATE", "state must be one of unwatched, in_progress, watched".to_string());
+    };
+    if let Err(resp) = ensure_file_allowed(&ctx, &client_addr, &file_id).await {
+        return resp;
+    }
+    let db_pool = &ctx.app_state.db_pool;
+
+    match fetch_uploaded_file_by_id(db_pool, &file_id).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return error_response(StatusCode::NOT_FOUND, "FILE_NOT_FOUND", "File not found".to_string()),
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_FILE_RECORD_ERROR", e),
+    }
+
+    match set_watch_state(db_pool, &file_id, &client_principal(&client_addr), state).await {
+        Ok(()) => (StatusCode::OK, Json(ApiResponse::success(json!({
+            "file_id": file_id,
+            "watch_state": state,
+        })))).into_response(),
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, "UPDATE_WATCH_STATE_ERROR", e),
+    }
+}
@@ -0,0 +1,206 @@
+//! Checks of the environment run once at startup, before any task is started.
+//! Each result is logged and printed as a report; a failed check stops the
+//! server with a message saying what to fix, rather than leaving uploads to
+//! fail later. Problems the server can live with (no ffmpeg, an unreachable
+//! database it keeps retrying) are reported as warnings.
+
+use log::{error, info, warn};
+use sqlx::SqlitePool;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+use crate::config::AppConfig;
+use crate::init_env::ensure_sqlite_db_parent_dir;
+use crate::paths::{long_path, CHUNKS_DIR, SUBTITLES_DIR, TRANSCODED_DIR, UPLOADS_DIR};
+use crate::server::bind_http;
+
+/// A database that doesn't answer within this is reported as unreachable
+const DATABASE_TIMEOUT: Duration = Duration::from_secs(5);
+const FFMPEG_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckStatus {
+    Ok,
+    Warn,
+    Fail,
+}
+
+impl CheckStatus {
+    fn label(self) -> &'static str {
+        match self {
+            Self::Ok => "ok",
+            Self::Warn => "warn",
+            Self::Fail => "FAIL",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Check {
+    pub name: String,
+    pub status: CheckStatus,
+    pub detail: String,
+}
+
+/// Outcome of the startup checks. The HTTP listener is bound while checking
+/// the port and handed on to the server, so the port can't be taken between
+/// the check and serving.
+pub struct Preflight {
+    checks: Vec<Check>,
+    listener: Option<Arc<std::net::TcpListener>>,
+}
+
+impl Preflight {
+    fn push(&mut self, name: impl Into<String>, status: CheckStatus, detail: impl Into<String>) {
+        self.checks.push(Check { name: name.into(), status, detail: detail.into() });
+    }
+
+    /// Log every check and print the report to stdout
+    pub fn report(&self) {
+        let width = self.checks.iter().map(|c| c.name.len()).max().unwrap_or(0);
+        println!("Startup checks:");
+        for check in &self.checks {
+            println!("  [{:<4}] {:<width$}  {}", check.status.label(), check.name, check.detail, width = width);
+            match check.status {
+                CheckStatus::Ok => info!("Startup check {}: {}", check.name, check.detail),
+                CheckStatus::Warn => warn!("Startup check {}: {}", check.name, check.detail),
+                CheckStatus::Fail => error!("Startup check {} failed: {}", check.name, check.detail),
+            }
+        }
+    }
+
+    /// The listener, or one line per failed check
+    pub fn into_listener(self) -> Result<Arc<std::net::TcpListener>, String> {
+        let failures: Vec<String> = self
+            .checks
+            .iter()
+            .filter(|c| c.status == CheckStatus::Fail)
+            .map(|c| format!("{}: {}", c.name, c.detail))
+            .collect();
+        match self.listener {
+            Some(listener) if failures.is_empty() => Ok(listener),
+            _ => Err(failures.join("; ")),
+        }
+    }
+}
+
+/// Create `dir` when `create` is set and check a file can be written in it
+fn check_writable(dir: &Path, create: bool) -> Result<(), String> {
+    if create {
+        std::fs::create_dir_all(long_path(dir)).map_err(|e| format!("can't create {}: {}", dir.display(), e))?;
+    } else if !dir.is_dir() {
+        return Err(format!("{} does not exist or is not a directory", dir.display()));
+    }
+    let probe = dir.join(format!(".nascraft-write-check-{}", std::process::id()));
+    std::fs::write(long_path(&probe), b"").map_err(|e| format!("{} is not writable: {}", dir.display(), e))?;
+    let _ = std::fs::remove_file(long_path(&probe));
+    Ok(())
+}
+
+async fn ffmpeg_version(config: &AppConfig) -> Result<String, String> {
+    let output = tokio::process::Command::new(&config.ffmpeg_path)
+        .arg("-version")
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .output();
+    let output = tokio::time::timeout(FFMPEG_TIMEOUT, output)
+        .await
+        .map_err(|_| format!("{} -version timed out", config.ffmpeg_path))?
+        .map_err(|e| format!("can't run {}: {}", config.ffmpeg_path, e))?;
+    if !output.status.success() {
+        return Err(format!("{} -version exited with {}", config.ffmpeg_path, output.status));
+    }
+    // 首行形如 "ffmpeg version 6.1.1 Copyright (c) ..."
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let version = stdout.lines().next().and_then(|line| line.split_whitespace().nth(2)).unwrap_or("unknown");
+    Ok(format!("{} (version {})", config.ffmpeg_path, version))
+}
+
+/// Check the data directories, the HTTP port, the database, ffmpeg and the
+/// files named by the settings
+pub async fn run_preflight(config: &AppConfig, db_pool: &SqlitePool) -> Preflight {
+    let mut preflight = Preflight { checks: Vec::new(), listener: None };
+
+    match bind_http(config.server_port) {
+        Ok(listener) => {
+            preflight.listener = Some(listener);
+            preflight.push("http_port", CheckStatus::Ok, format!("listening on 0.0.0.0:{}", config.server_port));
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::AddrInUse => preflight.push("http_port", CheckStatus::Fail, format!(
+            "port {} is already in use; stop the other server or set NASCRAFT_PORT to a free port", config.server_port
+        )),
+        Err(e) => preflight.push("http_port", CheckStatus::Fail, format!("can't listen on port {}: {}", config.server_port, e)),
+    }
+
+    // 上传目录和分片目录不可写时无法接收任何文件；其余目录只影响缩略图、转码和字幕
+    let data_dirs: [(&str, PathBuf, CheckStatus); 5] = [
+        ("uploads_dir", PathBuf::from(UPLOADS_DIR), CheckStatus::Fail),
+        ("chunks_dir", PathBuf::from(CHUNKS_DIR), CheckStatus::Fail),
+        ("thumbnails_dir", PathBuf::from("thumbnails"), CheckStatus::Warn),
+        ("transcoded_dir", PathBuf::from(TRANSCODED_DIR), CheckStatus::Warn),
+        ("subtitles_dir", PathBuf::from(SUBTITLES_DIR), CheckStatus::Warn),
+    ];
+    for (name, dir, severity) in data_dirs {
+        match check_writable(&dir, true) {
+            Ok(()) => preflight.push(name, CheckStatus::Ok, format!("{} is writable", dir.display())),
+            Err(e) => preflight.push(name, severity, format!("{}; fix the directory's owner or permissions", e)),
+        }
+    }
+    if let Some(dir) = &config.cold_storage_dir {
+        // 冷存储通常是挂载点，未挂载时不应在本地磁盘上建出同名目录
+        match check_writable(dir, false) {
+            Ok(()) => preflight.push("cold_storage_dir", CheckStatus::Ok, format!("{} is writable", dir.display())),
+            Err(e) => preflight.push("cold_storage_dir", CheckStatus::Fail, format!("{}; mount it or unset NASCRAFT_COLD_STORAGE_DIR", e)),
+        }
+    }
+    if config.backup_interval_hours > 0 {
+        match check_writable(&config.backup_dir, true) {
+            Ok(()) => preflight.push("backup_dir", CheckStatus::Ok, format!("{} is writable", config.backup_dir.display())),
+            Err(e) => preflight.push("backup_dir", CheckStatus::Warn, format!("{}; scheduled backups will fail", e)),
+        }
+    }
+    if let Some(dir) = &config.hooks_dir {
+        if !dir.is_dir() {
+            preflight.push("hooks_dir", CheckStatus::Warn, format!("{} does not exist, no file hooks will run", dir.display()));
+        }
+    }
+
+    if config.http3_port.is_some() {
+        for (name, path) in [("tls_cert", &config.tls_cert), ("tls_key", &config.tls_key)] {
+            match path {
+                Some(path) => match std::fs::metadata(path) {
+                    Ok(_) => preflight.push(name, CheckStatus::Ok, format!("{} found", path.display())),
+                    Err(e) => preflight.push(name, CheckStatus::Fail, format!("can't read {}: {}", path.display(), e)),
+                },
+                None => preflight.push(name, CheckStatus::Warn, "not set, HTTP/3 is disabled"),
+            }
+        }
+    }
+
+    match &config.database_url {
+        None => preflight.push("database", CheckStatus::Warn, "DATABASE_URL is not set, metadata is kept in memory and lost on restart"),
+        Some(_) => match tokio::time::timeout(DATABASE_TIMEOUT, async {
+            ensure_sqlite_db_parent_dir(db_pool)?;
+            sqlx::query("SELECT 1").execute(db_pool).await
+        }).await {
+            Ok(Ok(_)) => preflight.push("database", CheckStatus::Ok, "reachable"),
+            Ok(Err(e)) => preflight.push("database", CheckStatus::Warn, format!(
+                "can't be opened ({}); the server starts anyway and keeps retrying, check DATABASE_URL and the file's permissions", e
+            )),
+            Err(_) => preflight.push("database", CheckStatus::Warn, format!(
+                "didn't answer within {}s; the server starts anyway and keeps retrying", DATABASE_TIMEOUT.as_secs()
+            )),
+        },
+    }
+
+    match ffmpeg_version(config).await {
+        Ok(version) => preflight.push("ffmpeg", CheckStatus::Ok, version),
+        Err(e) => preflight.push("ffmpeg", CheckStatus::Warn, format!(
+            "{}; video thumbnails, transcodes and audio extraction will fail until ffmpeg is installed or NASCRAFT_FFMPEG_PATH points to it", e
+        )),
+    }
+
+    preflight
+}
@@ -0,0 +1,460 @@
+use axum::{
+    extract::{ConnectInfo, Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use sqlx::{FromRow, SqlitePool};
+use std::net::SocketAddr;
+use crate::config::AppConfig;
+use crate::context::AppContext;
+use crate::helper::{error_response, ApiResponse};
+use crate::library_query::LibraryQuery;
+use crate::tenants::{ensure_file_in_tenant, ensure_not_tenant};
+use crate::thumbnail::VIDEO_EXTENSIONS;
+use crate::traffic::client_principal;
+use crate::upload_dao::fetch_uploaded_file_by_id;
+
+#[derive(Debug, FromRow)]
+struct ProfileRow {
+    id: i64,
+    name: String,
+    pin_hash: Option<String>,
+    max_content_age: Option<i64>,
+    allow_unrated: bool,
+    blocked_tags: String,
+    blocked_folders: String,
+    created_at: i64,
+}
+
+/// A viewing profile and the content it is allowed to see
+#[derive(Debug, Clone, Serialize)]
+pub struct Profile {
+    pub id: i64,
+    pub name: String,
+    pub has_pin: bool,
+    /// Highest minimum-age rating allowed, None for no rating limit
+    pub max_content_age: Option<i64>,
+    /// Whether videos without a known rating are allowed under a rating limit
+    pub allow_unrated: bool,
+    pub blocked_tags: Vec<String>,
+    /// Path prefixes (as stored in file_path) that are hidden
+    pub blocked_folders: Vec<String>,
+    #[serde(with = "crate::api::timestamp")]
+    pub created_at: i64,
+    #[serde(skip)]
+    pin_hash: Option<String>,
+}
+
+impl From<ProfileRow> for Profile {
+    fn from(row: ProfileRow) -> Self {
+        Self {
+            id: row.id,
+            name: row.name,
+            has_pin: row.pin_hash.is_some(),
+            max_content_age: row.max_content_age,
+            allow_unrated: row.allow_unrated,
+            blocked_tags: serde_json::from_str(&row.blocked_tags).unwrap_or_default(),
+            blocked_folders: serde_json::from_str(&row.blocked_folders).unwrap_or_default(),
+            created_at: row.created_at,
+            pin_hash: row.pin_hash,
+        }
+    }
+}
+
+/// SQL condition over `upload_file_meta f` that keeps only files a profile may see
+#[derive(Debug, Clone)]
+pub struct ContentRestriction {
+    pub clause: String,
+    pub binds: Vec<String>,
+}
+
+fn escape_like(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+}
+
+impl Profile {
+    pub fn is_restricted(&self) -> bool {
+        self.max_content_age.is_some() || !self.blocked_tags.is_empty() || !self.blocked_folders.is_empty()
+    }
+
+    pub fn restriction(&self) -> Option<ContentRestriction> {
+        if !self.is_restricted() {
+            return None;
+        }
+
+        let mut conditions = Vec::new();
+        let mut binds = Vec::new();
+
+        if !self.blocked_tags.is_empty() {
+            let placeholders = vec!["?"; self.blocked_tags.len()].join(", ");
+            conditions.push(format!(
+                "NOT EXISTS (SELECT 1 FROM file_tags t WHERE t.file_id = f.file_id AND t.tag IN ({}))",
+                placeholders
+            ));
+            binds.extend(self.blocked_tags.iter().cloned());
+        }
+
+        for folder in &self.blocked_folders {
+            conditions.push("f.file_path NOT LIKE ? ESCAPE '\\'".to_string());
+            binds.push(format!("{}/%", escape_like(folder.trim_end_matches(['/', '\\']))));
+        }
+
+        if let Some(max_age) = self.max_content_age {
+            // 未分级的视频视为超出限制，除非允许未分级内容；非视频文件不受分级限制
+            let unrated_age = if self.allow_unrated {
+                "-1".to_string()
+            } else {
+                let is_video = VIDEO_EXTENSIONS
+                    .iter()
+                    .map(|ext| format!("lower(f.filename) LIKE '%.{}'", ext))
+                    .collect::<Vec<_>>()
+                    .join(" OR ");
+                format!("CASE WHEN {} THEN 1000 ELSE -1 END", is_video)
+            };
+            conditions.push(format!(
+                "COALESCE((SELECT m.content_age FROM media_titles m WHERE m.file_id = f.file_id), {}) <= {}",
+                unrated_age, max_age
+            ));
+        }
+
+        Some(ContentRestriction {
+            clause: conditions.join(" AND "),
+            binds,
+        })
+    }
+
+    fn verify_pin(&self, pin: Option<&str>) -> bool {
+        match (&self.pin_hash, pin) {
+            (None, _) => true,
+            (Some(stored), Some(pin)) => match stored.split_once('$') {
+                Some((salt, _)) => hash_pin_with_salt(salt, pin) == *stored,
+                None => false,
+            },
+            (Some(_), None) => false,
+        }
+    }
+}
+
+fn hash_pin_with_salt(salt: &str, pin: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(salt.as_bytes());
+    hasher.update(b":");
+    hasher.update(pin.as_bytes());
+    format!("{}${:x}", salt, hasher.finalize())
+}
+
+fn hash_pin(pin: &str) -> String {
+    hash_pin_with_salt(&uuid::Uuid::new_v4().simple().to_string(), pin)
+}
+
+const PROFILE_COLUMNS: &str = "id, name, pin_hash, max_content_age, allow_unrated, blocked_tags, blocked_folders, created_at";
+
+pub async fn fetch_profiles(db_pool: &SqlitePool) -> Result<Vec<Profile>, String> {
+    sqlx::query_as::<_, ProfileRow>(&format!("SELECT {} FROM profiles ORDER BY id", PROFILE_COLUMNS))
+        .fetch_all(db_pool)
+        .await
+        .map(|rows| rows.into_iter().map(Profile::from).collect())
+        .map_err(|e| {
+            error!("Failed to fetch profiles: {}", e);
+            "Failed to fetch profiles".to_string()
+        })
+}
+
+pub async fn fetch_profile(db_pool: &SqlitePool, id: i64) -> Result<Option<Profile>, String> {
+    sqlx::query_as::<_, ProfileRow>(&format!("SELECT {} FROM profiles WHERE id = ?", PROFILE_COLUMNS))
+        .bind(id)
+        .fetch_optional(db_pool)
+        .await
+        .map(|row| row.map(Profile::from))
+        .map_err(|e| {
+            error!("Failed to fetch profile: {}", e);
+            "Failed to fetch profile".to_string()
+        })
+}
+
+async fn fetch_profile_by_name(db_pool: &SqlitePool, name: &str) -> Result<Option<Profile>, String> {
+    sqlx::query_as::<_, ProfileRow>(&format!("SELECT {} FROM profiles WHERE name = ?", PROFILE_COLUMNS))
+        .bind(name)
+        .fetch_optional(db_pool)
+        .await
+        .map(|row| row.map(Profile::from))
+        .map_err(|e| {
+            error!("Failed to fetch profile by name: {}", e);
+            "Failed to fetch profile".to_string()
+        })
+}
+
+/// Profile in effect for a client: the one it activated, otherwise the configured
+/// default profile. None means unrestricted.
+pub async fn active_profile(db_pool: &SqlitePool, config: &AppConfig, principal: &str) -> Result<Option<Profile>, String> {
+    let active = sqlx::query_as::<_, ProfileRow>(&format!(
+        "SELECT {} FROM profiles WHERE id = (SELECT profile_id FROM active_profiles WHERE principal = ?)",
+        PROFILE_COLUMNS
+    ))
+    .bind(principal)
+    .fetch_optional(db_pool)
+    .await
+    .map_err(|e| {
+        error!("Failed to fetch active profile: {}", e);
+        "Failed to fetch active profile".to_string()
+    })?;
+    if let Some(row) = active {
+        return Ok(Some(Profile::from(row)));
+    }
+
+    match &config.default_profile {
+        Some(name) => match fetch_profile_by_name(db_pool, name).await? {
+            Some(profile) => Ok(Some(profile)),
+            // 默认档案配置错误时拒绝访问，而不是退回为无限制
+            None => {
+                error!("Default profile '{}' does not exist", name);
+                Err(format!("Default profile '{}' does not exist", name))
+            }
+        },
+        None => Ok(None),
+    }
+}
+
+/// Check a client may access a file, as a ready-made error response otherwise
+pub async fn ensure_file_allowed(ctx: &AppContext, client_addr: &SocketAddr, file_id: &str) -> Result<(), Response> {
+    let db_pool = &ctx.app_state.db_pool;
+    ensure_file_in_tenant(db_pool, file_id).await?;
+    let allowed = match LibraryQuery::for_client(db_pool, &ctx.config.load(), &client_principal(client_addr)).await {
+        Ok(library) if library.is_restricted() => library.status(None).contains(db_pool, file_id).await,
+        Ok(_) => Ok(true),
+        Err(e) => Err(e),
+    };
+    match allowed {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(error_response(StatusCode::FORBIDDEN, "PROFILE_RESTRICTED", "This content is not available in the active profile".to_string())),
+        Err(e) => Err(error_response(StatusCode::INTERNAL_SERVER_ERROR, "PROFILE_CHECK_ERROR", e)),
+    }
+}
+
+/// Reject the request unless the client's profile is unrestricted. Used for
+/// endpoints that could lift restrictions (managing profiles and tags) and for
+/// surfaces where content can't be checked item by item. Tenants are always rejected.
+pub async fn ensure_unrestricted(ctx: &AppContext, client_addr: &SocketAddr) -> Result<(), Response> {
+    ensure_not_tenant().await?;
+    ensure_unrestricted_profile(ctx, client_addr).await
+}
+
+/// Like `ensure_unrestricted`, but lets tenants through for surfaces that are
+/// scoped to the tenant anyway
+pub async fn ensure_unrestricted_profile(ctx: &AppContext, client_addr: &SocketAddr) -> Result<(), Response> {
+    match active_profile(&ctx.app_state.db_pool, &ctx.config.load(), &client_principal(client_addr)).await {
+        Ok(Some(profile)) if profile.is_restricted() => Err(error_response(
+            StatusCode::FORBIDDEN,
+            "PROFILE_RESTRICTED",
+            format!("Not available in restricted profile '{}'", profile.name),
+        )),
+        Ok(_) => Ok(()),
+        Err(e) => Err(error_response(StatusCode::INTERNAL_SERVER_ERROR, "PROFILE_CHECK_ERROR", e)),
+    }
+}
+
+pub async fn list_profiles(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+) -> impl IntoResponse {
+    let db_pool = &ctx.app_state.db_pool;
+    let profiles = match fetch_profiles(db_pool).await {
+        Ok(profiles) => profiles,
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_PROFILES_ERROR", e),
+    };
+    let active = active_profile(db_pool, &ctx.config.load(), &client_principal(&client_addr)).await.ok().flatten();
+
+    (StatusCode::OK, Json(ApiResponse::success(json!({
+        "profiles": profiles,
+        "active_profile_id": active.map(|p| p.id),
+    })))).into_response()
+}
+
+#[derive(Deserialize)]
+pub struct CreateProfileRequest {
+    name: String,
+    pin: Option<String>,
+    max_content_age: Option<i64>,
+    #[serde(default)]
+    allow_unrated: bool,
+    #[serde(default)]
+    blocked_tags: Vec<String>,
+    #[serde(default)]
+    blocked_folders: Vec<String>,
+}
+
+pub async fn create_profile(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    Json(req): Json<CreateProfileRequest>,
+) -> impl IntoResponse {
+    if let Err(resp) = ensure_unrestricted(&ctx, &client_addr).await {
+        return resp;
+    }
+    let name = req.name.trim();
+    if name.is_empty() {
+        return error_response(StatusCode::BAD_REQUEST, "INVALID_PROFILE", "Profile name must not be empty".to_string());
+    }
+
+    let blocked_tags: Vec<String> = req.blocked_tags.iter().map(|t| normalize_tag(t)).filter(|t| !t.is_empty()).collect();
+    let pin_hash = req.pin.as_deref().filter(|p| !p.is_empty()).map(hash_pin);
+    let result = sqlx::query(
+        "INSERT INTO profiles (name, pin_hash, max_content_age, allow_unrated, blocked_tags, blocked_folders, created_at) VALUES (?, ?, ?, ?, ?, ?, strftime('%s', 'now'))"
+    )
+    .bind(name)
+    .bind(pin_hash)
+    .bind(req.max_content_age)
+    .bind(req.allow_unrated)
+    .bind(serde_json::to_string(&blocked_tags).unwrap_or_else(|_| "[]".to_string()))
+    .bind(serde_json::to_string(&req.blocked_folders).unwrap_or_else(|_| "[]".to_string()))
+    .execute(&ctx.app_state.db_pool)
+    .await;
+
+    match result {
+        Ok(done) => {
+            info!("Created profile '{}'", name);
+            match fetch_profile(&ctx.app_state.db_pool, done.last_insert_rowid()).await {
+                Ok(Some(profile)) => (StatusCode::OK, Json(ApiResponse::success(profile))).into_response(),
+                Ok(None) | Err(_) => (StatusCode::OK, Json(ApiResponse::success(()))).into_response(),
+            }
+        }
+        Err(e) => {
+            error!("Failed to create profile: {}", e);
+            error_response(StatusCode::CONFLICT, "CREATE_PROFILE_ERROR", format!("Failed to create profile '{}'", name))
+        }
+    }
+}
+
+pub async fn delete_profile(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    Path(id): Path<i64>,
+) -> impl IntoResponse {
+    if let Err(resp) = ensure_unrestricted(&ctx, &client_addr).await {
+        return resp;
+    }
+
+    let db_pool = &ctx.app_state.db_pool;
+    let result = async {
+        let mut tx = db_pool.begin().await?;
+        sqlx::query("DELETE FROM active_profiles WHERE profile_id = ?").bind(id).execute(&mut *tx).await?;
+        let deleted = sqlx::query("DELETE FROM profiles WHERE id = ?").bind(id).execute(&mut *tx).await?;
+        tx.commit().await?;
+        Ok::<u64, sqlx::Error>(deleted.rows_affected())
+    }
+    .await;
+
+    match result {
+        Ok(0) => error_response(StatusCode::NOT_FOUND, "PROFILE_NOT_FOUND", "Profile not found".to_string()),
+        Ok(_) => (StatusCode::OK, Json(ApiResponse::success(()))).into_response(),
+        Err(e) => {
+            error!("Failed to delete profile: {}", e);
+            error_response(StatusCode::INTERNAL_SERVER_ERROR, "DELETE_PROFILE_ERROR", "Failed to delete profile".to_string())
+        }
+    }
+}
+
+#[derive(Deserialize, Default)]
+pub struct ActivateProfileRequest {
+    pin: Option<String>,
+}
+
+/// Switch the client's profile; profiles with a PIN require it
+pub async fn activate_profile(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    Path(id): Path<i64>,
+    req: Option<Json<ActivateProfileRequest>>,
+) -> impl IntoResponse {
+    let db_pool = &ctx.app_state.db_pool;
+    let req = req.map(|Json(r)| r).unwrap_or_default();
+
+    let profile = match fetch_profile(db_pool, id).await {
+        Ok(Some(profile)) => profile,
+        Ok(None) => return error_response(StatusCode::NOT_FOUND, "PROFILE_NOT_FOUND", "Profile not found".to_string()),
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_PROFILES_ERROR", e),
+    };
+
+    if !profile.verify_pin(req.pin.as_deref()) {
+        return error_response(StatusCode::FORBIDDEN, "INVALID_PIN", "Incorrect PIN".to_string());
+    }
+
+    let principal = client_principal(&client_addr);
+    if let Err(e) = sqlx::query(
+        "INSERT INTO active_profiles (principal, profile_id, activated_at) VALUES (?, ?, strftime('%s', 'now'))
+         ON CONFLICT(principal) DO UPDATE SET profile_id = excluded.profile_id, activated_at = excluded.activated_at"
+    )
+    .bind(&principal)
+    .bind(id)
+    .execute(db_pool)
+    .await
+    {
+        error!("Failed to activate profile: {}", e);
+        return error_response(StatusCode::INTERNAL_SERVER_ERROR, "ACTIVATE_PROFILE_ERROR", "Failed to activate profile".to_string());
+    }
+
+    info!("{} switched to profile '{}'", principal, profile.name);
+    (StatusCode::OK, Json(ApiResponse::success(profile))).into_response()
+}
+
+pub fn normalize_tag(tag: &str) -> String {
+    tag.trim().to_lowercase()
+}
+
+#[derive(Deserialize)]
+pub struct FileTagsRequest {
+    tags: Vec<String>,
+}
+
+/// Replace the tags of a file
+pub async fn set_file_tags(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    Path(file_id): Path<String>,
+    Json(req): Json<FileTagsRequest>,
+) -> impl IntoResponse {
+    if let Err(resp) = ensure_unrestricted(&ctx, &client_addr).await {
+        return resp;
+    }
+
+    let db_pool = &ctx.app_state.db_pool;
+    match fetch_uploaded_file_by_id(db_pool, &file_id).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return error_response(StatusCode::NOT_FOUND, "FILE_NOT_FOUND", "File not found".to_string()),
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_FILE_RECORD_ERROR", e),
+    }
+
+    let mut tags: Vec<String> = req.tags.iter().map(|t| normalize_tag(t)).filter(|t| !t.is_empty()).collect();
+    tags.sort();
+    tags.dedup();
+
+    let result = async {
+        let mut tx = db_pool.begin().await?;
+        sqlx::query("DELETE FROM file_tags WHERE file_id = ?").bind(&file_id).execute(&mut *tx).await?;
+        for tag in &tags {
+            sqlx::query("INSERT INTO file_tags (file_id, tag) VALUES (?, ?)")
+                .bind(&file_id)
+                .bind(tag)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await
+    }
+    .await;
+
+    match result {
+        Ok(()) => (StatusCode::OK, Json(ApiResponse::success(json!({
+            "file_id": file_id,
+            "tags": tags,
+        })))).into_response(),
+        Err(e) => {
+            error!("Failed to set file tags: {}", e);
+            error_response(StatusCode::INTERNAL_SERVER_ERROR, "SET_FILE_TAGS_ERROR", "Failed to set file tags".to_string())
+        }
+    }
+}
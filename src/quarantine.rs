@@ -0,0 +1,374 @@
+//! Built-in upload rules that don't need an antivirus: extension blocklists
+//! enforced before an upload starts, and quarantine for completed files whose
+//! content contradicts their extension, e.g. a Windows executable named
+//! `holiday.jpg`. Quarantined files stay on disk but are hidden from every
+//! listing and download until an administrator releases or deletes them.
+
+use axum::{
+    extract::{ConnectInfo, Path, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use log::{error, info};
+use serde::Serialize;
+use sqlx::{FromRow, Sqlite, SqlitePool};
+use std::net::SocketAddr;
+use tokio::io::AsyncReadExt;
+use crate::config::AppConfig;
+use crate::context::AppContext;
+use crate::events::ServerEvent;
+use crate::file_edit::{bump_revision, etag_mismatch, file_etag, if_match_failure};
+use crate::folders::fetch_file_folder;
+use crate::helper::{error_response, ApiResponse};
+use crate::media_library::spawn_scrape;
+use crate::outbox::enqueue_event;
+use crate::paths::long_path;
+use crate::profiles::ensure_unrestricted;
+use crate::repository::UploadRepository;
+use crate::retention::delete_stored_file;
+use crate::thumbnail::{generate_thumbnail, is_image_file, is_video_file, ThumbnailConfig};
+use crate::transcode::spawn_transcode;
+use crate::upload_dao::fetch_file_priority;
+
+/// `upload_file_meta.status` of quarantined files
+pub const QUARANTINED: i32 = 3;
+
+/// Lowercase extensions without the leading dot, empty entries dropped
+pub fn normalize_extensions<'a>(extensions: impl IntoIterator<Item = &'a str>) -> Vec<String> {
+    extensions
+        .into_iter()
+        .map(|e| e.trim().trim_start_matches('.').to_lowercase())
+        .filter(|e| !e.is_empty())
+        .collect()
+}
+
+fn extension(filename: &str) -> Option<String> {
+    std::path::Path::new(filename)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(str::to_lowercase)
+}
+
+/// Refuse a file whose extension is in `blocked`. Only the last extension
+/// counts, so `invoice.pdf.exe` is an `exe`. The error is an API error code
+/// and message.
+pub fn check_extension(blocked: &[String], filename: &str, target: &str) -> Result<(), (&'static str, String)> {
+    match extension(filename) {
+        Some(ext) if blocked.contains(&ext) => Err(("FILE_TYPE_BLOCKED", format!("{} does not accept .{} files", target, ext))),
+        _ => Ok(()),
+    }
+}
+
+/// What a file's first bytes say it is, when they are a well-known signature
+fn sniff(head: &[u8]) -> Option<&'static str> {
+    let at = |offset: usize, magic: &[u8]| head.get(offset..offset + magic.len()) == Some(magic);
+    let kind = if at(0, b"MZ") || at(0, b"\x7fELF") || at(0, &[0xCF, 0xFA, 0xED, 0xFE]) || at(0, &[0xCE, 0xFA, 0xED, 0xFE]) {
+        "executable"
+    } else if at(0, &[0xFF, 0xD8, 0xFF]) {
+        "jpeg"
+    } else if at(0, b"\x89PNG\r\n\x1a\n") {
+        "png"
+    } else if at(0, b"GIF87a") || at(0, b"GIF89a") {
+        "gif"
+    } else if at(0, b"RIFF") && at(8, b"WEBP") {
+        "webp"
+    } else if at(0, b"RIFF") && at(8, b"AVI ") {
+        "avi"
+    } else if at(0, b"RIFF") && at(8, b"WAVE") {
+        "wav"
+    } else if at(0, b"II*\0") || at(0, b"MM\0*") {
+        "tiff"
+    } else if at(4, b"ftyp") {
+        "mp4"
+    } else if at(0, &[0x1A, 0x45, 0xDF, 0xA3]) {
+        "matroska"
+    } else if at(0, b"ID3") {
+        "mp3"
+    } else if at(0, b"fLaC") {
+        "flac"
+    } else if at(0, b"OggS") {
+        "ogg"
+    } else if at(0, b"%PDF-") {
+        "pdf"
+    } else if at(0, b"PK\x03\x04") {
+        "zip"
+    } else if at(0, b"7z\xBC\xAF\x27\x1C") {
+        "7z"
+    } else if at(0, b"Rar!\x1a\x07") {
+        "rar"
+    } else if at(0, &[0x1F, 0x8B]) {
+        "gzip"
+    } else {
+        return None;
+    };
+    Some(kind)
+}
+
+/// Signatures a file with this extension may have; None for extensions
+/// without a reliable one
+fn expected_kinds(ext: &str) -> Option<&'static [&'static str]> {
+    let kinds: &[&str] = match ext {
+        "jpg" | "jpeg" | "jpe" => &["jpeg"],
+        "png" => &["png"],
+        "gif" => &["gif"],
+        "webp" => &["webp"],
+        "tif" | "tiff" | "dng" | "nef" | "cr2" | "arw" => &["tiff"],
+        "heic" | "heif" | "avif" | "mp4" | "m4v" | "m4a" | "mov" | "3gp" => &["mp4"],
+        "mkv" | "mka" | "webm" => &["matroska"],
+        "avi" => &["avi"],
+        "wav" => &["wav"],
+        "flac" => &["flac"],
+        "ogg" | "oga" | "ogv" | "opus" => &["ogg"],
+        "pdf" => &["pdf"],
+        "zip" | "docx" | "xlsx" | "pptx" | "odt" | "ods" | "odp" | "epub" | "cbz" | "jar" | "apk" => &["zip"],
+        "7z" => &["7z"],
+        "rar" | "cbr" => &["rar"],
+        "gz" | "tgz" => &["gzip"],
+        "exe" | "dll" | "scr" | "sys" | "so" => &["executable"],
+        _ => return None,
+    };
+    Some(kinds)
+}
+
+/// Why a completed upload should be quarantined: its content is a known type
+/// other than the one its extension names, or an executable under any other
+/// extension. Files without an extension or a recognised signature pass.
+pub async fn quarantine_reason(config: &AppConfig, path: &std::path::Path, filename: &str) -> Option<String> {
+    if !config.quarantine_mismatched_types {
+        return None;
+    }
+    let ext = extension(filename)?;
+    let mut head = [0u8; 16];
+    let mut len = 0;
+    let read = async {
+        let mut file = tokio::fs::File::open(long_path(path)).await?;
+        while len < head.len() {
+            match file.read(&mut head[len..]).await? {
+                0 => break,
+                n => len += n,
+            }
+        }
+        Ok::<(), std::io::Error>(())
+    }
+    .await;
+    if let Err(e) = read {
+        error!("Failed to read {} to check its type: {}", path.display(), e);
+        return None;
+    }
+
+    let detected = sniff(&head[..len])?;
+    let contradicts = match expected_kinds(&ext) {
+        Some(kinds) => !kinds.contains(&detected),
+        None => detected == "executable",
+    };
+    contradicts.then(|| format!("Content is {} but the name ends in .{}", detected, ext))
+}
+
+/// Move a file from `from_status` into quarantine at `path`
+pub async fn quarantine_file<'e, E: sqlx::Executor<'e, Database = Sqlite>>(
+    executor: E,
+    file_id: &str,
+    from_status: i32,
+    path: &str,
+    reason: &str,
+) -> Result<(), String> {
+    sqlx::query(
+        "UPDATE upload_file_meta SET status = ?, file_path = ?, quarantine_reason = ?, last_updated = strftime('%s', 'now')
+         WHERE file_id = ? AND status = ?"
+    )
+    .bind(QUARANTINED)
+    .bind(path)
+    .bind(reason)
+    .bind(file_id)
+    .bind(from_status)
+    .execute(executor)
+    .await
+    .map(|_| info!("Quarantined file ID {}: {}", file_id, reason))
+    .map_err(|e| {
+        error!("Failed to quarantine file ID {}: {}", file_id, e);
+        "Failed to quarantine file".to_string()
+    })
+}
+
+#[derive(Debug, Serialize, FromRow)]
+struct QuarantinedFile {
+    file_id: String,
+    filename: String,
+    original_filename: Option<String>,
+    total_size: i64,
+    folder_id: Option<i64>,
+    owner: Option<String>,
+    tenant_id: Option<i64>,
+    quarantine_reason: Option<String>,
+    #[serde(with = "crate::api::timestamp")]
+    last_updated: i64,
+    #[serde(skip)]
+    checksum: String,
+    #[serde(skip)]
+    revision: i64,
+    /// `If-Match` value for releasing or deleting the file
+    #[sqlx(skip)]
+    etag: String,
+}
+
+async fn fetch_quarantined(db_pool: &SqlitePool, file_id: Option<&str>) -> Result<Vec<QuarantinedFile>, String> {
+    sqlx::query_as::<_, QuarantinedFile>(
+        "SELECT file_id, filename, original_filename, total_size, folder_id, owner, tenant_id, quarantine_reason, last_updated, checksum, revision
+         FROM upload_file_meta WHERE status = ? AND (? IS NULL OR file_id = ?) ORDER BY last_updated DESC"
+    )
+    .bind(QUARANTINED)
+    .bind(file_id)
+    .bind(file_id)
+    .fetch_all(db_pool)
+    .await
+    .map(|mut files| {
+        for file in &mut files {
+            file.etag = file_etag(&file.checksum, file.last_updated, file.revision);
+        }
+        files
+    })
+    .map_err(|e| {
+        error!("Failed to fetch quarantined files: {}", e);
+        "Failed to fetch quarantined files".to_string()
+    })
+}
+
+/// The quarantined file with the given ID, or the response for a missing one
+async fn ensure_quarantined(db_pool: &SqlitePool, file_id: &str) -> Result<QuarantinedFile, Response> {
+    match fetch_quarantined(db_pool, Some(file_id)).await {
+        Ok(mut files) if !files.is_empty() => Ok(files.remove(0)),
+        Ok(_) => Err(error_response(StatusCode::NOT_FOUND, "FILE_NOT_QUARANTINED", format!("File {} is not in quarantine", file_id))),
+        Err(e) => Err(error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_QUARANTINE_ERROR", e)),
+    }
+}
+
+/// Quarantined files of all tenants, most recent first
+pub async fn list_quarantine(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+) -> Response {
+    if let Err(resp) = ensure_unrestricted(&ctx, &client_addr).await {
+        return resp;
+    }
+    match fetch_quarantined(&ctx.app_state.db_pool, None).await {
+        Ok(files) => (StatusCode::OK, Json(ApiResponse::success(files))).into_response(),
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_QUARANTINE_ERROR", e),
+    }
+}
+
+/// Accept a quarantined file as a completed upload. It is announced and
+/// processed as if it had just been uploaded.
+pub async fn release_quarantined(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    Path(file_id): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    if let Err(resp) = ensure_unrestricted(&ctx, &client_addr).await {
+        return resp;
+    }
+    let db_pool = &ctx.app_state.db_pool;
+    let quarantined = match ensure_quarantined(db_pool, &file_id).await {
+        Ok(file) => file,
+        Err(resp) => return resp,
+    };
+    if let Some(resp) = if_match_failure(&headers, &quarantined.etag) {
+        return resp;
+    }
+    // 放行与完成事件在同一事务中写入发件箱
+    let event = ServerEvent::UploadCompleted {
+        file_id: file_id.clone(),
+        filename: quarantined.filename.clone(),
+        size: quarantined.total_size as u64,
+        tenant_id: quarantined.tenant_id,
+        inbox: None,
+    };
+    let released = async {
+        let mut tx = db_pool.begin().await.map_err(|e| {
+            error!("Failed to begin transaction: {}", e);
+            "Failed to begin transaction".to_string()
+        })?;
+        if !bump_revision(&mut tx, &file_id, &quarantined.checksum, quarantined.last_updated, quarantined.revision).await? {
+            return Ok(false);
+        }
+        sqlx::query(
+            "UPDATE upload_file_meta SET status = 2, quarantine_reason = NULL, last_updated = strftime('%s', 'now') WHERE file_id = ? AND status = ?"
+        )
+        .bind(&file_id)
+        .bind(QUARANTINED)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| {
+            error!("Failed to release file ID {}: {}", file_id, e);
+            "Failed to release file".to_string()
+        })?;
+        enqueue_event(&mut tx, &event).await?;
+        tx.commit().await.map_err(|e| {
+            error!("Failed to commit transaction: {}", e);
+            "Failed to commit transaction".to_string()
+        })?;
+        Ok(true)
+    }
+    .await;
+    match released {
+        Ok(true) => {}
+        // 校验 If-Match 之后文件又被修改
+        Ok(false) => return match ensure_quarantined(db_pool, &file_id).await {
+            Ok(current) => etag_mismatch(&current.etag),
+            Err(resp) => resp,
+        },
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "RELEASE_ERROR", e),
+    }
+    info!("Released file ID {} from quarantine", file_id);
+    ctx.outbox.notify_one();
+
+    let file = match db_pool.fetch_uploaded_file(&file_id).await {
+        Ok(Some(file)) => file,
+        Ok(None) | Err(_) => return (StatusCode::OK, Json(ApiResponse::success_with_message("File released", ()))).into_response(),
+    };
+
+    if is_image_file(&file.filename) {
+        if let Some(thumbnail_path) = generate_thumbnail(&ThumbnailConfig::default(), &file.file_path, &file.checksum).await {
+            if let Err(e) = db_pool.update_file_thumbnail_path(&file_id, &thumbnail_path).await {
+                error!("Failed to save thumbnail path to database: {}", e);
+            }
+        }
+    }
+    if is_video_file(&file.filename) {
+        spawn_scrape(db_pool.clone(), ctx.config.load(), file_id.clone());
+        if let Ok(Some(format)) = fetch_file_folder(db_pool, &file_id).await.map(|folder| folder.and_then(|f| f.auto_transcode)) {
+            let priority = fetch_file_priority(db_pool, &file_id).await.unwrap_or_default();
+            spawn_transcode(&ctx, file_id.clone(), file.file_path.clone().into(), format, priority);
+        }
+    }
+
+    (StatusCode::OK, Json(ApiResponse::success_with_message("File released", file))).into_response()
+}
+
+/// Delete a quarantined file from disk and the database
+pub async fn delete_quarantined(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    Path(file_id): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    if let Err(resp) = ensure_unrestricted(&ctx, &client_addr).await {
+        return resp;
+    }
+    let db_pool = &ctx.app_state.db_pool;
+    let quarantined = match ensure_quarantined(db_pool, &file_id).await {
+        Ok(file) => file,
+        Err(resp) => return resp,
+    };
+    if let Some(resp) = if_match_failure(&headers, &quarantined.etag) {
+        return resp;
+    }
+    match delete_stored_file(db_pool, &file_id).await {
+        Ok(()) => {
+            info!("Deleted quarantined file ID {}", file_id);
+            (StatusCode::OK, Json(ApiResponse::success_with_message("File deleted", ()))).into_response()
+        }
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, "DELETE_FILE_ERROR", e),
+    }
+}
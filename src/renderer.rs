@@ -0,0 +1,225 @@
+//! Direct control of UPnP AV media renderers (TVs, speakers) over SOAP, to
+//! play library files without going through the external media server.
+
+use futures::StreamExt;
+use log::{info, warn};
+use rupnp::ssdp::{SearchTarget, URN};
+use rupnp::Device;
+use std::collections::HashMap;
+use std::time::Duration;
+use crate::helper::xml_escape;
+use crate::renderer_diagnostics::{record_event, EventSource};
+
+const MEDIA_RENDERER: URN = URN::device("schemas-upnp-org", "MediaRenderer", 1);
+const AV_TRANSPORT: URN = URN::service("schemas-upnp-org", "AVTransport", 1);
+const CONNECTION_MANAGER: URN = URN::service("schemas-upnp-org", "ConnectionManager", 1);
+/// How long renderers get to answer an SSDP search
+const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// A media renderer on the LAN that accepts AVTransport commands
+pub struct Renderer {
+    pub name: String,
+    /// Location of the device description, unique per device
+    pub location: String,
+    device: Device,
+}
+
+/// Media renderers answering an SSDP search; devices that can't be read are skipped
+pub async fn discover_renderers() -> Result<Vec<Renderer>, String> {
+    let devices = rupnp::discover(&SearchTarget::URN(MEDIA_RENDERER), DISCOVERY_TIMEOUT)
+        .await
+        .map_err(|e| format!("Renderer discovery failed: {}", e))?;
+    futures::pin_mut!(devices);
+
+    let mut renderers: Vec<Renderer> = Vec::new();
+    while let Some(device) = devices.next().await {
+        match device {
+            Ok(device) if device.find_service(&AV_TRANSPORT).is_some() => {
+                if renderers.iter().all(|r| r.location != device.url().to_string()) {
+                    renderers.push(Renderer {
+                        name: device.friendly_name().to_string(),
+                        location: device.url().to_string(),
+                        device,
+                    });
+                }
+            }
+            Ok(_) => {}
+            Err(e) => warn!("Skipping renderer that couldn't be read: {}", e),
+        }
+    }
+    Ok(renderers)
+}
+
+/// Index of the renderer whose name is `query` (case-insensitive), else of the only one whose name contains it
+fn find_renderer_index(renderers: &[Renderer], query: &str) -> Result<usize, String> {
+    let query = query.trim().to_lowercase();
+    if let Some(index) = renderers.iter().position(|r| r.name.to_lowercase() == query) {
+        return Ok(index);
+    }
+    let matches: Vec<usize> = (0..renderers.len()).filter(|&i| renderers[i].name.to_lowercase().contains(&query)).collect();
+    match matches.as_slice() {
+        [index] => Ok(*index),
+        [] => Err(format!("No renderer named '{}'", query)),
+        _ => Err(format!(
+            "'{}' matches several renderers: {}",
+            query,
+            matches.iter().map(|&i| renderers[i].name.as_str()).collect::<Vec<_>>().join(", ")
+        )),
+    }
+}
+
+/// The renderer `query` names, see `find_renderer_index`
+pub fn find_renderer<'a>(renderers: &'a [Renderer], query: &str) -> Result<&'a Renderer, String> {
+    find_renderer_index(renderers, query).map(|index| &renderers[index])
+}
+
+/// Take the renderer `query` names out of `renderers`, for callers that keep it
+pub fn take_renderer(mut renderers: Vec<Renderer>, query: &str) -> Result<Renderer, String> {
+    find_renderer_index(&renderers, query).map(|index| renderers.swap_remove(index))
+}
+
+/// Result of GetPositionInfo
+pub struct PositionInfo {
+    /// URI of the media being played, which changes when the renderer moves on
+    /// to the one set with `set_next_url`
+    pub track_uri: String,
+    /// None when the renderer doesn't report it
+    pub position_secs: Option<f64>,
+    pub duration_secs: Option<f64>,
+}
+
+/// `H+:MM:SS[.F+]` as used by AVTransport; None for `NOT_IMPLEMENTED` and the like
+pub fn parse_duration(text: &str) -> Option<f64> {
+    let mut parts = text.trim().split(':');
+    let hours: f64 = parts.next()?.parse().ok()?;
+    let minutes: f64 = parts.next()?.parse().ok()?;
+    let seconds: f64 = parts.next()?.parse().ok()?;
+    if parts.next().is_some() || hours < 0.0 || !(0.0..60.0).contains(&minutes) || !(0.0..60.0).contains(&seconds) {
+        return None;
+    }
+    Some(hours * 3600.0 + minutes * 60.0 + seconds)
+}
+
+pub fn format_duration(secs: f64) -> String {
+    let secs = secs.max(0.0) as u64;
+    format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+/// DIDL-Lite description of the media, which many TVs need to accept a URI
+fn didl_metadata(url: &str, title: &str, mime_type: &str) -> String {
+    let class = match mime_type.split('/').next() {
+        Some("audio") => "object.item.audioItem.musicTrack",
+        Some("image") => "object.item.imageItem.photo",
+        _ => "object.item.videoItem",
+    };
+    format!(
+        concat!(
+            r#"<DIDL-Lite xmlns="urn:schemas-upnp-org:metadata-1-0/DIDL-Lite/" xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:upnp="urn:schemas-upnp-org:metadata-1-0/upnp/">"#,
+            r#"<item id="0" parentID="-1" restricted="1"><dc:title>{}</dc:title><upnp:class>{}</upnp:class>"#,
+            r#"<res protocolInfo="http-get:*:{}:*">{}</res></item></DIDL-Lite>"#
+        ),
+        xml_escape(title), class, xml_escape(mime_type), xml_escape(url)
+    )
+}
+
+impl Renderer {
+    /// Unique device name from the device description, e.g. `uuid:4d696e69-...`
+    pub fn udn(&self) -> &str {
+        self.device.udn()
+    }
+
+    /// Call an AVTransport action, returning its output arguments
+    async fn av_transport(&self, action: &str, arguments: &str) -> Result<HashMap<String, String>, String> {
+        let service = self.device.find_service(&AV_TRANSPORT)
+            .ok_or_else(|| format!("{} has no AVTransport service", self.name))?;
+        let result = service
+            .action(self.device.url(), action, arguments)
+            .await
+            .map_err(|e| format!("{} failed on {}: {}", action, self.name, e));
+        // 轮询类查询只记录失败，否则播放队列每两秒一条会挤掉其余记录
+        match &result {
+            Ok(_) if !action.starts_with("Get") => {
+                record_event(self.udn(), EventSource::Soap, false, format!("{} {}", action, arguments));
+            }
+            Ok(_) => {}
+            Err(e) => record_event(self.udn(), EventSource::Soap, true, e.clone()),
+        }
+        result
+    }
+
+    /// The protocolInfo entries the renderer accepts (`http-get:*:video/mp4:*`
+    /// and so on), from ConnectionManager's GetProtocolInfo
+    pub async fn sink_protocol_info(&self) -> Result<Vec<String>, String> {
+        let service = self.device.find_service(&CONNECTION_MANAGER)
+            .ok_or_else(|| format!("{} has no ConnectionManager service", self.name))?;
+        let output = service
+            .action(self.device.url(), "GetProtocolInfo", "")
+            .await
+            .map_err(|e| format!("GetProtocolInfo failed on {}: {}", self.name, e))?;
+        Ok(output
+            .get("Sink")
+            .map(|sink| sink.split(',').map(str::trim).filter(|entry| !entry.is_empty()).map(String::from).collect())
+            .unwrap_or_default())
+    }
+
+    /// Load `url` and start playing it
+    pub async fn play_url(&self, url: &str, title: &str, mime_type: &str) -> Result<(), String> {
+        info!("Playing {} on {}", title, self.name);
+        let metadata = didl_metadata(url, title, mime_type);
+        self.av_transport("SetAVTransportURI", &format!(
+            "<InstanceID>0</InstanceID><CurrentURI>{}</CurrentURI><CurrentURIMetaData>{}</CurrentURIMetaData>",
+            xml_escape(url),
+            xml_escape(&metadata)
+        )).await?;
+        self.resume().await
+    }
+
+    /// Queue `url` to play right after the current media, without a gap.
+    /// Optional in UPnP AV; many renderers refuse it.
+    pub async fn set_next_url(&self, url: &str, title: &str, mime_type: &str) -> Result<(), String> {
+        let metadata = didl_metadata(url, title, mime_type);
+        self.av_transport("SetNextAVTransportURI", &format!(
+            "<InstanceID>0</InstanceID><NextURI>{}</NextURI><NextURIMetaData>{}</NextURIMetaData>",
+            xml_escape(url),
+            xml_escape(&metadata)
+        )).await.map(|_| ())
+    }
+
+    /// `PLAYING`, `PAUSED_PLAYBACK`, `STOPPED`, `TRANSITIONING` or `NO_MEDIA_PRESENT`
+    pub async fn transport_state(&self) -> Result<String, String> {
+        let output = self.av_transport("GetTransportInfo", "<InstanceID>0</InstanceID>").await?;
+        Ok(output.get("CurrentTransportState").cloned().unwrap_or_default())
+    }
+
+    /// What the renderer is playing and how far it got
+    pub async fn position_info(&self) -> Result<PositionInfo, String> {
+        let output = self.av_transport("GetPositionInfo", "<InstanceID>0</InstanceID>").await?;
+        let time = |name: &str| output.get(name).and_then(|t| parse_duration(t));
+        Ok(PositionInfo {
+            track_uri: output.get("TrackURI").cloned().unwrap_or_default(),
+            position_secs: time("RelTime"),
+            duration_secs: time("TrackDuration"),
+        })
+    }
+
+    /// Jump to `position_secs` into the current media
+    pub async fn seek(&self, position_secs: f64) -> Result<(), String> {
+        self.av_transport("Seek", &format!(
+            "<InstanceID>0</InstanceID><Unit>REL_TIME</Unit><Target>{}</Target>",
+            format_duration(position_secs)
+        )).await.map(|_| ())
+    }
+
+    pub async fn pause(&self) -> Result<(), String> {
+        self.av_transport("Pause", "<InstanceID>0</InstanceID>").await.map(|_| ())
+    }
+
+    /// Continue what was paused
+    pub async fn resume(&self) -> Result<(), String> {
+        self.av_transport("Play", "<InstanceID>0</InstanceID><Speed>1</Speed>").await.map(|_| ())
+    }
+
+    pub async fn stop(&self) -> Result<(), String> {
+        self.av_transport("Stop", "<InstanceID>0</InstanceID>").await.map(|_| ())
+    }
+}
@@ -0,0 +1,280 @@
+//! Recent events and errors of each renderer, to find out why a TV refuses to
+//! play a file: what the external media server reported about it, the SOAP
+//! actions sent to it and their faults, and how it fetched media from the
+//! share links. Kept in memory only, a bounded number per device.
+
+use axum::{
+    extract::{ConnectInfo, Path, Query, Request, State},
+    http::{header, HeaderMap, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{LazyLock, Mutex};
+use crate::context::AppContext;
+use crate::display_remote::DeviceState;
+use crate::helper::{error_response, ApiResponse};
+use crate::profiles::ensure_unrestricted;
+use crate::renderer::discover_renderers;
+use crate::upload_dao::fetch_uploaded_file_by_id;
+
+/// Events kept per device or address; older ones are dropped
+const MAX_EVENTS: usize = 100;
+/// Devices and addresses tracked; the one quiet the longest is dropped first
+const MAX_KEYS: usize = 256;
+/// Longer messages, such as DIDL-Lite metadata, are cut
+const MAX_MESSAGE_CHARS: usize = 4000;
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventSource {
+    /// Raw device event from the external media server
+    MediaServer,
+    /// Control request sent through the external media server
+    Control,
+    /// AVTransport action sent directly to the renderer
+    Soap,
+    /// Request the renderer made for a share link
+    MediaRequest,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RendererEvent {
+    pub at: i64,
+    pub source: EventSource,
+    pub error: bool,
+    pub message: String,
+}
+
+/// Events by device UUID, or by `ip:<address>` for media requests, which
+/// only carry the client address
+static HISTORY: LazyLock<Mutex<HashMap<String, VecDeque<RendererEvent>>>> = LazyLock::new(Default::default);
+
+/// `uuid:ABC` and `abc` name the same device
+fn device_key(uuid: &str) -> String {
+    uuid.trim().trim_start_matches("uuid:").to_lowercase()
+}
+
+fn address_key(ip: IpAddr) -> String {
+    format!("ip:{}", ip.to_canonical())
+}
+
+fn push(key: String, event: RendererEvent) {
+    let mut history = HISTORY.lock().unwrap();
+    if !history.contains_key(&key) && history.len() >= MAX_KEYS {
+        let quietest = history
+            .iter()
+            .min_by_key(|(_, events)| events.back().map_or(0, |e| e.at))
+            .map(|(key, _)| key.clone());
+        if let Some(quietest) = quietest {
+            history.remove(&quietest);
+        }
+    }
+    let events = history.entry(key).or_default();
+    if events.len() >= MAX_EVENTS {
+        events.pop_front();
+    }
+    events.push_back(event);
+}
+
+fn new_event(source: EventSource, error: bool, mut message: String) -> RendererEvent {
+    if let Some((cut, _)) = message.char_indices().nth(MAX_MESSAGE_CHARS) {
+        message.truncate(cut);
+        message.push('…');
+    }
+    RendererEvent { at: chrono::Utc::now().timestamp(), source, error, message }
+}
+
+/// Remember an event of the device with `uuid`
+pub fn record_event(uuid: &str, source: EventSource, error: bool, message: String) {
+    if uuid.trim().is_empty() {
+        return;
+    }
+    push(device_key(uuid), new_event(source, error, message));
+}
+
+fn events_of(key: &str) -> Vec<RendererEvent> {
+    HISTORY.lock().unwrap().get(key).map(|events| events.iter().cloned().collect()).unwrap_or_default()
+}
+
+fn header_text(headers: &HeaderMap, name: header::HeaderName) -> String {
+    headers.get(name).and_then(|v| v.to_str().ok()).unwrap_or("-").to_string()
+}
+
+/// Record how clients fetch share links: method, path, range, status and user
+/// agent, by client address. Share links are what renderers play from, so
+/// this shows the HTTP errors a TV got fetching media.
+pub async fn record_media_requests(
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let range = header_text(req.headers(), header::RANGE);
+    let user_agent = header_text(req.headers(), header::USER_AGENT);
+    let request_line = format!("{} {}", req.method(), req.uri().path());
+    let response = next.run(req).await;
+    let status = response.status();
+    push(address_key(client_addr.ip()), new_event(
+        EventSource::MediaRequest,
+        status.is_client_error() || status.is_server_error(),
+        format!("{} range={} -> {} ({})", request_line, range, status.as_u16(), user_agent),
+    ));
+    response
+}
+
+/// The IP in a media server device address, which may be a bare address, an
+/// `address:port` or a description URL
+fn address_ip(address: &str) -> Option<IpAddr> {
+    let address = address.trim();
+    if let Ok(ip) = address.parse() {
+        return Some(ip);
+    }
+    if let Ok(addr) = address.parse::<SocketAddr>() {
+        return Some(addr.ip());
+    }
+    let host = address.parse::<axum::http::Uri>().ok()?.host()?.trim_matches(['[', ']']).to_string();
+    host.parse().ok()
+}
+
+/// Whether a protocolInfo entry (`http-get:*:video/mp4:DLNA.ORG_PN=...`)
+/// accepts `mime_type`
+fn accepts(entry: &str, mime_type: &str) -> bool {
+    let mut fields = entry.split(':');
+    let (protocol, content_format) = (fields.next().unwrap_or(""), fields.nth(1).unwrap_or(""));
+    if !(protocol == "http-get" || protocol == "*") {
+        return false;
+    }
+    let content_format = content_format.to_lowercase();
+    match content_format.split_once('/') {
+        _ if content_format == "*" => true,
+        Some((kind, "*")) => mime_type.split('/').next() == Some(kind),
+        _ => content_format == mime_type,
+    }
+}
+
+#[derive(Serialize)]
+pub struct FileCompatibility {
+    pub file_id: String,
+    /// What the server announces in protocolInfo when playing the file
+    pub mime_type: String,
+    /// None when the renderer's protocolInfo couldn't be read
+    pub supported: Option<bool>,
+    pub matching_protocol_info: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct RendererDiagnostics {
+    pub uuid: String,
+    pub name: Option<String>,
+    pub addresses: Vec<String>,
+    /// Last state reported by the external media server
+    pub state: Option<DeviceState>,
+    /// Whether the renderer answered an SSDP search, which direct control needs
+    pub reachable: bool,
+    pub sink_protocol_info: Option<Vec<String>>,
+    pub protocol_info_error: Option<String>,
+    pub file: Option<FileCompatibility>,
+    /// Newest first
+    pub events: Vec<RendererEvent>,
+}
+
+#[derive(Deserialize)]
+pub struct DiagnosticsQuery {
+    /// Check whether the renderer accepts this file's type
+    file_id: Option<String>,
+}
+
+/// Recent events of a renderer and what it accepts, optionally checked
+/// against a file
+pub async fn renderer_diagnostics(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    Path(uuid): Path<String>,
+    Query(query): Query<DiagnosticsQuery>,
+) -> Response {
+    if let Err(resp) = ensure_unrestricted(&ctx, &client_addr).await {
+        return resp;
+    }
+    let key = device_key(&uuid);
+    let reported = ctx.dlna_player.lock().await.renderers().await
+        .into_values()
+        .find(|device| device_key(&device.uuid) == key);
+
+    // 直连渲染器需要 SSDP 可达；搜索失败时仍返回媒体服务器上报的信息与事件记录
+    let renderer = match discover_renderers().await {
+        Ok(renderers) => renderers.into_iter().find(|r| device_key(r.udn()) == key),
+        Err(e) => {
+            warn!("Renderer discovery for diagnostics failed: {}", e);
+            None
+        }
+    };
+    let mut events = events_of(&key);
+    if reported.is_none() && renderer.is_none() && events.is_empty() {
+        return error_response(StatusCode::NOT_FOUND, "DEVICE_NOT_FOUND", format!("No renderer with UUID {}", uuid));
+    }
+
+    let mut addresses: Vec<IpAddr> = Vec::new();
+    let candidates = [
+        reported.as_ref().map(|device| device.address.clone()),
+        renderer.as_ref().map(|r| r.location.clone()),
+    ];
+    for ip in candidates.iter().flatten().filter_map(|address| address_ip(address)) {
+        if !addresses.contains(&ip) {
+            addresses.push(ip);
+        }
+    }
+    for ip in &addresses {
+        events.extend(events_of(&address_key(*ip)));
+    }
+    events.sort_by_key(|e| std::cmp::Reverse(e.at));
+
+    let (sink_protocol_info, protocol_info_error) = match &renderer {
+        Some(renderer) => match renderer.sink_protocol_info().await {
+            Ok(entries) => (Some(entries), None),
+            Err(e) => (None, Some(e)),
+        },
+        None => (None, Some("Renderer didn't answer the SSDP search".to_string())),
+    };
+
+    let file = match query.file_id {
+        Some(file_id) => {
+            let file = match fetch_uploaded_file_by_id(&ctx.app_state.db_pool, &file_id).await {
+                Ok(Some(file)) => file,
+                Ok(None) => return error_response(StatusCode::NOT_FOUND, "FILE_NOT_FOUND", "File not found".to_string()),
+                Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_FILE_RECORD_ERROR", e),
+            };
+            // 与 bot::play_file 推送给渲染器的类型一致
+            let name = file.original_filename.as_deref().unwrap_or(&file.filename);
+            let mime_type = mime_guess::from_path(name).first_or_octet_stream().essence_str().to_string();
+            let matching: Vec<String> = sink_protocol_info
+                .iter()
+                .flatten()
+                .filter(|entry| accepts(entry, &mime_type))
+                .cloned()
+                .collect();
+            Some(FileCompatibility {
+                file_id: file.file_id,
+                supported: sink_protocol_info.as_ref().map(|_| !matching.is_empty()),
+                mime_type,
+                matching_protocol_info: matching,
+            })
+        }
+        None => None,
+    };
+
+    (StatusCode::OK, Json(ApiResponse::success(RendererDiagnostics {
+        uuid: key,
+        name: renderer.as_ref().map(|r| r.name.clone()).or_else(|| reported.as_ref().map(|d| d.name.clone())),
+        addresses: addresses.iter().map(|ip| ip.to_string()).collect(),
+        state: reported.map(|device| device.state),
+        reachable: renderer.is_some(),
+        sink_protocol_info,
+        protocol_info_error,
+        file,
+        events,
+    }))).into_response()
+}
@@ -0,0 +1,167 @@
+//! Typed access to upload records and chunk progress. Code written against
+//! these traits runs on the SQLite pool in the server, on the in-memory pool
+//! used without DATABASE_URL, and on the mockall mocks in tests.
+
+use sqlx::SqlitePool;
+use crate::api::ChunkProgress;
+use crate::upload_dao::{self, UploadedFile};
+
+/// `upload_file_meta` records
+#[cfg_attr(test, mockall::automock)]
+pub trait UploadRepository {
+    /// (filename, checksum, total_size, status, file_path)
+    async fn fetch_file_record(&self, file_id: &str) -> Result<(String, String, i64, i32, String), String>;
+
+    async fn fetch_uploaded_file(&self, file_id: &str) -> Result<Option<UploadedFile>, String>;
+
+    /// (file_id, filename, file_path) of a completed file with this checksum
+    async fn fetch_file_by_checksum(&self, checksum: &str, tenant_id: Option<i64>) -> Result<Option<(String, String, String)>, String>;
+
+    async fn fetch_file_created_at(&self, file_id: &str) -> Result<i64, String>;
+
+    /// Move the file to `new_status` only if it is still in `current_status`
+    async fn update_file_status_and_path(&self, file_id: &str, current_status: i32, new_status: i32, file_path: &str) -> Result<(), String>;
+
+    async fn update_file_meta_info(&self, file_id: &str, file_mtime: i64, file_ctime: i64, file_ino: i64) -> Result<(), String>;
+
+    async fn update_file_thumbnail_path(&self, file_id: &str, thumbnail_path: &str) -> Result<(), String>;
+}
+
+/// `upload_progress` rows, one per chunk
+#[cfg_attr(test, mockall::automock)]
+pub trait ProgressRepository {
+    async fn fetch_upload_progress(&self, file_id: &str) -> Result<Vec<ChunkProgress>, String>;
+
+    async fn get_total_uploaded(&self, file_id: &str) -> Result<u64, String>;
+
+    async fn fetch_chunk_hash_algorithm(&self, file_id: &str, start_offset: u64) -> Result<Option<String>, String>;
+
+    /// (start_offset, end_offset) of the chunk holding `position`
+    async fn fetch_chunk_containing(&self, file_id: &str, position: u64) -> Result<Option<(u64, u64)>, String>;
+
+    async fn update_upload_progress(&self, file_id: &str, start_offset: u64, uploaded_size: u64, checksum: &str) -> Result<(), String>;
+
+    async fn update_upload_progress_size(&self, file_id: &str, start_offset: u64, uploaded_size: u64) -> Result<(), String>;
+}
+
+impl UploadRepository for SqlitePool {
+    async fn fetch_file_record(&self, file_id: &str) -> Result<(String, String, i64, i32, String), String> {
+        upload_dao::fetch_file_record(self, file_id).await
+    }
+
+    async fn fetch_uploaded_file(&self, file_id: &str) -> Result<Option<UploadedFile>, String> {
+        upload_dao::fetch_uploaded_file_by_id(self, file_id).await
+    }
+
+    async fn fetch_file_by_checksum(&self, checksum: &str, tenant_id: Option<i64>) -> Result<Option<(String, String, String)>, String> {
+        upload_dao::fetch_file_by_checksum(self, checksum, tenant_id).await
+    }
+
+    async fn fetch_file_created_at(&self, file_id: &str) -> Result<i64, String> {
+        upload_dao::fetch_file_created_at(self, file_id).await
+    }
+
+    async fn update_file_status_and_path(&self, file_id: &str, current_status: i32, new_status: i32, file_path: &str) -> Result<(), String> {
+        upload_dao::update_file_status_and_path(self, file_id, current_status, new_status, file_path).await
+    }
+
+    async fn update_file_meta_info(&self, file_id: &str, file_mtime: i64, file_ctime: i64, file_ino: i64) -> Result<(), String> {
+        upload_dao::update_file_meta_info(self, file_id, file_mtime, file_ctime, file_ino).await
+    }
+
+    async fn update_file_thumbnail_path(&self, file_id: &str, thumbnail_path: &str) -> Result<(), String> {
+        upload_dao::update_file_thumbnail_path(self, file_id, thumbnail_path).await
+    }
+}
+
+impl ProgressRepository for SqlitePool {
+    async fn fetch_upload_progress(&self, file_id: &str) -> Result<Vec<ChunkProgress>, String> {
+        upload_dao::fetch_upload_progress(self, file_id).await
+    }
+
+    async fn get_total_uploaded(&self, file_id: &str) -> Result<u64, String> {
+        upload_dao::get_total_uploaded(self, file_id).await
+    }
+
+    async fn fetch_chunk_hash_algorithm(&self, file_id: &str, start_offset: u64) -> Result<Option<String>, String> {
+        upload_dao::fetch_chunk_hash_algorithm(self, file_id, start_offset).await
+    }
+
+    async fn fetch_chunk_containing(&self, file_id: &str, position: u64) -> Result<Option<(u64, u64)>, String> {
+        upload_dao::fetch_chunk_containing(self, file_id, position).await
+    }
+
+    async fn update_upload_progress(&self, file_id: &str, start_offset: u64, uploaded_size: u64, checksum: &str) -> Result<(), String> {
+        upload_dao::update_upload_progress(self, uploaded_size, checksum, file_id, start_offset).await
+    }
+
+    async fn update_upload_progress_size(&self, file_id: &str, start_offset: u64, uploaded_size: u64) -> Result<(), String> {
+        upload_dao::update_upload_progress_size(self, uploaded_size, file_id, start_offset).await
+    }
+}
+
+/// Both mocks behind one value, for code that needs both repositories
+#[cfg(test)]
+#[derive(Default)]
+pub struct MockRepositories {
+    pub uploads: MockUploadRepository,
+    pub progress: MockProgressRepository,
+}
+
+#[cfg(test)]
+impl UploadRepository for MockRepositories {
+    async fn fetch_file_record(&self, file_id: &str) -> Result<(String, String, i64, i32, String), String> {
+        self.uploads.fetch_file_record(file_id).await
+    }
+
+    async fn fetch_uploaded_file(&self, file_id: &str) -> Result<Option<UploadedFile>, String> {
+        self.uploads.fetch_uploaded_file(file_id).await
+    }
+
+    async fn fetch_file_by_checksum(&self, checksum: &str, tenant_id: Option<i64>) -> Result<Option<(String, String, String)>, String> {
+        self.uploads.fetch_file_by_checksum(checksum, tenant_id).await
+    }
+
+    async fn fetch_file_created_at(&self, file_id: &str) -> Result<i64, String> {
+        self.uploads.fetch_file_created_at(file_id).await
+    }
+
+    async fn update_file_status_and_path(&self, file_id: &str, current_status: i32, new_status: i32, file_path: &str) -> Result<(), String> {
+        self.uploads.update_file_status_and_path(file_id, current_status, new_status, file_path).await
+    }
+
+    async fn update_file_meta_info(&self, file_id: &str, file_mtime: i64, file_ctime: i64, file_ino: i64) -> Result<(), String> {
+        self.uploads.update_file_meta_info(file_id, file_mtime, file_ctime, file_ino).await
+    }
+
+    async fn update_file_thumbnail_path(&self, file_id: &str, thumbnail_path: &str) -> Result<(), String> {
+        self.uploads.update_file_thumbnail_path(file_id, thumbnail_path).await
+    }
+}
+
+#[cfg(test)]
+impl ProgressRepository for MockRepositories {
+    async fn fetch_upload_progress(&self, file_id: &str) -> Result<Vec<ChunkProgress>, String> {
+        self.progress.fetch_upload_progress(file_id).await
+    }
+
+    async fn get_total_uploaded(&self, file_id: &str) -> Result<u64, String> {
+        self.progress.get_total_uploaded(file_id).await
+    }
+
+    async fn fetch_chunk_hash_algorithm(&self, file_id: &str, start_offset: u64) -> Result<Option<String>, String> {
+        self.progress.fetch_chunk_hash_algorithm(file_id, start_offset).await
+    }
+
+    async fn fetch_chunk_containing(&self, file_id: &str, position: u64) -> Result<Option<(u64, u64)>, String> {
+        self.progress.fetch_chunk_containing(file_id, position).await
+    }
+
+    async fn update_upload_progress(&self, file_id: &str, start_offset: u64, uploaded_size: u64, checksum: &str) -> Result<(), String> {
+        self.progress.update_upload_progress(file_id, start_offset, uploaded_size, checksum).await
+    }
+
+    async fn update_upload_progress_size(&self, file_id: &str, start_offset: u64, uploaded_size: u64) -> Result<(), String> {
+        self.progress.update_upload_progress_size(file_id, start_offset, uploaded_size).await
+    }
+}
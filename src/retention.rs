@@ -0,0 +1,501 @@
+use axum::{
+    extract::{ConnectInfo, Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, SqlitePool};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+use crate::config::{AppConfig, SharedConfig};
+use crate::context::AppContext;
+use crate::derived_files::{clean_up_derived_files, fetch_derived_files};
+use crate::file_locks::fetch_locked_paths;
+use crate::folders::{fetch_folder, fetch_folders};
+use crate::helper::{error_response, ApiResponse};
+use crate::paths::{long_path, path_to_string, transcode_file_path, SUBTITLES_DIR};
+use crate::profiles::ensure_unrestricted;
+use crate::transcode::TRANSCODE_FORMATS;
+use crate::usage::record_file_usage;
+use crate::upload_dao::{
+    delete_file_records, fetch_uploaded_file_by_id, is_file_path_referenced, is_file_path_shared,
+    update_archived_file_path,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RetentionAction {
+    Delete,
+    /// Move the file under `NASCRAFT_COLD_STORAGE_DIR`, keeping its record
+    ColdStorage,
+}
+
+impl RetentionAction {
+    fn as_str(self) -> &'static str {
+        match self {
+            RetentionAction::Delete => "delete",
+            RetentionAction::ColdStorage => "cold_storage",
+        }
+    }
+
+    fn parse(value: &str) -> Self {
+        match value {
+            "cold_storage" => RetentionAction::ColdStorage,
+            _ => RetentionAction::Delete,
+        }
+    }
+}
+
+#[derive(Debug, FromRow)]
+struct RetentionRuleRow {
+    id: i64,
+    name: String,
+    folder_id: Option<i64>,
+    tag: Option<String>,
+    max_age_days: Option<i64>,
+    keep_versions: Option<i64>,
+    action: String,
+    created_at: i64,
+}
+
+/// A cleanup rule for the files of a folder or with a tag.
+/// Files match when older than `max_age_days`, or when they are beyond the newest
+/// `keep_versions` uploads sharing their original filename.
+#[derive(Debug, Clone, Serialize)]
+pub struct RetentionRule {
+    /// None for the rule derived from a folder's `retention_days`
+    pub id: Option<i64>,
+    pub name: String,
+    pub folder_id: Option<i64>,
+    pub tag: Option<String>,
+    pub max_age_days: Option<i64>,
+    pub keep_versions: Option<i64>,
+    pub action: RetentionAction,
+    #[serde(with = "crate::api::timestamp")]
+    pub created_at: i64,
+}
+
+impl From<RetentionRuleRow> for RetentionRule {
+    fn from(row: RetentionRuleRow) -> Self {
+        Self {
+            id: Some(row.id),
+            name: row.name,
+            folder_id: row.folder_id,
+            tag: row.tag,
+            max_age_days: row.max_age_days,
+            keep_versions: row.keep_versions,
+            action: RetentionAction::parse(&row.action),
+            created_at: row.created_at,
+        }
+    }
+}
+
+pub async fn fetch_retention_rules(db_pool: &SqlitePool) -> Result<Vec<RetentionRule>, String> {
+    sqlx::query_as::<_, RetentionRuleRow>(
+        "SELECT id, name, folder_id, tag, max_age_days, keep_versions, action, created_at FROM retention_rules ORDER BY id"
+    )
+    .fetch_all(db_pool)
+    .await
+    .map(|rows| rows.into_iter().map(RetentionRule::from).collect())
+    .map_err(|e| {
+        error!("Failed to fetch retention rules: {}", e);
+        "Failed to fetch retention rules".to_string()
+    })
+}
+
+/// Stored rules plus one delete rule for each folder with `retention_days`
+async fn effective_rules(db_pool: &SqlitePool) -> Result<Vec<RetentionRule>, String> {
+    let mut rules = fetch_retention_rules(db_pool).await?;
+    for folder in fetch_folders(db_pool).await? {
+        if let Some(days) = folder.retention_days {
+            rules.push(RetentionRule {
+                id: None,
+                name: format!("Folder '{}' retention", folder.name),
+                folder_id: Some(folder.id),
+                tag: None,
+                max_age_days: Some(days),
+                keep_versions: None,
+                action: RetentionAction::Delete,
+                created_at: folder.created_at,
+            });
+        }
+    }
+    Ok(rules)
+}
+
+#[derive(Debug, FromRow)]
+struct RetentionFile {
+    file_id: String,
+    name: String,
+    total_size: i64,
+    file_path: String,
+    folder_id: Option<i64>,
+    uploaded_at: i64,
+    archived_at: Option<i64>,
+}
+
+/// A file a rule applies to and what would happen to it
+#[derive(Debug, Clone, Serialize)]
+pub struct RetentionCandidate {
+    pub file_id: String,
+    pub filename: String,
+    pub file_path: String,
+    pub size: i64,
+    pub action: RetentionAction,
+    pub rule_id: Option<i64>,
+    pub rule: String,
+    pub reason: String,
+}
+
+/// Evaluate every rule against the completed files. A file matched by several
+/// rules is listed once; deletion takes precedence over cold storage.
+pub async fn evaluate_rules(db_pool: &SqlitePool) -> Result<Vec<RetentionCandidate>, String> {
+    let rules = effective_rules(db_pool).await?;
+    if rules.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    // 旧记录没有创建时间，以最后更新时间代替
+    let files = sqlx::query_as::<_, RetentionFile>(
+        "SELECT file_id, COALESCE(original_filename, filename) AS name, total_size, file_path, folder_id,
+                COALESCE(NULLIF(created_at, 0), last_updated) AS uploaded_at, archived_at
+         FROM upload_file_meta WHERE status = 2
+         ORDER BY uploaded_at DESC, file_id"
+    )
+    .fetch_all(db_pool)
+    .await
+    .map_err(|e| {
+        error!("Failed to fetch files for retention: {}", e);
+        "Failed to fetch files for retention".to_string()
+    })?;
+
+    let mut tags: HashMap<String, Vec<String>> = HashMap::new();
+    if rules.iter().any(|rule| rule.tag.is_some()) {
+        let rows: Vec<(String, String)> = sqlx::query_as("SELECT file_id, tag FROM file_tags")
+            .fetch_all(db_pool)
+            .await
+            .map_err(|e| {
+                error!("Failed to fetch file tags for retention: {}", e);
+                "Failed to fetch file tags".to_string()
+            })?;
+        for (file_id, tag) in rows {
+            tags.entry(file_id).or_default().push(tag);
+        }
+    }
+
+    let now = chrono::Utc::now().timestamp();
+    let mut candidates: Vec<RetentionCandidate> = Vec::new();
+    let mut index: HashMap<String, usize> = HashMap::new();
+
+    for rule in &rules {
+        let in_scope = files.iter().filter(|file| {
+            match (&rule.folder_id, &rule.tag) {
+                (Some(folder_id), _) => file.folder_id == Some(*folder_id),
+                (None, Some(tag)) => tags.get(&file.file_id).is_some_and(|t| t.contains(tag)),
+                (None, None) => false,
+            }
+        });
+
+        // 文件按上传时间从新到旧排列，同名文件的前 keep_versions 个保留
+        let mut versions: HashMap<&str, i64> = HashMap::new();
+        for file in in_scope {
+            if rule.action == RetentionAction::ColdStorage && file.archived_at.is_some() {
+                continue;
+            }
+            let version = versions.entry(file.name.as_str()).or_insert(0);
+            *version += 1;
+
+            let reason = match (rule.max_age_days, rule.keep_versions) {
+                (Some(days), _) if file.uploaded_at < now - days * 86400 => {
+                    format!("Uploaded {} days ago, rule keeps {} days", (now - file.uploaded_at) / 86400, days)
+                }
+                (_, Some(keep)) if *version > keep => {
+                    format!("Version {} of '{}', rule keeps the newest {}", version, file.name, keep)
+                }
+                _ => continue,
+            };
+
+            let candidate = RetentionCandidate {
+                file_id: file.file_id.clone(),
+                filename: file.name.clone(),
+                file_path: file.file_path.clone(),
+                size: file.total_size,
+                action: rule.action,
+                rule_id: rule.id,
+                rule: rule.name.clone(),
+                reason,
+            };
+            match index.get(&file.file_id) {
+                Some(&i) if candidates[i].action == RetentionAction::ColdStorage && rule.action == RetentionAction::Delete => {
+                    candidates[i] = candidate;
+                }
+                Some(_) => {}
+                None => {
+                    index.insert(file.file_id.clone(), candidates.len());
+                    candidates.push(candidate);
+                }
+            }
+        }
+    }
+
+    // 被锁定的文件正在编辑，本轮不处理；锁定的文件仍计入版本数
+    let locked = fetch_locked_paths(db_pool).await?;
+    candidates.retain(|candidate| !locked.contains(&candidate.file_path));
+    Ok(candidates)
+}
+
+async fn remove_if_exists(path: &std::path::Path) {
+    match tokio::fs::remove_file(long_path(path)).await {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => warn!("Failed to remove {}: {}", path.display(), e),
+        _ => {}
+    }
+}
+
+/// Delete a stored file: its database records, the file itself and what was derived from it
+pub async fn delete_stored_file(db_pool: &SqlitePool, file_id: &str) -> Result<(), String> {
+    let Some(file) = fetch_uploaded_file_by_id(db_pool, file_id).await? else {
+        return Ok(());
+    };
+    let derived = fetch_derived_files(db_pool, file_id).await?;
+    record_file_usage(db_pool, file_id, -1).await;
+    if let Err(e) = delete_file_records(db_pool, file_id).await {
+        record_file_usage(db_pool, file_id, 1).await;
+        return Err(e);
+    }
+
+    // 同名重复上传会覆盖同一路径，仍被其他记录使用时只删除记录
+    if !is_file_path_referenced(db_pool, &file.file_path).await? {
+        remove_if_exists(std::path::Path::new(&file.file_path)).await;
+    }
+    for format in TRANSCODE_FORMATS {
+        remove_if_exists(&transcode_file_path(file_id, format)).await;
+    }
+    clean_up_derived_files(db_pool, &derived).await;
+    let subtitles = std::path::Path::new(SUBTITLES_DIR).join(file_id);
+    match tokio::fs::remove_dir_all(long_path(&subtitles)).await {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => warn!("Failed to remove {}: {}", subtitles.display(), e),
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Move a stored file under the cold storage directory, mirroring its relative path
+async fn move_to_cold_storage(db_pool: &SqlitePool, cold_dir: &std::path::Path, candidate: &RetentionCandidate) -> Result<(), String> {
+    let source = PathBuf::from(&candidate.file_path);
+    let relative: PathBuf = source
+        .components()
+        .filter(|c| matches!(c, std::path::Component::Normal(_)))
+        .collect();
+    let target = cold_dir.join(relative);
+
+    let moved = async {
+        if let Some(parent) = target.parent() {
+            tokio::fs::create_dir_all(long_path(parent)).await?;
+        }
+        // 冷存储常在另一块磁盘上，跨设备时改名失败则复制后删除
+        if tokio::fs::rename(long_path(&source), long_path(&target)).await.is_err() {
+            tokio::fs::copy(long_path(&source), long_path(&target)).await?;
+            tokio::fs::remove_file(long_path(&source)).await?;
+        }
+        Ok::<(), std::io::Error>(())
+    }
+    .await;
+    if let Err(e) = moved {
+        error!("Failed to move {} to {}: {}", source.display(), target.display(), e);
+        return Err(format!("Failed to move file to cold storage: {}", e));
+    }
+    update_archived_file_path(db_pool, &candidate.file_id, &path_to_string(&target)).await
+}
+
+/// Apply the rules, returning how many files were deleted and archived
+pub async fn apply_retention_rules(db_pool: &SqlitePool, config: &AppConfig) -> Result<(usize, usize), String> {
+    let (mut deleted, mut archived) = (0, 0);
+    for candidate in evaluate_rules(db_pool).await? {
+        let result = match candidate.action {
+            RetentionAction::Delete => delete_stored_file(db_pool, &candidate.file_id).await,
+            RetentionAction::ColdStorage => match &config.cold_storage_dir {
+                Some(cold_dir) => {
+                    if is_file_path_shared(db_pool, &candidate.file_path, &candidate.file_id).await? {
+                        warn!("Not archiving file ID {}: {} is shared with a newer upload", candidate.file_id, candidate.file_path);
+                        continue;
+                    }
+                    move_to_cold_storage(db_pool, cold_dir, &candidate).await
+                }
+                None => {
+                    warn!("Skipping cold storage rule '{}': NASCRAFT_COLD_STORAGE_DIR is not set", candidate.rule);
+                    continue;
+                }
+            },
+        };
+        match result {
+            Ok(()) => {
+                info!("Retention rule '{}' applied {} to file ID {}: {}", candidate.rule, candidate.action.as_str(), candidate.file_id, candidate.reason);
+                match candidate.action {
+                    RetentionAction::Delete => deleted += 1,
+                    RetentionAction::ColdStorage => archived += 1,
+                }
+            }
+            Err(e) => error!("Failed to apply retention to file ID {}: {}", candidate.file_id, e),
+        }
+    }
+    Ok((deleted, archived))
+}
+
+/// 每小时执行一次保留规则
+pub async fn run_retention_scheduler(db_pool: SqlitePool, config: SharedConfig) {
+    let mut interval = tokio::time::interval(Duration::from_secs(3600));
+    loop {
+        interval.tick().await;
+        match apply_retention_rules(&db_pool, &config.load()).await {
+            Ok((0, 0)) => {}
+            Ok((deleted, archived)) => info!("Retention cleanup deleted {} files and archived {} files", deleted, archived),
+            Err(e) => error!("Retention cleanup failed: {}", e),
+        }
+    }
+}
+
+fn invalid_rule(message: String) -> axum::response::Response {
+    error_response(StatusCode::BAD_REQUEST, "INVALID_RETENTION_RULE", message)
+}
+
+pub async fn list_retention_rules(
+    State(ctx): State<AppContext>,
+) -> impl IntoResponse {
+    match effective_rules(&ctx.app_state.db_pool).await {
+        Ok(rules) => (StatusCode::OK, Json(ApiResponse::success(rules))).into_response(),
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_RETENTION_RULES_ERROR", e),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CreateRetentionRuleRequest {
+    name: String,
+    folder_id: Option<i64>,
+    tag: Option<String>,
+    max_age_days: Option<i64>,
+    keep_versions: Option<i64>,
+    #[serde(default = "default_action")]
+    action: RetentionAction,
+}
+
+fn default_action() -> RetentionAction {
+    RetentionAction::Delete
+}
+
+pub async fn create_retention_rule(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    Json(req): Json<CreateRetentionRuleRequest>,
+) -> impl IntoResponse {
+    if let Err(resp) = ensure_unrestricted(&ctx, &client_addr).await {
+        return resp;
+    }
+    let db_pool = &ctx.app_state.db_pool;
+
+    let name = req.name.trim().to_string();
+    if name.is_empty() {
+        return invalid_rule("Rule name must not be empty".to_string());
+    }
+    let tag = req.tag.map(|t| t.trim().to_lowercase()).filter(|t| !t.is_empty());
+    if req.folder_id.is_some() == tag.is_some() {
+        return invalid_rule("Set exactly one of folder_id or tag".to_string());
+    }
+    if req.max_age_days.is_none() && req.keep_versions.is_none() {
+        return invalid_rule("Set max_age_days, keep_versions or both".to_string());
+    }
+    if req.max_age_days.is_some_and(|days| days <= 0) {
+        return invalid_rule("max_age_days must be positive".to_string());
+    }
+    if req.keep_versions.is_some_and(|keep| keep <= 0) {
+        return invalid_rule("keep_versions must be positive".to_string());
+    }
+    if req.action == RetentionAction::ColdStorage && ctx.config.load().cold_storage_dir.is_none() {
+        return invalid_rule("cold_storage needs NASCRAFT_COLD_STORAGE_DIR to be set".to_string());
+    }
+    if let Some(folder_id) = req.folder_id {
+        match fetch_folder(db_pool, folder_id).await {
+            Ok(Some(_)) => {}
+            Ok(None) => return error_response(StatusCode::NOT_FOUND, "FOLDER_NOT_FOUND", "Folder not found".to_string()),
+            Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_FOLDER_ERROR", e),
+        }
+    }
+
+    let result = sqlx::query(
+        "INSERT INTO retention_rules (name, folder_id, tag, max_age_days, keep_versions, action, created_at) VALUES (?, ?, ?, ?, ?, ?, strftime('%s', 'now'))"
+    )
+    .bind(&name)
+    .bind(req.folder_id)
+    .bind(&tag)
+    .bind(req.max_age_days)
+    .bind(req.keep_versions)
+    .bind(req.action.as_str())
+    .execute(db_pool)
+    .await;
+
+    match result {
+        Ok(done) => {
+            info!("Created retention rule '{}'", name);
+            (StatusCode::OK, Json(ApiResponse::success(RetentionRule {
+                id: Some(done.last_insert_rowid()),
+                name,
+                folder_id: req.folder_id,
+                tag,
+                max_age_days: req.max_age_days,
+                keep_versions: req.keep_versions,
+                action: req.action,
+                created_at: chrono::Utc::now().timestamp(),
+            }))).into_response()
+        }
+        Err(e) => {
+            error!("Failed to create retention rule: {}", e);
+            error_response(StatusCode::INTERNAL_SERVER_ERROR, "CREATE_RETENTION_RULE_ERROR", "Failed to create retention rule".to_string())
+        }
+    }
+}
+
+pub async fn delete_retention_rule(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    Path(id): Path<i64>,
+) -> impl IntoResponse {
+    if let Err(resp) = ensure_unrestricted(&ctx, &client_addr).await {
+        return resp;
+    }
+    match sqlx::query("DELETE FROM retention_rules WHERE id = ?").bind(id).execute(&ctx.app_state.db_pool).await {
+        Ok(done) if done.rows_affected() == 0 => error_response(StatusCode::NOT_FOUND, "RETENTION_RULE_NOT_FOUND", "Retention rule not found".to_string()),
+        Ok(_) => (StatusCode::OK, Json(ApiResponse::success(()))).into_response(),
+        Err(e) => {
+            error!("Failed to delete retention rule: {}", e);
+            error_response(StatusCode::INTERNAL_SERVER_ERROR, "DELETE_RETENTION_RULE_ERROR", "Failed to delete retention rule".to_string())
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct RetentionPreview {
+    total_files: usize,
+    total_bytes: i64,
+    files: Vec<RetentionCandidate>,
+}
+
+/// Dry run: what the next scheduled cleanup would delete or archive
+pub async fn preview_retention(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+) -> impl IntoResponse {
+    if let Err(resp) = ensure_unrestricted(&ctx, &client_addr).await {
+        return resp;
+    }
+    match evaluate_rules(&ctx.app_state.db_pool).await {
+        Ok(files) => (StatusCode::OK, Json(ApiResponse::success(RetentionPreview {
+            total_files: files.len(),
+            total_bytes: files.iter().map(|f| f.size).sum(),
+            files,
+        }))).into_response(),
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, "RETENTION_PREVIEW_ERROR", e),
+    }
+}
@@ -1,31 +1,239 @@
-use axum::{routing::{get, post}, Router};
-
-use crate::context::AppContext;
-use crate::display_remote::{
-    browse_files, discovered_devices, hello, pause_video, play_video, resume_video, stop_video,
-};
-use crate::download::{download_file, serve_thumbnail};
-use crate::ssdp::ssdp_routes;
-use crate::upload::{
-    get_uploaded_files, get_upload_status, submit_file_metadata, upload_file,
-};
-
-pub fn build_router(ctx: AppContext) -> Router {
-    let router = Router::new()
-        .route("/api/upload", post(upload_file))
-        .route("/api/submit_metadata", post(submit_file_metadata))
-        .route("/api/upload_status/:file_id", get(get_upload_status))
-        .route("/api/download/:file_id", get(download_file))
-        .route("/api/thumbnail/:file_id", get(serve_thumbnail))
-        .route("/api/uploaded_files", get(get_uploaded_files))
-        .route("/api/dlna/devices", get(discovered_devices))
-        .route("/api/dlna/play", post(play_video))
-        .route("/api/dlna/pause", post(pause_video))
-        .route("/api/dlna/resume", post(resume_video))
-        .route("/api/dlna/stop", post(stop_video))
-        .route("/api/dlna/browse", post(browse_files))
-        .route("/api/hello", get(hello))
-        .with_state(ctx);
-
-    ssdp_routes(router)
-}
+use axum::{
+    extract::{DefaultBodyLimit, Request},
+    http::{header, HeaderValue},
+    middleware::{self, Next},
+    response::Response,
+    routing::{delete, get, patch, post, put},
+    Router,
+};
+
+use nascraft::api::API_VERSION;
+use crate::bot::discord_interaction;
+use crate::context::AppContext;
+use crate::display_remote::{
+    browse_files, discovered_devices, hello, pause_video, play_video, resume_video, sse_status, stop_video,
+};
+use crate::download::{download_file, serve_thumbnail};
+use crate::download_verify::get_verification;
+use crate::file_edit::{get_file, update_file};
+use crate::extract::{extract_archive, upload_bundle};
+use crate::extract_audio::extract_audio;
+use crate::image_resize::{serve_image, share_image};
+use crate::handoff::transfer_playback;
+use crate::renderer_diagnostics::{record_media_requests, renderer_diagnostics};
+use crate::music_queue::{enqueue_tracks, list_music_queues, next_track, previous_track, set_queue_mode, start_music_queue, stop_music_queue};
+use crate::slideshow::{list_slideshows, next_slide, previous_slide, start_slideshow, stop_slideshow};
+use crate::ssdp::ssdp_routes;
+use crate::traffic::get_traffic_stats;
+use crate::analytics::get_upload_stats;
+use crate::usage::get_usage_stats;
+use crate::storage_buckets::purge_storage_bucket;
+use crate::subtitles::{list_subtitles, serve_subtitle};
+use crate::opensubtitles::fetch_subtitle_for_file;
+use crate::media_library::{scrape_library_entry, search_library};
+use crate::media_proxy::{create_proxy_link, proxy_media};
+use crate::playback::{report_playback, update_watch_state};
+use crate::series::list_series;
+use crate::delta::{get_signatures, upload_delta};
+use crate::profiles::{activate_profile, create_profile, delete_profile, list_profiles, set_file_tags};
+use crate::folders::{create_folder, delete_folder, list_folders, update_folder};
+use crate::inbox::{create_inbox, delete_inbox, inbox_page, inbox_upload, list_inboxes};
+use crate::transcode::download_transcode;
+use crate::transcode_cache::{get_transcode_cache, purge_transcode_cache};
+use crate::duplicates::{deduplicate, list_duplicates};
+use crate::file_locks::{lock_file, unlock_file};
+use crate::manifest::{export_manifest, verify_manifest};
+use crate::tenants::{create_tenant, delete_tenant, list_tenants, resolve_tenant};
+use crate::db_health::require_database;
+use crate::disk_space::refuse_writes_when_read_only;
+use crate::body_limits::limit_request_body;
+use crate::ip_allowlist::restrict_sensitive_endpoints;
+use crate::auth_failures::{guard_authentication, list_auth_failures, unlock_auth};
+use crate::backup::{check_backup, upload_backup};
+use crate::client_devices::{create_client_device, list_client_devices, resolve_client_device, revoke_client_device};
+use crate::supervisor::healthz;
+use crate::config_reload::{get_config, reload_config};
+use crate::export::export_files;
+use crate::jobs::get_job;
+use crate::hooks::{create_hook, delete_hook, list_hook_runs, list_hooks};
+use crate::io_scheduler::get_server_stats;
+use crate::web_push::{get_vapid_public_key, subscribe_push, unsubscribe_push};
+use crate::quarantine::{delete_quarantined, list_quarantine, release_quarantined};
+use crate::simple_control::{simple_devices, simple_pause, simple_play, simple_resume, simple_stop};
+use crate::shares::{
+    create_file_share, download_share, get_share, list_file_shares, reissue_file_share, revoke_file_share, share_thumbnail,
+};
+use crate::feeds::{recent_ical, recent_json_feed, recent_rss};
+use crate::devices::{create_device, delete_device, list_devices, wake_device};
+use crate::metadata_backup::{create_backup, list_backups, restore_metadata};
+use crate::retention::{create_retention_rule, delete_retention_rule, list_retention_rules, preview_retention};
+use crate::upload::{
+    abort_chunk, get_uploaded_files, get_upload_status, submit_file_metadata, upload_file,
+};
+
+/// JSON endpoints, served under `/api/v1` and under `/api` for older clients
+fn api_routes(ctx: &AppContext) -> Router<AppContext> {
+    Router::new()
+        .route("/upload", post(upload_file))
+        .route("/upload/:file_id/abort_chunk", post(abort_chunk))
+        .route("/submit_metadata", post(submit_file_metadata))
+        .route("/upload_status/:file_id", get(get_upload_status))
+        .route("/bundles", post(upload_bundle))
+        .route("/backup/check", post(check_backup))
+        .route("/backup/upload", post(upload_backup))
+        .route("/download/:file_id", get(download_file))
+        .route("/download/:file_id/verify/:verify_id", get(get_verification))
+        .route("/thumbnail/:file_id", get(serve_thumbnail))
+        .route("/uploaded_files", get(get_uploaded_files))
+        .route("/stats/traffic", get(get_traffic_stats))
+        .route("/stats/uploads", get(get_upload_stats))
+        .route("/stats/usage", get(get_usage_stats))
+        .route("/stats/usage/:bucket/purge", post(purge_storage_bucket))
+        .route("/subtitles/:file_id", get(list_subtitles))
+        .route("/subtitles/:file_id/fetch", post(fetch_subtitle_for_file))
+        .route("/subtitles/:file_id/:subtitle_id", get(serve_subtitle))
+        .route("/library/search", get(search_library))
+        .route("/library/scrape/:file_id", post(scrape_library_entry))
+        .route("/library/series", get(list_series))
+        .route("/playback/:file_id", post(report_playback))
+        .route("/files/:file_id", get(get_file).patch(update_file))
+        .route("/files/:file_id/watch_state", patch(update_watch_state))
+        .route("/files/:file_id/tags", put(set_file_tags))
+        .route("/files/:file_id/signatures", get(get_signatures))
+        .route("/files/:file_id/delta", post(upload_delta))
+        .route("/files/:file_id/transcode", get(download_transcode))
+        .route("/files/:file_id/image", get(serve_image))
+        .route("/files/:file_id/lock", post(lock_file))
+        .route("/files/:file_id/share", post(create_file_share))
+        .route("/files/:file_id/shares", get(list_file_shares))
+        .route("/files/:file_id/unlock", post(unlock_file))
+        .route("/files/:file_id/extract", post(extract_archive))
+        .route("/files/:file_id/extract_audio", post(extract_audio))
+        .route("/folders", get(list_folders).post(create_folder))
+        .route("/folders/:id", put(update_folder).delete(delete_folder))
+        .route("/folders/:id/manifest", get(export_manifest))
+        .route("/folders/:id/verify", post(verify_manifest))
+        .route("/inboxes", get(list_inboxes).post(create_inbox))
+        .route("/inboxes/:id", delete(delete_inbox))
+        .route("/quarantine", get(list_quarantine))
+        .route("/quarantine/:file_id", delete(delete_quarantined))
+        .route("/quarantine/:file_id/release", post(release_quarantined))
+        .route("/shares/:id", get(get_share))
+        .route("/shares/:id/revoke", post(revoke_file_share))
+        .route("/shares/:id/reissue", post(reissue_file_share))
+        .route("/retention/rules", get(list_retention_rules).post(create_retention_rule))
+        .route("/retention/rules/:id", delete(delete_retention_rule))
+        .route("/retention/preview", get(preview_retention))
+        .route("/admin/duplicates", get(list_duplicates))
+        .route("/admin/duplicates/dedupe", post(deduplicate))
+        .route("/admin/tenants", get(list_tenants).post(create_tenant))
+        .route("/admin/tenants/:id", delete(delete_tenant))
+        .route("/admin/config", get(get_config))
+        .route("/admin/reload_config", post(reload_config))
+        .route("/admin/stats", get(get_server_stats))
+        .route("/admin/cache", get(get_transcode_cache))
+        .route("/admin/cache/purge", post(purge_transcode_cache))
+        .route("/admin/export", post(export_files))
+        .route("/admin/auth/failures", get(list_auth_failures))
+        .route("/admin/auth/unlock", post(unlock_auth))
+        .route("/jobs/:id", get(get_job))
+        .route("/hooks", get(list_hooks).post(create_hook))
+        .route("/hooks/:id", delete(delete_hook))
+        .route("/hooks/:id/runs", get(list_hook_runs))
+        .route("/admin/backups", get(list_backups).post(create_backup))
+        .route("/admin/restore_metadata", post(restore_metadata))
+        .route("/feeds/recent.rss", get(recent_rss))
+        .route("/feeds/recent.json", get(recent_json_feed))
+        .route("/feeds/recent.ics", get(recent_ical))
+        .route("/push/vapid_public_key", get(get_vapid_public_key))
+        .route("/push/subscriptions", post(subscribe_push).delete(unsubscribe_push))
+        .route("/devices", get(list_devices).post(create_device))
+        .route("/devices/:id", delete(delete_device))
+        .route("/devices/:id/wake", post(wake_device))
+        .route("/client_devices", get(list_client_devices).post(create_client_device))
+        .route("/client_devices/:id/revoke", post(revoke_client_device))
+        .route("/profiles", get(list_profiles).post(create_profile))
+        .route("/profiles/:id", delete(delete_profile))
+        .route("/profiles/:id/activate", post(activate_profile))
+        .route("/dlna/devices", get(discovered_devices))
+        .route("/dlna/devices/:uuid/diagnostics", get(renderer_diagnostics))
+        .route("/dlna/sse_status", get(sse_status))
+        .route("/dlna/play", post(play_video))
+        .route("/dlna/pause", post(pause_video))
+        .route("/dlna/resume", post(resume_video))
+        .route("/dlna/stop", post(stop_video))
+        .route("/dlna/browse", post(browse_files))
+        .route("/dlna/transfer", post(transfer_playback))
+        .route("/media/proxy", post(create_proxy_link))
+        .route("/dlna/slideshow", get(list_slideshows).post(start_slideshow))
+        .route("/dlna/slideshow/:id/next", post(next_slide))
+        .route("/dlna/slideshow/:id/prev", post(previous_slide))
+        .route("/dlna/slideshow/:id/stop", post(stop_slideshow))
+        .route("/dlna/queue", get(list_music_queues).post(start_music_queue))
+        .route("/dlna/queue/:id", patch(set_queue_mode))
+        .route("/dlna/queue/:id/enqueue", post(enqueue_tracks))
+        .route("/dlna/queue/:id/next", post(next_track))
+        .route("/dlna/queue/:id/prev", post(previous_track))
+        .route("/dlna/queue/:id/stop", post(stop_music_queue))
+        // 请求体上限按接口在 limit_request_body 中设置，取代 axum 的默认上限
+        .layer(middleware::from_fn(limit_request_body))
+        // 磁盘空间严重不足时拒绝上传，删除等释放空间的请求照常处理
+        .layer(middleware::from_fn_with_state(ctx.clone(), refuse_writes_when_read_only))
+        .layer(DefaultBodyLimit::disable())
+        // 以上接口在多租户模式下按租户隔离；发现接口不区分租户
+        .layer(middleware::from_fn_with_state(ctx.clone(), resolve_tenant))
+        // 上传客户端的令牌可代替租户 API Key，需在识别租户之前校验
+        .layer(middleware::from_fn_with_state(ctx.clone(), resolve_client_device))
+        // 数据库不可用时直接返回 503，需在识别租户之前执行
+        .layer(middleware::from_fn_with_state(ctx.clone(), require_database))
+        // 管理接口仅允许来自指定网段的请求，最先检查
+        .layer(middleware::from_fn_with_state(ctx.clone(), restrict_sensitive_endpoints))
+        // 被锁定的客户端地址直接拒绝，并记录各认证环节标记的失败
+        .layer(middleware::from_fn_with_state(ctx.clone(), guard_authentication))
+        .route("/hello", get(hello))
+}
+
+/// Mark a response of the unversioned API as deprecated and point to its
+/// `/api/v1` successor
+async fn deprecated_alias(req: Request, next: Next) -> Response {
+    // 嵌套路由中的路径已去掉 `/api` 前缀
+    let successor = format!("</api/v{}{}>; rel=\"successor-version\"", API_VERSION, req.uri().path());
+    let mut response = next.run(req).await;
+    let headers = response.headers_mut();
+    headers.insert("Deprecation", HeaderValue::from_static("true"));
+    if let Ok(link) = HeaderValue::from_str(&successor) {
+        headers.insert(header::LINK, link);
+    }
+    response
+}
+
+pub fn build_router(ctx: AppContext) -> Router {
+    let api = api_routes(&ctx);
+    let router = Router::new()
+        .nest(&format!("/api/v{}", API_VERSION), api.clone())
+        .nest("/api", api.layer(middleware::from_fn(deprecated_alias)))
+        .route("/healthz", get(healthz))
+        // 分享与收件箱链接无需 API key 或租户，token 即凭证；Discord 请求与代理链接由签名校验，/simple 由查询参数 key 校验
+        .merge(
+            Router::new()
+                // 渲染器从分享链接取媒体，记录其请求供 /dlna/devices/:uuid/diagnostics 排查
+                .route("/share/:token", get(download_share).layer(middleware::from_fn(record_media_requests)))
+                .route("/share/:token/thumbnail", get(share_thumbnail))
+                .route("/share/:token/image", get(share_image).layer(middleware::from_fn(record_media_requests)))
+                .route("/media/proxy", get(proxy_media).layer(middleware::from_fn(record_media_requests)))
+                .route("/inbox/:token", get(inbox_page).post(inbox_upload).layer(middleware::from_fn_with_state(ctx.clone(), refuse_writes_when_read_only)))
+                .route("/bot/discord/interactions", post(discord_interaction))
+                .route("/simple/play", get(simple_play))
+                .route("/simple/pause", get(simple_pause))
+                .route("/simple/resume", get(simple_resume))
+                .route("/simple/stop", get(simple_stop))
+                .route("/simple/devices", get(simple_devices))
+                .layer(middleware::from_fn_with_state(ctx.clone(), require_database))
+                .layer(middleware::from_fn_with_state(ctx.clone(), guard_authentication)),
+        )
+        .with_state(ctx.clone());
+
+    #[cfg(feature = "mock-renderer")]
+    let router = crate::mock_renderer::mock_renderer_routes(router, &ctx.config.load());
+    ssdp_routes(router)
+}
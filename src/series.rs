@@ -0,0 +1,153 @@
+use axum::{
+    extract::{ConnectInfo, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+use crate::context::AppContext;
+use crate::helper::{error_response, ApiResponse};
+use crate::library_query::LibraryQuery;
+use crate::media_library::{attach_media_titles, parse_media_name};
+use crate::playback::{fetch_watch_states, WatchState};
+use crate::thumbnail::is_video_file;
+use crate::traffic::client_principal;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EpisodeEntry {
+    pub file_id: String,
+    pub season: i64,
+    pub episode: i64,
+    pub episode_title: Option<String>,
+    pub filename: String,
+    pub watch_state: WatchState,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SeasonEntry {
+    pub season: i64,
+    pub episodes: Vec<EpisodeEntry>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SeriesEntry {
+    pub title: String,
+    pub tmdb_id: Option<i64>,
+    pub year: Option<i64>,
+    pub poster_url: Option<String>,
+    pub episode_count: usize,
+    pub seasons: Vec<SeasonEntry>,
+    /// First unwatched episode after the last watched one, None when caught up
+    pub next_unwatched: Option<EpisodeEntry>,
+}
+
+/// Show-level fields plus episodes keyed by (season, episode)
+struct SeriesBuilder {
+    title: String,
+    tmdb_id: Option<i64>,
+    year: Option<i64>,
+    poster_url: Option<String>,
+    episodes: BTreeMap<(i64, i64), EpisodeEntry>,
+}
+
+impl SeriesBuilder {
+    fn build(self) -> SeriesEntry {
+        let episodes: Vec<EpisodeEntry> = self.episodes.into_values().collect();
+        let resume_from = episodes.iter().rposition(|e| e.watch_state == WatchState::Watched).map(|i| i + 1).unwrap_or(0);
+        let next_unwatched = episodes[resume_from..].iter().find(|e| e.watch_state != WatchState::Watched).cloned();
+
+        let mut seasons: Vec<SeasonEntry> = Vec::new();
+        let episode_count = episodes.len();
+        for episode in episodes {
+            match seasons.last_mut() {
+                Some(season) if season.season == episode.season => season.episodes.push(episode),
+                _ => seasons.push(SeasonEntry { season: episode.season, episodes: vec![episode] }),
+            }
+        }
+
+        SeriesEntry {
+            title: self.title,
+            tmdb_id: self.tmdb_id,
+            year: self.year,
+            poster_url: self.poster_url,
+            episode_count,
+            seasons,
+            next_unwatched,
+        }
+    }
+}
+
+/// List shows → seasons → episodes from scraped metadata, falling back to
+/// filename parsing for videos that haven't been scraped
+pub async fn list_series(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+) -> impl IntoResponse {
+    let db_pool = &ctx.app_state.db_pool;
+    let principal = client_principal(&client_addr);
+
+    let mut files = match LibraryQuery::for_client(db_pool, &ctx.config.load(), &principal).await {
+        Ok(library) => match library.fetch(db_pool).await {
+            Ok(files) => files,
+            Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_SERIES_ERROR", e),
+        },
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "PROFILE_CHECK_ERROR", e),
+    };
+    attach_media_titles(db_pool, &mut files).await;
+
+    let watch_states = match fetch_watch_states(db_pool, &principal).await {
+        Ok(states) => states,
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_SERIES_ERROR", e),
+    };
+
+    // 有 TMDB id 的按 id 分组，否则按小写片名分组
+    let mut shows: HashMap<String, SeriesBuilder> = HashMap::new();
+    for file in files {
+        let name = file.original_filename.clone().unwrap_or_else(|| file.filename.clone());
+        let media = file.media;
+        let (title, season, episode) = match &media {
+            Some(m) => match (m.media_type.as_str(), m.season, m.episode) {
+                ("episode", Some(season), Some(episode)) => (m.title.clone(), season, episode),
+                _ => continue,
+            },
+            None if is_video_file(&file.filename) => {
+                let parsed = parse_media_name(&name);
+                match (parsed.season, parsed.episode) {
+                    (Some(season), Some(episode)) => (parsed.title, season, episode),
+                    _ => continue,
+                }
+            }
+            None => continue,
+        };
+
+        let tmdb_id = media.as_ref().and_then(|m| m.tmdb_id);
+        let key = match tmdb_id {
+            Some(id) => format!("tmdb:{}", id),
+            None => format!("title:{}", title.to_lowercase()),
+        };
+        let show = shows.entry(key).or_insert_with(|| SeriesBuilder {
+            title: title.clone(),
+            tmdb_id,
+            year: None,
+            poster_url: None,
+            episodes: BTreeMap::new(),
+        });
+        show.year = show.year.or(media.as_ref().and_then(|m| m.year));
+        show.poster_url = show.poster_url.take().or(media.as_ref().and_then(|m| m.poster_url.clone()));
+        show.episodes.insert((season, episode), EpisodeEntry {
+            watch_state: watch_states.get(&file.file_id).copied().unwrap_or(WatchState::Unwatched),
+            file_id: file.file_id,
+            season,
+            episode,
+            episode_title: media.and_then(|m| m.episode_title),
+            filename: name,
+        });
+    }
+
+    let mut series: Vec<SeriesEntry> = shows.into_values().map(SeriesBuilder::build).collect();
+    series.sort_by_key(|s| s.title.to_lowercase());
+
+    (StatusCode::OK, Json(ApiResponse::success(series))).into_response()
+}
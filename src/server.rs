@@ -1,18 +1,81 @@
-use axum::Router;
-use log::error;
-use log::info;
-
-pub async fn serve_http(app: Router, server_port: u16) -> std::io::Result<()> {
-    let bind_addr = format!("0.0.0.0:{}", server_port);
-    info!("Binding HTTP listener: addr={}", bind_addr);
-    let listener = tokio::net::TcpListener::bind(&bind_addr).await?;
-
-    tokio::spawn(async move {
-        info!("HTTP server started");
-        if let Err(e) = axum::serve(listener, app).await {
-            error!("Main server error: {}", e);
-        }
-    });
-
-    Ok(())
-}
+use axum::{extract::ConnectInfo, http::Request, Router};
+use hyper::body::Incoming;
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper_util::rt::{TokioIo, TokioTimer};
+use log::{debug, error, info, warn};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use tower::ServiceExt;
+use crate::config::AppConfig;
+
+/// Pause after a failed accept, e.g. when out of file descriptors
+const ACCEPT_ERROR_DELAY: Duration = Duration::from_secs(1);
+
+/// Bind the HTTP port at startup, so a port in use stops the server before it
+/// reports that it is running
+pub fn bind_http(server_port: u16) -> std::io::Result<Arc<std::net::TcpListener>> {
+    let bind_addr = format!("0.0.0.0:{}", server_port);
+    info!("Binding HTTP listener: addr={}", bind_addr);
+    let listener = std::net::TcpListener::bind(&bind_addr)?;
+    listener.set_nonblocking(true)?;
+    Ok(Arc::new(listener))
+}
+
+/// Serve on the bound listener; a restarted server accepts on the same socket.
+/// Connections are served with the limits of `NASCRAFT_MAX_CONNECTIONS`,
+/// `NASCRAFT_KEEP_ALIVE` and `NASCRAFT_CLIENT_TIMEOUT_SECS`.
+pub async fn serve_http(app: Router, listener: Arc<std::net::TcpListener>, config: Arc<AppConfig>) {
+    let listener = match listener.try_clone().and_then(tokio::net::TcpListener::from_std) {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("Failed to use HTTP listener: {}", e);
+            return;
+        }
+    };
+    info!("HTTP server started");
+    let connections = (config.max_connections > 0).then(|| Arc::new(Semaphore::new(config.max_connections)));
+    let mut builder = http1::Builder::new();
+    builder
+        .timer(TokioTimer::new())
+        .keep_alive(config.keep_alive)
+        .header_read_timeout(Duration::from_secs(config.client_timeout_secs));
+
+    loop {
+        // 达到连接上限时暂停接受新连接，它们在内核的队列中等待
+        let permit = match &connections {
+            Some(connections) => match connections.clone().acquire_owned().await {
+                Ok(permit) => Some(permit),
+                Err(_) => return,
+            },
+            None => None,
+        };
+        let (stream, remote_addr) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                // 文件描述符耗尽等错误稍后重试，而不是让服务退出
+                warn!("Failed to accept HTTP connection: {}", e);
+                tokio::time::sleep(ACCEPT_ERROR_DELAY).await;
+                continue;
+            }
+        };
+        // Connections are kept alive between requests; without Nagle's algorithm the
+        // small responses of range-heavy clients (network boot, hypervisors) aren't delayed
+        let _ = stream.set_nodelay(true);
+
+        let app = app.clone();
+        let service = service_fn(move |mut req: Request<Incoming>| {
+            // Handlers use ConnectInfo for traffic accounting and per-client state
+            req.extensions_mut().insert(ConnectInfo(remote_addr));
+            app.clone().oneshot(req)
+        });
+        let connection = builder.serve_connection(TokioIo::new(stream), service).with_upgrades();
+        tokio::spawn(async move {
+            if let Err(e) = connection.await {
+                debug!("HTTP connection from {} ended: {}", remote_addr, e);
+            }
+            drop(permit);
+        });
+    }
+}
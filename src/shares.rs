@@ -0,0 +1,605 @@
+use axum::{
+    extract::{ConnectInfo, Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{Html, IntoResponse, Response},
+    Json,
+};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, SqlitePool};
+use std::net::SocketAddr;
+use uuid::Uuid;
+use crate::config::AppConfig;
+use crate::context::AppContext;
+use crate::download::{stream_file, thumbnail_response};
+use crate::helper::{error_response, xml_escape, ApiResponse};
+use crate::metadata_scrub::scrubbed_response;
+use crate::partial_playback::stream_unfinished_upload;
+use crate::profiles::ensure_file_allowed;
+use crate::repository::UploadRepository;
+use crate::traffic::client_principal;
+use crate::upload_dao::UploadedFile;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ShareKind {
+    /// Created by a user for one file
+    Link,
+    /// Created for a feed item and reused by every later feed
+    Feed,
+}
+
+impl ShareKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            ShareKind::Link => "link",
+            ShareKind::Feed => "feed",
+        }
+    }
+
+    fn parse(value: &str) -> Self {
+        match value {
+            "feed" => ShareKind::Feed,
+            _ => ShareKind::Link,
+        }
+    }
+}
+
+#[derive(Debug, FromRow)]
+struct ShareRow {
+    id: i64,
+    token: String,
+    file_id: String,
+    kind: String,
+    created_at: i64,
+    expires_at: Option<i64>,
+    revoked_at: Option<i64>,
+    require_email: bool,
+    strip_metadata: bool,
+}
+
+/// A link that serves one file to anyone holding its token, without the
+/// client's profile or tenant
+#[derive(Debug, Clone, Serialize)]
+pub struct Share {
+    pub id: i64,
+    pub token: String,
+    pub file_id: String,
+    pub kind: ShareKind,
+    #[serde(with = "crate::api::timestamp")]
+    pub created_at: i64,
+    #[serde(with = "crate::api::timestamp::option")]
+    pub expires_at: Option<i64>,
+    /// Revoked links answer `410` but keep their download history
+    #[serde(with = "crate::api::timestamp::option")]
+    pub revoked_at: Option<i64>,
+    /// Ask for the downloader's email address before serving the file
+    pub require_email: bool,
+    /// Serve photos and videos without EXIF, GPS and other embedded metadata
+    pub strip_metadata: bool,
+}
+
+impl From<ShareRow> for Share {
+    fn from(row: ShareRow) -> Self {
+        Share {
+            id: row.id,
+            token: row.token,
+            file_id: row.file_id,
+            kind: ShareKind::parse(&row.kind),
+            created_at: row.created_at,
+            expires_at: row.expires_at,
+            revoked_at: row.revoked_at,
+            require_email: row.require_email,
+            strip_metadata: row.strip_metadata,
+        }
+    }
+}
+
+impl Share {
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|at| at <= chrono::Utc::now().timestamp())
+    }
+
+    pub fn url(&self, base_url: &str) -> String {
+        format!("{}/share/{}", base_url, self.token)
+    }
+}
+
+/// Base of absolute links handed out to clients: `NASCRAFT_PUBLIC_URL`, or the
+/// scheme and host the request was made to
+pub fn public_base_url(config: &AppConfig, headers: &HeaderMap) -> String {
+    if let Some(url) = &config.public_url {
+        return url.clone();
+    }
+    let forwarded = |name: &str| headers.get(name).and_then(|v| v.to_str().ok()).and_then(|v| v.split(',').next()).map(str::trim);
+    let scheme = forwarded("X-Forwarded-Proto").filter(|p| *p == "https").unwrap_or("http");
+    let host = forwarded("X-Forwarded-Host")
+        .or_else(|| headers.get(header::HOST).and_then(|v| v.to_str().ok()))
+        .map(str::to_string)
+        .unwrap_or_else(|| format!("localhost:{}", config.server_port));
+    format!("{}://{}", scheme, host)
+}
+
+const SHARE_COLUMNS: &str = "id, token, file_id, kind, created_at, expires_at, revoked_at, require_email, strip_metadata";
+
+pub async fn fetch_share_by_token(db_pool: &SqlitePool, token: &str) -> Result<Option<Share>, String> {
+    sqlx::query_as::<_, ShareRow>(&format!("SELECT {} FROM shares WHERE token = ?", SHARE_COLUMNS))
+        .bind(token)
+        .fetch_optional(db_pool)
+        .await
+        .map(|row| row.map(Share::from))
+        .map_err(|e| {
+            error!("Failed to fetch share: {}", e);
+            "Failed to fetch share".to_string()
+        })
+}
+
+async fn fetch_share(db_pool: &SqlitePool, id: i64) -> Result<Option<Share>, String> {
+    sqlx::query_as::<_, ShareRow>(&format!("SELECT {} FROM shares WHERE id = ?", SHARE_COLUMNS))
+        .bind(id)
+        .fetch_optional(db_pool)
+        .await
+        .map(|row| row.map(Share::from))
+        .map_err(|e| {
+            error!("Failed to fetch share {}: {}", id, e);
+            "Failed to fetch share".to_string()
+        })
+}
+
+/// How a new share link behaves; the default never expires and serves the
+/// file as stored to anyone
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ShareOptions {
+    pub expires_at: Option<i64>,
+    pub require_email: bool,
+    pub strip_metadata: bool,
+}
+
+pub async fn create_share(db_pool: &SqlitePool, file_id: &str, kind: ShareKind, options: ShareOptions) -> Result<Share, String> {
+    sqlx::query_as::<_, ShareRow>(&format!(
+        "INSERT INTO shares (token, file_id, kind, created_at, expires_at, require_email, strip_metadata)
+         VALUES (?, ?, ?, strftime('%s', 'now'), ?, ?, ?)
+         RETURNING {}",
+        SHARE_COLUMNS
+    ))
+    .bind(Uuid::new_v4().simple().to_string())
+    .bind(file_id)
+    .bind(kind.as_str())
+    .bind(options.expires_at)
+    .bind(options.require_email)
+    .bind(options.strip_metadata)
+    .fetch_one(db_pool)
+    .await
+    .map(Share::from)
+    .map_err(|e| {
+        error!("Failed to create share for {}: {}", file_id, e);
+        "Failed to create share".to_string()
+    })
+}
+
+/// The file's feed share, created on first use so feed links stay stable
+/// until it is revoked
+pub async fn feed_share(db_pool: &SqlitePool, file_id: &str) -> Result<Share, String> {
+    let existing = sqlx::query_as::<_, ShareRow>(&format!(
+        "SELECT {} FROM shares WHERE file_id = ? AND kind = 'feed' AND revoked_at IS NULL ORDER BY id LIMIT 1",
+        SHARE_COLUMNS
+    ))
+    .bind(file_id)
+    .fetch_optional(db_pool)
+    .await
+    .map_err(|e| {
+        error!("Failed to fetch feed share: {}", e);
+        "Failed to fetch feed share".to_string()
+    })?;
+    match existing {
+        Some(row) => Ok(Share::from(row)),
+        None => create_share(db_pool, file_id, ShareKind::Feed, ShareOptions::default()).await,
+    }
+}
+
+#[derive(Deserialize, Default)]
+pub struct CreateShareRequest {
+    /// None creates a link that doesn't expire
+    expires_in_hours: Option<i64>,
+    #[serde(default)]
+    require_email: bool,
+    #[serde(default)]
+    strip_metadata: bool,
+}
+
+#[derive(Serialize)]
+struct CreatedShare {
+    #[serde(flatten)]
+    share: Share,
+    url: String,
+}
+
+pub async fn create_file_share(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    Path(file_id): Path<String>,
+    headers: HeaderMap,
+    req: Option<Json<CreateShareRequest>>,
+) -> impl IntoResponse {
+    if let Err(resp) = ensure_file_allowed(&ctx, &client_addr, &file_id).await {
+        return resp;
+    }
+    let req = req.map(|Json(req)| req).unwrap_or_default();
+    if req.expires_in_hours.is_some_and(|hours| hours <= 0) {
+        return error_response(StatusCode::BAD_REQUEST, "INVALID_SHARE", "expires_in_hours must be positive".to_string());
+    }
+
+    let db_pool = &ctx.app_state.db_pool;
+    match db_pool.fetch_uploaded_file(&file_id).await {
+        Ok(Some(file)) if file.status == 2 => {}
+        Ok(_) => return error_response(StatusCode::NOT_FOUND, "FILE_NOT_FOUND", "Only completed files can be shared".to_string()),
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_FILE_ERROR", e),
+    }
+
+    let options = ShareOptions {
+        expires_at: req.expires_in_hours.map(|hours| chrono::Utc::now().timestamp() + hours * 3600),
+        require_email: req.require_email,
+        strip_metadata: req.strip_metadata,
+    };
+    match create_share(db_pool, &file_id, ShareKind::Link, options).await {
+        Ok(share) => {
+            info!("Created share {} for file {}", share.id, file_id);
+            let url = share.url(&public_base_url(&ctx.config.load(), &headers));
+            (StatusCode::OK, Json(ApiResponse::success(CreatedShare { share, url }))).into_response()
+        }
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, "CREATE_SHARE_ERROR", e),
+    }
+}
+
+/// The share behind a token and its completed file, or the response for a
+/// missing, expired, revoked or broken link
+pub async fn shared_file(ctx: &AppContext, token: &str) -> Result<(Share, UploadedFile), Response> {
+    match shared_upload(ctx, token).await? {
+        (share, file) if file.status == 2 => Ok((share, file)),
+        _ => Err((StatusCode::NOT_FOUND, "File not found").into_response()),
+    }
+}
+
+/// Like `shared_file`, but also a file still uploading, which playback links
+/// to it stream as it arrives
+async fn shared_upload(ctx: &AppContext, token: &str) -> Result<(Share, UploadedFile), Response> {
+    let db_pool = &ctx.app_state.db_pool;
+    let share = match fetch_share_by_token(db_pool, token).await {
+        Ok(Some(share)) => share,
+        Ok(None) => return Err((StatusCode::NOT_FOUND, "Share not found").into_response()),
+        Err(e) => return Err((StatusCode::INTERNAL_SERVER_ERROR, e).into_response()),
+    };
+    if share.revoked_at.is_some() {
+        return Err((StatusCode::GONE, "Share has been revoked").into_response());
+    }
+    if share.is_expired() {
+        return Err((StatusCode::GONE, "Share has expired").into_response());
+    }
+    match db_pool.fetch_uploaded_file(&share.file_id).await {
+        Ok(Some(file)) if matches!(file.status, 0..=2) => Ok((share, file)),
+        Ok(_) => Err((StatusCode::NOT_FOUND, "File not found").into_response()),
+        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e).into_response()),
+    }
+}
+
+const EMAIL_PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>{name}</title>
+<style>
+body { font-family: sans-serif; max-width: 36em; margin: 2em auto; padding: 0 1em; }
+.failed { color: #b00; }
+</style>
+</head>
+<body>
+<h1>{name}</h1>
+<p>Enter your email address to download this file.</p>
+<p class="failed">{error}</p>
+<form method="get">
+<input type="email" name="email" required>
+<button type="submit">Download</button>
+</form>
+</body>
+</html>
+"#;
+
+fn is_valid_email(email: &str) -> bool {
+    match email.split_once('@') {
+        Some((local, domain)) => !local.is_empty() && domain.contains('.') && !domain.starts_with('.') && !domain.ends_with('.') && !email.contains(char::is_whitespace),
+        None => false,
+    }
+}
+
+/// Whether a request reads the file from its start, so the seeks and resumed
+/// ranges of a player count as one download
+fn is_new_download(headers: &HeaderMap) -> bool {
+    match headers.get(header::RANGE).and_then(|v| v.to_str().ok()) {
+        Some(range) => range.trim().starts_with("bytes=0-"),
+        None => true,
+    }
+}
+
+async fn record_download(db_pool: &SqlitePool, share_id: i64, principal: &str, email: Option<&str>) {
+    if let Err(e) = sqlx::query(
+        "INSERT INTO share_downloads (share_id, principal, email, downloaded_at) VALUES (?, ?, ?, strftime('%s', 'now'))",
+    )
+    .bind(share_id)
+    .bind(principal)
+    .bind(email)
+    .execute(db_pool)
+    .await
+    {
+        error!("Failed to record download of share {}: {}", share_id, e);
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ShareQuery {
+    email: Option<String>,
+}
+
+/// Download a shared file. Public: the token is the only credential. Links
+/// that require an email answer `403` with a form asking for it until the
+/// request carries `?email=`.
+pub async fn download_share(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    Path(token): Path<String>,
+    Query(query): Query<ShareQuery>,
+    headers: HeaderMap,
+) -> Response {
+    let (share, file) = match shared_upload(&ctx, &token).await {
+        Ok(shared) => shared,
+        Err(resp) => return resp,
+    };
+    let email = query.email.as_deref().map(str::trim).filter(|email| !email.is_empty());
+    if share.require_email && !email.is_some_and(is_valid_email) {
+        let name = file.original_filename.as_deref().unwrap_or(&file.filename);
+        let error = match email {
+            Some(_) => "That doesn't look like an email address.",
+            None => "",
+        };
+        return (StatusCode::FORBIDDEN, Html(EMAIL_PAGE.replace("{name}", &xml_escape(name)).replace("{error}", error))).into_response();
+    }
+
+    if file.status != 2 {
+        // 边上传边播放：元数据清理需要完整文件，此时不提供
+        if share.strip_metadata {
+            return (StatusCode::NOT_FOUND, "File not found").into_response();
+        }
+        return stream_unfinished_upload(&ctx, &client_addr, &file, &headers).await;
+    }
+    let scrubbed = match share.strip_metadata {
+        true => scrubbed_response(&ctx, &client_addr, &file, &headers).await,
+        false => None,
+    };
+    let resp = match scrubbed {
+        Some(resp) => resp,
+        None => stream_file(&ctx, &client_addr, &file, &headers).await,
+    };
+    if resp.status().is_success() && is_new_download(&headers) {
+        let email = email.filter(|_| share.require_email);
+        record_download(&ctx.app_state.db_pool, share.id, &client_principal(&client_addr), email).await;
+    }
+    resp
+}
+
+pub async fn share_thumbnail(
+    State(ctx): State<AppContext>,
+    Path(token): Path<String>,
+) -> Response {
+    match shared_file(&ctx, &token).await {
+        Ok((_, file)) => thumbnail_response(&file).await,
+        Err(resp) => resp,
+    }
+}
+
+#[derive(Debug, FromRow)]
+struct ShareStatsRow {
+    #[sqlx(flatten)]
+    share: ShareRow,
+    downloads: i64,
+    unique_downloaders: i64,
+    last_downloaded_at: Option<i64>,
+}
+
+const SHARE_STATS_COLUMNS: &str = "id, token, file_id, kind, created_at, expires_at, revoked_at, require_email, strip_metadata,
+    (SELECT COUNT(*) FROM share_downloads d WHERE d.share_id = shares.id) AS downloads,
+    (SELECT COUNT(DISTINCT d.principal) FROM share_downloads d WHERE d.share_id = shares.id) AS unique_downloaders,
+    (SELECT MAX(d.downloaded_at) FROM share_downloads d WHERE d.share_id = shares.id) AS last_downloaded_at";
+
+/// A share with its link and download counts, as listed to whoever may see the file
+#[derive(Serialize)]
+struct ShareSummary {
+    #[serde(flatten)]
+    share: Share,
+    url: String,
+    downloads: i64,
+    unique_downloaders: i64,
+    #[serde(with = "crate::api::timestamp::option")]
+    last_downloaded_at: Option<i64>,
+}
+
+impl ShareSummary {
+    fn new(row: ShareStatsRow, base_url: &str) -> Self {
+        let share = Share::from(row.share);
+        ShareSummary {
+            url: share.url(base_url),
+            share,
+            downloads: row.downloads,
+            unique_downloaders: row.unique_downloaders,
+            last_downloaded_at: row.last_downloaded_at,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, FromRow)]
+struct ShareDownload {
+    principal: String,
+    email: Option<String>,
+    #[serde(with = "crate::api::timestamp")]
+    downloaded_at: i64,
+}
+
+/// Most recent downloads listed for one share; the counts cover all of them
+const MAX_LISTED_DOWNLOADS: i64 = 1000;
+
+#[derive(Serialize)]
+struct ShareDetails {
+    #[serde(flatten)]
+    summary: ShareSummary,
+    recent_downloads: Vec<ShareDownload>,
+}
+
+/// The share with the given id, when the client may see its file
+async fn ensure_share_allowed(ctx: &AppContext, client_addr: &SocketAddr, id: i64) -> Result<Share, Response> {
+    let share = match fetch_share(&ctx.app_state.db_pool, id).await {
+        Ok(Some(share)) => share,
+        Ok(None) => return Err(error_response(StatusCode::NOT_FOUND, "SHARE_NOT_FOUND", format!("Share {} not found", id))),
+        Err(e) => return Err(error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_SHARE_ERROR", e)),
+    };
+    ensure_file_allowed(ctx, client_addr, &share.file_id).await?;
+    Ok(share)
+}
+
+/// Every share of a file, newest first, with download counts
+pub async fn list_file_shares(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    Path(file_id): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    if let Err(resp) = ensure_file_allowed(&ctx, &client_addr, &file_id).await {
+        return resp;
+    }
+    let rows = sqlx::query_as::<_, ShareStatsRow>(&format!(
+        "SELECT {} FROM shares WHERE file_id = ? ORDER BY id DESC",
+        SHARE_STATS_COLUMNS
+    ))
+    .bind(&file_id)
+    .fetch_all(&ctx.app_state.db_pool)
+    .await;
+    match rows {
+        Ok(rows) => {
+            let base_url = public_base_url(&ctx.config.load(), &headers);
+            let shares: Vec<ShareSummary> = rows.into_iter().map(|row| ShareSummary::new(row, &base_url)).collect();
+            (StatusCode::OK, Json(ApiResponse::success(shares))).into_response()
+        }
+        Err(e) => {
+            error!("Failed to list shares of {}: {}", file_id, e);
+            error_response(StatusCode::INTERNAL_SERVER_ERROR, "LIST_SHARES_ERROR", "Failed to list shares".to_string())
+        }
+    }
+}
+
+/// One share with its download counts and who downloaded it when
+pub async fn get_share(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    Path(id): Path<i64>,
+    headers: HeaderMap,
+) -> Response {
+    if let Err(resp) = ensure_share_allowed(&ctx, &client_addr, id).await {
+        return resp;
+    }
+    let db_pool = &ctx.app_state.db_pool;
+    let row = sqlx::query_as::<_, ShareStatsRow>(&format!("SELECT {} FROM shares WHERE id = ?", SHARE_STATS_COLUMNS))
+        .bind(id)
+        .fetch_one(db_pool)
+        .await;
+    let downloads = sqlx::query_as::<_, ShareDownload>(
+        "SELECT principal, email, downloaded_at FROM share_downloads WHERE share_id = ? ORDER BY id DESC LIMIT ?",
+    )
+    .bind(id)
+    .bind(MAX_LISTED_DOWNLOADS)
+    .fetch_all(db_pool)
+    .await;
+    match (row, downloads) {
+        (Ok(row), Ok(recent_downloads)) => {
+            let summary = ShareSummary::new(row, &public_base_url(&ctx.config.load(), &headers));
+            (StatusCode::OK, Json(ApiResponse::success(ShareDetails { summary, recent_downloads }))).into_response()
+        }
+        (Err(e), _) | (_, Err(e)) => {
+            error!("Failed to fetch downloads of share {}: {}", id, e);
+            error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_SHARE_ERROR", "Failed to fetch share downloads".to_string())
+        }
+    }
+}
+
+async fn revoke_share(db_pool: &SqlitePool, id: i64) -> Result<Share, String> {
+    sqlx::query_as::<_, ShareRow>(&format!(
+        "UPDATE shares SET revoked_at = COALESCE(revoked_at, strftime('%s', 'now')) WHERE id = ? RETURNING {}",
+        SHARE_COLUMNS
+    ))
+    .bind(id)
+    .fetch_one(db_pool)
+    .await
+    .map(Share::from)
+    .map_err(|e| {
+        error!("Failed to revoke share {}: {}", id, e);
+        "Failed to revoke share".to_string()
+    })
+}
+
+/// Stop a link from working. Revoking twice keeps the first revocation time.
+pub async fn revoke_file_share(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    Path(id): Path<i64>,
+) -> Response {
+    if let Err(resp) = ensure_share_allowed(&ctx, &client_addr, id).await {
+        return resp;
+    }
+    match revoke_share(&ctx.app_state.db_pool, id).await {
+        Ok(share) => {
+            info!("Revoked share {} for file {}", share.id, share.file_id);
+            (StatusCode::OK, Json(ApiResponse::success_with_message("Share revoked", share))).into_response()
+        }
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, "REVOKE_SHARE_ERROR", e),
+    }
+}
+
+/// Replace a link with a new token: the old one is revoked and the new one
+/// requires an email and strips metadata when the old one did. It is valid
+/// for `expires_in_hours`, or for as long as the old link was.
+pub async fn reissue_file_share(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    Path(id): Path<i64>,
+    headers: HeaderMap,
+    req: Option<Json<CreateShareRequest>>,
+) -> Response {
+    let old = match ensure_share_allowed(&ctx, &client_addr, id).await {
+        Ok(share) => share,
+        Err(resp) => return resp,
+    };
+    let req = req.map(|Json(req)| req).unwrap_or_default();
+    if req.expires_in_hours.is_some_and(|hours| hours <= 0) {
+        return error_response(StatusCode::BAD_REQUEST, "INVALID_SHARE", "expires_in_hours must be positive".to_string());
+    }
+
+    let db_pool = &ctx.app_state.db_pool;
+    let now = chrono::Utc::now().timestamp();
+    let expires_at = match req.expires_in_hours {
+        Some(hours) => Some(now + hours * 3600),
+        None => old.expires_at.map(|at| now + (at - old.created_at).max(0)),
+    };
+    let options = ShareOptions {
+        expires_at,
+        require_email: old.require_email || req.require_email,
+        strip_metadata: old.strip_metadata || req.strip_metadata,
+    };
+    let created = match create_share(db_pool, &old.file_id, old.kind, options).await {
+        Ok(share) => share,
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "CREATE_SHARE_ERROR", e),
+    };
+    if let Err(e) = revoke_share(db_pool, old.id).await {
+        return error_response(StatusCode::INTERNAL_SERVER_ERROR, "REVOKE_SHARE_ERROR", e);
+    }
+
+    info!("Reissued share {} of file {} as share {}", old.id, old.file_id, created.id);
+    let url = created.url(&public_base_url(&ctx.config.load(), &headers));
+    (StatusCode::OK, Json(ApiResponse::success(CreatedShare { share: created, url }))).into_response()
+}
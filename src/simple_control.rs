@@ -0,0 +1,145 @@
+//! Playback control over plain GET requests with the key in the query, for
+//! IFTTT applets and voice assistant webhooks that can't send headers or JSON,
+//! e.g. `/simple/play?key=...&device=living room&title=the matrix`.
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Deserialize;
+use serde_json::json;
+use crate::auth_failures::{locked_response, AuthFailure};
+use crate::bot::{find_file, play_file};
+use crate::context::AppContext;
+use crate::feeds::item_title;
+use crate::helper::{error_response, ApiResponse};
+use crate::renderer::{discover_renderers, take_renderer, Renderer};
+
+#[derive(Deserialize)]
+pub struct SimpleQuery {
+    key: Option<String>,
+    device: Option<String>,
+    title: Option<String>,
+}
+
+/// Account failed `key`s are counted against
+const SIMPLE_ACCOUNT: &str = "simple";
+
+/// `404` while `NASCRAFT_SIMPLE_API_KEY` is unset, `401` unless `key` matches
+/// it, `429` while too many wrong keys locked the API
+async fn ensure_simple_key(ctx: &AppContext, query: &SimpleQuery) -> Result<(), Response> {
+    let Some(key) = &ctx.config.load().simple_api_key else {
+        return Err(error_response(StatusCode::NOT_FOUND, "SIMPLE_API_DISABLED", "The simple control API is disabled".to_string()));
+    };
+    if let Some(until) = ctx.auth_failures.account_locked_until(SIMPLE_ACCOUNT) {
+        return Err(locked_response(until));
+    }
+    if query.key.as_ref() == Some(key) {
+        return Ok(());
+    }
+    let response = error_response(StatusCode::UNAUTHORIZED, "INVALID_KEY", "Missing or invalid key".to_string());
+    Err(AuthFailure::new("simple_key", Some(SIMPLE_ACCOUNT.to_string())).attach(response))
+}
+
+fn required<'a>(value: &'a Option<String>, name: &str) -> Result<&'a str, String> {
+    match value.as_deref().map(str::trim) {
+        Some(value) if !value.is_empty() => Ok(value),
+        _ => Err(format!("Query parameter '{}' is required", name)),
+    }
+}
+
+/// Renderers on the LAN, or a `502` when discovery failed
+async fn renderers() -> Result<Vec<Renderer>, Response> {
+    discover_renderers().await.map_err(|e| error_response(StatusCode::BAD_GATEWAY, "DISCOVERY_ERROR", e))
+}
+
+/// The renderer on the LAN named by `device`, or a `404`
+async fn find_device(device: &str) -> Result<Renderer, Response> {
+    let renderers = renderers().await?;
+    take_renderer(renderers, device).map_err(|e| error_response(StatusCode::NOT_FOUND, "DEVICE_NOT_FOUND", e))
+}
+
+/// Play the file whose name or title best matches `title` on `device`
+pub async fn simple_play(State(ctx): State<AppContext>, Query(query): Query<SimpleQuery>) -> Response {
+    if let Err(resp) = ensure_simple_key(&ctx, &query).await {
+        return resp;
+    }
+    let params = required(&query.device, "device").and_then(|device| Ok((device, required(&query.title, "title")?)));
+    let (device, title) = match params {
+        Ok(params) => params,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, "MISSING_PARAMETER", e),
+    };
+    let file = match find_file(&ctx.app_state.db_pool, title).await {
+        Ok(file) => file,
+        Err(e) => return error_response(StatusCode::NOT_FOUND, "FILE_NOT_FOUND", e),
+    };
+    let renderer = match find_device(device).await {
+        Ok(renderer) => renderer,
+        Err(resp) => return resp,
+    };
+
+    match play_file(&ctx, &file, &renderer).await {
+        Ok(message) => (StatusCode::OK, Json(ApiResponse::success_with_message(&message, json!({
+            "file_id": file.file_id,
+            "title": item_title(&file),
+            "device": renderer.name,
+        })))).into_response(),
+        Err(e) => error_response(StatusCode::BAD_GATEWAY, "PLAY_ERROR", e),
+    }
+}
+
+/// Send `action` (`pause`, `resume` or `stop`) to the renderer named by `device`
+async fn transport_action(ctx: &AppContext, query: &SimpleQuery, action: &str) -> Response {
+    if let Err(resp) = ensure_simple_key(ctx, query).await {
+        return resp;
+    }
+    let device = match required(&query.device, "device") {
+        Ok(device) => device,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, "MISSING_PARAMETER", e),
+    };
+    let renderer = match find_device(device).await {
+        Ok(renderer) => renderer,
+        Err(resp) => return resp,
+    };
+
+    let result = match action {
+        "pause" => renderer.pause().await,
+        "resume" => renderer.resume().await,
+        _ => renderer.stop().await,
+    };
+    match result {
+        Ok(()) => {
+            let message = format!("Sent {} to {}", action, renderer.name);
+            (StatusCode::OK, Json(ApiResponse::success_with_message(&message, json!({ "device": renderer.name })))).into_response()
+        }
+        Err(e) => error_response(StatusCode::BAD_GATEWAY, "CONTROL_ERROR", e),
+    }
+}
+
+pub async fn simple_pause(State(ctx): State<AppContext>, Query(query): Query<SimpleQuery>) -> Response {
+    transport_action(&ctx, &query, "pause").await
+}
+
+pub async fn simple_resume(State(ctx): State<AppContext>, Query(query): Query<SimpleQuery>) -> Response {
+    transport_action(&ctx, &query, "resume").await
+}
+
+pub async fn simple_stop(State(ctx): State<AppContext>, Query(query): Query<SimpleQuery>) -> Response {
+    transport_action(&ctx, &query, "stop").await
+}
+
+/// Names of the renderers on the LAN, as `device` accepts them
+pub async fn simple_devices(State(ctx): State<AppContext>, Query(query): Query<SimpleQuery>) -> Response {
+    if let Err(resp) = ensure_simple_key(&ctx, &query).await {
+        return resp;
+    }
+    match renderers().await {
+        Ok(renderers) => {
+            let names: Vec<&str> = renderers.iter().map(|r| r.name.as_str()).collect();
+            (StatusCode::OK, Json(ApiResponse::success(names))).into_response()
+        }
+        Err(resp) => resp,
+    }
+}
@@ -0,0 +1,278 @@
+//! Photo slideshows on UPnP renderers: the server pushes the images of a folder
+//! or tag to the renderer one after another with SetAVTransportURI, and
+//! clients step through them or stop the show while it runs. A renderer shows
+//! one slideshow at a time; starting another replaces it.
+
+use axum::{
+    extract::{ConnectInfo, Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use crate::bot::{lan_base_url, share_link, PLAYBACK_LINK_HOURS};
+use crate::context::AppContext;
+use crate::feeds::item_title;
+use crate::folders::fetch_visible_folder;
+use crate::helper::{error_response, ApiResponse};
+use crate::library_query::LibraryQuery;
+use crate::profiles::normalize_tag;
+use crate::renderer::{discover_renderers, take_renderer, Renderer};
+use crate::thumbnail::is_image_file;
+use crate::traffic::client_principal;
+use crate::upload_dao::UploadedFile;
+
+const DEFAULT_INTERVAL_SECONDS: u64 = 10;
+const MIN_INTERVAL_SECONDS: u64 = 2;
+const MAX_INTERVAL_SECONDS: u64 = 3600;
+
+/// Size images are scaled to before they are pushed, see `image_resize`
+const SLIDE_WIDTH: u32 = 1920;
+const SLIDE_HEIGHT: u32 = 1080;
+
+/// Images in a row the renderer may refuse before the slideshow ends
+const MAX_FAILURES: u32 = 3;
+
+#[derive(Debug, Clone, Copy)]
+enum SlideCommand {
+    Next,
+    Previous,
+    Stop,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SlideshowStatus {
+    pub id: u64,
+    pub device: String,
+    pub interval_seconds: u64,
+    pub repeat: bool,
+    pub total: usize,
+    /// Index of the image shown, from 0
+    pub position: usize,
+    pub file_id: String,
+    pub title: String,
+    #[serde(with = "crate::api::timestamp")]
+    pub started_at: i64,
+}
+
+struct Slideshow {
+    location: String,
+    status: SlideshowStatus,
+    commands: mpsc::UnboundedSender<SlideCommand>,
+}
+
+/// Running slideshows by id
+#[derive(Default)]
+pub struct Slideshows {
+    next_id: AtomicU64,
+    running: Mutex<HashMap<u64, Slideshow>>,
+}
+
+impl Slideshows {
+    fn list(&self) -> Vec<SlideshowStatus> {
+        let mut list: Vec<SlideshowStatus> = self.running.lock().unwrap().values().map(|s| s.status.clone()).collect();
+        list.sort_by_key(|s| s.id);
+        list
+    }
+
+    fn send(&self, id: u64, command: SlideCommand) -> bool {
+        self.running
+            .lock()
+            .unwrap()
+            .get(&id)
+            .is_some_and(|s| s.commands.send(command).is_ok())
+    }
+
+    fn set_position(&self, id: u64, position: usize, file: &UploadedFile) {
+        if let Some(slideshow) = self.running.lock().unwrap().get_mut(&id) {
+            slideshow.status.position = position;
+            slideshow.status.file_id = file.file_id.clone();
+            slideshow.status.title = item_title(file);
+        }
+    }
+
+    fn remove(&self, id: u64) {
+        self.running.lock().unwrap().remove(&id);
+    }
+}
+
+/// Push a scaled copy of an image to the renderer
+async fn show(ctx: &AppContext, renderer: &Renderer, file: &UploadedFile) -> Result<(), String> {
+    let config = ctx.config.load();
+    let url = share_link(ctx, file, PLAYBACK_LINK_HOURS, &lan_base_url(ctx, &config)).await?;
+    let url = format!("{}/image?w={}&h={}", url, SLIDE_WIDTH, SLIDE_HEIGHT);
+    renderer.play_url(&url, &item_title(file), "image/jpeg").await
+}
+
+async fn run_slideshow(
+    ctx: AppContext,
+    id: u64,
+    renderer: Renderer,
+    files: Vec<UploadedFile>,
+    interval: Duration,
+    repeat: bool,
+    mut commands: mpsc::UnboundedReceiver<SlideCommand>,
+) {
+    let slideshows = ctx.slideshows.clone();
+    let mut position = 0;
+    let mut failures = 0;
+    loop {
+        let file = &files[position];
+        slideshows.set_position(id, position, file);
+        // 单张图片失败时跳到下一张，连续失败说明渲染器已离线，结束放映
+        match show(&ctx, &renderer, file).await {
+            Ok(()) => failures = 0,
+            Err(e) => {
+                warn!("Slideshow {} couldn't show {}: {}", id, file.file_id, e);
+                failures += 1;
+                if failures >= MAX_FAILURES {
+                    break;
+                }
+            }
+        }
+
+        // 通道关闭表示被同一渲染器上的新放映取代，不再发送 Stop
+        let command = tokio::select! {
+            _ = tokio::time::sleep(interval) => Some(SlideCommand::Next),
+            command = commands.recv() => command,
+        };
+        let Some(command) = command else {
+            break;
+        };
+        let next = match command {
+            SlideCommand::Next if position + 1 < files.len() => Some(position + 1),
+            SlideCommand::Next => repeat.then_some(0),
+            SlideCommand::Previous if position > 0 => Some(position - 1),
+            SlideCommand::Previous => Some(if repeat { files.len() - 1 } else { 0 }),
+            SlideCommand::Stop => {
+                if let Err(e) = renderer.stop().await {
+                    warn!("Failed to stop slideshow {} on {}: {}", id, renderer.name, e);
+                }
+                None
+            }
+        };
+        match next {
+            Some(next) => position = next,
+            None => break,
+        }
+    }
+    slideshows.remove(id);
+    info!("Slideshow {} on {} ended", id, renderer.name);
+}
+
+#[derive(Deserialize)]
+pub struct SlideshowRequest {
+    /// Renderer name, matched like `/simple/play`'s `device`
+    device: String,
+    folder_id: Option<i64>,
+    tag: Option<String>,
+    interval_seconds: Option<u64>,
+    /// Start over after the last image instead of ending
+    #[serde(default)]
+    repeat: bool,
+}
+
+/// Start a slideshow of the images in a folder and/or with a tag
+pub async fn start_slideshow(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    Json(req): Json<SlideshowRequest>,
+) -> Response {
+    let db_pool = &ctx.app_state.db_pool;
+    let interval_seconds = req.interval_seconds.unwrap_or(DEFAULT_INTERVAL_SECONDS);
+    if !(MIN_INTERVAL_SECONDS..=MAX_INTERVAL_SECONDS).contains(&interval_seconds) {
+        return error_response(StatusCode::BAD_REQUEST, "INVALID_SLIDESHOW", format!(
+            "interval_seconds must be between {} and {}",
+            MIN_INTERVAL_SECONDS, MAX_INTERVAL_SECONDS
+        ));
+    }
+    let tag = req.tag.as_deref().map(normalize_tag).filter(|t| !t.is_empty());
+    if req.folder_id.is_none() && tag.is_none() {
+        return error_response(StatusCode::BAD_REQUEST, "INVALID_SLIDESHOW", "Set folder_id, tag or both".to_string());
+    }
+
+    // 只放映客户端档案与租户可见的图片
+    let mut library = match LibraryQuery::for_client(db_pool, &ctx.config.load(), &client_principal(&client_addr)).await {
+        Ok(library) => library,
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_PROFILE_ERROR", e),
+    };
+    if let Some(folder_id) = req.folder_id {
+        match fetch_visible_folder(db_pool, folder_id).await {
+            Ok(Some(_)) => library = library.folder(folder_id),
+            Ok(None) => return error_response(StatusCode::NOT_FOUND, "FOLDER_NOT_FOUND", "Folder not found".to_string()),
+            Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_FOLDER_ERROR", e),
+        }
+    }
+    if let Some(tag) = &tag {
+        library = library.tag(tag);
+    }
+    let files: Vec<UploadedFile> = match library.fetch(db_pool).await {
+        Ok(files) => files.into_iter().filter(|f| is_image_file(&f.filename)).collect(),
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_FILES_ERROR", e),
+    };
+    if files.is_empty() {
+        return error_response(StatusCode::NOT_FOUND, "NO_IMAGES", "No images match the slideshow".to_string());
+    }
+
+    let renderers = match discover_renderers().await {
+        Ok(renderers) => renderers,
+        Err(e) => return error_response(StatusCode::BAD_GATEWAY, "DISCOVERY_ERROR", e),
+    };
+    let renderer = match take_renderer(renderers, &req.device) {
+        Ok(renderer) => renderer,
+        Err(e) => return error_response(StatusCode::NOT_FOUND, "DEVICE_NOT_FOUND", e),
+    };
+
+    let id = ctx.slideshows.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+    let (commands, receiver) = mpsc::unbounded_channel();
+    let status = SlideshowStatus {
+        id,
+        device: renderer.name.clone(),
+        interval_seconds,
+        repeat: req.repeat,
+        total: files.len(),
+        position: 0,
+        file_id: files[0].file_id.clone(),
+        title: item_title(&files[0]),
+        started_at: chrono::Utc::now().timestamp(),
+    };
+    {
+        let mut running = ctx.slideshows.running.lock().unwrap();
+        running.retain(|_, s| s.location != renderer.location);
+        running.insert(id, Slideshow { location: renderer.location.clone(), status: status.clone(), commands });
+    }
+    info!("Starting slideshow {} of {} images on {}", id, files.len(), renderer.name);
+    tokio::spawn(run_slideshow(ctx.clone(), id, renderer, files, Duration::from_secs(interval_seconds), req.repeat, receiver));
+
+    (StatusCode::OK, Json(ApiResponse::success(status))).into_response()
+}
+
+pub async fn list_slideshows(State(ctx): State<AppContext>) -> Response {
+    (StatusCode::OK, Json(ApiResponse::success(ctx.slideshows.list()))).into_response()
+}
+
+async fn control(ctx: &AppContext, id: u64, command: SlideCommand) -> Response {
+    if !ctx.slideshows.send(id, command) {
+        return error_response(StatusCode::NOT_FOUND, "SLIDESHOW_NOT_FOUND", "Slideshow not found or ended".to_string());
+    }
+    (StatusCode::OK, Json(ApiResponse::<()>::success(()))).into_response()
+}
+
+pub async fn next_slide(State(ctx): State<AppContext>, Path(id): Path<u64>) -> Response {
+    control(&ctx, id, SlideCommand::Next).await
+}
+
+pub async fn previous_slide(State(ctx): State<AppContext>, Path(id): Path<u64>) -> Response {
+    control(&ctx, id, SlideCommand::Previous).await
+}
+
+pub async fn stop_slideshow(State(ctx): State<AppContext>, Path(id): Path<u64>) -> Response {
+    control(&ctx, id, SlideCommand::Stop).await
+}
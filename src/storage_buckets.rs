@@ -0,0 +1,255 @@
+//! Space used besides the stored files: chunks of unfinished uploads and other
+//! temporary files, transcoded copies and thumbnails. `/stats/usage` reports
+//! each bucket, and each can be purged of what no upload or file needs.
+
+use axum::{
+    extract::{ConnectInfo, Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use log::{error, info, warn};
+use serde::Serialize;
+use sqlx::SqlitePool;
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::path::{Path as FsPath, PathBuf};
+use std::time::Duration;
+use crate::context::AppContext;
+use crate::helper::{error_response, ApiResponse};
+use crate::paths::{CHUNKS_DIR, TRANSCODED_DIR, UPLOADS_DIR};
+use crate::profiles::ensure_unrestricted;
+use crate::tenants::ensure_not_tenant;
+use crate::thumbnail::ThumbnailConfig;
+use crate::transcode_cache::evict_all;
+
+const BUCKET_CHUNKS: &str = "chunks";
+pub const BUCKET_TRANSCODES: &str = "transcodes";
+const BUCKET_THUMBNAILS: &str = "thumbnails";
+
+/// Temporary files of delta and bundle uploads younger than this may still be
+/// in use and are kept by a purge
+const TEMP_FILE_MIN_AGE: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, Serialize)]
+pub struct StorageBucket {
+    pub name: &'static str,
+    pub files: u64,
+    pub bytes: u64,
+}
+
+/// What a purge removed
+#[derive(Debug, Default, Serialize)]
+pub struct PurgeResult {
+    pub bucket: String,
+    pub files: u64,
+    pub bytes: u64,
+}
+
+impl PurgeResult {
+    fn add(&mut self, (files, bytes): (u64, u64)) {
+        self.files += files;
+        self.bytes += bytes;
+    }
+}
+
+fn thumbnails_dir() -> PathBuf {
+    PathBuf::from(ThumbnailConfig::default().thumbnails_dir)
+}
+
+/// Temporary file of a delta (`.<name>.delta-<id>`) or bundle (`.bundle-<id>`) upload
+fn is_temp_file(name: &str) -> bool {
+    name.starts_with(".bundle-") || (name.starts_with('.') && name.contains(".delta-"))
+}
+
+/// Number and size of the files below `path`; missing paths count as empty
+fn path_usage(path: &FsPath) -> (u64, u64) {
+    let Ok(metadata) = std::fs::symlink_metadata(path) else {
+        return (0, 0);
+    };
+    if !metadata.is_dir() {
+        return (1, metadata.len());
+    }
+    let Ok(entries) = std::fs::read_dir(path) else {
+        return (0, 0);
+    };
+    entries.flatten().fold((0, 0), |(files, bytes), entry| {
+        let (f, b) = path_usage(&entry.path());
+        (files + f, bytes + b)
+    })
+}
+
+/// Temporary files directly in the uploads directory, with their age
+fn temp_files() -> Vec<(PathBuf, Duration)> {
+    let Ok(entries) = std::fs::read_dir(UPLOADS_DIR) else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .filter(|entry| is_temp_file(&entry.file_name().to_string_lossy()))
+        .map(|entry| {
+            let age = entry.metadata().and_then(|m| m.modified()).ok().and_then(|t| t.elapsed().ok()).unwrap_or_default();
+            (entry.path(), age)
+        })
+        .collect()
+}
+
+fn measure_sync() -> Vec<StorageBucket> {
+    let (mut chunk_files, mut chunk_bytes) = path_usage(FsPath::new(CHUNKS_DIR));
+    for (path, _) in temp_files() {
+        let (files, bytes) = path_usage(&path);
+        chunk_files += files;
+        chunk_bytes += bytes;
+    }
+    let (transcode_files, transcode_bytes) = path_usage(FsPath::new(TRANSCODED_DIR));
+    let (thumbnail_files, thumbnail_bytes) = path_usage(&thumbnails_dir());
+    vec![
+        StorageBucket { name: BUCKET_CHUNKS, files: chunk_files, bytes: chunk_bytes },
+        StorageBucket { name: BUCKET_TRANSCODES, files: transcode_files, bytes: transcode_bytes },
+        StorageBucket { name: BUCKET_THUMBNAILS, files: thumbnail_files, bytes: thumbnail_bytes },
+    ]
+}
+
+/// Size of each bucket, measured on disk
+pub async fn measure_buckets() -> Vec<StorageBucket> {
+    tokio::task::spawn_blocking(measure_sync).await.unwrap_or_else(|e| {
+        error!("Failed to measure storage buckets: {}", e);
+        Vec::new()
+    })
+}
+
+/// Remove `path` and everything below it, adding what was removed to `result`
+fn remove_path(path: &FsPath, result: &mut PurgeResult) {
+    let usage = path_usage(path);
+    let removed = if path.is_dir() { std::fs::remove_dir_all(path) } else { std::fs::remove_file(path) };
+    match removed {
+        Ok(()) => result.add(usage),
+        Err(e) => warn!("Failed to purge {}: {}", path.display(), e),
+    }
+}
+
+/// Children of `dir`, or none when it doesn't exist
+fn dir_entries(dir: &FsPath) -> Vec<std::fs::DirEntry> {
+    std::fs::read_dir(dir).map(|entries| entries.flatten().collect()).unwrap_or_default()
+}
+
+/// Chunk directories of uploads that no longer exist or have finished, and
+/// temporary files left by interrupted delta and bundle uploads
+async fn purge_chunks(db_pool: &SqlitePool) -> Result<PurgeResult, String> {
+    let unfinished: HashSet<String> = sqlx::query_scalar::<_, String>("SELECT file_id FROM upload_file_meta WHERE status IN (0, 1)")
+        .fetch_all(db_pool)
+        .await
+        .map_err(|e| {
+            error!("Failed to fetch unfinished uploads: {}", e);
+            "Failed to fetch unfinished uploads".to_string()
+        })?
+        .into_iter()
+        .collect();
+
+    tokio::task::spawn_blocking(move || {
+        let mut result = PurgeResult { bucket: BUCKET_CHUNKS.to_string(), ..Default::default() };
+        for entry in dir_entries(FsPath::new(CHUNKS_DIR)) {
+            if !unfinished.contains(entry.file_name().to_string_lossy().as_ref()) {
+                remove_path(&entry.path(), &mut result);
+            }
+        }
+        for (path, age) in temp_files() {
+            if age >= TEMP_FILE_MIN_AGE {
+                remove_path(&path, &mut result);
+            }
+        }
+        result
+    })
+    .await
+    .map_err(|e| format!("Failed to purge chunks: {}", e))
+}
+
+/// Every transcoded copy except those of transcodes still running; evicted
+/// transcodes are made again when requested
+async fn purge_transcodes(db_pool: &SqlitePool) -> Result<PurgeResult, String> {
+    let evicted = evict_all(db_pool, None).await?;
+    let pending: HashSet<String> = sqlx::query_scalar::<_, String>("SELECT file_id FROM transcodes WHERE status = 'pending'")
+        .fetch_all(db_pool)
+        .await
+        .map_err(|e| {
+            error!("Failed to fetch pending transcodes: {}", e);
+            "Failed to fetch pending transcodes".to_string()
+        })?
+        .into_iter()
+        .collect();
+
+    // 剩下的是没有记录对应的文件，如转码中断留下的临时文件
+    tokio::task::spawn_blocking(move || {
+        let mut result = evicted;
+        for entry in dir_entries(FsPath::new(TRANSCODED_DIR)) {
+            // 文件名以 file_id 开头，进行中的转码写入 <file_id>.partial.<format>
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let file_id = name.split('.').next().unwrap_or_default();
+            if !pending.contains(file_id) {
+                remove_path(&entry.path(), &mut result);
+            }
+        }
+        result
+    })
+    .await
+    .map_err(|e| format!("Failed to purge transcodes: {}", e))
+}
+
+/// Resized image variants, which are recreated on request, and thumbnails no
+/// file refers to
+async fn purge_thumbnails(db_pool: &SqlitePool) -> Result<PurgeResult, String> {
+    let referenced: HashSet<String> = sqlx::query_scalar::<_, String>(
+        "SELECT thumbnail_path FROM upload_file_meta WHERE thumbnail_path IS NOT NULL"
+    )
+    .fetch_all(db_pool)
+    .await
+    .map_err(|e| {
+        error!("Failed to fetch thumbnail paths: {}", e);
+        "Failed to fetch thumbnail paths".to_string()
+    })?
+    .iter()
+    .filter_map(|path| FsPath::new(path).file_name().map(|name| name.to_string_lossy().into_owned()))
+    .collect();
+
+    tokio::task::spawn_blocking(move || {
+        let mut result = PurgeResult { bucket: BUCKET_THUMBNAILS.to_string(), ..Default::default() };
+        for entry in dir_entries(&thumbnails_dir()) {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if entry.path().is_dir() || !referenced.contains(&name) {
+                remove_path(&entry.path(), &mut result);
+            }
+        }
+        result
+    })
+    .await
+    .map_err(|e| format!("Failed to purge thumbnails: {}", e))
+}
+
+/// Free the space of one bucket that no upload or file needs. Unrestricted
+/// profiles outside tenants only.
+pub async fn purge_storage_bucket(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    Path(bucket): Path<String>,
+) -> Response {
+    if let Err(resp) = ensure_not_tenant().await {
+        return resp;
+    }
+    if let Err(resp) = ensure_unrestricted(&ctx, &client_addr).await {
+        return resp;
+    }
+    let db_pool = &ctx.app_state.db_pool;
+    let result = match bucket.as_str() {
+        BUCKET_CHUNKS => purge_chunks(db_pool).await,
+        BUCKET_TRANSCODES => purge_transcodes(db_pool).await,
+        BUCKET_THUMBNAILS => purge_thumbnails(db_pool).await,
+        _ => return error_response(StatusCode::NOT_FOUND, "BUCKET_NOT_FOUND", format!("Unknown storage bucket '{}'", bucket)),
+    };
+    match result {
+        Ok(result) => {
+            info!("Purged {} files ({} bytes) from the {} bucket", result.files, result.bytes, result.bucket);
+            (StatusCode::OK, Json(ApiResponse::success(result))).into_response()
+        }
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, "PURGE_ERROR", e),
+    }
+}
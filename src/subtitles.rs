@@ -0,0 +1,156 @@
+use axum::{
+    extract::{ConnectInfo, Path, State},
+    http::{header, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use log::error;
+use serde::Serialize;
+use sqlx::{FromRow, SqlitePool};
+use std::net::SocketAddr;
+use crate::context::AppContext;
+use crate::helper::{error_response, ApiResponse};
+use crate::paths::long_path;
+use crate::profiles::ensure_file_allowed;
+
+/// A subtitle file attached to an uploaded video, with where it came from
+#[derive(Debug, Serialize, FromRow)]
+pub struct Subtitle {
+    pub id: i64,
+    pub file_id: String,
+    pub language: String,
+    pub format: String,
+    #[serde(skip_serializing)]
+    pub file_path: String,
+    /// Provider the subtitle was fetched from, e.g. `opensubtitles`
+    pub source: String,
+    /// Identifier of the subtitle on the provider side
+    pub source_ref: Option<String>,
+    pub release_name: Option<String>,
+    /// Whether the provider matched it by file hash rather than by name
+    pub hash_match: bool,
+    #[serde(with = "crate::api::timestamp")]
+    pub created_at: i64,
+}
+
+/// Subtitle about to be stored; the file is already written to `file_path`
+pub struct NewSubtitle<'a> {
+    pub file_id: &'a str,
+    pub language: &'a str,
+    pub format: &'a str,
+    pub file_path: &'a str,
+    pub source: &'a str,
+    pub source_ref: Option<&'a str>,
+    pub release_name: Option<&'a str>,
+    pub hash_match: bool,
+}
+
+/// 保存字幕记录，返回新记录的 id
+pub async fn save_subtitle(db_pool: &SqlitePool, subtitle: &NewSubtitle<'_>) -> Result<i64, String> {
+    match sqlx::query(
+        "INSERT INTO subtitles (file_id, language, format, file_path, source, source_ref, release_name, hash_match, created_at) VALUES (?, ?, ?, ?, ?, ?, ?, ?, strftime('%s', 'now'))"
+    )
+    .bind(subtitle.file_id)
+    .bind(subtitle.language)
+    .bind(subtitle.format)
+    .bind(subtitle.file_path)
+    .bind(subtitle.source)
+    .bind(subtitle.source_ref)
+    .bind(subtitle.release_name)
+    .bind(subtitle.hash_match)
+    .execute(db_pool)
+    .await
+    {
+        Ok(result) => Ok(result.last_insert_rowid()),
+        Err(e) => {
+            error!("Failed to save subtitle: {}", e);
+            Err("Failed to save subtitle".to_string())
+        }
+    }
+}
+
+pub async fn fetch_subtitles(db_pool: &SqlitePool, file_id: &str) -> Result<Vec<Subtitle>, String> {
+    sqlx::query_as::<_, Subtitle>(
+        "SELECT id, file_id, language, format, file_path, source, source_ref, release_name, hash_match, created_at FROM subtitles WHERE file_id = ? ORDER BY id"
+    )
+    .bind(file_id)
+    .fetch_all(db_pool)
+    .await
+    .map_err(|e| {
+        error!("Failed to fetch subtitles: {}", e);
+        "Failed to fetch subtitles".to_string()
+    })
+}
+
+pub async fn fetch_subtitle(db_pool: &SqlitePool, file_id: &str, id: i64) -> Result<Option<Subtitle>, String> {
+    sqlx::query_as::<_, Subtitle>(
+        "SELECT id, file_id, language, format, file_path, source, source_ref, release_name, hash_match, created_at FROM subtitles WHERE file_id = ? AND id = ?"
+    )
+    .bind(file_id)
+    .bind(id)
+    .fetch_optional(db_pool)
+    .await
+    .map_err(|e| {
+        error!("Failed to fetch subtitle: {}", e);
+        "Failed to fetch subtitle".to_string()
+    })
+}
+
+/// Find a subtitle previously fetched from the same provider entry
+pub async fn fetch_subtitle_by_source(db_pool: &SqlitePool, file_id: &str, source: &str, source_ref: &str) -> Result<Option<Subtitle>, String> {
+    sqlx::query_as::<_, Subtitle>(
+        "SELECT id, file_id, language, format, file_path, source, source_ref, release_name, hash_match, created_at FROM subtitles WHERE file_id = ? AND source = ? AND source_ref = ?"
+    )
+    .bind(file_id)
+    .bind(source)
+    .bind(source_ref)
+    .fetch_optional(db_pool)
+    .await
+    .map_err(|e| {
+        error!("Failed to fetch subtitle by source: {}", e);
+        "Failed to fetch subtitle".to_string()
+    })
+}
+
+pub async fn list_subtitles(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    Path(file_id): Path<String>,
+) -> impl IntoResponse {
+    if let Err(resp) = ensure_file_allowed(&ctx, &client_addr, &file_id).await {
+        return resp;
+    }
+    match fetch_subtitles(&ctx.app_state.db_pool, &file_id).await {
+        Ok(subtitles) => (StatusCode::OK, Json(ApiResponse::success(subtitles))).into_response(),
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_SUBTITLES_ERROR", e),
+    }
+}
+
+pub async fn serve_subtitle(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    Path((file_id, id)): Path<(String, i64)>,
+) -> impl IntoResponse {
+    if let Err(resp) = ensure_file_allowed(&ctx, &client_addr, &file_id).await {
+        return resp;
+    }
+    let subtitle = match fetch_subtitle(&ctx.app_state.db_pool, &file_id, id).await {
+        Ok(Some(subtitle)) => subtitle,
+        Ok(None) => return (StatusCode::NOT_FOUND, "Subtitle not found").into_response(),
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
+    };
+
+    match tokio::fs::read(long_path(std::path::Path::new(&subtitle.file_path))).await {
+        Ok(content) => {
+            let content_type = match subtitle.format.as_str() {
+                "vtt" => "text/vtt; charset=utf-8",
+                _ => "application/x-subrip; charset=utf-8",
+            };
+            (StatusCode::OK, [(header::CONTENT_TYPE, content_type)], content).into_response()
+        }
+        Err(e) => {
+            error!("Failed to read subtitle {}: {}", subtitle.file_path, e);
+            (StatusCode::NOT_FOUND, "Subtitle file missing").into_response()
+        }
+    }
+}
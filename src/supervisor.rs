@@ -0,0 +1,133 @@
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use log::{error, info};
+use serde::Serialize;
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use crate::context::AppContext;
+use crate::helper::ApiResponse;
+
+const MIN_RESTART_DELAY: Duration = Duration::from_secs(1);
+const MAX_RESTART_DELAY: Duration = Duration::from_secs(300);
+/// A task that ran this long before failing restarts after the minimum delay again
+const STABLE_RUN: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskState {
+    Running,
+    /// Failed and waiting to be restarted
+    Restarting,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TaskHealth {
+    name: &'static str,
+    state: TaskState,
+    restarts: u32,
+    last_error: Option<String>,
+    /// When the current run started
+    #[serde(with = "crate::api::timestamp")]
+    started_at: i64,
+}
+
+/// Runs the long-lived background tasks and restarts them with exponential
+/// backoff when they panic or return
+#[derive(Default)]
+pub struct Supervisor {
+    tasks: Mutex<BTreeMap<&'static str, TaskHealth>>,
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => match payload.downcast::<&'static str>() {
+            Ok(message) => message.to_string(),
+            Err(_) => "non-string panic payload".to_string(),
+        },
+    }
+}
+
+impl Supervisor {
+    /// Run `task` under supervision. It is called again for every restart, so
+    /// it must build a fresh future each time.
+    pub fn spawn<F, Fut>(self: &Arc<Self>, name: &'static str, task: F)
+    where
+        F: Fn() -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let supervisor = self.clone();
+        tokio::spawn(async move {
+            let mut delay = MIN_RESTART_DELAY;
+            loop {
+                supervisor.mark_running(name);
+                let started = Instant::now();
+                // 任务在单独的 tokio 任务中运行，panic 只会结束该任务
+                let reason = match tokio::spawn(task()).await {
+                    Ok(()) => "stopped".to_string(),
+                    Err(e) if e.is_panic() => format!("panicked: {}", panic_message(e.into_panic())),
+                    Err(e) => format!("was cancelled: {}", e),
+                };
+                if started.elapsed() >= STABLE_RUN {
+                    delay = MIN_RESTART_DELAY;
+                }
+                error!("Background task {} {} after {:?}, restarting in {:?}", name, reason, started.elapsed(), delay);
+                supervisor.mark_restarting(name, reason);
+                tokio::time::sleep(delay).await;
+                delay = (delay * 2).min(MAX_RESTART_DELAY);
+            }
+        });
+    }
+
+    fn mark_running(&self, name: &'static str) {
+        let mut tasks = self.tasks.lock().unwrap();
+        let task = tasks.entry(name).or_insert_with(|| TaskHealth {
+            name,
+            state: TaskState::Running,
+            restarts: 0,
+            last_error: None,
+            started_at: 0,
+        });
+        if task.started_at != 0 {
+            task.restarts += 1;
+            info!("Restarting background task {} (restart {})", name, task.restarts);
+        }
+        task.state = TaskState::Running;
+        task.started_at = chrono::Utc::now().timestamp();
+    }
+
+    fn mark_restarting(&self, name: &'static str, reason: String) {
+        if let Some(task) = self.tasks.lock().unwrap().get_mut(name) {
+            task.state = TaskState::Restarting;
+            task.last_error = Some(reason);
+        }
+    }
+
+    pub fn report(&self) -> Vec<TaskHealth> {
+        self.tasks.lock().unwrap().values().cloned().collect()
+    }
+}
+
+#[derive(Serialize)]
+struct HealthReport {
+    healthy: bool,
+    database: bool,
+    tasks: Vec<TaskHealth>,
+}
+
+/// Liveness of the database and the supervised background tasks. Answers 503
+/// while any of them is down, so it can back a container health check.
+pub async fn healthz(State(ctx): State<AppContext>) -> impl IntoResponse {
+    let tasks = ctx.supervisor.report();
+    let database = ctx.db_health.is_available();
+    let healthy = database && tasks.iter().all(|t| t.state == TaskState::Running);
+    let status = if healthy { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    (status, Json(ApiResponse::success(HealthReport { healthy, database, tasks }))).into_response()
+}
@@ -0,0 +1,584 @@
+use axum::{
+    extract::{Path, Request, State},
+    http::{header, HeaderMap, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, SqlitePool};
+use uuid::Uuid;
+use nascraft::api::DEVICE_TOKEN_HEADER;
+use crate::auth_failures::{locked_response, AuthFailure};
+use crate::client_devices::current_client_device;
+use crate::upload_hints::signed_chunk;
+use crate::context::AppContext;
+use crate::helper::{error_response, secret_matches, ApiResponse};
+use crate::paths::{long_path, UPLOADS_DIR};
+
+/// Header carrying a tenant's API key
+pub const API_KEY_HEADER: &str = "X-Api-Key";
+
+/// Header carrying `NASCRAFT_ADMIN_KEY`
+pub const ADMIN_KEY_HEADER: &str = "X-Admin-Key";
+
+/// Account failed admin key attempts are counted against
+const ADMIN_ACCOUNT: &str = "admin";
+
+/// Directory under `uploads/` holding one subdirectory per tenant
+const TENANTS_DIR: &str = "tenants";
+
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct Tenant {
+    pub id: i64,
+    /// Subdomain the tenant's requests may be sent to and name of its storage directory
+    pub slug: String,
+    pub name: String,
+    #[serde(skip)]
+    pub api_key: String,
+    pub quota_bytes: Option<i64>,
+    #[serde(with = "crate::api::timestamp")]
+    pub created_at: i64,
+}
+
+impl Tenant {
+    /// Directory under `uploads/` the tenant's files and folders are stored in
+    pub fn storage_dir(&self) -> String {
+        format!("{}/{}", TENANTS_DIR, self.slug)
+    }
+}
+
+tokio::task_local! {
+    static CURRENT_TENANT: Tenant;
+}
+
+/// Tenant the current request was made for. None in single-tenant mode, for
+/// requests made with the admin key and outside request handling (background
+/// jobs), which all see every tenant's files.
+pub fn current_tenant() -> Option<Tenant> {
+    CURRENT_TENANT.try_with(Tenant::clone).ok()
+}
+
+pub fn current_tenant_id() -> Option<i64> {
+    CURRENT_TENANT.try_with(|tenant| tenant.id).ok()
+}
+
+const TENANT_COLUMNS: &str = "id, slug, name, api_key, quota_bytes, created_at";
+
+async fn fetch_tenant_where(db_pool: &SqlitePool, column: &str, value: &str) -> Result<Option<Tenant>, String> {
+    sqlx::query_as::<_, Tenant>(&format!("SELECT {} FROM tenants WHERE {} = ?", TENANT_COLUMNS, column))
+        .bind(value)
+        .fetch_optional(db_pool)
+        .await
+        .map_err(|e| {
+            error!("Failed to fetch tenant: {}", e);
+            "Failed to fetch tenant".to_string()
+        })
+}
+
+pub async fn fetch_tenant(db_pool: &SqlitePool, id: i64) -> Result<Option<Tenant>, String> {
+    fetch_tenant_where(db_pool, "id", &id.to_string()).await
+}
+
+/// Tenant an upload belongs to
+pub async fn fetch_file_tenant(db_pool: &SqlitePool, file_id: &str) -> Result<Option<Tenant>, String> {
+    sqlx::query_as::<_, Tenant>(&format!(
+        "SELECT {} FROM tenants WHERE id = (SELECT tenant_id FROM upload_file_meta WHERE file_id = ?)",
+        TENANT_COLUMNS
+    ))
+    .bind(file_id)
+    .fetch_optional(db_pool)
+    .await
+    .map_err(|e| {
+        error!("Failed to fetch file tenant: {}", e);
+        "Failed to fetch file tenant".to_string()
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AdminKey {
+    Valid,
+    /// Not sent, or sent while `NASCRAFT_ADMIN_KEY` is unset
+    Missing,
+    Invalid,
+}
+
+fn admin_key(ctx: &AppContext, headers: &HeaderMap) -> AdminKey {
+    let Some(provided) = headers.get(ADMIN_KEY_HEADER).and_then(|v| v.to_str().ok()) else {
+        return AdminKey::Missing;
+    };
+    match ctx.config.load().admin_key.as_deref() {
+        Some(key) if secret_matches(provided, key) => AdminKey::Valid,
+        Some(_) => AdminKey::Invalid,
+        None => AdminKey::Missing,
+    }
+}
+
+/// End of the admin account's lockout, for requests sending an admin key
+fn admin_locked_until(ctx: &AppContext, admin: AdminKey) -> Option<i64> {
+    ctx.auth_failures.account_locked_until(ADMIN_ACCOUNT).filter(|_| admin != AdminKey::Missing)
+}
+
+fn tenant_account(slug: &str) -> String {
+    format!("tenant:{}", slug)
+}
+
+/// First label of the Host header when it has a parent domain, e.g. `alice` for `alice.nas.lan:8080`
+fn subdomain(headers: &HeaderMap) -> Option<&str> {
+    let host = headers.get(header::HOST)?.to_str().ok()?;
+    let host = host.rsplit_once(':').map_or(host, |(name, _)| name);
+    let (label, parent) = host.split_once('.')?;
+    (parent.contains('.') && !label.is_empty() && !label.chars().all(|c| c.is_ascii_digit())).then_some(label)
+}
+
+/// In multi-tenant mode, resolve the tenant of every request from `X-Api-Key`,
+/// the registered client (`X-Device-Token`) or a pre-signed chunk upload URL
+/// and run the handler scoped to it. A tenant subdomain only has to match the
+/// tenant those name, it never selects one by itself. Requests with the admin
+/// key are not scoped to a tenant.
+pub async fn resolve_tenant(
+    State(ctx): State<AppContext>,
+    req: Request,
+    next: Next,
+) -> Response {
+    if !ctx.config.load().multi_tenant {
+        return next.run(req).await;
+    }
+    let admin = admin_key(&ctx, req.headers());
+    if let Some(until) = admin_locked_until(&ctx, admin) {
+        return locked_response(until);
+    }
+    if admin == AdminKey::Valid {
+        return next.run(req).await;
+    }
+
+    let db_pool = &ctx.app_state.db_pool;
+    let api_key = req.headers().get(API_KEY_HEADER).and_then(|v| v.to_str().ok());
+    let device_tenant_id = current_client_device().and_then(|device| device.tenant_id);
+    let tenant = match (api_key, device_tenant_id) {
+        (Some(key), _) => fetch_tenant_where(db_pool, "api_key", key).await,
+        (None, Some(id)) => fetch_tenant(db_pool, id).await,
+        // 预签名的分片上传 URL 属于其文件所在的租户
+        (None, None) => match signed_chunk(req.uri()).filter(|_| req.uri().path().ends_with("/upload")) {
+            Some(Ok(chunk)) => fetch_file_tenant(db_pool, &chunk.file_id).await,
+            _ => Ok(None),
+        },
+    };
+    let slug = subdomain(req.headers())
+        .filter(|slug| slug.chars().all(|c| c.is_ascii_alphanumeric() || c == '-'))
+        .map(str::to_lowercase);
+    // 错误的 API Key 计入子域名所指租户的失败次数
+    let account = slug.as_deref().map(tenant_account);
+    let key_sent = api_key.is_some();
+    let response = match tenant {
+        Ok(Some(tenant)) if slug.as_ref().is_some_and(|slug| *slug != tenant.slug) => {
+            let response = error_response(StatusCode::UNAUTHORIZED, "TENANT_MISMATCH", format!("The credentials don't belong to tenant '{}'", slug.unwrap_or_default()));
+            if key_sent {
+                AuthFailure::new("api_key", account).attach(response)
+            } else {
+                response
+            }
+        }
+        Ok(Some(tenant)) => match ctx.auth_failures.account_locked_until(&tenant_account(&tenant.slug)) {
+            Some(until) => locked_response(until),
+            None => CURRENT_TENANT.scope(tenant, next.run(req)).await,
+        },
+        Ok(None) => {
+            let response = error_response(
+                StatusCode::UNAUTHORIZED,
+                "TENANT_REQUIRED",
+                format!("A valid {} or {} header is required", API_KEY_HEADER, DEVICE_TOKEN_HEADER),
+            );
+            if key_sent {
+                AuthFailure::new("api_key", account).attach(response)
+            } else {
+                response
+            }
+        }
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_TENANT_ERROR", e),
+    };
+    // 管理员密钥错误但租户凭证有效时请求照常处理，失败仍需记录
+    match admin {
+        AdminKey::Invalid => AuthFailure::new("admin_key", Some(ADMIN_ACCOUNT.to_string())).attach(response),
+        _ => response,
+    }
+}
+
+/// Reject a tenant's request for a file of another tenant. The file is
+/// reported as missing so other tenants' file IDs can't be probed.
+pub async fn ensure_file_in_tenant(db_pool: &SqlitePool, file_id: &str) -> Result<(), Response> {
+    let Some(tenant_id) = current_tenant_id() else {
+        return Ok(());
+    };
+    match sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM upload_file_meta WHERE file_id = ? AND tenant_id = ?")
+        .bind(file_id)
+        .bind(tenant_id)
+        .fetch_one(db_pool)
+        .await
+    {
+        Ok(0) => Err(error_response(StatusCode::NOT_FOUND, "FILE_NOT_FOUND", "File not found".to_string())),
+        Ok(_) => Ok(()),
+        Err(e) => {
+            error!("Failed to check file tenant: {}", e);
+            Err(error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_TENANT_ERROR", "Failed to check file tenant".to_string()))
+        }
+    }
+}
+
+/// Reject tenants from server-wide endpoints (statistics, retention,
+/// deduplication, profiles), which are left to requests with the admin key
+pub async fn ensure_not_tenant() -> Result<(), Response> {
+    match current_tenant() {
+        Some(tenant) => Err(error_response(StatusCode::FORBIDDEN, "TENANT_FORBIDDEN", format!("Not available to tenant '{}'", tenant.slug))),
+        None => Ok(()),
+    }
+}
+
+/// Bytes stored or reserved by a tenant's uploads, completed or not
+pub async fn tenant_usage(db_pool: &SqlitePool, tenant_id: i64) -> Result<i64, String> {
+    sqlx::query_scalar::<_, i64>("SELECT COALESCE(SUM(total_size), 0) FROM upload_file_meta WHERE tenant_id = ?")
+        .bind(tenant_id)
+        .fetch_one(db_pool)
+        .await
+        .map_err(|e| {
+            error!("Failed to compute tenant usage: {}", e);
+            "Failed to compute tenant usage".to_string()
+        })
+}
+
+/// Check a new upload of `total_size` bytes fits in the tenant's quota.
+/// The error is an API error code and message.
+pub async fn check_quota(db_pool: &SqlitePool, tenant: &Tenant, total_size: u64) -> Result<(), (&'static str, String)> {
+    let Some(quota) = tenant.quota_bytes else {
+        return Ok(());
+    };
+    let used = tenant_usage(db_pool, tenant.id).await.map_err(|e| ("QUOTA_CHECK_ERROR", e))?;
+    if used as u64 + total_size > quota as u64 {
+        return Err(("QUOTA_EXCEEDED", format!(
+            "Tenant '{}' has {} of {} bytes left, the file needs {}",
+            tenant.slug, (quota - used).max(0), quota, total_size
+        )));
+    }
+    Ok(())
+}
+
+async fn ensure_admin(ctx: &AppContext, headers: &HeaderMap) -> Result<(), Response> {
+    let admin = admin_key(ctx, headers);
+    if let Some(until) = admin_locked_until(ctx, admin) {
+        return Err(locked_response(until));
+    }
+    if admin == AdminKey::Valid {
+        return Ok(());
+    }
+    let message = if ctx.config.load().admin_key.is_some() {
+        format!("A valid {} header is required", ADMIN_KEY_HEADER)
+    } else {
+        "Tenant administration is disabled, set NASCRAFT_ADMIN_KEY to enable it".to_string()
+    };
+    let response = error_response(StatusCode::FORBIDDEN, "ADMIN_KEY_REQUIRED", message);
+    match admin {
+        AdminKey::Invalid => Err(AuthFailure::new("admin_key", Some(ADMIN_ACCOUNT.to_string())).attach(response)),
+        _ => Err(response),
+    }
+}
+
+#[derive(Serialize)]
+struct TenantUsage {
+    #[serde(flatten)]
+    tenant: Tenant,
+    used_bytes: i64,
+}
+
+pub async fn list_tenants(
+    State(ctx): State<AppContext>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    if let Err(resp) = ensure_admin(&ctx, &headers).await {
+        return resp;
+    }
+    let db_pool = &ctx.app_state.db_pool;
+    let tenants = match sqlx::query_as::<_, Tenant>(&format!("SELECT {} FROM tenants ORDER BY slug", TENANT_COLUMNS))
+        .fetch_all(db_pool)
+        .await
+    {
+        Ok(tenants) => tenants,
+        Err(e) => {
+            error!("Failed to fetch tenants: {}", e);
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_TENANTS_ERROR", "Failed to fetch tenants".to_string());
+        }
+    };
+
+    let mut result = Vec::new();
+    for tenant in tenants {
+        let used_bytes = tenant_usage(db_pool, tenant.id).await.unwrap_or_default();
+        result.push(TenantUsage { tenant, used_bytes });
+    }
+    (StatusCode::OK, Json(ApiResponse::success(result))).into_response()
+}
+
+#[derive(Deserialize)]
+pub struct CreateTenantRequest {
+    slug: String,
+    name: Option<String>,
+    quota_bytes: Option<i64>,
+}
+
+#[derive(Serialize)]
+struct CreatedTenant {
+    #[serde(flatten)]
+    tenant: Tenant,
+    /// Only returned when the tenant is created
+    api_key: String,
+}
+
+/// Create a tenant with a generated API key and its storage directory
+pub async fn create_tenant(
+    State(ctx): State<AppContext>,
+    headers: HeaderMap,
+    Json(req): Json<CreateTenantRequest>,
+) -> impl IntoResponse {
+    if let Err(resp) = ensure_admin(&ctx, &headers).await {
+        return resp;
+    }
+    // slug 同时用作子域名和目录名
+    let slug = req.slug.trim().to_lowercase();
+    let valid_slug = !slug.is_empty()
+        && slug.len() <= 63
+        && slug.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        && !slug.starts_with('-')
+        && !slug.ends_with('-');
+    if !valid_slug {
+        return error_response(StatusCode::BAD_REQUEST, "INVALID_TENANT", "slug must be 1-63 letters, digits or inner hyphens".to_string());
+    }
+    if req.quota_bytes.is_some_and(|quota| quota <= 0) {
+        return error_response(StatusCode::BAD_REQUEST, "INVALID_TENANT", "quota_bytes must be positive".to_string());
+    }
+    let name = req.name.map(|n| n.trim().to_string()).filter(|n| !n.is_empty()).unwrap_or_else(|| slug.clone());
+
+    let db_pool = &ctx.app_state.db_pool;
+    let result = sqlx::query_as::<_, Tenant>(&format!(
+        "INSERT INTO tenants (slug, name, api_key, quota_bytes, created_at) VALUES (?, ?, ?, ?, strftime('%s', 'now'))
+         RETURNING {}",
+        TENANT_COLUMNS
+    ))
+    .bind(&slug)
+    .bind(&name)
+    .bind(Uuid::new_v4().simple().to_string())
+    .bind(req.quota_bytes)
+    .fetch_one(db_pool)
+    .await;
+
+    let tenant = match result {
+        Ok(tenant) => tenant,
+        Err(e) => {
+            error!("Failed to create tenant: {}", e);
+            return error_response(StatusCode::CONFLICT, "CREATE_TENANT_ERROR", format!("A tenant named '{}' already exists", slug));
+        }
+    };
+
+    if let Err(e) = tokio::fs::create_dir_all(long_path(&std::path::Path::new(UPLOADS_DIR).join(tenant.storage_dir()))).await {
+        error!("Failed to create tenant directory {}: {}", tenant.storage_dir(), e);
+        let _ = sqlx::query("DELETE FROM tenants WHERE id = ?").bind(tenant.id).execute(db_pool).await;
+        return error_response(StatusCode::INTERNAL_SERVER_ERROR, "CREATE_TENANT_ERROR", format!("Failed to create directory for tenant '{}'", slug));
+    }
+
+    info!("Created tenant '{}'", slug);
+    (StatusCode::OK, Json(ApiResponse::success(CreatedTenant {
+        api_key: tenant.api_key.clone(),
+        tenant,
+    }))).into_response()
+}
+
+/// Delete a tenant without files or folders; its directory on disk is left in place
+pub async fn delete_tenant(
+    State(ctx): State<AppContext>,
+    headers: HeaderMap,
+    Path(id): Path<i64>,
+) -> impl IntoResponse {
+    if let Err(resp) = ensure_admin(&ctx, &headers).await {
+        return resp;
+    }
+
+    let db_pool = &ctx.app_state.db_pool;
+    let result = async {
+        let mut tx = db_pool.begin().await?;
+        let used: i64 = sqlx::query_scalar(
+            "SELECT (SELECT COUNT(*) FROM upload_file_meta WHERE tenant_id = ?) + (SELECT COUNT(*) FROM folders WHERE tenant_id = ?)"
+        )
+        .bind(id)
+        .bind(id)
+        .fetch_one(&mut *tx)
+        .await?;
+        if used > 0 {
+            return Ok(None);
+        }
+        let deleted = sqlx::query("DELETE FROM tenants WHERE id = ?").bind(id).execute(&mut *tx).await?;
+        tx.commit().await?;
+        Ok::<Option<u64>, sqlx::Error>(Some(deleted.rows_affected()))
+    }
+    .await;
+
+    match result {
+        Ok(None) => error_response(StatusCode::CONFLICT, "TENANT_NOT_EMPTY", "Tenant still has files or folders".to_string()),
+        Ok(Some(0)) => error_response(StatusCode::NOT_FOUND, "TENANT_NOT_FOUND", "Tenant not found".to_string()),
+        Ok(Some(_)) => (StatusCode::OK, Json(ApiResponse::success(()))).into_response(),
+        Err(e) => {
+            error!("Failed to delete tenant: {}", e);
+            error_response(StatusCode::INTERNAL_SERVER_ERROR, "DELETE_TENANT_ERROR", "Failed to delete tenant".to_string())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, extract::ConnectInfo, routing::get, Router};
+    use http_body_util::BodyExt;
+    use std::net::SocketAddr;
+    use tower::ServiceExt;
+    use crate::config::AppConfig;
+
+    const ALICE_FILE: &str = "0b7c5a52-6d1e-4b43-9d8e-4a4f4c7e0001";
+
+    async fn setup() -> (AppContext, Tenant, Tenant) {
+        let mut config = AppConfig::for_tests();
+        config.multi_tenant = true;
+        config.admin_key = Some("admin-secret".to_string());
+        config.opensubtitles_api_key = Some("opensubtitles".to_string());
+        let ctx = AppContext::for_tests(config).await;
+        let db_pool = &ctx.app_state.db_pool;
+        let mut tenants = Vec::new();
+        for (slug, quota) in [("alice", Some(1000)), ("bob", None)] {
+            tenants.push(
+                sqlx::query_as::<_, Tenant>(&format!(
+                    "INSERT INTO tenants (slug, name, api_key, quota_bytes) VALUES (?, ?, ?, ?) RETURNING {}",
+                    TENANT_COLUMNS
+                ))
+                .bind(slug)
+                .bind(slug)
+                .bind(format!("{}-key", slug))
+                .bind(quota)
+                .fetch_one(db_pool)
+                .await
+                .unwrap(),
+            );
+        }
+        sqlx::query("INSERT INTO upload_file_meta (file_id, filename, total_size, checksum, status, file_path, tenant_id) VALUES (?, 'a.mkv', 600, 'x', 2, 'uploads/tenants/alice/a.mkv', ?)")
+            .bind(ALICE_FILE)
+            .bind(tenants[0].id)
+            .execute(db_pool)
+            .await
+            .unwrap();
+        let bob = tenants.pop().unwrap();
+        (ctx, tenants.pop().unwrap(), bob)
+    }
+
+    async fn body_code(response: Response) -> (StatusCode, String) {
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap_or_default();
+        (status, json["code"].as_str().unwrap_or_default().to_string())
+    }
+
+    #[tokio::test]
+    async fn files_of_other_tenants_are_not_found() {
+        let (ctx, alice, bob) = setup().await;
+        let db_pool = &ctx.app_state.db_pool;
+        assert!(CURRENT_TENANT.scope(alice, ensure_file_in_tenant(db_pool, ALICE_FILE)).await.is_ok());
+        let response = CURRENT_TENANT.scope(bob, ensure_file_in_tenant(db_pool, ALICE_FILE)).await.unwrap_err();
+        assert_eq!(body_code(response).await, (StatusCode::NOT_FOUND, "FILE_NOT_FOUND".to_string()));
+        // 管理员与后台任务不限租户
+        assert!(ensure_file_in_tenant(db_pool, ALICE_FILE).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn quota_counts_the_tenants_own_files() {
+        let (ctx, alice, bob) = setup().await;
+        let db_pool = &ctx.app_state.db_pool;
+        assert_eq!(tenant_usage(db_pool, alice.id).await, Ok(600));
+        assert_eq!(tenant_usage(db_pool, bob.id).await, Ok(0));
+        assert!(check_quota(db_pool, &alice, 400).await.is_ok());
+        let (code, message) = check_quota(db_pool, &alice, 401).await.unwrap_err();
+        assert_eq!(code, "QUOTA_EXCEEDED");
+        assert!(message.contains("400 of 1000"), "{}", message);
+        assert!(check_quota(db_pool, &bob, u32::MAX as u64).await.is_ok());
+    }
+
+    /// Send a request through `resolve_tenant` to a handler answering with the tenant's slug
+    async fn resolve(ctx: &AppContext, headers: &[(&str, &str)]) -> (StatusCode, String) {
+        let app = Router::new()
+            .route("/files", get(|| async { current_tenant().map_or("-".to_string(), |tenant| tenant.slug) }))
+            .layer(axum::middleware::from_fn_with_state(ctx.clone(), resolve_tenant));
+        let mut request = Request::builder().uri("/files");
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        let response = app.oneshot(request.body(Body::empty()).unwrap()).await.unwrap();
+        let status = response.status();
+        if status != StatusCode::OK {
+            return body_code(response).await;
+        }
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn tenants_are_resolved_from_credentials() {
+        let (ctx, _, _) = setup().await;
+        let ok = |slug: &str| (StatusCode::OK, slug.to_string());
+        let unauthorized = |code: &str| (StatusCode::UNAUTHORIZED, code.to_string());
+        assert_eq!(resolve(&ctx, &[(API_KEY_HEADER, "alice-key")]).await, ok("alice"));
+        assert_eq!(resolve(&ctx, &[(API_KEY_HEADER, "bob-key"), ("Host", "bob.nas.lan")]).await, ok("bob"));
+        assert_eq!(resolve(&ctx, &[(ADMIN_KEY_HEADER, "admin-secret")]).await, ok("-"));
+        assert_eq!(resolve(&ctx, &[]).await, unauthorized("TENANT_REQUIRED"));
+        assert_eq!(resolve(&ctx, &[(API_KEY_HEADER, "wrong")]).await, unauthorized("TENANT_REQUIRED"));
+    }
+
+    #[tokio::test]
+    async fn subdomains_never_select_a_tenant() {
+        let (ctx, _, _) = setup().await;
+        assert_eq!(resolve(&ctx, &[("Host", "alice.nas.lan")]).await, (StatusCode::UNAUTHORIZED, "TENANT_REQUIRED".to_string()));
+        assert_eq!(
+            resolve(&ctx, &[(API_KEY_HEADER, "bob-key"), ("Host", "alice.nas.lan:8080")]).await,
+            (StatusCode::UNAUTHORIZED, "TENANT_MISMATCH".to_string())
+        );
+        // 错误的管理员密钥不会绕过租户
+        assert_eq!(resolve(&ctx, &[(ADMIN_KEY_HEADER, "admin-secreT")]).await, (StatusCode::UNAUTHORIZED, "TENANT_REQUIRED".to_string()));
+    }
+
+    #[tokio::test]
+    async fn file_endpoints_hide_other_tenants_files() {
+        use axum::extract::State;
+        use crate::media_library::scrape_library_entry;
+        use crate::opensubtitles::fetch_subtitle_for_file;
+        use crate::playback::{report_playback, update_watch_state, PlaybackReport, WatchStateRequest};
+        use crate::subtitles::{list_subtitles, serve_subtitle};
+
+        let (ctx, _, bob) = setup().await;
+        let addr = ConnectInfo(SocketAddr::from(([192, 168, 1, 20], 50000)));
+        let file_id = || Path(ALICE_FILE.to_string());
+        let report: PlaybackReport = serde_json::from_value(serde_json::json!({ "position_secs": 10.0, "duration_secs": 100.0 })).unwrap();
+        let watch: WatchStateRequest = serde_json::from_value(serde_json::json!({ "state": "watched" })).unwrap();
+        let responses = CURRENT_TENANT
+            .scope(bob, async {
+                vec![
+                    ("list_subtitles", list_subtitles(State(ctx.clone()), addr, file_id()).await.into_response()),
+                    ("serve_subtitle", serve_subtitle(State(ctx.clone()), addr, Path((ALICE_FILE.to_string(), 1))).await.into_response()),
+                    ("fetch_subtitle_for_file", fetch_subtitle_for_file(State(ctx.clone()), addr, file_id(), None).await.into_response()),
+                    ("scrape_library_entry", scrape_library_entry(State(ctx.clone()), addr, file_id()).await.into_response()),
+                    ("report_playback", report_playback(State(ctx.clone()), addr, file_id(), Json(report)).await.into_response()),
+                    ("update_watch_state", update_watch_state(State(ctx.clone()), addr, file_id(), Json(watch)).await.into_response()),
+                ]
+            })
+            .await;
+        for (handler, response) in responses {
+            assert_eq!(body_code(response).await, (StatusCode::NOT_FOUND, "FILE_NOT_FOUND".to_string()), "{}", handler);
+        }
+        let written: i64 = sqlx::query_scalar("SELECT (SELECT COUNT(*) FROM playback_history) + (SELECT COUNT(*) FROM watch_state)")
+            .fetch_one(&ctx.app_state.db_pool)
+            .await
+            .unwrap();
+        assert_eq!(written, 0);
+    }
+}
@@ -1,6 +1,7 @@
 use image::{ImageFormat, io::Reader as ImageReader, GenericImageView};
 use log::{info, error};
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use crate::paths::{long_path, path_to_string};
 use tokio::fs;
 
 /// Configuration for thumbnail generation
@@ -32,14 +33,29 @@ pub fn is_image_file(filename: &str) -> bool {
         .unwrap_or(false)
 }
 
+/// Extensions treated as video files
+pub const VIDEO_EXTENSIONS: [&str; 8] = ["mp4", "webm", "mkv", "avi", "mov", "flv", "wmv", "m4v"];
+
 /// Check if a file is a video based on file extension
 pub fn is_video_file(filename: &str) -> bool {
-    let extensions = ["mp4", "webm", "mkv", "avi", "mov", "flv", "wmv", "m4v"];
     filename
         .to_lowercase()
         .rsplit('.')
         .next()
-        .map(|ext| extensions.contains(&ext))
+        .map(|ext| VIDEO_EXTENSIONS.contains(&ext))
+        .unwrap_or(false)
+}
+
+/// Extensions treated as audio files
+pub const AUDIO_EXTENSIONS: [&str; 8] = ["mp3", "flac", "m4a", "aac", "ogg", "opus", "wav", "wma"];
+
+/// Check if a file is audio based on file extension
+pub fn is_audio_file(filename: &str) -> bool {
+    filename
+        .to_lowercase()
+        .rsplit('.')
+        .next()
+        .map(|ext| AUDIO_EXTENSIONS.contains(&ext))
         .unwrap_or(false)
 }
 
@@ -56,7 +72,7 @@ pub async fn generate_thumbnail(
         return None;
     }
 
-    let thumbnail_path = format!("{}/{}.webp", config.thumbnails_dir, checksum);
+    let thumbnail_path = thumbnail_file_path(config, checksum);
 
     // Generate thumbnail synchronously (CPU-bound work)
     let original_path = original_path.to_string();
@@ -68,8 +84,9 @@ pub async fn generate_thumbnail(
     match result {
         Ok(Ok(image)) => {
             // Save as WebP
-            match fs::write(&thumbnail_path, image).await {
+            match fs::write(long_path(&thumbnail_path), image).await {
                 Ok(_) => {
+                    let thumbnail_path = path_to_string(&thumbnail_path);
                     info!("Thumbnail generated successfully: {}", thumbnail_path);
                     Some(thumbnail_path)
                 }
@@ -92,7 +109,7 @@ pub async fn generate_thumbnail(
 
 /// Generate thumbnail synchronously (runs in blocking thread)
 fn generate_thumbnail_sync(original_path: &str, max_size: u32) -> Result<Vec<u8>, String> {
-    let path = Path::new(original_path);
+    let path = long_path(Path::new(original_path));
     let img = ImageReader::open(path)
         .map_err(|e| format!("Failed to open image: {}", e))?
         .decode()
@@ -120,8 +137,7 @@ fn generate_thumbnail_sync(original_path: &str, max_size: u32) -> Result<Vec<u8>
     Ok(buffer)
 }
 
-/// Check if a thumbnail already exists
-pub async fn thumbnail_exists(config: &ThumbnailConfig, checksum: &str) -> bool {
-    let thumbnail_path = format!("{}/{}.webp", config.thumbnails_dir, checksum);
-    fs::metadata(&thumbnail_path).await.is_ok()
+/// Thumbnails are keyed by checksum so identical images share one file
+fn thumbnail_file_path(config: &ThumbnailConfig, checksum: &str) -> PathBuf {
+    Path::new(&config.thumbnails_dir).join(format!("{}.webp", checksum))
 }
@@ -0,0 +1,195 @@
+use log::error;
+use serde::Deserialize;
+
+const API_BASE: &str = "https://api.themoviedb.org/3";
+const IMAGE_BASE: &str = "https://image.tmdb.org/t/p/w500";
+
+/// Details TMDB returned for a movie or an episode of a show
+#[derive(Debug, Default)]
+pub struct TmdbMatch {
+    pub tmdb_id: i64,
+    pub title: String,
+    pub year: Option<i64>,
+    pub overview: Option<String>,
+    pub poster_url: Option<String>,
+    pub episode_title: Option<String>,
+    /// Certification for the configured region, e.g. `PG-13` or `TV-MA`
+    pub content_rating: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct SearchResponse<T> {
+    results: Vec<T>,
+}
+
+#[derive(Deserialize)]
+struct MovieResult {
+    id: i64,
+    title: String,
+    release_date: Option<String>,
+    overview: Option<String>,
+    poster_path: Option<String>,
+    #[serde(default)]
+    adult: bool,
+}
+
+#[derive(Deserialize)]
+struct TvResult {
+    id: i64,
+    name: String,
+    first_air_date: Option<String>,
+    overview: Option<String>,
+    poster_path: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct EpisodeResult {
+    name: Option<String>,
+    overview: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct MovieReleaseDates {
+    results: Vec<CountryReleaseDates>,
+}
+
+#[derive(Deserialize)]
+struct CountryReleaseDates {
+    iso_3166_1: String,
+    release_dates: Vec<ReleaseDate>,
+}
+
+#[derive(Deserialize)]
+struct ReleaseDate {
+    #[serde(default)]
+    certification: String,
+}
+
+#[derive(Deserialize)]
+struct TvContentRatings {
+    results: Vec<CountryRating>,
+}
+
+#[derive(Deserialize)]
+struct CountryRating {
+    iso_3166_1: String,
+    rating: String,
+}
+
+pub struct TmdbClient {
+    client: reqwest::Client,
+    api_key: String,
+    language: String,
+    region: String,
+}
+
+/// "2010-07-15" -> 2010
+fn year_of(date: Option<&str>) -> Option<i64> {
+    date.and_then(|d| d.get(..4)).and_then(|y| y.parse().ok())
+}
+
+fn poster_url(path: Option<String>) -> Option<String> {
+    path.filter(|p| !p.is_empty()).map(|p| format!("{}{}", IMAGE_BASE, p))
+}
+
+impl TmdbClient {
+    pub fn new(api_key: &str, language: &str, region: &str) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            api_key: api_key.to_string(),
+            language: language.to_string(),
+            region: region.to_uppercase(),
+        }
+    }
+
+    async fn get<T: for<'de> Deserialize<'de>>(&self, path: &str, query: &[(&str, String)]) -> Result<T, String> {
+        let response = self.client
+            .get(format!("{}{}", API_BASE, path))
+            .query(&[("api_key", self.api_key.as_str()), ("language", self.language.as_str())])
+            .query(query)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| {
+                error!("TMDB request {} failed: {}", path, e);
+                format!("TMDB request failed: {}", e)
+            })?;
+        response.json::<T>().await.map_err(|e| {
+            error!("Failed to parse TMDB response for {}: {}", path, e);
+            "Invalid response from TMDB".to_string()
+        })
+    }
+
+    pub async fn search_movie(&self, title: &str, year: Option<i64>) -> Result<Option<TmdbMatch>, String> {
+        let mut query = vec![("query", title.to_string())];
+        if let Some(year) = year {
+            query.push(("year", year.to_string()));
+        }
+        let response: SearchResponse<MovieResult> = self.get("/search/movie", &query).await?;
+        let Some(movie) = response.results.into_iter().next() else {
+            return Ok(None);
+        };
+
+        // 分级获取失败不影响匹配结果
+        let content_rating = self
+            .get::<MovieReleaseDates>(&format!("/movie/{}/release_dates", movie.id), &[])
+            .await
+            .ok()
+            .and_then(|dates| {
+                dates.results
+                    .into_iter()
+                    .find(|c| c.iso_3166_1 == self.region)
+                    .and_then(|c| c.release_dates.into_iter().map(|d| d.certification).find(|c| !c.is_empty()))
+            })
+            .or_else(|| movie.adult.then(|| "adult".to_string()));
+
+        Ok(Some(TmdbMatch {
+            tmdb_id: movie.id,
+            title: movie.title,
+            year: year_of(movie.release_date.as_deref()),
+            overview: movie.overview.filter(|o| !o.is_empty()),
+            poster_url: poster_url(movie.poster_path),
+            episode_title: None,
+            content_rating,
+        }))
+    }
+
+    /// Look up a show and, when season and episode are known, the episode itself
+    pub async fn search_episode(&self, title: &str, year: Option<i64>, season: i64, episode: i64) -> Result<Option<TmdbMatch>, String> {
+        let mut query = vec![("query", title.to_string())];
+        if let Some(year) = year {
+            query.push(("first_air_date_year", year.to_string()));
+        }
+        let response: SearchResponse<TvResult> = self.get("/search/tv", &query).await?;
+        let Some(show) = response.results.into_iter().next() else {
+            return Ok(None);
+        };
+
+        // 剧集详情获取失败时仍保留剧集层面的信息
+        let details = self
+            .get::<EpisodeResult>(&format!("/tv/{}/season/{}/episode/{}", show.id, season, episode), &[])
+            .await
+            .ok();
+        let content_rating = self
+            .get::<TvContentRatings>(&format!("/tv/{}/content_ratings", show.id), &[])
+            .await
+            .ok()
+            .and_then(|ratings| ratings.results.into_iter().find(|r| r.iso_3166_1 == self.region))
+            .map(|r| r.rating)
+            .filter(|r| !r.is_empty());
+
+        Ok(Some(TmdbMatch {
+            tmdb_id: show.id,
+            title: show.name,
+            year: year_of(show.first_air_date.as_deref()),
+            overview: details
+                .as_ref()
+                .and_then(|d| d.overview.clone())
+                .filter(|o| !o.is_empty())
+                .or(show.overview.filter(|o| !o.is_empty())),
+            poster_url: poster_url(show.poster_path),
+            episode_title: details.and_then(|d| d.name),
+            content_rating,
+        }))
+    }
+}
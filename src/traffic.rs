@@ -0,0 +1,102 @@
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use chrono::{Duration, Utc};
+use log::error;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, SqlitePool};
+use std::net::SocketAddr;
+use crate::context::AppContext;
+use crate::helper::{error_response, ApiResponse};
+use crate::tenants::ensure_not_tenant;
+
+/// Identify who is transferring data. There are no user accounts yet,
+/// so traffic is attributed to the client IP.
+pub fn client_principal(addr: &SocketAddr) -> String {
+    format!("ip:{}", addr.ip())
+}
+
+/// 累加某个主体当天的上传/下载字节数
+pub async fn record_traffic(db_pool: &SqlitePool, principal: &str, bytes_uploaded: u64, bytes_downloaded: u64) {
+    if bytes_uploaded == 0 && bytes_downloaded == 0 {
+        return;
+    }
+
+    let day = Utc::now().format("%Y-%m-%d").to_string();
+    if let Err(e) = sqlx::query(
+        "INSERT INTO traffic_daily (day, principal, bytes_uploaded, bytes_downloaded) VALUES (?, ?, ?, ?)
+         ON CONFLICT(day, principal) DO UPDATE SET
+             bytes_uploaded = bytes_uploaded + excluded.bytes_uploaded,
+             bytes_downloaded = bytes_downloaded + excluded.bytes_downloaded"
+    )
+    .bind(&day)
+    .bind(principal)
+    .bind(bytes_uploaded as i64)
+    .bind(bytes_downloaded as i64)
+    .execute(db_pool)
+    .await
+    {
+        // Accounting must never fail a transfer
+        error!("Failed to record traffic for {}: {}", principal, e);
+    }
+}
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct TrafficRow {
+    pub day: String,
+    pub principal: String,
+    pub bytes_uploaded: i64,
+    pub bytes_downloaded: i64,
+}
+
+pub async fn fetch_traffic(db_pool: &SqlitePool, since_day: &str, principal: Option<&str>) -> Result<Vec<TrafficRow>, String> {
+    let mut query = "SELECT day, principal, bytes_uploaded, bytes_downloaded FROM traffic_daily WHERE day >= ?".to_string();
+    if principal.is_some() {
+        query.push_str(" AND principal = ?");
+    }
+    query.push_str(" ORDER BY day DESC, principal ASC");
+
+    let mut q = sqlx::query_as::<_, TrafficRow>(&query).bind(since_day);
+    if let Some(principal) = principal {
+        q = q.bind(principal);
+    }
+
+    q.fetch_all(db_pool).await.map_err(|e| {
+        error!("Failed to fetch traffic stats: {}", e);
+        "Failed to fetch traffic stats".to_string()
+    })
+}
+
+#[derive(Deserialize)]
+pub struct TrafficQuery {
+    days: Option<i64>,
+    principal: Option<String>,
+}
+
+pub async fn get_traffic_stats(
+    State(ctx): State<AppContext>,
+    Query(query): Query<TrafficQuery>,
+) -> impl IntoResponse {
+    if let Err(resp) = ensure_not_tenant().await {
+        return resp;
+    }
+    let days = query.days.unwrap_or(30).clamp(1, 366);
+    let since_day = (Utc::now() - Duration::days(days - 1)).format("%Y-%m-%d").to_string();
+
+    match fetch_traffic(&ctx.app_state.db_pool, &since_day, query.principal.as_deref()).await {
+        Ok(rows) => {
+            let total_uploaded: i64 = rows.iter().map(|r| r.bytes_uploaded).sum();
+            let total_downloaded: i64 = rows.iter().map(|r| r.bytes_downloaded).sum();
+            (StatusCode::OK, Json(ApiResponse::success(serde_json::json!({
+                "since": since_day,
+                "total_uploaded": total_uploaded,
+                "total_downloaded": total_downloaded,
+                "days": rows,
+            })))).into_response()
+        }
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_TRAFFIC_ERROR", e),
+    }
+}
@@ -0,0 +1,206 @@
+use axum::{
+    extract::{ConnectInfo, Path, State},
+    http::{header, StatusCode},
+    response::IntoResponse,
+};
+use log::{error, info, warn};
+use serde_json::json;
+use sqlx::SqlitePool;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use tokio::io::AsyncReadExt;
+use crate::api::Priority;
+use crate::config::AppConfig;
+use crate::context::AppContext;
+use crate::derived_files::{remove_derived_files, replace_derived_file, KIND_TRANSCODE};
+use crate::helper::error_response;
+use crate::hwaccel::{HwEncoder, HwEncoders};
+use crate::io_scheduler::IoClass;
+use crate::paths::{long_path, path_to_string, transcode_file_path, TRANSCODED_DIR};
+use crate::profiles::ensure_file_allowed;
+use crate::repository::UploadRepository;
+use crate::traffic::{client_principal, record_traffic};
+use crate::transcode_cache::{evict_transcodes, touch_transcode, EVICTED};
+
+/// Container formats a folder can auto-transcode to
+pub const TRANSCODE_FORMATS: [&str; 2] = ["mp4", "webm"];
+
+/// ffmpeg options of the software video encoder for each supported format
+fn software_video_args(format: &str) -> Option<&'static [&'static str]> {
+    match format {
+        "mp4" => Some(&["-c:v", "libx264", "-preset", "veryfast", "-crf", "23"]),
+        "webm" => Some(&["-c:v", "libvpx-vp9", "-crf", "32", "-b:v", "0"]),
+        _ => None,
+    }
+}
+
+/// ffmpeg audio and container options for each supported format
+fn output_args(format: &str) -> Option<&'static [&'static str]> {
+    match format {
+        "mp4" => Some(&["-c:a", "aac", "-b:a", "160k", "-movflags", "+faststart", "-f", "mp4"]),
+        "webm" => Some(&["-c:a", "libopus", "-f", "webm"]),
+        _ => None,
+    }
+}
+
+async fn set_transcode_status(db_pool: &SqlitePool, file_id: &str, format: &str, path: Option<&str>, status: &str, error_text: Option<&str>) {
+    if let Err(e) = sqlx::query(
+        "INSERT INTO transcodes (file_id, format, path, status, error, updated_at, last_accessed_at) VALUES (?, ?, ?, ?, ?, strftime('%s', 'now'), strftime('%s', 'now'))
+         ON CONFLICT(file_id) DO UPDATE SET format = excluded.format, path = excluded.path, status = excluded.status, error = excluded.error,
+             updated_at = excluded.updated_at, last_accessed_at = excluded.last_accessed_at"
+    )
+    .bind(file_id)
+    .bind(format)
+    .bind(path)
+    .bind(status)
+    .bind(error_text)
+    .execute(db_pool)
+    .await
+    {
+        error!("Failed to record transcode status for file ID {}: {}", file_id, e);
+    }
+    let _ = match path.filter(|_| status == "done") {
+        Some(path) => replace_derived_file(db_pool, file_id, KIND_TRANSCODE, Some(&json!({ "format": format })), path).await,
+        None => remove_derived_files(db_pool, file_id, KIND_TRANSCODE).await,
+    };
+}
+
+/// Transcode `source` with `encoder`, or in software when None
+async fn run_ffmpeg(config: &AppConfig, encoder: Option<&HwEncoder>, source: &std::path::Path, format: &str, output: &std::path::Path) -> Result<(), String> {
+    let args = output_args(format).ok_or_else(|| format!("Unsupported transcode format: {}", format))?;
+    let (input_args, video_args) = match encoder {
+        Some(encoder) => (encoder.input_args(&config.vaapi_device), encoder.video_args()),
+        None => (Vec::new(), software_video_args(format).unwrap_or_default().iter().map(|a| a.to_string()).collect()),
+    };
+    let result = tokio::process::Command::new(&config.ffmpeg_path)
+        .arg("-nostdin")
+        .arg("-y")
+        .args(input_args)
+        .arg("-i")
+        .arg(source)
+        .args(video_args)
+        .args(args)
+        .arg(output)
+        .output()
+        .await
+        .map_err(|e| format!("Failed to run {}: {}", config.ffmpeg_path, e))?;
+
+    if result.status.success() {
+        return Ok(());
+    }
+    // ffmpeg 的错误信息在 stderr 末尾
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    let tail: Vec<&str> = stderr.lines().rev().take(5).collect();
+    Err(format!("ffmpeg exited with {}: {}", result.status, tail.into_iter().rev().collect::<Vec<_>>().join(" | ")))
+}
+
+/// Transcode with the fastest hardware encoder for `format`, falling back to
+/// software when there is none or it fails on this file
+async fn transcode(config: &AppConfig, encoders: &HwEncoders, source: &std::path::Path, format: &str, output: &std::path::Path) -> Result<(), String> {
+    if let Some(encoder) = encoders.select(format) {
+        match run_ffmpeg(config, Some(encoder), source, format, output).await {
+            Ok(()) => return Ok(()),
+            Err(e) => warn!("Hardware encoder {} failed on {}, transcoding in software: {}", encoder.codec, source.display(), e),
+        }
+    }
+    run_ffmpeg(config, None, source, format, output).await
+}
+
+/// Transcode a completed upload in the background, recording the outcome in
+/// `transcodes`. Waiting transcodes start in order of `priority`.
+pub fn spawn_transcode(ctx: &AppContext, file_id: String, source: PathBuf, format: String, priority: Priority) {
+    let db_pool = ctx.app_state.db_pool.clone();
+    let config = ctx.config.load();
+    let io = ctx.io.clone();
+    let encoders = ctx.encoders.clone();
+    tokio::spawn(async move {
+        set_transcode_status(&db_pool, &file_id, &format, None, "pending", None).await;
+        // 保持 pending 状态直到轮到本次转码
+        let _slot = io.acquire(IoClass::Transcode, priority).await;
+
+        let output = transcode_file_path(&file_id, &format);
+        // 先写入临时文件，完成后再改名，避免提供不完整的文件
+        let partial = output.with_extension(format!("partial.{}", format));
+        let result = async {
+            tokio::fs::create_dir_all(TRANSCODED_DIR)
+                .await
+                .map_err(|e| format!("Failed to create {} directory: {}", TRANSCODED_DIR, e))?;
+            transcode(&config, &encoders, &long_path(&source), &format, &long_path(&partial)).await?;
+            tokio::fs::rename(long_path(&partial), long_path(&output))
+                .await
+                .map_err(|e| format!("Failed to move transcoded file into place: {}", e))
+        }
+        .await;
+
+        match result {
+            Ok(()) => {
+                info!("Transcoded file ID {} to {}", file_id, format);
+                set_transcode_status(&db_pool, &file_id, &format, Some(&path_to_string(&output)), "done", None).await;
+                evict_transcodes(&db_pool, config.transcode_cache_max_bytes, &file_id).await;
+            }
+            Err(e) => {
+                error!("Failed to transcode file ID {}: {}", file_id, e);
+                let _ = tokio::fs::remove_file(long_path(&partial)).await;
+                set_transcode_status(&db_pool, &file_id, &format, None, "failed", Some(&e)).await;
+            }
+        }
+    });
+}
+
+/// Status, format and, once done, path of the transcode of `file_id`
+pub async fn fetch_transcode(db_pool: &SqlitePool, file_id: &str) -> Result<Option<(String, String, Option<String>)>, String> {
+    sqlx::query_as::<_, (String, String, Option<String>)>("SELECT status, format, path FROM transcodes WHERE file_id = ?")
+        .bind(file_id)
+        .fetch_optional(db_pool)
+        .await
+        .map_err(|e| {
+            error!("Failed to fetch transcode: {}", e);
+            "Failed to fetch transcode".to_string()
+        })
+}
+
+pub async fn download_transcode(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    Path(file_id): Path<String>,
+) -> impl IntoResponse {
+    let db_pool = &ctx.app_state.db_pool;
+
+    if let Err(resp) = ensure_file_allowed(&ctx, &client_addr, &file_id).await {
+        return resp;
+    }
+
+    let path = match fetch_transcode(db_pool, &file_id).await {
+        Ok(Some((status, _, Some(path)))) if status == "done" => path,
+        // 被淘汰的转码在请求时重新生成
+        Ok(Some((status, format, _))) if status == EVICTED => {
+            let source = match db_pool.fetch_file_record(&file_id).await {
+                Ok((_, _, _, 2, file_path)) => PathBuf::from(file_path),
+                Ok(_) | Err(_) => return error_response(StatusCode::NOT_FOUND, "TRANSCODE_NOT_FOUND", "The file is no longer available to transcode".to_string()),
+            };
+            info!("Transcoding file ID {} to {} again after it was evicted", file_id, format);
+            // 有人正在请求该转码，优先于其他排队的转码
+            spawn_transcode(&ctx, file_id.clone(), source, format, Priority::High);
+            return error_response(StatusCode::ACCEPTED, "TRANSCODE_PENDING", "The transcode was evicted from the cache and is being made again".to_string());
+        }
+        Ok(_) => return error_response(StatusCode::NOT_FOUND, "TRANSCODE_NOT_FOUND", "No finished transcode for this file".to_string()),
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_TRANSCODE_ERROR", e),
+    };
+
+    let mut buffer = Vec::new();
+    let read = async {
+        let mut file = tokio::fs::File::open(long_path(std::path::Path::new(&path))).await?;
+        file.read_to_end(&mut buffer).await
+    }
+    .await;
+    if let Err(e) = read {
+        error!("Failed to read transcoded file {}: {}", path, e);
+        return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read transcoded file").into_response();
+    }
+
+    touch_transcode(db_pool, &file_id).await;
+    record_traffic(db_pool, &client_principal(&client_addr), 0, buffer.len() as u64).await;
+
+    let content_type = mime_guess::from_path(&path).first_or_octet_stream().to_string();
+    (StatusCode::OK, [(header::CONTENT_TYPE, content_type)], buffer).into_response()
+}
@@ -0,0 +1,183 @@
+//! Transcoded copies are a cache: they can outgrow the originals, so
+//! `transcoded/` is kept under `NASCRAFT_TRANSCODE_CACHE_MAX_BYTES` by evicting
+//! the transcodes downloaded least recently. An evicted transcode is made
+//! again the next time it is requested.
+
+use axum::{
+    extract::{ConnectInfo, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use std::net::SocketAddr;
+use crate::context::AppContext;
+use crate::derived_files::{remove_derived_files, KIND_TRANSCODE};
+use crate::helper::{error_response, ApiResponse};
+use crate::paths::long_path;
+use crate::profiles::ensure_unrestricted;
+use crate::storage_buckets::{PurgeResult, BUCKET_TRANSCODES};
+use crate::tenants::ensure_not_tenant;
+
+/// Status of a transcode whose file was evicted from the cache
+pub const EVICTED: &str = "evicted";
+
+/// A finished transcode in the cache
+#[derive(Debug, Serialize)]
+pub struct CacheEntry {
+    pub file_id: String,
+    pub format: String,
+    #[serde(skip)]
+    pub path: String,
+    pub bytes: u64,
+    #[serde(with = "crate::api::timestamp")]
+    pub last_accessed_at: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TranscodeCache {
+    /// 0 when the cache isn't bounded
+    pub max_bytes: u64,
+    pub total_bytes: u64,
+    /// Least recently used first, i.e. in eviction order
+    pub entries: Vec<CacheEntry>,
+}
+
+/// Finished transcodes, least recently used first, with their size on disk
+pub async fn fetch_cache_entries(db_pool: &SqlitePool) -> Result<Vec<CacheEntry>, String> {
+    let rows: Vec<(String, String, String, i64)> = sqlx::query_as(
+        "SELECT file_id, format, path, last_accessed_at FROM transcodes
+         WHERE status = 'done' AND path IS NOT NULL ORDER BY last_accessed_at, file_id"
+    )
+    .fetch_all(db_pool)
+    .await
+    .map_err(|e| {
+        error!("Failed to fetch cached transcodes: {}", e);
+        "Failed to fetch cached transcodes".to_string()
+    })?;
+
+    let mut entries = Vec::with_capacity(rows.len());
+    for (file_id, format, path, last_accessed_at) in rows {
+        let bytes = tokio::fs::metadata(long_path(std::path::Path::new(&path))).await.map(|m| m.len()).unwrap_or(0);
+        entries.push(CacheEntry { file_id, format, path, bytes, last_accessed_at });
+    }
+    Ok(entries)
+}
+
+/// Record a download of the transcode of `file_id`
+pub async fn touch_transcode(db_pool: &SqlitePool, file_id: &str) {
+    if let Err(e) = sqlx::query("UPDATE transcodes SET last_accessed_at = strftime('%s', 'now') WHERE file_id = ?")
+        .bind(file_id)
+        .execute(db_pool)
+        .await
+    {
+        error!("Failed to record access to the transcode of file ID {}: {}", file_id, e);
+    }
+}
+
+async fn evict_entry(db_pool: &SqlitePool, entry: &CacheEntry) -> Result<(), String> {
+    match tokio::fs::remove_file(long_path(std::path::Path::new(&entry.path))).await {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => {
+            warn!("Failed to evict transcode {}: {}", entry.path, e);
+            return Err(format!("Failed to delete {}", entry.path));
+        }
+    }
+    sqlx::query("UPDATE transcodes SET status = ?, path = NULL, updated_at = strftime('%s', 'now') WHERE file_id = ? AND status = 'done'")
+        .bind(EVICTED)
+        .bind(&entry.file_id)
+        .execute(db_pool)
+        .await
+        .map_err(|e| {
+            error!("Failed to mark the transcode of file ID {} evicted: {}", entry.file_id, e);
+            "Failed to update the transcode".to_string()
+        })?;
+    remove_derived_files(db_pool, &entry.file_id, KIND_TRANSCODE).await
+}
+
+/// Evict the least recently used transcodes until the cache fits in
+/// `max_bytes`; `keep`, the transcode just made, is never evicted
+pub async fn evict_transcodes(db_pool: &SqlitePool, max_bytes: u64, keep: &str) {
+    if max_bytes == 0 {
+        return;
+    }
+    let entries = match fetch_cache_entries(db_pool).await {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    let mut total: u64 = entries.iter().map(|e| e.bytes).sum();
+    for entry in entries.iter().filter(|e| e.file_id != keep) {
+        if total <= max_bytes {
+            break;
+        }
+        if evict_entry(db_pool, entry).await.is_ok() {
+            info!("Evicted the {} transcode of file ID {} ({} bytes) from the cache", entry.format, entry.file_id, entry.bytes);
+            total -= entry.bytes;
+        }
+    }
+}
+
+/// Evict every finished transcode, or only that of `file_id`
+pub async fn evict_all(db_pool: &SqlitePool, file_id: Option<&str>) -> Result<PurgeResult, String> {
+    let mut result = PurgeResult { bucket: BUCKET_TRANSCODES.to_string(), ..Default::default() };
+    for entry in fetch_cache_entries(db_pool).await? {
+        if file_id.is_some_and(|id| id != entry.file_id) {
+            continue;
+        }
+        evict_entry(db_pool, &entry).await?;
+        result.files += 1;
+        result.bytes += entry.bytes;
+    }
+    Ok(result)
+}
+
+/// The cached transcodes in eviction order. Unrestricted profiles outside
+/// tenants only.
+pub async fn get_transcode_cache(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+) -> Response {
+    if let Err(resp) = ensure_not_tenant().await {
+        return resp;
+    }
+    if let Err(resp) = ensure_unrestricted(&ctx, &client_addr).await {
+        return resp;
+    }
+    match fetch_cache_entries(&ctx.app_state.db_pool).await {
+        Ok(entries) => (StatusCode::OK, Json(ApiResponse::success(TranscodeCache {
+            max_bytes: ctx.config.load().transcode_cache_max_bytes,
+            total_bytes: entries.iter().map(|e| e.bytes).sum(),
+            entries,
+        }))).into_response(),
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_CACHE_ERROR", e),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct PurgeCacheQuery {
+    file_id: Option<String>,
+}
+
+/// Evict every cached transcode, or that of `file_id`
+pub async fn purge_transcode_cache(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    Query(query): Query<PurgeCacheQuery>,
+) -> Response {
+    if let Err(resp) = ensure_not_tenant().await {
+        return resp;
+    }
+    if let Err(resp) = ensure_unrestricted(&ctx, &client_addr).await {
+        return resp;
+    }
+    match evict_all(&ctx.app_state.db_pool, query.file_id.as_deref()).await {
+        Ok(result) => {
+            info!("Evicted {} transcodes ({} bytes) from the cache", result.files, result.bytes);
+            (StatusCode::OK, Json(ApiResponse::success(result))).into_response()
+        }
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, "PURGE_ERROR", e),
+    }
+}
@@ -1,649 +1,1469 @@
-use axum::{
-    body::Body,
-    extract::{Path, Query, State},
-    http::{HeaderMap, StatusCode},
-    response::IntoResponse,
-    Json,
-};
-use futures::StreamExt;
-use sha2::{Sha256, Digest as ShaDigest};
-use tokio::fs::{self, OpenOptions};
-use tokio::io::{AsyncSeekExt, AsyncWriteExt, AsyncReadExt};
-use tokio::sync::Mutex;
-use std::collections::HashMap;
-use serde::{Deserialize, Serialize};
-use serde_json::json;
-use log::{error, info};
-use sanitize_filename::sanitize;
-use uuid::Uuid;
-use sqlx::{SqlitePool, Transaction, Sqlite};
-use crate::init_env::check_system_initialized;
-use crate::upload_dao::{fetch_file_record, update_upload_progress, get_total_uploaded, update_file_status_and_path, fetch_chunk_size, initialize_upload_progress, save_upload_state_to_db, fetch_uploaded_files, fetch_total_uploaded_files,  fetch_upload_progress, fetch_file_by_checksum, update_file_meta_info};
-use chrono::Utc;
-use md5::Md5;
-use crate::context::AppContext;
-use crate::thumbnail::{is_image_file, generate_thumbnail, ThumbnailConfig};
-use crate::upload_dao::update_file_thumbnail_path;
-
-#[derive(Debug)]
-pub struct AppState {
-    pub uploads: Mutex<HashMap<String, UploadState>>,
-    pub db_pool: SqlitePool,
-}
-
-impl Default for AppState {
-    fn default() -> Self {
-        Self {
-            uploads: Mutex::new(HashMap::new()),
-            db_pool: SqlitePool::connect_lazy("sqlite::memory:").expect("failed to create default sqlite pool"),
-        }
-    }
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct UploadState {
-    pub id: String,
-    pub filename: String,
-    pub total_size: u64,
-    pub checksum: String,
-}
-
-impl UploadState {
-    pub async fn save_to_db(&self, tx: &mut Transaction<'_, Sqlite>, file_path: &str) -> Result<(), String> {
-        save_upload_state_to_db(tx, &self.id, &self.filename, self.total_size, &self.checksum, file_path).await
-    }
-}
-
-#[derive(Debug, Serialize)]
-struct ApiResponse<T> {
-    message: String,
-    status: i32,
-    code: String,
-    data: Option<T>,
-}
-
-impl<T> ApiResponse<T> {
-    fn success(message: &str, data: T) -> Self {
-        Self {
-            message: message.to_string(),
-            status: 1,
-            code: "0".to_string(),
-            data: Some(data),
-        }
-    }
-
-    fn error(message: &str, code: &str) -> Self {
-        Self {
-            message: message.to_string(),
-            status: 0,
-            code: code.to_string(),
-            data: None,
-        }
-    }
-}
-
-pub async fn upload_file(
-    State(ctx): State<AppContext>,
-    headers: HeaderMap,
-    body: Body,
-) -> impl IntoResponse {
-    let db_pool = &ctx.app_state.db_pool;
-    if let Err(_) = check_system_initialized(db_pool).await {
-        return (StatusCode::BAD_REQUEST, Json(ApiResponse::<()>::error(
-            "System not initialized",
-            "SYSTEM_NOT_INITIALIZED"
-        ))).into_response();
-    }
-
-
-    let file_id = match headers
-        .get("X-File-ID")
-        .and_then(|h| h.to_str().ok()) {
-            Some(id) => id.to_string(),
-            None => {
-                return (StatusCode::BAD_REQUEST, Json(ApiResponse::<()>::error(
-                    "Missing file ID",
-                    "MISSING_FILE_ID"
-                ))).into_response();
-            }
-        };
-
-    let start_offset = match headers
-        .get("X-Start-Offset")
-        .and_then(|h| h.to_str().ok())
-        .and_then(|h| h.parse::<u64>().ok()) {
-            Some(offset) => offset,
-            None => {
-                error!("Missing or invalid start offset");
-                return (StatusCode::BAD_REQUEST, "Missing or invalid start offset").into_response();
-            }
-        };
-
-    let (filename, _, total_size, _, _) = match fetch_file_record(db_pool, &file_id).await {
-        Ok(record) => record,
-        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
-    };
-
-    let safe_filename = sanitize(&filename);
-    let total_size = total_size as u64;
-
-    let content_length = match headers
-        .get(axum::http::header::CONTENT_LENGTH)
-        .and_then(|h| h.to_str().ok())
-        .and_then(|h| h.parse::<u64>().ok()) {
-            Some(len) => len,
-            None => {
-                error!("Invalid content length");
-                return (StatusCode::BAD_REQUEST, "Invalid content length").into_response();
-            }
-        };
-
-    let content_range = headers
-        .get(axum::http::header::CONTENT_RANGE)
-        .and_then(|h| h.to_str().ok());
-
-    let (start_pos, _end_pos) = match content_range {
-        Some(range) => {
-            let parts: Vec<&str> = range.split('/').next()
-                .unwrap_or("bytes 0-0")
-                .split('-')
-                .collect();
-            let start = parts[0].replace("bytes ", "").parse::<u64>().unwrap_or(0);
-            let end = parts.get(1).and_then(|&s| s.parse::<u64>().ok()).unwrap_or(start + content_length - 1);
-            (start, end)
-        },
-        None => (0u64, content_length - 1)
-    };
-
-    // 分片文件路径
-    let chunk_file_path = format!("uploads/{}_chunk_{}", safe_filename, start_offset);
-
-    let mut file = match OpenOptions::new()
-        .create(true)
-        .write(true)
-        .open(&chunk_file_path)
-        .await {
-            Ok(f) => f,
-            Err(e) => {
-                error!("File error: {}", e);
-                return (StatusCode::INTERNAL_SERVER_ERROR, format!("File error: {}", e)).into_response();
-            }
-        };
-
-    // 移动文件指针到 start_pos
-    if let Err(e) = file.seek(tokio::io::SeekFrom::Start(start_pos-start_offset)).await {
-        error!("Failed to seek file: {}", e);
-        return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to seek file: {}", e)).into_response();
-    }
-
-    let mut hasher = Sha256::new();
-    let mut uploaded_size = start_pos;
-
-    let mut payload = body.into_data_stream();
-    while let Some(chunk) = payload.next().await {
-        let chunk = chunk.map_err(|e| {
-            error!("Payload error: {}", e);
-            (StatusCode::INTERNAL_SERVER_ERROR, format!("Payload error: {}", e)).into_response()
-        });
-        let chunk = match chunk {
-            Ok(c) => c,
-            Err(resp) => return resp,
-        };
-
-        // 计算剩余需要写入的字节数
-        let remaining_bytes = content_length.saturating_sub(uploaded_size - start_pos);
-        let bytes_to_write = chunk.len().min(remaining_bytes as usize);
-
-        if let Err(e) = file.write_all(&chunk[..bytes_to_write]).await {
-            error!("Write error: {}", e);
-            return (StatusCode::INTERNAL_SERVER_ERROR, format!("Write error: {}", e)).into_response();
-        }
-        hasher.update(&chunk[..bytes_to_write]);
-        uploaded_size += bytes_to_write as u64;
-        info!("file_id: {}, uploaded_size: {}, bytes_to_write: {},start_offset: {}, start_pos: {}, content_length: {}", file_id, uploaded_size, bytes_to_write, start_offset, start_pos, content_length);
-
-        let checksum = format!("{:x}", hasher.clone().finalize());
-
-        // 更新上传进度表，仅更新 uploaded_size 和 checksum
-        if let Err(e) = update_upload_progress(db_pool, uploaded_size-start_pos, &checksum, &file_id, start_offset).await {
-            return (StatusCode::INTERNAL_SERVER_ERROR, e).into_response();
-        }
-
-        // 如果已经写入了足够的字节数，退出循环
-        if uploaded_size - start_pos >= content_length {
-            break;
-        }
-    }
-
-    // Log successful chunk upload
-    info!("Chunk uploaded successfully for file ID: {}, start_offset: {}", file_id, start_offset);
-
-    // 检查所有分片是否上传完成
-    let total_uploaded = match get_total_uploaded(db_pool, &file_id).await {
-        Ok(size) => size,
-        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
-    };
-
-    if total_uploaded >= total_size {
-        // 更新文件状态为处理中
-        if let Err(e) = update_file_status_and_path(db_pool, &file_id, 0, 1, "").await {
-            return (StatusCode::INTERNAL_SERVER_ERROR, e).into_response();
-        }
-
-        // 组合分片文件为完整文件
-        let final_file_path = format!("uploads/{}", safe_filename);
-        if let Err(e) = merge_chunks(&safe_filename, total_size).await {
-            return (StatusCode::INTERNAL_SERVER_ERROR, e).into_response();
-        }
-
-        // Log successful merge
-        info!("Chunks merged successfully for file ID: {}", file_id);
-
-        // 计算合并后文件的 MD5 哈希值
-        let mut file = match OpenOptions::new().read(true).open(&final_file_path).await {
-            Ok(f) => f,
-            Err(e) => {
-                error!("Failed to open final file for hashing: {}", e);
-                return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to open final file for hashing").into_response();
-            }
-        };
-
-        let mut hasher = Md5::new();
-        let mut buffer = [0; 1024];
-        loop {
-            let n = match file.read(&mut buffer).await {
-                Ok(n) if n == 0 => break,
-                Ok(n) => n,
-                Err(e) => {
-                    error!("Failed to read final file for hashing: {}", e);
-                    return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read final file for hashing").into_response();
-                }
-            };
-            hasher.update(&buffer[..n]);
-        }
-        let calculated_md5 = format!("{:x}", hasher.finalize());
-
-        // 从数据库中获取预期的哈希值
-        let (_, expected_md5, _, _, _) = match fetch_file_record(db_pool, &file_id).await {
-            Ok(record) => record,
-            Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
-        };
-
-        // 比较哈希值
-        if calculated_md5 != expected_md5 {
-            return (StatusCode::INTERNAL_SERVER_ERROR, "File is corrupted: MD5 hash mismatch").into_response();
-        }
-
-        // Log successful checksum validation
-        info!("Checksum validated successfully for file ID: {}", file_id);
-
-        // 获取文件元信息
-        let file_metadata = match fs::metadata(&final_file_path).await {
-            Ok(meta) => meta,
-            Err(e) => {
-                error!("Failed to get file metadata: {}", e);
-                return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to get file metadata: {}", e)).into_response();
-            }
-        };
-
-        let file_mtime = file_metadata.modified()
-            .map(|t| t.duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_secs() as i64)
-            .unwrap_or(0);
-        let file_ctime = file_metadata.created()
-            .map(|t| t.duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_secs() as i64)
-            .unwrap_or(file_mtime);
-
-        // 获取inode（仅Unix-like系统）
-        let file_ino = std::fs::metadata(&final_file_path)
-            .ok()
-            .and_then(|m| std::os::unix::fs::MetadataExt::ino(&m).try_into().ok())
-            .unwrap_or(0);
-
-        // 更新文件元信息
-        if let Err(e) = update_file_meta_info(db_pool, &file_id, file_mtime, file_ctime, file_ino).await {
-            error!("Failed to update file meta info: {}", e);
-            return (StatusCode::INTERNAL_SERVER_ERROR, e).into_response();
-        }
-
-        // 更新文件状态为已完成并更新文件路径
-        if let Err(e) = update_file_status_and_path(db_pool, &file_id, 1, 2, &final_file_path).await {
-            return (StatusCode::INTERNAL_SERVER_ERROR, e).into_response();
-        }
-
-        // Generate thumbnail if this is an image file
-        if is_image_file(&safe_filename) {
-            let config = ThumbnailConfig::default();
-            if let Some(thumbnail_path) = generate_thumbnail(&config, &final_file_path, &calculated_md5).await {
-                if let Err(e) = update_file_thumbnail_path(db_pool, &file_id, &thumbnail_path).await {
-                    error!("Failed to save thumbnail path to database: {}", e);
-                    // Don't fail the upload if thumbnail generation fails
-                }
-            }
-        }
-
-        (StatusCode::OK, Json(ApiResponse::success(
-            "File upload completed successfully",
-            json!({
-                "status": "success",
-                "filename": safe_filename,
-                "size": total_size,
-                "checksum": calculated_md5
-            })
-        ))).into_response()
-    } else {
-        let final_checksum = format!("{:x}", hasher.finalize());
-
-        (StatusCode::OK, Json(ApiResponse::success(
-            "Chunk upload successful",
-            json!({
-                "status": "range_success",
-                "filename": safe_filename,
-                "size": uploaded_size,
-                "checksum": final_checksum
-            })
-        ))).into_response()
-    }
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct FileMetadata {
-    pub filename: String,
-    pub total_size: u64,
-    pub checksum: String,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct ChunkInfo {
-    pub start_offset: u64,
-    pub end_offset: u64,
-    pub chunk_size: u64,
-}
-
-pub async fn submit_file_metadata(
-    State(ctx): State<AppContext>,
-    Json(metadata): Json<FileMetadata>,
-) -> impl IntoResponse {
-    let db_pool = &ctx.app_state.db_pool;
-    if let Err(_) = check_system_initialized(db_pool).await {
-        return (StatusCode::BAD_REQUEST, Json(ApiResponse::<()>::error(
-            "System not initialized",
-            "SYSTEM_NOT_INITIALIZED"
-        ))).into_response();
-    }
-
-    let safe_filename = sanitize(&metadata.filename);
-
-    // 检查文件是否已存在（基于 checksum 去重）
-    match fetch_file_by_checksum(db_pool, &metadata.checksum).await {
-        Ok(Some((existing_file_id, existing_filename, existing_file_path))) => {
-            info!("File with checksum {} already exists (file_id: {}), skipping upload", metadata.checksum, existing_file_id);
-            return (StatusCode::OK, Json(ApiResponse::success(
-                "File already exists, upload skipped",
-                json!({
-                    "status": "duplicate",
-                    "message": "File with same checksum already exists on server",
-                    "id": existing_file_id,
-                    "filename": existing_filename,
-                    "file_path": existing_file_path,
-                    "total_size": metadata.total_size,
-                    "checksum": metadata.checksum,
-                    "skipped": true
-                })
-            ))).into_response();
-        }
-        Ok(None) => {
-            info!("File with checksum {} not found, proceeding with upload", metadata.checksum);
-        }
-        Err(e) => {
-            error!("Failed to check file by checksum: {}", e);
-            return (StatusCode::INTERNAL_SERVER_ERROR, Json(ApiResponse::<()>::error(
-                &e,
-                "CHECKSUM_CHECK_ERROR"
-            ))).into_response();
-        }
-    }
-
-    let unique_id = Uuid::new_v4().to_string();
-    let file_id = unique_id.clone();
-
-    let mut uploads = ctx.app_state.uploads.lock().await;
-    let upload_state = UploadState {
-        id: unique_id.clone(),
-        filename: safe_filename.clone(),
-        total_size: metadata.total_size,
-        checksum: metadata.checksum.clone(),
-    };
-
-    // Start a transaction
-    let mut tx = match db_pool.begin().await {
-        Ok(transaction) => transaction,
-        Err(e) => {
-            error!("Failed to begin transaction: {}", e);
-            return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to begin transaction").into_response();
-        }
-    };
-
-    // Save to database
-    if let Err(e) = upload_state.save_to_db(&mut tx, "").await {
-        tx.rollback().await.unwrap_or_else(|e| error!("Failed to rollback transaction: {}", e));
-        return (StatusCode::INTERNAL_SERVER_ERROR, Json(ApiResponse::<()>::error(
-            &e,
-            "DB_SAVE_ERROR"
-        ))).into_response();
-    }
-
-    // Get chunk size configuration
-    let chunk_size = match fetch_chunk_size(db_pool).await {
-        Ok(size) => size,
-        Err(e) => {
-            tx.rollback().await.unwrap_or_else(|e| error!("Failed to rollback transaction: {}", e));
-            return (StatusCode::INTERNAL_SERVER_ERROR, Json(ApiResponse::<()>::error(
-                &e,
-                "FETCH_CHUNK_SIZE_ERROR"
-            ))).into_response();
-        }
-    };
-
-    // Calculate number of chunks and initialize upload_progress table
-    let num_chunks = (metadata.total_size + chunk_size - 1) / chunk_size;
-    let mut chunks = Vec::new();
-
-    for i in 0..num_chunks {
-        let start_offset = i * chunk_size;
-        let end_offset = ((i + 1) * chunk_size).min(metadata.total_size)-1;
-            let chunk_size= end_offset - start_offset+1;
-
-        if let Err(e) = initialize_upload_progress(&mut tx, &file_id, &safe_filename, chunk_size, start_offset, end_offset).await {
-            tx.rollback().await.unwrap_or_else(|e| error!("Failed to rollback transaction: {}", e));
-            return (StatusCode::INTERNAL_SERVER_ERROR, e).into_response();
-        }
-
-        chunks.push(ChunkInfo {
-            start_offset,
-            end_offset,
-            chunk_size,
-        });
-    }
-
-    // Commit the transaction
-    if let Err(e) = tx.commit().await {
-        error!("Failed to commit transaction: {}", e);
-        return (StatusCode::INTERNAL_SERVER_ERROR, Json(ApiResponse::<()>::error(
-            &e.to_string(),
-            "COMMIT_TRANSACTION_ERROR"
-        ))).into_response();
-    }
-
-    // Save to in-memory state
-    uploads.insert(safe_filename.clone(), upload_state);
-
-    (StatusCode::OK, Json(ApiResponse::success(
-        "Metadata submitted successfully",
-        json!({
-            "id": file_id,
-            "filename": safe_filename,
-            "total_size": metadata.total_size,
-            "chunk_size": chunk_size,
-            "total_chunks": num_chunks,
-            "chunks": chunks
-        })
-    ))).into_response()
-}
-
-// 新增辅助函数
-async fn merge_chunks(filename: &str, total_size: u64) -> Result<(), String> {
-    let final_file_path = format!("uploads/{}", filename);
-    let mut final_file = match OpenOptions::new()
-        .create(true)
-        .write(true)
-        .open(&final_file_path)
-        .await {
-            Ok(file) => file,
-            Err(e) => {
-                error!("Failed to create final file: {}", e);
-                return Err("Failed to create final file".to_string());
-            }
-        };
-
-    for start in (0..total_size).step_by(1024 * 1024) {
-        let chunk_file_path = format!("uploads/{}_chunk_{}", filename, start);
-        let mut chunk_file = match OpenOptions::new()
-            .read(true)
-            .open(&chunk_file_path)
-            .await {
-                Ok(file) => file,
-                Err(e) => {
-                    error!("Failed to open chunk file: {}", e);
-                    return Err("Failed to open chunk file".to_string());
-                }
-            };
-
-        if let Err(e) = tokio::io::copy(&mut chunk_file, &mut final_file).await {
-            error!("Failed to copy chunk to final file: {}", e);
-            return Err("Failed to copy chunk to final file".to_string());
-        }
-
-        if let Err(e) = fs::remove_file(&chunk_file_path).await {
-            error!("Failed to delete chunk file: {}", e);
-            return Err("Failed to delete chunk file".to_string());
-        }
-    }
-
-    Ok(())
-}
-
-#[derive(Deserialize)]
-pub struct Pagination {
-    page: u32,
-    page_size: u32,
-    status: Option<i32>,
-    sort_by: Option<String>,
-    order: Option<String>,
-}
-
-pub async fn get_uploaded_files(
-    State(ctx): State<AppContext>,
-    Query(query): Query<Pagination>,
-) -> impl IntoResponse {
-    let page = query.page;
-    let page_size = query.page_size;
-    let status = query.status;
-    let sort_by = query.sort_by.as_deref().unwrap_or("id");
-    let order = query.order.as_deref().unwrap_or("asc");
-
-
-    let db_pool = &ctx.app_state.db_pool;
-
-    let total_files = match fetch_total_uploaded_files(db_pool, status).await {
-        Ok(total) => total,
-        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, Json(ApiResponse::<()>::error(
-            &e,
-            "FETCH_TOTAL_FILES_ERROR",
-        ))).into_response(),
-    };
-
-    match fetch_uploaded_files(db_pool, page, page_size, status, sort_by, order).await {
-        Ok(mut files) => {
-            // Add thumbnail_url for files that have a thumbnail
-            for file in &mut files {
-                if file.thumbnail_path.is_some() {
-                    file.thumbnail_url = Some(format!("/api/thumbnail/{}", file.file_id));
-                }
-            }
-
-            (StatusCode::OK, Json(ApiResponse::success(
-                "Fetched uploaded files successfully",
-                json!({
-                    "total_files": total_files,
-                    "files": files
-                }),
-            ))).into_response()
-        },
-        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(ApiResponse::<()>::error(
-            &e,
-            "FETCH_FILES_ERROR",
-        ))).into_response(),
-    }
-}
-
-pub async fn get_upload_status(
-    State(ctx): State<AppContext>,
-    Path(file_id_str): Path<String>,
-) -> impl IntoResponse {
-    let db_pool = &ctx.app_state.db_pool;
-
-    // Fetch file record to get the current status
-    let (_filename, _, _, status, _) = match fetch_file_record(db_pool, &file_id_str).await {
-        Ok(record) => record,
-        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, Json(ApiResponse::<()>::error(
-            &e,
-            "FETCH_FILE_RECORD_ERROR",
-        ))).into_response(),
-    };
-
-    // If status is 1 (processing) or 2 (completed), return it directly
-    let status_str = match status {
-        1 => "processing",
-        2 => "completed",
-        _ => {
-            // Fetch upload progress for each chunk
-            let chunk_progress = match fetch_upload_progress(db_pool, &file_id_str).await {
-                Ok(progress) => progress,
-                Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, Json(ApiResponse::<()>::error(
-                    &e,
-                    "FETCH_PROGRESS_ERROR",
-                ))).into_response(),
-            };
-
-            // Determine overall status
-            let now = Utc::now().timestamp();
-            let is_paused = chunk_progress.iter().all(|chunk| {
-                now - chunk.last_updated > 60 // Check if last updated is more than 60 seconds ago
-            });
-
-            if is_paused {
-                "paused"
-            } else {
-                "uploading"
-            }
-        }
-    };
-
-    // Prepare the response
-    let mut response_data = json!({
-        "file_id": file_id_str,
-        "status": status_str,
-    });
-
-    // Include chunk information only if status is not processing or completed
-    if status_str != "processing" && status_str != "completed" {
-        let chunk_progress = fetch_upload_progress(db_pool, &file_id_str).await.unwrap_or_default();
-        response_data["chunks"] = json!(chunk_progress);
-    }
-
-    (StatusCode::OK, Json(ApiResponse::success(
-        "Fetched upload status successfully",
-        response_data,
-    ))).into_response()
-}
-
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, OriginalUri, Path, Query, State},
+    http::{HeaderMap, StatusCode, Uri},
+    response::IntoResponse,
+    Json,
+};
+use futures::StreamExt;
+use tokio::fs::{self, OpenOptions};
+use tokio::io::{AsyncSeekExt, AsyncWriteExt, AsyncReadExt};
+use tokio::sync::{oneshot, Mutex};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use log::{error, info, warn};
+use uuid::Uuid;
+use sqlx::{SqlitePool, Transaction, Sqlite};
+use crate::init_env::check_system_initialized;
+use crate::client_devices::record_upload_client;
+use crate::upload_dao::{initialize_hole_progress, initialize_upload_progress, save_upload_state_to_db, set_file_placement, set_file_priority, fetch_file_by_upload_key, fetch_file_priority, has_unfinished_upload_named, set_upload_key, KeyedUpload};
+use crate::repository::{ProgressRepository, UploadRepository};
+use chrono::Utc;
+use md5::{Md5, Digest};
+use crate::context::AppContext;
+use crate::events::ServerEvent;
+use crate::thumbnail::{is_image_file, is_video_file, generate_thumbnail, ThumbnailConfig};
+use crate::content_range::parse_content_range;
+use crate::api::{ChunkInfo, ChunkProgress, FileConflict, FileMetadata, FileSortKey, Hole, SortOrder};
+use crate::helper::{error_response, ApiResponse, MAX_PAGE_SIZE};
+use crate::hashing::{HashAlgorithm, UploadHasher};
+use crate::traffic::{client_principal, record_traffic};
+use crate::analytics::{record_upload_completed, record_upload_failure};
+use crate::usage::record_file_usage;
+use crate::upload_progress::UploadProgressTracker;
+use crate::upload_hints::{signed_chunk, upload_hints};
+use crate::media_library::{attach_media_titles, spawn_scrape};
+use crate::playback::attach_watch_states;
+use crate::library_query::LibraryQuery;
+use crate::filename::{sanitize_filename, normalize_original_filename, SanitizePolicy};
+use crate::file_locks::{ensure_unlocked, lock_token};
+use crate::paths::{chunk_dir, chunk_file_path, final_file_path, folder_file_path, file_inode, long_path, path_to_string};
+use crate::folders::{fetch_file_folder, fetch_visible_folder, Folder};
+use crate::tenants::{check_quota, current_tenant, ensure_file_in_tenant, fetch_file_tenant, Tenant};
+use crate::transcode::spawn_transcode;
+use crate::io_scheduler::IoClass;
+use crate::file_edit::file_etag;
+use crate::quarantine::{check_extension, quarantine_file, quarantine_reason, QUARANTINED};
+use crate::outbox::update_status_with_event;
+
+#[derive(Debug)]
+pub struct AppState {
+    pub uploads: Mutex<HashMap<String, UploadState>>,
+    pub db_pool: SqlitePool,
+    pub activity: UploadActivity,
+    pub chunks: InFlightChunks,
+}
+
+impl Default for AppState {
+    fn default() -> Self {
+        Self {
+            uploads: Mutex::new(HashMap::new()),
+            activity: UploadActivity::default(),
+            chunks: InFlightChunks::default(),
+            db_pool: SqlitePool::connect_lazy("sqlite::memory:").expect("failed to create default sqlite pool"),
+        }
+    }
+}
+
+/// When each unfinished upload last received a chunk, to tell uploads in
+/// progress from abandoned ones
+#[derive(Debug, Default)]
+pub struct UploadActivity {
+    last_chunk: std::sync::Mutex<HashMap<String, Instant>>,
+}
+
+impl UploadActivity {
+    fn touch(&self, file_id: &str) {
+        self.last_chunk.lock().unwrap().insert(file_id.to_string(), Instant::now());
+    }
+
+    fn finish(&self, file_id: &str) {
+        self.last_chunk.lock().unwrap().remove(file_id);
+    }
+
+    /// Uploads that received a chunk within `window`; older ones are forgotten
+    pub fn active(&self, window: Duration) -> usize {
+        let mut last_chunk = self.last_chunk.lock().unwrap();
+        last_chunk.retain(|_, at| at.elapsed() < window);
+        last_chunk.len()
+    }
+}
+
+/// Chunks whose payload is being received. An entry is the chunk's lock, so
+/// two requests can't write the same chunk file at once, and lets a client
+/// abort the request to send other chunks first.
+#[derive(Debug, Default)]
+pub struct InFlightChunks {
+    chunks: std::sync::Mutex<HashMap<(String, u64), InFlightChunk>>,
+    next_id: AtomicU64,
+}
+
+#[derive(Debug)]
+struct InFlightChunk {
+    id: u64,
+    abort: oneshot::Sender<()>,
+}
+
+impl InFlightChunks {
+    /// Lock the chunk at `start_offset`; `None` while another request holds it
+    fn start(&self, file_id: &str, start_offset: u64) -> Option<ChunkGuard<'_>> {
+        let mut chunks = self.chunks.lock().unwrap();
+        let key = (file_id.to_string(), start_offset);
+        if chunks.contains_key(&key) {
+            return None;
+        }
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (abort, aborted) = oneshot::channel();
+        chunks.insert(key.clone(), InFlightChunk { id, abort });
+        Some(ChunkGuard { chunks: self, key, id, aborted })
+    }
+
+    /// Signal the request receiving the chunk at `start_offset` to stop and
+    /// release the chunk. Returns whether one was in flight.
+    pub fn abort(&self, file_id: &str, start_offset: u64) -> bool {
+        let entry = self.chunks.lock().unwrap().remove(&(file_id.to_string(), start_offset));
+        match entry {
+            Some(chunk) => {
+                let _ = chunk.abort.send(());
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Held by the request receiving a chunk; releases the chunk when dropped
+struct ChunkGuard<'a> {
+    chunks: &'a InFlightChunks,
+    key: (String, u64),
+    id: u64,
+    aborted: oneshot::Receiver<()>,
+}
+
+impl Drop for ChunkGuard<'_> {
+    fn drop(&mut self) {
+        // 已被中止的分片可能已由新请求持有，只移除自己的记录
+        let mut chunks = self.chunks.chunks.lock().unwrap();
+        if chunks.get(&self.key).is_some_and(|chunk| chunk.id == self.id) {
+            chunks.remove(&self.key);
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UploadState {
+    pub id: String,
+    pub filename: String,
+    pub original_filename: String,
+    pub total_size: u64,
+    pub checksum: String,
+    pub folder_id: Option<i64>,
+    pub owner: String,
+    pub tenant_id: Option<i64>,
+}
+
+impl UploadState {
+    pub async fn save_to_db(&self, tx: &mut Transaction<'_, Sqlite>, file_path: &str) -> Result<(), String> {
+        save_upload_state_to_db(tx, &self.id, &self.filename, &self.original_filename, self.total_size, &self.checksum, file_path).await?;
+        set_file_placement(tx, &self.id, self.folder_id, &self.owner, self.tenant_id).await
+    }
+}
+
+fn invalid_range_response(message: &str) -> axum::response::Response {
+    error!("Invalid upload range: {}", message);
+    error_response(StatusCode::BAD_REQUEST, "INVALID_CONTENT_RANGE", message.to_string())
+}
+
+#[derive(Deserialize)]
+pub struct AbortChunkRequest {
+    start_offset: u64,
+}
+
+/// Stop receiving a chunk that is being uploaded, so the client can send
+/// other chunks first. The bytes received so far are kept for resuming.
+pub async fn abort_chunk(
+    State(ctx): State<AppContext>,
+    Path(file_id): Path<String>,
+    Json(request): Json<AbortChunkRequest>,
+) -> impl IntoResponse {
+    if let Err(resp) = ensure_file_in_tenant(&ctx.app_state.db_pool, &file_id).await {
+        return resp;
+    }
+    let aborted = ctx.app_state.chunks.abort(&file_id, request.start_offset);
+    let message = if aborted {
+        info!("Aborting chunk at {} of file ID {}", request.start_offset, file_id);
+        "Chunk upload aborted"
+    } else {
+        "No upload of this chunk is in progress"
+    };
+    (StatusCode::OK, Json(ApiResponse::success_with_message(message, json!({
+        "file_id": file_id,
+        "start_offset": request.start_offset,
+        "aborted": aborted,
+    })))).into_response()
+}
+
+pub async fn upload_file(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Body,
+) -> impl IntoResponse {
+    let db_pool = &ctx.app_state.db_pool;
+    if check_system_initialized(db_pool).await.is_err() {
+        return error_response(StatusCode::BAD_REQUEST, "SYSTEM_NOT_INITIALIZED", "System not initialized".to_string());
+    }
+
+
+    // 预签名 URL 自带文件与分片，代替 X-File-ID 与 X-Start-Offset
+    let signed = match signed_chunk(&uri) {
+        Some(Ok(chunk)) => Some(chunk),
+        Some(Err(e)) => return error_response(StatusCode::FORBIDDEN, "INVALID_UPLOAD_URL", e),
+        None => None,
+    };
+
+    let file_id = match signed.as_ref().map(|chunk| chunk.file_id.as_str()).or_else(|| headers
+        .get("X-File-ID")
+        .and_then(|h| h.to_str().ok())) {
+            Some(id) => id.to_string(),
+            None => {
+                return error_response(StatusCode::BAD_REQUEST, "MISSING_FILE_ID", "Missing file ID".to_string());
+            }
+        };
+    if let Err(resp) = ensure_file_in_tenant(db_pool, &file_id).await {
+        return resp;
+    }
+
+    // X-Start-Offset 可省略：有合法 Content-Range 时由其推算所属分片
+    let header_start_offset = match headers.get("X-Start-Offset") {
+        _ if signed.is_some() => signed.as_ref().map(|chunk| chunk.start_offset),
+        Some(value) => match value.to_str().ok().and_then(|h| h.trim().parse::<u64>().ok()) {
+            Some(offset) => Some(offset),
+            None => {
+                error!("Invalid start offset");
+                return (StatusCode::BAD_REQUEST, "Invalid start offset").into_response();
+            }
+        },
+        None => None,
+    };
+
+    let content_range = match headers.get(axum::http::header::CONTENT_RANGE) {
+        Some(value) => match value.to_str().map_err(|_| "Content-Range is not valid ASCII".to_string()).and_then(parse_content_range) {
+            Ok(range) => Some(range),
+            Err(e) => return invalid_range_response(&e),
+        },
+        None => None,
+    };
+
+    // 可选的分片校验值：客户端提供本次请求数据的 SHA-256，校验失败时整个分片作废
+    let expected_chunk_checksum = headers
+        .get("X-Chunk-Checksum")
+        .and_then(|h| h.to_str().ok())
+        .map(|h| h.trim().to_lowercase());
+
+    let (filename, _, total_size, _, _) = match db_pool.fetch_file_record(&file_id).await {
+        Ok(record) => record,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
+    };
+
+    let safe_filename = sanitize_filename(&filename, ctx.config.load().filename_policy);
+    let total_size = total_size as u64;
+    ctx.app_state.activity.touch(&file_id);
+
+    let header_content_length = headers
+        .get(axum::http::header::CONTENT_LENGTH)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.parse::<u64>().ok());
+
+    // Content-Range 给出时决定写入位置和长度，Content-Length 可省略（如分块传输）
+    let (start_pos, content_length) = match (content_range, header_start_offset) {
+        (Some(range), _) => {
+            if let Some(total) = range.total.filter(|&total| total != total_size) {
+                return invalid_range_response(&format!("Content-Range total {} does not match file size {}", total, total_size));
+            }
+            if let Some(len) = header_content_length.filter(|&len| len != range.len()) {
+                return invalid_range_response(&format!("Content-Length {} does not match Content-Range length {}", len, range.len()));
+            }
+            (range.start, range.len())
+        }
+        (None, Some(offset)) => match header_content_length.filter(|&len| len > 0) {
+            Some(len) => (offset, len),
+            None => {
+                error!("Invalid content length");
+                return (StatusCode::BAD_REQUEST, "Invalid content length").into_response();
+            }
+        },
+        (None, None) => {
+            error!("Missing Content-Range and X-Start-Offset");
+            return (StatusCode::BAD_REQUEST, "Missing Content-Range or X-Start-Offset").into_response();
+        }
+    };
+
+    let (start_offset, chunk_end) = match db_pool.fetch_chunk_containing(&file_id, start_pos).await {
+        Ok(Some(chunk)) => chunk,
+        Ok(None) => return invalid_range_response(&format!("No chunk of this upload contains byte {}", start_pos)),
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
+    };
+    if let Some(offset) = header_start_offset.filter(|&offset| offset != start_offset) {
+        return invalid_range_response(&format!("X-Start-Offset {} does not match the chunk at {}-{} containing byte {}", offset, start_offset, chunk_end, start_pos));
+    }
+    if start_pos + content_length - 1 > chunk_end {
+        return invalid_range_response(&format!("Range {}-{} crosses the end of chunk {}-{}", start_pos, start_pos + content_length - 1, start_offset, chunk_end));
+    }
+
+    // 同一分片同时只接收一个请求，被中止的请求立即释放分片
+    let Some(mut chunk_guard) = ctx.app_state.chunks.start(&file_id, start_offset) else {
+        return error_response(
+            StatusCode::CONFLICT,
+            "CHUNK_IN_PROGRESS",
+            format!("Chunk {}-{} is already being received; wait for that request or abort it", start_offset, chunk_end),
+        );
+    };
+
+    // 分片文件路径，按 file_id 分目录存放
+    let chunk_file_path = long_path(&chunk_file_path(&file_id, start_offset));
+    if let Err(e) = fs::create_dir_all(long_path(&chunk_dir(&file_id))).await {
+        error!("Failed to create chunk directory: {}", e);
+        return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to create chunk directory").into_response();
+    }
+
+    let mut file = match OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&chunk_file_path)
+        .await {
+            Ok(f) => f,
+            Err(e) => {
+                error!("File error: {}", e);
+                return (StatusCode::INTERNAL_SERVER_ERROR, format!("File error: {}", e)).into_response();
+            }
+        };
+
+    // 移动文件指针到 start_pos
+    if let Err(e) = file.seek(tokio::io::SeekFrom::Start(start_pos-start_offset)).await {
+        error!("Failed to seek file: {}", e);
+        return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to seek file: {}", e)).into_response();
+    }
+
+    // 使用分片创建时记录的校验算法，避免部署配置变更后校验失败
+    let hash_algorithm = match db_pool.fetch_chunk_hash_algorithm(&file_id, start_offset).await {
+        Ok(name) => name
+            .and_then(|n| HashAlgorithm::parse(&n))
+            .unwrap_or(HashAlgorithm::Sha256),
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
+    };
+
+    let mut hasher = UploadHasher::new(hash_algorithm, content_length, ctx.config.load().hash_offload_min_bytes);
+
+    // 从分片中间续传时，分片校验值需覆盖已有数据，先重新计算已写入部分
+    let resume_prefix = start_pos - start_offset;
+    let mut chunk_hasher = None;
+    if resume_prefix > 0 {
+        let mut prefix_hasher = UploadHasher::new(hash_algorithm, resume_prefix + content_length, ctx.config.load().hash_offload_min_bytes);
+        match hash_chunk_prefix(&chunk_file_path, resume_prefix, &mut prefix_hasher).await {
+            Ok(None) => chunk_hasher = Some(prefix_hasher),
+            Ok(Some(received)) => return invalid_range_response(&format!(
+                "Only {} bytes of chunk {}-{} were received, resume from byte {}", received, start_offset, chunk_end, start_offset + received
+            )),
+            Err(e) => {
+                error!("Failed to hash existing chunk data: {}", e);
+                return (StatusCode::INTERNAL_SERVER_ERROR, e).into_response();
+            }
+        }
+    }
+
+    let mut uploaded_size = start_pos;
+    let mut progress = UploadProgressTracker::new(db_pool, &file_id, start_offset);
+
+    let mut payload = body.into_data_stream();
+    let mut overflow = false;
+    let mut aborted = false;
+    while let Some(chunk) = tokio::select! {
+        chunk = payload.next() => chunk,
+        _ = &mut chunk_guard.aborted => {
+            aborted = true;
+            None
+        }
+    } {
+        let chunk = chunk.map_err(|e| {
+            error!("Payload error: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, format!("Payload error: {}", e)).into_response()
+        });
+        let chunk = match chunk {
+            Ok(c) => c,
+            Err(resp) => {
+                // 客户端断开时保留已写入部分的进度，以便续传；带校验值的请求需整片重发
+                if expected_chunk_checksum.is_none() {
+                    let _ = progress.flush().await;
+                } else {
+                    discard_unverified_write(file, &chunk_file_path, resume_prefix).await;
+                }
+                record_upload_failure(db_pool, "PAYLOAD_ERROR").await;
+                return resp;
+            }
+        };
+
+        // 请求体超出声明的范围时不写入多余数据，整个请求作废
+        let remaining_bytes = content_length - (uploaded_size - start_pos);
+        if chunk.len() as u64 > remaining_bytes {
+            overflow = true;
+            break;
+        }
+        let bytes_to_write = chunk.len();
+
+        if let Err(e) = file.write_all(&chunk[..bytes_to_write]).await {
+            error!("Write error: {}", e);
+            if expected_chunk_checksum.is_some() {
+                discard_unverified_write(file, &chunk_file_path, resume_prefix).await;
+            }
+            record_upload_failure(db_pool, "WRITE_ERROR").await;
+            return (StatusCode::INTERNAL_SERVER_ERROR, format!("Write error: {}", e)).into_response();
+        }
+        let mut hashed = hasher.update(chunk.slice(..bytes_to_write)).await;
+        if let (Ok(()), Some(chunk_hasher)) = (&hashed, chunk_hasher.as_mut()) {
+            hashed = chunk_hasher.update(chunk.slice(..bytes_to_write)).await;
+        }
+        if let Err(e) = hashed {
+            error!("Hash error: {}", e);
+            if expected_chunk_checksum.is_some() {
+                discard_unverified_write(file, &chunk_file_path, resume_prefix).await;
+            }
+            return (StatusCode::INTERNAL_SERVER_ERROR, e).into_response();
+        }
+        uploaded_size += bytes_to_write as u64;
+        info!("file_id: {}, uploaded_size: {}, bytes_to_write: {},start_offset: {}, start_pos: {}, content_length: {}", file_id, uploaded_size, bytes_to_write, start_offset, start_pos, content_length);
+
+        // 带校验值的请求在校验通过前不更新进度，保证失败时进度不变
+        if expected_chunk_checksum.is_none() {
+            if let Err(e) = progress.record(uploaded_size - start_offset).await {
+                return (StatusCode::INTERNAL_SERVER_ERROR, e).into_response();
+            }
+        }
+    }
+
+    // 流量统计按实际接收的字节计算，与校验结果无关
+    let received = uploaded_size - start_pos;
+    record_traffic(db_pool, &client_principal(&client_addr), received, 0).await;
+
+    if aborted {
+        // 与客户端断开相同：保留已写入部分的进度以便续传，带校验值的请求需整片重发
+        let resume_from = if expected_chunk_checksum.is_none() {
+            let _ = progress.flush().await;
+            uploaded_size
+        } else {
+            discard_unverified_write(file, &chunk_file_path, resume_prefix).await;
+            start_pos
+        };
+        info!("Chunk {}-{} of file ID {} aborted after {} bytes", start_offset, chunk_end, file_id, received);
+        return error_response(
+            StatusCode::CONFLICT,
+            "CHUNK_ABORTED",
+            format!("Upload of chunk {}-{} was aborted, resume from byte {}", start_offset, chunk_end, resume_from),
+        );
+    }
+
+    if overflow || received < content_length {
+        let (code, message) = if overflow {
+            ("BODY_EXCEEDS_RANGE", format!("Request body is longer than the {} bytes declared for range starting at {}", content_length, start_pos))
+        } else {
+            ("INCOMPLETE_BODY", format!("Request body ended after {} of {} declared bytes, resume from byte {}", received, content_length, uploaded_size))
+        };
+        error!("Rejected chunk for file ID {}: {}", file_id, message);
+
+        // 超出范围或带校验值的请求整体作废；其余保留已写入部分以便续传
+        if overflow || expected_chunk_checksum.is_some() {
+            if let Err(e) = discard_chunk_write(file, &chunk_file_path, resume_prefix).await {
+                error!("Failed to discard rejected chunk data: {}", e);
+            }
+            if expected_chunk_checksum.is_none() {
+                let _ = progress.rollback(resume_prefix).await;
+            }
+        } else {
+            let _ = progress.flush().await;
+        }
+        record_upload_failure(db_pool, code).await;
+        let status = if overflow { StatusCode::PAYLOAD_TOO_LARGE } else { StatusCode::BAD_REQUEST };
+        return error_response(status, code, message);
+    }
+
+    let computed = match hasher.hex_digest().await {
+        Ok(checksum) => checksum,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
+    };
+    if let Some(expected) = &expected_chunk_checksum {
+        if *expected != computed {
+            error!("Chunk checksum mismatch for file ID: {}, start_offset: {}, expected: {}, computed: {}", file_id, start_offset, expected, computed);
+            if let Err(e) = discard_chunk_write(file, &chunk_file_path, resume_prefix).await {
+                error!("Failed to discard corrupted chunk data: {}", e);
+            }
+            record_upload_failure(db_pool, "CHUNK_CHECKSUM_MISMATCH").await;
+            return error_response(StatusCode::BAD_REQUEST, "CHUNK_CHECKSUM_MISMATCH", format!("Chunk checksum mismatch: expected {}, computed {}", expected, computed));
+        }
+    }
+
+    let chunk_checksum = match &chunk_hasher {
+        Some(chunk_hasher) => match chunk_hasher.hex_digest().await {
+            Ok(checksum) => checksum,
+            Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
+        },
+        None => computed,
+    };
+
+    // 更新上传进度表，仅更新 uploaded_size 和 checksum；进度从分片起点计算
+    if let Err(e) = progress.finish(uploaded_size - start_offset, &chunk_checksum).await {
+        return (StatusCode::INTERNAL_SERVER_ERROR, e).into_response();
+    }
+
+    // Log successful chunk upload
+    info!("Chunk uploaded successfully for file ID: {}, start_offset: {}", file_id, start_offset);
+
+    // 检查所有分片是否上传完成
+    let total_uploaded = match db_pool.get_total_uploaded(&file_id).await {
+        Ok(size) => size,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
+    };
+
+    if total_uploaded >= total_size {
+        ctx.app_state.activity.finish(&file_id);
+
+        // 提交到目录的文件合并到该目录下，目录策略在合并后继续生效
+        let folder = match fetch_file_folder(db_pool, &file_id).await {
+            Ok(folder) => folder,
+            Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
+        };
+        let tenant = match fetch_file_tenant(db_pool, &file_id).await {
+            Ok(tenant) => tenant,
+            Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
+        };
+        let stored_file_path = stored_file_path(folder.as_ref(), tenant.as_ref(), &safe_filename);
+
+        // 上传期间同名文件被锁定时暂不合并，分片保留，解锁后重传最后一个分片即可完成
+        if let Err(resp) = ensure_unlocked(db_pool, &path_to_string(&stored_file_path), lock_token(&headers)).await {
+            return resp;
+        }
+
+        let priority = match fetch_file_priority(db_pool, &file_id).await {
+            Ok(priority) => priority,
+            Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
+        };
+        // 等待合并名额后再标记为处理中，排队期间请求中断时状态不受影响
+        let merge_slot = ctx.io.acquire(IoClass::Merge, priority).await;
+
+        // 更新文件状态为处理中
+        if let Err(e) = db_pool.update_file_status_and_path(&file_id, 0, 1, "").await {
+            return (StatusCode::INTERNAL_SERVER_ERROR, e).into_response();
+        }
+
+        // 组合分片文件为完整文件
+        let chunks = match db_pool.fetch_upload_progress(&file_id).await {
+            Ok(chunks) => chunks,
+            Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
+        };
+        let final_file_path = long_path(&stored_file_path);
+        let merge_started = Instant::now();
+        if let Err(e) = merge_chunks(&file_id, chunks, &final_file_path).await {
+            record_upload_failure(db_pool, "MERGE_ERROR").await;
+            return (StatusCode::INTERNAL_SERVER_ERROR, e).into_response();
+        }
+
+        // Log successful merge
+        info!("Chunks merged successfully for file ID: {}", file_id);
+
+        // 计算合并后文件的 MD5 哈希值
+        let mut file = match OpenOptions::new().read(true).open(&final_file_path).await {
+            Ok(f) => f,
+            Err(e) => {
+                error!("Failed to open final file for hashing: {}", e);
+                return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to open final file for hashing").into_response();
+            }
+        };
+
+        let mut hasher = Md5::new();
+        let mut buffer = [0; 1024];
+        loop {
+            let n = match file.read(&mut buffer).await {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) => {
+                    error!("Failed to read final file for hashing: {}", e);
+                    return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read final file for hashing").into_response();
+                }
+            };
+            hasher.update(&buffer[..n]);
+        }
+        let calculated_md5 = format!("{:x}", hasher.finalize());
+
+        // 从数据库中获取预期的哈希值
+        let (_, expected_md5, _, _, _) = match db_pool.fetch_file_record(&file_id).await {
+            Ok(record) => record,
+            Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
+        };
+
+        // 比较哈希值
+        if calculated_md5 != expected_md5 {
+            record_upload_failure(db_pool, "FILE_CHECKSUM_MISMATCH").await;
+            return (StatusCode::INTERNAL_SERVER_ERROR, "File is corrupted: MD5 hash mismatch").into_response();
+        }
+
+        // Log successful checksum validation
+        info!("Checksum validated successfully for file ID: {}", file_id);
+        // 合并耗时包含分片拼接与整体 MD5 校验
+        let merge_ms = merge_started.elapsed().as_millis() as u64;
+        drop(merge_slot);
+
+        // 获取文件元信息
+        let file_metadata = match fs::metadata(&final_file_path).await {
+            Ok(meta) => meta,
+            Err(e) => {
+                error!("Failed to get file metadata: {}", e);
+                return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to get file metadata: {}", e)).into_response();
+            }
+        };
+
+        let file_mtime = file_metadata.modified()
+            .map(|t| t.duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_secs() as i64)
+            .unwrap_or(0);
+        let file_ctime = file_metadata.created()
+            .map(|t| t.duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_secs() as i64)
+            .unwrap_or(file_mtime);
+
+        // 获取inode（仅Unix-like系统）
+        let file_ino = file_inode(&file_metadata).unwrap_or(0);
+
+        // 更新文件元信息
+        if let Err(e) = db_pool.update_file_meta_info(&file_id, file_mtime, file_ctime, file_ino).await {
+            error!("Failed to update file meta info: {}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, e).into_response();
+        }
+
+        // 内容与扩展名不符的文件进入隔离，管理员放行前不对外提供
+        let quarantine = quarantine_reason(&ctx.config.load(), &stored_file_path, &safe_filename).await;
+        let event = ServerEvent::UploadCompleted {
+            file_id: file_id.clone(),
+            filename: safe_filename.clone(),
+            size: total_size,
+            tenant_id: tenant.as_ref().map(|t| t.id),
+            inbox: None,
+        };
+        let completed = match &quarantine {
+            Some(reason) => quarantine_file(db_pool, &file_id, 1, &path_to_string(&stored_file_path), reason).await,
+            // 更新文件状态为已完成并更新文件路径，完成事件在同一事务中写入发件箱
+            None => update_status_with_event(db_pool, &file_id, 1, 2, &path_to_string(&stored_file_path), &event).await,
+        };
+        if let Err(e) = completed {
+            return (StatusCode::INTERNAL_SERVER_ERROR, e).into_response();
+        }
+
+        // 上传耗时从提交元数据开始计算，旧记录没有创建时间时记为 0
+        let upload_ms = match db_pool.fetch_file_created_at(&file_id).await {
+            Ok(created_at) if created_at > 0 => (Utc::now().timestamp_millis() - created_at * 1000).max(0) as u64,
+            _ => 0,
+        };
+        record_upload_completed(db_pool, total_size, upload_ms, merge_ms).await;
+        record_file_usage(db_pool, &file_id, 1).await;
+        if let Some(reason) = quarantine {
+            return (StatusCode::OK, Json(ApiResponse::success_with_message(
+                "File uploaded and quarantined for review",
+                json!({
+                    "status": "quarantined",
+                    "filename": safe_filename,
+                    "size": total_size,
+                    "checksum": calculated_md5,
+                    "reason": reason
+                })
+            ))).into_response();
+        }
+        ctx.outbox.notify_one();
+
+        // Generate thumbnail if this is an image file
+        if is_image_file(&safe_filename) {
+            let config = ThumbnailConfig::default();
+            if let Some(thumbnail_path) = generate_thumbnail(&config, &path_to_string(&stored_file_path), &calculated_md5).await {
+                if let Err(e) = db_pool.update_file_thumbnail_path(&file_id, &thumbnail_path).await {
+                    error!("Failed to save thumbnail path to database: {}", e);
+                    // Don't fail the upload if thumbnail generation fails
+                }
+            }
+        }
+
+        // 视频文件在后台匹配片名信息，不影响上传结果
+        if is_video_file(&safe_filename) {
+            spawn_scrape(db_pool.clone(), ctx.config.load(), file_id.clone());
+            if let Some(format) = folder.as_ref().and_then(|f| f.auto_transcode.clone()) {
+                spawn_transcode(&ctx, file_id.clone(), stored_file_path.clone(), format, priority);
+            }
+        }
+
+        (StatusCode::OK, Json(ApiResponse::success_with_message(
+            "File upload completed successfully",
+            json!({
+                "status": "success",
+                "filename": safe_filename,
+                "size": total_size,
+                "checksum": calculated_md5
+            })
+        ))).into_response()
+    } else {
+        let final_checksum = match hasher.hex_digest().await {
+            Ok(checksum) => checksum,
+            Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
+        };
+
+        (StatusCode::OK, Json(ApiResponse::success_with_message(
+            "Chunk upload successful",
+            json!({
+                "status": "range_success",
+                "filename": safe_filename,
+                "size": uploaded_size,
+                "checksum": final_checksum,
+                "hash_algorithm": hash_algorithm.as_str()
+            })
+        ))).into_response()
+    }
+}
+
+
+pub async fn submit_file_metadata(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(metadata): Json<FileMetadata>,
+) -> impl IntoResponse {
+    let db_pool = &ctx.app_state.db_pool;
+    if check_system_initialized(db_pool).await.is_err() {
+        return error_response(StatusCode::BAD_REQUEST, "SYSTEM_NOT_INITIALIZED", "System not initialized".to_string());
+    }
+
+    let config = ctx.config.load();
+    let original_filename = normalize_original_filename(&metadata.filename);
+    let safe_filename = sanitize_filename(&metadata.filename, config.filename_policy);
+
+    // 目标目录的大小与类型策略在上传开始前检查
+    if let Err((code, message)) = check_extension(&config.blocked_extensions, &original_filename, "This server") {
+        return error_response(StatusCode::UNSUPPORTED_MEDIA_TYPE, code, message);
+    }
+    let holes = match normalize_holes(&metadata.holes, metadata.total_size) {
+        Ok(holes) => holes,
+        Err(message) => return error_response(StatusCode::BAD_REQUEST, "INVALID_HOLES", message),
+    };
+    let tenant = current_tenant();
+
+    // 带上传键的重复提交返回首次提交的上传
+    if let Some(upload_key) = &metadata.upload_key {
+        match fetch_file_by_upload_key(db_pool, upload_key, tenant.as_ref().map(|t| t.id)).await {
+            Ok(Some(existing)) => return repeated_submission(&ctx, &metadata, &original_filename, config.hash_algorithm, existing).await,
+            Ok(None) => {}
+            Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "UPLOAD_KEY_CHECK_ERROR", e),
+        }
+    }
+
+    let mut folder = None;
+    if let Some(folder_id) = metadata.folder_id {
+        let target = match fetch_visible_folder(db_pool, folder_id).await {
+            Ok(Some(folder)) => folder,
+            Ok(None) => return error_response(StatusCode::NOT_FOUND, "FOLDER_NOT_FOUND", "Folder not found".to_string()),
+            Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_FOLDER_ERROR", e),
+        };
+        if let Err((code, message)) = target.check_upload(&original_filename, metadata.total_size) {
+            let status = if code == "FILE_TOO_LARGE" { StatusCode::PAYLOAD_TOO_LARGE } else { StatusCode::UNSUPPORTED_MEDIA_TYPE };
+            return error_response(status, code, message);
+        }
+        folder = Some(target);
+    }
+    if let Some(tenant) = &tenant {
+        if let Err((code, message)) = check_quota(db_pool, tenant, metadata.total_size).await {
+            let status = if code == "QUOTA_EXCEEDED" { StatusCode::PAYLOAD_TOO_LARGE } else { StatusCode::INTERNAL_SERVER_ERROR };
+            return error_response(status, code, message);
+        }
+    }
+    let stored_file_path = stored_file_path(folder.as_ref(), tenant.as_ref(), &safe_filename);
+
+    // 检查文件是否已存在（基于 checksum 去重）
+    match db_pool.fetch_file_by_checksum(&metadata.checksum, tenant.as_ref().map(|t| t.id)).await {
+        Ok(Some((existing_file_id, existing_filename, existing_file_path))) => {
+            info!("File with checksum {} already exists (file_id: {}), skipping upload", metadata.checksum, existing_file_id);
+            return (StatusCode::OK, Json(ApiResponse::success_with_message(
+                "File already exists, upload skipped",
+                json!({
+                    "status": "duplicate",
+                    "message": "File with same checksum already exists on server",
+                    "id": existing_file_id,
+                    "filename": existing_filename,
+                    "file_path": existing_file_path,
+                    "total_size": metadata.total_size,
+                    "checksum": metadata.checksum,
+                    "skipped": true
+                })
+            ))).into_response();
+        }
+        Ok(None) => {
+            info!("File with checksum {} not found, proceeding with upload", metadata.checksum);
+        }
+        Err(e) => {
+            error!("Failed to check file by checksum: {}", e);
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "CHECKSUM_CHECK_ERROR", e);
+        }
+    }
+
+    // 同名文件只在客户端要求时覆盖，否则返回可用的名称供客户端选择
+    if !metadata.overwrite {
+        match filename_taken(db_pool, folder.as_ref(), tenant.as_ref(), &safe_filename).await {
+            Ok(false) => {}
+            Ok(true) => return filename_conflict(db_pool, folder.as_ref(), tenant.as_ref(), &original_filename, config.filename_policy).await,
+            Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "FILENAME_CHECK_ERROR", e),
+        }
+    }
+
+    // 覆盖同名文件时，被他人锁定则拒绝
+    if let Err(resp) = ensure_unlocked(db_pool, &path_to_string(&stored_file_path), lock_token(&headers)).await {
+        return resp;
+    }
+
+    let unique_id = Uuid::new_v4().to_string();
+    let file_id = unique_id.clone();
+
+    let mut uploads = ctx.app_state.uploads.lock().await;
+    let upload_state = UploadState {
+        id: unique_id.clone(),
+        filename: safe_filename.clone(),
+        original_filename: original_filename.clone(),
+        total_size: metadata.total_size,
+        checksum: metadata.checksum.clone(),
+        folder_id: metadata.folder_id,
+        owner: client_principal(&client_addr),
+        tenant_id: tenant.as_ref().map(|t| t.id),
+    };
+
+    // Start a transaction
+    let mut tx = match db_pool.begin().await {
+        Ok(transaction) => transaction,
+        Err(e) => {
+            error!("Failed to begin transaction: {}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to begin transaction").into_response();
+        }
+    };
+
+    // Save to database
+    if let Err(e) = upload_state.save_to_db(&mut tx, "").await {
+        tx.rollback().await.unwrap_or_else(|e| error!("Failed to rollback transaction: {}", e));
+        return error_response(StatusCode::INTERNAL_SERVER_ERROR, "DB_SAVE_ERROR", e);
+    }
+    if let Err(e) = record_upload_client(&mut tx, &file_id, &headers).await {
+        tx.rollback().await.unwrap_or_else(|e| error!("Failed to rollback transaction: {}", e));
+        return error_response(StatusCode::INTERNAL_SERVER_ERROR, "DB_SAVE_ERROR", e);
+    }
+    if let Err(e) = set_file_priority(&mut *tx, &file_id, metadata.priority.unwrap_or_default()).await {
+        tx.rollback().await.unwrap_or_else(|e| error!("Failed to rollback transaction: {}", e));
+        return error_response(StatusCode::INTERNAL_SERVER_ERROR, "DB_SAVE_ERROR", e);
+    }
+    if let Some(upload_key) = &metadata.upload_key {
+        match set_upload_key(&mut tx, &file_id, upload_key).await {
+            Ok(true) => {}
+            // 同一个键的并发提交先完成了
+            Ok(false) => {
+                tx.rollback().await.unwrap_or_else(|e| error!("Failed to rollback transaction: {}", e));
+                return match fetch_file_by_upload_key(db_pool, upload_key, tenant.as_ref().map(|t| t.id)).await {
+                    Ok(Some(existing)) => repeated_submission(&ctx, &metadata, &original_filename, config.hash_algorithm, existing).await,
+                    Ok(None) | Err(_) => error_response(StatusCode::INTERNAL_SERVER_ERROR, "UPLOAD_KEY_CHECK_ERROR", "Failed to fetch file by upload key".to_string()),
+                };
+            }
+            Err(e) => {
+                tx.rollback().await.unwrap_or_else(|e| error!("Failed to rollback transaction: {}", e));
+                return error_response(StatusCode::INTERNAL_SERVER_ERROR, "DB_SAVE_ERROR", e);
+            }
+        }
+    }
+
+    let chunk_size = config.chunk_size;
+
+    // Calculate number of chunks and initialize upload_progress table
+    // 数据区间按分片大小切分，空洞记为已完成的分片，不需要上传
+    let mut chunks = Vec::new();
+    let mut position = 0;
+    let extents = holes.iter().map(|hole| (hole.offset, Some(hole))).chain(std::iter::once((metadata.total_size, None)));
+    for (data_end, hole) in extents {
+        while position < data_end {
+            let start_offset = position;
+            let end_offset = (start_offset + chunk_size).min(data_end) - 1;
+            let chunk_size = end_offset - start_offset + 1;
+
+            if let Err(e) = initialize_upload_progress(&mut tx, &file_id, &safe_filename, chunk_size, start_offset, end_offset, config.hash_algorithm.as_str()).await {
+                tx.rollback().await.unwrap_or_else(|e| error!("Failed to rollback transaction: {}", e));
+                return (StatusCode::INTERNAL_SERVER_ERROR, e).into_response();
+            }
+
+            chunks.push(ChunkInfo {
+                start_offset,
+                end_offset,
+                chunk_size,
+                upload_url: None,
+            });
+            position = end_offset + 1;
+        }
+        if let Some(hole) = hole {
+            if let Err(e) = initialize_hole_progress(&mut tx, &file_id, &safe_filename, hole.offset, hole.offset + hole.length - 1).await {
+                tx.rollback().await.unwrap_or_else(|e| error!("Failed to rollback transaction: {}", e));
+                return (StatusCode::INTERNAL_SERVER_ERROR, e).into_response();
+            }
+            position = hole.offset + hole.length;
+        }
+    }
+    let num_chunks = chunks.len();
+
+    // Commit the transaction
+    if let Err(e) = tx.commit().await {
+        error!("Failed to commit transaction: {}", e);
+        return error_response(StatusCode::INTERNAL_SERVER_ERROR, "COMMIT_TRANSACTION_ERROR", e.to_string());
+    }
+
+    // Save to in-memory state
+    uploads.insert(safe_filename.clone(), upload_state);
+    let hints = upload_hints(&ctx, &file_id, &mut chunks);
+
+    (StatusCode::OK, Json(ApiResponse::success_with_message(
+        "Metadata submitted successfully",
+        json!({
+            "id": file_id,
+            "filename": safe_filename,
+            "original_filename": original_filename,
+            "total_size": metadata.total_size,
+            "chunk_size": chunk_size,
+            "hash_algorithm": config.hash_algorithm.as_str(),
+            "total_chunks": num_chunks,
+            "chunks": chunks,
+            "holes": holes,
+            "hints": hints
+        })
+    ))).into_response()
+}
+
+/// Names suggested when a submitted filename is taken
+const MAX_NAME_SUGGESTIONS: usize = 3;
+/// Numbered names tried before giving up on suggestions
+const MAX_NAME_ATTEMPTS: usize = 1000;
+
+/// Whether a new upload named `safe_filename` would collide with a stored
+/// file or an unfinished upload of the same name in the same place
+pub async fn filename_taken(db_pool: &SqlitePool, folder: Option<&Folder>, tenant: Option<&Tenant>, safe_filename: &str) -> Result<bool, String> {
+    if fs::try_exists(long_path(&stored_file_path(folder, tenant, safe_filename))).await.unwrap_or(true) {
+        return Ok(true);
+    }
+    has_unfinished_upload_named(db_pool, safe_filename, folder.map(|f| f.id), tenant.map(|t| t.id)).await
+}
+
+/// `name` with ` (n)` before its extension, e.g. `movie (1).mp4`
+pub fn numbered_filename(name: &str, n: usize) -> String {
+    match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => format!("{} ({}).{}", stem, n, extension),
+        _ => format!("{} ({})", name, n),
+    }
+}
+
+/// `409 FILE_EXISTS` with free numbered variants of `original_filename`
+async fn filename_conflict(
+    db_pool: &SqlitePool,
+    folder: Option<&Folder>,
+    tenant: Option<&Tenant>,
+    original_filename: &str,
+    policy: SanitizePolicy,
+) -> axum::response::Response {
+    let mut suggestions = Vec::new();
+    for n in 1..=MAX_NAME_ATTEMPTS {
+        if suggestions.len() == MAX_NAME_SUGGESTIONS {
+            break;
+        }
+        let candidate = numbered_filename(original_filename, n);
+        match filename_taken(db_pool, folder, tenant, &sanitize_filename(&candidate, policy)).await {
+            Ok(false) => suggestions.push(candidate),
+            Ok(true) => {}
+            Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "FILENAME_CHECK_ERROR", e),
+        }
+    }
+
+    info!("Refused upload of {}: a file with that name already exists", original_filename);
+    let message = match suggestions.first() {
+        Some(suggestion) => format!(
+            "A file named '{}' already exists; submit it as '{}' or with \"overwrite\": true to replace it",
+            original_filename, suggestion
+        ),
+        None => format!("A file named '{}' already exists; submit it with \"overwrite\": true to replace it", original_filename),
+    };
+    (StatusCode::CONFLICT, Json(ApiResponse::error_with_data(
+        "FILE_EXISTS".to_string(),
+        message,
+        FileConflict { filename: original_filename.to_string(), suggestions },
+    ))).into_response()
+}
+
+/// Most holes one upload may declare; their list fits in the metadata body limit
+const MAX_HOLES: usize = 16384;
+
+/// Sort the declared holes and merge adjacent ones. Empty, overlapping or
+/// out-of-range holes are rejected.
+fn normalize_holes(holes: &[Hole], total_size: u64) -> Result<Vec<Hole>, String> {
+    if holes.len() > MAX_HOLES {
+        return Err(format!("At most {} holes may be declared", MAX_HOLES));
+    }
+    let mut sorted = holes.to_vec();
+    sorted.sort_by_key(|hole| hole.offset);
+    let mut normalized: Vec<Hole> = Vec::with_capacity(sorted.len());
+    for hole in sorted {
+        if hole.length == 0 || hole.offset.checked_add(hole.length).is_none_or(|end| end > total_size) {
+            return Err(format!("Hole at {} of {} bytes is empty or ends past the file size {}", hole.offset, hole.length, total_size));
+        }
+        match normalized.last_mut() {
+            Some(last) if hole.offset < last.offset + last.length => {
+                return Err(format!("Hole at {} overlaps the hole at {}", hole.offset, last.offset));
+            }
+            Some(last) if hole.offset == last.offset + last.length => last.length += hole.length,
+            _ => normalized.push(hole),
+        }
+    }
+    Ok(normalized)
+}
+
+/// Answer a submission whose upload key was used before: the original plan
+/// while the upload is unfinished, like a duplicate once it is complete
+async fn repeated_submission(
+    ctx: &AppContext,
+    metadata: &FileMetadata,
+    original_filename: &str,
+    default_algorithm: HashAlgorithm,
+    existing: KeyedUpload,
+) -> axum::response::Response {
+    if existing.original_filename.as_deref() != Some(original_filename)
+        || existing.total_size as u64 != metadata.total_size
+        || existing.checksum != metadata.checksum
+        || existing.folder_id != metadata.folder_id
+    {
+        return error_response(StatusCode::UNPROCESSABLE_ENTITY, "UPLOAD_KEY_REUSED", "Upload key was already used for a different file".to_string());
+    }
+    info!("Repeated submission of file ID {} with the same upload key", existing.file_id);
+
+    if existing.status == 2 || existing.status == QUARANTINED {
+        return (StatusCode::OK, Json(ApiResponse::success_with_message(
+            "File already uploaded, upload skipped",
+            json!({
+                "status": "duplicate",
+                "message": "File with same upload key already exists on server",
+                "id": existing.file_id,
+                "filename": existing.filename,
+                "file_path": existing.file_path,
+                "total_size": metadata.total_size,
+                "checksum": metadata.checksum,
+                "skipped": true
+            })
+        ))).into_response();
+    }
+
+    let db_pool = &ctx.app_state.db_pool;
+    // 重新提交可调整优先级，例如用户想立即播放仍在上传的文件
+    if let Some(priority) = metadata.priority {
+        if let Err(e) = set_file_priority(db_pool, &existing.file_id, priority).await {
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "DB_SAVE_ERROR", e);
+        }
+    }
+    let mut progress = match db_pool.fetch_upload_progress(&existing.file_id).await {
+        Ok(progress) => progress,
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_PROGRESS_ERROR", e),
+    };
+    progress.sort_by_key(|c| c.start_offset);
+    let holes: Vec<Hole> = progress
+        .iter()
+        .filter(|c| c.is_hole)
+        .map(|c| Hole { offset: c.start_offset as u64, length: (c.end_offset - c.start_offset + 1) as u64 })
+        .collect();
+    let mut chunks: Vec<ChunkInfo> = progress
+        .iter()
+        .filter(|c| !c.is_hole)
+        .map(|c| ChunkInfo {
+            start_offset: c.start_offset as u64,
+            end_offset: c.end_offset as u64,
+            chunk_size: (c.end_offset - c.start_offset + 1) as u64,
+            upload_url: None,
+        })
+        .collect();
+    let hints = upload_hints(ctx, &existing.file_id, &mut chunks);
+    let hash_algorithm = progress
+        .iter()
+        .find(|c| !c.is_hole)
+        .and_then(|c| c.hash_algorithm.clone())
+        .unwrap_or_else(|| default_algorithm.as_str().to_string());
+
+    (StatusCode::OK, Json(ApiResponse::success_with_message(
+        "Metadata already submitted",
+        json!({
+            "id": existing.file_id,
+            "filename": existing.filename,
+            "original_filename": original_filename,
+            "total_size": metadata.total_size,
+            "chunk_size": chunks.first().map_or(0, |c| c.chunk_size),
+            "hash_algorithm": hash_algorithm,
+            "total_chunks": chunks.len(),
+            "chunks": chunks,
+            "holes": holes,
+            "hints": hints
+        })
+    ))).into_response()
+}
+
+/// Where a completed upload is stored: in its folder, else in its tenant's directory, else directly under uploads/
+pub fn stored_file_path(folder: Option<&Folder>, tenant: Option<&Tenant>, filename: &str) -> PathBuf {
+    match (folder, tenant) {
+        (Some(folder), _) => folder_file_path(&folder.path, filename),
+        (None, Some(tenant)) => folder_file_path(&tenant.storage_dir(), filename),
+        (None, None) => final_file_path(filename),
+    }
+}
+
+// 丢弃本次请求写入的数据：截断回写入前的长度，若分片此前为空则直接删除
+async fn discard_chunk_write(file: tokio::fs::File, chunk_file_path: &std::path::Path, previous_len: u64) -> Result<(), String> {
+    if previous_len == 0 {
+        drop(file);
+        return fs::remove_file(chunk_file_path)
+            .await
+            .map_err(|e| format!("Failed to delete chunk file: {}", e));
+    }
+
+    file.set_len(previous_len)
+        .await
+        .map_err(|e| format!("Failed to truncate chunk file: {}", e))
+}
+
+// 带校验值的请求中途失败时丢弃未经校验的数据，否则启动时按分片文件大小恢复进度会把它们计为已上传
+async fn discard_unverified_write(file: tokio::fs::File, chunk_file_path: &std::path::Path, resume_prefix: u64) {
+    if let Err(e) = discard_chunk_write(file, chunk_file_path, resume_prefix).await {
+        error!("Failed to discard unverified chunk data: {}", e);
+    }
+}
+
+/// 读取分片文件开头 `len` 字节送入校验器。文件不足 `len` 字节时返回实际长度。
+async fn hash_chunk_prefix(chunk_file_path: &std::path::Path, len: u64, hasher: &mut UploadHasher) -> Result<Option<u64>, String> {
+    let mut file = fs::File::open(chunk_file_path)
+        .await
+        .map_err(|e| format!("Failed to open chunk file: {}", e))?;
+    let on_disk = file.metadata()
+        .await
+        .map_err(|e| format!("Failed to read chunk file metadata: {}", e))?
+        .len();
+    if on_disk < len {
+        return Ok(Some(on_disk));
+    }
+
+    let mut remaining = len;
+    let mut buffer = vec![0u8; 1024 * 1024];
+    while remaining > 0 {
+        let want = remaining.min(buffer.len() as u64) as usize;
+        file.read_exact(&mut buffer[..want])
+            .await
+            .map_err(|e| format!("Failed to read chunk file: {}", e))?;
+        hasher.update(axum::body::Bytes::copy_from_slice(&buffer[..want])).await?;
+        remaining -= want as u64;
+    }
+    Ok(None)
+}
+
+/// Concatenate `chunks` into the final file and remove them. Holes are skipped
+/// over, so the file system stores them sparsely where it can.
+async fn merge_chunks(file_id: &str, mut chunks: Vec<ChunkProgress>, final_file_path: &std::path::Path) -> Result<(), String> {
+    if let Some(parent) = final_file_path.parent() {
+        if let Err(e) = fs::create_dir_all(parent).await {
+            error!("Failed to create directory for final file: {}", e);
+            return Err("Failed to create directory for final file".to_string());
+        }
+    }
+    let mut final_file = match OpenOptions::new()
+        .create(true)
+        .truncate(true)
+        .write(true)
+        .open(&final_file_path)
+        .await {
+            Ok(file) => file,
+            Err(e) => {
+                error!("Failed to create final file: {}", e);
+                return Err("Failed to create final file".to_string());
+            }
+        };
+
+    chunks.sort_unstable_by_key(|c| c.start_offset);
+    let file_size = chunks.last().map_or(0, |c| c.end_offset as u64 + 1);
+    for chunk in chunks {
+        if chunk.is_hole {
+            if let Err(e) = final_file.seek(tokio::io::SeekFrom::Start(chunk.end_offset as u64 + 1)).await {
+                error!("Failed to skip hole in final file: {}", e);
+                return Err("Failed to skip hole in final file".to_string());
+            }
+            continue;
+        }
+        let chunk_file_path = long_path(&chunk_file_path(file_id, chunk.start_offset as u64));
+        let mut chunk_file = match OpenOptions::new()
+            .read(true)
+            .open(&chunk_file_path)
+            .await {
+                Ok(file) => file,
+                Err(e) => {
+                    error!("Failed to open chunk file: {}", e);
+                    return Err("Failed to open chunk file".to_string());
+                }
+            };
+
+        if let Err(e) = tokio::io::copy(&mut chunk_file, &mut final_file).await {
+            error!("Failed to copy chunk to final file: {}", e);
+            return Err("Failed to copy chunk to final file".to_string());
+        }
+
+        if let Err(e) = fs::remove_file(&chunk_file_path).await {
+            error!("Failed to delete chunk file: {}", e);
+            return Err("Failed to delete chunk file".to_string());
+        }
+    }
+    // 以空洞结尾的文件需要补足长度
+    if let Err(e) = final_file.set_len(file_size).await {
+        error!("Failed to set length of final file: {}", e);
+        return Err("Failed to set length of final file".to_string());
+    }
+    if let Err(e) = fs::remove_dir(long_path(&chunk_dir(file_id))).await {
+        warn!("Failed to remove chunk directory of file ID {}: {}", file_id, e);
+    }
+
+    Ok(())
+}
+
+#[derive(Deserialize)]
+pub struct Pagination {
+    page: Option<u32>,
+    page_size: Option<u32>,
+    status: Option<i32>,
+    sort_by: Option<String>,
+    order: Option<String>,
+}
+
+pub async fn get_uploaded_files(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    OriginalUri(uri): OriginalUri,
+    Query(query): Query<Pagination>,
+) -> impl IntoResponse {
+    let page = query.page.unwrap_or(1).max(1);
+    let page_size = query.page_size.unwrap_or(10).clamp(1, MAX_PAGE_SIZE);
+    let status = query.status;
+    // 排序字段拼接进 SQL，只接受白名单中的值
+    let sort_by = match query.sort_by.as_deref().map(|s| FileSortKey::parse(s).ok_or(s)).transpose() {
+        Ok(sort_by) => sort_by.unwrap_or_default(),
+        Err(value) => {
+            let keys: Vec<&str> = FileSortKey::ALL.iter().map(|k| k.as_str()).collect();
+            return error_response(StatusCode::BAD_REQUEST, "INVALID_SORT", format!("sort_by must be one of {}, got '{}'", keys.join(", "), value));
+        }
+    };
+    let order = match query.order.as_deref().map(|s| SortOrder::parse(s).ok_or(s)).transpose() {
+        Ok(order) => order.unwrap_or_default(),
+        Err(value) => return error_response(StatusCode::BAD_REQUEST, "INVALID_SORT", format!("order must be asc or desc, got '{}'", value)),
+    };
+
+    let db_pool = &ctx.app_state.db_pool;
+    let principal = client_principal(&client_addr);
+
+    let library = match LibraryQuery::for_client(db_pool, &ctx.config.load(), &principal).await {
+        Ok(library) => library.status(status).sort(sort_by, order).paginate(page, page_size),
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "PROFILE_CHECK_ERROR", e),
+    };
+
+    let total_files = match library.count(db_pool).await {
+        Ok(total) => total,
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_TOTAL_FILES_ERROR", e),
+    };
+
+    match library.fetch(db_pool).await {
+        Ok(mut files) => {
+            // Add thumbnail_url for files that have a thumbnail
+            for file in &mut files {
+                if file.thumbnail_path.is_some() {
+                    file.thumbnail_url = Some(format!("/api/v1/thumbnail/{}", file.file_id));
+                }
+                file.etag = Some(file_etag(&file.checksum, file.last_updated, file.revision));
+            }
+            attach_media_titles(db_pool, &mut files).await;
+            attach_watch_states(db_pool, &principal, &mut files).await;
+
+            (StatusCode::OK, Json(ApiResponse::paginated(files, page, page_size, total_files, &uri))).into_response()
+        },
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_FILES_ERROR", e),
+    }
+}
+
+/// Longest `wait` of a long-polling `upload_status` request
+const MAX_STATUS_WAIT_SECS: u64 = 60;
+
+/// How often a long-polling request re-reads the status; chunk progress is
+/// saved about this often while data is received
+const STATUS_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Deserialize)]
+pub struct UploadStatusQuery {
+    /// Hold the request up to this many seconds until the status differs
+    /// from `since`
+    #[serde(default)]
+    wait: u64,
+    /// `version` of the status the client already has; the status at the
+    /// time of the request when unset
+    since: Option<String>,
+}
+
+/// Identifies a status response, so clients can tell whether anything changed
+fn status_version(status: &serde_json::Value) -> String {
+    format!("{:x}", Md5::digest(status.to_string().as_bytes()))
+}
+
+pub async fn get_upload_status(
+    State(ctx): State<AppContext>,
+    Path(file_id_str): Path<String>,
+    Query(query): Query<UploadStatusQuery>,
+) -> impl IntoResponse {
+    let db_pool = &ctx.app_state.db_pool;
+    if let Err(resp) = ensure_file_in_tenant(db_pool, &file_id_str).await {
+        return resp;
+    }
+
+    // 长轮询：状态或进度变化、上传结束或超时后返回
+    let deadline = Instant::now() + Duration::from_secs(query.wait.min(MAX_STATUS_WAIT_SECS));
+    let mut since = query.since;
+    loop {
+        let mut response_data = match upload_status(db_pool, &file_id_str).await {
+            Ok(response_data) => response_data,
+            Err((code, e)) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, code, e),
+        };
+        let version = status_version(&response_data);
+        let finished = matches!(response_data["status"].as_str(), Some("completed" | "quarantined"));
+        let unchanged = since.get_or_insert_with(|| version.clone()) == &version;
+        if !unchanged || finished || Instant::now() >= deadline {
+            response_data["version"] = json!(version);
+            return (StatusCode::OK, Json(ApiResponse::success_with_message(
+                "Fetched upload status successfully",
+                response_data,
+            ))).into_response();
+        }
+        tokio::time::sleep(STATUS_POLL_INTERVAL.min(deadline.saturating_duration_since(Instant::now()))).await;
+    }
+}
+
+/// Status of an upload, with its chunks while it is neither processing nor
+/// completed. Errors carry the response code.
+async fn upload_status<R: UploadRepository + ProgressRepository>(repo: &R, file_id: &str) -> Result<serde_json::Value, (&'static str, String)> {
+    // Fetch file record to get the current status
+    let (_filename, _, _, status, _) = repo
+        .fetch_file_record(file_id)
+        .await
+        .map_err(|e| ("FETCH_FILE_RECORD_ERROR", e))?;
+
+    // If status is 1 (processing) or 2 (completed), return it directly
+    let status_str = match status {
+        1 => "processing",
+        2 => "completed",
+        QUARANTINED => "quarantined",
+        _ => {
+            // Fetch upload progress for each chunk
+            let chunk_progress = repo
+                .fetch_upload_progress(file_id)
+                .await
+                .map_err(|e| ("FETCH_PROGRESS_ERROR", e))?;
+
+            // Determine overall status
+            let now = Utc::now().timestamp();
+            let is_paused = chunk_progress.iter().all(|chunk| {
+                now - chunk.last_updated > 60 // Check if last updated is more than 60 seconds ago
+            });
+
+            return Ok(json!({
+                "file_id": file_id,
+                "status": if is_paused { "paused" } else { "uploading" },
+                "chunks": chunk_progress,
+            }));
+        }
+    };
+
+    Ok(json!({
+        "file_id": file_id,
+        "status": status_str,
+    }))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockall::predicate::eq;
+    use crate::repository::MockRepositories;
+
+    fn record_with_status(status: i32) -> (String, String, i64, i32, String) {
+        ("a.bin".to_string(), "checksum".to_string(), 20, status, String::new())
+    }
+
+    fn chunk(start_offset: i64, end_offset: i64, uploaded_size: i64, last_updated: i64) -> ChunkProgress {
+        ChunkProgress {
+            start_offset,
+            end_offset,
+            uploaded_size,
+            checksum: String::new(),
+            hash_algorithm: None,
+            last_updated,
+            is_hole: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn finished_uploads_report_their_status_without_chunks() {
+        for (status, expected) in [(1, "processing"), (2, "completed"), (QUARANTINED, "quarantined")] {
+            let mut repo = MockRepositories::default();
+            repo.uploads.expect_fetch_file_record().with(eq("id")).returning(move |_| Ok(record_with_status(status)));
+            repo.progress.expect_fetch_upload_progress().never();
+
+            let result = upload_status(&repo, "id").await.unwrap();
+            assert_eq!(result, json!({ "file_id": "id", "status": expected }));
+        }
+    }
+
+    #[tokio::test]
+    async fn unfinished_uploads_list_their_chunks() {
+        let now = Utc::now().timestamp();
+        let mut repo = MockRepositories::default();
+        repo.uploads.expect_fetch_file_record().returning(|_| Ok(record_with_status(0)));
+        repo.progress
+            .expect_fetch_upload_progress()
+            .with(eq("id"))
+            .returning(move |_| Ok(vec![chunk(0, 9, 10, now - 600), chunk(10, 19, 4, now)]));
+
+        let result = upload_status(&repo, "id").await.unwrap();
+        assert_eq!(result["status"], "uploading");
+        assert_eq!(result["chunks"].as_array().map(Vec::len), Some(2));
+        assert_eq!(result["chunks"][1]["uploaded_size"], 4);
+    }
+
+    #[tokio::test]
+    async fn uploads_without_recent_progress_are_paused() {
+        let now = Utc::now().timestamp();
+        let mut repo = MockRepositories::default();
+        repo.uploads.expect_fetch_file_record().returning(|_| Ok(record_with_status(0)));
+        repo.progress.expect_fetch_upload_progress().returning(move |_| Ok(vec![chunk(0, 19, 4, now - 61)]));
+
+        assert_eq!(upload_status(&repo, "id").await.unwrap()["status"], "paused");
+    }
+
+    #[tokio::test]
+    async fn status_errors_carry_their_response_code() {
+        let mut repo = MockRepositories::default();
+        repo.uploads.expect_fetch_file_record().returning(|_| Err("File not found".to_string()));
+        assert_eq!(upload_status(&repo, "id").await.unwrap_err(), ("FETCH_FILE_RECORD_ERROR", "File not found".to_string()));
+
+        let mut repo = MockRepositories::default();
+        repo.uploads.expect_fetch_file_record().returning(|_| Ok(record_with_status(0)));
+        repo.progress.expect_fetch_upload_progress().returning(|_| Err("database is locked".to_string()));
+        assert_eq!(upload_status(&repo, "id").await.unwrap_err(), ("FETCH_PROGRESS_ERROR", "database is locked".to_string()));
+    }
+}
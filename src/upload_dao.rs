@@ -1,332 +1,651 @@
-use sqlx::{Sqlite, SqlitePool, Transaction, Row};
-use log::{error, info};
-use serde::Serialize;
-use sqlx::FromRow;
-use chrono;
-
-pub async fn fetch_file_record(db_pool: &SqlitePool, file_id: &str) -> Result<(String, String, i64, i32, String), String> {
-    match sqlx::query("SELECT filename, checksum, total_size, status, file_path, thumbnail_path FROM upload_file_meta WHERE file_id = ?")
-        .bind(file_id)
-        .fetch_one(db_pool)
-        .await
-    {
-        Ok(row) => {
-            let filename: String = row.get("filename");
-            let checksum: String = row.get("checksum");
-            let total_size: i64 = row.get("total_size");
-            let status: Option<i32> = row.try_get("status").ok();
-            let file_path: String = row.get("file_path");
-            // We don't need thumbnail_path for this result type, just ignore it
-            let _: Option<String> = row.try_get("thumbnail_path").ok();
-            Ok((filename, checksum, total_size, status.unwrap_or(0), file_path))
-        }
-        Err(e) => {
-            error!("Failed to fetch file record: {}", e);
-            Err("Failed to fetch file record".to_string())
-        }
-    }
-}
-
-pub async fn update_upload_progress(db_pool: &SqlitePool, uploaded_size: u64, checksum: &str, file_id: &str, start_offset: u64) -> Result<(), String> {
-    if let Err(e) = sqlx::query("UPDATE upload_progress SET uploaded_size = ?, checksum = ? WHERE file_id = ? AND start_offset = ?")
-        .bind(uploaded_size as i64)
-        .bind(checksum)
-        .bind(file_id)
-        .bind(start_offset as i64)
-        .execute(db_pool)
-        .await
-    {
-        error!("Failed to update upload progress: {}", e);
-        return Err("Failed to update upload progress".to_string());
-    }
-    Ok(())
-}
-
-pub async fn get_total_uploaded(db_pool: &SqlitePool, file_id: &str) -> Result<u64, String> {
-    match sqlx::query("SELECT COALESCE(SUM(uploaded_size), 0) as total_uploaded FROM upload_progress WHERE file_id = ?")
-        .bind(file_id)
-        .fetch_one(db_pool)
-        .await
-    {
-        Ok(row) => {
-            let total_uploaded: i64 = row.get("total_uploaded");
-            Ok(total_uploaded.max(0) as u64)
-        }
-        Err(e) => {
-            error!("Failed to get total uploaded size: {}", e);
-            Err("Failed to get total uploaded size".to_string())
-        }
-    }
-}
-
-pub async fn update_file_status_and_path(
-    db_pool: &SqlitePool,
-    file_id: &str,
-    current_status: i32,
-    new_status: i32,
-    file_path: &str,
-) -> Result<(), String> {
-    // Get current timestamp
-    let current_time = chrono::Utc::now().timestamp();
-
-    if let Err(e) = sqlx::query("UPDATE upload_file_meta SET status = ?, file_path = ?, last_updated = ? WHERE file_id = ? AND status = ?")
-        .bind(new_status)
-        .bind(file_path)
-        .bind(current_time)
-        .bind(file_id)
-        .bind(current_status)
-        .execute(db_pool)
-        .await
-    {
-        error!("Failed to update file status and path: {}", e);
-        return Err("Failed to update file status and path".to_string());
-    }
-    Ok(())
-}
-
-pub async fn fetch_chunk_size(db_pool: &SqlitePool) -> Result<u64, String> {
-    match sqlx::query("SELECT config_value FROM system_config WHERE config_key = 'chunk_size'")
-        .fetch_one(db_pool)
-        .await
-    {
-        Ok(row) => {
-            let config_value: String = row.get("config_value");
-            config_value.parse().map_err(|_| "Invalid chunk size".to_string())
-        }
-        Err(e) => {
-            error!("Failed to fetch chunk size: {}", e);
-            Err("Failed to fetch chunk size".to_string())
-        }
-    }
-}
-
-pub async fn initialize_upload_progress(
-    tx: &mut Transaction<'_, Sqlite>,
-    file_id: &str,
-    safe_filename: &str,
-    total_size: u64,
-    start_offset: u64,
-    end_offset: u64,
-) -> Result<(), String> {
-    if let Err(e) = sqlx::query(
-        "INSERT INTO upload_progress (file_id, checksum, filename, total_size, uploaded_size, start_offset, end_offset) VALUES (?, ?, ?, ?, ?, ?, ?)"
-    )
-    .bind(file_id)
-    .bind("") // Initial checksum is empty
-    .bind(safe_filename)
-    .bind(total_size as i64)
-    .bind(0) // Initial uploaded size is 0
-    .bind(start_offset as i64)
-    .bind(end_offset as i64)
-    .execute(&mut **tx)
-    .await
-    {
-        error!("Failed to initialize upload progress: {}", e);
-        return Err("Failed to initialize upload progress".to_string());
-    }
-    Ok(())
-}
-
-pub async fn save_upload_state_to_db(
-    tx: &mut Transaction<'_, Sqlite>,
-    file_id: &str,
-    filename: &str,
-    total_size: u64,
-    checksum: &str,
-    file_path: &str,
-) -> Result<(), String> {
-    if let Err(e) = sqlx::query(
-        "INSERT INTO upload_file_meta (file_id, filename, total_size, checksum, file_path, file_mtime, file_ctime, file_ino) VALUES (?, ?, ?, ?, ?, 0, 0, 0)"
-    )
-    .bind(file_id)
-    .bind(filename)
-    .bind(total_size as i64)
-    .bind(checksum)
-    .bind(file_path)
-    .execute(&mut **tx)
-    .await
-    {
-        error!("Failed to save upload state: {}", e);
-        return Err("Failed to save upload state".to_string());
-    }
-    info!("Successfully saved upload state for file '{}', ID: '{}'", filename, file_id);
-
-    Ok(())
-}
-
-/// 更新文件元信息（文件系统元信息）
-pub async fn update_file_meta_info(
-    db_pool: &SqlitePool,
-    file_id: &str,
-    file_mtime: i64,
-    file_ctime: i64,
-    file_ino: i64,
-) -> Result<(), String> {
-    match sqlx::query(
-        "UPDATE upload_file_meta SET file_mtime = ?, file_ctime = ?, file_ino = ?, last_updated = strftime('%s', 'now') WHERE file_id = ?"
-    )
-    .bind(file_mtime)
-    .bind(file_ctime)
-    .bind(file_ino)
-    .bind(file_id)
-    .execute(db_pool)
-    .await
-    {
-        Ok(_) => Ok(()),
-        Err(e) => Err(format!("Failed to update file meta info: {}", e)),
-    }
-}
-
-#[derive(Debug, Serialize, FromRow)]
-pub struct UploadedFile {
-    pub file_id: String,
-    pub filename: String,
-    pub total_size: i64,
-    pub checksum: String,
-    pub status: i32,
-    pub file_path: String,
-    pub thumbnail_path: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    #[sqlx(default)]
-    pub thumbnail_url: Option<String>,
-    pub last_updated: i64,
-}
-
-pub async fn fetch_uploaded_files(
-    db_pool: &SqlitePool,
-    page: u32,
-    page_size: u32,
-    status: Option<i32>,
-    sort_by: &str,
-    order: &str,
-) -> Result<Vec<UploadedFile>, String> {
-    let offset = (page - 1) * page_size;
-    let mut query = format!(
-        "SELECT file_id, filename, total_size, checksum, status, file_path, thumbnail_path, last_updated FROM upload_file_meta WHERE 1=1"
-    );
-
-    if let Some(status) = status {
-        query.push_str(&format!(" AND status = {}", status));
-    }
-
-    match sort_by {
-        "size" => query.push_str(" ORDER BY total_size"),
-        "date" => query.push_str(" ORDER BY last_updated"),
-        _ => query.push_str(" ORDER BY id"), // Default sorting by id
-    }
-
-    match order {
-        "desc" => query.push_str(" DESC"),
-        _ => query.push_str(" ASC"), // Default order is ascending
-    }
-
-    query.push_str(&format!(" LIMIT {} OFFSET {}", page_size, offset));
-
-    match sqlx::query_as::<_, UploadedFile>(&query)
-        .fetch_all(db_pool)
-        .await
-    {
-        Ok(files) => Ok(files),
-        Err(e) => {
-            error!("Failed to fetch uploaded files: {}", e);
-            Err("Failed to fetch uploaded files".to_string())
-        }
-    }
-}
-
-pub async fn fetch_total_uploaded_files(db_pool: &SqlitePool, status: Option<i32>) -> Result<i64, String> {
-    let mut query_str = "SELECT COUNT(*) as total FROM upload_file_meta WHERE 1=1".to_string();
-
-    if let Some(status) = status {
-        query_str.push_str(&format!(" AND status = {}", status));
-    }
-
-    match sqlx::query(&query_str)
-        .fetch_one(db_pool)
-        .await
-    {
-        Ok(row) => Ok(row.get::<i64, _>("total")),
-        Err(e) => {
-            error!("Failed to fetch total uploaded files: {}", e);
-            Err("Failed to fetch total uploaded files".to_string())
-        }
-    }
-}
-
-#[derive(Debug, Serialize, FromRow)]
-pub struct ChunkProgress {
-    pub start_offset: i64,
-    pub end_offset: i64,
-    pub uploaded_size: i64,
-    pub last_updated: i64,
-}
-
-pub async fn fetch_upload_progress(db_pool: &SqlitePool, file_id: &str) -> Result<Vec<ChunkProgress>, String> {
-    match sqlx::query_as::<_, ChunkProgress>(
-        "SELECT start_offset, end_offset, uploaded_size, last_updated FROM upload_progress WHERE file_id = ?"
-    )
-    .bind(file_id)
-    .fetch_all(db_pool)
-    .await
-    {
-        Ok(chunks) => Ok(chunks),
-        Err(e) => {
-            error!("Failed to fetch upload progress: {}", e);
-            Err("Failed to fetch upload progress".to_string())
-        }
-    }
-}
-
-/// 根据文件 MD5 checksum 查找已存在的文件记录
-/// 返回 Option<(file_id, filename, file_path)>
-pub async fn fetch_file_by_checksum(db_pool: &SqlitePool, checksum: &str) -> Result<Option<(String, String, String)>, String> {
-    match sqlx::query_as::<_, (String, String, String)>(
-        "SELECT file_id, filename, file_path FROM upload_file_meta WHERE checksum = ? AND status = 2"
-    )
-    .bind(checksum)
-    .fetch_optional(db_pool)
-    .await
-    {
-        Ok(result) => Ok(result),
-        Err(e) => {
-            error!("Failed to fetch file by checksum: {}", e);
-            Err("Failed to fetch file by checksum".to_string())
-        }
-    }
-}
-
-/// Fetch a complete UploadedFile by file_id
-pub async fn fetch_uploaded_file_by_id(db_pool: &SqlitePool, file_id: &str) -> Result<Option<UploadedFile>, String> {
-    match sqlx::query_as::<_, UploadedFile>(
-        "SELECT file_id, filename, total_size, checksum, status, file_path, thumbnail_path, last_updated FROM upload_file_meta WHERE file_id = ?"
-    )
-    .bind(file_id)
-    .fetch_optional(db_pool)
-    .await
-    {
-        Ok(result) => Ok(result),
-        Err(e) => {
-            error!("Failed to fetch uploaded file by id: {}", e);
-            Err("Failed to fetch uploaded file".to_string())
-        }
-    }
-}
-
-/// 更新文件的缩略图路径
-pub async fn update_file_thumbnail_path(
-    db_pool: &SqlitePool,
-    file_id: &str,
-    thumbnail_path: &str,
-) -> Result<(), String> {
-    match sqlx::query(
-        "UPDATE upload_file_meta SET thumbnail_path = ?, last_updated = strftime('%s', 'now') WHERE file_id = ?"
-    )
-    .bind(thumbnail_path)
-    .bind(file_id)
-    .execute(db_pool)
-    .await
-    {
-        Ok(_) => Ok(()),
-        Err(e) => Err(format!("Failed to update file thumbnail path: {}", e)),
-    }
-}
+use sqlx::{Sqlite, SqlitePool, Transaction, Row};
+use log::{error, info};
+use serde::Serialize;
+use sqlx::FromRow;
+use crate::derived_files::{replace_derived_file, DerivedFile, KIND_THUMBNAIL};
+use crate::api::{ChunkProgress, Priority, StoredChecksum};
+use crate::media_library::MediaTitle;
+use crate::playback::WatchState;
+
+pub async fn fetch_file_record(db_pool: &SqlitePool, file_id: &str) -> Result<(String, String, i64, i32, String), String> {
+    match sqlx::query("SELECT filename, checksum, total_size, status, file_path, thumbnail_path FROM upload_file_meta WHERE file_id = ?")
+        .bind(file_id)
+        .fetch_one(db_pool)
+        .await
+    {
+        Ok(row) => {
+            let filename: String = row.get("filename");
+            let checksum: String = row.get("checksum");
+            let total_size: i64 = row.get("total_size");
+            let status: Option<i32> = row.try_get("status").ok();
+            let file_path: String = row.get("file_path");
+            // We don't need thumbnail_path for this result type, just ignore it
+            let _: Option<String> = row.try_get("thumbnail_path").ok();
+            Ok((filename, checksum, total_size, status.unwrap_or(0), file_path))
+        }
+        Err(e) => {
+            error!("Failed to fetch file record: {}", e);
+            Err("Failed to fetch file record".to_string())
+        }
+    }
+}
+
+pub async fn update_upload_progress(db_pool: &SqlitePool, uploaded_size: u64, checksum: &str, file_id: &str, start_offset: u64) -> Result<(), String> {
+    if let Err(e) = sqlx::query("UPDATE upload_progress SET uploaded_size = ?, checksum = ? WHERE file_id = ? AND start_offset = ?")
+        .bind(uploaded_size as i64)
+        .bind(checksum)
+        .bind(file_id)
+        .bind(start_offset as i64)
+        .execute(db_pool)
+        .await
+    {
+        error!("Failed to update upload progress: {}", e);
+        return Err("Failed to update upload progress".to_string());
+    }
+    Ok(())
+}
+
+/// 只更新分片已接收的字节数，校验值在请求结束时再写入
+pub async fn update_upload_progress_size(db_pool: &SqlitePool, uploaded_size: u64, file_id: &str, start_offset: u64) -> Result<(), String> {
+    if let Err(e) = sqlx::query("UPDATE upload_progress SET uploaded_size = ? WHERE file_id = ? AND start_offset = ?")
+        .bind(uploaded_size as i64)
+        .bind(file_id)
+        .bind(start_offset as i64)
+        .execute(db_pool)
+        .await
+    {
+        error!("Failed to update upload progress: {}", e);
+        return Err("Failed to update upload progress".to_string());
+    }
+    Ok(())
+}
+
+/// 查询分片创建时使用的校验算法，旧数据默认 sha256
+pub async fn fetch_chunk_hash_algorithm(db_pool: &SqlitePool, file_id: &str, start_offset: u64) -> Result<Option<String>, String> {
+    match sqlx::query_scalar::<_, Option<String>>(
+        "SELECT hash_algorithm FROM upload_progress WHERE file_id = ? AND start_offset = ?"
+    )
+    .bind(file_id)
+    .bind(start_offset as i64)
+    .fetch_optional(db_pool)
+    .await
+    {
+        Ok(result) => Ok(result.flatten()),
+        Err(e) => {
+            error!("Failed to fetch chunk hash algorithm: {}", e);
+            Err("Failed to fetch chunk hash algorithm".to_string())
+        }
+    }
+}
+
+/// 包含文件中指定字节位置的分片，返回 (start_offset, end_offset)；空洞不接收数据
+pub async fn fetch_chunk_containing(db_pool: &SqlitePool, file_id: &str, position: u64) -> Result<Option<(u64, u64)>, String> {
+    match sqlx::query_as::<_, (i64, i64)>(
+        "SELECT start_offset, end_offset FROM upload_progress WHERE file_id = ? AND start_offset <= ? AND end_offset >= ? AND is_hole = 0"
+    )
+    .bind(file_id)
+    .bind(position as i64)
+    .bind(position as i64)
+    .fetch_optional(db_pool)
+    .await
+    {
+        Ok(result) => Ok(result.map(|(start, end)| (start as u64, end as u64))),
+        Err(e) => {
+            error!("Failed to fetch chunk: {}", e);
+            Err("Failed to fetch chunk".to_string())
+        }
+    }
+}
+
+/// 元数据提交时间（秒），旧记录为 0
+pub async fn fetch_file_created_at(db_pool: &SqlitePool, file_id: &str) -> Result<i64, String> {
+    match sqlx::query_scalar::<_, Option<i64>>(
+        "SELECT created_at FROM upload_file_meta WHERE file_id = ?"
+    )
+    .bind(file_id)
+    .fetch_optional(db_pool)
+    .await
+    {
+        Ok(result) => Ok(result.flatten().unwrap_or(0)),
+        Err(e) => {
+            error!("Failed to fetch file created_at: {}", e);
+            Err("Failed to fetch file created_at".to_string())
+        }
+    }
+}
+
+pub async fn get_total_uploaded(db_pool: &SqlitePool, file_id: &str) -> Result<u64, String> {
+    match sqlx::query("SELECT COALESCE(SUM(uploaded_size), 0) as total_uploaded FROM upload_progress WHERE file_id = ?")
+        .bind(file_id)
+        .fetch_one(db_pool)
+        .await
+    {
+        Ok(row) => {
+            let total_uploaded: i64 = row.get("total_uploaded");
+            Ok(total_uploaded.max(0) as u64)
+        }
+        Err(e) => {
+            error!("Failed to get total uploaded size: {}", e);
+            Err("Failed to get total uploaded size".to_string())
+        }
+    }
+}
+
+pub async fn update_file_status_and_path<'e, E: sqlx::Executor<'e, Database = Sqlite>>(
+    executor: E,
+    file_id: &str,
+    current_status: i32,
+    new_status: i32,
+    file_path: &str,
+) -> Result<(), String> {
+    // Get current timestamp
+    let current_time = chrono::Utc::now().timestamp();
+
+    if let Err(e) = sqlx::query("UPDATE upload_file_meta SET status = ?, file_path = ?, last_updated = ? WHERE file_id = ? AND status = ?")
+        .bind(new_status)
+        .bind(file_path)
+        .bind(current_time)
+        .bind(file_id)
+        .bind(current_status)
+        .execute(executor)
+        .await
+    {
+        error!("Failed to update file status and path: {}", e);
+        return Err("Failed to update file status and path".to_string());
+    }
+    Ok(())
+}
+
+pub async fn fetch_chunk_size(db_pool: &SqlitePool) -> Result<u64, String> {
+    match sqlx::query("SELECT config_value FROM system_config WHERE config_key = 'chunk_size'")
+        .fetch_one(db_pool)
+        .await
+    {
+        Ok(row) => {
+            let config_value: String = row.get("config_value");
+            config_value.parse().map_err(|_| "Invalid chunk size".to_string())
+        }
+        Err(e) => {
+            error!("Failed to fetch chunk size: {}", e);
+            Err("Failed to fetch chunk size".to_string())
+        }
+    }
+}
+
+pub async fn initialize_upload_progress(
+    tx: &mut Transaction<'_, Sqlite>,
+    file_id: &str,
+    safe_filename: &str,
+    total_size: u64,
+    start_offset: u64,
+    end_offset: u64,
+    hash_algorithm: &str,
+) -> Result<(), String> {
+    if let Err(e) = sqlx::query(
+        "INSERT INTO upload_progress (file_id, checksum, hash_algorithm, filename, total_size, uploaded_size, start_offset, end_offset) VALUES (?, ?, ?, ?, ?, ?, ?, ?)"
+    )
+    .bind(file_id)
+    .bind("") // Initial checksum is empty
+    .bind(hash_algorithm)
+    .bind(safe_filename)
+    .bind(total_size as i64)
+    .bind(0) // Initial uploaded size is 0
+    .bind(start_offset as i64)
+    .bind(end_offset as i64)
+    .execute(&mut **tx)
+    .await
+    {
+        error!("Failed to initialize upload progress: {}", e);
+        return Err("Failed to initialize upload progress".to_string());
+    }
+    Ok(())
+}
+
+/// 记录稀疏文件的一个空洞，作为已完成、无需上传的分片
+pub async fn initialize_hole_progress(tx: &mut Transaction<'_, Sqlite>, file_id: &str, safe_filename: &str, start_offset: u64, end_offset: u64) -> Result<(), String> {
+    sqlx::query(
+        "INSERT INTO upload_progress (file_id, checksum, filename, total_size, uploaded_size, start_offset, end_offset, is_hole) VALUES (?, '', ?, ?, ?, ?, ?, 1)"
+    )
+    .bind(file_id)
+    .bind(safe_filename)
+    .bind((end_offset - start_offset + 1) as i64)
+    .bind((end_offset - start_offset + 1) as i64)
+    .bind(start_offset as i64)
+    .bind(end_offset as i64)
+    .execute(&mut **tx)
+    .await
+    .map(|_| ())
+    .map_err(|e| {
+        error!("Failed to record hole of file ID {}: {}", file_id, e);
+        "Failed to initialize upload progress".to_string()
+    })
+}
+
+pub async fn save_upload_state_to_db(
+    tx: &mut Transaction<'_, Sqlite>,
+    file_id: &str,
+    filename: &str,
+    original_filename: &str,
+    total_size: u64,
+    checksum: &str,
+    file_path: &str,
+) -> Result<(), String> {
+    if let Err(e) = sqlx::query(
+        "INSERT INTO upload_file_meta (file_id, filename, original_filename, total_size, checksum, file_path, file_mtime, file_ctime, file_ino, created_at) VALUES (?, ?, ?, ?, ?, ?, 0, 0, 0, strftime('%s', 'now'))"
+    )
+    .bind(file_id)
+    .bind(filename)
+    .bind(original_filename)
+    .bind(total_size as i64)
+    .bind(checksum)
+    .bind(file_path)
+    .execute(&mut **tx)
+    .await
+    {
+        error!("Failed to save upload state: {}", e);
+        return Err("Failed to save upload state".to_string());
+    }
+    info!("Successfully saved upload state for file '{}', ID: '{}'", filename, file_id);
+
+    Ok(())
+}
+
+/// 记录上传的目标目录与提交者
+pub async fn set_file_placement(tx: &mut Transaction<'_, Sqlite>, file_id: &str, folder_id: Option<i64>, owner: &str, tenant_id: Option<i64>) -> Result<(), String> {
+    sqlx::query("UPDATE upload_file_meta SET folder_id = ?, owner = ?, tenant_id = ? WHERE file_id = ?")
+        .bind(folder_id)
+        .bind(owner)
+        .bind(tenant_id)
+        .bind(file_id)
+        .execute(&mut **tx)
+        .await
+        .map(|_| ())
+        .map_err(|e| {
+            error!("Failed to set folder, owner and tenant for file ID {}: {}", file_id, e);
+            "Failed to set file placement".to_string()
+        })
+}
+
+pub async fn set_file_priority<'e, E: sqlx::Executor<'e, Database = Sqlite>>(executor: E, file_id: &str, priority: Priority) -> Result<(), String> {
+    sqlx::query("UPDATE upload_file_meta SET priority = ? WHERE file_id = ?")
+        .bind(priority.as_str())
+        .bind(file_id)
+        .execute(executor)
+        .await
+        .map(|_| ())
+        .map_err(|e| {
+            error!("Failed to set priority for file ID {}: {}", file_id, e);
+            "Failed to set file priority".to_string()
+        })
+}
+
+/// Priority of a file's merge and transcodes; `normal` for unknown values
+pub async fn fetch_file_priority(db_pool: &SqlitePool, file_id: &str) -> Result<Priority, String> {
+    sqlx::query_scalar::<_, String>("SELECT priority FROM upload_file_meta WHERE file_id = ?")
+        .bind(file_id)
+        .fetch_optional(db_pool)
+        .await
+        .map(|priority| priority.and_then(|p| Priority::parse(&p)).unwrap_or_default())
+        .map_err(|e| {
+            error!("Failed to fetch priority of file ID {}: {}", file_id, e);
+            "Failed to fetch file priority".to_string()
+        })
+}
+
+/// Link a file to the file it was made from
+pub async fn set_file_source(tx: &mut Transaction<'_, Sqlite>, file_id: &str, source_file_id: &str) -> Result<(), String> {
+    sqlx::query("UPDATE upload_file_meta SET source_file_id = ? WHERE file_id = ?")
+        .bind(source_file_id)
+        .bind(file_id)
+        .execute(&mut **tx)
+        .await
+        .map(|_| ())
+        .map_err(|e| {
+            error!("Failed to set source of file ID {}: {}", file_id, e);
+            "Failed to set file source".to_string()
+        })
+}
+
+/// 更新文件元信息（文件系统元信息）
+pub async fn update_file_meta_info(
+    db_pool: &SqlitePool,
+    file_id: &str,
+    file_mtime: i64,
+    file_ctime: i64,
+    file_ino: i64,
+) -> Result<(), String> {
+    match sqlx::query(
+        "UPDATE upload_file_meta SET file_mtime = ?, file_ctime = ?, file_ino = ?, last_updated = strftime('%s', 'now') WHERE file_id = ?"
+    )
+    .bind(file_mtime)
+    .bind(file_ctime)
+    .bind(file_ino)
+    .bind(file_id)
+    .execute(db_pool)
+    .await
+    {
+        Ok(_) => Ok(()),
+        Err(e) => Err(format!("Failed to update file meta info: {}", e)),
+    }
+}
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct UploadedFile {
+    pub file_id: String,
+    pub filename: String,
+    pub original_filename: Option<String>,
+    pub total_size: i64,
+    pub checksum: String,
+    pub status: i32,
+    pub file_path: String,
+    pub thumbnail_path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[sqlx(default)]
+    pub thumbnail_url: Option<String>,
+    #[serde(with = "crate::api::timestamp")]
+    pub last_updated: i64,
+    /// When the upload was started
+    #[serde(with = "crate::api::timestamp")]
+    #[sqlx(default)]
+    pub created_at: i64,
+    /// Bumped by every change made through an ETag-checked endpoint
+    #[serde(skip)]
+    #[sqlx(default)]
+    pub revision: i64,
+    /// File this one was made from, e.g. the video of extracted audio
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[sqlx(default)]
+    pub source_file_id: Option<String>,
+    /// What was made from this file, filled in by `GET /api/v1/files/:file_id`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[sqlx(skip)]
+    pub derived: Option<Vec<DerivedFile>>,
+    /// `If-Match` value for renaming, moving or changing the status of the
+    /// file, filled in by listing endpoints
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[sqlx(skip)]
+    pub etag: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[sqlx(skip)]
+    pub media: Option<MediaTitle>,
+    /// Watch state for the requesting client, filled in by listing endpoints
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[sqlx(skip)]
+    pub watch_state: Option<WatchState>,
+}
+
+pub async fn fetch_upload_progress(db_pool: &SqlitePool, file_id: &str) -> Result<Vec<ChunkProgress>, String> {
+    match sqlx::query_as::<_, ChunkProgress>(
+        "SELECT start_offset, end_offset, uploaded_size, checksum, hash_algorithm, last_updated, is_hole FROM upload_progress WHERE file_id = ?"
+    )
+    .bind(file_id)
+    .fetch_all(db_pool)
+    .await
+    {
+        Ok(chunks) => Ok(chunks),
+        Err(e) => {
+            error!("Failed to fetch upload progress: {}", e);
+            Err("Failed to fetch upload progress".to_string())
+        }
+    }
+}
+
+/// 根据文件 MD5 checksum 查找已存在的文件记录
+/// 返回 Option<(file_id, filename, file_path)>
+/// 多租户模式下只在同一租户内去重
+pub async fn fetch_file_by_checksum(db_pool: &SqlitePool, checksum: &str, tenant_id: Option<i64>) -> Result<Option<(String, String, String)>, String> {
+    match sqlx::query_as::<_, (String, String, String)>(
+        "SELECT file_id, filename, file_path FROM upload_file_meta WHERE checksum = ? AND status = 2 AND tenant_id IS ?"
+    )
+    .bind(checksum)
+    .bind(tenant_id)
+    .fetch_optional(db_pool)
+    .await
+    {
+        Ok(result) => Ok(result),
+        Err(e) => {
+            error!("Failed to fetch file by checksum: {}", e);
+            Err("Failed to fetch file by checksum".to_string())
+        }
+    }
+}
+
+/// 同一位置是否有同名的未完成上传（上传中或合并中），合并时会与新上传冲突
+pub async fn has_unfinished_upload_named(db_pool: &SqlitePool, filename: &str, folder_id: Option<i64>, tenant_id: Option<i64>) -> Result<bool, String> {
+    sqlx::query_scalar::<_, i64>(
+        "SELECT COUNT(*) FROM upload_file_meta WHERE filename = ? AND folder_id IS ? AND tenant_id IS ? AND status IN (0, 1)"
+    )
+    .bind(filename)
+    .bind(folder_id)
+    .bind(tenant_id)
+    .fetch_one(db_pool)
+    .await
+    .map(|count| count > 0)
+    .map_err(|e| {
+        error!("Failed to check unfinished uploads named {}: {}", filename, e);
+        "Failed to check for uploads with the same name".to_string()
+    })
+}
+
+/// 在给定校验和中已有完成文件的部分，每个校验和取一个文件
+pub async fn fetch_stored_checksums(db_pool: &SqlitePool, checksums: &[String], tenant_id: Option<i64>) -> Result<Vec<StoredChecksum>, String> {
+    if checksums.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let placeholders = vec!["?"; checksums.len()].join(", ");
+    let query = format!(
+        "SELECT checksum, MIN(file_id) AS file_id FROM upload_file_meta WHERE status = 2 AND tenant_id IS ? AND checksum IN ({}) GROUP BY checksum",
+        placeholders
+    );
+    let mut q = sqlx::query_as::<_, StoredChecksum>(&query).bind(tenant_id);
+    for checksum in checksums {
+        q = q.bind(checksum);
+    }
+
+    q.fetch_all(db_pool).await.map_err(|e| {
+        error!("Failed to fetch stored checksums: {}", e);
+        "Failed to fetch stored checksums".to_string()
+    })
+}
+
+/// 以客户端上传键提交过的文件
+#[derive(Debug, FromRow)]
+pub struct KeyedUpload {
+    pub file_id: String,
+    pub filename: String,
+    pub original_filename: Option<String>,
+    pub total_size: i64,
+    pub checksum: String,
+    pub folder_id: Option<i64>,
+    pub status: i32,
+    pub file_path: String,
+}
+
+/// 按上传键查找同一租户此前提交的文件
+pub async fn fetch_file_by_upload_key(db_pool: &SqlitePool, upload_key: &str, tenant_id: Option<i64>) -> Result<Option<KeyedUpload>, String> {
+    sqlx::query_as::<_, KeyedUpload>(
+        "SELECT file_id, filename, original_filename, total_size, checksum, folder_id, status, file_path
+         FROM upload_file_meta WHERE upload_key = ? AND tenant_id IS ?"
+    )
+    .bind(upload_key)
+    .bind(tenant_id)
+    .fetch_optional(db_pool)
+    .await
+    .map_err(|e| {
+        error!("Failed to fetch file by upload key: {}", e);
+        "Failed to fetch file by upload key".to_string()
+    })
+}
+
+/// 记录文件的上传键；键已被同一租户的其他文件占用时返回 false
+pub async fn set_upload_key(tx: &mut Transaction<'_, Sqlite>, file_id: &str, upload_key: &str) -> Result<bool, String> {
+    match sqlx::query("UPDATE upload_file_meta SET upload_key = ? WHERE file_id = ?")
+        .bind(upload_key)
+        .bind(file_id)
+        .execute(&mut **tx)
+        .await
+    {
+        Ok(_) => Ok(true),
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => Ok(false),
+        Err(e) => {
+            error!("Failed to set upload key for file ID {}: {}", file_id, e);
+            Err("Failed to set upload key".to_string())
+        }
+    }
+}
+
+/// Fetch a complete UploadedFile by file_id
+pub async fn fetch_uploaded_file_by_id(db_pool: &SqlitePool, file_id: &str) -> Result<Option<UploadedFile>, String> {
+    match sqlx::query_as::<_, UploadedFile>(
+        "SELECT file_id, filename, original_filename, total_size, checksum, status, file_path, thumbnail_path, last_updated, created_at, revision, source_file_id FROM upload_file_meta WHERE file_id = ?"
+    )
+    .bind(file_id)
+    .fetch_optional(db_pool)
+    .await
+    {
+        Ok(result) => Ok(result),
+        Err(e) => {
+            error!("Failed to fetch uploaded file by id: {}", e);
+            Err("Failed to fetch uploaded file".to_string())
+        }
+    }
+}
+
+/// 更新文件的缩略图路径
+pub async fn update_file_thumbnail_path(
+    db_pool: &SqlitePool,
+    file_id: &str,
+    thumbnail_path: &str,
+) -> Result<(), String> {
+    match sqlx::query(
+        "UPDATE upload_file_meta SET thumbnail_path = ?, last_updated = strftime('%s', 'now') WHERE file_id = ?"
+    )
+    .bind(thumbnail_path)
+    .bind(file_id)
+    .execute(db_pool)
+    .await
+    {
+        Ok(_) => replace_derived_file(db_pool, file_id, KIND_THUMBNAIL, None, thumbnail_path).await,
+        Err(e) => Err(format!("Failed to update file thumbnail path: {}", e)),
+    }
+}
+
+/// 增量上传后更新文件内容的校验和与大小
+pub async fn update_file_content(
+    db_pool: &SqlitePool,
+    file_id: &str,
+    checksum: &str,
+    total_size: u64,
+) -> Result<(), String> {
+    match sqlx::query(
+        "UPDATE upload_file_meta SET checksum = ?, total_size = ?, last_updated = strftime('%s', 'now') WHERE file_id = ?"
+    )
+    .bind(checksum)
+    .bind(total_size as i64)
+    .bind(file_id)
+    .execute(db_pool)
+    .await
+    {
+        Ok(_) => Ok(()),
+        Err(e) => {
+            error!("Failed to update file content: {}", e);
+            Err("Failed to update file content".to_string())
+        }
+    }
+}
+
+/// 删除文件记录及所有关联数据（分片进度、字幕、片名、播放记录、标签、转码、文件锁、派生文件记录）
+pub async fn delete_file_records(db_pool: &SqlitePool, file_id: &str) -> Result<(), String> {
+    let result = async {
+        let mut tx = db_pool.begin().await?;
+        for table in ["upload_progress", "subtitles", "media_titles", "playback_history", "watch_state", "file_tags", "transcodes", "file_locks", "upload_file_meta"] {
+            sqlx::query(&format!("DELETE FROM {} WHERE file_id = ?", table))
+                .bind(file_id)
+                .execute(&mut *tx)
+                .await?;
+        }
+        // 由该文件生成的文件保留为独立文件，只解除关联
+        sqlx::query("DELETE FROM derived_files WHERE source_id = ? OR derived_file_id = ?")
+            .bind(file_id)
+            .bind(file_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("UPDATE upload_file_meta SET source_file_id = NULL WHERE source_file_id = ?")
+            .bind(file_id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await
+    }
+    .await;
+
+    result.map_err(|e| {
+        error!("Failed to delete file records: {}", e);
+        "Failed to delete file records".to_string()
+    })
+}
+
+/// 是否还有记录指向该文件（同名文件重复上传时旧记录与新记录共用路径）
+pub async fn is_file_path_referenced(db_pool: &SqlitePool, file_path: &str) -> Result<bool, String> {
+    match sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM upload_file_meta WHERE file_path = ?")
+        .bind(file_path)
+        .fetch_one(db_pool)
+        .await
+    {
+        Ok(count) => Ok(count > 0),
+        Err(e) => {
+            error!("Failed to check file path references: {}", e);
+            Err("Failed to check file path references".to_string())
+        }
+    }
+}
+
+/// 是否有其他记录与该文件共用同一路径（同名重复上传或按引用去重）
+pub async fn is_file_path_shared(db_pool: &SqlitePool, file_path: &str, file_id: &str) -> Result<bool, String> {
+    sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM upload_file_meta WHERE file_path = ? AND file_id != ?")
+        .bind(file_path)
+        .bind(file_id)
+        .fetch_one(db_pool)
+        .await
+        .map(|count| count > 0)
+        .map_err(|e| {
+            error!("Failed to check file path references: {}", e);
+            "Failed to check file path references".to_string()
+        })
+}
+
+/// 文件移到冷存储后更新路径，清空元信息以便完整性检查重新校验
+pub async fn update_archived_file_path(db_pool: &SqlitePool, file_id: &str, file_path: &str) -> Result<(), String> {
+    match sqlx::query(
+        "UPDATE upload_file_meta SET file_path = ?, archived_at = strftime('%s', 'now'), file_mtime = 0, file_ctime = 0, file_ino = 0, last_updated = strftime('%s', 'now') WHERE file_id = ?"
+    )
+    .bind(file_path)
+    .bind(file_id)
+    .execute(db_pool)
+    .await
+    {
+        Ok(_) => Ok(()),
+        Err(e) => {
+            error!("Failed to update archived file path: {}", e);
+            Err("Failed to update archived file path".to_string())
+        }
+    }
+}
+
+/// 去重后指向保留的副本，清空元信息以便完整性检查重新读取
+pub async fn update_deduplicated_file_path(db_pool: &SqlitePool, file_id: &str, file_path: &str) -> Result<(), String> {
+    match sqlx::query(
+        "UPDATE upload_file_meta SET file_path = ?, file_mtime = 0, file_ctime = 0, file_ino = 0, last_updated = strftime('%s', 'now') WHERE file_id = ?"
+    )
+    .bind(file_path)
+    .bind(file_id)
+    .execute(db_pool)
+    .await
+    {
+        Ok(_) => Ok(()),
+        Err(e) => {
+            error!("Failed to update deduplicated file path: {}", e);
+            Err("Failed to update deduplicated file path".to_string())
+        }
+    }
+}
@@ -0,0 +1,124 @@
+//! Hints in the upload plan that let clients tune themselves: how many chunks
+//! to send at once and how long to back off, derived from the server's
+//! current load, and a pre-signed URL per chunk. A signed URL names the file
+//! and chunk itself and is accepted without an API key, so a client can hand
+//! chunks to helpers that know nothing about the upload.
+
+use axum::extract::Query;
+use axum::http::Uri;
+use nascraft::api::{ChunkInfo, UploadHints, API_VERSION};
+use ring::hmac;
+use ring::rand::SystemRandom;
+use serde::Deserialize;
+use std::sync::LazyLock;
+use std::time::Duration;
+use crate::context::AppContext;
+
+/// Chunk requests the server aims to receive at once across all uploads
+const TOTAL_CHUNK_STREAMS: usize = 16;
+const MAX_PARALLEL_CHUNKS: usize = 8;
+
+const BASE_BACKOFF_MS: u64 = 500;
+const MAX_BACKOFF_MS: u64 = 60_000;
+
+/// Uploads that received a chunk this recently count as active
+const ACTIVE_UPLOAD_WINDOW: Duration = Duration::from_secs(120);
+
+/// How long pre-signed chunk URLs stay valid; submitting the metadata again
+/// with the same upload key returns fresh ones
+const URL_TTL_SECS: i64 = 24 * 60 * 60;
+
+/// Generated at startup, so URLs signed before a restart are refused
+static SIGNING_KEY: LazyLock<hmac::Key> = LazyLock::new(|| {
+    hmac::Key::generate(hmac::HMAC_SHA256, &SystemRandom::new()).expect("Failed to generate the upload URL signing key")
+});
+
+fn signed_message(file_id: &str, start_offset: u64, expires: i64) -> String {
+    format!("{}:{}:{}", file_id, start_offset, expires)
+}
+
+fn sign(file_id: &str, start_offset: u64, expires: i64) -> String {
+    let tag = hmac::sign(&SIGNING_KEY, signed_message(file_id, start_offset, expires).as_bytes());
+    tag.as_ref().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn decode_hex(value: &str) -> Option<Vec<u8>> {
+    if !value.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..value.len())
+        .step_by(2)
+        .map(|i| value.get(i..i + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok()))
+        .collect()
+}
+
+#[derive(Deserialize)]
+struct SignedChunkQuery {
+    file_id: Option<String>,
+    start: Option<u64>,
+    expires: Option<i64>,
+    signature: Option<String>,
+}
+
+/// The chunk a pre-signed upload URL is for
+#[derive(Debug, Clone)]
+pub struct SignedChunk {
+    pub file_id: String,
+    pub start_offset: u64,
+}
+
+/// The chunk `uri` was signed for; None when it carries no signature, an
+/// error when the signature is invalid or expired
+pub fn signed_chunk(uri: &Uri) -> Option<Result<SignedChunk, String>> {
+    let Ok(Query(query)) = Query::<SignedChunkQuery>::try_from_uri(uri) else {
+        return Some(Err("Malformed upload URL".to_string()));
+    };
+    let signature = query.signature?;
+    let (Some(file_id), Some(start_offset), Some(expires)) = (query.file_id, query.start, query.expires) else {
+        return Some(Err("A signed upload URL needs file_id, start and expires".to_string()));
+    };
+    let valid = decode_hex(&signature).is_some_and(|tag| {
+        hmac::verify(&SIGNING_KEY, signed_message(&file_id, start_offset, expires).as_bytes(), &tag).is_ok()
+    });
+    if !valid {
+        return Some(Err("Invalid upload URL signature".to_string()));
+    }
+    if expires < chrono::Utc::now().timestamp() {
+        return Some(Err("The upload URL has expired; submit the metadata again for new URLs".to_string()));
+    }
+    Some(Ok(SignedChunk { file_id, start_offset }))
+}
+
+/// Hints for the upload `file_id`; fills in the `upload_url` of each chunk
+pub fn upload_hints(ctx: &AppContext, file_id: &str, chunks: &mut [ChunkInfo]) -> UploadHints {
+    let active_uploads = ctx.app_state.activity.active(ACTIVE_UPLOAD_WINDOW) + 1;
+    let merges_waiting = ctx.io.stats().merge.queued;
+
+    // 并发上传越多，每个上传分到的并发分片越少；合并排队说明磁盘已忙
+    let mut parallel_chunks = (TOTAL_CHUNK_STREAMS / active_uploads).clamp(1, MAX_PARALLEL_CHUNKS);
+    if merges_waiting > 0 {
+        parallel_chunks = (parallel_chunks / 2).max(1);
+    }
+    let load = 1 + merges_waiting as u64 + (active_uploads / TOTAL_CHUNK_STREAMS) as u64;
+    let initial_backoff_ms = (BASE_BACKOFF_MS * load).min(MAX_BACKOFF_MS / 4);
+
+    let expires = chrono::Utc::now().timestamp() + URL_TTL_SECS;
+    for chunk in chunks.iter_mut() {
+        chunk.upload_url = Some(format!(
+            "/api/v{}/upload?file_id={}&start={}&expires={}&signature={}",
+            API_VERSION,
+            file_id,
+            chunk.start_offset,
+            expires,
+            sign(file_id, chunk.start_offset, expires)
+        ));
+    }
+
+    UploadHints {
+        parallel_chunks: parallel_chunks.min(chunks.len().max(1)),
+        initial_backoff_ms,
+        max_backoff_ms: MAX_BACKOFF_MS,
+        active_uploads,
+        urls_expire_at: expires,
+    }
+}
@@ -0,0 +1,226 @@
+use log::{error, info, warn};
+use sqlx::SqlitePool;
+use std::time::{Duration, Instant};
+use crate::filename::{sanitize_filename, SanitizePolicy};
+use crate::paths::{chunk_file_path, legacy_chunk_file_path, long_path, previous_chunk_file_path, CHUNKS_DIR, PREVIOUS_CHUNKS_DIR};
+use crate::repository::ProgressRepository;
+
+/// How often the progress of a chunk that is still being received is written
+const PROGRESS_FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Batches the `upload_progress` writes of one chunk request. Received bytes are
+/// kept in memory and written at most once per interval; `finish` writes the
+/// final size together with the chunk checksum.
+pub struct UploadProgressTracker<'a, R: ProgressRepository> {
+    repo: &'a R,
+    file_id: &'a str,
+    start_offset: u64,
+    uploaded: u64,
+    flushed: u64,
+    last_flush: Instant,
+}
+
+impl<'a, R: ProgressRepository> UploadProgressTracker<'a, R> {
+    pub fn new(repo: &'a R, file_id: &'a str, start_offset: u64) -> Self {
+        Self {
+            repo,
+            file_id,
+            start_offset,
+            uploaded: 0,
+            flushed: 0,
+            last_flush: Instant::now(),
+        }
+    }
+
+    /// Note that `uploaded` bytes of the chunk have been written
+    pub async fn record(&mut self, uploaded: u64) -> Result<(), String> {
+        self.uploaded = uploaded;
+        if self.last_flush.elapsed() >= PROGRESS_FLUSH_INTERVAL {
+            self.flush().await?;
+        }
+        Ok(())
+    }
+
+    /// Write the recorded size without a checksum, e.g. when the client disconnects mid-chunk
+    pub async fn flush(&mut self) -> Result<(), String> {
+        if self.uploaded != self.flushed {
+            self.repo.update_upload_progress_size(self.file_id, self.start_offset, self.uploaded).await?;
+            self.flushed = self.uploaded;
+        }
+        self.last_flush = Instant::now();
+        Ok(())
+    }
+
+    /// Restore the chunk's size from before this request after its data was discarded
+    pub async fn rollback(self, uploaded: u64) -> Result<(), String> {
+        self.repo.update_upload_progress_size(self.file_id, self.start_offset, uploaded).await
+    }
+
+    pub async fn finish(self, uploaded: u64, checksum: &str) -> Result<(), String> {
+        self.repo.update_upload_progress(self.file_id, self.start_offset, uploaded, checksum).await
+    }
+}
+
+/// Move a chunk from the old `<filename>_chunk_<offset>` layout to its file_id
+/// directory, unless the new layout already has it. Returns whether it moved.
+async fn adopt_legacy_chunk(legacy: &std::path::Path, path: &std::path::Path) -> std::io::Result<bool> {
+    if !tokio::fs::try_exists(long_path(legacy)).await? || tokio::fs::try_exists(long_path(path)).await? {
+        return Ok(false);
+    }
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(long_path(parent)).await?;
+    }
+    tokio::fs::rename(long_path(legacy), long_path(path)).await?;
+    Ok(true)
+}
+
+/// 进程异常退出时最后一批进度可能未写入，启动时按分片文件大小重新计算未完成上传的进度。
+/// 分片中未经校验的数据由合并后的整文件 MD5 兜底。旧版按文件名或在 uploads/chunks 下存放的分片在此移入 file_id 目录。
+pub async fn recover_upload_progress(db_pool: &SqlitePool, policy: SanitizePolicy) -> Result<(), String> {
+    let chunks: Vec<(String, String, i64, i64, i64)> = sqlx::query_as(
+        "SELECT p.file_id, m.filename, p.start_offset, p.end_offset, p.uploaded_size
+         FROM upload_progress p JOIN upload_file_meta m ON m.file_id = p.file_id
+         WHERE COALESCE(m.status, 0) = 0 AND p.is_hole = 0"
+    )
+    .fetch_all(db_pool)
+    .await
+    .map_err(|e| {
+        error!("Failed to fetch upload progress for recovery: {}", e);
+        "Failed to fetch upload progress".to_string()
+    })?;
+
+    let (mut recovered, mut moved) = (0, 0);
+    for (file_id, filename, start_offset, end_offset, uploaded_size) in chunks {
+        let path = chunk_file_path(&file_id, start_offset as u64);
+        let previous = previous_chunk_file_path(&file_id, start_offset as u64);
+        let legacy = legacy_chunk_file_path(&sanitize_filename(&filename, policy), start_offset as u64);
+        for old in [previous, legacy] {
+            match adopt_legacy_chunk(&old, &path).await {
+                Ok(true) => moved += 1,
+                Ok(false) => {}
+                Err(e) => warn!("Failed to move chunk file {} to {}: {}", old.display(), path.display(), e),
+            }
+        }
+        let on_disk = match tokio::fs::metadata(long_path(&path)).await {
+            Ok(metadata) => metadata.len(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => 0,
+            Err(e) => {
+                warn!("Failed to read chunk file {}: {}", path.display(), e);
+                continue;
+            }
+        };
+        let derived = on_disk.min((end_offset - start_offset + 1) as u64);
+        if derived != uploaded_size as u64 {
+            info!(
+                "Recovered progress of file ID {} chunk {}: {} -> {} bytes",
+                file_id, start_offset, uploaded_size, derived
+            );
+            db_pool.update_upload_progress_size(&file_id, start_offset as u64, derived).await?;
+            recovered += 1;
+        }
+    }
+    if moved > 0 {
+        info!("Moved {} chunk files of unfinished uploads to {}", moved, CHUNKS_DIR);
+        // 仅删除已清空的旧目录，其中仍有文件时保留
+        if let Ok(mut entries) = tokio::fs::read_dir(PREVIOUS_CHUNKS_DIR).await {
+            while let Ok(Some(entry)) = entries.next_entry().await {
+                let _ = tokio::fs::remove_dir(entry.path()).await;
+            }
+            let _ = tokio::fs::remove_dir(PREVIOUS_CHUNKS_DIR).await;
+        }
+    }
+    if recovered > 0 {
+        info!("Recovered upload progress of {} chunks from chunk files", recovered);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockall::predicate::eq;
+    use crate::repository::MockProgressRepository;
+
+    #[tokio::test]
+    async fn record_waits_for_the_flush_interval() {
+        let mut repo = MockProgressRepository::new();
+        repo.expect_update_upload_progress_size().never();
+
+        let mut tracker = UploadProgressTracker::new(&repo, "id", 0);
+        tracker.record(100).await.unwrap();
+        tracker.record(200).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn record_flushes_once_the_interval_has_passed() {
+        let mut repo = MockProgressRepository::new();
+        repo.expect_update_upload_progress_size()
+            .with(eq("id"), eq(1024), eq(300))
+            .times(1)
+            .returning(|_, _, _| Ok(()));
+
+        let mut tracker = UploadProgressTracker::new(&repo, "id", 1024);
+        tracker.last_flush -= PROGRESS_FLUSH_INTERVAL;
+        tracker.record(300).await.unwrap();
+        // 刚写入过，间隔内不再写
+        tracker.record(400).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn flush_writes_only_changed_sizes() {
+        let mut repo = MockProgressRepository::new();
+        repo.expect_update_upload_progress_size()
+            .with(eq("id"), eq(0), eq(500))
+            .times(1)
+            .returning(|_, _, _| Ok(()));
+
+        let mut tracker = UploadProgressTracker::new(&repo, "id", 0);
+        tracker.flush().await.unwrap();
+        tracker.record(500).await.unwrap();
+        tracker.flush().await.unwrap();
+        tracker.flush().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn failed_flushes_are_retried() {
+        let mut repo = MockProgressRepository::new();
+        let mut attempts = 0;
+        repo.expect_update_upload_progress_size().times(2).returning(move |_, _, _| {
+            attempts += 1;
+            if attempts == 1 { Err("database is locked".to_string()) } else { Ok(()) }
+        });
+
+        let mut tracker = UploadProgressTracker::new(&repo, "id", 0);
+        tracker.record(500).await.unwrap();
+        assert!(tracker.flush().await.is_err());
+        tracker.flush().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn rollback_restores_the_previous_size() {
+        let mut repo = MockProgressRepository::new();
+        repo.expect_update_upload_progress_size()
+            .with(eq("id"), eq(1024), eq(100))
+            .times(1)
+            .returning(|_, _, _| Ok(()));
+        repo.expect_update_upload_progress().never();
+
+        let mut tracker = UploadProgressTracker::new(&repo, "id", 1024);
+        tracker.record(700).await.unwrap();
+        tracker.rollback(100).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn finish_writes_the_size_with_the_checksum() {
+        let mut repo = MockProgressRepository::new();
+        repo.expect_update_upload_progress()
+            .with(eq("id"), eq(0), eq(1024), eq("abc"))
+            .times(1)
+            .returning(|_, _, _, _| Ok(()));
+        repo.expect_update_upload_progress_size().never();
+
+        let mut tracker = UploadProgressTracker::new(&repo, "id", 0);
+        tracker.record(1024).await.unwrap();
+        tracker.finish(1024, "abc").await.unwrap();
+    }
+}
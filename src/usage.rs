@@ -0,0 +1,271 @@
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use log::{error, info};
+use serde::Serialize;
+use sqlx::{FromRow, SqlitePool};
+use std::collections::{BTreeMap, HashMap};
+use crate::context::AppContext;
+use crate::disk_space::{disk_space, DiskSpace};
+use crate::folders::fetch_folders;
+use crate::helper::{error_response, ApiResponse};
+use crate::paths::UPLOADS_DIR;
+use crate::storage_buckets::{measure_buckets, StorageBucket};
+use crate::tenants::ensure_not_tenant;
+
+const DIMENSION_FOLDER: &str = "folder";
+const DIMENSION_MIME: &str = "mime";
+const DIMENSION_OWNER: &str = "owner";
+
+/// Owner of files uploaded before owners were recorded
+const UNKNOWN_OWNER: &str = "unknown";
+
+#[derive(Debug, FromRow)]
+struct UsageFile {
+    folder_id: Option<i64>,
+    name: String,
+    owner: Option<String>,
+    total_size: i64,
+}
+
+impl UsageFile {
+    /// The `storage_usage` rows this file counts towards
+    fn keys(&self) -> [(&'static str, String); 3] {
+        [
+            (DIMENSION_FOLDER, self.folder_id.map(|id| id.to_string()).unwrap_or_default()),
+            (DIMENSION_MIME, mime_guess::from_path(&self.name).first_or_octet_stream().essence_str().to_string()),
+            (DIMENSION_OWNER, self.owner.clone().unwrap_or_else(|| UNKNOWN_OWNER.to_string())),
+        ]
+    }
+}
+
+const USAGE_FILE_COLUMNS: &str = "folder_id, COALESCE(original_filename, filename) AS name, owner, total_size";
+
+/// 累加（sign 为 1）或扣除（sign 为 -1）一个已完成文件的占用空间
+pub async fn record_file_usage(db_pool: &SqlitePool, file_id: &str, sign: i64) {
+    let result = async {
+        let Some(file) = sqlx::query_as::<_, UsageFile>(&format!(
+            "SELECT {} FROM upload_file_meta WHERE file_id = ? AND status = 2",
+            USAGE_FILE_COLUMNS
+        ))
+        .bind(file_id)
+        .fetch_optional(db_pool)
+        .await?
+        else {
+            return Ok(());
+        };
+
+        let mut tx = db_pool.begin().await?;
+        for (dimension, key) in file.keys() {
+            sqlx::query(
+                "INSERT INTO storage_usage (dimension, key, files, bytes) VALUES (?, ?, ?, ?)
+                 ON CONFLICT(dimension, key) DO UPDATE SET files = files + excluded.files, bytes = bytes + excluded.bytes"
+            )
+            .bind(dimension)
+            .bind(&key)
+            .bind(sign)
+            .bind(sign * file.total_size)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await
+    }
+    .await;
+
+    if let Err(e) = result {
+        error!("Failed to record storage usage for file ID {}: {}", file_id, e);
+    }
+}
+
+/// 汇总表为空时（首次升级）从已完成文件全量计算一次，之后只做增量更新
+pub async fn rebuild_usage_if_empty(db_pool: &SqlitePool) -> Result<(), String> {
+    let result = async {
+        let rows: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM storage_usage").fetch_one(db_pool).await?;
+        if rows > 0 {
+            return Ok(0);
+        }
+
+        let files = sqlx::query_as::<_, UsageFile>(&format!(
+            "SELECT {} FROM upload_file_meta WHERE status = 2",
+            USAGE_FILE_COLUMNS
+        ))
+        .fetch_all(db_pool)
+        .await?;
+
+        let mut totals: HashMap<(&'static str, String), (i64, i64)> = HashMap::new();
+        for file in &files {
+            for key in file.keys() {
+                let entry = totals.entry(key).or_default();
+                entry.0 += 1;
+                entry.1 += file.total_size;
+            }
+        }
+
+        let mut tx = db_pool.begin().await?;
+        for ((dimension, key), (count, bytes)) in totals {
+            sqlx::query("INSERT INTO storage_usage (dimension, key, files, bytes) VALUES (?, ?, ?, ?)")
+                .bind(dimension)
+                .bind(key)
+                .bind(count)
+                .bind(bytes)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+        Ok::<usize, sqlx::Error>(files.len())
+    }
+    .await;
+
+    match result {
+        Ok(0) => Ok(()),
+        Ok(count) => {
+            info!("Computed storage usage for {} existing files", count);
+            Ok(())
+        }
+        Err(e) => {
+            error!("Failed to rebuild storage usage: {}", e);
+            Err("Failed to rebuild storage usage".to_string())
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UsageEntry {
+    pub key: String,
+    pub files: i64,
+    pub bytes: i64,
+}
+
+/// Usage of a directory under `uploads/`, including everything below it
+#[derive(Debug, Default, Serialize)]
+pub struct FolderUsage {
+    pub name: String,
+    pub path: String,
+    /// Set when the directory is a configured folder
+    pub folder_id: Option<i64>,
+    pub files: i64,
+    pub bytes: i64,
+    pub children: Vec<FolderUsage>,
+}
+
+impl FolderUsage {
+    fn add(&mut self, components: &[&str], folder_id: Option<i64>, files: i64, bytes: i64) {
+        self.files += files;
+        self.bytes += bytes;
+        let Some((first, rest)) = components.split_first() else {
+            self.folder_id = folder_id.or(self.folder_id);
+            return;
+        };
+        let index = match self.children.iter().position(|c| c.name == *first) {
+            Some(index) => index,
+            None => {
+                let path = if self.path.is_empty() { first.to_string() } else { format!("{}/{}", self.path, first) };
+                self.children.push(FolderUsage { name: first.to_string(), path, ..Default::default() });
+                self.children.len() - 1
+            }
+        };
+        self.children[index].add(rest, folder_id, files, bytes);
+    }
+
+    fn sort(&mut self) {
+        self.children.sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.name.cmp(&b.name)));
+        self.children.iter_mut().for_each(FolderUsage::sort);
+    }
+}
+
+/// Usage of a top-level MIME type such as `video`, broken down by full type
+#[derive(Debug, Serialize)]
+pub struct MimeClassUsage {
+    pub class: String,
+    pub files: i64,
+    pub bytes: i64,
+    pub types: Vec<UsageEntry>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UsageReport {
+    pub files: i64,
+    pub bytes: i64,
+    pub folders: FolderUsage,
+    pub mime_classes: Vec<MimeClassUsage>,
+    pub owners: Vec<UsageEntry>,
+    /// Space used besides the stored files, measured on disk
+    pub storage: Vec<StorageBucket>,
+    /// Free space on the uploads volume, when it can be checked
+    pub disk: Option<DiskSpace>,
+}
+
+pub async fn fetch_usage_report(db_pool: &SqlitePool) -> Result<UsageReport, String> {
+    let rows: Vec<(String, String, i64, i64)> = sqlx::query_as(
+        "SELECT dimension, key, files, bytes FROM storage_usage WHERE files > 0 ORDER BY bytes DESC, key"
+    )
+    .fetch_all(db_pool)
+    .await
+    .map_err(|e| {
+        error!("Failed to fetch storage usage: {}", e);
+        "Failed to fetch storage usage".to_string()
+    })?;
+    let folder_paths: HashMap<String, String> = fetch_folders(db_pool)
+        .await?
+        .into_iter()
+        .map(|f| (f.id.to_string(), f.path))
+        .collect();
+
+    let mut folders = FolderUsage { name: "uploads".to_string(), ..Default::default() };
+    let mut classes: BTreeMap<String, Vec<UsageEntry>> = BTreeMap::new();
+    let mut owners = Vec::new();
+    for (dimension, key, files, bytes) in rows {
+        match dimension.as_str() {
+            DIMENSION_FOLDER if key.is_empty() => folders.add(&[], None, files, bytes),
+            DIMENSION_FOLDER => {
+                // 目录只能在清空后删除，找不到路径时按 ID 单独列出
+                let path = folder_paths.get(&key).cloned().unwrap_or_else(|| format!("#{}", key));
+                let components: Vec<&str> = path.split('/').collect();
+                folders.add(&components, key.parse().ok(), files, bytes);
+            }
+            DIMENSION_MIME => {
+                let class = key.split('/').next().unwrap_or_default().to_string();
+                classes.entry(class).or_default().push(UsageEntry { key, files, bytes });
+            }
+            DIMENSION_OWNER => owners.push(UsageEntry { key, files, bytes }),
+            _ => {}
+        }
+    }
+    folders.sort();
+
+    let mut mime_classes: Vec<MimeClassUsage> = classes
+        .into_iter()
+        .map(|(class, types)| MimeClassUsage {
+            class,
+            files: types.iter().map(|t| t.files).sum(),
+            bytes: types.iter().map(|t| t.bytes).sum(),
+            types,
+        })
+        .collect();
+    mime_classes.sort_by_key(|c| std::cmp::Reverse(c.bytes));
+
+    Ok(UsageReport {
+        files: folders.files,
+        bytes: folders.bytes,
+        folders,
+        mime_classes,
+        owners,
+        storage: measure_buckets().await,
+        disk: disk_space(std::path::Path::new(UPLOADS_DIR)).ok(),
+    })
+}
+
+pub async fn get_usage_stats(
+    State(ctx): State<AppContext>,
+) -> impl IntoResponse {
+    if let Err(resp) = ensure_not_tenant().await {
+        return resp;
+    }
+    match fetch_usage_report(&ctx.app_state.db_pool).await {
+        Ok(report) => (StatusCode::OK, Json(ApiResponse::success(report))).into_response(),
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, "FETCH_USAGE_ERROR", e),
+    }
+}
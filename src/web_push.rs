@@ -0,0 +1,411 @@
+//! WebPush notifications (RFC 8030) for the web UI in PWA mode. Payloads are
+//! encrypted with `aes128gcm` (RFC 8291) and requests signed with a VAPID key
+//! (RFC 8292) kept in `NASCRAFT_VAPID_KEY_FILE`.
+
+use axum::{
+    extract::{ConnectInfo, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use log::{error, info, warn};
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING};
+use ring::{aead, agreement, hkdf};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::{FromRow, SqlitePool};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use crate::config::AppConfig;
+use crate::context::AppContext;
+use crate::events::{EventSender, ServerEvent};
+use crate::helper::{error_response, format_size, ApiResponse};
+use crate::profiles::ensure_unrestricted_profile;
+use crate::tenants::current_tenant_id;
+
+/// How long push services keep a message for an offline device
+const MESSAGE_TTL_SECS: u64 = 24 * 60 * 60;
+/// Validity of the VAPID token, at most 24 hours per RFC 8292
+const VAPID_TOKEN_SECS: i64 = 12 * 60 * 60;
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+/// Record size announced in the aes128gcm header; messages are a single record
+const RECORD_SIZE: u32 = 4096;
+
+/// Output length for ring's HKDF
+struct Len(usize);
+
+impl hkdf::KeyType for Len {
+    fn len(&self) -> usize {
+        self.0
+    }
+}
+
+fn hkdf_expand(prk: &hkdf::Prk, info: &[&[u8]], out: &mut [u8]) -> Result<(), String> {
+    prk.expand(info, Len(out.len()))
+        .and_then(|okm| okm.fill(out))
+        .map_err(|_| "HKDF expansion failed".to_string())
+}
+
+/// Decode a base64url key as browsers send it, tolerating padding and the standard alphabet
+fn decode_key(value: &str) -> Option<Vec<u8>> {
+    let normalized: String = value
+        .trim()
+        .trim_end_matches('=')
+        .chars()
+        .map(|c| match c {
+            '+' => '-',
+            '/' => '_',
+            c => c,
+        })
+        .collect();
+    URL_SAFE_NO_PAD.decode(normalized).ok()
+}
+
+pub struct WebPush {
+    key_pair: EcdsaKeyPair,
+    /// Uncompressed P-256 point, base64url; the `applicationServerKey` of `PushManager.subscribe`
+    public_key: String,
+    subject: String,
+    client: reqwest::Client,
+    rng: SystemRandom,
+}
+
+fn write_key_file(path: &Path, pkcs8: &[u8]) -> std::io::Result<()> {
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    std::io::Write::write_all(&mut options.open(path)?, pkcs8)
+}
+
+impl WebPush {
+    /// Load the VAPID key, creating it on first start. Subscriptions are bound
+    /// to the key, so losing the file means clients have to subscribe again.
+    pub fn load_or_create(cfg: &AppConfig) -> Result<Self, String> {
+        let rng = SystemRandom::new();
+        let path = &cfg.vapid_key_file;
+        let pkcs8 = match std::fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let document = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng)
+                    .map_err(|_| "Failed to generate VAPID key".to_string())?;
+                write_key_file(path, document.as_ref())
+                    .map_err(|e| format!("Failed to write VAPID key to {}: {}", path.display(), e))?;
+                info!("Created VAPID key at {}", path.display());
+                document.as_ref().to_vec()
+            }
+            Err(e) => return Err(format!("Failed to read VAPID key from {}: {}", path.display(), e)),
+        };
+        let key_pair = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &pkcs8, &rng)
+            .map_err(|e| format!("Invalid VAPID key in {}: {}", path.display(), e))?;
+        let public_key = URL_SAFE_NO_PAD.encode(key_pair.public_key().as_ref());
+
+        Ok(Self {
+            key_pair,
+            public_key,
+            subject: cfg.vapid_subject.clone(),
+            client: reqwest::Client::new(),
+            rng,
+        })
+    }
+
+    /// VAPID JWT for the push service at `audience` (scheme and host of the endpoint)
+    fn vapid_token(&self, audience: &str) -> Result<String, String> {
+        let header = URL_SAFE_NO_PAD.encode(r#"{"typ":"JWT","alg":"ES256"}"#);
+        let claims = URL_SAFE_NO_PAD.encode(json!({
+            "aud": audience,
+            "exp": chrono::Utc::now().timestamp() + VAPID_TOKEN_SECS,
+            "sub": self.subject,
+        }).to_string());
+        let signing_input = format!("{}.{}", header, claims);
+        let signature = self.key_pair
+            .sign(&self.rng, signing_input.as_bytes())
+            .map_err(|_| "Failed to sign VAPID token".to_string())?;
+        Ok(format!("{}.{}", signing_input, URL_SAFE_NO_PAD.encode(signature.as_ref())))
+    }
+
+    /// Encrypt `payload` for a subscription as an aes128gcm body (RFC 8291)
+    fn encrypt(&self, subscription: &PushSubscription, payload: &[u8]) -> Result<Vec<u8>, String> {
+        let ua_public = decode_key(&subscription.p256dh).ok_or("Invalid p256dh key")?;
+        let auth_secret = decode_key(&subscription.auth).ok_or("Invalid auth secret")?;
+
+        let as_private = agreement::EphemeralPrivateKey::generate(&agreement::ECDH_P256, &self.rng)
+            .map_err(|_| "Failed to generate ECDH key".to_string())?;
+        let as_public = as_private.compute_public_key()
+            .map_err(|_| "Failed to compute ECDH public key".to_string())?;
+        let ecdh_secret = agreement::agree_ephemeral(
+            as_private,
+            &agreement::UnparsedPublicKey::new(&agreement::ECDH_P256, &ua_public),
+            |secret| secret.to_vec(),
+        )
+        .map_err(|_| "ECDH key agreement failed".to_string())?;
+
+        let prk_key = hkdf::Salt::new(hkdf::HKDF_SHA256, &auth_secret).extract(&ecdh_secret);
+        let mut ikm = [0u8; 32];
+        hkdf_expand(&prk_key, &[b"WebPush: info\0", &ua_public, as_public.as_ref()], &mut ikm)?;
+
+        let mut salt = [0u8; 16];
+        self.rng.fill(&mut salt).map_err(|_| "Failed to generate salt".to_string())?;
+        let prk = hkdf::Salt::new(hkdf::HKDF_SHA256, &salt).extract(&ikm);
+        let mut cek = [0u8; 16];
+        hkdf_expand(&prk, &[b"Content-Encoding: aes128gcm\0"], &mut cek)?;
+        let mut nonce = [0u8; 12];
+        hkdf_expand(&prk, &[b"Content-Encoding: nonce\0"], &mut nonce)?;
+
+        // 单条记录，0x02 为最后一条记录的分隔符
+        let mut record = payload.to_vec();
+        record.push(0x02);
+        let key = aead::UnboundKey::new(&aead::AES_128_GCM, &cek)
+            .map_err(|_| "Invalid content encryption key".to_string())?;
+        aead::LessSafeKey::new(key)
+            .seal_in_place_append_tag(aead::Nonce::assume_unique_for_key(nonce), aead::Aad::empty(), &mut record)
+            .map_err(|_| "Payload encryption failed".to_string())?;
+
+        let as_public = as_public.as_ref();
+        let mut body = Vec::with_capacity(16 + 4 + 1 + as_public.len() + record.len());
+        body.extend_from_slice(&salt);
+        body.extend_from_slice(&RECORD_SIZE.to_be_bytes());
+        body.push(as_public.len() as u8);
+        body.extend_from_slice(as_public);
+        body.extend_from_slice(&record);
+        Ok(body)
+    }
+
+    /// Deliver one message. Ok(false) means the push service no longer knows
+    /// the subscription and it should be removed.
+    async fn send(&self, subscription: &PushSubscription, payload: &[u8], urgency: &str) -> Result<bool, String> {
+        let endpoint = reqwest::Url::parse(&subscription.endpoint)
+            .map_err(|e| format!("Invalid endpoint: {}", e))?;
+        let token = self.vapid_token(&endpoint.origin().ascii_serialization())?;
+        let body = self.encrypt(subscription, payload)?;
+
+        let response = self.client
+            .post(endpoint)
+            .timeout(SEND_TIMEOUT)
+            .header("TTL", MESSAGE_TTL_SECS.to_string())
+            .header("Urgency", urgency)
+            .header("Content-Encoding", "aes128gcm")
+            .header("Content-Type", "application/octet-stream")
+            .header("Authorization", format!("vapid t={}, k={}", token, self.public_key))
+            .body(body)
+            .send()
+            .await
+            .map_err(|e| format!("Request failed: {}", e))?;
+
+        match response.status() {
+            status if status.is_success() => Ok(true),
+            reqwest::StatusCode::NOT_FOUND | reqwest::StatusCode::GONE => Ok(false),
+            status => {
+                let detail = response.text().await.unwrap_or_default();
+                Err(format!("Push service answered {}: {}", status, detail.trim()))
+            }
+        }
+    }
+}
+
+#[derive(Debug, FromRow)]
+struct PushSubscription {
+    id: i64,
+    endpoint: String,
+    p256dh: String,
+    auth: String,
+}
+
+/// Subscriptions that receive notifications for the tenant; None for server-wide events
+async fn fetch_subscriptions(db_pool: &SqlitePool, tenant_id: Option<i64>) -> Result<Vec<PushSubscription>, String> {
+    sqlx::query_as::<_, PushSubscription>(
+        "SELECT id, endpoint, p256dh, auth FROM push_subscriptions WHERE tenant_id IS NULL OR tenant_id = ?"
+    )
+    .bind(tenant_id)
+    .fetch_all(db_pool)
+    .await
+    .map_err(|e| {
+        error!("Failed to fetch push subscriptions: {}", e);
+        "Failed to fetch push subscriptions".to_string()
+    })
+}
+
+async fn delete_subscription(db_pool: &SqlitePool, id: i64) {
+    if let Err(e) = sqlx::query("DELETE FROM push_subscriptions WHERE id = ?").bind(id).execute(db_pool).await {
+        error!("Failed to delete push subscription {}: {}", id, e);
+    }
+}
+
+/// Notification shown by the service worker, with the subscriptions it goes to
+fn notification(event: &ServerEvent) -> (serde_json::Value, Option<i64>, &'static str) {
+    match event {
+        ServerEvent::UploadCompleted { file_id, filename, size, tenant_id, inbox } => (json!({
+            "event": "upload_completed",
+            "title": match inbox {
+                Some(inbox) => format!("New file in {}", inbox),
+                None => "Upload completed".to_string(),
+            },
+            "body": format!("{} ({})", filename, format_size(*size)),
+            "tag": format!("upload-{}", file_id),
+            "file_id": file_id,
+        }), *tenant_id, "normal"),
+        ServerEvent::LowDiskSpace { path, available_bytes, total_bytes, critical, read_only } => (json!({
+            "event": "low_disk_space",
+            "title": if *critical { "Critically low disk space" } else { "Low disk space" },
+            "body": format!(
+                "{} of {} free on {}{}",
+                format_size(*available_bytes), format_size(*total_bytes), path.display(),
+                if *read_only { ", uploads are paused" } else { "" }
+            ),
+            "critical": critical,
+            "tag": "low-disk-space",
+        }), None, "high"),
+    }
+}
+
+async fn push_event(db_pool: &SqlitePool, web_push: &WebPush, event: &ServerEvent) {
+    let (message, tenant_id, urgency) = notification(event);
+    // 磁盘空间等全局事件没有租户，只发给不属于租户的订阅
+    let Ok(subscriptions) = fetch_subscriptions(db_pool, tenant_id).await else {
+        return;
+    };
+    let payload = message.to_string();
+    for subscription in subscriptions {
+        match web_push.send(&subscription, payload.as_bytes(), urgency).await {
+            Ok(true) => {}
+            Ok(false) => {
+                info!("Push subscription {} expired, removing it", subscription.id);
+                delete_subscription(db_pool, subscription.id).await;
+            }
+            Err(e) => warn!("Failed to push to subscription {}: {}", subscription.id, e),
+        }
+    }
+}
+
+/// Push server events to the subscribed browsers
+pub async fn run_push_notifier(db_pool: SqlitePool, web_push: Arc<WebPush>, events: EventSender) {
+    let mut receiver = events.subscribe();
+    loop {
+        match receiver.recv().await {
+            Ok(event) => push_event(&db_pool, &web_push, &event).await,
+            Err(RecvError::Lagged(skipped)) => warn!("Push notifier fell behind, {} events were not pushed", skipped),
+            Err(RecvError::Closed) => return,
+        }
+    }
+}
+
+fn push_unavailable() -> axum::response::Response {
+    error_response(StatusCode::SERVICE_UNAVAILABLE, "PUSH_UNAVAILABLE", "Push notifications are unavailable, the VAPID key couldn't be loaded".to_string())
+}
+
+fn invalid_subscription(message: &str) -> axum::response::Response {
+    error_response(StatusCode::BAD_REQUEST, "INVALID_PUSH_SUBSCRIPTION", message.to_string())
+}
+
+#[derive(Serialize)]
+struct VapidPublicKey {
+    public_key: String,
+}
+
+/// The `applicationServerKey` for `PushManager.subscribe`
+pub async fn get_vapid_public_key(
+    State(ctx): State<AppContext>,
+) -> impl IntoResponse {
+    match &ctx.web_push {
+        Some(web_push) => (StatusCode::OK, Json(ApiResponse::success(VapidPublicKey {
+            public_key: web_push.public_key.clone(),
+        }))).into_response(),
+        None => push_unavailable(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct PushKeys {
+    p256dh: String,
+    auth: String,
+}
+
+/// A `PushSubscription` as serialized by `JSON.stringify` in the browser
+#[derive(Deserialize)]
+pub struct SubscribeRequest {
+    endpoint: String,
+    keys: PushKeys,
+}
+
+pub async fn subscribe_push(
+    State(ctx): State<AppContext>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    Json(req): Json<SubscribeRequest>,
+) -> impl IntoResponse {
+    if ctx.web_push.is_none() {
+        return push_unavailable();
+    }
+    // 受限档案看不到的文件也会出现在通知里
+    if let Err(resp) = ensure_unrestricted_profile(&ctx, &client_addr).await {
+        return resp;
+    }
+
+    let endpoint = req.endpoint.trim();
+    if !reqwest::Url::parse(endpoint).is_ok_and(|url| url.scheme() == "https") {
+        return invalid_subscription("endpoint must be an https URL");
+    }
+    if !decode_key(&req.keys.p256dh).is_some_and(|key| key.len() == 65 && key[0] == 0x04) {
+        return invalid_subscription("keys.p256dh must be an uncompressed P-256 public key");
+    }
+    if decode_key(&req.keys.auth).is_none_or(|secret| secret.len() != 16) {
+        return invalid_subscription("keys.auth must be a 16-byte secret");
+    }
+
+    let result = sqlx::query(
+        "INSERT INTO push_subscriptions (endpoint, p256dh, auth, tenant_id, created_at) VALUES (?, ?, ?, ?, strftime('%s', 'now'))
+         ON CONFLICT(endpoint) DO UPDATE SET p256dh = excluded.p256dh, auth = excluded.auth, tenant_id = excluded.tenant_id"
+    )
+    .bind(endpoint)
+    .bind(req.keys.p256dh.trim())
+    .bind(req.keys.auth.trim())
+    .bind(current_tenant_id())
+    .execute(&ctx.app_state.db_pool)
+    .await;
+
+    match result {
+        Ok(_) => {
+            info!("Push subscription saved for {}", client_addr.ip());
+            (StatusCode::OK, Json(ApiResponse::success(()))).into_response()
+        }
+        Err(e) => {
+            error!("Failed to save push subscription: {}", e);
+            error_response(StatusCode::INTERNAL_SERVER_ERROR, "SAVE_PUSH_SUBSCRIPTION_ERROR", "Failed to save push subscription".to_string())
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct UnsubscribeRequest {
+    endpoint: String,
+}
+
+pub async fn unsubscribe_push(
+    State(ctx): State<AppContext>,
+    Json(req): Json<UnsubscribeRequest>,
+) -> impl IntoResponse {
+    match sqlx::query("DELETE FROM push_subscriptions WHERE endpoint = ? AND tenant_id IS ?")
+        .bind(req.endpoint.trim())
+        .bind(current_tenant_id())
+        .execute(&ctx.app_state.db_pool)
+        .await
+    {
+        Ok(done) if done.rows_affected() == 0 => error_response(StatusCode::NOT_FOUND, "PUSH_SUBSCRIPTION_NOT_FOUND", "Push subscription not found".to_string()),
+        Ok(_) => (StatusCode::OK, Json(ApiResponse::success(()))).into_response(),
+        Err(e) => {
+            error!("Failed to delete push subscription: {}", e);
+            error_response(StatusCode::INTERNAL_SERVER_ERROR, "DELETE_PUSH_SUBSCRIPTION_ERROR", "Failed to delete push subscription".to_string())
+        }
+    }
+}